@@ -0,0 +1,138 @@
+package wzprof
+
+import (
+	"fmt"
+	"sort"
+
+	"github.com/google/pprof/profile"
+)
+
+// AnnotatedFunction is the disassembly of a function of a WebAssembly module
+// annotated with the values of the samples of a profile.
+type AnnotatedFunction struct {
+	// Index of the function in the module.
+	Index uint32
+	// Name of the function in the "name" section or the exports of the
+	// module.
+	Name string
+	// Instructions of the function, in the order of the code.
+	Instructions []AnnotatedInstruction
+	// Value of the samples where the function is the innermost frame, and
+	// of the samples where it is one of the frames.
+	Flat, Cum int64
+}
+
+// AnnotatedInstruction is an instruction of an AnnotatedFunction.
+type AnnotatedInstruction struct {
+	// Offset of the instruction in the "Code" section of the module, which is
+	// the address of the locations of the profiles.
+	Offset uint64
+	// Number of blocks enclosing the instruction.
+	Depth int
+	// Instruction in the WebAssembly text format.
+	Text string
+	// Value of the samples where the instruction is the innermost frame.
+	Flat int64
+	// Value of the samples where the instruction is one of the frames.
+	Cum int64
+}
+
+// Annotate disassembles the functions of the WebAssembly module wasm with the
+// given name, and attributes to their instructions the values at sampleIndex of
+// the samples of prof, which must be a profile of the module. Functions are
+// matched by their name in the module or its demangled form.
+//
+// The samples are attributed by the address of their locations, which is the
+// offset of the instruction in the code section. The innermost frames of the
+// call stacks captured by the function listeners are on entry to functions, so
+// their values are attributed to the first instruction of functions, and the
+// values of the callers to the instructions calling them.
+func Annotate(wasm []byte, prof *profile.Profile, sampleIndex int, function string) ([]AnnotatedFunction, error) {
+	if sampleIndex < 0 || sampleIndex >= len(prof.SampleType) {
+		return nil, fmt.Errorf("sample index %d out of range [0:%d]", sampleIndex, len(prof.SampleType))
+	}
+	bodies := wasmFunctionBodies(wasm)
+	if bodies == nil {
+		return nil, fmt.Errorf("the code of the module could not be parsed")
+	}
+	names := wasmFunctionNames(wasm)
+
+	var indexes []uint32
+	for index, name := range names {
+		if demangled, _ := demangle(name, false); name == function || demangled == function {
+			if int(index) < len(bodies) && bodies[index].code != nil {
+				indexes = append(indexes, index)
+			}
+		}
+	}
+	if len(indexes) == 0 {
+		return nil, fmt.Errorf("function %q not found in the module", function)
+	}
+	sort.Slice(indexes, func(i, j int) bool { return indexes[i] < indexes[j] })
+
+	fns := make([]AnnotatedFunction, len(indexes))
+	for i, index := range indexes {
+		body := bodies[index]
+		insns, err := wasmDisassemble(body.code, names)
+		if err != nil {
+			return nil, fmt.Errorf("%s: %w", names[index], err)
+		}
+		fn := AnnotatedFunction{
+			Index:        index,
+			Name:         names[index],
+			Instructions: make([]AnnotatedInstruction, len(insns)),
+		}
+		for j, insn := range insns {
+			fn.Instructions[j] = AnnotatedInstruction{
+				Offset: body.offset + uint64(insn.offset),
+				Depth:  insn.depth,
+				Text:   insn.text,
+			}
+		}
+		annotateSamples(&fn, prof, sampleIndex)
+		fns[i] = fn
+	}
+	return fns, nil
+}
+
+// annotateSamples adds the values of the samples of prof to fn and to its
+// instructions at the addresses of their locations.
+func annotateSamples(fn *AnnotatedFunction, prof *profile.Profile, sampleIndex int) {
+	insns := fn.Instructions
+	if len(insns) == 0 {
+		return
+	}
+	start, end := insns[0].Offset, insns[len(insns)-1].Offset
+	lookup := func(address uint64) int {
+		if address < start || address > end {
+			return -1
+		}
+		return sort.Search(len(insns), func(i int) bool { return insns[i].Offset > address }) - 1
+	}
+
+	seen := make(map[int]struct{})
+	for _, s := range prof.Sample {
+		v := s.Value[sampleIndex]
+		for i, loc := range s.Location {
+			j := lookup(loc.Address)
+			if j < 0 {
+				continue
+			}
+			if i == 0 {
+				insns[j].Flat += v
+				fn.Flat += v
+			}
+			if len(seen) == 0 {
+				fn.Cum += v
+			}
+			// Recursive functions only count once per sample.
+			if _, ok := seen[j]; !ok {
+				seen[j] = struct{}{}
+				insns[j].Cum += v
+			}
+		}
+		for j := range seen {
+			delete(seen, j)
+		}
+	}
+}
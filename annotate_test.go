@@ -0,0 +1,114 @@
+package wzprof
+
+import (
+	"testing"
+
+	"github.com/google/pprof/profile"
+)
+
+func TestWasmDisassemble(t *testing.T) {
+	code := []byte{
+		0x04, 0x7f,                   // if (result i32)
+		0x28, 0x02, 0x08,             // i32.load offset=8 align=4
+		0x05,                         // else
+		0x43, 0x00, 0x00, 0xc0, 0x3f, // f32.const 1.5
+		0x0b,                         // end
+		0x0e, 0x02, 0x00, 0x01, 0x02, // br_table 0 1 2
+		0x42, 0x7f,                   // i64.const -1
+		0xfc, 0x0a, 0x00, 0x00,       // memory.copy
+		0xfe, 0x1e, 0x02, 0x00,       // i32.atomic.rmw.add align=4
+		0x10, 0x01,                   // call $f
+	}
+	insns, err := wasmDisassemble(code, map[uint32]string{1: "f"})
+	if err != nil {
+		t.Fatal(err)
+	}
+	want := []wasmInstruction{
+		{offset: 0, depth: 0, text: "if (result i32)"},
+		{offset: 2, depth: 1, text: "i32.load offset=8 align=4"},
+		{offset: 5, depth: 0, text: "else"},
+		{offset: 6, depth: 1, text: "f32.const 1.5"},
+		{offset: 11, depth: 0, text: "end"},
+		{offset: 12, depth: 0, text: "br_table 0 1 2"},
+		{offset: 17, depth: 0, text: "i64.const -1"},
+		{offset: 19, depth: 0, text: "memory.copy"},
+		{offset: 23, depth: 0, text: "i32.atomic.rmw.add align=4"},
+		{offset: 27, depth: 0, text: "call $f"},
+	}
+	if len(insns) != len(want) {
+		t.Fatalf("wrong number of instructions: want=%d got=%d", len(want), len(insns))
+	}
+	for i := range want {
+		if insns[i] != want[i] {
+			t.Errorf("wrong instruction %d: want=%+v got=%+v", i, want[i], insns[i])
+		}
+	}
+
+	if _, err := wasmDisassemble([]byte{0xff}, nil); err == nil {
+		t.Error("no error for an unknown instruction")
+	}
+	if _, err := wasmDisassemble([]byte{0x41}, nil); err == nil {
+		t.Error("no error for a truncated instruction")
+	}
+}
+
+func TestAnnotate(t *testing.T) {
+	module := []byte("\x00asm\x01\x00\x00\x00")
+	module = append(module, 1, 4, 1, 0x60, 0, 0)             // type: () -> ()
+	module = append(module, 3, 3, 2, 0, 0)                   // function: 2 functions
+	module = append(module, 7, 7, 1, 3, 'r', 'u', 'n', 0, 1) // export: run
+	// code: the second function is
+	//	block
+	//	  i32.const 42
+	//	  drop
+	//	  call 0
+	//	end
+	module = append(module, 10, 15, 2, 2, 0, 0x0b, 10, 0, 0x02, 0x40, 0x41, 0x2a, 0x1a, 0x10, 0x00, 0x0b, 0x0b)
+
+	f0 := &profile.Location{ID: 1, Address: 3}
+	block := &profile.Location{ID: 2, Address: 6}
+	call := &profile.Location{ID: 3, Address: 12}
+	prof := &profile.Profile{
+		SampleType: []*profile.ValueType{{Type: "samples", Unit: "count"}},
+		Sample: []*profile.Sample{
+			{Location: []*profile.Location{block}, Value: []int64{10}},
+			{Location: []*profile.Location{f0, call}, Value: []int64{5}},
+			// Recursive calls only count once.
+			{Location: []*profile.Location{block, block}, Value: []int64{1}},
+		},
+		Location: []*profile.Location{f0, block, call},
+	}
+
+	fns, err := Annotate(module, prof, 0, "run")
+	if err != nil {
+		t.Fatal(err)
+	}
+	if len(fns) != 1 {
+		t.Fatalf("wrong number of functions: want=1 got=%d", len(fns))
+	}
+	fn := fns[0]
+	if fn.Index != 1 || fn.Name != "run" || fn.Flat != 11 || fn.Cum != 16 {
+		t.Errorf("wrong function: index=%d name=%q flat=%d cum=%d", fn.Index, fn.Name, fn.Flat, fn.Cum)
+	}
+
+	want := []AnnotatedInstruction{
+		{Offset: 6, Depth: 0, Text: "block", Flat: 11, Cum: 11},
+		{Offset: 8, Depth: 1, Text: "i32.const 42"},
+		{Offset: 10, Depth: 1, Text: "drop"},
+		{Offset: 11, Depth: 1, Text: "call 0", Cum: 5},
+		{Offset: 13, Depth: 0, Text: "end"},
+		{Offset: 14, Depth: 0, Text: "end"},
+	}
+	if len(fn.Instructions) != len(want) {
+		t.Fatalf("wrong number of instructions: want=%d got=%d", len(want), len(fn.Instructions))
+	}
+	for i := range want {
+		if fn.Instructions[i] != want[i] {
+			t.Errorf("wrong instruction %d: want=%+v got=%+v", i, want[i], fn.Instructions[i])
+		}
+	}
+
+	if _, err := Annotate(module, prof, 0, "missing"); err == nil {
+		t.Error("no error for a missing function")
+	}
+}
@@ -0,0 +1,168 @@
+package wzprof
+
+import (
+	"context"
+	"net/http"
+	"time"
+
+	"github.com/google/pprof/profile"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+// BlockProfiler is the implementation of a profiler recording the time spent
+// by a WebAssembly module blocked in calls to host functions, for example when
+// waiting on I/O in WASI functions like poll_oneoff, fd_read, or sock_recv.
+//
+// While the CPU profiler shows where the guest burns CPU time, the block
+// profile shows where it waits.
+//
+// The profiler generates samples of two types:
+// - "contentions" counts the number of calls to host functions.
+// - "delay" records the time spent in calls to host functions (in nanoseconds).
+//
+// Similarly to the block profiles of Go programs, the values are cumulative
+// since the creation of the profiler.
+type BlockProfiler struct {
+	delayProfiler
+}
+
+func newBlockProfiler(p *Profiling) *BlockProfiler {
+	b := &BlockProfiler{}
+	b.init(p, "block")
+	return b
+}
+
+// Name returns "block" to match the name of the block profiler in pprof.
+func (p *BlockProfiler) Name() string {
+	return "block"
+}
+
+// Desc returns a description copied from net/http/pprof.
+func (p *BlockProfiler) Desc() string {
+	return profileDescriptions[p.Name()]
+}
+
+// NewFunctionListener returns a function listener recording the time spent in
+// calls to the function passed as argument if it is a host function, and nil
+// otherwise.
+func (p *BlockProfiler) NewFunctionListener(def api.FunctionDefinition) experimental.FunctionListener {
+	if def.GoFunction() == nil {
+		return nil
+	}
+	return profilingListener{p.p, delayListener{&p.delayProfiler}}
+}
+
+// delayProfiler is the implementation shared by profilers recording the time
+// spent in calls to a set of functions, cumulated for each call stack.
+type delayProfiler struct {
+	p      *Profiling
+	name   string
+	counts stackCounterShards
+	stacks threadLocal[delayThread]
+	types  []*profile.ValueType
+	time   func() int64
+	start  time.Time
+}
+
+// delayThread holds the state of a delay profiler for a single thread. Calls
+// may be nested, for example when the host calls back into the guest.
+type delayThread struct {
+	frames []delayFrame
+}
+
+type delayFrame struct {
+	def   api.FunctionDefinition
+	start int64
+	trace stackTrace
+}
+
+func (p *delayProfiler) init(prof *Profiling, name string) {
+	p.p = prof
+	p.name = name
+	p.types = []*profile.ValueType{
+		{Type: "contentions", Unit: "count"},
+		{Type: "delay", Unit: "nanoseconds"},
+	}
+	p.time = nanotime
+	p.start = time.Now()
+}
+
+// NewProfile builds a profile of the time spent in the observed calls since
+// the profiler was created.
+func (p *delayProfiler) NewProfile(sampleRate float64) *profile.Profile {
+	return p.p.endProfile(p.name, p.newProfile(sampleRate))
+}
+
+func (p *delayProfiler) newProfile(sampleRate float64) *profile.Profile {
+	samples := p.counts.merge(false)
+	samples.compact(p.p.maxStacks, addStackCounters)
+
+	// Unlike CPU time which is measured on calls selected by the sampling,
+	// each sampled call stands for the ones that were skipped, so both values
+	// are scaled.
+	ratio := 1 / sampleRate
+	return buildProfile(p.p, samples, p.start, time.Since(p.start), p.SampleType(),
+		[]float64{ratio, ratio},
+	)
+}
+
+// Count returns the number of call stacks recorded in p.
+func (p *delayProfiler) Count() int {
+	return p.counts.len()
+}
+
+// SampleType returns the set of value types present in samples recorded by the
+// profiler.
+func (p *delayProfiler) SampleType() []*profile.ValueType {
+	return p.types
+}
+
+// NewHandler returns a http handler allowing the profiler to be exposed on a
+// pprof-compatible http endpoint.
+//
+// The sample rate is a value between 0 and 1 used to scale the profile results
+// based on the sampling rate applied to the profiler so the resulting values
+// remain representative.
+//
+// When the request has a "seconds" query parameter, the handler responds with
+// a delta profile of the time recorded during that period.
+func (p *delayProfiler) NewHandler(sampleRate float64) http.Handler {
+	return http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		serveCumulativeProfile(w, r, p.p, p.name, func() *profile.Profile {
+			return p.newProfile(sampleRate)
+		})
+	})
+}
+
+type delayListener struct{ *delayProfiler }
+
+func (p delayListener) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64, si experimental.StackIterator) {
+	t := p.stacks.get(mod)
+	i := len(t.frames)
+	if i < cap(t.frames) {
+		t.frames = t.frames[:i+1]
+	} else {
+		t.frames = append(t.frames, delayFrame{})
+	}
+	f := &t.frames[i]
+	f.def = def
+	f.trace = makeStackTrace(ctx, f.trace, p.p.threads.lookup(mod), si, p.p.maxFrames)
+	f.start = p.time()
+}
+
+func (p delayListener) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64) {
+	t := p.stacks.get(mod)
+	i := returningFrame(len(t.frames), def, func(i int) api.FunctionDefinition { return t.frames[i].def })
+	f := &t.frames[i]
+	t.frames = t.frames[:i]
+
+	delay := p.time() - f.start
+	if trace, delay, ok := p.p.hookSample(p.name, f.trace, delay); ok {
+		p.counts.observe(mod, trace, delay, p.p.maxStacks)
+	}
+}
+
+func (p delayListener) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
+	p.After(ctx, mod, def, nil)
+}
@@ -0,0 +1,61 @@
+package wzprof
+
+import (
+	"context"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestBlockProfiler(t *testing.T) {
+	currentTime := int64(0)
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).BlockProfiler()
+	p.time = func() int64 { return currentTime }
+
+	pollOneoff := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	pollOneoff.FunctionName = "poll_oneoff"
+	fdRead := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	fdRead.FunctionName = "fd_read"
+
+	module := wazerotest.NewModule(nil, pollOneoff, fdRead)
+	pollStack := []experimental.StackFrame{{Function: pollOneoff, PC: 1}}
+	readStack := []experimental.StackFrame{{Function: fdRead, PC: 2}}
+	ctx := context.Background()
+
+	listener := delayListener{&p.delayProfiler}
+	for _, call := range []struct {
+		fn    *wazerotest.Function
+		stack []experimental.StackFrame
+		start int64
+		end   int64
+	}{
+		{pollOneoff, pollStack, 0, 100},
+		{fdRead, readStack, 100, 110},
+		{pollOneoff, pollStack, 110, 160},
+	} {
+		def := call.fn.Definition()
+		currentTime = call.start
+		listener.Before(ctx, module, def, nil, experimental.NewStackIterator(call.stack...))
+		currentTime = call.end
+		listener.After(ctx, module, def, nil)
+	}
+
+	thread := p.p.threads.lookup(module)
+	assertStackCount(t, p.counts.merge(false), makeStackTraceFromFrames(thread, pollStack), 2, 150)
+	assertStackCount(t, p.counts.merge(false), makeStackTraceFromFrames(thread, readStack), 1, 10)
+
+	prof := p.NewProfile(0.5)
+	if len(prof.Sample) != 2 {
+		t.Fatalf("wrong number of samples: want=2 got=%d", len(prof.Sample))
+	}
+	var delay int64
+	for _, sample := range prof.Sample {
+		delay += sample.Value[1]
+	}
+	if delay != 320 {
+		t.Errorf("wrong total delay: want=320 got=%d", delay)
+	}
+}
@@ -0,0 +1,110 @@
+package wzprof
+
+import (
+	"context"
+	"crypto/sha256"
+	"sync"
+
+	"github.com/tetratelabs/wazero"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+// preparedModule is the state computed by Profiling.Prepare to symbolize the
+// call stacks of a module.
+type preparedModule struct {
+	symbols       symbolizer
+	stackIterator func(mod api.Module, def api.FunctionDefinition, wasmsi experimental.StackIterator) experimental.StackIterator
+	entryOffsets  []uint64 // see Profiling.symbolize
+}
+
+// preparedKey identifies the modules sharing the same prepared state, which is
+// derived from the code of the module and the location of its debug info.
+type preparedKey struct {
+	hash         [sha256.Size]byte
+	lang         language
+	debugInfo    string
+	sourceMap    string
+	nativeFrames bool
+}
+
+type preparedEntry struct {
+	once   sync.Once
+	module *preparedModule
+	err    error
+}
+
+// preparedModules caches the state of the modules prepared by the program.
+// Parsing the debug information of large modules (e.g. the Python interpreter)
+// takes seconds, which the cache saves when the same module is compiled and
+// profiled again, for example by services instantiating a new module for each
+// request. The entries are retained for the lifetime of the program.
+var preparedModules struct {
+	mutex   sync.Mutex
+	entries map[preparedKey]*preparedEntry
+}
+
+// prepareCached returns the prepared state of mod, which is only computed the
+// first time that a module with the same code and options is prepared.
+//
+// The state of Go programs is not cached, their stack iterators keep the
+// memory of the module instance that they unwind.
+func (p *Profiling) prepareCached(mod wazero.CompiledModule, wasm []byte) (*preparedModule, error) {
+	if len(wasm) == 0 {
+		// Without the code of the module there is no key to cache the
+		// state with.
+		return p.prepareModule(mod, wasm)
+	}
+
+	key := preparedKey{
+		hash:         sha256.Sum256(wasm),
+		lang:         p.lang,
+		debugInfo:    p.debugInfo,
+		sourceMap:    p.sourceMap,
+		nativeFrames: p.nativeFrames,
+	}
+
+	preparedModules.mutex.Lock()
+	e := preparedModules.entries[key]
+	if e == nil {
+		if preparedModules.entries == nil {
+			preparedModules.entries = make(map[preparedKey]*preparedEntry)
+		}
+		e = new(preparedEntry)
+		preparedModules.entries[key] = e
+	}
+	preparedModules.mutex.Unlock()
+
+	e.once.Do(func() { e.module, e.err = p.prepareModule(mod, wasm) })
+
+	if e.err != nil {
+		// Errors are not cached, the debug information may be loaded from
+		// a URL which is only temporarily unavailable.
+		preparedModules.mutex.Lock()
+		if preparedModules.entries[key] == e {
+			delete(preparedModules.entries, key)
+		}
+		preparedModules.mutex.Unlock()
+	}
+	return e.module, e.err
+}
+
+// Preload prepares the symbolization of a wasm module ahead of time, so the
+// following calls to Prepare for the same module and options complete without
+// parsing its debug information again.
+//
+// Programs may call Preload in the background when they start, before the
+// module is compiled to be profiled. The module is only decoded by Preload,
+// its code is not compiled to native code.
+func Preload(ctx context.Context, wasm []byte, options ...ProfilingOption) error {
+	// Prepare reads the custom sections from wasm, the runtime does not
+	// need to copy them.
+	runtime := wazero.NewRuntimeWithConfig(ctx, wazero.NewRuntimeConfigInterpreter())
+	defer runtime.Close(ctx)
+
+	mod, err := runtime.CompileModule(ctx, wasm)
+	if err != nil {
+		return err
+	}
+	return ProfilingFor(wasm, options...).Prepare(mod)
+}
@@ -0,0 +1,40 @@
+package wzprof
+
+import (
+	"context"
+	"testing"
+
+	"github.com/tetratelabs/wazero"
+)
+
+func TestPreload(t *testing.T) {
+	ctx := context.Background()
+	// A module without functions, with a custom section so its hash differs
+	// from the modules of other tests.
+	wasm := []byte("\x00asm\x01\x00\x00\x00\x00\x08\x07preload")
+
+	if err := Preload(ctx, wasm); err != nil {
+		t.Fatal(err)
+	}
+
+	runtime := wazero.NewRuntimeWithConfig(ctx, wazero.NewRuntimeConfigInterpreter().
+		WithCustomSections(true))
+	defer runtime.Close(ctx)
+
+	mod, err := runtime.CompileModule(ctx, wasm)
+	if err != nil {
+		t.Fatal(err)
+	}
+
+	p1 := ProfilingFor(wasm)
+	if err := p1.Prepare(mod); err != nil {
+		t.Fatal(err)
+	}
+	p2 := ProfilingFor(wasm)
+	if err := p2.Prepare(mod); err != nil {
+		t.Fatal(err)
+	}
+	if p1.symbols != p2.symbols {
+		t.Error("the symbolizer of the module was prepared again")
+	}
+}
@@ -0,0 +1,174 @@
+package wzprof
+
+import (
+	"context"
+	"net/http"
+	"sync"
+	"sync/atomic"
+	"time"
+
+	"github.com/google/pprof/profile"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+// CallProfiler is the implementation of a profiler counting the calls to each
+// function of a WebAssembly module.
+//
+// Unlike the CPU profiler, the profiler does not capture call stacks, each
+// call only increments a counter of the function, which is cheap enough to
+// observe all the calls of a program. The profile has a sample for each
+// function called at least once, which can be used for coverage analysis, or
+// to find small functions called often enough to matter but which sampling
+// rarely catches.
+//
+// The profiler generates samples of a single type:
+// - "calls" counts the number of calls to the function.
+//
+// The counted functions are the functions of the wasm module, not the
+// functions of interpreted languages like Python. The profiler counts all the
+// calls, it must not be wrapped by Sample.
+//
+// The values are cumulative since the creation of the profiler.
+type CallProfiler struct {
+	p        *Profiling
+	mutex    sync.Mutex
+	counters map[callKey]*callCounter
+	start    time.Time
+}
+
+type callKey struct {
+	module string
+	index  uint32
+}
+
+// callCounter is the function listener of a single function. The frame of the
+// function is captured on the first call to symbolize the samples.
+type callCounter struct {
+	count atomic.Int64
+	frame atomic.Pointer[stackFrame]
+}
+
+func newCallProfiler(p *Profiling) *CallProfiler {
+	return &CallProfiler{
+		p:        p,
+		counters: make(map[callKey]*callCounter),
+		start:    time.Now(),
+	}
+}
+
+// Name returns "calls".
+func (p *CallProfiler) Name() string {
+	return "calls"
+}
+
+// Desc returns a description of the call count profile.
+func (p *CallProfiler) Desc() string {
+	return profileDescriptions[p.Name()]
+}
+
+// Count returns the number of functions called at least once.
+func (p *CallProfiler) Count() int {
+	p.mutex.Lock()
+	defer p.mutex.Unlock()
+	n := 0
+	for _, c := range p.counters {
+		if c.frame.Load() != nil {
+			n++
+		}
+	}
+	return n
+}
+
+// SampleType returns the set of value types present in samples recorded by the
+// profiler.
+func (p *CallProfiler) SampleType() []*profile.ValueType {
+	return []*profile.ValueType{
+		{Type: "calls", Unit: "count"},
+	}
+}
+
+// NewProfile builds a profile of the calls counted since the profiler was
+// created.
+//
+// All function calls are counted, the values are not scaled by the sample
+// rate.
+func (p *CallProfiler) NewProfile(sampleRate float64) *profile.Profile {
+	return p.p.endProfile(p.Name(), p.newProfile(sampleRate))
+}
+
+func (p *CallProfiler) newProfile(sampleRate float64) *profile.Profile {
+	p.mutex.Lock()
+	samples := make(stackCounterMap, len(p.counters))
+	for _, c := range p.counters {
+		f := c.frame.Load()
+		if f == nil {
+			continue
+		}
+		pc := f.pc
+		switch p.p.lang {
+		case golang, python3, cruby:
+			// The symbolizers of these languages resolve the frames of
+			// their own stack iterators, the wasm functions are only
+			// named after their definition.
+			pc = 0
+		}
+		key := uint64(len(samples))
+		samples[key] = &stackCounter{
+			stack: stackTrace{
+				fns: []experimental.InternalFunction{f.fn},
+				pcs: []experimental.ProgramCounter{pc},
+				key: key,
+			},
+			value: [2]int64{c.count.Load(), 0},
+		}
+	}
+	p.mutex.Unlock()
+
+	return buildProfile(p.p, samples, p.start, time.Since(p.start), p.SampleType(),
+		[]float64{1},
+	)
+}
+
+// NewHandler returns a http handler allowing the profiler to be exposed on a
+// pprof-compatible http endpoint.
+//
+// When the request has a "seconds" query parameter, the handler responds with
+// a delta profile of the calls counted during that period.
+func (p *CallProfiler) NewHandler(sampleRate float64) http.Handler {
+	return http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		serveCumulativeProfile(w, r, p.p, p.Name(), func() *profile.Profile {
+			return p.newProfile(sampleRate)
+		})
+	})
+}
+
+// NewFunctionListener returns a function listener counting the calls to the
+// function passed as argument, or nil if the function is not instrumented (see
+// Filter).
+func (p *CallProfiler) NewFunctionListener(def api.FunctionDefinition) experimental.FunctionListener {
+	if !p.p.instrumented(def) {
+		return nil
+	}
+	key := callKey{module: def.ModuleName(), index: def.Index()}
+	p.mutex.Lock()
+	defer p.mutex.Unlock()
+	c := p.counters[key]
+	if c == nil {
+		c = new(callCounter)
+		p.counters[key] = c
+	}
+	return c
+}
+
+func (c *callCounter) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64, si experimental.StackIterator) {
+	if c.count.Add(1) == 1 && si.Next() {
+		c.frame.Store(&stackFrame{fn: si.Function(), pc: si.ProgramCounter()})
+	}
+}
+
+func (c *callCounter) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64) {
+}
+
+func (c *callCounter) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
+}
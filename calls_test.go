@@ -0,0 +1,51 @@
+package wzprof
+
+import (
+	"context"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestCallProfiler(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).CallProfiler()
+
+	f0 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f0.FunctionName = "f0"
+	f1 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f1.FunctionName = "f1"
+	f2 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f2.FunctionName = "f2"
+
+	module := wazerotest.NewModule(nil, f0, f1, f2)
+	ctx := context.Background()
+
+	calls := map[*wazerotest.Function]int{f0: 1, f1: 3}
+	for fn, n := range calls {
+		def := fn.Definition()
+		listener := p.NewFunctionListener(def)
+		for i := 0; i < n; i++ {
+			listener.Before(ctx, module, def, nil, experimental.NewStackIterator(experimental.StackFrame{Function: fn}))
+			listener.After(ctx, module, def, nil)
+		}
+	}
+	p.NewFunctionListener(f2.Definition())
+
+	if n := p.Count(); n != 2 {
+		t.Errorf("wrong number of functions called: want=2 got=%d", n)
+	}
+
+	prof := p.NewProfile(1)
+	if len(prof.Sample) != 2 {
+		t.Fatalf("wrong number of samples: want=2 got=%d", len(prof.Sample))
+	}
+	for _, sample := range prof.Sample {
+		name := sample.Location[0].Line[0].Function.Name
+		want := map[string]int64{"f0": 1, "f1": 3}[name]
+		if sample.Value[0] != want {
+			t.Errorf("%s: wrong number of calls: want=%d got=%d", name, want, sample.Value[0])
+		}
+	}
+}
@@ -0,0 +1,471 @@
+package wzprof
+
+import (
+	"encoding/gob"
+	"fmt"
+	"io"
+	"time"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+// Checkpoint writes the samples recorded by the profilers to w, so they can be
+// saved alongside a snapshot of the module instance and added back to the
+// profilers with Restore when the instance is resumed. The profiles of the
+// resumed instance then account for the execution which happened before the
+// snapshot instead of starting over from zero.
+//
+// The call stacks are written with the source locations that they resolve to,
+// so restoring them does not require preparing the profilers for the module
+// that they were recorded from, and may happen in another process.
+//
+// The profile of a CPU profiler is only written if it was started, Restore then
+// starts the profile again. The state of the calls in progress is not written,
+// nor is the profile recorded between calls to StartProfile and StopProfile of
+// the memory profiler.
+//
+// The function returns an error if one of the profilers is not implemented by
+// this package.
+func Checkpoint(w io.Writer, profilers ...Profiler) error {
+	c := &checkpointWriter{frames: make(map[locationKey]int)}
+	for _, p := range profilers {
+		cp, ok := p.(checkpointer)
+		if !ok {
+			return fmt.Errorf("wzprof: the %s profiler does not support checkpoints", p.Name())
+		}
+		prof := checkpointProfile{Name: p.Name()}
+		cp.checkpoint(c, &prof)
+		c.state.Profiles = append(c.state.Profiles, prof)
+	}
+	return gob.NewEncoder(w).Encode(&c.state)
+}
+
+// Restore reads a checkpoint written by Checkpoint from r, and adds its samples
+// to the profilers with the same names. The samples are added to the ones that
+// the profilers recorded already, if any.
+//
+// Restore should be called before the module instance resumes its execution.
+// When the memory in use is tracked (see InuseMemory), the objects allocated
+// before the snapshot are restored as well, so the profiler accounts for the
+// objects released by the resumed instance.
+func Restore(r io.Reader, profilers ...Profiler) error {
+	var state checkpointState
+	if err := gob.NewDecoder(r).Decode(&state); err != nil {
+		return fmt.Errorf("wzprof: reading checkpoint: %w", err)
+	}
+	if err := state.validate(); err != nil {
+		return err
+	}
+
+	c := &checkpointReader{fns: make([]*restoredFunction, len(state.Frames))}
+	for i := range state.Frames {
+		c.fns[i] = &restoredFunction{frame: &state.Frames[i]}
+	}
+	for _, p := range profilers {
+		cp, ok := p.(checkpointer)
+		if !ok {
+			return fmt.Errorf("wzprof: the %s profiler does not support checkpoints", p.Name())
+		}
+		for i := range state.Profiles {
+			if prof := &state.Profiles[i]; prof.Name == p.Name() {
+				cp.restore(c, prof)
+			}
+		}
+	}
+	return nil
+}
+
+// checkpointer is implemented by the profilers supporting Checkpoint and
+// Restore.
+type checkpointer interface {
+	checkpoint(c *checkpointWriter, prof *checkpointProfile)
+	restore(c *checkpointReader, prof *checkpointProfile)
+}
+
+var (
+	_ checkpointer = (*CPUProfiler)(nil)
+	_ checkpointer = (*MemoryProfiler)(nil)
+	_ checkpointer = (*BlockProfiler)(nil)
+	_ checkpointer = (*MutexProfiler)(nil)
+	_ checkpointer = (*GrowProfiler)(nil)
+	_ checkpointer = (*StackProfiler)(nil)
+	_ checkpointer = (*TrapProfiler)(nil)
+	_ checkpointer = (*CallProfiler)(nil)
+	_ checkpointer = (*SyscallProfiler)(nil)
+	_ checkpointer = (*GCProfiler)(nil)
+)
+
+// checkpointState is the content of checkpoints, encoded with encoding/gob.
+// The frames are shared by the call stacks of all the profiles.
+type checkpointState struct {
+	Frames   []checkpointFrame
+	Profiles []checkpointProfile
+}
+
+type checkpointFrame struct {
+	Module    string
+	Index     uint32
+	Name      string
+	DebugName string
+	Host      bool
+	PC        uint64
+	Address   uint64
+	Locations []location
+}
+
+type checkpointProfile struct {
+	Name string
+	// Start time of the profile (in nanoseconds since the epoch), and whether
+	// the CPU profile was started.
+	Start  int64
+	Active bool
+	// Samples and objects in use, only memory profilers tracking the memory
+	// in use have objects.
+	Samples []checkpointSample
+	Objects []checkpointObject
+}
+
+type checkpointSample struct {
+	Frames []int // indexes in checkpointState.Frames
+	Depth  int   // number of frames, including the truncated frames
+	Thread uint32
+	Labels []string // key/value pairs
+	Value  [2]int64
+}
+
+type checkpointObject struct {
+	Addr   uint32
+	Size   uint32
+	Sample int // index of the sample of the allocation
+}
+
+// validate checks that the indexes of the checkpoint are in bounds, so that
+// corrupted checkpoints do not crash the profilers.
+func (s *checkpointState) validate() error {
+	for _, prof := range s.Profiles {
+		for _, sample := range prof.Samples {
+			for _, f := range sample.Frames {
+				if f < 0 || f >= len(s.Frames) {
+					return fmt.Errorf("wzprof: invalid checkpoint: frame %d out of bounds", f)
+				}
+			}
+			if len(sample.Labels)%2 != 0 {
+				return fmt.Errorf("wzprof: invalid checkpoint: uneven number of labels")
+			}
+		}
+		for _, obj := range prof.Objects {
+			if obj.Sample < 0 || obj.Sample >= len(prof.Samples) {
+				return fmt.Errorf("wzprof: invalid checkpoint: sample %d out of bounds", obj.Sample)
+			}
+		}
+	}
+	return nil
+}
+
+type checkpointWriter struct {
+	state  checkpointState
+	frames map[locationKey]int
+}
+
+// sample adds a sample of the call stack st to prof, symbolized by p, and
+// returns its index.
+func (c *checkpointWriter) sample(p *Profiling, prof *checkpointProfile, st stackTrace, value [2]int64) int {
+	s := checkpointSample{
+		Frames: make([]int, st.len()),
+		Depth:  st.depth,
+		Thread: st.thread,
+		Value:  value,
+	}
+	for i := range s.Frames {
+		s.Frames[i] = c.frame(p, st.fns[i], st.pcs[i])
+	}
+	if st.labels != nil {
+		for _, l := range st.labels.labels {
+			s.Labels = append(s.Labels, l.key, l.value)
+		}
+	}
+	prof.Samples = append(prof.Samples, s)
+	return len(prof.Samples) - 1
+}
+
+// samples adds the samples of the counters to prof.
+func (c *checkpointWriter) samples(p *Profiling, prof *checkpointProfile, counts stackCounterMap) {
+	for _, sc := range counts {
+		c.sample(p, prof, sc.stack, sc.value)
+	}
+}
+
+func (c *checkpointWriter) frame(p *Profiling, fn experimental.InternalFunction, pc experimental.ProgramCounter) int {
+	def := fn.Definition()
+	key := makeLocationKey(def, pc)
+	if i, ok := c.frames[key]; ok {
+		return i
+	}
+	// The locations are resolved like locationForCall does, the frames of
+	// host functions are named when the profiles are built.
+	f := checkpointFrame{
+		Module:    def.ModuleName(),
+		Index:     def.Index(),
+		Name:      def.Name(),
+		DebugName: definitionName(def),
+		Host:      def.GoFunction() != nil,
+		PC:        uint64(pc),
+	}
+	f.Address, f.Locations = p.symbolize(fn, pc)
+	i := len(c.state.Frames)
+	c.state.Frames = append(c.state.Frames, f)
+	c.frames[key] = i
+	return i
+}
+
+type checkpointReader struct {
+	fns []*restoredFunction
+}
+
+// stack returns the call stack of the sample s.
+func (c *checkpointReader) stack(s *checkpointSample) stackTrace {
+	st := stackTrace{
+		fns:    make([]experimental.InternalFunction, len(s.Frames)),
+		pcs:    make([]experimental.ProgramCounter, len(s.Frames)),
+		thread: s.Thread,
+		depth:  s.Depth,
+	}
+	for i, f := range s.Frames {
+		st.fns[i] = c.fns[f]
+		st.pcs[i] = experimental.ProgramCounter(c.fns[f].frame.PC)
+	}
+	if len(s.Labels) > 0 {
+		labels := make([]label, 0, len(s.Labels)/2)
+		for i := 0; i < len(s.Labels); i += 2 {
+			labels = append(labels, label{key: s.Labels[i], value: s.Labels[i+1]})
+		}
+		st.labels = newLabelSet(labels)
+	}
+	st.restoreSites()
+	st.key = st.hash()
+	return st
+}
+
+// addSamples adds the samples of prof to counts, the values of call stacks
+// present in counts already are combined with merge.
+func (c *checkpointReader) addSamples(counts stackCounterMap, prof *checkpointProfile, merge func(dst, src *stackCounter)) {
+	for i := range prof.Samples {
+		s := &prof.Samples[i]
+		merge(counts.lookup(c.stack(s)), &stackCounter{value: s.Value})
+	}
+}
+
+// restoredFunction is the function of a frame restored from a checkpoint. Its
+// definition only implements the methods which the profilers use to name the
+// frames, and the source locations are the ones resolved when the checkpoint
+// was written.
+type restoredFunction struct {
+	frame *checkpointFrame
+
+	api.FunctionDefinition
+}
+
+func (f *restoredFunction) Definition() api.FunctionDefinition { return f }
+
+func (f *restoredFunction) SourceOffsetForPC(experimental.ProgramCounter) uint64 { return 0 }
+
+func (f *restoredFunction) ModuleName() string { return f.frame.Module }
+
+func (f *restoredFunction) Index() uint32 { return f.frame.Index }
+
+func (f *restoredFunction) Import() (string, string, bool) { return "", "", false }
+
+func (f *restoredFunction) ExportNames() []string { return nil }
+
+func (f *restoredFunction) Name() string { return f.frame.Name }
+
+func (f *restoredFunction) DebugName() string { return f.frame.DebugName }
+
+func (f *restoredFunction) GoFunction() interface{} {
+	if f.frame.Host {
+		return f.frame // any non-nil value marks host functions
+	}
+	return nil
+}
+
+func (f *restoredFunction) ParamTypes() []api.ValueType { return nil }
+
+func (f *restoredFunction) ResultTypes() []api.ValueType { return nil }
+
+// restoreStart moves the start time of a profile to the start of the profile
+// restored from a checkpoint if it is earlier.
+func restoreStart(start *time.Time, prof *checkpointProfile) {
+	if t := time.Unix(0, prof.Start); prof.Start != 0 && t.Before(*start) {
+		*start = t
+	}
+}
+
+// restoreShards adds the samples of prof to the shard of the nil module, which
+// no thread of the program runs on.
+func (c *checkpointReader) restoreShards(s *stackCounterShards, prof *checkpointProfile) {
+	shard := s.shards.get(nil)
+	shard.mutex.Lock()
+	if shard.counts == nil {
+		shard.counts = make(stackCounterMap)
+	}
+	c.addSamples(shard.counts, prof, addStackCounters)
+	shard.mutex.Unlock()
+}
+
+func (p *CPUProfiler) checkpoint(c *checkpointWriter, prof *checkpointProfile) {
+	p.mutex.Lock()
+	active, start := p.active.Load(), p.start
+	p.mutex.Unlock()
+	if active {
+		prof.Start, prof.Active = start.UnixNano(), true
+		c.samples(p.p, prof, p.counts.merge(false))
+	}
+}
+
+func (p *CPUProfiler) restore(c *checkpointReader, prof *checkpointProfile) {
+	if !prof.Active {
+		return
+	}
+	p.StartProfile()
+	p.mutex.Lock()
+	restoreStart(&p.start, prof)
+	p.mutex.Unlock()
+	c.restoreShards(&p.counts, prof)
+}
+
+func (p *delayProfiler) checkpoint(c *checkpointWriter, prof *checkpointProfile) {
+	prof.Start = p.start.UnixNano()
+	c.samples(p.p, prof, p.counts.merge(false))
+}
+
+func (p *delayProfiler) restore(c *checkpointReader, prof *checkpointProfile) {
+	restoreStart(&p.start, prof)
+	c.restoreShards(&p.counts, prof)
+}
+
+func (p *GrowProfiler) checkpoint(c *checkpointWriter, prof *checkpointProfile) {
+	prof.Start = p.start.UnixNano()
+	c.samples(p.p, prof, p.counts.merge(false))
+}
+
+func (p *GrowProfiler) restore(c *checkpointReader, prof *checkpointProfile) {
+	restoreStart(&p.start, prof)
+	c.restoreShards(&p.counts, prof)
+}
+
+func (p *StackProfiler) checkpoint(c *checkpointWriter, prof *checkpointProfile) {
+	p.mutex.Lock()
+	defer p.mutex.Unlock()
+	prof.Start = p.start.UnixNano()
+	c.samples(p.p, prof, p.depths)
+}
+
+func (p *StackProfiler) restore(c *checkpointReader, prof *checkpointProfile) {
+	p.mutex.Lock()
+	defer p.mutex.Unlock()
+	restoreStart(&p.start, prof)
+	c.addSamples(p.depths, prof, maxStackCounters)
+	p.depths.compact(p.p.maxStacks, maxStackCounters)
+}
+
+func (p *TrapProfiler) checkpoint(c *checkpointWriter, prof *checkpointProfile) {
+	p.mutex.Lock()
+	defer p.mutex.Unlock()
+	prof.Start = p.start.UnixNano()
+	c.samples(p.p, prof, p.counts)
+}
+
+func (p *TrapProfiler) restore(c *checkpointReader, prof *checkpointProfile) {
+	p.mutex.Lock()
+	defer p.mutex.Unlock()
+	restoreStart(&p.start, prof)
+	c.addSamples(p.counts, prof, addStackCounters)
+}
+
+func (p *CallProfiler) checkpoint(c *checkpointWriter, prof *checkpointProfile) {
+	p.mutex.Lock()
+	defer p.mutex.Unlock()
+	prof.Start = p.start.UnixNano()
+	for _, counter := range p.counters {
+		if f := counter.frame.Load(); f != nil {
+			st := stackTrace{
+				fns: []experimental.InternalFunction{f.fn},
+				pcs: []experimental.ProgramCounter{f.pc},
+			}
+			c.sample(p.p, prof, st, [2]int64{counter.count.Load(), 0})
+		}
+	}
+}
+
+func (p *CallProfiler) restore(c *checkpointReader, prof *checkpointProfile) {
+	p.mutex.Lock()
+	defer p.mutex.Unlock()
+	restoreStart(&p.start, prof)
+	for i := range prof.Samples {
+		s := &prof.Samples[i]
+		if len(s.Frames) != 1 {
+			continue
+		}
+		fn := c.fns[s.Frames[0]]
+		key := callKey{module: fn.frame.Module, index: fn.frame.Index}
+		counter := p.counters[key]
+		if counter == nil {
+			counter = new(callCounter)
+			p.counters[key] = counter
+		}
+		counter.count.Add(s.Value[0])
+		counter.frame.CompareAndSwap(nil, &stackFrame{fn: fn, pc: experimental.ProgramCounter(fn.frame.PC)})
+	}
+}
+
+func (p *MemoryProfiler) checkpoint(c *checkpointWriter, prof *checkpointProfile) {
+	p.mutex.Lock()
+	defer p.mutex.Unlock()
+	prof.Start = p.start.UnixNano()
+
+	samples := make(map[*stackCounter]int, len(p.alloc))
+	for _, alloc := range p.alloc {
+		samples[alloc] = c.sample(p.p, prof, alloc.stack, alloc.value)
+	}
+	for addr, inuse := range p.inuse {
+		i, ok := samples[inuse.stackCounter]
+		if !ok {
+			// The allocation counter was merged into its caller by
+			// compact, the objects keep the call stack which allocated
+			// them.
+			i = c.sample(p.p, prof, inuse.stack, [2]int64{})
+			samples[inuse.stackCounter] = i
+		}
+		prof.Objects = append(prof.Objects, checkpointObject{Addr: addr, Size: inuse.size, Sample: i})
+	}
+}
+
+func (p *MemoryProfiler) restore(c *checkpointReader, prof *checkpointProfile) {
+	p.mutex.Lock()
+	defer p.mutex.Unlock()
+	restoreStart(&p.start, prof)
+
+	allocs := make([]*stackCounter, len(prof.Samples))
+	for i := range prof.Samples {
+		s := &prof.Samples[i]
+		stack := c.stack(s)
+		if s.Value[0] == 0 {
+			allocs[i] = &stackCounter{stack: stack}
+			continue
+		}
+		if p.sizes {
+			stack.key = sizeClassKey(stack.key, uint32(s.Value[1]/s.Value[0]))
+		}
+		alloc := p.alloc.lookup(stack)
+		addStackCounters(alloc, &stackCounter{value: s.Value})
+		allocs[i] = alloc
+	}
+	p.alloc.compact(p.p.maxStacks, addStackCounters)
+
+	if p.inuse != nil {
+		for _, obj := range prof.Objects {
+			p.trackObject(obj.Addr, memoryAllocation{allocs[obj.Sample], obj.Size})
+		}
+	}
+}
@@ -0,0 +1,138 @@
+package wzprof
+
+import (
+	"bytes"
+	"context"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestCheckpointRestore(t *testing.T) {
+	malloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+	malloc.FunctionName = "malloc"
+
+	free := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, addr uint32) {})
+	free.FunctionName = "free"
+
+	module := wazerotest.NewModule(nil, malloc, free)
+	stack := []experimental.StackFrame{{Function: malloc}}
+	ctx := WithLabels(context.Background(), "tenant", "a")
+
+	mallocDef, freeDef := malloc.Definition(), free.Definition()
+	p0 := ProfilingFor(nil, AllowUnprepared(true))
+	mem0 := p0.MemoryProfiler(InuseMemory(true))
+	calls0 := p0.CallProfiler()
+
+	mallocListener := mem0.NewFunctionListener(mallocDef)
+	callListener := calls0.NewFunctionListener(mallocDef)
+	for _, addr := range []uint64{16, 32, 48} {
+		callListener.Before(ctx, module, mallocDef, []uint64{10}, experimental.NewStackIterator(stack...))
+		mallocListener.Before(ctx, module, mallocDef, []uint64{10}, experimental.NewStackIterator(stack...))
+		mallocListener.After(ctx, module, mallocDef, []uint64{addr})
+	}
+
+	b := new(bytes.Buffer)
+	if err := Checkpoint(b, mem0, calls0); err != nil {
+		t.Fatal(err)
+	}
+
+	// The profilers of the resumed instance are created by another instance
+	// of Profiling, as if the snapshot was restored in another process.
+	p1 := ProfilingFor(nil, AllowUnprepared(true))
+	mem1 := p1.MemoryProfiler(InuseMemory(true))
+	calls1 := p1.CallProfiler()
+	if err := Restore(b, mem1, calls1); err != nil {
+		t.Fatal(err)
+	}
+
+	// The objects allocated before the snapshot are released by the resumed
+	// instance.
+	freeListener := mem1.NewFunctionListener(freeDef)
+	freeListener.Before(ctx, module, freeDef, []uint64{32}, experimental.NewStackIterator(stack...))
+	freeListener.After(ctx, module, freeDef, nil)
+
+	samples := mem1.snapshot()
+	if len(samples) != 1 {
+		t.Fatalf("wrong number of samples: want=1 got=%d", len(samples))
+	}
+	for _, sample := range samples {
+		want := [4]int64{3, 30, 2, 20}
+		if sample.value != want {
+			t.Errorf("sample values mismatch: want=%v got=%v", want, sample.value)
+		}
+	}
+
+	prof := mem1.NewProfile(1)
+	if err := prof.CheckValid(); err != nil {
+		t.Fatal(err)
+	}
+	s := prof.Sample[0]
+	if name := s.Location[0].Line[0].Function.Name; name != "malloc" {
+		t.Errorf("wrong function of the restored sample: want=malloc got=%s", name)
+	}
+	if tenant := s.Label["tenant"]; len(tenant) != 1 || tenant[0] != "a" {
+		t.Errorf("wrong labels of the restored sample: %v", s.Label)
+	}
+
+	// Calls of the resumed instance add up with the calls restored from the
+	// checkpoint.
+	callListener = calls1.NewFunctionListener(mallocDef)
+	callListener.Before(ctx, module, mallocDef, []uint64{10}, experimental.NewStackIterator(stack...))
+	prof = calls1.NewProfile(1)
+	if len(prof.Sample) != 1 || prof.Sample[0].Value[0] != 4 {
+		t.Errorf("wrong calls of the restored profile: %v", prof.Sample)
+	}
+}
+
+func TestCheckpointCPUProfile(t *testing.T) {
+	currentTime := int64(1)
+
+	f := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f.FunctionName = "f"
+	module := wazerotest.NewModule(nil, f)
+	def := f.Definition()
+	stack := []experimental.StackFrame{{Function: f, PC: 1}}
+	ctx := context.Background()
+
+	call := func(cpu *CPUProfiler, duration int64) {
+		listener := cpu.NewFunctionListener(def)
+		listener.Before(ctx, module, def, nil, experimental.NewStackIterator(stack...))
+		currentTime += duration
+		listener.After(ctx, module, def, nil)
+	}
+
+	cpu0 := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(HostTime(true))
+	cpu0.time = func() int64 { return currentTime }
+	cpu0.StartProfile()
+	call(cpu0, 10)
+
+	b := new(bytes.Buffer)
+	if err := Checkpoint(b, cpu0); err != nil {
+		t.Fatal(err)
+	}
+
+	cpu1 := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(HostTime(true))
+	cpu1.time = func() int64 { return currentTime }
+	if err := Restore(b, cpu1); err != nil {
+		t.Fatal(err)
+	}
+	call(cpu1, 20)
+
+	prof := cpu1.StopProfile(1)
+	if prof == nil {
+		t.Fatal("the profile was not started by Restore")
+	}
+	var calls, total int64
+	for _, s := range prof.Sample {
+		calls += s.Value[0]
+		total += s.Value[1]
+	}
+	if calls != 2 || total != 30 {
+		t.Errorf("wrong values of the restored profile: want=[2 30] got=[%d %d]", calls, total)
+	}
+}
@@ -0,0 +1,79 @@
+package main
+
+import (
+	"flag"
+	"fmt"
+	"io"
+	"os"
+	"strings"
+	"text/tabwriter"
+
+	"github.com/google/pprof/profile"
+	"github.com/stealthrocket/wzprof"
+)
+
+// runAnnotate implements the "wzprof annotate" command, which prints the
+// disassembly of a function with the values of a profile attributed to its
+// instructions, similarly to "go tool pprof -disasm".
+func runAnnotate(args []string) error {
+	flags := flag.NewFlagSet("annotate", flag.ContinueOnError)
+	sampleType := flags.String("sample-type", "", "Sample type of the report (default: the default sample type of the profile, or the last one).")
+	paths, err := parseInterspersed(flags, args)
+	if err != nil {
+		return err
+	}
+	if len(paths) != 3 {
+		return fmt.Errorf("usage: wzprof annotate [options] <app.wasm> <profile> <function>")
+	}
+
+	wasm, err := os.ReadFile(paths[0])
+	if err != nil {
+		return err
+	}
+	prof, err := readProfile(paths[1])
+	if err != nil {
+		return err
+	}
+	return printAnnotate(os.Stdout, wasm, prof, *sampleType, paths[2])
+}
+
+// printAnnotate writes to w the disassembly of the functions of the module
+// named function, with the flat and cumulative values of each instruction.
+func printAnnotate(w io.Writer, wasm []byte, prof *profile.Profile, sampleType, function string) error {
+	index, err := prof.SampleIndexByName(sampleType)
+	if err != nil {
+		return err
+	}
+	fns, err := wzprof.Annotate(wasm, prof, index, function)
+	if err != nil {
+		return err
+	}
+	unit := prof.SampleType[index].Unit
+
+	var total int64
+	for _, s := range prof.Sample {
+		total += s.Value[index]
+	}
+	format := func(v int64) string {
+		if v == 0 {
+			return "."
+		}
+		return formatValue(v, unit)
+	}
+
+	for _, fn := range fns {
+		fmt.Fprintf(w, "ROUTINE ======================== %s\n", fn.Name)
+		fmt.Fprintf(w, "%s %s (flat, cum) %s of Total\n", formatValue(fn.Flat, unit), formatValue(fn.Cum, unit), percentOf(fn.Cum, total))
+
+		tw := tabwriter.NewWriter(w, 0, 8, 2, ' ', 0)
+		for _, insn := range fn.Instructions {
+			fmt.Fprintf(tw, "%10s %10s\t%#x:\t%s%s\n",
+				format(insn.Flat), format(insn.Cum), insn.Offset,
+				strings.Repeat("  ", insn.Depth), insn.Text)
+		}
+		if err := tw.Flush(); err != nil {
+			return err
+		}
+	}
+	return nil
+}
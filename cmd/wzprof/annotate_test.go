@@ -0,0 +1,42 @@
+package main
+
+import (
+	"bytes"
+	"strings"
+	"testing"
+
+	"github.com/google/pprof/profile"
+)
+
+func TestPrintAnnotate(t *testing.T) {
+	module := []byte("\x00asm\x01\x00\x00\x00")
+	module = append(module, 1, 4, 1, 0x60, 0, 0)                    // type: () -> ()
+	module = append(module, 3, 2, 1, 0)                             // function
+	module = append(module, 7, 7, 1, 3, 'r', 'u', 'n', 0, 0)        // export: run
+	module = append(module, 10, 7, 1, 5, 0, 0x41, 0x2a, 0x1a, 0x0b) // code: i32.const 42, drop
+
+	loc := &profile.Location{ID: 1, Address: 3}
+	prof := &profile.Profile{
+		SampleType: []*profile.ValueType{{Type: "cpu", Unit: "nanoseconds"}},
+		Sample:     []*profile.Sample{{Location: []*profile.Location{loc}, Value: []int64{10e6}}},
+		Location:   []*profile.Location{loc},
+	}
+
+	var buf bytes.Buffer
+	if err := printAnnotate(&buf, module, prof, "", "run"); err != nil {
+		t.Fatal(err)
+	}
+	lines := strings.Split(strings.TrimSuffix(buf.String(), "\n"), "\n")
+	if len(lines) != 5 {
+		t.Fatalf("wrong number of lines:\n%s", buf.String())
+	}
+	if want := "ROUTINE ======================== run"; lines[0] != want {
+		t.Errorf("wrong header: want=%q got=%q", want, lines[0])
+	}
+	if fields := strings.Fields(lines[2]); strings.Join(fields, " ") != "10ms 10ms 0x3: i32.const 42" {
+		t.Errorf("wrong annotation of the first instruction: %v", fields)
+	}
+	if fields := strings.Fields(lines[3]); strings.Join(fields, " ") != ". . 0x5: drop" {
+		t.Errorf("wrong annotation of the second instruction: %v", fields)
+	}
+}
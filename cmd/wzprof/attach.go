@@ -0,0 +1,60 @@
+package main
+
+import (
+	"context"
+	"flag"
+	"fmt"
+	"net/http"
+	"net/url"
+	"strconv"
+
+	"github.com/google/pprof/profile"
+
+	"github.com/stealthrocket/wzprof"
+)
+
+// runAttach implements the "wzprof attach" command, which captures a profile
+// of a running program embedding wzprof and serving its profilers on a unix
+// socket with wzprof.ServeSocket.
+func runAttach(args []string) error {
+	flags := flag.NewFlagSet("attach", flag.ContinueOnError)
+	output := flags.String("o", "profile.pprof", "Write the profile to the specified file.")
+	format := flags.String("format", "pprof", "Format of the profile (pprof, folded, speedscope, firefox).")
+	name := flags.String("profile", "profile", "Name of the profile to capture (e.g. profile, allocs, block).")
+	seconds := flags.Int("seconds", 0, "Duration of the profile in seconds, the CPU profile defaults to 30s and the other profiles are snapshots when not set.")
+	module := flags.String("module", "", "Name of the module to profile, when the program serves the profiles of several modules.")
+	paths, err := parseInterspersed(flags, args)
+	if err != nil {
+		return err
+	}
+	if len(paths) != 1 {
+		return fmt.Errorf("usage: wzprof attach [options] <socket> -o <profile.pprof>")
+	}
+
+	write, ok := profileFormats[*format]
+	if !ok {
+		return fmt.Errorf("unsupported profile format: %s", *format)
+	}
+
+	query := url.Values{}
+	if *seconds > 0 {
+		query.Set("seconds", strconv.Itoa(*seconds))
+	}
+	if *module != "" {
+		query.Set("module", *module)
+	}
+	stdout.Printf("capturing %s profile from %s", *name, paths[0])
+	prof, err := fetchProfile(context.Background(), wzprof.SocketClient(paths[0]), *name, query)
+	if err != nil {
+		return err
+	}
+	stdout.Printf("writing %s profile to %s", *name, *output)
+	return writeFile(*output, prof, write)
+}
+
+// fetchProfile requests the profile of the given name from the pprof handler
+// that the client is connected to.
+func fetchProfile(ctx context.Context, client *http.Client, name string, query url.Values) (*profile.Profile, error) {
+	u := &url.URL{Scheme: "http", Host: "wzprof", Path: "/debug/pprof/" + name, RawQuery: query.Encode()}
+	return getProfile(ctx, client, u.String(), name, nil)
+}
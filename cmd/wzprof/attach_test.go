@@ -0,0 +1,57 @@
+package main
+
+import (
+	"context"
+	"net/http"
+	"net/url"
+	"os"
+	"path/filepath"
+	"strings"
+	"testing"
+	"time"
+
+	"github.com/stealthrocket/wzprof"
+)
+
+func TestFetchProfile(t *testing.T) {
+	var query url.Values
+	handler := http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		if r.URL.Path != "/debug/pprof/profile" {
+			http.NotFound(w, r)
+			return
+		}
+		query = r.URL.Query()
+		_ = writePprof(w, newTestProfile(map[string]int64{"a": 10, "b": 20}))
+	})
+
+	path := filepath.Join(t.TempDir(), "wzprof.sock")
+	ctx, cancel := context.WithCancel(context.Background())
+	defer cancel()
+	go wzprof.ServeSocket(ctx, path, handler)
+	for i := 0; ; i++ {
+		if _, err := os.Stat(path); err == nil {
+			break
+		}
+		if i == 100 {
+			t.Fatal("socket was not created")
+		}
+		time.Sleep(10 * time.Millisecond)
+	}
+
+	client := wzprof.SocketClient(path)
+	prof, err := fetchProfile(ctx, client, "profile", url.Values{"seconds": {"5"}})
+	if err != nil {
+		t.Fatal(err)
+	}
+	if len(prof.Sample) != 2 {
+		t.Errorf("wrong number of samples: want=2 got=%d", len(prof.Sample))
+	}
+	if query.Get("seconds") != "5" {
+		t.Errorf("wrong query: %v", query)
+	}
+
+	_, err = fetchProfile(ctx, client, "missing", nil)
+	if err == nil || !strings.Contains(err.Error(), "404") {
+		t.Errorf("wrong error for missing profile: %v", err)
+	}
+}
@@ -0,0 +1,261 @@
+package main
+
+import (
+	"context"
+	"crypto/rand"
+	"errors"
+	"flag"
+	"fmt"
+	"io"
+	"os"
+	"path/filepath"
+	"runtime"
+	"sort"
+	"strings"
+	"text/tabwriter"
+	"time"
+
+	"github.com/tetratelabs/wazero"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/imports/wasi_snapshot_preview1"
+	"github.com/tetratelabs/wazero/sys"
+
+	"github.com/stealthrocket/wzprof"
+)
+
+// benchProfilers maps the names of the profiles of configuration files to the
+// constructors of the profilers measured by "wzprof bench".
+var benchProfilers = map[string]func(p *wzprof.Profiling) experimental.FunctionListenerFactory{
+	"block":      func(p *wzprof.Profiling) experimental.FunctionListenerFactory { return p.BlockProfiler() },
+	"calls":      func(p *wzprof.Profiling) experimental.FunctionListenerFactory { return p.CallProfiler() },
+	"cpu":        func(p *wzprof.Profiling) experimental.FunctionListenerFactory { return p.CPUProfiler() },
+	"gc":         func(p *wzprof.Profiling) experimental.FunctionListenerFactory { return p.GCProfiler() },
+	"memgrow":    func(p *wzprof.Profiling) experimental.FunctionListenerFactory { return p.GrowProfiler() },
+	"memory":     func(p *wzprof.Profiling) experimental.FunctionListenerFactory { return p.MemoryProfiler() },
+	"mutex":      func(p *wzprof.Profiling) experimental.FunctionListenerFactory { return p.MutexProfiler() },
+	"stackdepth": func(p *wzprof.Profiling) experimental.FunctionListenerFactory { return p.StackProfiler() },
+	"syscalls":   func(p *wzprof.Profiling) experimental.FunctionListenerFactory { return p.SyscallProfiler() },
+	"traps":      func(p *wzprof.Profiling) experimental.FunctionListenerFactory { return p.TrapProfiler() },
+}
+
+// runBench implements the "wzprof bench" command, which runs a module without
+// profilers, then with each profiler enabled, and reports the overhead of the
+// profilers on the wall time of the module and on the memory allocated by the
+// host, to help choosing the profilers and sampling rate of a program.
+func runBench(ctx context.Context, args []string) error {
+	names := make([]string, 0, len(benchProfilers))
+	for name := range benchProfilers {
+		names = append(names, name)
+	}
+	sort.Strings(names)
+
+	flags := flag.NewFlagSet("bench", flag.ContinueOnError)
+	profilers := flags.String("profilers", strings.Join(names, ","), "Comma-separated list of the profilers to measure.")
+	sampleRate := flags.Float64("sample", defaultSampleRate, "Set the profile sampling rate (0-1).")
+	count := flags.Int("count", 1, "Number of runs of each configuration, the fastest run is reported.")
+	interpreter := flags.Bool("interpreter", false, "Run the guest with the wazero interpreter instead of the compiler.")
+	mounts := flags.String("mount", "", "Comma-separated list of directories to mount (e.g. /tmp:/tmp:ro).")
+	if err := flags.Parse(args); err != nil {
+		return err
+	}
+	args = flags.Args()
+	if len(args) < 1 {
+		return fmt.Errorf("usage: wzprof bench [options] <module.wasm> [-- args...]")
+	}
+	if !(*sampleRate > 0) {
+		return fmt.Errorf("invalid sample rate: %g", *sampleRate)
+	}
+	if *count < 1 {
+		return fmt.Errorf("invalid number of runs: %d", *count)
+	}
+	for _, name := range split(*profilers) {
+		if _, ok := benchProfilers[name]; !ok {
+			return fmt.Errorf("unknown profiler: %s", name)
+		}
+	}
+
+	b := &bench{
+		filePath:    args[0],
+		args:        args[1:],
+		sampleRate:  *sampleRate,
+		interpreter: *interpreter || !compilerSupported(),
+		mounts:      split(*mounts),
+	}
+	if len(b.args) > 0 && b.args[0] == "--" {
+		b.args = b.args[1:]
+	}
+	wasmCode, err := os.ReadFile(b.filePath)
+	if err != nil {
+		return fmt.Errorf("reading wasm module: %w", err)
+	}
+	b.wasmCode = wasmCode
+
+	var results []benchResult
+	for _, name := range append([]string{""}, split(*profilers)...) {
+		r, err := b.measure(ctx, name, *count)
+		if err != nil {
+			return err
+		}
+		results = append(results, r)
+	}
+	return printBench(os.Stdout, results)
+}
+
+type bench struct {
+	filePath    string
+	wasmCode    []byte
+	args        []string
+	sampleRate  float64
+	interpreter bool
+	mounts      []string
+}
+
+// benchResult is the measure of a run of the module with a profiler, or of the
+// baseline run without profilers if the name is empty.
+type benchResult struct {
+	name      string
+	wallTime  time.Duration
+	allocated int64 // bytes allocated by the host during the run
+	heap      int64 // bytes retained in the host heap after the run
+}
+
+// measure runs the module count times with the profiler of the given name and
+// returns the fastest run.
+func (b *bench) measure(ctx context.Context, name string, count int) (benchResult, error) {
+	var best benchResult
+	for i := 0; i < count; i++ {
+		r, err := b.run(ctx, name)
+		if err != nil {
+			return r, err
+		}
+		if i == 0 || r.wallTime < best.wallTime {
+			best = r
+		}
+	}
+	return best, nil
+}
+
+func (b *bench) run(ctx context.Context, name string) (benchResult, error) {
+	result := benchResult{name: name}
+	wasmName := filepath.Base(b.filePath)
+
+	p := wzprof.ProfilingFor(b.wasmCode, wzprof.ModuleFile(wasmName))
+	var listener experimental.FunctionListenerFactory
+	if name != "" {
+		listener = benchProfilers[name](p)
+		// The CPU and memory profilers only record samples while a profile
+		// is being captured.
+		if profiler, ok := listener.(interface{ StartProfile() bool }); ok {
+			profiler.StartProfile()
+		}
+		if b.sampleRate < 1 {
+			listener = wzprof.Sample(b.sampleRate, listener)
+		}
+		ctx = context.WithValue(ctx, experimental.FunctionListenerFactoryKey{}, listener)
+	}
+
+	config := wazero.NewRuntimeConfig()
+	if b.interpreter {
+		config = wazero.NewRuntimeConfigInterpreter()
+	}
+	rt := wazero.NewRuntimeWithConfig(ctx, config.
+		WithDebugInfoEnabled(true).
+		WithCustomSections(true))
+	defer rt.Close(ctx)
+
+	compiledModule, err := rt.CompileModule(ctx, b.wasmCode)
+	if err != nil {
+		return result, fmt.Errorf("compiling wasm module: %w", err)
+	}
+	if err := p.Prepare(compiledModule); err != nil {
+		return result, fmt.Errorf("preparing wasm module: %w", err)
+	}
+	wasi_snapshot_preview1.MustInstantiate(ctx, rt)
+	if wzprof.ImportsHostModule(compiledModule) {
+		noop := func() {}
+		if _, err := wzprof.NewHostModule(rt, noop, noop).Instantiate(ctx); err != nil {
+			return result, fmt.Errorf("instantiating host module: %w", err)
+		}
+	}
+
+	// The output of the guest would be repeated for each run, only the
+	// errors are printed.
+	moduleConfig := wazero.NewModuleConfig().
+		WithStdout(io.Discard).
+		WithStderr(os.Stderr).
+		WithRandSource(rand.Reader).
+		WithSysNanosleep().
+		WithSysNanotime().
+		WithSysWalltime().
+		WithArgs(append([]string{wasmName}, b.args...)...).
+		WithFSConfig(createFSConfig(b.mounts))
+
+	if name == "" {
+		stdout.Printf("running %s without profilers", wasmName)
+	} else {
+		stdout.Printf("running %s with the %s profiler", wasmName, name)
+	}
+	before := readMemStats()
+	start := time.Now()
+	instance, err := rt.InstantiateModule(ctx, compiledModule, moduleConfig)
+	result.wallTime = time.Since(start)
+	if err != nil {
+		var exitErr *sys.ExitError
+		if !errors.As(err, &exitErr) || exitErr.ExitCode() != 0 {
+			return result, fmt.Errorf("running guest module: %w", err)
+		}
+	} else if err := instance.Close(ctx); err != nil {
+		return result, fmt.Errorf("closing guest module: %w", err)
+	}
+	after := readMemStats()
+	result.allocated = int64(after.TotalAlloc - before.TotalAlloc)
+	result.heap = int64(after.HeapAlloc) - int64(before.HeapAlloc)
+	// The samples recorded by the profiler are part of the heap retained
+	// after the run.
+	runtime.KeepAlive(listener)
+	return result, nil
+}
+
+// readMemStats returns the memory statistics of the host after a garbage
+// collection, so the size of the heap only counts the objects in use.
+func readMemStats() *runtime.MemStats {
+	runtime.GC()
+	stats := new(runtime.MemStats)
+	runtime.ReadMemStats(stats)
+	return stats
+}
+
+// printBench writes to w a table of the wall time and memory of the runs of a
+// module, with their overhead relative to the first run which is the baseline
+// without profilers.
+func printBench(w io.Writer, results []benchResult) error {
+	if len(results) == 0 {
+		return nil
+	}
+	base := results[0]
+	tw := tabwriter.NewWriter(w, 0, 8, 2, ' ', tabwriter.AlignRight)
+	fmt.Fprintf(tw, "profiler\twall time\toverhead\tallocated\toverhead\theap\t\n")
+	for _, r := range results {
+		name := r.name
+		if name == "" {
+			name = "(none)"
+		}
+		fmt.Fprintf(tw, "%s\t%s\t%s\t%s\t%s\t%s\t\n",
+			name,
+			formatValue(int64(r.wallTime), "nanoseconds"),
+			overheadOf(int64(r.wallTime), int64(base.wallTime)),
+			formatValue(r.allocated, "bytes"),
+			overheadOf(r.allocated, base.allocated),
+			formatValue(r.heap-base.heap, "bytes"),
+		)
+	}
+	return tw.Flush()
+}
+
+// overheadOf returns the relative difference of v to base as a percentage.
+func overheadOf(v, base int64) string {
+	if base == 0 {
+		return "-"
+	}
+	return fmt.Sprintf("%+.2f%%", 100*float64(v-base)/float64(base))
+}
@@ -0,0 +1,51 @@
+package main
+
+import (
+	"bytes"
+	"context"
+	"os"
+	"strings"
+	"testing"
+	"time"
+)
+
+func TestPrintBench(t *testing.T) {
+	var buf bytes.Buffer
+	err := printBench(&buf, []benchResult{
+		{wallTime: 100 * time.Millisecond, allocated: 1024, heap: 2048},
+		{name: "cpu", wallTime: 150 * time.Millisecond, allocated: 4096, heap: 3072},
+	})
+	if err != nil {
+		t.Fatal(err)
+	}
+	lines := strings.Split(strings.TrimSuffix(buf.String(), "\n"), "\n")
+	if len(lines) != 3 {
+		t.Fatalf("wrong number of lines:\n%s", buf.String())
+	}
+	for i, want := range [][]string{
+		{"profiler", "wall", "time", "overhead", "allocated", "overhead", "heap"},
+		{"(none)", "100ms", "+0.00%", "1.00kB", "+0.00%", "0B"},
+		{"cpu", "150ms", "+50.00%", "4.00kB", "+300.00%", "1.00kB"},
+	} {
+		if got := strings.Fields(lines[i]); strings.Join(got, " ") != strings.Join(want, " ") {
+			t.Errorf("wrong line %d: want=%q got=%q", i, want, got)
+		}
+	}
+}
+
+func TestBenchRun(t *testing.T) {
+	b := &bench{filePath: "../../testdata/c/simple.wasm", sampleRate: 1, interpreter: true}
+	var err error
+	if b.wasmCode, err = os.ReadFile(b.filePath); err != nil {
+		t.Fatal(err)
+	}
+	for _, name := range []string{"", "cpu", "memory"} {
+		r, err := b.measure(context.Background(), name, 1)
+		if err != nil {
+			t.Fatalf("%q: %v", name, err)
+		}
+		if r.name != name || r.wallTime <= 0 {
+			t.Errorf("wrong result of run with profiler %q: %+v", name, r)
+		}
+	}
+}
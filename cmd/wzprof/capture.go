@@ -0,0 +1,130 @@
+package main
+
+import (
+	"context"
+	"os"
+	"os/signal"
+	"strconv"
+	"sync"
+	"time"
+)
+
+// capture controls the recording of the guest profiles written to files.
+//
+// The profiles are recorded from the start of the program until it exits, but
+// the -duration flag or signals may stop the recording earlier, start it again
+// or rotate the profile files (see captureSignals), and -rotate rotates them
+// periodically. The files written after the first ones are suffixed with their
+// sequence number (e.g. cpu.pprof.1).
+type capture struct {
+	mutex   sync.Mutex
+	active  bool
+	count   int
+	started time.Time
+	starts  []func()
+	writes  []func(suffix string)
+}
+
+// start begins recording the profiles, unless they are already recorded.
+func (c *capture) start() {
+	c.mutex.Lock()
+	defer c.mutex.Unlock()
+	if !c.active {
+		c.begin()
+	}
+}
+
+// stop writes the profiles recorded since they were started and stops the
+// recording.
+func (c *capture) stop() {
+	c.mutex.Lock()
+	defer c.mutex.Unlock()
+	if c.active {
+		c.end()
+	}
+}
+
+// toggle stops the recording if the profiles are recorded, or starts it.
+func (c *capture) toggle() {
+	c.mutex.Lock()
+	defer c.mutex.Unlock()
+	if c.active {
+		stdout.Printf("stopping the capture of guest profiles")
+		c.end()
+	} else {
+		stdout.Printf("starting the capture of guest profiles")
+		c.begin()
+	}
+}
+
+// rotate writes the profiles recorded since they were started and starts
+// recording the next ones.
+func (c *capture) rotate() {
+	c.mutex.Lock()
+	defer c.mutex.Unlock()
+	if c.active {
+		stdout.Printf("rotating the capture of guest profiles")
+		c.end()
+		c.begin()
+	}
+}
+
+func (c *capture) begin() {
+	c.started = time.Now()
+	for _, start := range c.starts {
+		start()
+	}
+	c.active = true
+}
+
+func (c *capture) end() {
+	suffix := ""
+	if c.count > 0 {
+		suffix = "." + strconv.Itoa(c.count)
+	}
+	c.count++
+	for _, write := range c.writes {
+		write(suffix)
+	}
+	c.active = false
+}
+
+// rotateEvery rotates the profiles at the given interval until ctx is done.
+func (c *capture) rotateEvery(ctx context.Context, interval time.Duration) {
+	t := time.NewTicker(interval)
+	defer t.Stop()
+
+	for {
+		select {
+		case <-ctx.Done():
+			return
+		case <-t.C:
+			c.rotate()
+		}
+	}
+}
+
+// handleSignals toggles the recording when the first of captureSignals is
+// received and rotates the profiles on the second one, until ctx is done.
+func (c *capture) handleSignals(ctx context.Context) {
+	if len(captureSignals) < 2 {
+		return
+	}
+	signals := make(chan os.Signal, 1)
+	signal.Notify(signals, captureSignals...)
+	defer signal.Stop(signals)
+
+	for {
+		select {
+		case <-ctx.Done():
+			return
+		case sig := <-signals:
+			switch sig {
+			case captureSignals[0]:
+				c.toggle()
+			case captureSignals[1]:
+				c.rotate()
+			}
+		}
+	}
+}
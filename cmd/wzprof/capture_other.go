@@ -0,0 +1,9 @@
+//go:build !unix
+
+package main
+
+import "os"
+
+// captureSignals is empty on systems without the SIGUSR1 and SIGUSR2 signals,
+// the capture of profiles can only be limited with -duration.
+var captureSignals []os.Signal
@@ -0,0 +1,44 @@
+package main
+
+import (
+	"path/filepath"
+	"reflect"
+	"testing"
+	"time"
+)
+
+func TestCapture(t *testing.T) {
+	var events []string
+	c := &capture{
+		starts: []func(){func() { events = append(events, "start") }},
+		writes: []func(string){func(suffix string) { events = append(events, "write"+suffix) }},
+	}
+
+	c.start()
+	c.start() // already started
+	c.rotate()
+	c.toggle() // stops
+	c.rotate() // not started
+	c.toggle() // starts
+	c.stop()
+	c.stop() // already stopped
+
+	want := []string{
+		"start",
+		"write", "start",
+		"write.1",
+		"start",
+		"write.2",
+	}
+	if !reflect.DeepEqual(events, want) {
+		t.Errorf("wrong sequence of events:\nwant=%v\ngot= %v", want, events)
+	}
+}
+
+func TestOutputPath(t *testing.T) {
+	started := time.Date(2023, 6, 1, 14, 0, 0, 0, time.FixedZone("CEST", 2*3600))
+	want := filepath.Join("profiles", "cpu-20230601T120000Z.pb.gz")
+	if path := outputPath("profiles", "cpu", started); path != want {
+		t.Errorf("wrong output path: want=%s got=%s", want, path)
+	}
+}
@@ -0,0 +1,12 @@
+//go:build unix
+
+package main
+
+import (
+	"os"
+	"syscall"
+)
+
+// captureSignals are the signals controlling the capture of profiles: SIGUSR1
+// stops or starts the recording, and SIGUSR2 rotates the profile files.
+var captureSignals = []os.Signal{syscall.SIGUSR1, syscall.SIGUSR2}
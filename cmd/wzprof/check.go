@@ -0,0 +1,202 @@
+package main
+
+import (
+	"flag"
+	"fmt"
+	"io"
+	"os"
+	"strconv"
+	"strings"
+	"time"
+
+	"github.com/google/pprof/profile"
+)
+
+// runCheck implements the "wzprof check" command, which verifies that the
+// functions of a profile stay within budgets, and that the profile did not
+// regress compared to a baseline, so performance gates can be set up in CI.
+// The command fails if any of the checks fails.
+func runCheck(args []string) error {
+	var budgets stringList
+	flags := flag.NewFlagSet("check", flag.ContinueOnError)
+	flags.Var(&budgets, "max", "Budget of the cumulative value of a function, as \"name<=value\" (e.g. \"my_app::hot_fn<=200ms\"). May be repeated.")
+	baseline := flags.String("baseline", "", "Profile that the values of the functions with a budget and the total value of the profile are compared to.")
+	threshold := flags.String("regression-threshold", "10%", "Increase of the values compared to the baseline above which the check fails.")
+	sampleType := flags.String("sample-type", "", "Sample type of the checks (default: the default sample type of the profile, or the last one).")
+	paths, err := parseInterspersed(flags, args)
+	if err != nil {
+		return err
+	}
+	if len(paths) != 1 || (len(budgets) == 0 && *baseline == "") {
+		return fmt.Errorf("usage: wzprof check [-max name<=value]... [-baseline <profile>] <profile>")
+	}
+	ratio, err := parsePercent(*threshold)
+	if err != nil {
+		return fmt.Errorf("invalid regression threshold: %w", err)
+	}
+
+	prof, err := readProfile(paths[0])
+	if err != nil {
+		return err
+	}
+	var base *profile.Profile
+	if *baseline != "" {
+		if base, err = readProfile(*baseline); err != nil {
+			return err
+		}
+	}
+	failed, err := checkProfile(os.Stdout, prof, base, *sampleType, budgets, ratio)
+	if err != nil {
+		return err
+	}
+	if failed > 0 {
+		return fmt.Errorf("%d check(s) failed", failed)
+	}
+	return nil
+}
+
+// stringList is a flag which may be repeated to collect multiple values.
+type stringList []string
+
+func (l *stringList) String() string { return strings.Join(*l, " ") }
+
+func (l *stringList) Set(s string) error {
+	*l = append(*l, s)
+	return nil
+}
+
+// checkProfile writes to w the result of the checks of prof, and returns the
+// number of checks which failed. The budgets apply to the cumulative values of
+// the functions, which must be present in the profile. When base is not nil,
+// the values of the functions with a budget and the total value of prof must
+// not exceed those of base by more than the threshold ratio; the functions
+// absent from base are not compared.
+func checkProfile(w io.Writer, prof, base *profile.Profile, sampleType string, budgets []string, threshold float64) (int, error) {
+	index, err := prof.SampleIndexByName(sampleType)
+	if err != nil {
+		return 0, err
+	}
+	valueType := prof.SampleType[index]
+	entries, total := functionValues(prof, index)
+	format := func(v int64) string { return formatValue(v, valueType.Unit) }
+
+	failed := 0
+	report := func(ok bool, msg string, args ...any) {
+		status := "ok"
+		if !ok {
+			status = "FAIL"
+			failed++
+		}
+		fmt.Fprintf(w, "%-4s  %s\n", status, fmt.Sprintf(msg, args...))
+	}
+
+	var names []string
+	for _, budget := range budgets {
+		name, limit, err := parseBudget(budget, valueType.Unit)
+		if err != nil {
+			return 0, err
+		}
+		names = append(names, name)
+		e := entries[name]
+		if e == nil {
+			report(false, "%s: not found in the profile", name)
+			continue
+		}
+		if e.cum <= limit {
+			report(true, "%s: %s <= %s", name, format(e.cum), format(limit))
+		} else {
+			report(false, "%s: %s > %s", name, format(e.cum), format(limit))
+		}
+	}
+
+	if base == nil {
+		return failed, nil
+	}
+	baseIndex, err := base.SampleIndexByName(valueType.Type)
+	if err != nil {
+		return 0, fmt.Errorf("baseline: %w", err)
+	}
+	baseEntries, baseTotal := functionValues(base, baseIndex)
+
+	compare := func(name string, v, baseValue int64) {
+		if baseValue <= 0 {
+			return
+		}
+		change := float64(v-baseValue) / float64(baseValue)
+		msg := "%s: %s vs %s in the baseline (%+.2f%%)"
+		if change > threshold {
+			report(false, msg+" > %+.2f%%", name, format(v), format(baseValue), 100*change, 100*threshold)
+		} else {
+			report(true, msg, name, format(v), format(baseValue), 100*change)
+		}
+	}
+	compare("total", total, baseTotal)
+	for _, name := range names {
+		if e, baseEntry := entries[name], baseEntries[name]; e != nil && baseEntry != nil {
+			compare(name, e.cum, baseEntry.cum)
+		}
+	}
+	return failed, nil
+}
+
+// parseBudget parses a budget of the form "name<=value", where the value is
+// expressed in the unit of the sample type (e.g. "200ms" for nanoseconds or
+// "4MiB" for bytes).
+func parseBudget(s, unit string) (string, int64, error) {
+	i := strings.LastIndex(s, "<=")
+	if i <= 0 {
+		return "", 0, fmt.Errorf("invalid budget %q: expected name<=value", s)
+	}
+	name, value := strings.TrimSpace(s[:i]), strings.TrimSpace(s[i+2:])
+	limit, err := parseValue(value, unit)
+	if err != nil {
+		return "", 0, fmt.Errorf("invalid budget %q: %w", s, err)
+	}
+	return name, limit, nil
+}
+
+// parseValue parses a value of the given unit, the reverse of formatValue.
+// Values of all units may also be plain integers.
+func parseValue(s, unit string) (int64, error) {
+	if v, err := strconv.ParseInt(s, 10, 64); err == nil {
+		return v, nil
+	}
+	switch unit {
+	case "nanoseconds":
+		d, err := time.ParseDuration(s)
+		return int64(d), err
+	case "bytes":
+		for _, u := range byteUnits {
+			if num, ok := strings.CutSuffix(s, u.suffix); ok {
+				f, err := strconv.ParseFloat(num, 64)
+				if err != nil {
+					return 0, err
+				}
+				return int64(f * float64(u.size)), nil
+			}
+		}
+	}
+	return 0, fmt.Errorf("invalid value for unit %s: %q", unit, s)
+}
+
+// byteUnits are the suffixes of sizes accepted by parseValue, the units are
+// powers of 1024 like the sizes printed by formatValue. The longest suffixes
+// come first so "kB" is not mistaken for "B".
+var byteUnits = [...]struct {
+	suffix string
+	size   int64
+}{
+	{"KiB", 1 << 10}, {"MiB", 1 << 20}, {"GiB", 1 << 30},
+	{"kB", 1 << 10}, {"KB", 1 << 10}, {"MB", 1 << 20}, {"GB", 1 << 30},
+	{"B", 1},
+}
+
+// parsePercent parses a percentage like "10%" (or a plain number of percents)
+// to a ratio.
+func parsePercent(s string) (float64, error) {
+	f, err := strconv.ParseFloat(strings.TrimSuffix(strings.TrimSpace(s), "%"), 64)
+	if err != nil {
+		return 0, err
+	}
+	return f / 100, nil
+}
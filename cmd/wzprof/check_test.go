@@ -0,0 +1,85 @@
+package main
+
+import (
+	"bytes"
+	"strings"
+	"testing"
+
+	"github.com/google/pprof/profile"
+)
+
+func TestCheckProfile(t *testing.T) {
+	newProfile := func(a, b int64) *profile.Profile {
+		fa := &profile.Function{ID: 1, Name: "a"}
+		fb := &profile.Function{ID: 2, Name: "b"}
+		locA := &profile.Location{ID: 1, Line: []profile.Line{{Function: fa}}}
+		locB := &profile.Location{ID: 2, Line: []profile.Line{{Function: fb}}}
+		return &profile.Profile{
+			SampleType: []*profile.ValueType{{Type: "cpu", Unit: "nanoseconds"}},
+			Sample: []*profile.Sample{
+				// b called by a.
+				{Location: []*profile.Location{locB, locA}, Value: []int64{b}},
+				{Location: []*profile.Location{locA}, Value: []int64{a}},
+			},
+			Location: []*profile.Location{locA, locB},
+			Function: []*profile.Function{fa, fb},
+		}
+	}
+	prof := newProfile(10e6, 30e6)
+
+	for _, test := range []struct {
+		budgets []string
+		base    *profile.Profile
+		failed  int
+	}{
+		// The budgets apply to cumulative values.
+		{budgets: []string{"a<=40ms", "b<=30ms"}, failed: 0},
+		{budgets: []string{"a<=39ms", "b<=30000000"}, failed: 1},
+		{budgets: []string{"c<=1s"}, failed: 1},
+		// The total and the functions with a budget are compared.
+		{base: newProfile(10e6, 28e6), failed: 0},
+		{base: newProfile(5e6, 25e6), failed: 1},
+		{budgets: []string{"b<=1s"}, base: newProfile(12e6, 25e6), failed: 1},
+		{budgets: []string{"a<=1s"}, base: newProfile(12e6, 25e6), failed: 0},
+	} {
+		var buf bytes.Buffer
+		failed, err := checkProfile(&buf, prof, test.base, "", test.budgets, 0.1)
+		if err != nil {
+			t.Fatal(err)
+		}
+		if failed != test.failed {
+			t.Errorf("%q: wrong number of failed checks: want=%d got=%d\n%s", test.budgets, test.failed, failed, buf.String())
+		}
+		if n := strings.Count(buf.String(), "FAIL"); n != failed {
+			t.Errorf("%q: wrong number of failed checks reported: want=%d got=%d\n%s", test.budgets, failed, n, buf.String())
+		}
+	}
+
+	if _, err := checkProfile(new(bytes.Buffer), prof, nil, "", []string{"a"}, 0.1); err == nil {
+		t.Error("invalid budget accepted")
+	}
+}
+
+func TestParseValue(t *testing.T) {
+	for _, test := range []struct {
+		s, unit string
+		want    int64
+	}{
+		{"200ms", "nanoseconds", 200e6},
+		{"42", "nanoseconds", 42},
+		{"4MiB", "bytes", 4 << 20},
+		{"1.5kB", "bytes", 1536},
+		{"10B", "bytes", 10},
+		{"7", "count", 7},
+	} {
+		v, err := parseValue(test.s, test.unit)
+		if err != nil {
+			t.Errorf("%s: %v", test.s, err)
+		} else if v != test.want {
+			t.Errorf("%s: want=%d got=%d", test.s, test.want, v)
+		}
+	}
+	if _, err := parseValue("1s", "bytes"); err == nil {
+		t.Error("duration accepted as a size")
+	}
+}
@@ -0,0 +1,78 @@
+package main
+
+import (
+	"flag"
+	"strconv"
+
+	"github.com/stealthrocket/wzprof"
+)
+
+// configProfileFlags maps the names of the profiles of configuration files to
+// the flags enabling them.
+var configProfileFlags = map[string]string{
+	"block":      "blockprofile",
+	"calls":      "callprofile",
+	"cpu":        "cpuprofile",
+	"gc":         "gcprofile",
+	"memgrow":    "memgrowprofile",
+	"memory":     "memprofile",
+	"mutex":      "mutexprofile",
+	"stackdepth": "stackprofile",
+	"syscalls":   "syscallprofile",
+	"traps":      "traps",
+}
+
+// loadConfig reads the configuration file at path and applies the settings of
+// the module of the given name to the flags which were not set on the command
+// line, so the flags take precedence over the configuration file.
+func loadConfig(path, moduleName string) error {
+	config, err := wzprof.ReadConfig(path)
+	if err != nil {
+		return err
+	}
+	m := config.Module(moduleName)
+
+	values := map[string]string{
+		"filter":     m.Filter,
+		"exclude":    m.Exclude,
+		"elide":      m.Elide,
+		"debug-info": m.DebugInfo,
+		"source-map": m.SourceMap,
+		"output-dir": m.OutputDir,
+		"push-url":   m.PushURL,
+	}
+	if m.SampleRate != 0 {
+		values["sample"] = strconv.FormatFloat(m.SampleRate, 'g', -1, 64)
+	}
+	if m.MaxStacks != 0 {
+		values["max-stacks"] = strconv.Itoa(m.MaxStacks)
+	}
+	if m.StripTemplates {
+		values["strip-templates"] = "true"
+	}
+	if m.HostFrames {
+		values["host-frames"] = "true"
+	}
+	if m.NativeFrames {
+		values["native-frames"] = "true"
+	}
+	for name, path := range m.Profiles {
+		if name == "traps" {
+			path = "true"
+		}
+		values[configProfileFlags[name]] = path
+	}
+
+	set := make(map[string]bool)
+	flag.Visit(func(f *flag.Flag) { set[f.Name] = true })
+
+	for name, value := range values {
+		if value == "" || set[name] {
+			continue
+		}
+		if err := flag.Set(name, value); err != nil {
+			return err
+		}
+	}
+	return nil
+}
@@ -0,0 +1,157 @@
+package main
+
+import (
+	"flag"
+	"fmt"
+	"io"
+	"os"
+	"sort"
+	"text/tabwriter"
+
+	"github.com/google/pprof/profile"
+)
+
+// runDiff implements the "wzprof diff" command, which computes the difference
+// between two profiles, for example to quantify a regression between two builds
+// of a wasm module without the Go toolchain to run "go tool pprof -diff_base".
+func runDiff(args []string) error {
+	flags := flag.NewFlagSet("diff", flag.ContinueOnError)
+	output := flags.String("o", "", "Write the difference to the specified file instead of printing a summary.")
+	format := flags.String("format", "pprof", "Format of the profile written with -o (pprof, folded, speedscope, firefox).")
+	sampleType := flags.String("sample-type", "", "Sample type of the summary (default: the default sample type of the profiles, or the last one).")
+	top := flags.Int("top", 20, "Number of functions printed in the summary.")
+	paths, err := parseInterspersed(flags, args)
+	if err != nil {
+		return err
+	}
+	if len(paths) != 2 {
+		return fmt.Errorf("usage: wzprof diff [options] <before.pprof> <after.pprof>")
+	}
+
+	base, err := readProfile(paths[0])
+	if err != nil {
+		return err
+	}
+	prof, err := readProfile(paths[1])
+	if err != nil {
+		return err
+	}
+	diff, err := diffProfiles(base, prof)
+	if err != nil {
+		return err
+	}
+
+	if *output != "" {
+		write, ok := profileFormats[*format]
+		if !ok {
+			return fmt.Errorf("unsupported profile format: %s", *format)
+		}
+		return writeFile(*output, diff, write)
+	}
+	return printDiff(os.Stdout, base, diff, *sampleType, *top)
+}
+
+func readProfile(path string) (*profile.Profile, error) {
+	f, err := os.Open(path)
+	if err != nil {
+		return nil, err
+	}
+	defer f.Close()
+	prof, err := profile.Parse(f)
+	if err != nil {
+		return nil, fmt.Errorf("parsing %s: %w", path, err)
+	}
+	return prof, nil
+}
+
+// diffProfiles returns a profile of the values of prof minus the values of
+// base, with the same semantics as the -diff_base option of pprof: the samples
+// of base are negated and merged with the samples of prof. The call stacks
+// with the same values in both profiles cancel out and are removed.
+func diffProfiles(base, prof *profile.Profile) (*profile.Profile, error) {
+	base = base.Copy()
+	base.Scale(-1)
+
+	diff, err := profile.Merge([]*profile.Profile{prof, base})
+	if err != nil {
+		return nil, fmt.Errorf("profiles cannot be compared: %w", err)
+	}
+
+	samples := diff.Sample[:0]
+	for _, s := range diff.Sample {
+		for _, v := range s.Value {
+			if v != 0 {
+				samples = append(samples, s)
+				break
+			}
+		}
+	}
+	diff.Sample = samples
+	return diff, nil
+}
+
+// printDiff writes a summary of the difference to w, listing the functions of
+// which the flat values changed the most.
+func printDiff(w io.Writer, base, diff *profile.Profile, sampleType string, top int) error {
+	index, err := diff.SampleIndexByName(sampleType)
+	if err != nil {
+		return err
+	}
+	valueType := diff.SampleType[index]
+
+	var total int64
+	for _, s := range base.Sample {
+		total += s.Value[index]
+	}
+
+	var change int64
+	flat := make(map[string]int64)
+	for _, s := range diff.Sample {
+		change += s.Value[index]
+		// The first line of the first location is the innermost function
+		// of the call stack, the flat value is attributed to it.
+		if len(s.Location) > 0 && len(s.Location[0].Line) > 0 {
+			flat[s.Location[0].Line[0].Function.Name] += s.Value[index]
+		}
+	}
+
+	names := make([]string, 0, len(flat))
+	for name, v := range flat {
+		if v != 0 {
+			names = append(names, name)
+		}
+	}
+	sort.Slice(names, func(i, j int) bool {
+		vi, vj := abs(flat[names[i]]), abs(flat[names[j]])
+		if vi != vj {
+			return vi > vj
+		}
+		return names[i] < names[j]
+	})
+	if len(names) > top {
+		names = names[:top]
+	}
+
+	fmt.Fprintf(w, "%s (%s): %+d (%s of %d)\n", valueType.Type, valueType.Unit, change, percent(change, total), total)
+	tw := tabwriter.NewWriter(w, 0, 8, 2, ' ', tabwriter.AlignRight)
+	fmt.Fprintln(tw, "change\tchange%\t")
+	for _, name := range names {
+		v := flat[name]
+		fmt.Fprintf(tw, "%+d\t%s\t  %s\n", v, percent(v, total), name)
+	}
+	return tw.Flush()
+}
+
+func percent(v, total int64) string {
+	if total == 0 {
+		return "n/a"
+	}
+	return fmt.Sprintf("%+.2f%%", 100*float64(v)/float64(total))
+}
+
+func abs(v int64) int64 {
+	if v < 0 {
+		return -v
+	}
+	return v
+}
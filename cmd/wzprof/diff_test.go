@@ -0,0 +1,69 @@
+package main
+
+import (
+	"bytes"
+	"strings"
+	"testing"
+
+	"github.com/google/pprof/profile"
+)
+
+func TestDiffProfiles(t *testing.T) {
+	base := newTestProfile(map[string]int64{"a": 100, "b": 50})
+	prof := newTestProfile(map[string]int64{"a": 100, "b": 80, "c": 20})
+
+	diff, err := diffProfiles(base, prof)
+	if err != nil {
+		t.Fatal(err)
+	}
+
+	values := make(map[string]int64)
+	for _, s := range diff.Sample {
+		values[s.Location[0].Line[0].Function.Name] += s.Value[0]
+	}
+	if len(values) != 2 || values["b"] != 30 || values["c"] != 20 {
+		t.Errorf("wrong values of the difference: %v", values)
+	}
+
+	// The base profile must not be modified.
+	if v := base.Sample[0].Value[0]; v != 100 {
+		t.Errorf("base profile was modified: want=100 got=%d", v)
+	}
+
+	var buf bytes.Buffer
+	if err := printDiff(&buf, base, diff, "", 10); err != nil {
+		t.Fatal(err)
+	}
+	output := buf.String()
+	if !strings.HasPrefix(output, "cpu (nanoseconds): +50 (+33.33% of 150)\n") {
+		t.Errorf("wrong summary:\n%s", output)
+	}
+	if b, c := strings.Index(output, " b\n"), strings.Index(output, " c\n"); b < 0 || c < 0 || b > c {
+		t.Errorf("functions are not sorted by change:\n%s", output)
+	}
+}
+
+// newTestProfile returns a CPU profile with a sample of the given value for
+// each function.
+func newTestProfile(values map[string]int64) *profile.Profile {
+	prof := &profile.Profile{
+		SampleType: []*profile.ValueType{{Type: "cpu", Unit: "nanoseconds"}},
+		PeriodType: &profile.ValueType{Type: "cpu", Unit: "nanoseconds"},
+	}
+	for _, name := range []string{"a", "b", "c"} {
+		v, ok := values[name]
+		if !ok {
+			continue
+		}
+		id := uint64(len(prof.Function) + 1)
+		fn := &profile.Function{ID: id, Name: name, SystemName: name}
+		loc := &profile.Location{ID: id, Line: []profile.Line{{Function: fn}}}
+		prof.Function = append(prof.Function, fn)
+		prof.Location = append(prof.Location, loc)
+		prof.Sample = append(prof.Sample, &profile.Sample{
+			Location: []*profile.Location{loc},
+			Value:    []int64{v},
+		})
+	}
+	return prof
+}
@@ -0,0 +1,205 @@
+package main
+
+import (
+	"context"
+	"crypto/tls"
+	"crypto/x509"
+	"errors"
+	"flag"
+	"fmt"
+	"io"
+	"net/http"
+	"net/url"
+	"os"
+	"path"
+	"strconv"
+	"strings"
+	"time"
+
+	"github.com/google/pprof/profile"
+)
+
+// runFetch implements the "wzprof fetch" command, which downloads a profile
+// from a pprof http endpoint, like the -pprof-addr endpoint of wzprof or the
+// net/http/pprof handlers of Go programs, without requiring the Go toolchain.
+func runFetch(ctx context.Context, args []string) error {
+	flags := flag.NewFlagSet("fetch", flag.ContinueOnError)
+	output := flags.String("o", "profile.pprof", "Write the profile to the specified file.")
+	format := flags.String("format", "pprof", "Format of the profile (pprof, folded, speedscope, firefox).")
+	timeout := flags.Duration("timeout", 0, "Timeout of each attempt to fetch the profile (default: 30s more than the duration requested with the seconds parameter of the URL).")
+	retries := flags.Int("retries", 3, "Number of times to retry fetching the profile after network errors or server errors.")
+	retryDelay := flags.Duration("retry-delay", time.Second, "Delay before the first retry, which doubles after each attempt.")
+	token := flags.String("token", os.Getenv("WZPROF_PPROF_TOKEN"), "Authenticate with this bearer token (default to $WZPROF_PPROF_TOKEN), the user:password credentials of the URL are sent with basic authentication.")
+	caCert := flags.String("cacert", "", "Verify the certificate of the server with the certificate authorities of this PEM file instead of those of the system.")
+	cert := flags.String("cert", "", "Authenticate with the client certificate of this PEM file (requires -key).")
+	key := flags.String("key", "", "PEM file of the private key of the -cert client certificate.")
+	insecure := flags.Bool("insecure", false, "Do not verify the certificate of the server.")
+	urls, err := parseInterspersed(flags, args)
+	if err != nil {
+		return err
+	}
+	if len(urls) != 1 {
+		return fmt.Errorf("usage: wzprof fetch [options] <url> -o <profile.pprof>")
+	}
+
+	write, ok := profileFormats[*format]
+	if !ok {
+		return fmt.Errorf("unsupported profile format: %s", *format)
+	}
+	u, err := url.Parse(urls[0])
+	if err != nil {
+		return err
+	}
+	if u.Scheme != "http" && u.Scheme != "https" {
+		return fmt.Errorf("unsupported URL scheme: %q", u.Scheme)
+	}
+	if *timeout == 0 {
+		*timeout = fetchTimeout(u)
+	}
+	tlsConfig, err := fetchTLSConfig(*caCert, *cert, *key, *insecure)
+	if err != nil {
+		return err
+	}
+	client := &http.Client{
+		Transport: &http.Transport{
+			Proxy:           http.ProxyFromEnvironment,
+			TLSClientConfig: tlsConfig,
+		},
+	}
+	header := make(http.Header)
+	if *token != "" {
+		header.Set("Authorization", "Bearer "+*token)
+	}
+
+	name := path.Base(u.Path)
+	stdout.Printf("fetching %s profile from %s", name, u.Redacted())
+	prof, err := retryFetch(ctx, *retries, *retryDelay, func(ctx context.Context) (*profile.Profile, error) {
+		ctx, cancel := context.WithTimeout(ctx, *timeout)
+		defer cancel()
+		return getProfile(ctx, client, u.String(), name, header)
+	})
+	if err != nil {
+		return err
+	}
+	stdout.Printf("writing %s profile to %s", name, *output)
+	return writeFile(*output, prof, write)
+}
+
+// fetchTimeoutMargin is the time given to the servers to respond in addition
+// to the duration of the profiles that they record.
+const fetchTimeoutMargin = 30 * time.Second
+
+// fetchTimeout returns the default timeout of the requests fetching the profile
+// at u, which accounts for the duration of delta and CPU profiles set with the
+// seconds parameter.
+func fetchTimeout(u *url.URL) time.Duration {
+	seconds, err := strconv.Atoi(u.Query().Get("seconds"))
+	if err != nil || seconds < 0 {
+		seconds = 0
+	}
+	return time.Duration(seconds)*time.Second + fetchTimeoutMargin
+}
+
+// fetchTLSConfig returns the TLS configuration of the client fetching profiles
+// from https endpoints.
+func fetchTLSConfig(caCert, cert, key string, insecure bool) (*tls.Config, error) {
+	config := &tls.Config{InsecureSkipVerify: insecure}
+	if caCert != "" {
+		b, err := os.ReadFile(caCert)
+		if err != nil {
+			return nil, err
+		}
+		config.RootCAs = x509.NewCertPool()
+		if !config.RootCAs.AppendCertsFromPEM(b) {
+			return nil, fmt.Errorf("no certificates found in %s", caCert)
+		}
+	}
+	if cert != "" || key != "" {
+		if cert == "" || key == "" {
+			return nil, fmt.Errorf("-cert and -key must be set together")
+		}
+		c, err := tls.LoadX509KeyPair(cert, key)
+		if err != nil {
+			return nil, err
+		}
+		config.Certificates = []tls.Certificate{c}
+	}
+	return config, nil
+}
+
+// retryFetch calls fetch until it succeeds, retrying up to the given number of
+// times when the errors are temporary, with an exponential backoff starting at
+// delay.
+func retryFetch(ctx context.Context, retries int, delay time.Duration, fetch func(context.Context) (*profile.Profile, error)) (*profile.Profile, error) {
+	for attempt := 0; ; attempt++ {
+		prof, err := fetch(ctx)
+		if err == nil || attempt >= retries || !retryable(err) {
+			return prof, err
+		}
+		stdout.Printf("retrying in %s: %v", delay, err)
+		t := time.NewTimer(delay)
+		select {
+		case <-t.C:
+		case <-ctx.Done():
+			t.Stop()
+			return nil, err
+		}
+		delay *= 2
+	}
+}
+
+// retryable returns true if err is a network error or a server error, which
+// may not happen again.
+func retryable(err error) bool {
+	var status *statusError
+	if errors.As(err, &status) {
+		return status.code >= 500 || status.code == http.StatusTooManyRequests
+	}
+	var urlError *url.Error
+	return errors.As(err, &urlError)
+}
+
+// statusError is the error returned by getProfile when the server responds
+// with a status other than 200.
+type statusError struct {
+	name   string
+	code   int
+	status string
+	body   string
+}
+
+func (e *statusError) Error() string {
+	return fmt.Sprintf("capturing %s profile: %s: %s", e.name, e.status, e.body)
+}
+
+// getProfile requests the profile of the given name at the URL u, with the
+// headers of the request set to header.
+func getProfile(ctx context.Context, client *http.Client, u, name string, header http.Header) (*profile.Profile, error) {
+	req, err := http.NewRequestWithContext(ctx, http.MethodGet, u, nil)
+	if err != nil {
+		return nil, err
+	}
+	for k, v := range header {
+		req.Header[k] = v
+	}
+	res, err := client.Do(req)
+	if err != nil {
+		return nil, err
+	}
+	defer res.Body.Close()
+
+	if res.StatusCode != http.StatusOK {
+		b, _ := io.ReadAll(io.LimitReader(res.Body, 1024))
+		return nil, &statusError{
+			name:   name,
+			code:   res.StatusCode,
+			status: res.Status,
+			body:   strings.TrimSpace(string(b)),
+		}
+	}
+	prof, err := profile.Parse(res.Body)
+	if err != nil {
+		return nil, fmt.Errorf("parsing %s profile: %w", name, err)
+	}
+	return prof, nil
+}
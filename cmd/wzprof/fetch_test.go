@@ -0,0 +1,79 @@
+package main
+
+import (
+	"context"
+	"net/http"
+	"net/http/httptest"
+	"net/url"
+	"path/filepath"
+	"testing"
+	"time"
+)
+
+func TestRunFetch(t *testing.T) {
+	attempts := 0
+	handler := http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		attempts++
+		if r.Header.Get("Authorization") != "Bearer secret" {
+			http.Error(w, "unauthorized", http.StatusUnauthorized)
+			return
+		}
+		if attempts == 1 {
+			http.Error(w, "unavailable", http.StatusServiceUnavailable)
+			return
+		}
+		_ = writePprof(w, newTestProfile(map[string]int64{"a": 10, "b": 20}))
+	})
+	server := httptest.NewTLSServer(handler)
+	defer server.Close()
+
+	// Server errors are retried.
+	output := filepath.Join(t.TempDir(), "cpu.pprof")
+	args := []string{server.URL + "/debug/pprof/profile?seconds=1", "-o", output, "-insecure", "-token", "secret", "-retry-delay", "1ms"}
+	if err := runFetch(context.Background(), args); err != nil {
+		t.Fatal(err)
+	}
+	if attempts != 2 {
+		t.Errorf("wrong number of attempts: want=2 got=%d", attempts)
+	}
+	prof, err := readProfile(output)
+	if err != nil {
+		t.Fatal(err)
+	}
+	if len(prof.Sample) != 2 {
+		t.Errorf("wrong number of samples: want=2 got=%d", len(prof.Sample))
+	}
+
+	// Client errors are not.
+	attempts = 0
+	args = []string{server.URL + "/debug/pprof/profile", "-o", output, "-insecure", "-retry-delay", "1ms"}
+	if err := runFetch(context.Background(), args); err == nil {
+		t.Error("no error fetching the profile without credentials")
+	}
+	if attempts != 1 {
+		t.Errorf("wrong number of attempts: want=1 got=%d", attempts)
+	}
+
+	// The certificate of the server is verified by default.
+	attempts = 0
+	args = []string{server.URL + "/debug/pprof/profile", "-o", output, "-token", "secret", "-retries", "0"}
+	if err := runFetch(context.Background(), args); err == nil {
+		t.Error("no error fetching the profile from a server with an unknown certificate")
+	}
+}
+
+func TestFetchTimeout(t *testing.T) {
+	for rawURL, want := range map[string]time.Duration{
+		"http://localhost/debug/pprof/profile?seconds=30": 60 * time.Second,
+		"http://localhost/debug/pprof/allocs":             30 * time.Second,
+		"http://localhost/debug/pprof/allocs?seconds=foo": 30 * time.Second,
+	} {
+		u, err := url.Parse(rawURL)
+		if err != nil {
+			t.Fatal(err)
+		}
+		if got := fetchTimeout(u); got != want {
+			t.Errorf("%s: wrong timeout: want=%s got=%s", rawURL, want, got)
+		}
+	}
+}
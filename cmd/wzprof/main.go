@@ -1,6 +1,7 @@
 package main
 
 import (
+	"compress/gzip"
 	"context"
 	"crypto/rand"
 	"flag"
@@ -13,9 +14,12 @@ import (
 	"os"
 	"os/signal"
 	"path/filepath"
+	"regexp"
 	"runtime"
 	"runtime/pprof"
+	"sort"
 	"strings"
+	"time"
 
 	"github.com/google/pprof/profile"
 	"github.com/tetratelabs/wazero"
@@ -38,54 +42,243 @@ func main() {
 const defaultSampleRate = 1.0 / 19
 
 type program struct {
-	filePath    string
-	args        []string
-	pprofAddr   string
-	cpuProfile  string
-	memProfile  string
-	sampleRate  float64
-	hostProfile bool
-	hostTime    bool
-	inuseMemory bool
-	mounts      []string
+	filePath       string
+	args           []string
+	pprofAddr      string
+	pprofAuth      string
+	pprofToken     string
+	pprofRateLimit float64
+	pprofCaptures  int
+	cpuProfile     string
+	startupProfile string
+	memProfile     string
+	blockProfile   string
+	mutexProfile   string
+	growProfile    string
+	stackProfile   string
+	callProfile    string
+	syscallProfile string
+	gcProfile      string
+	traceFile      string
+	heapSnapshot   string
+	fragmentation  string
+	leaks          bool
+	memTimeline    string
+	timelineEvery  time.Duration
+	flamegraph     string
+	duration       time.Duration
+	outputDir      string
+	rotate         time.Duration
+	webAddr        string
+	format         string
+	pushURL        string
+	pushEvery      time.Duration
+	pushLabels     []string
+	sampleRate     float64
+	overhead       float64
+	sampleType     string
+	hostProfile    bool
+	hostTime       bool
+	hostFrames     bool
+	nativeFrames   bool
+	interpreter    bool
+	mmap           bool
+	deterministic  bool
+	selfProfile    bool
+	inuseMemory    bool
+	allocSizes     bool
+	traps          bool
+	maxStacks      int
+	maxFrames      int
+	stripTemplates bool
+	filter         string
+	exclude        string
+	elide          string
+	debugInfo      string
+	sourceMap      string
+	mounts         []string
 }
 
 func (prog *program) run(ctx context.Context) error {
 	wasmName := filepath.Base(prog.filePath)
-	wasmCode, err := os.ReadFile(prog.filePath)
+	readFile := os.ReadFile
+	if prog.mmap {
+		readFile = wzprof.MapFile
+	}
+	wasmCode, err := readFile(prog.filePath)
 	if err != nil {
 		return fmt.Errorf("reading wasm module: %w", err)
 	}
 
-	p := wzprof.ProfilingFor(wasmCode)
+	debugInfo := prog.debugInfo
+	if debugInfo == "" {
+		// Look for a companion file holding the debug information of
+		// modules that were stripped, e.g. app.debug.wasm for app.wasm.
+		companion := strings.TrimSuffix(prog.filePath, ".wasm") + ".debug.wasm"
+		if _, err := os.Stat(companion); err == nil {
+			debugInfo = companion
+		}
+	}
+
+	sourceMap := prog.sourceMap
+	if sourceMap == "" {
+		// AssemblyScript writes the source map of app.wasm to app.wasm.map.
+		companion := prog.filePath + ".map"
+		if _, err := os.Stat(companion); err == nil {
+			sourceMap = companion
+		}
+	}
+
+	options := []wzprof.ProfilingOption{
+		wzprof.StripTemplates(prog.stripTemplates),
+		wzprof.HostFrames(prog.hostFrames),
+		wzprof.NativeFrames(prog.nativeFrames),
+		wzprof.Deterministic(prog.deterministic),
+		wzprof.SelfProfile(prog.selfProfile),
+		wzprof.MaxStacks(prog.maxStacks),
+		wzprof.MaxFrames(prog.maxFrames),
+		wzprof.PrepareProgress(logPrepareProgress),
+		wzprof.ExternalDebugInfo(debugInfo),
+		wzprof.SourceMap(sourceMap),
+		wzprof.ModuleFile(wasmName),
+		wzprof.CommandLine(append([]string{wasmName}, prog.args...)...),
+	}
+	if prog.filter != "" {
+		re, err := regexp.Compile(prog.filter)
+		if err != nil {
+			return fmt.Errorf("invalid -filter expression: %w", err)
+		}
+		options = append(options, wzprof.Filter(re))
+	}
+	if prog.exclude != "" {
+		re, err := regexp.Compile(prog.exclude)
+		if err != nil {
+			return fmt.Errorf("invalid -exclude expression: %w", err)
+		}
+		options = append(options, wzprof.Exclude(re))
+	}
+	if prog.elide == "" {
+		options = append(options, wzprof.ElideFrames(nil))
+	} else {
+		re, err := regexp.Compile(prog.elide)
+		if err != nil {
+			return fmt.Errorf("invalid -elide expression: %w", err)
+		}
+		options = append(options, wzprof.ElideFrames(re))
+	}
+	p := wzprof.ProfilingFor(wasmCode, options...)
+
+	wallTime := prog.sampleType == "wall"
+	cpu := p.CPUProfiler(
+		wzprof.HostTime(prog.hostTime),
+		wzprof.WallTime(wallTime),
+		wzprof.RecordStartup(prog.startupProfile != ""),
+	)
+	// The heap snapshot, the leaks report and the memory timeline hold the
+	// objects which were not seen being released, which requires observing
+	// all the allocations.
+	memUnsampled := prog.heapSnapshot != "" || prog.fragmentation != "" || prog.leaks || prog.memTimeline != ""
+	mem := p.MemoryProfiler(
+		wzprof.InuseMemory(prog.inuseMemory || memUnsampled),
+		wzprof.AllocationSizes(prog.allocSizes),
+		wzprof.UnsampledMemory(memUnsampled),
+	)
+	block := p.BlockProfiler()
+	mutex := p.MutexProfiler()
+	grow := p.GrowProfiler()
+	stack := p.StackProfiler()
+	traps := p.TrapProfiler()
+	calls := p.CallProfiler()
+	syscalls := p.SyscallProfiler()
+	gc := p.GCProfiler()
+
+	// The flame graph and the web UI render the CPU profile, unless only the
+	// memory profile was requested.
+	graph := prog.flamegraph != "" || prog.webAddr != ""
+	cpuGraph := graph && (prog.cpuProfile != "" || prog.memProfile == "")
+	memGraph := graph && !cpuGraph
 
-	cpu := p.CPUProfiler(wzprof.HostTime(prog.hostTime))
-	mem := p.MemoryProfiler(wzprof.InuseMemory(prog.inuseMemory))
+	// The push loop drives the CPU profiler, so it only pushes CPU profiles
+	// when no CPU profile was requested to be written before exiting.
+	pushCPU := prog.pushURL != "" && prog.cpuProfile == "" && prog.outputDir == "" && !cpuGraph
 
 	var listeners []experimental.FunctionListenerFactory
-	if prog.cpuProfile != "" || prog.pprofAddr != "" {
+	if prog.cpuProfile != "" || prog.startupProfile != "" || prog.outputDir != "" || prog.pprofAddr != "" || cpuGraph || pushCPU {
 		stdout.Printf("enabling cpu profiler")
 		listeners = append(listeners, cpu)
 	}
-	if prog.memProfile != "" || prog.pprofAddr != "" {
+	if prog.memProfile != "" || prog.outputDir != "" || prog.pprofAddr != "" || prog.pushURL != "" || memUnsampled {
 		stdout.Printf("enabling memory profiler")
 		listeners = append(listeners, mem)
 	}
-	if prog.sampleRate < 1 {
-		stdout.Printf("configuring sampling rate to %.2g%%", prog.sampleRate)
-		for i, lstn := range listeners {
-			listeners[i] = wzprof.Sample(prog.sampleRate, lstn)
-		}
+	if prog.blockProfile != "" || prog.pprofAddr != "" {
+		stdout.Printf("enabling block profiler")
+		listeners = append(listeners, block)
+	}
+	if prog.mutexProfile != "" || prog.pprofAddr != "" {
+		stdout.Printf("enabling mutex profiler")
+		listeners = append(listeners, mutex)
+	}
+	if prog.growProfile != "" || prog.pprofAddr != "" {
+		stdout.Printf("enabling memory growth profiler")
+		listeners = append(listeners, grow)
+	}
+	if prog.stackProfile != "" || prog.pprofAddr != "" {
+		stdout.Printf("enabling stack depth profiler")
+		listeners = append(listeners, stack)
+	}
+	if prog.traps || prog.pprofAddr != "" {
+		stdout.Printf("enabling trap profiler")
+		listeners = append(listeners, traps)
+	}
+	if prog.callProfile != "" || prog.pprofAddr != "" {
+		stdout.Printf("enabling call count profiler")
+		listeners = append(listeners, calls)
+	}
+	if prog.syscallProfile != "" || prog.pprofAddr != "" {
+		stdout.Printf("enabling syscall profiler")
+		listeners = append(listeners, syscalls)
+	}
+	if prog.gcProfile != "" || prog.pprofAddr != "" {
+		stdout.Printf("enabling garbage collection profiler")
+		listeners = append(listeners, gc)
+	}
+	var tracer *wzprof.Tracer
+	if prog.traceFile != "" {
+		stdout.Printf("enabling tracer")
+		tracer = p.Tracer(wzprof.TraceOutput(true))
+		listeners = append(listeners, tracer)
+	}
+	var timeline *wzprof.MemoryTimeline
+	if prog.memTimeline != "" {
+		stdout.Printf("enabling memory timeline")
+		timeline = p.MemoryTimeline(mem, wzprof.TimelinePeriod(prog.timelineEvery))
+		listeners = append(listeners, timeline)
+	}
+	// The sampling rate may be changed at runtime through the pprof server
+	// or to cap the overhead, the listeners are always sampled then.
+	sampler := wzprof.NewSampler(prog.sampleRate)
+	var sampled *wzprof.Sampler
+	if prog.sampleRate < 1 || prog.pprofAddr != "" || prog.overhead > 0 {
+		stdout.Printf("configuring sampling rate to %.2g%%", 100*prog.sampleRate)
+		sampled = sampler
 	}
 
 	ctx = context.WithValue(ctx,
 		experimental.FunctionListenerFactoryKey{},
-		experimental.MultiFunctionListenerFactory(listeners...),
+		wzprof.Compose(sampled, listeners...),
 	)
 
-	runtime := wazero.NewRuntimeWithConfig(ctx, wazero.NewRuntimeConfig().
+	config := wazero.NewRuntimeConfig()
+	if prog.interpreter || !compilerSupported() {
+		stdout.Printf("using the wazero interpreter")
+		config = wazero.NewRuntimeConfigInterpreter()
+	}
+	runtime := wazero.NewRuntimeWithConfig(ctx, config.
 		WithDebugInfoEnabled(true).
-		WithCustomSections(true))
+		// The profilers read the custom sections from the mapping of the
+		// module instead of a copy.
+		WithCustomSections(!prog.mmap))
 
 	stdout.Printf("compiling wasm module %s", prog.filePath)
 	compiledModule, err := runtime.CompileModule(ctx, wasmCode)
@@ -97,20 +290,86 @@ func (prog *program) run(ctx context.Context) error {
 		return fmt.Errorf("preparing wasm module: %w", err)
 	}
 
+	if prog.overhead > 0 {
+		stdout.Printf("adapting sampling rate to %.2g%% overhead", 100*prog.overhead)
+		go func(ctx context.Context) {
+			if err := sampler.Adapt(ctx, prog.overhead); err != nil {
+				stderr.Print("adapting sampling rate: ", err)
+			}
+		}(ctx)
+	}
+
 	if prog.pprofAddr != "" {
 		u := &url.URL{Scheme: "http", Host: prog.pprofAddr, Path: "/debug/pprof"}
 		stdout.Printf("starting prrof http sever at %s", u)
 
+		var protect []wzprof.ProtectOption
+		if prog.pprofAuth != "" {
+			user, password, ok := strings.Cut(prog.pprofAuth, ":")
+			if !ok {
+				return fmt.Errorf("invalid -pprof-auth credentials, expected user:password")
+			}
+			protect = append(protect, wzprof.BasicAuth(user, password))
+		}
+		if prog.pprofToken != "" {
+			protect = append(protect, wzprof.BearerToken(prog.pprofToken))
+		}
+		if prog.pprofRateLimit > 0 {
+			protect = append(protect, wzprof.RateLimit(prog.pprofRateLimit, int(math.Ceil(prog.pprofRateLimit))))
+		}
+		if prog.pprofCaptures > 0 {
+			protect = append(protect, wzprof.MaxConcurrentCaptures(prog.pprofCaptures))
+		}
+
 		server := http.NewServeMux()
-		server.Handle("/debug/pprof/", wzprof.Handler(prog.sampleRate, cpu, mem))
+		server.Handle("/debug/pprof/", sampler.Handler(cpu, mem, block, mutex, grow, stack, traps, calls, syscalls, gc))
+		server.Handle("/metrics", wzprof.MetricsHandler(sampler, cpu, mem, block, mutex, grow, stack, traps, calls, syscalls, gc))
+		if timeline != nil {
+			server.Handle("/debug/memtimeline", timeline.NewHandler())
+		}
+		handler := wzprof.Protect(server, protect...)
 
 		go func() {
-			if err := http.ListenAndServe(prog.pprofAddr, server); err != nil {
+			if err := http.ListenAndServe(prog.pprofAddr, handler); err != nil {
 				stderr.Println(err)
 			}
 		}()
 	}
 
+	if prog.pushURL != "" {
+		labels := map[string]string{"module": wasmName}
+		for _, label := range prog.pushLabels {
+			k, v, ok := strings.Cut(label, "=")
+			if !ok {
+				return fmt.Errorf("invalid push label: %s", label)
+			}
+			labels[k] = v
+		}
+		name := labels["service_name"]
+		if name == "" {
+			name = strings.TrimSuffix(wasmName, filepath.Ext(wasmName))
+		}
+		delete(labels, "service_name")
+
+		var pushProfiler *wzprof.CPUProfiler
+		if pushCPU {
+			pushProfiler = cpu
+		} else {
+			stderr.Print("not pushing CPU profiles: the CPU profiler is used by -cpuprofile, -output-dir or -flamegraph")
+		}
+
+		stdout.Printf("pushing profiles to %s every %s", prog.pushURL, prog.pushEvery)
+		go func(ctx context.Context) {
+			err := wzprof.Push(ctx, prog.pushURL, name, prog.sampleRate, pushProfiler, mem,
+				wzprof.PushInterval(prog.pushEvery),
+				wzprof.PushLabels(labels),
+			)
+			if err != nil {
+				stderr.Print("pushing profiles: ", err)
+			}
+		}(ctx)
+	}
+
 	if prog.hostProfile {
 		if prog.cpuProfile != "" {
 			f, err := os.Create(prog.cpuProfile)
@@ -128,24 +387,178 @@ func (prog *program) run(ctx context.Context) error {
 			}
 			defer writeHeapProfile(f)
 		}
+
+		if prog.blockProfile != "" {
+			f, err := os.Create(prog.blockProfile)
+			if err != nil {
+				return err
+			}
+			defer writeBlockProfile(f)
+		}
+
+		if prog.mutexProfile != "" {
+			f, err := os.Create(prog.mutexProfile)
+			if err != nil {
+				return err
+			}
+			defer writeMutexProfile(f)
+		}
+	}
+
+	if prog.outputDir != "" && !prog.hostProfile {
+		if err := os.MkdirAll(prog.outputDir, 0755); err != nil {
+			return err
+		}
+	}
+
+	// The web UI is served after the profiles were written, the function is
+	// deferred first so it runs last.
+	var webProfile *profile.Profile
+	if prog.webAddr != "" {
+		defer func() {
+			if webProfile == nil {
+				return
+			}
+			if err := serveWeb(prog.webAddr, webProfile); err != nil {
+				stderr.Print("serving web UI:", err)
+			}
+		}()
+	}
+
+	// The guest profiles are written when the program exits, unless their
+	// recording is stopped earlier (see capture).
+	c := new(capture)
+
+	if prog.cpuProfile != "" || prog.outputDir != "" || cpuGraph {
+		c.starts = append(c.starts, func() { cpu.StartProfile() })
+		c.writes = append(c.writes, func(suffix string) {
+			p := cpu.StopProfile(sampler.SampleRate())
+			if prog.cpuProfile != "" {
+				writeProfile(prog.format, "cpu", prog.cpuProfile+suffix, p)
+			}
+			if prog.outputDir != "" {
+				writeProfile("pprof", "cpu", outputPath(prog.outputDir, "cpu", c.started), p)
+			}
+			if cpuGraph {
+				if prog.flamegraph != "" {
+					writeFlameGraph("cpu", prog.flamegraph+suffix, p)
+				}
+				webProfile = p
+			}
+		})
+	}
+
+	if prog.memProfile != "" || prog.outputDir != "" {
+		c.starts = append(c.starts, func() { mem.StartProfile() })
+		c.writes = append(c.writes, func(suffix string) {
+			p := mem.StopProfile(sampler.SampleRate())
+			if prog.memProfile != "" {
+				writeProfile(prog.format, "memory", prog.memProfile+suffix, p)
+			}
+			if prog.outputDir != "" {
+				writeProfile("pprof", "memory", outputPath(prog.outputDir, "mem", c.started), p)
+			}
+			if memGraph {
+				if prog.flamegraph != "" {
+					writeFlameGraph("memory", prog.flamegraph+suffix, p)
+				}
+				webProfile = p
+			}
+		})
+	}
+
+	// The other profilers only record cumulative profiles, the profiles are
+	// written with the values since the program started.
+	for _, cumulative := range []struct {
+		path     string
+		name     string
+		profiler interface {
+			NewProfile(sampleRate float64) *profile.Profile
+		}
+	}{
+		{prog.blockProfile, "block", block},
+		{prog.mutexProfile, "mutex", mutex},
+		{prog.growProfile, "memgrow", grow},
+		{prog.stackProfile, "stack depth", stack},
+		{prog.callProfile, "call count", calls},
+		{prog.syscallProfile, "syscall", syscalls},
+		{prog.gcProfile, "gc", gc},
+	} {
+		if cumulative.path == "" {
+			continue
+		}
+		path, name, profiler := cumulative.path, cumulative.name, cumulative.profiler
+		c.writes = append(c.writes, func(suffix string) {
+			p := profiler.NewProfile(sampler.SampleRate())
+			writeProfile(prog.format, name, path+suffix, p)
+		})
+	}
+
+	if !prog.hostProfile {
+		// Guests importing the wzprof host module start the recording
+		// themselves.
+		if wzprof.ImportsHostModule(compiledModule) {
+			stdout.Printf("guest profiles are captured when requested by the guest")
+		} else {
+			c.start()
+		}
+		defer c.stop()
+
+		if prog.duration > 0 {
+			stdout.Printf("capturing guest profiles for %s", prog.duration)
+			t := time.AfterFunc(prog.duration, c.stop)
+			defer t.Stop()
+		}
+		if prog.rotate > 0 {
+			stdout.Printf("rotating guest profiles every %s", prog.rotate)
+			go c.rotateEvery(ctx, prog.rotate)
+		}
+		go c.handleSignals(ctx)
+	}
+
+	if prog.traps {
+		defer func() {
+			printTraps(os.Stderr, traps.NewProfile(sampler.SampleRate()))
+		}()
 	}
 
-	if prog.cpuProfile != "" {
-		cpu.StartProfile()
+	if prog.leaks {
 		defer func() {
-			p := cpu.StopProfile(prog.sampleRate)
-			if !prog.hostProfile {
-				writeProfile("cpu", wasmName, prog.cpuProfile, p)
+			if !printLeaks(os.Stderr, mem.NewProfile(1)) {
+				stdout.Printf("no guest memory leaks detected")
 			}
 		}()
 	}
 
-	if prog.memProfile != "" {
+	if tracer != nil {
+		defer writeTrace(prog.traceFile, tracer)
+	}
+
+	if timeline != nil {
+		timeline.Start()
+		defer writeTimeline(prog.memTimeline, timeline)
+	}
+
+	if prog.heapSnapshot != "" {
+		defer func() {
+			writeProfile(prog.format, "heap snapshot", prog.heapSnapshot, mem.HeapSnapshot())
+		}()
+	}
+
+	if prog.fragmentation != "" {
+		defer func() {
+			writeFragmentation(prog.fragmentation, mem.Fragmentation())
+		}()
+	}
+
+	if prog.startupProfile != "" {
 		defer func() {
-			p := mem.NewProfile(prog.sampleRate)
-			if !prog.hostProfile {
-				writeProfile("memory", wasmName, prog.memProfile, p)
+			p := cpu.StartupProfile(sampler.SampleRate())
+			if p == nil {
+				stderr.Print("not writing the guest startup profile: the guest did not complete its start")
+				return
 			}
+			writeProfile(prog.format, "startup", prog.startupProfile, p)
 		}()
 	}
 
@@ -155,6 +568,14 @@ func (prog *program) run(ctx context.Context) error {
 		stdout.Printf("instantiating host module: wasi_snapshot_preview1")
 		wasi_snapshot_preview1.MustInstantiate(ctx, runtime)
 
+		if wzprof.ImportsHostModule(compiledModule) {
+			stdout.Printf("instantiating host module: %s", wzprof.HostModuleName)
+			if _, err := wzprof.NewHostModule(runtime, c.start, c.stop).Instantiate(ctx); err != nil {
+				cancel(fmt.Errorf("instantiating host module: %w", err))
+				return
+			}
+		}
+
 		config := wazero.NewModuleConfig().
 			WithStdout(os.Stdout).
 			WithStderr(os.Stderr).
@@ -194,16 +615,63 @@ func silenceContextCanceled(err error) error {
 }
 
 var (
-	pprofAddr    string
-	cpuProfile   string
-	memProfile   string
-	sampleRate   float64
-	hostProfile  bool
-	hostTime     bool
-	inuseMemory  bool
-	verbose      bool
-	mounts       string
-	printVersion bool
+	pprofAddr      string
+	pprofAuth      string
+	pprofToken     string
+	pprofRateLimit float64
+	pprofCaptures  int
+	cpuProfile     string
+	startupProfile string
+	memProfile     string
+	blockProfile   string
+	mutexProfile   string
+	growProfile    string
+	stackProfile   string
+	callProfile    string
+	syscallProfile string
+	gcProfile      string
+	traceFile      string
+	heapSnapshot   string
+	fragmentation  string
+	leaks          bool
+	memTimeline    string
+	timelineEvery  time.Duration
+	flamegraph     string
+	duration       time.Duration
+	outputDir      string
+	rotate         time.Duration
+	webAddr        string
+	format         string
+	compression    int
+	pushURL        string
+	pushEvery      time.Duration
+	pushLabels     string
+	sampleRate     float64
+	overhead       float64
+	sampleType     string
+	hostProfile    bool
+	hostTime       bool
+	hostFrames     bool
+	nativeFrames   bool
+	interpreter    bool
+	mmap           bool
+	deterministic  bool
+	selfProfile    bool
+	inuseMemory    bool
+	allocSizes     bool
+	traps          bool
+	maxStacks      int
+	maxFrames      int
+	stripTemplates bool
+	filter         string
+	exclude        string
+	elide          string
+	debugInfo      string
+	sourceMap      string
+	configPath     string
+	verbose        bool
+	mounts         string
+	printVersion   bool
 
 	version = "dev"
 	stdout  = log.Default()
@@ -212,12 +680,59 @@ var (
 
 func init() {
 	flag.StringVar(&pprofAddr, "pprof-addr", "", "Address where to expose a pprof HTTP endpoint.")
+	flag.StringVar(&pprofAuth, "pprof-auth", "", "Require the requests to the -pprof-addr endpoint to authenticate with these user:password credentials (HTTP basic authentication).")
+	flag.StringVar(&pprofToken, "pprof-token", "", "Require the requests to the -pprof-addr endpoint to authenticate with this bearer token (default to $WZPROF_PPROF_TOKEN).")
+	flag.Float64Var(&pprofRateLimit, "pprof-rate-limit", 0, "Limit the requests of each client of the -pprof-addr endpoint to this number per second (0 for no limit).")
+	flag.IntVar(&pprofCaptures, "pprof-max-captures", 0, "Limit the number of profiles captured concurrently over a period of time by the -pprof-addr endpoint (0 for no limit).")
 	flag.StringVar(&cpuProfile, "cpuprofile", "", "Write a CPU profile to the specified file before exiting.")
+	flag.StringVar(&startupProfile, "startupprofile", "", "Write a CPU profile of the start of the guest, until its _start or _initialize function returns, to the specified file before exiting.")
 	flag.StringVar(&memProfile, "memprofile", "", "Write a memory profile to the specified file before exiting.")
+	flag.StringVar(&blockProfile, "blockprofile", "", "Write a profile of the time spent blocked in host functions to the specified file before exiting.")
+	flag.StringVar(&mutexProfile, "mutexprofile", "", "Write a profile of the time spent waiting on locks by guest threads to the specified file before exiting.")
+	flag.StringVar(&growProfile, "memgrowprofile", "", "Write a profile of the call stacks growing the guest linear memory to the specified file before exiting.")
+	flag.StringVar(&stackProfile, "stackprofile", "", "Write a profile of the deepest stacks reached by each guest call path to the specified file before exiting.")
+	flag.StringVar(&callProfile, "callprofile", "", "Write a profile of the number of calls to each guest function to the specified file before exiting.")
+	flag.StringVar(&syscallProfile, "syscallprofile", "", "Write a profile of the calls to WASI host functions to the specified file before exiting.")
+	flag.StringVar(&gcProfile, "gcprofile", "", "Write a profile of the time spent in the garbage collectors of TinyGo, Python or .NET guests to the specified file before exiting.")
+	flag.StringVar(&traceFile, "trace", "", "Write a trace of the guest function calls and of its writes to stdout and stderr in the Chrome trace event format to the specified file before exiting, which can be opened with https://ui.perfetto.dev.")
+	flag.StringVar(&heapSnapshot, "heapsnapshot", "", "Write a snapshot of the guest objects in use to the specified file before exiting, with a sample for each object labeled with its address.")
+	flag.StringVar(&fragmentation, "fragmentation", "", "Write a report of the fragmentation of the guest linear memory by the objects in use to the specified file before exiting.")
+	flag.StringVar(&memTimeline, "memtimeline", "", "Write a timeline of the size of the guest linear memory and of the objects in use to the specified file before exiting, as JSON if the file has a .json extension or CSV otherwise.")
+	flag.DurationVar(&timelineEvery, "memtimeline-interval", 100*time.Millisecond, "Interval between the points of the -memtimeline timeline.")
+	flag.StringVar(&flamegraph, "flamegraph", "", "Write an HTML flame graph of the CPU (or memory) profile to the specified file before exiting.")
+	flag.DurationVar(&duration, "duration", 0, "Stop recording the guest profiles and write them after this duration, while the program keeps running (0 to record until the program exits).")
+	flag.StringVar(&outputDir, "output-dir", "", "Write the guest CPU and memory profiles to timestamped files in the specified directory (e.g. cpu-20230601T120000Z.pb.gz).")
+	flag.DurationVar(&rotate, "rotate", 0, "Write the guest profiles and start recording the next ones at this interval (0 to disable).")
+	flag.StringVar(&webAddr, "web", "", "Serve a web UI of the CPU (or memory) profile at the specified address (e.g. localhost:8080) after the program exits.")
+	flag.StringVar(&format, "format", "pprof", "Format of the profiles written to files (pprof, folded, speedscope, firefox).")
+	flag.IntVar(&compression, "compression", gzip.DefaultCompression, "Gzip compression level of the pprof profiles written to files (1-9, 0 for uncompressed, -1 for the default level).")
+	flag.StringVar(&pushURL, "push-url", "", "URL of a Pyroscope server where to periodically push profiles.")
+	flag.DurationVar(&pushEvery, "push-interval", 15*time.Second, "Interval at which profiles are pushed to the -push-url server.")
+	flag.StringVar(&pushLabels, "push-labels", "", "Comma-separated list of labels attached to pushed profiles (e.g. service_name=app,env=prod).")
 	flag.Float64Var(&sampleRate, "sample", defaultSampleRate, "Set the profile sampling rate (0-1).")
+	flag.Float64Var(&overhead, "overhead", 0, "Lower the sampling rate automatically to keep the profiling overhead under this fraction of the time (e.g. 0.02), 0 to disable.")
+	flag.StringVar(&sampleType, "sample-type", "cpu", "Sample type of the guest CPU profile (cpu, wall).")
 	flag.BoolVar(&hostProfile, "host", false, "Generate profiles of the host instead of the guest application.")
 	flag.BoolVar(&hostTime, "iowait", false, "Include time spent waiting on I/O in guest CPU profile.")
+	flag.BoolVar(&hostFrames, "host-frames", false, "Represent calls to host functions as frames prefixed with host: and their module name, accounting for their time in the guest CPU profile.")
+	flag.BoolVar(&nativeFrames, "native-frames", false, "Merge the native functions called by Python code into the Python call stacks.")
+	flag.BoolVar(&interpreter, "interpreter", false, "Run the guest with the wazero interpreter instead of the compiler, which is always used on platforms without compiler support.")
+	flag.BoolVar(&mmap, "mmap", false, "Map the wasm module in memory instead of reading it, and read its debug information from the mapping, which reduces the memory usage of large modules.")
+	flag.BoolVar(&deterministic, "deterministic", false, "Generate reproducible profiles with sorted samples and without timestamps, e.g. for golden files.")
+	flag.BoolVar(&selfProfile, "self-profile", false, "Measure the time spent by the profilers, which the CPU profile attributes to a wzprof frame and all the profiles report in a comment.")
 	flag.BoolVar(&inuseMemory, "inuse", false, "Include snapshots of memory in use (experimental).")
+	flag.BoolVar(&allocSizes, "alloc-sizes", false, "Break down memory allocations of each call stack by power-of-two size class.")
+	flag.BoolVar(&leaks, "leaks", false, "Print the call stacks of the guest allocations which were never released before exiting, similarly to LeakSanitizer.")
+	flag.BoolVar(&traps, "traps", false, "Print the symbolized call stacks of the guest traps before exiting.")
+	flag.IntVar(&maxStacks, "max-stacks", 0, "Bound the number of call stacks recorded by each profiler, merging the least significant ones into their callers (0 for no limit).")
+	flag.IntVar(&maxFrames, "max-frames", 128, "Bound the number of frames of the recorded call stacks, replacing the outermost frames of deeper stacks with a ...truncated frame (0 for no limit).")
+	flag.BoolVar(&stripTemplates, "strip-templates", false, "Omit template arguments from the names of C++ functions.")
+	flag.StringVar(&filter, "filter", "", "Only instrument the guest functions matching this regular expression in the CPU, stack depth and call count profilers (e.g. ^my_app::).")
+	flag.StringVar(&exclude, "exclude", "", "Do not instrument the guest functions matching this regular expression in the CPU, stack depth and call count profilers.")
+	flag.StringVar(&elide, "elide", wzprof.DefaultElidedFrames.String(), "Remove the frames of the functions matching this regular expression from the call stacks, attributing their values to their callers (empty to keep all frames).")
+	flag.StringVar(&debugInfo, "debug-info", "", "Path or URL of a wasm file holding the DWARF sections of a stripped module (default: the .debug.wasm file next to the module, if any).")
+	flag.StringVar(&sourceMap, "source-map", "", "Path or URL of the source map of a module compiled without DWARF, e.g. by AssemblyScript (default: the .wasm.map file next to the module, if any).")
+	flag.StringVar(&configPath, "config", "", "Read the settings of the module from a JSON configuration file, the flags set on the command line take precedence.")
 	flag.BoolVar(&verbose, "verbose", false, "Enable more output")
 	flag.StringVar(&mounts, "mount", "", "Comma-separated list of directories to mount (e.g. /tmp:/tmp:ro).")
 	flag.BoolVar(&printVersion, "version", false, "Print the wzprof version.")
@@ -226,6 +741,12 @@ func init() {
 func run(ctx context.Context) error {
 	flag.Parse()
 
+	// The token is not the default value of the flag, which would print it
+	// in the usage message.
+	if pprofToken == "" {
+		pprofToken = os.Getenv("WZPROF_PPROF_TOKEN")
+	}
+
 	if printVersion {
 		fmt.Printf("wzprof version %s\n", version)
 		return nil
@@ -237,6 +758,29 @@ func run(ctx context.Context) error {
 		return fmt.Errorf("usage: wzprof </path/to/app.wasm>")
 	}
 
+	// Modules named like the commands can still be profiled with a path
+	// like ./diff.
+	switch args[0] {
+	case "diff":
+		return runDiff(args[1:])
+	case "merge":
+		return runMerge(args[1:])
+	case "top":
+		return runTop(args[1:])
+	case "check":
+		return runCheck(args[1:])
+	case "annotate":
+		return runAnnotate(args[1:])
+	case "serve":
+		return runServe(args[1:])
+	case "attach":
+		return runAttach(args[1:])
+	case "fetch":
+		return runFetch(ctx, args[1:])
+	case "bench":
+		return runBench(ctx, args[1:])
+	}
+
 	if verbose {
 		log.SetPrefix("==> ")
 		log.SetFlags(0)
@@ -245,6 +789,28 @@ func run(ctx context.Context) error {
 		log.SetOutput(io.Discard)
 	}
 
+	if configPath != "" {
+		if err := loadConfig(configPath, filepath.Base(args[0])); err != nil {
+			return fmt.Errorf("loading config: %w", err)
+		}
+	}
+
+	if _, ok := profileFormats[format]; !ok {
+		return fmt.Errorf("unsupported profile format: %s", format)
+	}
+
+	if compression < gzip.DefaultCompression || compression > gzip.BestCompression {
+		return fmt.Errorf("invalid compression level: %d", compression)
+	}
+
+	if sampleType != "cpu" && sampleType != "wall" {
+		return fmt.Errorf("unsupported sample type: %s", sampleType)
+	}
+
+	if !(sampleRate > 0) {
+		return fmt.Errorf("invalid sample rate: %g", sampleRate)
+	}
+
 	filePath := args[0]
 
 	rate := int(math.Ceil(1 / sampleRate))
@@ -252,19 +818,72 @@ func run(ctx context.Context) error {
 	runtime.SetMutexProfileFraction(rate)
 
 	return (&program{
-		filePath:    filePath,
-		args:        args[1:],
-		pprofAddr:   pprofAddr,
-		cpuProfile:  cpuProfile,
-		memProfile:  memProfile,
-		sampleRate:  sampleRate,
-		hostProfile: hostProfile,
-		hostTime:    hostTime,
-		inuseMemory: inuseMemory,
-		mounts:      split(mounts),
+		filePath:       filePath,
+		args:           args[1:],
+		pprofAddr:      pprofAddr,
+		pprofAuth:      pprofAuth,
+		pprofToken:     pprofToken,
+		pprofRateLimit: pprofRateLimit,
+		pprofCaptures:  pprofCaptures,
+		cpuProfile:     cpuProfile,
+		startupProfile: startupProfile,
+		memProfile:     memProfile,
+		blockProfile:   blockProfile,
+		mutexProfile:   mutexProfile,
+		growProfile:    growProfile,
+		stackProfile:   stackProfile,
+		callProfile:    callProfile,
+		syscallProfile: syscallProfile,
+		gcProfile:      gcProfile,
+		traceFile:      traceFile,
+		heapSnapshot:   heapSnapshot,
+		fragmentation:  fragmentation,
+		leaks:          leaks,
+		memTimeline:    memTimeline,
+		timelineEvery:  timelineEvery,
+		flamegraph:     flamegraph,
+		duration:       duration,
+		outputDir:      outputDir,
+		rotate:         rotate,
+		webAddr:        webAddr,
+		format:         format,
+		pushURL:        pushURL,
+		pushEvery:      pushEvery,
+		pushLabels:     split(pushLabels),
+		sampleRate:     sampleRate,
+		overhead:       overhead,
+		sampleType:     sampleType,
+		hostProfile:    hostProfile,
+		hostTime:       hostTime,
+		hostFrames:     hostFrames,
+		nativeFrames:   nativeFrames,
+		interpreter:    interpreter,
+		mmap:           mmap,
+		deterministic:  deterministic,
+		selfProfile:    selfProfile,
+		inuseMemory:    inuseMemory,
+		allocSizes:     allocSizes,
+		traps:          traps,
+		maxStacks:      maxStacks,
+		maxFrames:      maxFrames,
+		stripTemplates: stripTemplates,
+		filter:         filter,
+		exclude:        exclude,
+		elide:          elide,
+		debugInfo:      debugInfo,
+		sourceMap:      sourceMap,
+		mounts:         split(mounts),
 	}).run(ctx)
 }
 
+// logPrepareProgress logs the progress of parsing the DWARF sections of the
+// module at every tenth of the compilation units (visible with -verbose).
+func logPrepareProgress(done, total int) {
+	if done == total || done*10/total != (done-1)*10/total {
+		log.Printf("dwarf: parsed %d/%d compilation units", done, total)
+	}
+}
+
 func split(s string) []string {
 	if s == "" {
 		return nil
@@ -272,6 +891,17 @@ func split(s string) []string {
 	return strings.Split(s, ",")
 }
 
+// compilerSupported returns true if wazero.NewRuntimeConfig selects the
+// compiler on this platform, which wazero does not expose. The guests run with
+// the interpreter otherwise, which is only reported.
+func compilerSupported() bool {
+	switch runtime.GOOS {
+	case "darwin", "windows", "linux", "freebsd":
+		return runtime.GOARCH == "amd64" || runtime.GOARCH == "arm64"
+	}
+	return false
+}
+
 func startCPUProfile(f *os.File) {
 	if err := pprof.StartCPUProfile(f); err != nil {
 		stderr.Print("starting CPU profile:", err)
@@ -290,15 +920,191 @@ func writeHeapProfile(f *os.File) {
 	}
 }
 
-func writeProfile(profileName, wasmName, path string, prof *profile.Profile) {
-	m := &profile.Mapping{ID: 1, File: wasmName}
-	prof.Mapping = []*profile.Mapping{m}
+func writeBlockProfile(f *os.File) {
+	stdout.Printf("writing host block profile to %s", f.Name())
+	if err := pprof.Lookup("block").WriteTo(f, 0); err != nil {
+		stderr.Print("writing block profile:", err)
+	}
+}
+
+func writeMutexProfile(f *os.File) {
+	stdout.Printf("writing host mutex profile to %s", f.Name())
+	if err := pprof.Lookup("mutex").WriteTo(f, 0); err != nil {
+		stderr.Print("writing mutex profile:", err)
+	}
+}
+
+// profileFormats maps the values of the -format flag to the functions used to
+// encode profiles.
+var profileFormats = map[string]func(io.Writer, *profile.Profile) error{
+	"pprof":      writePprof,
+	"folded":     wzprof.WriteFolded,
+	"speedscope": wzprof.WriteSpeedscope,
+	"firefox":    wzprof.WriteFirefox,
+}
+
+func writePprof(w io.Writer, prof *profile.Profile) error {
+	if compression == 0 {
+		return wzprof.EncodeProfile(w, prof, wzprof.Uncompressed(true))
+	}
+	return wzprof.EncodeProfile(w, prof, wzprof.CompressionLevel(compression))
+}
+
+func writeProfile(format, profileName, path string, prof *profile.Profile) {
 	stdout.Printf("writing guest %s profile to %s", profileName, path)
-	if err := wzprof.WriteProfile(path, prof); err != nil {
+	write, ok := profileFormats[format]
+	if !ok {
+		write = writePprof
+	}
+	if err := writeFile(path, prof, write); err != nil {
 		stderr.Print("writing profile:", err)
 	}
 }
 
+func writeTrace(path string, tracer *wzprof.Tracer) {
+	stdout.Printf("writing guest trace of %d calls to %s", tracer.Count(), path)
+	f, err := os.Create(path)
+	if err != nil {
+		stderr.Print("writing trace:", err)
+		return
+	}
+	defer f.Close()
+	if err := tracer.WriteTrace(f); err != nil {
+		stderr.Print("writing trace:", err)
+	}
+}
+
+func writeFragmentation(path string, report *wzprof.FragmentationReport) {
+	stdout.Printf("writing guest memory fragmentation report to %s", path)
+	f, err := os.Create(path)
+	if err != nil {
+		stderr.Print("writing fragmentation report:", err)
+		return
+	}
+	defer f.Close()
+	if err := report.WriteText(f); err != nil {
+		stderr.Print("writing fragmentation report:", err)
+	}
+}
+
+func writeTimeline(path string, timeline *wzprof.MemoryTimeline) {
+	timeline.Stop()
+	stdout.Printf("writing guest memory timeline to %s", path)
+	f, err := os.Create(path)
+	if err != nil {
+		stderr.Print("writing memory timeline:", err)
+		return
+	}
+	defer f.Close()
+	write := timeline.WriteCSV
+	if filepath.Ext(path) == ".json" {
+		write = timeline.WriteJSON
+	}
+	if err := write(f); err != nil {
+		stderr.Print("writing memory timeline:", err)
+	}
+}
+
+// printLeaks writes the call stacks of the objects in use recorded in prof to
+// w, from the call stacks leaking the most bytes, similarly to the reports of
+// LeakSanitizer. The function returns false if there were no leaks.
+func printLeaks(w io.Writer, prof *profile.Profile) bool {
+	objects, space := -1, -1
+	for i, st := range prof.SampleType {
+		switch st.Type {
+		case "inuse_objects":
+			objects = i
+		case "inuse_space":
+			space = i
+		}
+	}
+	if objects < 0 || space < 0 {
+		return false
+	}
+
+	var leaks []*profile.Sample
+	var totalObjects, totalBytes int64
+	for _, s := range prof.Sample {
+		if s.Value[objects] > 0 {
+			leaks = append(leaks, s)
+			totalObjects += s.Value[objects]
+			totalBytes += s.Value[space]
+		}
+	}
+	if len(leaks) == 0 {
+		return false
+	}
+	sort.SliceStable(leaks, func(i, j int) bool {
+		return leaks[i].Value[space] > leaks[j].Value[space]
+	})
+
+	for _, s := range leaks {
+		fmt.Fprintf(w, "guest leak of %d byte(s) in %d object(s) allocated from:\n", s.Value[space], s.Value[objects])
+		frame := 0
+		for _, loc := range s.Location {
+			for _, line := range loc.Line {
+				fmt.Fprintf(w, "    #%d %s", frame, line.Function.Name)
+				if line.Function.Filename != "" {
+					fmt.Fprintf(w, " %s:%d", line.Function.Filename, line.Line)
+				}
+				fmt.Fprintln(w)
+				frame++
+			}
+		}
+		fmt.Fprintln(w)
+	}
+	fmt.Fprintf(w, "SUMMARY: %d byte(s) leaked in %d allocation(s).\n", totalBytes, totalObjects)
+	return true
+}
+
+// printTraps writes the call stacks of the traps recorded in prof to w, from
+// the innermost frame, similarly to the tracebacks of Go panics.
+func printTraps(w io.Writer, prof *profile.Profile) {
+	for _, s := range prof.Sample {
+		cause := "trap"
+		if errs := s.Label["error"]; len(errs) > 0 {
+			cause = errs[0]
+		}
+		fmt.Fprintf(w, "guest %s", cause)
+		if n := s.Value[0]; n > 1 {
+			fmt.Fprintf(w, " (%d times)", n)
+		}
+		fmt.Fprintln(w)
+		for _, loc := range s.Location {
+			for _, line := range loc.Line {
+				fmt.Fprintf(w, "%s\n", line.Function.Name)
+				if line.Function.Filename != "" {
+					fmt.Fprintf(w, "\t%s:%d\n", line.Function.Filename, line.Line)
+				}
+			}
+		}
+		fmt.Fprintln(w)
+	}
+}
+
+// outputPath returns the path of the profile of the given name written to dir
+// for the recording started at t, e.g. dir/cpu-20230601T120000Z.pb.gz. The
+// profiles are written in the pprof format, which is compressed with gzip.
+func outputPath(dir, name string, t time.Time) string {
+	return filepath.Join(dir, name+"-"+t.UTC().Format("20060102T150405Z")+".pb.gz")
+}
+
+func writeFlameGraph(profileName, path string, prof *profile.Profile) {
+	stdout.Printf("writing guest %s flame graph to %s", profileName, path)
+	if err := writeFile(path, prof, wzprof.WriteFlameGraph); err != nil {
+		stderr.Print("writing flame graph:", err)
+	}
+}
+
+func writeFile(path string, prof *profile.Profile, write func(io.Writer, *profile.Profile) error) error {
+	f, err := os.Create(path)
+	if err != nil {
+		return err
+	}
+	defer f.Close()
+	return write(f, prof)
+}
+
 func createFSConfig(mounts []string) wazero.FSConfig {
 	fs := wazero.NewFSConfig()
 	for _, m := range mounts {
@@ -4,6 +4,7 @@ import (
 	"context"
 	"os"
 	"path/filepath"
+	"strings"
 	"testing"
 
 	"github.com/google/pprof/profile"
@@ -355,3 +356,41 @@ func printSamples(samples []*profile.Sample) {
 	}
 }
 */
+
+func TestPrintLeaks(t *testing.T) {
+	fn := &profile.Function{ID: 1, Name: "malloc"}
+	caller := &profile.Function{ID: 2, Name: "func1", Filename: "simple.c"}
+	loc := &profile.Location{ID: 1, Line: []profile.Line{{Function: fn}}}
+	callerLoc := &profile.Location{ID: 2, Line: []profile.Line{{Function: caller, Line: 6}}}
+	prof := &profile.Profile{
+		SampleType: []*profile.ValueType{
+			{Type: "alloc_objects", Unit: "count"},
+			{Type: "alloc_space", Unit: "bytes"},
+			{Type: "inuse_objects", Unit: "count"},
+			{Type: "inuse_space", Unit: "bytes"},
+		},
+		Sample: []*profile.Sample{
+			{Location: []*profile.Location{loc}, Value: []int64{3, 30, 0, 0}},
+			{Location: []*profile.Location{loc, callerLoc}, Value: []int64{2, 64, 2, 64}},
+		},
+	}
+
+	b := new(strings.Builder)
+	if !printLeaks(b, prof) {
+		t.Fatal("no leaks reported")
+	}
+	want := `guest leak of 64 byte(s) in 2 object(s) allocated from:
+    #0 malloc
+    #1 func1 simple.c:6
+
+SUMMARY: 64 byte(s) leaked in 2 allocation(s).
+`
+	if got := b.String(); got != want {
+		t.Errorf("wrong leaks report:\nwant:\n%s\ngot:\n%s", want, got)
+	}
+
+	prof.Sample = prof.Sample[:1]
+	if printLeaks(b, prof) {
+		t.Error("leaks reported for objects which were released")
+	}
+}
@@ -0,0 +1,85 @@
+package main
+
+import (
+	"flag"
+	"fmt"
+	"path/filepath"
+
+	"github.com/google/pprof/profile"
+)
+
+// runMerge implements the "wzprof merge" command, which merges the profiles of
+// several runs of a program (e.g. short-lived invocations of a CLI) or several
+// instances of a module into a single profile.
+func runMerge(args []string) error {
+	flags := flag.NewFlagSet("merge", flag.ContinueOnError)
+	output := flags.String("o", "merged.pprof", "Write the merged profile to the specified file.")
+	format := flags.String("format", "pprof", "Format of the merged profile (pprof, folded, speedscope, firefox).")
+	paths, err := parseInterspersed(flags, args)
+	if err != nil {
+		return err
+	}
+	if len(paths) == 0 {
+		return fmt.Errorf("usage: wzprof merge [options] <profile>... -o <merged.pprof>")
+	}
+
+	write, ok := profileFormats[*format]
+	if !ok {
+		return fmt.Errorf("unsupported profile format: %s", *format)
+	}
+
+	profiles := make([]*profile.Profile, len(paths))
+	for i, path := range paths {
+		if profiles[i], err = readProfile(path); err != nil {
+			return err
+		}
+	}
+	merged, err := mergeProfiles(profiles)
+	if err != nil {
+		return err
+	}
+	stdout.Printf("writing merged profile of %d profiles to %s", len(profiles), *output)
+	return writeFile(*output, merged, write)
+}
+
+// mergeProfiles merges profiles into a single profile, summing the values of
+// the samples with the same call stacks and labels. The functions, locations
+// and mappings of the profiles are deduplicated and assigned new identifiers.
+//
+// wzprof names the mappings of profiles after the wasm module, the mappings of
+// modules profiled from different directories are normalized to the base name
+// of their files so they are merged.
+func mergeProfiles(profiles []*profile.Profile) (*profile.Profile, error) {
+	normalized := make([]*profile.Profile, len(profiles))
+	for i, prof := range profiles {
+		prof = prof.Copy()
+		for _, m := range prof.Mapping {
+			if m.File != "" {
+				m.File = filepath.Base(m.File)
+			}
+		}
+		normalized[i] = prof
+	}
+	merged, err := profile.Merge(normalized)
+	if err != nil {
+		return nil, fmt.Errorf("profiles cannot be merged: %w", err)
+	}
+	return merged, nil
+}
+
+// parseInterspersed parses the flags of args which may be placed after the
+// positional arguments, returning the positional arguments.
+func parseInterspersed(flags *flag.FlagSet, args []string) ([]string, error) {
+	var positional []string
+	for {
+		if err := flags.Parse(args); err != nil {
+			return nil, err
+		}
+		args = flags.Args()
+		if len(args) == 0 {
+			return positional, nil
+		}
+		positional = append(positional, args[0])
+		args = args[1:]
+	}
+}
@@ -0,0 +1,65 @@
+package main
+
+import (
+	"flag"
+	"reflect"
+	"testing"
+
+	"github.com/google/pprof/profile"
+)
+
+func TestMergeProfiles(t *testing.T) {
+	profiles := []*profile.Profile{
+		newTestProfile(map[string]int64{"a": 100, "b": 50}),
+		newTestProfile(map[string]int64{"b": 10, "c": 20}),
+	}
+	// The same module profiled from different directories.
+	for i, file := range []string{"app.wasm", "/tmp/app.wasm"} {
+		m := &profile.Mapping{ID: 1, File: file}
+		profiles[i].Mapping = []*profile.Mapping{m}
+		for _, loc := range profiles[i].Location {
+			loc.Mapping = m
+		}
+	}
+
+	merged, err := mergeProfiles(profiles)
+	if err != nil {
+		t.Fatal(err)
+	}
+	if err := merged.CheckValid(); err != nil {
+		t.Fatal(err)
+	}
+
+	values := make(map[string]int64)
+	for _, s := range merged.Sample {
+		values[s.Location[0].Line[0].Function.Name] += s.Value[0]
+	}
+	if want := map[string]int64{"a": 100, "b": 60, "c": 20}; !reflect.DeepEqual(values, want) {
+		t.Errorf("wrong values of the merged profile: want=%v got=%v", want, values)
+	}
+	if len(merged.Function) != 3 {
+		t.Errorf("wrong number of functions: want=3 got=%d", len(merged.Function))
+	}
+	if len(merged.Mapping) != 1 || merged.Mapping[0].File != "app.wasm" {
+		t.Errorf("mappings were not normalized: %v", merged.Mapping)
+	}
+	if profiles[1].Mapping[0].File != "/tmp/app.wasm" {
+		t.Error("the merged profiles were modified")
+	}
+}
+
+func TestParseInterspersed(t *testing.T) {
+	flags := flag.NewFlagSet("test", flag.ContinueOnError)
+	output := flags.String("o", "", "")
+
+	args, err := parseInterspersed(flags, []string{"a.pprof", "b.pprof", "-o", "merged.pprof", "c.pprof"})
+	if err != nil {
+		t.Fatal(err)
+	}
+	if want := []string{"a.pprof", "b.pprof", "c.pprof"}; !reflect.DeepEqual(args, want) {
+		t.Errorf("wrong arguments: want=%v got=%v", want, args)
+	}
+	if *output != "merged.pprof" {
+		t.Errorf("wrong flag value: want=merged.pprof got=%s", *output)
+	}
+}
@@ -0,0 +1,160 @@
+package main
+
+import (
+	"context"
+	"flag"
+	"fmt"
+	"html/template"
+	"net"
+	"net/http"
+	"os"
+	"os/signal"
+	"strconv"
+
+	"github.com/google/pprof/profile"
+
+	"github.com/stealthrocket/wzprof"
+)
+
+// runServe implements the "wzprof serve" command, which serves a web UI to
+// explore a profile, similarly to "go tool pprof -http".
+func runServe(args []string) error {
+	flags := flag.NewFlagSet("serve", flag.ContinueOnError)
+	addr := flags.String("http", "localhost:8080", "Address where to serve the web UI.")
+	paths, err := parseInterspersed(flags, args)
+	if err != nil {
+		return err
+	}
+	if len(paths) != 1 {
+		return fmt.Errorf("usage: wzprof serve [options] <profile>")
+	}
+
+	prof, err := readProfile(paths[0])
+	if err != nil {
+		return err
+	}
+	return serveWeb(*addr, prof)
+}
+
+// serveWeb serves the web UI of prof on addr until the program is interrupted.
+func serveWeb(addr string, prof *profile.Profile) error {
+	// The context of the program may already be canceled when the web UI
+	// is served after interrupting the guest.
+	ctx, cancel := signal.NotifyContext(context.Background(), os.Interrupt)
+	defer cancel()
+
+	l, err := net.Listen("tcp", addr)
+	if err != nil {
+		return err
+	}
+	server := &http.Server{Handler: newWebHandler(prof)}
+	go func() {
+		<-ctx.Done()
+		_ = server.Close()
+	}()
+
+	// The address is printed regardless of -verbose since the user needs it
+	// to open the web UI.
+	fmt.Fprintf(os.Stderr, "serving web UI at http://%s (press Ctrl-C to exit)\n", l.Addr())
+	if err := server.Serve(l); err != http.ErrServerClosed {
+		return err
+	}
+	return nil
+}
+
+// newWebHandler returns the http handler of the web UI of prof. The pages
+// accept a "si" query parameter selecting the sample type to display:
+//   - / lists the sample types of the profile.
+//   - /flamegraph renders the interactive flame graph of the profile.
+//   - /top prints the functions with the highest values (see printTop), sorted
+//     by cumulative value if the "cum" query parameter is set.
+//   - /profile downloads the profile, which can be opened with pprof.
+func newWebHandler(prof *profile.Profile) http.Handler {
+	mux := http.NewServeMux()
+
+	mux.HandleFunc("/", func(w http.ResponseWriter, r *http.Request) {
+		if r.URL.Path != "/" {
+			http.NotFound(w, r)
+			return
+		}
+		w.Header().Set("Content-Type", "text/html; charset=utf-8")
+		if err := webIndex.Execute(w, prof); err != nil {
+			http.Error(w, err.Error(), http.StatusInternalServerError)
+		}
+	})
+
+	mux.HandleFunc("/flamegraph", func(w http.ResponseWriter, r *http.Request) {
+		p, err := selectSampleType(prof, r.FormValue("si"))
+		if err != nil {
+			http.Error(w, err.Error(), http.StatusBadRequest)
+			return
+		}
+		w.Header().Set("Content-Type", "text/html; charset=utf-8")
+		if err := wzprof.WriteFlameGraph(w, p); err != nil {
+			http.Error(w, err.Error(), http.StatusInternalServerError)
+		}
+	})
+
+	mux.HandleFunc("/top", func(w http.ResponseWriter, r *http.Request) {
+		if _, err := prof.SampleIndexByName(r.FormValue("si")); err != nil {
+			http.Error(w, err.Error(), http.StatusBadRequest)
+			return
+		}
+		cum, _ := strconv.ParseBool(r.FormValue("cum"))
+		w.Header().Set("Content-Type", "text/plain; charset=utf-8")
+		if err := printTop(w, prof, r.FormValue("si"), 0, cum); err != nil {
+			http.Error(w, err.Error(), http.StatusInternalServerError)
+		}
+	})
+
+	mux.HandleFunc("/profile", func(w http.ResponseWriter, r *http.Request) {
+		w.Header().Set("Content-Type", "application/octet-stream")
+		w.Header().Set("Content-Disposition", `attachment; filename="profile.pprof"`)
+		if err := prof.Write(w); err != nil {
+			http.Error(w, err.Error(), http.StatusInternalServerError)
+		}
+	})
+
+	return mux
+}
+
+// selectSampleType returns a copy of prof displaying the sample type of the
+// given name by default, or prof itself if the name is empty.
+func selectSampleType(prof *profile.Profile, sampleType string) (*profile.Profile, error) {
+	if sampleType == "" {
+		return prof, nil
+	}
+	if _, err := prof.SampleIndexByName(sampleType); err != nil {
+		return nil, err
+	}
+	p := prof.Copy()
+	p.DefaultSampleType = sampleType
+	return p, nil
+}
+
+var webIndex = template.Must(template.New("index").Parse(`<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>wzprof</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+td { padding: 0.2em 1em 0.2em 0; }
+</style>
+</head>
+<body>
+<h3>wzprof</h3>
+<table>
+{{- range .SampleType}}
+<tr>
+<td>{{.Type}} ({{.Unit}})</td>
+<td><a href="flamegraph?si={{.Type}}">flame graph</a></td>
+<td><a href="top?si={{.Type}}">top</a></td>
+<td><a href="top?si={{.Type}}&amp;cum=1">top (cumulative)</a></td>
+</tr>
+{{- end}}
+</table>
+<p><a href="profile">Download the profile</a></p>
+</body>
+</html>
+`))
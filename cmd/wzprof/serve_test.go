@@ -0,0 +1,62 @@
+package main
+
+import (
+	"io"
+	"net/http"
+	"net/http/httptest"
+	"strings"
+	"testing"
+
+	"github.com/google/pprof/profile"
+)
+
+func TestWebHandler(t *testing.T) {
+	server := httptest.NewServer(newWebHandler(newTestProfile(map[string]int64{"a": 10, "b": 20})))
+	defer server.Close()
+
+	get := func(path string) (int, string) {
+		t.Helper()
+		res, err := http.Get(server.URL + path)
+		if err != nil {
+			t.Fatal(err)
+		}
+		defer res.Body.Close()
+		b, err := io.ReadAll(res.Body)
+		if err != nil {
+			t.Fatal(err)
+		}
+		return res.StatusCode, string(b)
+	}
+
+	for _, test := range []struct {
+		path   string
+		status int
+		body   string
+	}{
+		{"/", http.StatusOK, `href="flamegraph?si=cpu"`},
+		{"/flamegraph?si=cpu", http.StatusOK, `"name":"a"`},
+		{"/flamegraph?si=wall", http.StatusBadRequest, ""},
+		{"/top?si=cpu", http.StatusOK, "Showing 2 functions"},
+		{"/missing", http.StatusNotFound, ""},
+	} {
+		status, body := get(test.path)
+		if status != test.status {
+			t.Errorf("%s: wrong status: want=%d got=%d", test.path, test.status, status)
+		}
+		if !strings.Contains(body, test.body) {
+			t.Errorf("%s: response does not contain %q:\n%s", test.path, test.body, body)
+		}
+	}
+
+	status, body := get("/profile")
+	if status != http.StatusOK {
+		t.Fatalf("/profile: wrong status: want=200 got=%d", status)
+	}
+	prof, err := profile.Parse(strings.NewReader(body))
+	if err != nil {
+		t.Fatal(err)
+	}
+	if len(prof.Sample) != 2 {
+		t.Errorf("/profile: wrong number of samples: want=2 got=%d", len(prof.Sample))
+	}
+}
@@ -0,0 +1,160 @@
+package main
+
+import (
+	"flag"
+	"fmt"
+	"io"
+	"os"
+	"sort"
+	"text/tabwriter"
+	"time"
+
+	"github.com/google/pprof/profile"
+)
+
+// runTop implements the "wzprof top" command, which prints the functions with
+// the highest values of a profile, similarly to "go tool pprof -top".
+func runTop(args []string) error {
+	flags := flag.NewFlagSet("top", flag.ContinueOnError)
+	sampleType := flags.String("sample-type", "", "Sample type of the report (default: the default sample type of the profile, or the last one).")
+	count := flags.Int("n", 20, "Number of functions printed in the report (0 for all).")
+	cum := flags.Bool("cum", false, "Sort the functions by cumulative value instead of flat value.")
+	paths, err := parseInterspersed(flags, args)
+	if err != nil {
+		return err
+	}
+	if len(paths) != 1 {
+		return fmt.Errorf("usage: wzprof top [options] <profile>")
+	}
+
+	prof, err := readProfile(paths[0])
+	if err != nil {
+		return err
+	}
+	return printTop(os.Stdout, prof, *sampleType, *count, *cum)
+}
+
+type topEntry struct {
+	name      string
+	flat, cum int64
+}
+
+// printTop writes to w a table of the flat and cumulative values of the count
+// functions of prof with the highest values. The flat value of a function is
+// the value of the samples where it is the innermost frame, the cumulative
+// value includes the samples where it is one of the callers.
+func printTop(w io.Writer, prof *profile.Profile, sampleType string, count int, cum bool) error {
+	index, err := prof.SampleIndexByName(sampleType)
+	if err != nil {
+		return err
+	}
+	unit := prof.SampleType[index].Unit
+	entries, total := functionValues(prof, index)
+
+	top := make([]*topEntry, 0, len(entries))
+	for _, e := range entries {
+		top = append(top, e)
+	}
+	sort.Slice(top, func(i, j int) bool {
+		ei, ej := top[i], top[j]
+		vi, vj := ei.flat, ej.flat
+		if cum {
+			vi, vj = ei.cum, ej.cum
+		}
+		if vi != vj {
+			return vi > vj
+		}
+		return ei.name < ej.name
+	})
+	if count > 0 && len(top) > count {
+		top = top[:count]
+	}
+
+	var shown int64
+	for _, e := range top {
+		shown += e.flat
+	}
+	fmt.Fprintf(w, "Showing %d functions accounting for %s, %s of %s total\n",
+		len(top), formatValue(shown, unit), percentOf(shown, total), formatValue(total, unit))
+
+	tw := tabwriter.NewWriter(w, 0, 8, 2, ' ', tabwriter.AlignRight)
+	fmt.Fprintln(tw, "flat\tflat%\tsum%\tcum\tcum%\t")
+	var sum int64
+	for _, e := range top {
+		sum += e.flat
+		fmt.Fprintf(tw, "%s\t%s\t%s\t%s\t%s\t  %s\n",
+			formatValue(e.flat, unit), percentOf(e.flat, total), percentOf(sum, total),
+			formatValue(e.cum, unit), percentOf(e.cum, total), e.name)
+	}
+	return tw.Flush()
+}
+
+// functionValues returns the flat and cumulative values of the functions of
+// prof for the sample type at index, and the total value of the samples.
+func functionValues(prof *profile.Profile, index int) (map[string]*topEntry, int64) {
+	var total int64
+	entries := make(map[string]*topEntry)
+	entry := func(name string) *topEntry {
+		e := entries[name]
+		if e == nil {
+			e = &topEntry{name: name}
+			entries[name] = e
+		}
+		return e
+	}
+
+	seen := make(map[string]struct{})
+	for _, s := range prof.Sample {
+		v := s.Value[index]
+		total += v
+
+		for i, loc := range s.Location {
+			for j, line := range loc.Line {
+				name := line.Function.Name
+				// The first line of the first location is the innermost
+				// frame, inlined functions included.
+				if i == 0 && j == 0 {
+					entry(name).flat += v
+				}
+				// Recursive functions only count once per sample.
+				if _, ok := seen[name]; !ok {
+					seen[name] = struct{}{}
+					entry(name).cum += v
+				}
+			}
+		}
+		for name := range seen {
+			delete(seen, name)
+		}
+	}
+	return entries, total
+}
+
+func percentOf(v, total int64) string {
+	if total == 0 {
+		return "0%"
+	}
+	return fmt.Sprintf("%.2f%%", 100*float64(v)/float64(total))
+}
+
+// formatValue formats v in a human readable form according to its unit, for
+// the units of the profiles generated by wzprof.
+func formatValue(v int64, unit string) string {
+	switch unit {
+	case "nanoseconds":
+		return time.Duration(v).Round(10 * time.Microsecond).String()
+	case "bytes":
+		const units = "kMGTPE"
+		if v < 1024 && v > -1024 {
+			return fmt.Sprintf("%dB", v)
+		}
+		f, i := float64(v)/1024, 0
+		for (f >= 1024 || f <= -1024) && i < len(units)-1 {
+			f /= 1024
+			i++
+		}
+		return fmt.Sprintf("%.2f%cB", f, units[i])
+	default:
+		return fmt.Sprint(v)
+	}
+}
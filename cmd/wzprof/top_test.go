@@ -0,0 +1,78 @@
+package main
+
+import (
+	"bytes"
+	"strings"
+	"testing"
+
+	"github.com/google/pprof/profile"
+)
+
+func TestPrintTop(t *testing.T) {
+	a := &profile.Function{ID: 1, Name: "a"}
+	b := &profile.Function{ID: 2, Name: "b"}
+	locA := &profile.Location{ID: 1, Line: []profile.Line{{Function: a}}}
+	locB := &profile.Location{ID: 2, Line: []profile.Line{{Function: b}}}
+
+	prof := &profile.Profile{
+		SampleType: []*profile.ValueType{{Type: "cpu", Unit: "nanoseconds"}},
+		Sample: []*profile.Sample{
+			// b called by a.
+			{Location: []*profile.Location{locB, locA}, Value: []int64{30e6}},
+			{Location: []*profile.Location{locA}, Value: []int64{10e6}},
+		},
+		Location: []*profile.Location{locA, locB},
+		Function: []*profile.Function{a, b},
+	}
+
+	for _, test := range []struct {
+		cum   bool
+		order []string
+	}{
+		{cum: false, order: []string{"b", "a"}},
+		{cum: true, order: []string{"a", "b"}},
+	} {
+		var buf bytes.Buffer
+		if err := printTop(&buf, prof, "", 0, test.cum); err != nil {
+			t.Fatal(err)
+		}
+		lines := strings.Split(strings.TrimSuffix(buf.String(), "\n"), "\n")
+		if len(lines) != 4 {
+			t.Fatalf("wrong number of lines:\n%s", buf.String())
+		}
+		if want := "Showing 2 functions accounting for 40ms, 100.00% of 40ms total"; lines[0] != want {
+			t.Errorf("wrong header: want=%q got=%q", want, lines[0])
+		}
+		for i, name := range test.order {
+			if !strings.HasSuffix(lines[i+2], " "+name) {
+				t.Errorf("cum=%t: wrong function at rank %d: want=%s got=%q", test.cum, i, name, lines[i+2])
+			}
+		}
+	}
+
+	var buf bytes.Buffer
+	if err := printTop(&buf, prof, "", 0, false); err != nil {
+		t.Fatal(err)
+	}
+	// flat, flat%, sum%, cum, cum% of a.
+	if fields := strings.Fields(strings.Split(buf.String(), "\n")[3]); strings.Join(fields, " ") != "10ms 25.00% 100.00% 40ms 100.00% a" {
+		t.Errorf("wrong values of a: %v", fields)
+	}
+}
+
+func TestFormatValue(t *testing.T) {
+	for _, test := range []struct {
+		value int64
+		unit  string
+		want  string
+	}{
+		{1500000, "nanoseconds", "1.5ms"},
+		{512, "bytes", "512B"},
+		{3 << 20, "bytes", "3.00MB"},
+		{42, "count", "42"},
+	} {
+		if got := formatValue(test.value, test.unit); got != test.want {
+			t.Errorf("formatValue(%d, %q): want=%s got=%s", test.value, test.unit, test.want, got)
+		}
+	}
+}
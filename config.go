@@ -0,0 +1,242 @@
+package wzprof
+
+import (
+	"bytes"
+	"encoding/json"
+	"fmt"
+	"os"
+	"regexp"
+	"sort"
+
+	"golang.org/x/exp/slices"
+)
+
+// Config is the configuration of the profiling of a set of wasm modules, which
+// avoids passing many options to each of them. Configurations are usually
+// loaded from JSON files with ReadConfig, for example:
+//
+//	{
+//	  "default": {"sample_rate": 0.05},
+//	  "modules": {
+//	    "app.wasm": {
+//	      "sample_rate": 1,
+//	      "profiles": {"cpu": "/tmp/cpu.pprof", "memory": "/tmp/mem.pprof"},
+//	      "filter": "^my_app::"
+//	    }
+//	  }
+//	}
+type Config struct {
+	// Default holds the settings of the modules, which the settings of each
+	// module override.
+	Default ModuleConfig `json:"default"`
+	// Modules maps the names of modules (e.g. app.wasm) to their settings.
+	Modules map[string]ModuleConfig `json:"modules"`
+}
+
+// ModuleConfig is the configuration of the profiling of a single wasm module.
+// The zero value of the fields means that the setting is not configured.
+type ModuleConfig struct {
+	// SampleRate is the sampling rate of function calls (0-1).
+	SampleRate float64 `json:"sample_rate,omitempty"`
+	// Profiles maps the names of the profiles to enable to the paths of the
+	// files where they are written, which may be empty. The names are those
+	// returned by ConfigProfiles.
+	Profiles map[string]string `json:"profiles,omitempty"`
+	// Filter, Exclude and Elide are the regular expressions of the Filter,
+	// Exclude and ElideFrames options.
+	Filter  string `json:"filter,omitempty"`
+	Exclude string `json:"exclude,omitempty"`
+	Elide   string `json:"elide,omitempty"`
+	// MaxStacks is the limit of the MaxStacks option.
+	MaxStacks int `json:"max_stacks,omitempty"`
+	// StripTemplates, HostFrames and NativeFrames enable the options of the
+	// same names.
+	StripTemplates bool `json:"strip_templates,omitempty"`
+	HostFrames     bool `json:"host_frames,omitempty"`
+	NativeFrames   bool `json:"native_frames,omitempty"`
+	// DebugInfo and SourceMap are the locations of the ExternalDebugInfo
+	// and SourceMap options.
+	DebugInfo string `json:"debug_info,omitempty"`
+	SourceMap string `json:"source_map,omitempty"`
+	// OutputDir is a directory where to write timestamped profiles.
+	OutputDir string `json:"output_dir,omitempty"`
+	// PushURL is the URL of a Pyroscope server where to push profiles.
+	PushURL string `json:"push_url,omitempty"`
+}
+
+// ConfigProfiles returns the names of the profiles which can be enabled in
+// the Profiles field of ModuleConfig.
+func ConfigProfiles() []string {
+	return []string{"block", "calls", "cpu", "gc", "memgrow", "memory", "mutex", "stackdepth", "syscalls", "traps"}
+}
+
+// ReadConfig reads the JSON configuration file at the given path.
+//
+// Unknown fields, profile names and invalid regular expressions are reported
+// as errors, so mistakes in configuration files do not go unnoticed.
+func ReadConfig(path string) (*Config, error) {
+	b, err := os.ReadFile(path)
+	if err != nil {
+		return nil, err
+	}
+	d := json.NewDecoder(bytes.NewReader(b))
+	d.DisallowUnknownFields()
+
+	config := new(Config)
+	if err := d.Decode(config); err != nil {
+		return nil, fmt.Errorf("parsing %s: %w", path, err)
+	}
+	if err := config.Default.validate(); err != nil {
+		return nil, fmt.Errorf("%s: default: %w", path, err)
+	}
+	for name, module := range config.Modules {
+		if err := module.validate(); err != nil {
+			return nil, fmt.Errorf("%s: module %s: %w", path, name, err)
+		}
+	}
+	return config, nil
+}
+
+// Module returns the settings of the module of the given name, which are the
+// default settings overridden by the settings of the module, if any.
+func (c *Config) Module(name string) ModuleConfig {
+	m := c.Default
+	module, ok := c.Modules[name]
+	if !ok {
+		return m
+	}
+	if module.SampleRate != 0 {
+		m.SampleRate = module.SampleRate
+	}
+	if module.Profiles != nil {
+		m.Profiles = module.Profiles
+	}
+	if module.Filter != "" {
+		m.Filter = module.Filter
+	}
+	if module.Exclude != "" {
+		m.Exclude = module.Exclude
+	}
+	if module.Elide != "" {
+		m.Elide = module.Elide
+	}
+	if module.MaxStacks != 0 {
+		m.MaxStacks = module.MaxStacks
+	}
+	m.StripTemplates = m.StripTemplates || module.StripTemplates
+	m.HostFrames = m.HostFrames || module.HostFrames
+	m.NativeFrames = m.NativeFrames || module.NativeFrames
+	if module.DebugInfo != "" {
+		m.DebugInfo = module.DebugInfo
+	}
+	if module.SourceMap != "" {
+		m.SourceMap = module.SourceMap
+	}
+	if module.OutputDir != "" {
+		m.OutputDir = module.OutputDir
+	}
+	if module.PushURL != "" {
+		m.PushURL = module.PushURL
+	}
+	return m
+}
+
+func (c *ModuleConfig) validate() error {
+	if c.SampleRate < 0 || c.SampleRate > 1 {
+		return fmt.Errorf("invalid sample rate: %g", c.SampleRate)
+	}
+	for name := range c.Profiles {
+		if !slices.Contains(ConfigProfiles(), name) {
+			return fmt.Errorf("unknown profile: %s", name)
+		}
+	}
+	if _, err := c.regexps(); err != nil {
+		return err
+	}
+	return nil
+}
+
+func (c *ModuleConfig) regexps() ([]ProfilingOption, error) {
+	var options []ProfilingOption
+	if c.Filter != "" {
+		re, err := regexp.Compile(c.Filter)
+		if err != nil {
+			return nil, fmt.Errorf("invalid filter: %w", err)
+		}
+		options = append(options, Filter(re))
+	}
+	if c.Exclude != "" {
+		re, err := regexp.Compile(c.Exclude)
+		if err != nil {
+			return nil, fmt.Errorf("invalid exclude: %w", err)
+		}
+		options = append(options, Exclude(re))
+	}
+	if c.Elide != "" {
+		re, err := regexp.Compile(c.Elide)
+		if err != nil {
+			return nil, fmt.Errorf("invalid elide: %w", err)
+		}
+		options = append(options, ElideFrames(re))
+	}
+	return options, nil
+}
+
+// ProfilingFromConfig is like ProfilingFor but configures the profiling of the
+// wasm module according to the settings of config. The options passed as
+// arguments are applied after the settings.
+//
+// The sample rate, profiles and destinations of the settings are not used by
+// Profiling, see NewSampler and ModuleConfig.Profilers.
+func ProfilingFromConfig(wasm []byte, config ModuleConfig, options ...ProfilingOption) (*Profiling, error) {
+	regexps, err := config.regexps()
+	if err != nil {
+		return nil, err
+	}
+	opts := append(regexps,
+		MaxStacks(config.MaxStacks),
+		StripTemplates(config.StripTemplates),
+		HostFrames(config.HostFrames),
+		NativeFrames(config.NativeFrames),
+		ExternalDebugInfo(config.DebugInfo),
+		SourceMap(config.SourceMap),
+	)
+	return ProfilingFor(wasm, append(opts, options...)...), nil
+}
+
+// Profilers returns the profilers of the profiles enabled in the settings,
+// sorted by name of the profiles, which may be passed to Handler.
+func (c *ModuleConfig) Profilers(p *Profiling) []Profiler {
+	names := make([]string, 0, len(c.Profiles))
+	for name := range c.Profiles {
+		names = append(names, name)
+	}
+	sort.Strings(names)
+
+	profilers := make([]Profiler, 0, len(names))
+	for _, name := range names {
+		switch name {
+		case "block":
+			profilers = append(profilers, p.BlockProfiler())
+		case "calls":
+			profilers = append(profilers, p.CallProfiler())
+		case "cpu":
+			profilers = append(profilers, p.CPUProfiler())
+		case "gc":
+			profilers = append(profilers, p.GCProfiler())
+		case "memgrow":
+			profilers = append(profilers, p.GrowProfiler())
+		case "memory":
+			profilers = append(profilers, p.MemoryProfiler())
+		case "mutex":
+			profilers = append(profilers, p.MutexProfiler())
+		case "stackdepth":
+			profilers = append(profilers, p.StackProfiler())
+		case "syscalls":
+			profilers = append(profilers, p.SyscallProfiler())
+		case "traps":
+			profilers = append(profilers, p.TrapProfiler())
+		}
+	}
+	return profilers
+}
@@ -0,0 +1,73 @@
+package wzprof
+
+import (
+	"os"
+	"path/filepath"
+	"strings"
+	"testing"
+)
+
+func TestReadConfig(t *testing.T) {
+	path := filepath.Join(t.TempDir(), "wzprof.json")
+	err := os.WriteFile(path, []byte(`{
+		"default": {"sample_rate": 0.05, "max_stacks": 1000},
+		"modules": {
+			"app.wasm": {
+				"sample_rate": 1,
+				"profiles": {"cpu": "/tmp/cpu.pprof", "memory": ""},
+				"filter": "^my_app::"
+			}
+		}
+	}`), 0644)
+	if err != nil {
+		t.Fatal(err)
+	}
+
+	config, err := ReadConfig(path)
+	if err != nil {
+		t.Fatal(err)
+	}
+
+	app := config.Module("app.wasm")
+	if app.SampleRate != 1 || app.MaxStacks != 1000 || app.Filter != "^my_app::" {
+		t.Errorf("wrong settings of app.wasm: %+v", app)
+	}
+	if path := app.Profiles["cpu"]; path != "/tmp/cpu.pprof" {
+		t.Errorf("wrong path of the cpu profile: %q", path)
+	}
+
+	other := config.Module("other.wasm")
+	if other.SampleRate != 0.05 || other.Profiles != nil {
+		t.Errorf("wrong settings of other.wasm: %+v", other)
+	}
+
+	p, err := ProfilingFromConfig(nil, app)
+	if err != nil {
+		t.Fatal(err)
+	}
+	profilers := app.Profilers(p)
+	if len(profilers) != 2 || profilers[0].Name() != "profile" || profilers[1].Name() != "allocs" {
+		t.Errorf("wrong profilers: %v", profilers)
+	}
+}
+
+func TestReadConfigErrors(t *testing.T) {
+	for _, test := range []struct {
+		config string
+		error  string
+	}{
+		{`{"default": {"sample": 1}}`, "unknown field"},
+		{`{"default": {"sample_rate": 2}}`, "invalid sample rate"},
+		{`{"modules": {"app.wasm": {"profiles": {"heap": ""}}}}`, "unknown profile: heap"},
+		{`{"modules": {"app.wasm": {"exclude": "("}}}`, "invalid exclude"},
+	} {
+		path := filepath.Join(t.TempDir(), "wzprof.json")
+		if err := os.WriteFile(path, []byte(test.config), 0644); err != nil {
+			t.Fatal(err)
+		}
+		_, err := ReadConfig(path)
+		if err == nil || !strings.Contains(err.Error(), test.error) {
+			t.Errorf("%s: want error containing %q, got %v", test.config, test.error, err)
+		}
+	}
+}
@@ -0,0 +1,57 @@
+package wzprof
+
+import (
+	"context"
+	"errors"
+	"net"
+	"net/http"
+	"os"
+)
+
+// ServeSocket serves handler on a unix socket created at path, until ctx is
+// canceled. The handler is usually the one returned by Handler, which lets
+// "wzprof attach" capture the profiles of a program embedding the profilers
+// without exposing them on a network address:
+//
+//	go wzprof.ServeSocket(ctx, "/tmp/wzprof.sock", wzprof.Handler(1, cpu, mem))
+//
+// The CPU profiles are started and stopped by the requests, with the duration
+// of their "seconds" query parameter, and the other profiles are snapshots of
+// the values recorded when the request is received.
+//
+// A socket left at path by a previous run of the program is removed, and the
+// socket is removed when the function returns.
+func ServeSocket(ctx context.Context, path string, handler http.Handler) error {
+	if fi, err := os.Lstat(path); err == nil && fi.Mode()&os.ModeSocket != 0 {
+		_ = os.Remove(path)
+	}
+	l, err := net.Listen("unix", path)
+	if err != nil {
+		return err
+	}
+	defer os.Remove(path)
+
+	server := &http.Server{Handler: handler}
+	go func() {
+		<-ctx.Done()
+		_ = server.Close()
+	}()
+	if err := server.Serve(l); !errors.Is(err, http.ErrServerClosed) {
+		return err
+	}
+	return nil
+}
+
+// SocketClient returns a http client sending its requests to the unix socket
+// at path, whatever the host of their URLs, to fetch profiles served by
+// ServeSocket.
+func SocketClient(path string) *http.Client {
+	return &http.Client{
+		Transport: &http.Transport{
+			DialContext: func(ctx context.Context, _, _ string) (net.Conn, error) {
+				var d net.Dialer
+				return d.DialContext(ctx, "unix", path)
+			},
+		},
+	}
+}
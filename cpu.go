@@ -5,6 +5,7 @@ import (
 	"net/http"
 	"strconv"
 	"sync"
+	"sync/atomic"
 	"time"
 
 	"github.com/google/pprof/profile"
@@ -16,17 +17,27 @@ import (
 // samples of CPU time spent in functions of a WebAssembly module.
 //
 // The profiler generates samples of two types:
-// - "sample" counts the number of function calls.
-// - "cpu" records the time spent in function calls (in nanoseconds).
+// - "samples" counts the number of function calls.
+// - "cpu" records the time spent in function calls (in nanoseconds), which is
+// the default sample type of the profiles.
+//
+// When configured with WallTime, the profiler generates "sample" and "wall"
+// samples instead (see WallTime for details).
 type CPUProfiler struct {
-	p      *Profiling
-	mutex  sync.Mutex
-	counts stackCounterMap
-	frames []cpuTimeFrame
-	traces []stackTrace
-	time   func() int64
-	start  time.Time
-	host   bool
+	p       *Profiling
+	mutex   sync.Mutex
+	active  atomic.Bool
+	counts  stackCounterShards
+	stacks  threadLocal[cpuThread]
+	time    func() int64
+	start   time.Time
+	begin   atomic.Int64 // value of the time function at the start
+	base    int64        // time spent in the listeners at the start, see SelfProfile
+	host    bool
+	wall    bool
+	startup *startupProfile
+	stop    chan struct{}
+	done    chan struct{}
 }
 
 // CPUProfilerOption is a type used to represent configuration options for
@@ -49,12 +60,84 @@ func TimeFunc(time func() int64) CPUProfilerOption {
 	return func(p *CPUProfiler) { p.time = time }
 }
 
+// WallTime configures the CPU profiler to measure the elapsed real time instead
+// of the time spent in function calls. A timer takes samples of the guest call
+// stacks at a fixed interval, including stacks blocked in calls to host
+// functions, which is useful to investigate latency.
+//
+// The profiles have the "samples" and "wall" sample types, counting the number
+// of timer ticks and the elapsed time (in nanoseconds) that each call stack was
+// observed for.
+//
+// The profiler must observe all function calls to know the current call stack
+// of the program, so it must not be wrapped by Sample.
+//
+// Default to false.
+func WallTime(enable bool) CPUProfilerOption {
+	return func(p *CPUProfiler) { p.wall = enable }
+}
+
+// RecordStartup configures the CPU profiler to record a separate profile of the
+// start of the module instance, from the first call to its functions until its
+// _start or _initialize function returns, which is returned by StartupProfile.
+// The startup profile is recorded whether the profile of the CPU profiler is
+// started or not, so the cost of the cold start of the module (e.g. the static
+// constructors, the initialization of the language runtime, or the imports of
+// Python programs) can be analyzed independently from the steady state.
+//
+// The end of the start is observed by a listener of the entry function which is
+// not sampled when the profiler is wrapped by Sample, the other calls are.
+//
+// Default to false.
+func RecordStartup(enable bool) CPUProfilerOption {
+	return func(p *CPUProfiler) {
+		if enable {
+			p.startup = &startupProfile{call: callProfile{profiler: p}}
+		} else {
+			p.startup = nil
+		}
+	}
+}
+
+// startupProfile holds the samples recorded during the start of the module,
+// see RecordStartup.
+type startupProfile struct {
+	call  callProfile
+	begin atomic.Int64 // time of the first call, in nanoseconds since the epoch
+	end   atomic.Int64 // time at which the entry function returned
+}
+
+// wallTimePeriod is the interval between samples taken by the wall-clock timer,
+// which matches the default sampling frequency of the Go CPU profiler.
+const wallTimePeriod = 10 * time.Millisecond
+
+// cpuThread holds the state of the CPU profiler for a single thread.
+//
+// The frames are modified while holding the mutex of the thread because the
+// wall-clock timer reads them concurrently.
+type cpuThread struct {
+	mutex  sync.Mutex
+	frames []cpuTimeFrame
+	traces []stackTrace
+}
+
 type cpuTimeFrame struct {
+	def   api.FunctionDefinition
 	start int64
 	sub   int64
 	trace stackTrace
+	call  *callProfile
 }
 
+// callProfile holds the samples recorded during a call made by ProfileCall,
+// it is carried by the context of the call.
+type callProfile struct {
+	profiler *CPUProfiler
+	counts   stackCounterShards
+}
+
+type callProfileKey struct{}
+
 func newCPUProfiler(p *Profiling, options ...CPUProfilerOption) *CPUProfiler {
 	c := &CPUProfiler{
 		p:    p,
@@ -70,33 +153,273 @@ func newCPUProfiler(p *Profiling, options ...CPUProfilerOption) *CPUProfiler {
 // to indicate whether starting the profile succeeded (e.g. false is returned if
 // it was already started).
 func (p *CPUProfiler) StartProfile() bool {
+	if !p.startProfile() {
+		return false
+	}
+	p.p.startProfile(p.Name())
+	return true
+}
+
+func (p *CPUProfiler) startProfile() bool {
 	p.mutex.Lock()
 	defer p.mutex.Unlock()
 
-	if p.counts != nil {
+	if p.active.Load() {
 		return false // already started
 	}
 
-	p.counts = make(stackCounterMap)
+	// Discard the samples recorded by calls which had not observed that the
+	// previous profile was stopped.
+	p.counts.merge(true)
 	p.start = time.Now()
+	begin := p.time()
+	p.begin.Store(begin)
+	p.base = p.p.overhead.listeners.Load()
+
+	// The calls made during a previous profile which have not returned
+	// yet only account for the time they spend from now on.
+	p.stacks.values.Range(func(_, v any) bool {
+		t := v.(*cpuThread)
+		t.mutex.Lock()
+		for i := range t.frames {
+			if f := &t.frames[i]; f.start != 0 && f.call == nil {
+				f.start, f.sub = begin, 0
+			}
+		}
+		t.mutex.Unlock()
+		return true
+	})
+	p.active.Store(true)
+
+	if p.wall {
+		p.stop = make(chan struct{})
+		p.done = make(chan struct{})
+		go p.sampleWallTime(p.stop, p.done)
+	}
 	return true
 }
 
+func (p *CPUProfiler) sampleWallTime(stop <-chan struct{}, done chan<- struct{}) {
+	defer close(done)
+	ticker := time.NewTicker(wallTimePeriod)
+	defer ticker.Stop()
+	for {
+		select {
+		case <-ticker.C:
+			p.observeWallTime(int64(wallTimePeriod))
+		case <-stop:
+			return
+		}
+	}
+}
+
+// observeWallTime records a sample of the current call stack of each thread,
+// accounting for the given duration (in nanoseconds).
+func (p *CPUProfiler) observeWallTime(duration int64) {
+	if !p.active.Load() {
+		return
+	}
+
+	p.stacks.values.Range(func(k, v any) bool {
+		t := v.(*cpuThread)
+		t.mutex.Lock()
+		// Frames of calls made before the profile was started have no
+		// stack trace and are skipped.
+		for i := len(t.frames) - 1; i >= 0; i-- {
+			if f := &t.frames[i]; f.start != 0 {
+				if trace, duration, ok := p.p.hookSample(p.Name(), f.trace, duration); ok {
+					p.counts.observe(k.(api.Module), trace, duration, p.p.maxStacks)
+				}
+				break
+			}
+		}
+		t.mutex.Unlock()
+		return true
+	})
+}
+
 // StopProfile stops recording and returns the CPU profile. The method returns
 // nil if recording of the CPU profile wasn't started.
+//
+// The profile covers the period between the calls to StartProfile and
+// StopProfile: the calls which started before the profile only account for the
+// time they spent after it started, and the calls which have not returned yet
+// account for the time they spent until it stopped.
 func (p *CPUProfiler) StopProfile(sampleRate float64) *profile.Profile {
 	p.mutex.Lock()
-	samples, start := p.counts, p.start
-	p.counts = nil
+	if !p.active.Load() {
+		p.mutex.Unlock()
+		return nil
+	}
+	p.active.Store(false)
+	now, end := p.time(), time.Now()
+
+	// The wall-clock timer does not acquire the mutex, it can be waited on
+	// while holding it.
+	if p.stop != nil {
+		close(p.stop)
+		<-p.done
+		p.stop, p.done = nil, nil
+	}
+
+	samples, start, base := p.counts.merge(true), p.start, p.base
+	p.observeRunningCalls(samples, now)
 	p.mutex.Unlock()
 
-	if samples == nil {
+	prof := p.buildProfile(samples, start, end, sampleRate)
+	p.p.addOverheadSample(prof, base)
+	return p.p.endProfile(p.Name(), prof)
+}
+
+// SnapshotProfile returns a CPU profile of the samples recorded since the call
+// to StartProfile, without stopping the recording. The method returns nil if
+// recording of the CPU profile wasn't started.
+//
+// Successive snapshots cover overlapping periods of time, all starting when the
+// profile was started.
+func (p *CPUProfiler) SnapshotProfile(sampleRate float64) *profile.Profile {
+	p.mutex.Lock()
+	if !p.active.Load() {
+		p.mutex.Unlock()
+		return nil
+	}
+	now, end := p.time(), time.Now()
+	samples := p.counts.merge(false)
+	start, base := p.start, p.base
+	p.observeRunningCalls(samples, now)
+	p.mutex.Unlock()
+
+	prof := p.buildProfile(samples, start, end, sampleRate)
+	p.p.addOverheadSample(prof, base)
+	return p.p.endProfile(p.Name(), prof)
+}
+
+// StartupProfile returns the CPU profile of the start of the module instance,
+// which ends when its _start or _initialize function returns, or nil if the
+// profiler was not configured with RecordStartup or the start of the module did
+// not complete yet. The profile is the same at each call.
+func (p *CPUProfiler) StartupProfile(sampleRate float64) *profile.Profile {
+	if p.startup == nil {
+		return nil
+	}
+	end := p.startup.end.Load()
+	if end == 0 {
+		return nil
+	}
+	begin := p.startup.begin.Load()
+	if begin == 0 {
+		begin = end // no calls were recorded
+	}
+	prof := p.buildProfile(p.startup.call.counts.merge(false), time.Unix(0, begin), time.Unix(0, end), sampleRate)
+	if p.wall {
+		prof.PeriodType, prof.Period = nil, 0
+	}
+	return p.p.endProfile(p.Name(), prof)
+}
+
+// startupCall returns the profile recording the start of the module, or nil if
+// it is not recorded or has already ended.
+func (p *CPUProfiler) startupCall() *callProfile {
+	if p.startup == nil || p.startup.end.Load() != 0 {
 		return nil
 	}
+	if p.startup.begin.Load() == 0 {
+		p.startup.begin.CompareAndSwap(0, time.Now().UnixNano())
+	}
+	return &p.startup.call
+}
+
+// isEntryFunction reports whether def is the _start or _initialize function of
+// a module, which start its command or reactor.
+func isEntryFunction(def api.FunctionDefinition) bool {
+	for _, name := range def.ExportNames() {
+		if name == "_start" || name == "_initialize" {
+			return true
+		}
+	}
+	return false
+}
 
-	duration := time.Since(start)
+// ProfileCall calls fn with the given parameters and returns a CPU profile of
+// this invocation only, along with the results of the call. The calls made
+// concurrently by other invocations are not part of the profile, which makes
+// it possible to attach a profile to individual requests of a function served
+// by the module (e.g. a function-as-a-service handler).
+//
+// The profiler must be installed as function listener of the module, but its
+// profile does not need to be started. The samples of the call are not
+// scaled, so the profiler should not be wrapped by Sample. In wall-clock mode,
+// the time of calls is measured when they return rather than sampled by the
+// timer.
+func (p *CPUProfiler) ProfileCall(ctx context.Context, fn api.Function, params ...uint64) (*profile.Profile, []uint64, error) {
+	p.p.startProfile(p.Name())
+	call := &callProfile{profiler: p}
+	start := time.Now()
+	results, err := fn.Call(context.WithValue(ctx, callProfileKey{}, call), params...)
+
+	prof := p.buildProfile(call.counts.merge(true), start, time.Now(), 1)
+	if p.wall {
+		prof.PeriodType, prof.Period = nil, 0
+	}
+	return p.p.endProfile(p.Name(), prof), results, err
+}
 
-	if !p.host {
+// callProfile returns the profile of the call made by ProfileCall that ctx is
+// the context of, or nil if the call is not profiled by p.
+func (p *CPUProfiler) callProfile(ctx context.Context) *callProfile {
+	call, _ := ctx.Value(callProfileKey{}).(*callProfile)
+	if call == nil || call.profiler != p {
+		return nil
+	}
+	return call
+}
+
+// Suspend detaches the call stack of the thread that mod runs on, for host
+// functions which suspend the guest call that they serve and let the embedder
+// make other calls to the module until the suspended call is resumed, for
+// example by yielding on the cancelation of their context. The calls made while
+// the call is suspended are recorded on a separate stack rather than as callees
+// of the suspended call, and the returned function must be called when the call
+// resumes to restore its stack.
+//
+// The time during which the call is suspended is not accounted to its stack,
+// so the time spent before and after the suspension is attributed to the same
+// frames. In wall-clock mode, the timer samples the calls running while the
+// call is suspended instead of the suspended stack.
+//
+// When the suspended call is aborted instead of resumed (e.g. because its
+// context was canceled), its frames are discarded and resume must not be
+// called.
+func (p *CPUProfiler) Suspend(mod api.Module) (resume func()) {
+	t := p.stacks.get(mod)
+	t.mutex.Lock()
+	frames := t.frames
+	t.frames = nil
+	suspended := p.time()
+	t.mutex.Unlock()
+
+	return func() {
+		t.mutex.Lock()
+		defer t.mutex.Unlock()
+		elapsed := p.time() - suspended
+		for i := range frames {
+			if f := &frames[i]; f.start != 0 {
+				f.start += elapsed
+			}
+		}
+		// The calls made during the suspension which have not returned
+		// yet remain on top of the stack.
+		t.frames = append(frames, t.frames...)
+	}
+}
+
+func (p *CPUProfiler) buildProfile(samples stackCounterMap, start, end time.Time, sampleRate float64) *profile.Profile {
+	duration := end.Sub(start)
+	samples.compact(p.p.maxStacks, addStackCounters)
+
+	// Wall time includes the time spent blocked in host functions, and so do
+	// profiles representing calls to host functions as frames.
+	if !p.host && !p.wall && !p.p.hostFrames {
 		for k, sample := range samples {
 			if sample.stack.host() {
 				delete(samples, k)
@@ -110,8 +433,71 @@ func (p *CPUProfiler) StopProfile(sampleRate float64) *profile.Profile {
 		// to scale them out.
 		1,
 	}
+	if p.wall {
+		// Wall-clock samples are taken by the timer, they are not affected
+		// by the sampling of function calls.
+		ratios[0] = 1
+	}
+
+	prof := buildProfile(p.p, samples, start, duration, p.SampleType(), ratios)
+	// The time spent in calls is the value that pprof users expect to see by
+	// default, like the cpu sample type of Go CPU profiles, rather than the
+	// number of calls.
+	prof.DefaultSampleType = prof.SampleType[1].Type
+	if p.wall {
+		// Samples are taken on timer ticks rather than function calls.
+		prof.PeriodType = prof.SampleType[1]
+		prof.Period = int64(wallTimePeriod)
+	}
+	return prof
+}
+
+// observeRunningCalls adds to samples the time spent by the calls which have not
+// returned yet at time now (in units of the time function), so calls running
+// for the whole duration of the profile (e.g. the main function of the module)
+// are accounted for. The samples of the profiler are not modified by the calls
+// once they return, since the profile was stopped or the samples were copied.
+//
+// In wall-clock mode, the timer already sampled the running calls.
+func (p *CPUProfiler) observeRunningCalls(samples stackCounterMap, now int64) {
+	if p.wall {
+		return
+	}
+	p.stacks.values.Range(func(_, v any) bool {
+		t := v.(*cpuThread)
+		t.mutex.Lock()
+		running := int64(0) // time spent in the running callee of the frame
+		for i := len(t.frames) - 1; i >= 0; i-- {
+			f := &t.frames[i]
+			if f.start == 0 {
+				// The call started while the profiler was not
+				// recording, the time of its callees is unknown.
+				break
+			}
+			total := now - f.start
+			self := p.profiledTime(total-f.sub-running, now)
+			running = total
+			if trace, self, ok := p.p.hookSample(p.Name(), f.trace, self); ok && self > 0 {
+				samples.observe(trace, self)
+			}
+		}
+		t.mutex.Unlock()
+		return true
+	})
+}
 
-	return buildProfile(p.p, samples, start, duration, p.SampleType(), ratios)
+// profiledTime bounds the time spent by a call ending at now to the time
+// elapsed since the profile was started. StartProfile already resets the start
+// of the running calls, except those profiled by ProfileCall which keep their
+// duration for the profiles of the calls.
+func (p *CPUProfiler) profiledTime(duration, now int64) int64 {
+	if elapsed := now - p.begin.Load(); duration > elapsed {
+		duration = elapsed
+	}
+	if duration < 0 {
+		duration = 0
+	}
+	return duration
 }
 
 // Name returns "profile" to match the name of the CPU profiler in pprof.
@@ -126,15 +512,18 @@ func (p *CPUProfiler) Desc() string {
 
 // Count returns the number of execution stacks currently recorded in p.
 func (p *CPUProfiler) Count() int {
-	p.mutex.Lock()
-	n := len(p.counts)
-	p.mutex.Unlock()
-	return n
+	return p.counts.len()
 }
 
 // SampleType returns the set of value types present in samples recorded by the
 // CPU profiler.
 func (p *CPUProfiler) SampleType() []*profile.ValueType {
+	if p.wall {
+		return []*profile.ValueType{
+			{Type: "samples", Unit: "count"},
+			{Type: "wall", Unit: "nanoseconds"},
+		}
+	}
 	return []*profile.ValueType{
 		{Type: "samples", Unit: "count"},
 		{Type: "cpu", Unit: "nanoseconds"},
@@ -156,9 +545,11 @@ func (p *CPUProfiler) NewHandler(sampleRate float64) http.Handler {
 
 		if seconds := r.FormValue("seconds"); seconds != "" {
 			n, err := strconv.ParseInt(seconds, 10, 64)
-			if err == nil && n > 0 {
-				duration = time.Duration(n) * time.Second
+			if err != nil || n <= 0 {
+				serveError(w, http.StatusBadRequest, `invalid value for "seconds" - must be a positive integer`)
+				return
 			}
+			duration = time.Duration(n) * time.Second
 		}
 
 		ctx := r.Context()
@@ -181,13 +572,21 @@ func (p *CPUProfiler) NewHandler(sampleRate float64) http.Handler {
 		case <-ctx.Done():
 		}
 		timer.Stop()
-		serveProfile(w, p.StopProfile(sampleRate))
+		serveProfile(w, r, p.StopProfile(sampleRate))
 	})
 }
 
 // NewFunctionListener returns a function listener suited to record CPU timings
 // of calls to the function passed as argument.
 func (p *CPUProfiler) NewFunctionListener(def api.FunctionDefinition) experimental.FunctionListener {
+	lstn := p.newFunctionListener(def)
+	if p.startup != nil && isEntryFunction(def) {
+		return startupListener{p, lstn}
+	}
+	return lstn
+}
+
+func (p *CPUProfiler) newFunctionListener(def api.FunctionDefinition) experimental.FunctionListener {
 	name := def.Name()
 	if len(p.p.onlyFunctions) > 0 {
 		_, keep := p.p.onlyFunctions[name]
@@ -196,7 +595,7 @@ func (p *CPUProfiler) NewFunctionListener(def api.FunctionDefinition) experiment
 		}
 	}
 	_, skip := p.p.filteredFunctions[name]
-	if skip {
+	if skip || !p.p.instrumented(def) {
 		return nil
 	}
 	return profilingListener{p.p, cpuProfiler{p}}
@@ -205,49 +604,107 @@ func (p *CPUProfiler) NewFunctionListener(def api.FunctionDefinition) experiment
 type cpuProfiler struct{ *CPUProfiler }
 
 func (p cpuProfiler) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64, si experimental.StackIterator) {
-	var frame cpuTimeFrame
-	p.mutex.Lock()
+	frame := cpuTimeFrame{def: def}
+	t := p.stacks.get(mod)
+	t.mutex.Lock()
 
-	if p.counts != nil {
+	call := p.callProfile(ctx)
+	if call == nil {
+		call = p.startupCall()
+	}
+	if p.active.Load() || call != nil {
 		start := p.time()
 		trace := stackTrace{}
 
-		if i := len(p.traces); i > 0 {
+		if i := len(t.traces); i > 0 {
 			i--
-			trace = p.traces[i]
-			p.traces = p.traces[:i]
+			trace = t.traces[i]
+			t.traces = t.traces[:i]
 		}
 
-		frame = cpuTimeFrame{
-			start: start,
-			trace: makeStackTrace(trace, si),
-		}
+		frame.start = start
+		frame.trace = makeStackTrace(ctx, trace, p.p.threads.lookup(mod), si, p.p.maxFrames)
+		frame.call = call
 	}
 
-	p.mutex.Unlock()
-	p.frames = append(p.frames, frame)
+	t.frames = append(t.frames, frame)
+	t.mutex.Unlock()
 }
 
 func (p cpuProfiler) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64) {
-	i := len(p.frames) - 1
-	f := p.frames[i]
-	p.frames = p.frames[:i]
+	t := p.stacks.get(mod)
+	t.mutex.Lock()
+	if len(t.frames) == 0 {
+		// The call was suspended (see Suspend) and returns or is aborted
+		// without being resumed, or started before the listener.
+		t.mutex.Unlock()
+		return
+	}
+	i := len(t.frames) - 1
+	if t.frames[i].def != def {
+		i = returningFrame(len(t.frames), def, func(i int) api.FunctionDefinition { return t.frames[i].def })
+		// The traces of the calls which were unwound are reused.
+		for _, f := range t.frames[i+1:] {
+			if f.start != 0 {
+				t.traces = append(t.traces, f.trace)
+			}
+		}
+	}
+	f := t.frames[i]
+	t.frames = t.frames[:i]
 
 	if f.start != 0 {
-		duration := p.time() - f.start
-		if i := len(p.frames); i > 0 {
-			p.frames[i-1].sub += duration
+		now := p.time()
+		duration := now - f.start
+		if i := len(t.frames); i > 0 {
+			t.frames[i-1].sub += duration
 		}
 		duration -= f.sub
-		p.mutex.Lock()
-		if p.counts != nil {
-			p.counts.observe(f.trace, duration)
+		// In wall-clock mode, the samples are recorded by the timer.
+		record := p.active.Load() && !p.wall
+		if record || f.call != nil {
+			if trace, duration, ok := p.p.hookSample(p.Name(), f.trace, duration); ok {
+				if record {
+					p.counts.observe(mod, trace, p.profiledTime(duration, now), p.p.maxStacks)
+				}
+				if f.call != nil {
+					f.call.counts.observe(mod, trace, duration, p.p.maxStacks)
+				}
+			}
 		}
-		p.mutex.Unlock()
-		p.traces = append(p.traces, f.trace)
+		t.traces = append(t.traces, f.trace)
 	}
+	t.mutex.Unlock()
 }
 
 func (p cpuProfiler) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
 	p.After(ctx, mod, def, nil)
 }
+
+// startupListener observes the return of the entry function of the module to
+// end the recording of its start (see RecordStartup), and forwards the calls to
+// the listener of the CPU profiler for the function, if any.
+type startupListener struct {
+	*CPUProfiler
+	lstn experimental.FunctionListener
+}
+
+func (l startupListener) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, params []uint64, si experimental.StackIterator) {
+	if l.lstn != nil {
+		l.lstn.Before(ctx, mod, def, params, si)
+	}
+}
+
+func (l startupListener) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, results []uint64) {
+	if l.lstn != nil {
+		l.lstn.After(ctx, mod, def, results)
+	}
+	l.startup.end.CompareAndSwap(0, time.Now().UnixNano())
+}
+
+func (l startupListener) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, err error) {
+	if l.lstn != nil {
+		l.lstn.Abort(ctx, mod, def, err)
+	}
+	l.startup.end.CompareAndSwap(0, time.Now().UnixNano())
+}
@@ -2,6 +2,7 @@ package wzprof
 
 import (
 	"context"
+	"sync"
 	"testing"
 
 	"github.com/tetratelabs/wazero/api"
@@ -10,20 +11,20 @@ import (
 )
 
 func BenchmarkCPUProfilerOn(b *testing.B) {
-	p := ProfilingFor(nil).CPUProfiler()
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler()
 	p.StartProfile()
 	benchmarkFunctionListener(b, p)
 }
 
 func BenchmarkCPUProfilerOff(b *testing.B) {
-	p := ProfilingFor(nil).CPUProfiler()
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler()
 	benchmarkFunctionListener(b, p)
 }
 
 func TestCPUProfilerTime(t *testing.T) {
 	currentTime := int64(0)
 
-	p := ProfilingFor(nil).CPUProfiler(
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(
 		TimeFunc(func() int64 { return currentTime }),
 	)
 
@@ -87,17 +88,18 @@ func TestCPUProfilerTime(t *testing.T) {
 	currentTime = t5
 	f0.After(ctx, module, def0, nil)
 
-	trace0 := makeStackTraceFromFrames(stack0)
-	trace1 := makeStackTraceFromFrames(stack1)
-	trace2 := makeStackTraceFromFrames(stack2)
+	thread := p.p.threads.lookup(module)
+	trace0 := makeStackTraceFromFrames(thread, stack0)
+	trace1 := makeStackTraceFromFrames(thread, stack1)
+	trace2 := makeStackTraceFromFrames(thread, stack2)
 
 	d2 := t3 - t2
 	d1 := t4 - (t1 + d2)
 	d0 := t5 - (t0 + d1 + d2)
 
-	assertStackCount(t, p.counts, trace0, 1, d0)
-	assertStackCount(t, p.counts, trace1, 1, d1)
-	assertStackCount(t, p.counts, trace2, 1, d2)
+	assertStackCount(t, p.counts.merge(false), trace0, 1, d0)
+	assertStackCount(t, p.counts.merge(false), trace1, 1, d1)
+	assertStackCount(t, p.counts.merge(false), trace2, 1, d2)
 }
 
 func assertStackCount(t *testing.T, counts stackCounterMap, trace stackTrace, count, total int64) {
@@ -113,6 +115,530 @@ func assertStackCount(t *testing.T, counts stackCounterMap, trace stackTrace, co
 	}
 }
 
-func makeStackTraceFromFrames(stackFrames []experimental.StackFrame) stackTrace {
-	return makeStackTrace(stackTrace{}, experimental.NewStackIterator(stackFrames...))
+func TestCPUProfilerThreads(t *testing.T) {
+	currentTime := int64(0)
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(
+		HostTime(true),
+		TimeFunc(func() int64 { return currentTime }),
+	)
+
+	// With wasi-threads, each thread runs in its own module instance.
+	newThread := func() *wazerotest.Module {
+		return wazerotest.NewModule(nil,
+			wazerotest.NewFunction(func(context.Context, api.Module) {}),
+		)
+	}
+	thread1 := newThread()
+	thread2 := newThread()
+
+	def := thread1.Function(0).Definition()
+	listener := p.NewFunctionListener(def)
+	stack1 := []experimental.StackFrame{{Function: thread1.Function(0)}}
+	stack2 := []experimental.StackFrame{{Function: thread2.Function(0)}}
+	ctx := context.Background()
+
+	p.StartProfile()
+
+	// Interleave the calls of both threads, which would corrupt the call
+	// stacks if they were not tracked separately.
+	currentTime = 1
+	listener.Before(ctx, thread1, def, nil, experimental.NewStackIterator(stack1...))
+	currentTime = 2
+	listener.Before(ctx, thread2, def, nil, experimental.NewStackIterator(stack2...))
+	currentTime = 11
+	listener.After(ctx, thread1, def, nil)
+	currentTime = 32
+	listener.After(ctx, thread2, def, nil)
+
+	assertStackCount(t, p.counts.merge(false), makeStackTraceFromFrames(p.p.threads.lookup(thread1), stack1), 1, 10)
+	assertStackCount(t, p.counts.merge(false), makeStackTraceFromFrames(p.p.threads.lookup(thread2), stack2), 1, 30)
+
+	prof := p.StopProfile(1)
+	if prof.DefaultSampleType != "cpu" {
+		t.Errorf("wrong default sample type: want=cpu got=%q", prof.DefaultSampleType)
+	}
+	if len(prof.Sample) != 2 {
+		t.Fatalf("wrong number of samples: want=2 got=%d", len(prof.Sample))
+	}
+	threads := map[string]int64{}
+	for _, sample := range prof.Sample {
+		labels := sample.Label["thread"]
+		if len(labels) != 1 {
+			t.Fatalf("sample is missing the thread label: %v", sample.Label)
+		}
+		threads[labels[0]] = sample.Value[1]
+	}
+	if threads["1"] != 10 || threads["2"] != 30 {
+		t.Errorf("wrong cpu time per thread: %v", threads)
+	}
+}
+
+func TestCPUProfilerInstances(t *testing.T) {
+	currentTime := int64(0)
+
+	p := ProfilingFor(nil, AllowUnprepared(true), LabelInstances(true)).CPUProfiler(
+		HostTime(true),
+		TimeFunc(func() int64 { return currentTime }),
+	)
+
+	// Workers instantiate the same compiled module multiple times, with a
+	// distinct name for each instance.
+	newWorker := func(name string) *wazerotest.Module {
+		m := wazerotest.NewModule(nil,
+			wazerotest.NewFunction(func(context.Context, api.Module) {}),
+		)
+		m.ModuleName = name
+		return m
+	}
+	workers := []*wazerotest.Module{newWorker("worker-1"), newWorker("worker-2"), newWorker("")}
+
+	def := workers[0].Function(0).Definition()
+	listener := p.NewFunctionListener(def)
+	ctx := context.Background()
+
+	p.StartProfile()
+	for i, worker := range workers {
+		stack := []experimental.StackFrame{{Function: worker.Function(0)}}
+		listener.Before(ctx, worker, def, nil, experimental.NewStackIterator(stack...))
+		currentTime += int64(i+1) * 10
+		listener.After(ctx, worker, def, nil)
+	}
+
+	prof := p.StopProfile(1)
+	if len(prof.Sample) != len(workers) {
+		t.Fatalf("wrong number of samples: want=%d got=%d", len(workers), len(prof.Sample))
+	}
+	instances := map[string]int64{}
+	for _, sample := range prof.Sample {
+		name := ""
+		if labels := sample.Label["instance"]; len(labels) == 1 {
+			name = labels[0]
+		}
+		instances[name] = sample.Value[1]
+	}
+	if instances["worker-1"] != 10 || instances["worker-2"] != 20 || instances[""] != 30 {
+		t.Errorf("wrong cpu time per instance: %v", instances)
+	}
+}
+
+func TestCPUProfilerUnwoundCalls(t *testing.T) {
+	currentTime := int64(1)
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(
+		HostTime(true),
+		TimeFunc(func() int64 { return currentTime }),
+	)
+
+	module := wazerotest.NewModule(nil,
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+	)
+	def0 := module.Function(0).Definition()
+	def1 := module.Function(1).Definition()
+	f0 := p.NewFunctionListener(def0)
+	f1 := p.NewFunctionListener(def1)
+	stack0 := []experimental.StackFrame{{Function: module.Function(0)}}
+	stack1 := []experimental.StackFrame{{Function: module.Function(0)}, {Function: module.Function(1)}}
+	ctx := context.Background()
+
+	p.StartProfile()
+
+	// The call to the second function throws an exception caught by the
+	// first function, it never returns.
+	f0.Before(ctx, module, def0, nil, experimental.NewStackIterator(stack0...))
+	currentTime += 10
+	f1.Before(ctx, module, def1, nil, experimental.NewStackIterator(stack1...))
+	currentTime += 10
+	f0.After(ctx, module, def0, nil)
+
+	if n := len(p.stacks.get(module).frames); n != 0 {
+		t.Errorf("frames of the unwound calls left on the stack: %d", n)
+	}
+	thread := p.p.threads.lookup(module)
+	assertStackCount(t, p.counts.merge(false), makeStackTraceFromFrames(thread, stack0), 1, 20)
+	if n := p.counts.len(); n != 1 {
+		t.Errorf("wrong number of stacks: want=1 got=%d", n)
+	}
+}
+
+func TestCPUProfilerSuspend(t *testing.T) {
+	currentTime := int64(1)
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(
+		HostTime(true),
+		TimeFunc(func() int64 { return currentTime }),
+	)
+
+	module := wazerotest.NewModule(nil,
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+	)
+	def0 := module.Function(0).Definition()
+	def1 := module.Function(1).Definition()
+	def2 := module.Function(2).Definition()
+	f0 := p.NewFunctionListener(def0)
+	f1 := p.NewFunctionListener(def1)
+	f2 := p.NewFunctionListener(def2)
+	stack0 := []experimental.StackFrame{{Function: module.Function(0)}}
+	stack1 := []experimental.StackFrame{{Function: module.Function(0)}, {Function: module.Function(1)}}
+	stack2 := []experimental.StackFrame{{Function: module.Function(2)}}
+	ctx := context.Background()
+
+	p.StartProfile()
+
+	// The second function is a host function suspending its call, during
+	// which the embedder calls the third function.
+	f0.Before(ctx, module, def0, nil, experimental.NewStackIterator(stack0...))
+	currentTime += 10
+	f1.Before(ctx, module, def1, nil, experimental.NewStackIterator(stack1...))
+	currentTime++
+	resume := p.Suspend(module)
+	f2.Before(ctx, module, def2, nil, experimental.NewStackIterator(stack2...))
+	currentTime += 100
+	f2.After(ctx, module, def2, nil)
+	resume()
+	currentTime += 2
+	f1.After(ctx, module, def1, nil)
+	currentTime += 3
+	f0.After(ctx, module, def0, nil)
+
+	thread := p.p.threads.lookup(module)
+	samples := p.counts.merge(false)
+	assertStackCount(t, samples, makeStackTraceFromFrames(thread, stack0), 1, 13)
+	assertStackCount(t, samples, makeStackTraceFromFrames(thread, stack1), 1, 3)
+	assertStackCount(t, samples, makeStackTraceFromFrames(thread, stack2), 1, 100)
+	if n := p.counts.len(); n != 3 {
+		t.Errorf("wrong number of stacks: want=3 got=%d", n)
+	}
+}
+
+func TestCPUProfilerSuspendAborted(t *testing.T) {
+	currentTime := int64(1)
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(
+		HostTime(true),
+		TimeFunc(func() int64 { return currentTime }),
+	)
+
+	module := wazerotest.NewModule(nil,
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+	)
+	def0 := module.Function(0).Definition()
+	def1 := module.Function(1).Definition()
+	f0 := p.NewFunctionListener(def0)
+	f1 := p.NewFunctionListener(def1)
+	stack0 := []experimental.StackFrame{{Function: module.Function(0)}}
+	stack1 := []experimental.StackFrame{{Function: module.Function(1)}}
+	ctx := context.Background()
+
+	p.StartProfile()
+
+	// The call suspended by the host function is canceled, it aborts without
+	// being resumed.
+	f0.Before(ctx, module, def0, nil, experimental.NewStackIterator(stack0...))
+	currentTime += 10
+	_ = p.Suspend(module)
+	f1.Before(ctx, module, def1, nil, experimental.NewStackIterator(stack1...))
+	currentTime += 100
+	f1.After(ctx, module, def1, nil)
+	f0.Abort(ctx, module, def0, context.Canceled)
+
+	thread := p.p.threads.lookup(module)
+	samples := p.counts.merge(false)
+	assertStackCount(t, samples, makeStackTraceFromFrames(thread, stack1), 1, 100)
+	if n := p.counts.len(); n != 1 {
+		t.Errorf("wrong number of stacks: want=1 got=%d", n)
+	}
+}
+
+func TestCPUProfilerConcurrentThreads(t *testing.T) {
+	const (
+		numThreads = 8
+		numCalls   = 1000
+	)
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(
+		HostTime(true),
+		TimeFunc(func() int64 { return 1 }),
+	)
+	p.StartProfile()
+
+	// The samples of each thread are recorded in separate shards, which are
+	// merged when the profile is stopped.
+	var wg sync.WaitGroup
+	for i := 0; i < numThreads; i++ {
+		thread := wazerotest.NewModule(nil,
+			wazerotest.NewFunction(func(context.Context, api.Module) {}),
+		)
+		def := thread.Function(0).Definition()
+		listener := p.NewFunctionListener(def)
+		stack := []experimental.StackFrame{{Function: thread.Function(0)}}
+
+		wg.Add(1)
+		go func() {
+			defer wg.Done()
+			ctx := context.Background()
+			for j := 0; j < numCalls; j++ {
+				listener.Before(ctx, thread, def, nil, experimental.NewStackIterator(stack...))
+				listener.After(ctx, thread, def, nil)
+			}
+		}()
+	}
+	wg.Wait()
+
+	prof := p.StopProfile(1)
+	if len(prof.Sample) != numThreads {
+		t.Fatalf("wrong number of samples: want=%d got=%d", numThreads, len(prof.Sample))
+	}
+	for _, sample := range prof.Sample {
+		if sample.Value[0] != numCalls {
+			t.Errorf("wrong number of calls: want=%d got=%d", numCalls, sample.Value[0])
+		}
+	}
+}
+
+func TestCPUProfilerWallTime(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(
+		WallTime(true),
+		TimeFunc(func() int64 { return 1 }),
+	)
+
+	module := wazerotest.NewModule(nil,
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+	)
+
+	f0 := p.NewFunctionListener(module.Function(0).Definition())
+	f1 := p.NewFunctionListener(module.Function(1).Definition())
+
+	stack0 := []experimental.StackFrame{
+		{Function: module.Function(0), PC: 1},
+	}
+	stack1 := []experimental.StackFrame{
+		{Function: module.Function(0), PC: 1},
+		{Function: module.Function(1), PC: 2},
+	}
+
+	def0 := stack0[0].Function.Definition()
+	def1 := stack1[1].Function.Definition()
+	ctx := context.Background()
+
+	// Drive the timer manually instead of calling StartProfile.
+	p.active.Store(true)
+
+	f0.Before(ctx, module, def0, nil, experimental.NewStackIterator(stack0...))
+	p.observeWallTime(10)
+	f1.Before(ctx, module, def1, nil, experimental.NewStackIterator(stack1...))
+	p.observeWallTime(10)
+	p.observeWallTime(10)
+	f1.After(ctx, module, def1, nil)
+	p.observeWallTime(10)
+	f0.After(ctx, module, def0, nil)
+	p.observeWallTime(10)
+
+	thread := p.p.threads.lookup(module)
+	assertStackCount(t, p.counts.merge(false), makeStackTraceFromFrames(thread, stack0), 2, 20)
+	assertStackCount(t, p.counts.merge(false), makeStackTraceFromFrames(thread, stack1), 2, 20)
+
+	if n := p.counts.len(); n != 2 {
+		t.Errorf("wrong number of stacks: want=2 got=%d", n)
+	}
+}
+
+// listenedFunction is an api.Function invoking the listener of the function
+// around its calls, like the runtime does for functions of a module.
+type listenedFunction struct {
+	api.Function
+	module   *wazerotest.Module
+	listener experimental.FunctionListener
+	call     func(ctx context.Context)
+}
+
+func (f *listenedFunction) Call(ctx context.Context, params ...uint64) ([]uint64, error) {
+	def := f.Definition()
+	stack := []experimental.StackFrame{{Function: f.Function}}
+	f.listener.Before(ctx, f.module, def, params, experimental.NewStackIterator(stack...))
+	f.call(ctx)
+	f.listener.After(ctx, f.module, def, nil)
+	return []uint64{42}, nil
+}
+
+func TestCPUProfilerProfileCall(t *testing.T) {
+	currentTime := int64(1)
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(
+		HostTime(true),
+		TimeFunc(func() int64 { return currentTime }),
+	)
+
+	newFunction := func() *listenedFunction {
+		module := wazerotest.NewModule(nil,
+			wazerotest.NewFunction(func(context.Context, api.Module) {}),
+		)
+		return &listenedFunction{
+			Function: module.Function(0),
+			module:   module,
+			listener: p.NewFunctionListener(module.Function(0).Definition()),
+		}
+	}
+
+	// A call made concurrently by another invocation, on another thread, is
+	// not part of the profile of the call.
+	other := newFunction()
+	other.call = func(context.Context) {}
+	fn := newFunction()
+	fn.call = func(ctx context.Context) {
+		currentTime += 10
+		_, _ = other.Call(context.Background())
+	}
+
+	prof, results, err := p.ProfileCall(context.Background(), fn)
+	if err != nil {
+		t.Fatal(err)
+	}
+	if len(results) != 1 || results[0] != 42 {
+		t.Errorf("wrong results: %v", results)
+	}
+	if len(prof.Sample) != 1 {
+		t.Fatalf("wrong number of samples: want=1 got=%d", len(prof.Sample))
+	}
+	if v := prof.Sample[0].Value; v[0] != 1 || v[1] != 10 {
+		t.Errorf("wrong sample values: want=[1 10] got=%v", v)
+	}
+
+	// The profile of the profiler was not started by the call.
+	if n := p.Count(); n != 0 {
+		t.Errorf("wrong number of stacks recorded by the profiler: want=0 got=%d", n)
+	}
+}
+
+func makeStackTraceFromFrames(thread uint32, stackFrames []experimental.StackFrame) stackTrace {
+	return makeStackTrace(context.Background(), stackTrace{}, thread, experimental.NewStackIterator(stackFrames...), defaultMaxFrames)
+}
+
+func TestCPUProfilerWindow(t *testing.T) {
+	currentTime := int64(0)
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(
+		HostTime(true),
+		TimeFunc(func() int64 { return currentTime }),
+	)
+
+	module := wazerotest.NewModule(nil,
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+	)
+	def0 := module.Function(0).Definition()
+	def1 := module.Function(1).Definition()
+	f0 := p.NewFunctionListener(def0)
+	f1 := p.NewFunctionListener(def1)
+	stack0 := []experimental.StackFrame{{Function: module.Function(0)}}
+	stack1 := []experimental.StackFrame{{Function: module.Function(0)}, {Function: module.Function(1)}}
+	ctx := context.Background()
+
+	values := func(samples stackCounterMap) map[int]int64 {
+		v := make(map[int]int64)
+		for _, sc := range samples {
+			v[sc.stack.len()] = sc.total()
+		}
+		return v
+	}
+
+	// The main function of the module is still running when the profile
+	// is stopped.
+	p.StartProfile()
+	f0.Before(ctx, module, def0, nil, experimental.NewStackIterator(stack0...))
+	currentTime = 10
+	samples := p.counts.merge(false)
+	p.observeRunningCalls(samples, currentTime)
+	if v := values(samples); v[1] != 10 {
+		t.Errorf("wrong time of the running call: want=10 got=%v", v)
+	}
+	p.StopProfile(1)
+
+	// The next profile only accounts for the time spent after it started.
+	currentTime = 20
+	p.StartProfile()
+	currentTime = 25
+	f1.Before(ctx, module, def1, nil, experimental.NewStackIterator(stack1...))
+	currentTime = 30
+	f1.After(ctx, module, def1, nil)
+	currentTime = 50
+	samples = p.counts.merge(false)
+	p.observeRunningCalls(samples, currentTime)
+	if v := values(samples); v[1] != 25 || v[2] != 5 {
+		t.Errorf("wrong times of the profile: want=map[1:25 2:5] got=%v", v)
+	}
+
+	if prof := p.StopProfile(1); len(prof.Sample) != 2 {
+		t.Errorf("wrong number of samples: want=2 got=%d", len(prof.Sample))
+	}
+}
+
+func TestCPUProfilerStartup(t *testing.T) {
+	currentTime := int64(0)
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(
+		HostTime(true),
+		TimeFunc(func() int64 { return currentTime }),
+		RecordStartup(true),
+	)
+
+	start := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	start.ExportNames = []string{"_start"}
+	module := wazerotest.NewModule(nil,
+		start,
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+	)
+	def0 := module.Function(0).Definition()
+	def1 := module.Function(1).Definition()
+	f0 := p.NewFunctionListener(def0)
+	f1 := p.NewFunctionListener(def1)
+	stack0 := []experimental.StackFrame{{Function: module.Function(0)}}
+	stack1 := []experimental.StackFrame{{Function: module.Function(0)}, {Function: module.Function(1)}}
+	ctx := context.Background()
+
+	f0.Before(ctx, module, def0, nil, experimental.NewStackIterator(stack0...))
+	f1.Before(ctx, module, def1, nil, experimental.NewStackIterator(stack1...))
+	currentTime += 10
+	f1.After(ctx, module, def1, nil)
+	currentTime += 5
+	if prof := p.StartupProfile(1); prof != nil {
+		t.Error("startup profile returned before the entry function returned")
+	}
+	f0.After(ctx, module, def0, nil)
+
+	// The calls made after the start are not part of the startup profile,
+	// which is recorded without starting the profile of the profiler.
+	f1.Before(ctx, module, def1, nil, experimental.NewStackIterator(stack1...))
+	currentTime += 100
+	f1.After(ctx, module, def1, nil)
+
+	prof := p.StartupProfile(1)
+	if prof == nil {
+		t.Fatal("no startup profile")
+	}
+	values := make(map[int]int64)
+	for _, s := range prof.Sample {
+		values[len(s.Location)] = s.Value[1]
+	}
+	if values[1] != 5 || values[2] != 10 {
+		t.Errorf("wrong times of the startup profile: want=map[1:5 2:10] got=%v", values)
+	}
+	if n := p.Count(); n != 0 {
+		t.Errorf("wrong number of stacks recorded by the profiler: want=0 got=%d", n)
+	}
+
+	// The return of the entry function is observed when the profiler is
+	// sampled.
+	p = ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(RecordStartup(true))
+	f0 = NewSampler(0.01).Sample(p).NewFunctionListener(def0)
+	f0.Before(ctx, module, def0, nil, experimental.NewStackIterator(stack0...))
+	f0.After(ctx, module, def0, nil)
+	if prof := p.StartupProfile(1); prof == nil {
+		t.Error("no startup profile of the sampled profiler")
+	}
 }
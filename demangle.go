@@ -0,0 +1,818 @@
+package wzprof
+
+import (
+	"math"
+	"strconv"
+	"strings"
+	"unicode/utf8"
+)
+
+// demangle returns the human-readable form of a mangled symbol name, and a
+// boolean indicating whether the name was mangled with one of the supported
+// schemes: the legacy and v0 schemes of Rust, and the Itanium C++ ABI. When
+// stripTemplates is true, the template arguments of C++ names are omitted.
+//
+// Toolchains usually emit demangled names in DWARF, but the names found in the
+// wasm name section are the linkage names of functions, which are mangled for
+// languages like Rust or C++.
+func demangle(name string, stripTemplates bool) (string, bool) {
+	if s, ok := demangleRust(name); ok {
+		return s, true
+	}
+	return demangleItanium(name, stripTemplates)
+}
+
+// demangleRust demangles symbol names of Rust programs, using either the
+// legacy mangling scheme (derived from the Itanium C++ ABI) or the v0 scheme.
+//
+// The output matches the alternate format of rustc-demangle, omitting the
+// hashes and disambiguators of crates.
+func demangleRust(name string) (string, bool) {
+	switch {
+	case strings.HasPrefix(name, "_R"):
+		return demangleRustV0(name[2:])
+	case strings.HasPrefix(name, "__R"):
+		return demangleRustV0(name[3:])
+	}
+	return demangleRustLegacy(name)
+}
+
+func demangleRustLegacy(name string) (string, bool) {
+	var s string
+	switch {
+	case strings.HasPrefix(name, "_ZN"):
+		s = name[3:]
+	case strings.HasPrefix(name, "ZN"):
+		s = name[2:]
+	case strings.HasPrefix(name, "__ZN"):
+		s = name[4:]
+	default:
+		return "", false
+	}
+
+	var elems []string
+	for {
+		if s == "" {
+			return "", false
+		}
+		if s[0] == 'E' {
+			s = s[1:]
+			break
+		}
+		i, n := 0, 0
+		for i < len(s) && isDecimalDigit(s[i]) {
+			n = 10*n + int(s[i]-'0')
+			i++
+			if n > len(s) {
+				return "", false
+			}
+		}
+		if n == 0 || i+n > len(s) {
+			return "", false
+		}
+		elems = append(elems, s[i:i+n])
+		s = s[i+n:]
+	}
+	// Anything else than a suffix added by the compiler (e.g. ".llvm.1234")
+	// after the path means that this is not a Rust symbol; C++ symbols encode
+	// the function parameters there.
+	if len(elems) == 0 || (s != "" && s[0] != '.') {
+		return "", false
+	}
+	if len(elems) > 1 && isRustHash(elems[len(elems)-1]) {
+		elems = elems[:len(elems)-1]
+	}
+
+	var b strings.Builder
+	for i, elem := range elems {
+		if i > 0 {
+			b.WriteString("::")
+		}
+		if !unescapeRustLegacy(&b, elem) {
+			return "", false
+		}
+	}
+	return b.String(), true
+}
+
+// isRustHash returns true if s is the hash that rustc appends to the paths of
+// symbols in the legacy mangling scheme, for example "h4de46d8bb8a6ffe1".
+func isRustHash(s string) bool {
+	if len(s) != 17 || s[0] != 'h' {
+		return false
+	}
+	for i := 1; i < len(s); i++ {
+		if !isHexDigit(s[i]) {
+			return false
+		}
+	}
+	return true
+}
+
+func unescapeRustLegacy(b *strings.Builder, s string) bool {
+	if strings.HasPrefix(s, "_$") {
+		s = s[1:]
+	}
+	for s != "" {
+		switch {
+		case s[0] == '$':
+			end := strings.IndexByte(s[1:], '$')
+			if end < 0 {
+				return false
+			}
+			esc := s[1 : 1+end]
+			s = s[2+end:]
+			switch esc {
+			case "SP":
+				b.WriteByte('@')
+			case "BP":
+				b.WriteByte('*')
+			case "RF":
+				b.WriteByte('&')
+			case "LT":
+				b.WriteByte('<')
+			case "GT":
+				b.WriteByte('>')
+			case "LP":
+				b.WriteByte('(')
+			case "RP":
+				b.WriteByte(')')
+			case "C":
+				b.WriteByte(',')
+			default:
+				if len(esc) < 2 || esc[0] != 'u' {
+					return false
+				}
+				r, err := strconv.ParseUint(esc[1:], 16, 32)
+				if err != nil || !utf8.ValidRune(rune(r)) {
+					return false
+				}
+				b.WriteRune(rune(r))
+			}
+		case strings.HasPrefix(s, ".."):
+			b.WriteString("::")
+			s = s[2:]
+		default:
+			b.WriteByte(s[0])
+			s = s[1:]
+		}
+	}
+	return true
+}
+
+// demangleMaxDepth bounds the recursion of the demangler parsers, references
+// to previous parts of symbols allow crafting names which would otherwise
+// expand indefinitely.
+const demangleMaxDepth = 500
+
+// demangleError is used as panic value to abort the demangling of invalid
+// symbols.
+type demangleError struct{}
+
+// rustDemangler is a parser of the v0 mangling scheme, see
+// https://doc.rust-lang.org/rustc/symbol-mangling/v0.html for the grammar.
+type rustDemangler struct {
+	sym   string
+	pos   int
+	depth int
+	out   []byte
+}
+
+func demangleRustV0(sym string) (s string, ok bool) {
+	// Drop suffixes added by the compiler, which are not part of the grammar.
+	if i := strings.IndexByte(sym, '.'); i >= 0 {
+		sym = sym[:i]
+	}
+	// A leading digit would be the encoding version, only the implicit
+	// version 0 is defined.
+	if sym == "" || isDecimalDigit(sym[0]) {
+		return "", false
+	}
+
+	defer func() {
+		if r := recover(); r != nil {
+			if _, isDemangleError := r.(demangleError); !isDemangleError {
+				panic(r)
+			}
+			s, ok = "", false
+		}
+	}()
+
+	d := &rustDemangler{sym: sym}
+	d.path(true)
+	if d.pos < len(d.sym) {
+		// The instantiating crate is not part of the output.
+		mark := len(d.out)
+		d.path(false)
+		d.out = d.out[:mark]
+	}
+	if d.pos != len(d.sym) {
+		d.fail()
+	}
+	return string(d.out), true
+}
+
+func (d *rustDemangler) fail() {
+	panic(demangleError{})
+}
+
+func (d *rustDemangler) enter() {
+	if d.depth++; d.depth > demangleMaxDepth {
+		d.fail()
+	}
+}
+
+func (d *rustDemangler) leave() {
+	d.depth--
+}
+
+func (d *rustDemangler) peek() byte {
+	if d.pos < len(d.sym) {
+		return d.sym[d.pos]
+	}
+	return 0
+}
+
+func (d *rustDemangler) next() byte {
+	if d.pos >= len(d.sym) {
+		d.fail()
+	}
+	c := d.sym[d.pos]
+	d.pos++
+	return c
+}
+
+func (d *rustDemangler) eat(c byte) bool {
+	if d.peek() != c {
+		return false
+	}
+	d.pos++
+	return true
+}
+
+func (d *rustDemangler) print(s string) {
+	d.out = append(d.out, s...)
+}
+
+// integer62 parses a base-62-number, where "_" encodes 0 and the digits
+// followed by "_" encode their value plus one.
+func (d *rustDemangler) integer62() uint64 {
+	if d.eat('_') {
+		return 0
+	}
+	var x uint64
+	for !d.eat('_') {
+		c := d.next()
+		var v uint64
+		switch {
+		case isDecimalDigit(c):
+			v = uint64(c - '0')
+		case 'a' <= c && c <= 'z':
+			v = uint64(c-'a') + 10
+		case 'A' <= c && c <= 'Z':
+			v = uint64(c-'A') + 36
+		default:
+			d.fail()
+		}
+		if x > (math.MaxUint64-v)/62 {
+			d.fail()
+		}
+		x = 62*x + v
+	}
+	if x == math.MaxUint64 {
+		d.fail()
+	}
+	return x + 1
+}
+
+// optInteger62 parses a base-62-number prefixed by tag, returning 0 if the
+// number is absent and the value plus one otherwise.
+func (d *rustDemangler) optInteger62(tag byte) uint64 {
+	if !d.eat(tag) {
+		return 0
+	}
+	x := d.integer62()
+	if x == math.MaxUint64 {
+		d.fail()
+	}
+	return x + 1
+}
+
+func (d *rustDemangler) decimal() int {
+	c := d.next()
+	if !isDecimalDigit(c) {
+		d.fail()
+	}
+	if c == '0' {
+		return 0
+	}
+	x := int(c - '0')
+	for isDecimalDigit(d.peek()) {
+		x = 10*x + int(d.next()-'0')
+		if x > len(d.sym) {
+			d.fail()
+		}
+	}
+	return x
+}
+
+// ident parses an undisambiguated-identifier.
+func (d *rustDemangler) ident() (name string, punycode bool) {
+	punycode = d.eat('u')
+	n := d.decimal()
+	d.eat('_')
+	if n > len(d.sym)-d.pos {
+		d.fail()
+	}
+	name = d.sym[d.pos : d.pos+n]
+	d.pos += n
+	return name, punycode
+}
+
+func (d *rustDemangler) printIdent(name string, punycode bool) {
+	if punycode {
+		s, ok := decodeRustPunycode(name)
+		if !ok {
+			d.fail()
+		}
+		name = s
+	}
+	d.print(name)
+}
+
+// backref parses the position of a backreference, and calls parse to print
+// the production found at that position.
+func (d *rustDemangler) backref(parse func()) {
+	start := d.pos - 1 // position of the 'B' tag
+	i := d.integer62()
+	if i >= uint64(start) {
+		d.fail()
+	}
+	d.enter()
+	defer d.leave()
+	pos := d.pos
+	d.pos = int(i)
+	parse()
+	d.pos = pos
+}
+
+func (d *rustDemangler) path(inValue bool) {
+	d.enter()
+	defer d.leave()
+
+	switch tag := d.next(); tag {
+	case 'C':
+		d.optInteger62('s')
+		d.printIdent(d.ident())
+	case 'M':
+		d.implPath()
+		d.print("<")
+		d.typ()
+		d.print(">")
+	case 'X':
+		d.implPath()
+		d.print("<")
+		d.typ()
+		d.print(" as ")
+		d.path(false)
+		d.print(">")
+	case 'Y':
+		d.print("<")
+		d.typ()
+		d.print(" as ")
+		d.path(false)
+		d.print(">")
+	case 'N':
+		ns := d.next()
+		if !isASCIILetter(ns) {
+			d.fail()
+		}
+		d.path(inValue)
+		dis := d.optInteger62('s')
+		name, punycode := d.ident()
+		switch {
+		case 'A' <= ns && ns <= 'Z':
+			d.print("::{")
+			switch ns {
+			case 'C':
+				d.print("closure")
+			case 'S':
+				d.print("shim")
+			default:
+				d.out = append(d.out, ns)
+			}
+			if name != "" {
+				d.print(":")
+				d.printIdent(name, punycode)
+			}
+			d.print("#")
+			d.out = strconv.AppendUint(d.out, dis, 10)
+			d.print("}")
+		case name != "":
+			d.print("::")
+			d.printIdent(name, punycode)
+		}
+	case 'I':
+		d.path(inValue)
+		open := "<"
+		if inValue {
+			open = "::<"
+		}
+		if d.genericArgs(open) > 0 {
+			d.print(">")
+		}
+	case 'B':
+		d.backref(func() { d.path(inValue) })
+	default:
+		d.fail()
+	}
+}
+
+// implPath parses the path of an impl block, which is not part of the output.
+func (d *rustDemangler) implPath() {
+	d.optInteger62('s')
+	mark := len(d.out)
+	d.path(false)
+	d.out = d.out[:mark]
+}
+
+// genericArgs parses a list of generic arguments terminated by 'E', printing
+// open before the first argument. Lifetimes are omitted from the output. The
+// method returns the number of arguments that were printed.
+func (d *rustDemangler) genericArgs(open string) (n int) {
+	for !d.eat('E') {
+		if d.eat('L') {
+			d.integer62()
+			continue
+		}
+		if n == 0 {
+			d.print(open)
+		} else {
+			d.print(", ")
+		}
+		if d.eat('K') {
+			d.constant()
+		} else {
+			d.typ()
+		}
+		n++
+	}
+	return n
+}
+
+func (d *rustDemangler) typ() {
+	d.enter()
+	defer d.leave()
+
+	tag := d.next()
+	if s := rustBasicType(tag); s != "" {
+		d.print(s)
+		return
+	}
+	switch tag {
+	case 'R', 'Q':
+		d.print("&")
+		if d.eat('L') {
+			d.integer62()
+		}
+		if tag == 'Q' {
+			d.print("mut ")
+		}
+		d.typ()
+	case 'P':
+		d.print("*const ")
+		d.typ()
+	case 'O':
+		d.print("*mut ")
+		d.typ()
+	case 'A':
+		d.print("[")
+		d.typ()
+		d.print("; ")
+		d.constant()
+		d.print("]")
+	case 'S':
+		d.print("[")
+		d.typ()
+		d.print("]")
+	case 'T':
+		d.print("(")
+		n := 0
+		for ; !d.eat('E'); n++ {
+			if n > 0 {
+				d.print(", ")
+			}
+			d.typ()
+		}
+		if n == 1 {
+			d.print(",")
+		}
+		d.print(")")
+	case 'F':
+		d.fnSig()
+	case 'D':
+		d.dynBounds()
+	case 'B':
+		d.backref(d.typ)
+	default:
+		d.pos--
+		d.path(false)
+	}
+}
+
+func rustBasicType(tag byte) string {
+	switch tag {
+	case 'a':
+		return "i8"
+	case 'b':
+		return "bool"
+	case 'c':
+		return "char"
+	case 'd':
+		return "f64"
+	case 'e':
+		return "str"
+	case 'f':
+		return "f32"
+	case 'h':
+		return "u8"
+	case 'i':
+		return "isize"
+	case 'j':
+		return "usize"
+	case 'l':
+		return "i32"
+	case 'm':
+		return "u32"
+	case 'n':
+		return "i128"
+	case 'o':
+		return "u128"
+	case 's':
+		return "i16"
+	case 't':
+		return "u16"
+	case 'u':
+		return "()"
+	case 'v':
+		return "..."
+	case 'x':
+		return "i64"
+	case 'y':
+		return "u64"
+	case 'z':
+		return "!"
+	case 'p':
+		return "_"
+	}
+	return ""
+}
+
+// fnSig parses the signature of a function pointer type. Higher-ranked
+// lifetimes are omitted from the output.
+func (d *rustDemangler) fnSig() {
+	d.optInteger62('G')
+	if d.eat('U') {
+		d.print("unsafe ")
+	}
+	if d.eat('K') {
+		d.print(`extern "`)
+		if d.eat('C') {
+			d.print("C")
+		} else {
+			abi, punycode := d.ident()
+			if punycode {
+				d.fail()
+			}
+			d.print(strings.ReplaceAll(abi, "_", "-"))
+		}
+		d.print(`" `)
+	}
+	d.print("fn(")
+	for n := 0; !d.eat('E'); n++ {
+		if n > 0 {
+			d.print(", ")
+		}
+		d.typ()
+	}
+	d.print(")")
+	if d.eat('u') {
+		return
+	}
+	d.print(" -> ")
+	d.typ()
+}
+
+func (d *rustDemangler) dynBounds() {
+	d.optInteger62('G')
+	d.print("dyn ")
+	for n := 0; !d.eat('E'); n++ {
+		if n > 0 {
+			d.print(" + ")
+		}
+		d.dynTrait()
+	}
+	if !d.eat('L') {
+		d.fail()
+	}
+	if d.integer62() != 0 {
+		d.print(" + '_")
+	}
+}
+
+func (d *rustDemangler) dynTrait() {
+	open := d.pathMaybeOpenGenerics()
+	for d.eat('p') {
+		if open {
+			d.print(", ")
+		} else {
+			d.print("<")
+			open = true
+		}
+		d.printIdent(d.ident())
+		d.print(" = ")
+		d.typ()
+	}
+	if open {
+		d.print(">")
+	}
+}
+
+// pathMaybeOpenGenerics prints a path without closing its list of generic
+// arguments, so the associated types of dyn traits can be appended to it.
+func (d *rustDemangler) pathMaybeOpenGenerics() (open bool) {
+	switch {
+	case d.eat('B'):
+		d.backref(func() { open = d.pathMaybeOpenGenerics() })
+	case d.eat('I'):
+		d.path(false)
+		open = d.genericArgs("<") > 0
+	default:
+		d.path(false)
+	}
+	return open
+}
+
+func (d *rustDemangler) constant() {
+	d.enter()
+	defer d.leave()
+
+	if d.eat('B') {
+		d.backref(d.constant)
+		return
+	}
+	switch tag := d.next(); tag {
+	case 'p':
+		d.print("_")
+	case 'h', 't', 'm', 'y', 'o', 'j':
+		d.constInt(false)
+	case 'a', 's', 'l', 'x', 'n', 'i':
+		d.constInt(d.eat('n'))
+	case 'b':
+		switch d.hexNibbles() {
+		case "0":
+			d.print("false")
+		case "1":
+			d.print("true")
+		default:
+			d.fail()
+		}
+	case 'c':
+		v, err := strconv.ParseUint(d.hexNibbles(), 16, 32)
+		if err != nil || !utf8.ValidRune(rune(v)) {
+			d.fail()
+		}
+		d.out = strconv.AppendQuoteRune(d.out, rune(v))
+	default:
+		d.fail()
+	}
+}
+
+func (d *rustDemangler) constInt(negative bool) {
+	nibbles := strings.TrimLeft(d.hexNibbles(), "0")
+	if negative {
+		d.print("-")
+	}
+	if len(nibbles) > 16 {
+		d.print("0x")
+		d.print(nibbles)
+		return
+	}
+	v, _ := strconv.ParseUint(nibbles, 16, 64)
+	d.out = strconv.AppendUint(d.out, v, 10)
+}
+
+func (d *rustDemangler) hexNibbles() string {
+	start := d.pos
+	for !d.eat('_') {
+		if !isHexDigit(d.next()) {
+			d.fail()
+		}
+	}
+	return d.sym[start : d.pos-1]
+}
+
+// Parameters of the punycode encoding, see RFC 3492.
+const (
+	punycodeBase        = 36
+	punycodeTMin        = 1
+	punycodeTMax        = 26
+	punycodeSkew        = 38
+	punycodeDamp        = 700
+	punycodeInitialBias = 72
+	punycodeInitialN    = 128
+	punycodeMaxValue    = math.MaxInt32
+)
+
+// decodeRustPunycode decodes identifiers containing non-ASCII characters,
+// which v0 symbols encode with punycode using '_' instead of '-' as delimiter.
+func decodeRustPunycode(s string) (string, bool) {
+	var out []rune
+	if i := strings.LastIndexByte(s, '_'); i >= 0 {
+		out = []rune(s[:i])
+		s = s[i+1:]
+	}
+
+	n, bias, i := punycodeInitialN, punycodeInitialBias, 0
+	for s != "" {
+		oldi, w := i, 1
+		for k := punycodeBase; ; k += punycodeBase {
+			if s == "" {
+				return "", false
+			}
+			c := s[0]
+			s = s[1:]
+
+			var digit int
+			switch {
+			case 'a' <= c && c <= 'z':
+				digit = int(c - 'a')
+			case isDecimalDigit(c):
+				digit = int(c-'0') + 26
+			default:
+				return "", false
+			}
+			if digit > (punycodeMaxValue-i)/w {
+				return "", false
+			}
+			i += digit * w
+
+			t := k - bias
+			if t < punycodeTMin {
+				t = punycodeTMin
+			} else if t > punycodeTMax {
+				t = punycodeTMax
+			}
+			if digit < t {
+				break
+			}
+			if w > punycodeMaxValue/(punycodeBase-t) {
+				return "", false
+			}
+			w *= punycodeBase - t
+		}
+
+		length := len(out) + 1
+		bias = punycodeAdapt(i-oldi, length, oldi == 0)
+		if i/length > punycodeMaxValue-n {
+			return "", false
+		}
+		n += i / length
+		i %= length
+		if !utf8.ValidRune(rune(n)) {
+			return "", false
+		}
+		out = append(out, 0)
+		copy(out[i+1:], out[i:])
+		out[i] = rune(n)
+		i++
+	}
+	return string(out), true
+}
+
+func punycodeAdapt(delta, numPoints int, first bool) int {
+	if first {
+		delta /= punycodeDamp
+	} else {
+		delta /= 2
+	}
+	delta += delta / numPoints
+	k := 0
+	for delta > ((punycodeBase-punycodeTMin)*punycodeTMax)/2 {
+		delta /= punycodeBase - punycodeTMin
+		k += punycodeBase
+	}
+	return k + (punycodeBase-punycodeTMin+1)*delta/(delta+punycodeSkew)
+}
+
+func isDecimalDigit(c byte) bool {
+	return '0' <= c && c <= '9'
+}
+
+func isHexDigit(c byte) bool {
+	return isDecimalDigit(c) || ('a' <= c && c <= 'f')
+}
+
+func isASCIILetter(c byte) bool {
+	return ('a' <= c && c <= 'z') || ('A' <= c && c <= 'Z')
+}
@@ -0,0 +1,96 @@
+package wzprof
+
+import "testing"
+
+func TestDemangleRust(t *testing.T) {
+	tests := []struct {
+		mangled   string
+		demangled string
+	}{
+		// Legacy mangling scheme.
+		{
+			mangled:   "_ZN6simple20allocate_more_memory17h1234567890abcdefE",
+			demangled: "simple::allocate_more_memory",
+		},
+		{
+			mangled:   "_ZN4core3fmt9Formatter3pad17h4de46d8bb8a6ffe1E",
+			demangled: "core::fmt::Formatter::pad",
+		},
+		{
+			mangled:   "_ZN71_$LT$Test$u20$$u2b$$u20$$u27$static$u20$as$u20$foo..Bar$LT$Test$GT$$GT$3bar17h930b740aa94f1d3aE",
+			demangled: "<Test + 'static as foo::Bar<Test>>::bar",
+		},
+		{
+			mangled:   "_ZN3std2rt10lang_start17h0123456789abcdefE.llvm.1234",
+			demangled: "std::rt::lang_start",
+		},
+
+		// v0 mangling scheme.
+		{
+			mangled:   "_RNvC6_123foo3bar",
+			demangled: "123foo::bar",
+		},
+		{
+			mangled:   "_RNCNCNgCs6DXkGYLi8lr_2cc5spawn00B5_",
+			demangled: "cc::spawn::{closure#0}::{closure#0}",
+		},
+		{
+			mangled:   "_RINvNtC3std3mem8align_ofdE",
+			demangled: "std::mem::align_of::<f64>",
+		},
+		{
+			mangled:   "_RNvXC7mycrateNtC7mycrate3FooNtNtC4core3fmt5Debug3fmt",
+			demangled: "<mycrate::Foo as core::fmt::Debug>::fmt",
+		},
+		{
+			mangled:   "_RINvC7mycrate4takeTRhQlEAhj4_SeE",
+			demangled: "mycrate::take::<(&u8, &mut i32), [u8; 4], [str]>",
+		},
+		{
+			mangled:   "_RINvC7mycrate5applyFUKCjEmE",
+			demangled: `mycrate::apply::<unsafe extern "C" fn(usize) -> u32>`,
+		},
+		{
+			mangled:   "_RINvC7mycrate3runDNtC7mycrate4Iterp4ItemlEL_E",
+			demangled: "mycrate::run::<dyn mycrate::Iter<Item = i32>>",
+		},
+		{
+			mangled:   "_RINvC7mycrate5arrayKj10_Klna_E",
+			demangled: "mycrate::array::<16, -10>",
+		},
+		{
+			mangled:   "_RNvC7mycrateu30____7hkackfecea1cbdathfdh9hlq6y",
+			demangled: "mycrate::საჭმელად_გემრიელი_სადილი",
+		},
+
+		// Names which are not mangled Rust symbols are left unchanged.
+		{
+			mangled:   "allocate_more_memory",
+			demangled: "allocate_more_memory",
+		},
+		{
+			mangled:   "_ZN3foo",
+			demangled: "_ZN3foo",
+		},
+		{
+			mangled:   "_RNvC7mycrate",
+			demangled: "_RNvC7mycrate",
+		},
+		{
+			mangled:   "_RB_",
+			demangled: "_RB_",
+		},
+	}
+
+	for _, test := range tests {
+		t.Run(test.mangled, func(t *testing.T) {
+			got, ok := demangle(test.mangled, false)
+			if !ok {
+				got = test.mangled
+			}
+			if got != test.demangled {
+				t.Errorf("wrong demangled name: want=%q got=%q", test.demangled, got)
+			}
+		})
+	}
+}
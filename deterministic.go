@@ -0,0 +1,142 @@
+package wzprof
+
+import (
+	"sort"
+	"strconv"
+	"strings"
+
+	"github.com/google/pprof/profile"
+)
+
+// sortProfile sorts the functions, locations and samples of prof, and numbers
+// the functions and locations in this order. The order of the samples built
+// from the maps of the profilers is otherwise random, and so are the
+// identifiers of the functions and locations.
+func sortProfile(prof *profile.Profile) {
+	sort.Slice(prof.Function, func(i, j int) bool {
+		return compareFunctions(prof.Function[i], prof.Function[j]) < 0
+	})
+	for i, fn := range prof.Function {
+		fn.ID = uint64(i + 1)
+	}
+
+	sort.Slice(prof.Location, func(i, j int) bool {
+		return compareLocations(prof.Location[i], prof.Location[j]) < 0
+	})
+	for i, loc := range prof.Location {
+		loc.ID = uint64(i + 1)
+	}
+
+	keys := make(map[*profile.Sample]string, len(prof.Sample))
+	for _, s := range prof.Sample {
+		keys[s] = labelsKey(s)
+	}
+	sort.Slice(prof.Sample, func(i, j int) bool {
+		si, sj := prof.Sample[i], prof.Sample[j]
+		if c := compareLocationIDs(si.Location, sj.Location); c != 0 {
+			return c < 0
+		}
+		if c := strings.Compare(keys[si], keys[sj]); c != 0 {
+			return c < 0
+		}
+		return compareValues(si.Value, sj.Value) < 0
+	})
+}
+
+func compareFunctions(a, b *profile.Function) int {
+	if c := strings.Compare(a.Name, b.Name); c != 0 {
+		return c
+	}
+	if c := strings.Compare(a.SystemName, b.SystemName); c != 0 {
+		return c
+	}
+	if c := strings.Compare(a.Filename, b.Filename); c != 0 {
+		return c
+	}
+	return compareInts(a.StartLine, b.StartLine)
+}
+
+// compareLocations compares locations by address and lines. The functions of
+// the lines must have been sorted and numbered first.
+func compareLocations(a, b *profile.Location) int {
+	if a.Address != b.Address {
+		if a.Address < b.Address {
+			return -1
+		}
+		return 1
+	}
+	for i := 0; i < len(a.Line) && i < len(b.Line); i++ {
+		la, lb := a.Line[i], b.Line[i]
+		if c := compareInts(int64(la.Function.ID), int64(lb.Function.ID)); c != 0 {
+			return c
+		}
+		if c := compareInts(la.Line, lb.Line); c != 0 {
+			return c
+		}
+	}
+	return compareInts(int64(len(a.Line)), int64(len(b.Line)))
+}
+
+func compareLocationIDs(a, b []*profile.Location) int {
+	for i := 0; i < len(a) && i < len(b); i++ {
+		if c := compareInts(int64(a[i].ID), int64(b[i].ID)); c != 0 {
+			return c
+		}
+	}
+	return compareInts(int64(len(a)), int64(len(b)))
+}
+
+func compareValues(a, b []int64) int {
+	for i := 0; i < len(a) && i < len(b); i++ {
+		if c := compareInts(a[i], b[i]); c != 0 {
+			return c
+		}
+	}
+	return compareInts(int64(len(a)), int64(len(b)))
+}
+
+func compareInts(a, b int64) int {
+	switch {
+	case a < b:
+		return -1
+	case a > b:
+		return 1
+	default:
+		return 0
+	}
+}
+
+// labelsKey returns a string representation of the labels of s, which is the
+// same for samples with the same labels.
+func labelsKey(s *profile.Sample) string {
+	if len(s.Label) == 0 && len(s.NumLabel) == 0 {
+		return ""
+	}
+	var b strings.Builder
+	keys := make([]string, 0, len(s.Label))
+	for k := range s.Label {
+		keys = append(keys, k)
+	}
+	sort.Strings(keys)
+	for _, k := range keys {
+		b.WriteString(k)
+		b.WriteByte('=')
+		b.WriteString(strings.Join(s.Label[k], ","))
+		b.WriteByte(';')
+	}
+	keys = keys[:0]
+	for k := range s.NumLabel {
+		keys = append(keys, k)
+	}
+	sort.Strings(keys)
+	for _, k := range keys {
+		b.WriteString(k)
+		b.WriteByte('=')
+		for _, v := range s.NumLabel[k] {
+			b.WriteString(strconv.FormatInt(v, 10))
+			b.WriteByte(',')
+		}
+		b.WriteByte(';')
+	}
+	return b.String()
+}
@@ -0,0 +1,52 @@
+package wzprof
+
+import (
+	"bytes"
+	"context"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestDeterministicProfile(t *testing.T) {
+	names := []string{"f0", "f1", "f2", "f3", "f4", "f5", "f6", "f7"}
+
+	profileCalls := func(order []int) []byte {
+		p := ProfilingFor(nil, AllowUnprepared(true), Deterministic(true)).CallProfiler()
+
+		fns := make([]*wazerotest.Function, len(names))
+		for i, name := range names {
+			fns[i] = wazerotest.NewFunction(func(context.Context, api.Module) {})
+			fns[i].FunctionName = name
+		}
+		module := wazerotest.NewModule(nil, fns...)
+		ctx := context.Background()
+
+		for _, i := range order {
+			def := fns[i].Definition()
+			listener := p.NewFunctionListener(def)
+			for n := 0; n <= i; n++ {
+				listener.Before(ctx, module, def, nil, experimental.NewStackIterator(experimental.StackFrame{Function: fns[i]}))
+				listener.After(ctx, module, def, nil)
+			}
+		}
+
+		prof := p.NewProfile(1)
+		if prof.TimeNanos != 0 || prof.DurationNanos != 0 {
+			t.Errorf("deterministic profile has a time: %d+%d", prof.TimeNanos, prof.DurationNanos)
+		}
+		b := new(bytes.Buffer)
+		if err := EncodeProfile(b, prof); err != nil {
+			t.Fatal(err)
+		}
+		return b.Bytes()
+	}
+
+	p0 := profileCalls([]int{0, 1, 2, 3, 4, 5, 6, 7})
+	p1 := profileCalls([]int{7, 6, 5, 4, 3, 2, 1, 0})
+	if !bytes.Equal(p0, p1) {
+		t.Error("profiles of the same calls are different")
+	}
+}
@@ -0,0 +1,601 @@
+package wzprof
+
+import (
+	"encoding/binary"
+	"fmt"
+	"math"
+	"strconv"
+	"strings"
+)
+
+// wasmInstruction is an instruction decoded from the code of a function.
+type wasmInstruction struct {
+	offset int    // offset of the instruction in the code of the function
+	depth  int    // number of blocks enclosing the instruction
+	text   string // instruction in the text format
+}
+
+// wasmDisassemble decodes the instructions of the code of a function. The names
+// of the functions by index are used to print the targets of calls.
+func wasmDisassemble(code []byte, names map[uint32]string) ([]wasmInstruction, error) {
+	var insns []wasmInstruction
+	var text strings.Builder
+	depth := 0
+
+	r := wasmReader{b: code}
+	for r.off < len(r.b) {
+		offset := r.off
+		opcode := r.opcode()
+		if opcode.name == "" {
+			return nil, fmt.Errorf("unknown instruction at offset %d", offset)
+		}
+
+		switch opcode.name {
+		case "end", "delegate":
+			depth--
+		}
+		insn := wasmInstruction{offset: offset, depth: depth}
+		switch opcode.name {
+		case "else", "catch", "catch_all":
+			insn.depth--
+		}
+		if insn.depth < 0 {
+			insn.depth = 0
+		}
+		if opcode.imm == immBlock {
+			depth++
+		}
+
+		text.Reset()
+		text.WriteString(opcode.name)
+		r.immediates(&text, opcode.imm, names)
+		if r.err {
+			return nil, fmt.Errorf("truncated instruction at offset %d", offset)
+		}
+		insn.text = text.String()
+		insns = append(insns, insn)
+	}
+	return insns, nil
+}
+
+// opcode reads the opcode of an instruction, the name of the opcode is empty
+// if it is unknown.
+func (r *wasmReader) opcode() wasmOpcode {
+	switch op := r.byte(); op {
+	case 0xfc:
+		return wasmPrefixedOpcode(wasmMiscOpcodes[:], r.uvarint())
+	case 0xfd:
+		op := r.uvarint()
+		if opcode := wasmPrefixedOpcode(wasmSIMDOpcodes[:], op); opcode.name != "" {
+			return opcode
+		}
+		return wasmOpcode{fmt.Sprintf("simd.%#x", op), immNone}
+	case 0xfe:
+		return wasmPrefixedOpcode(wasmAtomicOpcodes[:], r.uvarint())
+	default:
+		return wasmPrefixedOpcode(wasmOpcodes[:], uint64(op))
+	}
+}
+
+func wasmPrefixedOpcode(opcodes []wasmOpcode, op uint64) wasmOpcode {
+	if op < uint64(len(opcodes)) {
+		return opcodes[op]
+	}
+	return wasmOpcode{}
+}
+
+// immediates reads the immediates of an instruction and writes them to text.
+func (r *wasmReader) immediates(text *strings.Builder, imm wasmImmediate, names map[uint32]string) {
+	switch imm {
+	case immBlock:
+		switch t := r.varint(); {
+		case t == -0x40: // empty
+		case t < 0:
+			fmt.Fprintf(text, " (result %s)", wasmValueType(t))
+		default:
+			fmt.Fprintf(text, " (type %d)", t)
+		}
+	case immIndex:
+		fmt.Fprintf(text, " %d", r.uvarint())
+	case immFunction:
+		index := r.uvarint()
+		if name, ok := names[uint32(index)]; ok {
+			fmt.Fprintf(text, " $%s", name)
+		} else {
+			fmt.Fprintf(text, " %d", index)
+		}
+	case immBrTable:
+		n := r.uvarint()
+		if n > uint64(len(r.b)) {
+			r.err = true
+			return
+		}
+		for i := uint64(0); i <= n; i++ {
+			fmt.Fprintf(text, " %d", r.uvarint())
+		}
+	case immCallIndirect:
+		typ := r.uvarint()
+		if table := r.uvarint(); table != 0 {
+			fmt.Fprintf(text, " %d", table)
+		}
+		fmt.Fprintf(text, " (type %d)", typ)
+	case immSelect:
+		n := r.uvarint()
+		if n > uint64(len(r.b)) {
+			r.err = true
+			return
+		}
+		text.WriteString(" (result")
+		for i := uint64(0); i < n; i++ {
+			fmt.Fprintf(text, " %s", wasmValueType(int64(int8(r.byte()))))
+		}
+		text.WriteString(")")
+	case immMemarg:
+		r.memarg(text)
+	case immMemargLane:
+		r.memarg(text)
+		fmt.Fprintf(text, " %d", r.byte())
+	case immMemory:
+		if memory := r.uvarint(); memory != 0 {
+			fmt.Fprintf(text, " %d", memory)
+		}
+	case immIndexMemory:
+		data := r.uvarint()
+		if memory := r.uvarint(); memory != 0 {
+			fmt.Fprintf(text, " %d", memory)
+		}
+		fmt.Fprintf(text, " %d", data)
+	case immTwoMemories:
+		dst, src := r.uvarint(), r.uvarint()
+		if dst != 0 || src != 0 {
+			fmt.Fprintf(text, " %d %d", dst, src)
+		}
+	case immTwoIndexes:
+		fmt.Fprintf(text, " %d", r.uvarint())
+		fmt.Fprintf(text, " %d", r.uvarint())
+	case immI32, immI64:
+		fmt.Fprintf(text, " %d", r.varint())
+	case immF32:
+		if b := r.bytes(4); len(b) == 4 {
+			f := math.Float32frombits(binary.LittleEndian.Uint32(b))
+			fmt.Fprintf(text, " %s", strconv.FormatFloat(float64(f), 'g', -1, 32))
+		}
+	case immF64:
+		if b := r.bytes(8); len(b) == 8 {
+			f := math.Float64frombits(binary.LittleEndian.Uint64(b))
+			fmt.Fprintf(text, " %s", strconv.FormatFloat(f, 'g', -1, 64))
+		}
+	case immRefNull:
+		fmt.Fprintf(text, " %s", strings.TrimSuffix(wasmValueType(int64(int8(r.byte()))), "ref"))
+	case immByte:
+		r.byte()
+	case immLane:
+		fmt.Fprintf(text, " %d", r.byte())
+	case immV128:
+		b := r.bytes(16)
+		if len(b) == 16 {
+			text.WriteString(" i32x4")
+			for i := 0; i < 16; i += 4 {
+				fmt.Fprintf(text, " 0x%08x", binary.LittleEndian.Uint32(b[i:]))
+			}
+		}
+	case immShuffle:
+		for _, lane := range r.bytes(16) {
+			fmt.Fprintf(text, " %d", lane)
+		}
+	}
+}
+
+// memarg reads the alignment, memory index and offset of a memory access.
+func (r *wasmReader) memarg(text *strings.Builder) {
+	align := r.uvarint()
+	// The multiple memories proposal sets bit 6 of the alignment when the
+	// index of the memory follows.
+	if align&0x40 != 0 {
+		if memory := r.uvarint(); memory != 0 {
+			fmt.Fprintf(text, " %d", memory)
+		}
+		align &^= 0x40
+	}
+	if offset := r.uvarint(); offset != 0 {
+		fmt.Fprintf(text, " offset=%d", offset)
+	}
+	if align < 64 {
+		fmt.Fprintf(text, " align=%d", uint64(1)<<align)
+	}
+}
+
+// wasmValueType returns the name of a value type, encoded as a negative number
+// like in block types.
+func wasmValueType(t int64) string {
+	switch t {
+	case -0x01:
+		return "i32"
+	case -0x02:
+		return "i64"
+	case -0x03:
+		return "f32"
+	case -0x04:
+		return "f64"
+	case -0x05:
+		return "v128"
+	case -0x10:
+		return "funcref"
+	case -0x11:
+		return "externref"
+	}
+	return fmt.Sprintf("type%#x", byte(t&0x7f))
+}
+
+// wasmImmediate is the kind of immediates of an instruction.
+type wasmImmediate uint8
+
+const (
+	immNone         wasmImmediate = iota // no immediates
+	immBlock                             // block type
+	immIndex                             // index (local, global, label, table, ...)
+	immFunction                          // function index
+	immBrTable                           // vector of labels and default label
+	immCallIndirect                      // type index and table index
+	immSelect                            // vector of value types
+	immMemarg                            // alignment and offset
+	immMemargLane                        // alignment, offset and lane index
+	immMemory                            // memory index
+	immIndexMemory                       // data index and memory index
+	immTwoMemories                       // destination and source memory indexes
+	immTwoIndexes                        // two table, element or data indexes
+	immI32                               // signed 32 bits integer
+	immI64                               // signed 64 bits integer
+	immF32                               // 32 bits floating point number
+	immF64                               // 64 bits floating point number
+	immRefNull                           // reference type
+	immByte                              // reserved byte
+	immLane                              // lane index
+	immV128                              // 128 bits constant
+	immShuffle                           // 16 lane indexes
+)
+
+// wasmOpcode describes the instructions of an opcode.
+type wasmOpcode struct {
+	name string
+	imm  wasmImmediate
+}
+
+// wasmOpcodes are the instructions of a single byte opcode.
+var wasmOpcodes = [...]wasmOpcode{
+	0x00: {"unreachable", immNone},
+	0x01: {"nop", immNone},
+	0x02: {"block", immBlock},
+	0x03: {"loop", immBlock},
+	0x04: {"if", immBlock},
+	0x05: {"else", immNone},
+	0x06: {"try", immBlock},
+	0x07: {"catch", immIndex},
+	0x08: {"throw", immIndex},
+	0x09: {"rethrow", immIndex},
+	0x0b: {"end", immNone},
+	0x0c: {"br", immIndex},
+	0x0d: {"br_if", immIndex},
+	0x0e: {"br_table", immBrTable},
+	0x0f: {"return", immNone},
+	0x10: {"call", immFunction},
+	0x11: {"call_indirect", immCallIndirect},
+	0x12: {"return_call", immFunction},
+	0x13: {"return_call_indirect", immCallIndirect},
+	0x18: {"delegate", immIndex},
+	0x19: {"catch_all", immNone},
+	0x1a: {"drop", immNone},
+	0x1b: {"select", immNone},
+	0x1c: {"select", immSelect},
+	0x20: {"local.get", immIndex},
+	0x21: {"local.set", immIndex},
+	0x22: {"local.tee", immIndex},
+	0x23: {"global.get", immIndex},
+	0x24: {"global.set", immIndex},
+	0x25: {"table.get", immIndex},
+	0x26: {"table.set", immIndex},
+	0x28: {"i32.load", immMemarg},
+	0x29: {"i64.load", immMemarg},
+	0x2a: {"f32.load", immMemarg},
+	0x2b: {"f64.load", immMemarg},
+	0x2c: {"i32.load8_s", immMemarg},
+	0x2d: {"i32.load8_u", immMemarg},
+	0x2e: {"i32.load16_s", immMemarg},
+	0x2f: {"i32.load16_u", immMemarg},
+	0x30: {"i64.load8_s", immMemarg},
+	0x31: {"i64.load8_u", immMemarg},
+	0x32: {"i64.load16_s", immMemarg},
+	0x33: {"i64.load16_u", immMemarg},
+	0x34: {"i64.load32_s", immMemarg},
+	0x35: {"i64.load32_u", immMemarg},
+	0x36: {"i32.store", immMemarg},
+	0x37: {"i64.store", immMemarg},
+	0x38: {"f32.store", immMemarg},
+	0x39: {"f64.store", immMemarg},
+	0x3a: {"i32.store8", immMemarg},
+	0x3b: {"i32.store16", immMemarg},
+	0x3c: {"i64.store8", immMemarg},
+	0x3d: {"i64.store16", immMemarg},
+	0x3e: {"i64.store32", immMemarg},
+	0x3f: {"memory.size", immMemory},
+	0x40: {"memory.grow", immMemory},
+	0x41: {"i32.const", immI32},
+	0x42: {"i64.const", immI64},
+	0x43: {"f32.const", immF32},
+	0x44: {"f64.const", immF64},
+	0x45: {"i32.eqz", immNone},
+	0x46: {"i32.eq", immNone},
+	0x47: {"i32.ne", immNone},
+	0x48: {"i32.lt_s", immNone},
+	0x49: {"i32.lt_u", immNone},
+	0x4a: {"i32.gt_s", immNone},
+	0x4b: {"i32.gt_u", immNone},
+	0x4c: {"i32.le_s", immNone},
+	0x4d: {"i32.le_u", immNone},
+	0x4e: {"i32.ge_s", immNone},
+	0x4f: {"i32.ge_u", immNone},
+	0x50: {"i64.eqz", immNone},
+	0x51: {"i64.eq", immNone},
+	0x52: {"i64.ne", immNone},
+	0x53: {"i64.lt_s", immNone},
+	0x54: {"i64.lt_u", immNone},
+	0x55: {"i64.gt_s", immNone},
+	0x56: {"i64.gt_u", immNone},
+	0x57: {"i64.le_s", immNone},
+	0x58: {"i64.le_u", immNone},
+	0x59: {"i64.ge_s", immNone},
+	0x5a: {"i64.ge_u", immNone},
+	0x5b: {"f32.eq", immNone},
+	0x5c: {"f32.ne", immNone},
+	0x5d: {"f32.lt", immNone},
+	0x5e: {"f32.gt", immNone},
+	0x5f: {"f32.le", immNone},
+	0x60: {"f32.ge", immNone},
+	0x61: {"f64.eq", immNone},
+	0x62: {"f64.ne", immNone},
+	0x63: {"f64.lt", immNone},
+	0x64: {"f64.gt", immNone},
+	0x65: {"f64.le", immNone},
+	0x66: {"f64.ge", immNone},
+	0x67: {"i32.clz", immNone},
+	0x68: {"i32.ctz", immNone},
+	0x69: {"i32.popcnt", immNone},
+	0x6a: {"i32.add", immNone},
+	0x6b: {"i32.sub", immNone},
+	0x6c: {"i32.mul", immNone},
+	0x6d: {"i32.div_s", immNone},
+	0x6e: {"i32.div_u", immNone},
+	0x6f: {"i32.rem_s", immNone},
+	0x70: {"i32.rem_u", immNone},
+	0x71: {"i32.and", immNone},
+	0x72: {"i32.or", immNone},
+	0x73: {"i32.xor", immNone},
+	0x74: {"i32.shl", immNone},
+	0x75: {"i32.shr_s", immNone},
+	0x76: {"i32.shr_u", immNone},
+	0x77: {"i32.rotl", immNone},
+	0x78: {"i32.rotr", immNone},
+	0x79: {"i64.clz", immNone},
+	0x7a: {"i64.ctz", immNone},
+	0x7b: {"i64.popcnt", immNone},
+	0x7c: {"i64.add", immNone},
+	0x7d: {"i64.sub", immNone},
+	0x7e: {"i64.mul", immNone},
+	0x7f: {"i64.div_s", immNone},
+	0x80: {"i64.div_u", immNone},
+	0x81: {"i64.rem_s", immNone},
+	0x82: {"i64.rem_u", immNone},
+	0x83: {"i64.and", immNone},
+	0x84: {"i64.or", immNone},
+	0x85: {"i64.xor", immNone},
+	0x86: {"i64.shl", immNone},
+	0x87: {"i64.shr_s", immNone},
+	0x88: {"i64.shr_u", immNone},
+	0x89: {"i64.rotl", immNone},
+	0x8a: {"i64.rotr", immNone},
+	0x8b: {"f32.abs", immNone},
+	0x8c: {"f32.neg", immNone},
+	0x8d: {"f32.ceil", immNone},
+	0x8e: {"f32.floor", immNone},
+	0x8f: {"f32.trunc", immNone},
+	0x90: {"f32.nearest", immNone},
+	0x91: {"f32.sqrt", immNone},
+	0x92: {"f32.add", immNone},
+	0x93: {"f32.sub", immNone},
+	0x94: {"f32.mul", immNone},
+	0x95: {"f32.div", immNone},
+	0x96: {"f32.min", immNone},
+	0x97: {"f32.max", immNone},
+	0x98: {"f32.copysign", immNone},
+	0x99: {"f64.abs", immNone},
+	0x9a: {"f64.neg", immNone},
+	0x9b: {"f64.ceil", immNone},
+	0x9c: {"f64.floor", immNone},
+	0x9d: {"f64.trunc", immNone},
+	0x9e: {"f64.nearest", immNone},
+	0x9f: {"f64.sqrt", immNone},
+	0xa0: {"f64.add", immNone},
+	0xa1: {"f64.sub", immNone},
+	0xa2: {"f64.mul", immNone},
+	0xa3: {"f64.div", immNone},
+	0xa4: {"f64.min", immNone},
+	0xa5: {"f64.max", immNone},
+	0xa6: {"f64.copysign", immNone},
+	0xa7: {"i32.wrap_i64", immNone},
+	0xa8: {"i32.trunc_f32_s", immNone},
+	0xa9: {"i32.trunc_f32_u", immNone},
+	0xaa: {"i32.trunc_f64_s", immNone},
+	0xab: {"i32.trunc_f64_u", immNone},
+	0xac: {"i64.extend_i32_s", immNone},
+	0xad: {"i64.extend_i32_u", immNone},
+	0xae: {"i64.trunc_f32_s", immNone},
+	0xaf: {"i64.trunc_f32_u", immNone},
+	0xb0: {"i64.trunc_f64_s", immNone},
+	0xb1: {"i64.trunc_f64_u", immNone},
+	0xb2: {"f32.convert_i32_s", immNone},
+	0xb3: {"f32.convert_i32_u", immNone},
+	0xb4: {"f32.convert_i64_s", immNone},
+	0xb5: {"f32.convert_i64_u", immNone},
+	0xb6: {"f32.demote_f64", immNone},
+	0xb7: {"f64.convert_i32_s", immNone},
+	0xb8: {"f64.convert_i32_u", immNone},
+	0xb9: {"f64.convert_i64_s", immNone},
+	0xba: {"f64.convert_i64_u", immNone},
+	0xbb: {"f64.promote_f32", immNone},
+	0xbc: {"i32.reinterpret_f32", immNone},
+	0xbd: {"i64.reinterpret_f64", immNone},
+	0xbe: {"f32.reinterpret_i32", immNone},
+	0xbf: {"f64.reinterpret_i64", immNone},
+	0xc0: {"i32.extend8_s", immNone},
+	0xc1: {"i32.extend16_s", immNone},
+	0xc2: {"i64.extend8_s", immNone},
+	0xc3: {"i64.extend16_s", immNone},
+	0xc4: {"i64.extend32_s", immNone},
+	0xd0: {"ref.null", immRefNull},
+	0xd1: {"ref.is_null", immNone},
+	0xd2: {"ref.func", immFunction},
+}
+
+// wasmMiscOpcodes are the instructions of the 0xFC prefix (saturating
+// truncations, bulk memory and table operations).
+var wasmMiscOpcodes = [...]wasmOpcode{
+	0x00: {"i32.trunc_sat_f32_s", immNone},
+	0x01: {"i32.trunc_sat_f32_u", immNone},
+	0x02: {"i32.trunc_sat_f64_s", immNone},
+	0x03: {"i32.trunc_sat_f64_u", immNone},
+	0x04: {"i64.trunc_sat_f32_s", immNone},
+	0x05: {"i64.trunc_sat_f32_u", immNone},
+	0x06: {"i64.trunc_sat_f64_s", immNone},
+	0x07: {"i64.trunc_sat_f64_u", immNone},
+	0x08: {"memory.init", immIndexMemory},
+	0x09: {"data.drop", immIndex},
+	0x0a: {"memory.copy", immTwoMemories},
+	0x0b: {"memory.fill", immMemory},
+	0x0c: {"table.init", immTwoIndexes},
+	0x0d: {"elem.drop", immIndex},
+	0x0e: {"table.copy", immTwoIndexes},
+	0x0f: {"table.grow", immIndex},
+	0x10: {"table.size", immIndex},
+	0x11: {"table.fill", immIndex},
+}
+
+// wasmSIMDOpcodes are the instructions of the 0xFD prefix which have immediates,
+// the other SIMD instructions are printed with their opcode.
+var wasmSIMDOpcodes = [...]wasmOpcode{
+	0x00: {"v128.load", immMemarg},
+	0x01: {"v128.load8x8_s", immMemarg},
+	0x02: {"v128.load8x8_u", immMemarg},
+	0x03: {"v128.load16x4_s", immMemarg},
+	0x04: {"v128.load16x4_u", immMemarg},
+	0x05: {"v128.load32x2_s", immMemarg},
+	0x06: {"v128.load32x2_u", immMemarg},
+	0x07: {"v128.load8_splat", immMemarg},
+	0x08: {"v128.load16_splat", immMemarg},
+	0x09: {"v128.load32_splat", immMemarg},
+	0x0a: {"v128.load64_splat", immMemarg},
+	0x0b: {"v128.store", immMemarg},
+	0x0c: {"v128.const", immV128},
+	0x0d: {"i8x16.shuffle", immShuffle},
+	0x0e: {"i8x16.swizzle", immNone},
+	0x0f: {"i8x16.splat", immNone},
+	0x10: {"i16x8.splat", immNone},
+	0x11: {"i32x4.splat", immNone},
+	0x12: {"i64x2.splat", immNone},
+	0x13: {"f32x4.splat", immNone},
+	0x14: {"f64x2.splat", immNone},
+	0x15: {"i8x16.extract_lane_s", immLane},
+	0x16: {"i8x16.extract_lane_u", immLane},
+	0x17: {"i8x16.replace_lane", immLane},
+	0x18: {"i16x8.extract_lane_s", immLane},
+	0x19: {"i16x8.extract_lane_u", immLane},
+	0x1a: {"i16x8.replace_lane", immLane},
+	0x1b: {"i32x4.extract_lane", immLane},
+	0x1c: {"i32x4.replace_lane", immLane},
+	0x1d: {"i64x2.extract_lane", immLane},
+	0x1e: {"i64x2.replace_lane", immLane},
+	0x1f: {"f32x4.extract_lane", immLane},
+	0x20: {"f32x4.replace_lane", immLane},
+	0x21: {"f64x2.extract_lane", immLane},
+	0x22: {"f64x2.replace_lane", immLane},
+	0x54: {"v128.load8_lane", immMemargLane},
+	0x55: {"v128.load16_lane", immMemargLane},
+	0x56: {"v128.load32_lane", immMemargLane},
+	0x57: {"v128.load64_lane", immMemargLane},
+	0x58: {"v128.store8_lane", immMemargLane},
+	0x59: {"v128.store16_lane", immMemargLane},
+	0x5a: {"v128.store32_lane", immMemargLane},
+	0x5b: {"v128.store64_lane", immMemargLane},
+	0x5c: {"v128.load32_zero", immMemarg},
+	0x5d: {"v128.load64_zero", immMemarg},
+}
+
+// wasmAtomicOpcodes are the instructions of the 0xFE prefix (threads).
+var wasmAtomicOpcodes = [...]wasmOpcode{
+	0x00: {"memory.atomic.notify", immMemarg},
+	0x01: {"memory.atomic.wait32", immMemarg},
+	0x02: {"memory.atomic.wait64", immMemarg},
+	0x03: {"atomic.fence", immByte},
+	0x10: {"i32.atomic.load", immMemarg},
+	0x11: {"i64.atomic.load", immMemarg},
+	0x12: {"i32.atomic.load8_u", immMemarg},
+	0x13: {"i32.atomic.load16_u", immMemarg},
+	0x14: {"i64.atomic.load8_u", immMemarg},
+	0x15: {"i64.atomic.load16_u", immMemarg},
+	0x16: {"i64.atomic.load32_u", immMemarg},
+	0x17: {"i32.atomic.store", immMemarg},
+	0x18: {"i64.atomic.store", immMemarg},
+	0x19: {"i32.atomic.store8", immMemarg},
+	0x1a: {"i32.atomic.store16", immMemarg},
+	0x1b: {"i64.atomic.store8", immMemarg},
+	0x1c: {"i64.atomic.store16", immMemarg},
+	0x1d: {"i64.atomic.store32", immMemarg},
+	0x1e: {"i32.atomic.rmw.add", immMemarg},
+	0x1f: {"i64.atomic.rmw.add", immMemarg},
+	0x20: {"i32.atomic.rmw8.add_u", immMemarg},
+	0x21: {"i32.atomic.rmw16.add_u", immMemarg},
+	0x22: {"i64.atomic.rmw8.add_u", immMemarg},
+	0x23: {"i64.atomic.rmw16.add_u", immMemarg},
+	0x24: {"i64.atomic.rmw32.add_u", immMemarg},
+	0x25: {"i32.atomic.rmw.sub", immMemarg},
+	0x26: {"i64.atomic.rmw.sub", immMemarg},
+	0x27: {"i32.atomic.rmw8.sub_u", immMemarg},
+	0x28: {"i32.atomic.rmw16.sub_u", immMemarg},
+	0x29: {"i64.atomic.rmw8.sub_u", immMemarg},
+	0x2a: {"i64.atomic.rmw16.sub_u", immMemarg},
+	0x2b: {"i64.atomic.rmw32.sub_u", immMemarg},
+	0x2c: {"i32.atomic.rmw.and", immMemarg},
+	0x2d: {"i64.atomic.rmw.and", immMemarg},
+	0x2e: {"i32.atomic.rmw8.and_u", immMemarg},
+	0x2f: {"i32.atomic.rmw16.and_u", immMemarg},
+	0x30: {"i64.atomic.rmw8.and_u", immMemarg},
+	0x31: {"i64.atomic.rmw16.and_u", immMemarg},
+	0x32: {"i64.atomic.rmw32.and_u", immMemarg},
+	0x33: {"i32.atomic.rmw.or", immMemarg},
+	0x34: {"i64.atomic.rmw.or", immMemarg},
+	0x35: {"i32.atomic.rmw8.or_u", immMemarg},
+	0x36: {"i32.atomic.rmw16.or_u", immMemarg},
+	0x37: {"i64.atomic.rmw8.or_u", immMemarg},
+	0x38: {"i64.atomic.rmw16.or_u", immMemarg},
+	0x39: {"i64.atomic.rmw32.or_u", immMemarg},
+	0x3a: {"i32.atomic.rmw.xor", immMemarg},
+	0x3b: {"i64.atomic.rmw.xor", immMemarg},
+	0x3c: {"i32.atomic.rmw8.xor_u", immMemarg},
+	0x3d: {"i32.atomic.rmw16.xor_u", immMemarg},
+	0x3e: {"i64.atomic.rmw8.xor_u", immMemarg},
+	0x3f: {"i64.atomic.rmw16.xor_u", immMemarg},
+	0x40: {"i64.atomic.rmw32.xor_u", immMemarg},
+	0x41: {"i32.atomic.rmw.xchg", immMemarg},
+	0x42: {"i64.atomic.rmw.xchg", immMemarg},
+	0x43: {"i32.atomic.rmw8.xchg_u", immMemarg},
+	0x44: {"i32.atomic.rmw16.xchg_u", immMemarg},
+	0x45: {"i64.atomic.rmw8.xchg_u", immMemarg},
+	0x46: {"i64.atomic.rmw16.xchg_u", immMemarg},
+	0x47: {"i64.atomic.rmw32.xchg_u", immMemarg},
+	0x48: {"i32.atomic.rmw.cmpxchg", immMemarg},
+	0x49: {"i64.atomic.rmw.cmpxchg", immMemarg},
+	0x4a: {"i32.atomic.rmw8.cmpxchg_u", immMemarg},
+	0x4b: {"i32.atomic.rmw16.cmpxchg_u", immMemarg},
+	0x4c: {"i64.atomic.rmw8.cmpxchg_u", immMemarg},
+	0x4d: {"i64.atomic.rmw16.cmpxchg_u", immMemarg},
+	0x4e: {"i64.atomic.rmw32.cmpxchg_u", immMemarg},
+}
@@ -1,23 +1,31 @@
 package wzprof
 
 import (
+	"bytes"
 	"debug/dwarf"
+	"encoding/binary"
 	"errors"
 	"fmt"
 	"io"
 	"log"
 	"math"
+	"net/http"
+	"os"
+	"runtime"
 	"sort"
+	"strings"
 	"sync"
+	"sync/atomic"
 
 	"github.com/tetratelabs/wazero"
 	"github.com/tetratelabs/wazero/experimental"
 )
 
 // buildDwarfSymbolizer constructs a Symbolizer instance from the DWARF sections
-// of the given WebAssembly module.
-func buildDwarfSymbolizer(parser dwarfparser) symbolizer {
-	return newDwarfmapper(parser)
+// of the given WebAssembly module. The progress function, if not nil, is called
+// as the compilation units are parsed (see PrepareProgress).
+func buildDwarfSymbolizer(parser dwarfparser, progress func(done, total int)) symbolizer {
+	return newDwarfmapper(parser, progress)
 }
 
 type sourceOffsetRange = [2]uint64
@@ -32,6 +40,9 @@ type subprogram struct {
 type entryRanges struct {
 	entry  *dwarf.Entry
 	ranges []sourceOffsetRange
+	// Inlined subroutines nested in this one, when the inlined function had
+	// itself inlined calls to other functions.
+	inlines []entryRanges
 }
 
 type subprogramRange struct {
@@ -83,6 +94,69 @@ func newDwarfparser(module wazero.CompiledModule) (dwarfparser, error) {
 	return dwarfparser{d: d, r: r}, nil
 }
 
+// externalDebugInfo is the name of the custom section holding the location of
+// the DWARF sections of modules which were stripped of their debug information,
+// see https://github.com/WebAssembly/tool-conventions/blob/main/Debugging.md.
+const externalDebugInfo = "external_debug_info"
+
+// hasDwarf returns true if the module embeds DWARF sections.
+func hasDwarf(module wazero.CompiledModule) bool {
+	for _, section := range module.CustomSections() {
+		if section.Name() == debugInfo {
+			return true
+		}
+	}
+	return false
+}
+
+// customSectionURL returns the location recorded in the custom section of the
+// module with the given name, or an empty string if the section does not exist.
+func customSectionURL(module wazero.CompiledModule, name string) string {
+	for _, section := range module.CustomSections() {
+		if section.Name() != name {
+			continue
+		}
+		b := section.Data()
+		n, k := binary.Uvarint(b)
+		if k <= 0 || n > uint64(len(b)-k) {
+			log.Printf("malformed %s section", name)
+			return ""
+		}
+		return string(b[k : k+int(n)])
+	}
+	return ""
+}
+
+// readLocation returns the content of the file at the given location, which is
+// either a path on the file system or a http URL.
+func readLocation(location string) ([]byte, error) {
+	if !strings.HasPrefix(location, "http://") && !strings.HasPrefix(location, "https://") {
+		return os.ReadFile(strings.TrimPrefix(location, "file://"))
+	}
+	res, err := http.Get(location)
+	if err != nil {
+		return nil, err
+	}
+	defer res.Body.Close()
+	if res.StatusCode != http.StatusOK {
+		return nil, fmt.Errorf("GET %s: %s", location, res.Status)
+	}
+	return io.ReadAll(res.Body)
+}
+
+// newDwarfParserFromURL parses the DWARF sections of the wasm file at the
+// given location.
+func newDwarfParserFromURL(location string) (dwarfparser, error) {
+	wasmbin, err := readLocation(location)
+	if err != nil {
+		return dwarfparser{}, fmt.Errorf("dwarf: loading debug info: %w", err)
+	}
+	if !bytes.HasPrefix(wasmbin, []byte("\x00asm")) {
+		return dwarfparser{}, fmt.Errorf("dwarf: %s is not a wasm file", location)
+	}
+	return newDwarfParserFromBin(wasmbin)
+}
+
 func newDwarfParserFromBin(wasmbin []byte) (dwarfparser, error) {
 	info := wasmCustomSection(wasmbin, debugInfo)
 	line := wasmCustomSection(wasmbin, debugLine)
@@ -99,8 +173,8 @@ func newDwarfParserFromBin(wasmbin []byte) (dwarfparser, error) {
 	return dwarfparser{d: d, r: r}, nil
 }
 
-func newDwarfmapper(p dwarfparser) *dwarfmapper {
-	subprograms := p.Parse()
+func newDwarfmapper(p dwarfparser, progress func(done, total int)) *dwarfmapper {
+	subprograms := p.Parse(progress)
 	log.Printf("dwarf: parsed %d subprogramm ranges", len(subprograms))
 
 	return &dwarfmapper{
@@ -116,17 +190,68 @@ type dwarfparser struct {
 	subprograms []subprogramRange
 }
 
-func (d *dwarfparser) Parse() []subprogramRange {
+// Parse collects the subprograms of the compilation units of the DWARF data.
+//
+// The compilation units are independent, they are parsed concurrently by up to
+// GOMAXPROCS goroutines, each reading the DWARF data with its own reader. The
+// subprograms are returned in the order of their compilation units regardless.
+// The progress function, if not nil, is called after each compilation unit is
+// parsed, with the number of units parsed so far and the total number of units.
+func (d *dwarfparser) Parse(progress func(done, total int)) []subprogramRange {
+	var units []dwarf.Offset
 	for {
 		ent, err := d.r.Next()
 		if err != nil || ent == nil {
 			break
 		}
 		if ent.Tag == dwarf.TagCompileUnit {
-			d.parseCompileUnit(ent, "")
-		} else {
-			d.r.SkipChildren()
+			units = append(units, ent.Offset)
 		}
+		d.r.SkipChildren()
+	}
+
+	workers := runtime.GOMAXPROCS(0)
+	if workers > len(units) {
+		workers = len(units)
+	}
+
+	var (
+		results = make([][]subprogramRange, len(units))
+		next    atomic.Int64
+		wg      sync.WaitGroup
+		mutex   sync.Mutex // serializes calls to progress
+		parsed  int
+	)
+	for w := 0; w < workers; w++ {
+		wg.Add(1)
+		go func() {
+			defer wg.Done()
+			p := dwarfparser{d: d.d, r: d.d.Reader()}
+			for {
+				i := int(next.Add(1) - 1)
+				if i >= len(units) {
+					return
+				}
+				p.subprograms = nil
+				p.r.Seek(units[i])
+				if cu, err := p.r.Next(); err == nil && cu != nil {
+					p.parseCompileUnit(cu, "")
+				}
+				results[i] = p.subprograms
+
+				if progress != nil {
+					mutex.Lock()
+					parsed++
+					progress(parsed, len(units))
+					mutex.Unlock()
+				}
+			}
+		}()
+	}
+	wg.Wait()
+
+	for _, subprograms := range results {
+		d.subprograms = append(d.subprograms, subprograms...)
 	}
 	return d.subprograms
 }
@@ -174,29 +299,7 @@ func (d *dwarfparser) parseSubprogram(cu *dwarf.Entry, ns string, e *dwarf.Entry
 	// Assumption is r has just read the top entry of the subprogram, which
 	// is e.
 
-	var inlines []entryRanges
-	for e.Children {
-		ent, err := d.r.Next()
-		if err != nil || ent == nil {
-			break
-		}
-		if ent.Tag == 0 {
-			break
-		}
-		if ent.Tag != dwarf.TagInlinedSubroutine {
-			d.r.SkipChildren()
-			continue
-		}
-		ranges, err := d.d.Ranges(ent)
-		if err != nil {
-			d.r.SkipChildren()
-			continue
-		}
-		inlines = append(inlines, entryRanges{ent, ranges})
-		// Inlines can have children that describe which variables were
-		// used during inlining.
-		d.r.SkipChildren()
-	}
+	inlines := d.parseInlines(e)
 
 	ranges, err := d.d.Ranges(e)
 	if err != nil {
@@ -232,6 +335,40 @@ func (d *dwarfparser) parseSubprogram(cu *dwarf.Entry, ns string, e *dwarf.Entry
 	}
 }
 
+// parseInlines collects the inlined subroutines among the children of e. They
+// may be nested in lexical blocks, or in other inlined subroutines when calls
+// were inlined recursively.
+func (d *dwarfparser) parseInlines(e *dwarf.Entry) []entryRanges {
+	// Assumption is r has just read e.
+
+	var inlines []entryRanges
+	for e.Children {
+		ent, err := d.r.Next()
+		if err != nil || ent == nil {
+			break
+		}
+		switch ent.Tag {
+		case 0:
+			return inlines
+		case dwarf.TagInlinedSubroutine:
+			ranges, err := d.d.Ranges(ent)
+			// Inlines can have children that describe which variables
+			// were used during inlining, the other inlined functions
+			// are kept.
+			children := d.parseInlines(ent)
+			if err != nil {
+				continue
+			}
+			inlines = append(inlines, entryRanges{ent, ranges, children})
+		case dwarf.TagLexDwarfBlock:
+			inlines = append(inlines, d.parseInlines(ent)...)
+		default:
+			d.r.SkipChildren()
+		}
+	}
+	return inlines
+}
+
 func (d *dwarfmapper) Locations(fn experimental.InternalFunction, pc experimental.ProgramCounter) (uint64, []location) {
 	offset := fn.SourceOffsetForPC(pc)
 	if offset == 0 {
@@ -311,47 +448,66 @@ func (d *dwarfmapper) Locations(fn experimental.InternalFunction, pc experimenta
 		panic("BUG: l.Pos was created from parsing dwarf but got error: " + err.Error())
 	}
 
+	// The line table describes the innermost inlined function, each of the
+	// callers it was inlined into is located at the call site recorded in the
+	// inlined subroutine of its callee.
+	chain := inlinedChain(spgm.Inlines, offset)
+	locations := make([]location, 1+len(chain))
 	human, stable := d.namesForSubprogram(spgm.Entry, spgm)
-	locations := make([]location, 0, 1+len(spgm.Inlines))
-	locations = append(locations, location{
-		File:       le.File.Name,
-		Line:       int64(le.Line),
-		Column:     int64(le.Column),
-		Inlined:    false,
+	locations[0] = location{
 		HumanName:  human,
 		StableName: stable,
-	})
-
-	if len(spgm.Inlines) > 0 {
-		files := lr.Files()
-		for i := len(spgm.Inlines) - 1; i >= 0; i-- {
-			er := spgm.Inlines[i]
-			fileIdx, ok := er.entry.Val(dwarf.AttrCallFile).(int64)
-			if !ok || fileIdx >= int64(len(files)) || !offsetInRanges(er.ranges, offset) {
-				continue
-			}
+	}
 
-			file := files[fileIdx]
-			line, _ := er.entry.Val(dwarf.AttrCallLine).(int64)
-			col, _ := er.entry.Val(dwarf.AttrCallLine).(int64)
-			human, stable := d.namesForSubprogram(er.entry, nil)
-			locations = append(locations, location{
-				File:       file.Name,
-				Line:       line,
-				Column:     col,
-				Inlined:    true,
-				StableName: stable,
-				HumanName:  human,
-			})
+	files := lr.Files()
+	for i, in := range chain {
+		caller := &locations[i]
+		caller.File, caller.Line, caller.Column = callSite(in.entry, files)
+
+		human, stable := d.namesForSubprogram(in.entry, nil)
+		locations[i+1] = location{
+			Inlined:    true,
+			HumanName:  human,
+			StableName: stable,
 		}
 	}
 
+	leaf := &locations[len(chain)]
+	leaf.File = le.File.Name
+	leaf.Line = int64(le.Line)
+	leaf.Column = int64(le.Column)
 	return offset, locations
 }
 
+// inlinedChain returns the inlined subroutines containing offset, starting
+// with the outermost one.
+func inlinedChain(inlines []entryRanges, offset uint64) []*entryRanges {
+	var chain []*entryRanges
+	for i := 0; i < len(inlines); i++ {
+		if offsetInRanges(inlines[i].ranges, offset) {
+			chain = append(chain, &inlines[i])
+			inlines, i = inlines[i].inlines, -1
+		}
+	}
+	return chain
+}
+
+// callSite returns the location of the call to the function that the inlined
+// subroutine e was inlined from.
+func callSite(e *dwarf.Entry, files []*dwarf.LineFile) (file string, line, column int64) {
+	if i, ok := e.Val(dwarf.AttrCallFile).(int64); ok && i >= 0 && i < int64(len(files)) && files[i] != nil {
+		file = files[i].Name
+	}
+	line, _ = e.Val(dwarf.AttrCallLine).(int64)
+	column, _ = e.Val(dwarf.AttrCallColumn).(int64)
+	return file, line, column
+}
+
 func offsetInRanges(ranges []sourceOffsetRange, offset uint64) bool {
 	for _, x := range ranges {
-		if x[0] <= offset && offset <= x[1] {
+		// The high bound of DWARF ranges is exclusive, which matters to
+		// select the right one between adjacent inlined subroutines.
+		if x[0] <= offset && offset < x[1] {
 			return true
 		}
 	}
@@ -412,3 +568,100 @@ func (d *dwarfmapper) namesForSubprogram(e *dwarf.Entry, spgm *subprogram) (stri
 
 	return name, stableName
 }
+
+// dwarfStructs holds struct types read from the DWARF type information, which
+// is used to access the structs of language runtimes in memory when their
+// layout depends on the version or configuration of the runtime.
+type dwarfStructs struct {
+	types map[string]*dwarf.StructType
+	err   error // first struct or field that could not be found
+}
+
+// Structs returns the struct types with the given names. The names are struct
+// tags or typedefs of structs (e.g. for anonymous structs).
+func (d *dwarfparser) Structs(names ...string) *dwarfStructs {
+	types := make(map[string]*dwarf.StructType, len(names))
+	for _, name := range names {
+		types[name] = nil
+	}
+
+	for {
+		ent, err := d.r.Next()
+		if err != nil || ent == nil {
+			break
+		}
+		switch ent.Tag {
+		case dwarf.TagStructType, dwarf.TagTypedef:
+		case dwarf.TagSubprogram:
+			d.r.SkipChildren()
+			continue
+		default:
+			continue
+		}
+		name, _ := ent.Val(dwarf.AttrName).(string)
+		if s, ok := types[name]; !ok || s != nil {
+			continue
+		}
+		t, err := d.d.Type(ent.Offset)
+		if err != nil {
+			continue
+		}
+		if s := dwarfStructType(t); s != nil && !s.Incomplete {
+			types[name] = s
+		}
+	}
+
+	return &dwarfStructs{types: types}
+}
+
+func dwarfStructType(t dwarf.Type) *dwarf.StructType {
+	for {
+		switch x := t.(type) {
+		case *dwarf.TypedefType:
+			t = x.Type
+		case *dwarf.StructType:
+			return x
+		default:
+			return nil
+		}
+	}
+}
+
+// offset returns the offset of a field of the struct. The path of nested
+// fields is separated by dots (e.g. "location.label").
+func (s *dwarfStructs) offset(name, path string) ptr32 {
+	t := s.types[name]
+	offset := int64(0)
+	for _, fieldName := range strings.Split(path, ".") {
+		var field *dwarf.StructField
+		if t != nil {
+			for _, f := range t.Field {
+				if f.Name == fieldName {
+					field = f
+					break
+				}
+			}
+		}
+		if field == nil {
+			if s.err == nil {
+				s.err = fmt.Errorf("dwarf: could not find %s.%s", name, path)
+			}
+			return 0
+		}
+		offset += field.ByteOffset
+		t = dwarfStructType(field.Type)
+	}
+	return ptr32(offset)
+}
+
+// size returns the size of the struct.
+func (s *dwarfStructs) size(name string) ptr32 {
+	t := s.types[name]
+	if t == nil || t.ByteSize <= 0 {
+		if s.err == nil {
+			s.err = fmt.Errorf("dwarf: could not find the size of %s", name)
+		}
+		return 0
+	}
+	return ptr32(t.ByteSize)
+}
@@ -0,0 +1,51 @@
+package wzprof
+
+import (
+	"debug/dwarf"
+	"testing"
+)
+
+func TestInlinedChain(t *testing.T) {
+	first := &dwarf.Entry{Offset: 1}
+	outer := &dwarf.Entry{Offset: 2}
+	inner := &dwarf.Entry{Offset: 3}
+
+	inlines := []entryRanges{
+		{
+			entry:  first,
+			ranges: []sourceOffsetRange{{0, 10}},
+		},
+		{
+			entry:  outer,
+			ranges: []sourceOffsetRange{{10, 30}},
+			inlines: []entryRanges{{
+				entry:  inner,
+				ranges: []sourceOffsetRange{{15, 20}},
+			}},
+		},
+	}
+
+	tests := []struct {
+		offset uint64
+		chain  []*dwarf.Entry
+	}{
+		{offset: 5, chain: []*dwarf.Entry{first}},
+		{offset: 10, chain: []*dwarf.Entry{outer}},
+		{offset: 17, chain: []*dwarf.Entry{outer, inner}},
+		{offset: 20, chain: []*dwarf.Entry{outer}},
+		{offset: 30, chain: nil},
+	}
+
+	for _, test := range tests {
+		chain := inlinedChain(inlines, test.offset)
+		if len(chain) != len(test.chain) {
+			t.Errorf("offset %d: wrong number of inlined subroutines: want=%d got=%d", test.offset, len(test.chain), len(chain))
+			continue
+		}
+		for i, in := range chain {
+			if in.entry != test.chain[i] {
+				t.Errorf("offset %d: wrong inlined subroutine at index %d: want=%d got=%d", test.offset, i, test.chain[i].Offset, in.entry.Offset)
+			}
+		}
+	}
+}
@@ -0,0 +1,337 @@
+package wzprof
+
+import (
+	"bufio"
+	"encoding/binary"
+	"io"
+	"math"
+	"sort"
+
+	"github.com/google/pprof/profile"
+)
+
+// streamProfile writes a profile of the samples to w in the protobuf format of
+// pprof, without building the profile in memory. The fields of prof other
+// than the samples, locations and functions are written to the profile (see
+// newProfileHeader), and the values of samples are scaled by ratios like
+// buildProfile does.
+//
+// The samples are encoded and written as they are converted, the memory used
+// by the function is bounded by the number of distinct locations of the call
+// stacks rather than the number of samples. The locations and functions are
+// written last, since the symbolization of later samples may still correct
+// the names of functions.
+func streamProfile[T sampleType](w io.Writer, p *Profiling, prof *profile.Profile, samples map[uint64]T, ratios []float64) error {
+	e := newProfileEncoder(w)
+	e.header(prof)
+
+	n := len(prof.SampleType)
+	values := make([]int64, n)
+	b := newProfileBuilder(p, samples)
+	for _, sample := range samples {
+		s := b.sample(sample, n)
+		// Samples of which all values are zero after scaling are dropped,
+		// like Profile.ScaleN does.
+		keep := false
+		for i, v := range s.Value {
+			if ratios[i] != 1 {
+				v = int64(math.Round(float64(v) * ratios[i]))
+			}
+			values[i] = v
+			keep = keep || v != 0
+		}
+		if keep {
+			s.Value = values
+			e.sample(s)
+		}
+		if e.err != nil {
+			return e.err
+		}
+	}
+
+	b.finish(prof)
+	for _, loc := range prof.Location {
+		e.location(loc)
+	}
+	for _, fn := range prof.Function {
+		e.function(fn)
+	}
+	prof.Location, prof.Function = nil, nil
+	return e.flush()
+}
+
+// Field numbers of the messages of profile.proto.
+const (
+	pbProfileSampleType        = 1
+	pbProfileSample            = 2
+	pbProfileMapping           = 3
+	pbProfileLocation          = 4
+	pbProfileFunction          = 5
+	pbProfileStringTable       = 6
+	pbProfileTimeNanos         = 9
+	pbProfileDurationNanos     = 10
+	pbProfilePeriodType        = 11
+	pbProfilePeriod            = 12
+	pbProfileComment           = 13
+	pbProfileDefaultSampleType = 14
+
+	pbValueTypeType = 1
+	pbValueTypeUnit = 2
+
+	pbSampleLocationID = 1
+	pbSampleValue      = 2
+	pbSampleLabel      = 3
+
+	pbLabelKey     = 1
+	pbLabelStr     = 2
+	pbLabelNum     = 3
+	pbLabelNumUnit = 4
+
+	pbMappingID              = 1
+	pbMappingStart           = 2
+	pbMappingLimit           = 3
+	pbMappingOffset          = 4
+	pbMappingFilename        = 5
+	pbMappingBuildID         = 6
+	pbMappingHasFunctions    = 7
+	pbMappingHasFilenames    = 8
+	pbMappingHasLineNumbers  = 9
+	pbMappingHasInlineFrames = 10
+
+	pbLocationID        = 1
+	pbLocationMappingID = 2
+	pbLocationAddress   = 3
+	pbLocationLine      = 4
+
+	pbLineFunctionID = 1
+	pbLineLine       = 2
+
+	pbFunctionID         = 1
+	pbFunctionName       = 2
+	pbFunctionSystemName = 3
+	pbFunctionFilename   = 4
+	pbFunctionStartLine  = 5
+)
+
+const (
+	wireVarint = 0
+	wireBytes  = 2
+)
+
+// profileEncoder writes the messages of a pprof profile one at a time. The
+// repeated fields of a protobuf message may be interleaved, so the strings are
+// added to the string table as they are first used by the messages.
+type profileEncoder struct {
+	w       *bufio.Writer
+	err     error
+	strings map[string]int64
+	msg     []byte // message being encoded
+	sub     []byte // nested message of msg being encoded
+}
+
+func newProfileEncoder(w io.Writer) *profileEncoder {
+	e := &profileEncoder{
+		w:       bufio.NewWriter(w),
+		strings: make(map[string]int64),
+	}
+	e.string("") // the first string of the table must be empty
+	return e
+}
+
+// string returns the index of s in the string table, writing it to the table
+// if it was not written yet.
+func (e *profileEncoder) string(s string) int64 {
+	if i, ok := e.strings[s]; ok {
+		return i
+	}
+	i := int64(len(e.strings))
+	e.strings[s] = i
+	e.write(pbProfileStringTable, nil, s)
+	return i
+}
+
+// write writes a field of the profile holding the bytes of b, followed by s.
+func (e *profileEncoder) write(field int, b []byte, s string) {
+	if e.err != nil {
+		return
+	}
+	var tag [2 * binary.MaxVarintLen64]byte
+	t := appendTag(tag[:0], field, wireBytes)
+	t = binary.AppendUvarint(t, uint64(len(b)+len(s)))
+	if _, err := e.w.Write(t); err != nil {
+		e.err = err
+		return
+	}
+	if _, err := e.w.Write(b); err != nil {
+		e.err = err
+		return
+	}
+	if _, err := e.w.WriteString(s); err != nil {
+		e.err = err
+	}
+}
+
+func (e *profileEncoder) writeInt(field int, v int64) {
+	if e.err != nil || v == 0 {
+		return
+	}
+	var b [2 * binary.MaxVarintLen64]byte
+	t := appendTag(b[:0], field, wireVarint)
+	t = binary.AppendUvarint(t, uint64(v))
+	if _, err := e.w.Write(t); err != nil {
+		e.err = err
+	}
+}
+
+func (e *profileEncoder) valueType(field int, vt *profile.ValueType) {
+	// The strings must be resolved before encoding the message since they
+	// may be written to the string table.
+	typ, unit := e.string(vt.Type), e.string(vt.Unit)
+	e.msg = appendInt(e.msg[:0], pbValueTypeType, typ)
+	e.msg = appendInt(e.msg, pbValueTypeUnit, unit)
+	e.write(field, e.msg, "")
+}
+
+func (e *profileEncoder) header(prof *profile.Profile) {
+	for _, vt := range prof.SampleType {
+		e.valueType(pbProfileSampleType, vt)
+	}
+	for _, m := range prof.Mapping {
+		e.mapping(m)
+	}
+	e.writeInt(pbProfileTimeNanos, prof.TimeNanos)
+	e.writeInt(pbProfileDurationNanos, prof.DurationNanos)
+	if prof.PeriodType != nil {
+		e.valueType(pbProfilePeriodType, prof.PeriodType)
+	}
+	e.writeInt(pbProfilePeriod, prof.Period)
+	for _, c := range prof.Comments {
+		e.writeInt(pbProfileComment, e.string(c))
+	}
+	if prof.DefaultSampleType != "" {
+		e.writeInt(pbProfileDefaultSampleType, e.string(prof.DefaultSampleType))
+	}
+}
+
+func (e *profileEncoder) sample(s *profile.Sample) {
+	// Labels are sorted so the encoding of profiles is deterministic.
+	var labels []byte
+	keys := make([]string, 0, len(s.Label)+len(s.NumLabel))
+	for k := range s.Label {
+		keys = append(keys, k)
+	}
+	sort.Strings(keys)
+	for _, k := range keys {
+		for _, v := range s.Label[k] {
+			key, str := e.string(k), e.string(v)
+			e.sub = appendInt(e.sub[:0], pbLabelKey, key)
+			e.sub = appendInt(e.sub, pbLabelStr, str)
+			labels = appendBytes(labels, pbSampleLabel, e.sub)
+		}
+	}
+	keys = keys[:0]
+	for k := range s.NumLabel {
+		keys = append(keys, k)
+	}
+	sort.Strings(keys)
+	for _, k := range keys {
+		units := s.NumUnit[k]
+		for i, v := range s.NumLabel[k] {
+			key := e.string(k)
+			unit := int64(0)
+			if i < len(units) {
+				unit = e.string(units[i])
+			}
+			e.sub = appendInt(e.sub[:0], pbLabelKey, key)
+			e.sub = appendInt(e.sub, pbLabelNum, v)
+			e.sub = appendInt(e.sub, pbLabelNumUnit, unit)
+			labels = appendBytes(labels, pbSampleLabel, e.sub)
+		}
+	}
+
+	e.sub = e.sub[:0]
+	for _, loc := range s.Location {
+		e.sub = binary.AppendUvarint(e.sub, loc.ID)
+	}
+	e.msg = appendBytes(e.msg[:0], pbSampleLocationID, e.sub)
+	e.sub = e.sub[:0]
+	for _, v := range s.Value {
+		e.sub = binary.AppendUvarint(e.sub, uint64(v))
+	}
+	e.msg = appendBytes(e.msg, pbSampleValue, e.sub)
+	e.msg = append(e.msg, labels...)
+	e.write(pbProfileSample, e.msg, "")
+}
+
+func (e *profileEncoder) mapping(m *profile.Mapping) {
+	file, buildID := e.string(m.File), e.string(m.BuildID)
+	e.msg = appendInt(e.msg[:0], pbMappingID, int64(m.ID))
+	e.msg = appendInt(e.msg, pbMappingStart, int64(m.Start))
+	e.msg = appendInt(e.msg, pbMappingLimit, int64(m.Limit))
+	e.msg = appendInt(e.msg, pbMappingOffset, int64(m.Offset))
+	e.msg = appendInt(e.msg, pbMappingFilename, file)
+	e.msg = appendInt(e.msg, pbMappingBuildID, buildID)
+	e.msg = appendBool(e.msg, pbMappingHasFunctions, m.HasFunctions)
+	e.msg = appendBool(e.msg, pbMappingHasFilenames, m.HasFilenames)
+	e.msg = appendBool(e.msg, pbMappingHasLineNumbers, m.HasLineNumbers)
+	e.msg = appendBool(e.msg, pbMappingHasInlineFrames, m.HasInlineFrames)
+	e.write(pbProfileMapping, e.msg, "")
+}
+
+func (e *profileEncoder) location(loc *profile.Location) {
+	e.msg = appendInt(e.msg[:0], pbLocationID, int64(loc.ID))
+	if loc.Mapping != nil {
+		e.msg = appendInt(e.msg, pbLocationMappingID, int64(loc.Mapping.ID))
+	}
+	e.msg = appendInt(e.msg, pbLocationAddress, int64(loc.Address))
+	for _, line := range loc.Line {
+		e.sub = appendInt(e.sub[:0], pbLineFunctionID, int64(line.Function.ID))
+		e.sub = appendInt(e.sub, pbLineLine, line.Line)
+		e.msg = appendBytes(e.msg, pbLocationLine, e.sub)
+	}
+	e.write(pbProfileLocation, e.msg, "")
+}
+
+func (e *profileEncoder) function(fn *profile.Function) {
+	name, systemName, filename := e.string(fn.Name), e.string(fn.SystemName), e.string(fn.Filename)
+	e.msg = appendInt(e.msg[:0], pbFunctionID, int64(fn.ID))
+	e.msg = appendInt(e.msg, pbFunctionName, name)
+	e.msg = appendInt(e.msg, pbFunctionSystemName, systemName)
+	e.msg = appendInt(e.msg, pbFunctionFilename, filename)
+	e.msg = appendInt(e.msg, pbFunctionStartLine, fn.StartLine)
+	e.write(pbProfileFunction, e.msg, "")
+}
+
+func (e *profileEncoder) flush() error {
+	if e.err != nil {
+		return e.err
+	}
+	return e.w.Flush()
+}
+
+func appendTag(b []byte, field, wire int) []byte {
+	return binary.AppendUvarint(b, uint64(field)<<3|uint64(wire))
+}
+
+// appendInt appends a varint field to b, omitted if v is zero like the
+// default values of proto3 fields.
+func appendInt(b []byte, field int, v int64) []byte {
+	if v == 0 {
+		return b
+	}
+	b = appendTag(b, field, wireVarint)
+	return binary.AppendUvarint(b, uint64(v))
+}
+
+func appendBool(b []byte, field int, v bool) []byte {
+	if v {
+		return appendInt(b, field, 1)
+	}
+	return b
+}
+
+func appendBytes(b []byte, field int, v []byte) []byte {
+	b = appendTag(b, field, wireBytes)
+	b = binary.AppendUvarint(b, uint64(len(v)))
+	return append(b, v...)
+}
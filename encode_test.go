@@ -0,0 +1,74 @@
+package wzprof
+
+import (
+	"bytes"
+	"context"
+	"testing"
+
+	"github.com/google/pprof/profile"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+	"golang.org/x/exp/slices"
+)
+
+func TestMemoryProfilerWriteProfile(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).MemoryProfiler(InuseMemory(true))
+
+	malloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+	malloc.FunctionName = "malloc"
+
+	module := wazerotest.NewModule(nil, malloc)
+	stack := []experimental.StackFrame{{Function: malloc}}
+	ctx := context.Background()
+
+	def := malloc.Definition()
+	listener := p.NewFunctionListener(def)
+	for i, size := range []uint64{10, 20, 30} {
+		listener.Before(ctx, module, def, []uint64{size}, experimental.NewStackIterator(stack...))
+		listener.After(ctx, module, def, []uint64{uint64(16 * (i + 1))})
+	}
+
+	for _, options := range [][]WriteOption{
+		{Uncompressed(true)},
+		{CompressionLevel(1)},
+	} {
+		b := new(bytes.Buffer)
+		if err := p.WriteProfile(b, 1, options...); err != nil {
+			t.Fatal(err)
+		}
+		got, err := profile.Parse(b)
+		if err != nil {
+			t.Fatal(err)
+		}
+		want := p.NewProfile(1)
+
+		if got.DefaultSampleType != want.DefaultSampleType {
+			t.Errorf("default sample type mismatch: want=%q got=%q", want.DefaultSampleType, got.DefaultSampleType)
+		}
+		if len(got.SampleType) != len(want.SampleType) {
+			t.Fatalf("wrong number of sample types: want=%d got=%d", len(want.SampleType), len(got.SampleType))
+		}
+		for i, vt := range want.SampleType {
+			if *got.SampleType[i] != *vt {
+				t.Errorf("sample type mismatch: want=%v got=%v", *vt, *got.SampleType[i])
+			}
+		}
+		if len(got.Sample) != len(want.Sample) {
+			t.Fatalf("wrong number of samples: want=%d got=%d", len(want.Sample), len(got.Sample))
+		}
+		for i, s := range want.Sample {
+			if !slices.Equal(got.Sample[i].Value, s.Value) {
+				t.Errorf("sample values mismatch: want=%v got=%v", s.Value, got.Sample[i].Value)
+			}
+			if len(got.Sample[i].Location) != len(s.Location) {
+				t.Errorf("wrong number of locations: want=%d got=%d", len(s.Location), len(got.Sample[i].Location))
+			}
+		}
+		if len(got.Function) != len(want.Function) {
+			t.Errorf("wrong number of functions: want=%d got=%d", len(want.Function), len(got.Function))
+		}
+	}
+}
@@ -0,0 +1,265 @@
+package wzprof
+
+import (
+	"encoding/json"
+	"fmt"
+	"io"
+
+	"github.com/google/pprof/profile"
+)
+
+// WriteFirefox writes prof to w in the JSON format of the profiles recorded by
+// the Gecko profiler, so it can be loaded in the Firefox Profiler at
+// https://profiler.firefox.com, which handles larger profiles than the pprof
+// web UI.
+//
+// The samples of the default sample type are converted, weighted by their
+// value: the time values are converted to milliseconds, the other values are
+// sizes in bytes or counts. The samples of each guest thread are written to a
+// separate thread of the Firefox profile when the profile has the thread label.
+// The pprof profiles do not record when the samples were taken, so they are
+// laid out one millisecond apart on the timeline of their thread.
+func WriteFirefox(w io.Writer, prof *profile.Profile) error {
+	index := defaultSampleIndex(prof)
+	if index < 0 {
+		return fmt.Errorf("profile has no sample types")
+	}
+	weightType, scale := firefoxWeight(prof.SampleType[index].Unit)
+
+	file := &firefoxProfile{
+		Meta: firefoxMeta{
+			Version:      firefoxGeckoVersion,
+			Interval:     1,
+			StartTime:    float64(prof.TimeNanos) / 1e6,
+			Product:      "wzprof",
+			Categories:   []firefoxCategory{{Name: "Other", Color: "grey", Subcategories: []string{"Other"}}},
+			MarkerSchema: []struct{}{},
+		},
+		Libs:         []struct{}{},
+		Threads:      []*firefoxThread{},
+		Processes:    []struct{}{},
+		PausedRanges: []struct{}{},
+	}
+
+	threads := make(map[string]*firefoxThreadBuilder)
+	for _, sample := range prof.Sample {
+		value := sample.Value[index]
+		if value == 0 {
+			continue
+		}
+		name := "wasm"
+		if thread := sample.Label["thread"]; len(thread) > 0 {
+			name = "thread " + thread[0]
+		}
+		t := threads[name]
+		if t == nil {
+			t = newFirefoxThreadBuilder(name, len(file.Threads)+1)
+			t.thread.Samples.WeightType = weightType
+			threads[name] = t
+			file.Threads = append(file.Threads, &t.thread)
+		}
+		t.sample(sampleFunctions(sample), float64(value)*scale)
+	}
+	return json.NewEncoder(w).Encode(file)
+}
+
+// firefoxGeckoVersion is the version of the Gecko profile format written by
+// WriteFirefox, the Firefox Profiler upgrades the profiles of older versions
+// when loading them.
+const firefoxGeckoVersion = 27
+
+// firefoxWeight returns the weight type of the samples of the Firefox Profiler
+// for values of the given pprof unit, and the factor converting the values to
+// weights.
+func firefoxWeight(unit string) (string, float64) {
+	switch unit {
+	case "nanoseconds":
+		return "tracing-ms", 1e-6
+	case "microseconds":
+		return "tracing-ms", 1e-3
+	case "milliseconds":
+		return "tracing-ms", 1
+	case "seconds":
+		return "tracing-ms", 1e3
+	case "bytes":
+		return "bytes", 1
+	default:
+		return "samples", 1
+	}
+}
+
+// firefoxThreadBuilder builds the tables of a thread of a Firefox profile,
+// sharing the strings, frames and stacks of its samples.
+type firefoxThreadBuilder struct {
+	thread  firefoxThread
+	strings map[string]int
+	frames  map[*profile.Function]int
+	stacks  map[[2]int]int // prefix (-1 for the root) and frame => stack
+}
+
+func newFirefoxThreadBuilder(name string, tid int) *firefoxThreadBuilder {
+	b := &firefoxThreadBuilder{
+		strings: make(map[string]int),
+		frames:  make(map[*profile.Function]int),
+		stacks:  make(map[[2]int]int),
+	}
+	t := &b.thread
+	t.Name = name
+	t.ProcessType = "default"
+	t.TID = tid
+	t.PID = 1
+	t.Markers.Schema = firefoxMarkerSchema{0, 1, 2, 3, 4, 5}
+	t.Markers.Data = [][]any{}
+	t.Samples.Schema = firefoxSampleSchema{0, 1, 2}
+	t.Samples.Data = [][]any{}
+	t.FrameTable.Schema = firefoxFrameSchema{0, 1, 2, 3, 4, 5, 6, 7}
+	t.FrameTable.Data = [][]any{}
+	t.StackTable.Schema = firefoxStackSchema{0, 1}
+	t.StackTable.Data = [][]any{}
+	t.StringTable = []string{}
+	return b
+}
+
+func (b *firefoxThreadBuilder) string(s string) int {
+	i, ok := b.strings[s]
+	if !ok {
+		i = len(b.thread.StringTable)
+		b.strings[s] = i
+		b.thread.StringTable = append(b.thread.StringTable, s)
+	}
+	return i
+}
+
+func (b *firefoxThreadBuilder) frame(fn *profile.Function) int {
+	i, ok := b.frames[fn]
+	if !ok {
+		i = len(b.thread.FrameTable.Data)
+		b.frames[fn] = i
+		var line any
+		if fn.StartLine != 0 {
+			line = fn.StartLine
+		}
+		// location, relevantForJS, innerWindowID, implementation, line,
+		// column, category, subcategory
+		b.thread.FrameTable.Data = append(b.thread.FrameTable.Data,
+			[]any{b.string(fn.Name), false, 0, nil, line, nil, 0, 0})
+	}
+	return i
+}
+
+// sample adds a sample of the call stack of the functions (starting from the
+// root of the stack) with the given weight.
+func (b *firefoxThreadBuilder) sample(functions []*profile.Function, weight float64) {
+	stack := -1
+	for _, fn := range functions {
+		key := [2]int{stack, b.frame(fn)}
+		i, ok := b.stacks[key]
+		if !ok {
+			i = len(b.thread.StackTable.Data)
+			b.stacks[key] = i
+			var prefix any
+			if stack >= 0 {
+				prefix = stack
+			}
+			b.thread.StackTable.Data = append(b.thread.StackTable.Data, []any{prefix, key[1]})
+		}
+		stack = i
+	}
+	var stackIndex any
+	if stack >= 0 {
+		stackIndex = stack
+	}
+	time := len(b.thread.Samples.Data)
+	b.thread.Samples.Data = append(b.thread.Samples.Data, []any{stackIndex, time, weight})
+}
+
+// Types below are a subset of the Gecko profile format, see
+// https://github.com/firefox-devtools/profiler/blob/main/src/types/gecko-profile.js
+type firefoxProfile struct {
+	Meta         firefoxMeta      `json:"meta"`
+	Libs         []struct{}       `json:"libs"`
+	Threads      []*firefoxThread `json:"threads"`
+	Processes    []struct{}       `json:"processes"`
+	PausedRanges []struct{}       `json:"pausedRanges"`
+}
+
+type firefoxMeta struct {
+	Version      int               `json:"version"`
+	Interval     float64           `json:"interval"`
+	StartTime    float64           `json:"startTime"`
+	ShutdownTime *float64          `json:"shutdownTime"`
+	ProcessType  int               `json:"processType"`
+	Product      string            `json:"product"`
+	Stackwalk    int               `json:"stackwalk"`
+	Debug        int               `json:"debug"`
+	GCPoison     int               `json:"gcpoison"`
+	AsyncStack   int               `json:"asyncstack"`
+	Categories   []firefoxCategory `json:"categories"`
+	MarkerSchema []struct{}        `json:"markerSchema"`
+}
+
+type firefoxCategory struct {
+	Name          string   `json:"name"`
+	Color         string   `json:"color"`
+	Subcategories []string `json:"subcategories"`
+}
+
+type firefoxThread struct {
+	Name           string   `json:"name"`
+	ProcessType    string   `json:"processType"`
+	TID            int      `json:"tid"`
+	PID            int      `json:"pid"`
+	RegisterTime   float64  `json:"registerTime"`
+	UnregisterTime *float64 `json:"unregisterTime"`
+	Markers        struct {
+		Schema firefoxMarkerSchema `json:"schema"`
+		Data   [][]any             `json:"data"`
+	} `json:"markers"`
+	Samples struct {
+		Schema     firefoxSampleSchema `json:"schema"`
+		WeightType string              `json:"weightType"`
+		Data       [][]any             `json:"data"`
+	} `json:"samples"`
+	FrameTable struct {
+		Schema firefoxFrameSchema `json:"schema"`
+		Data   [][]any            `json:"data"`
+	} `json:"frameTable"`
+	StackTable struct {
+		Schema firefoxStackSchema `json:"schema"`
+		Data   [][]any            `json:"data"`
+	} `json:"stackTable"`
+	StringTable []string `json:"stringTable"`
+}
+
+// The schemas map the names of the columns of the tables to their index in
+// the rows of data.
+type firefoxMarkerSchema struct {
+	Name      int `json:"name"`
+	StartTime int `json:"startTime"`
+	EndTime   int `json:"endTime"`
+	Phase     int `json:"phase"`
+	Category  int `json:"category"`
+	Data      int `json:"data"`
+}
+
+type firefoxSampleSchema struct {
+	Stack  int `json:"stack"`
+	Time   int `json:"time"`
+	Weight int `json:"weight"`
+}
+
+type firefoxFrameSchema struct {
+	Location       int `json:"location"`
+	RelevantForJS  int `json:"relevantForJS"`
+	InnerWindowID  int `json:"innerWindowID"`
+	Implementation int `json:"implementation"`
+	Line           int `json:"line"`
+	Column         int `json:"column"`
+	Category       int `json:"category"`
+	Subcategory    int `json:"subcategory"`
+}
+
+type firefoxStackSchema struct {
+	Prefix int `json:"prefix"`
+	Frame  int `json:"frame"`
+}
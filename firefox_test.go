@@ -0,0 +1,56 @@
+package wzprof
+
+import (
+	"bytes"
+	"encoding/json"
+	"math"
+	"reflect"
+	"testing"
+)
+
+func TestWriteFirefox(t *testing.T) {
+	b := new(bytes.Buffer)
+	if err := WriteFirefox(b, newTestProfile()); err != nil {
+		t.Fatal(err)
+	}
+
+	var file firefoxProfile
+	if err := json.Unmarshal(b.Bytes(), &file); err != nil {
+		t.Fatal(err)
+	}
+	if len(file.Threads) != 1 {
+		t.Fatalf("threads mismatch: want=1 got=%d", len(file.Threads))
+	}
+	thread := file.Threads[0]
+
+	var names []string
+	for _, frame := range thread.FrameTable.Data {
+		names = append(names, thread.StringTable[int(frame[0].(float64))])
+	}
+	if want := []string{"main", "a", "b", "c"}; !reflect.DeepEqual(names, want) {
+		t.Errorf("frames mismatch: want=%v got=%v", want, names)
+	}
+
+	want := [][]any{{nil, 0.0}, {0.0, 1.0}, {1.0, 2.0}, {0.0, 3.0}}
+	if !reflect.DeepEqual(thread.StackTable.Data, want) {
+		t.Errorf("stacks mismatch: want=%v got=%v", want, thread.StackTable.Data)
+	}
+
+	if thread.Samples.WeightType != "tracing-ms" {
+		t.Errorf("weight type mismatch: want=tracing-ms got=%s", thread.Samples.WeightType)
+	}
+	var stacks []float64
+	var weights []float64
+	for _, sample := range thread.Samples.Data {
+		stacks = append(stacks, sample[0].(float64))
+		weights = append(weights, sample[2].(float64))
+	}
+	if want := []float64{2, 1, 3}; !reflect.DeepEqual(stacks, want) {
+		t.Errorf("samples mismatch: want=%v got=%v", want, stacks)
+	}
+	for i, want := range []float64{10e-6, 20e-6, 30e-6} {
+		if math.Abs(weights[i]-want) > 1e-12 {
+			t.Errorf("weight %d mismatch: want=%g got=%g", i, want, weights[i])
+		}
+	}
+}
@@ -0,0 +1,233 @@
+package wzprof
+
+import (
+	"encoding/json"
+	"fmt"
+	"html"
+	"io"
+	"sort"
+
+	"github.com/google/pprof/profile"
+)
+
+// WriteFlameGraph renders prof as a self-contained interactive HTML flame
+// graph, which can be opened in a browser without any other tool.
+//
+// The flame graph represents the values of the default sample type of the
+// profile, which is the last one unless prof.DefaultSampleType is set.
+func WriteFlameGraph(w io.Writer, prof *profile.Profile) error {
+	index := defaultSampleIndex(prof)
+	if index < 0 {
+		return fmt.Errorf("profile has no sample types")
+	}
+
+	root := &flameNode{Name: "all"}
+	for _, sample := range prof.Sample {
+		value := sample.Value[index]
+		if value == 0 {
+			continue
+		}
+		node := root
+		node.Value += value
+		for _, name := range sampleFrames(sample) {
+			node = node.child(name)
+			node.Value += value
+		}
+	}
+	root.sort()
+
+	data, err := json.Marshal(root)
+	if err != nil {
+		return err
+	}
+
+	sampleType := prof.SampleType[index]
+	title := html.EscapeString(sampleType.Type)
+	unit, err := json.Marshal(sampleType.Unit)
+	if err != nil {
+		return err
+	}
+
+	for _, s := range []string{
+		flameGraphHeader,
+		"<title>", title, "</title>\n",
+		flameGraphStyle,
+		"</head>\n<body>\n<h3>", title, "</h3>\n",
+		flameGraphBody,
+		"<script>\nconst data = ", string(data), ";\nconst unit = ", string(unit), ";\n",
+		flameGraphScript,
+		"</script>\n</body>\n</html>\n",
+	} {
+		if _, err := io.WriteString(w, s); err != nil {
+			return err
+		}
+	}
+	return nil
+}
+
+type flameNode struct {
+	Name     string       `json:"name"`
+	Value    int64        `json:"value"`
+	Children []*flameNode `json:"children,omitempty"`
+
+	index map[string]*flameNode
+}
+
+func (n *flameNode) child(name string) *flameNode {
+	c := n.index[name]
+	if c == nil {
+		if n.index == nil {
+			n.index = make(map[string]*flameNode)
+		}
+		c = &flameNode{Name: name}
+		n.index[name] = c
+		n.Children = append(n.Children, c)
+	}
+	return c
+}
+
+func (n *flameNode) sort() {
+	sort.Slice(n.Children, func(i, j int) bool {
+		return n.Children[i].Name < n.Children[j].Name
+	})
+	for _, c := range n.Children {
+		c.sort()
+	}
+}
+
+// defaultSampleIndex returns the index of the sample type that tools should
+// present by default for prof, following the conventions of pprof. The
+// function returns -1 if the profile has no sample types.
+func defaultSampleIndex(prof *profile.Profile) int {
+	if prof.DefaultSampleType != "" {
+		for i, t := range prof.SampleType {
+			if t.Type == prof.DefaultSampleType {
+				return i
+			}
+		}
+	}
+	return len(prof.SampleType) - 1
+}
+
+// sampleFrames returns the names of functions in the call stack of a sample,
+// starting from the root of the stack. Inlined functions are expanded into
+// their own frames.
+func sampleFrames(sample *profile.Sample) []string {
+	functions := sampleFunctions(sample)
+	frames := make([]string, len(functions))
+	for i, fn := range functions {
+		frames[i] = fn.Name
+	}
+	return frames
+}
+
+// sampleFunctions is like sampleFrames but returns the functions instead of
+// their names.
+func sampleFunctions(sample *profile.Sample) []*profile.Function {
+	var functions []*profile.Function
+	for i := len(sample.Location) - 1; i >= 0; i-- {
+		lines := sample.Location[i].Line
+		// The last line of a location is the caller that the previous lines
+		// were inlined into.
+		for j := len(lines) - 1; j >= 0; j-- {
+			functions = append(functions, lines[j].Function)
+		}
+	}
+	return functions
+}
+
+const flameGraphHeader = `<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+`
+
+const flameGraphStyle = `<style>
+body {
+	font-family: sans-serif;
+	margin: 10px;
+}
+#details {
+	font-size: 13px;
+	height: 20px;
+	margin: 5px 0;
+	overflow: hidden;
+	white-space: nowrap;
+}
+#chart {
+	position: relative;
+	width: 100%;
+}
+.frame {
+	border: 1px solid #fff;
+	box-sizing: border-box;
+	cursor: pointer;
+	font-size: 12px;
+	height: 18px;
+	line-height: 16px;
+	overflow: hidden;
+	padding: 0 3px;
+	position: absolute;
+	white-space: nowrap;
+}
+</style>
+`
+
+const flameGraphBody = `<div id="details">Click on a frame to zoom in, click on the bottom frame to zoom out.</div>
+<div id="chart"></div>
+`
+
+const flameGraphScript = `const chart = document.getElementById("chart");
+const details = document.getElementById("details");
+const rowHeight = 18;
+
+function color(name) {
+	let h = 0;
+	for (let i = 0; i < name.length; i++) {
+		h = (h * 31 + name.charCodeAt(i)) >>> 0;
+	}
+	return "hsl(" + (10 + h % 40) + "," + (60 + h % 30) + "%," + (55 + h % 15) + "%)";
+}
+
+function depth(node) {
+	let d = 0;
+	for (const c of node.children || []) {
+		d = Math.max(d, depth(c));
+	}
+	return d + 1;
+}
+
+function render(root) {
+	chart.innerHTML = "";
+	const height = depth(root) * rowHeight;
+	chart.style.height = height + "px";
+
+	const walk = (node, x, level) => {
+		const width = node.value / root.value * 100;
+		if (width < 0.05) {
+			return;
+		}
+		const div = document.createElement("div");
+		const percent = (node.value / data.value * 100).toFixed(2);
+		div.className = "frame";
+		div.style.left = x + "%";
+		div.style.width = width + "%";
+		div.style.top = (height - (level + 1) * rowHeight) + "px";
+		div.style.background = color(node.name);
+		div.textContent = node.name;
+		div.title = node.name + " (" + node.value + " " + unit + ", " + percent + "%)";
+		div.onmouseover = () => { details.textContent = div.title; };
+		div.onclick = () => { render(node === root ? data : node); };
+		chart.appendChild(div);
+
+		let offset = x;
+		for (const c of node.children || []) {
+			walk(c, offset, level + 1);
+			offset += c.value / root.value * 100;
+		}
+	};
+	walk(root, 0, 0);
+}
+
+render(data);
+`
@@ -0,0 +1,57 @@
+package wzprof
+
+import (
+	"bytes"
+	"strings"
+	"testing"
+
+	"github.com/google/pprof/profile"
+)
+
+// newTestProfile returns a CPU profile recording the following samples:
+//
+//	main;a;b 1 10
+//	main;a   2 20
+//	main;c   3 30
+func newTestProfile() *profile.Profile {
+	fnMain := &profile.Function{ID: 1, Name: "main"}
+	fnA := &profile.Function{ID: 2, Name: "a"}
+	fnB := &profile.Function{ID: 3, Name: "b"}
+	fnC := &profile.Function{ID: 4, Name: "c"}
+
+	locMain := &profile.Location{ID: 1, Line: []profile.Line{{Function: fnMain}}}
+	// b is inlined in a.
+	locAB := &profile.Location{ID: 2, Line: []profile.Line{{Function: fnB}, {Function: fnA}}}
+	locA := &profile.Location{ID: 3, Line: []profile.Line{{Function: fnA}}}
+	locC := &profile.Location{ID: 4, Line: []profile.Line{{Function: fnC}}}
+
+	return &profile.Profile{
+		SampleType: []*profile.ValueType{
+			{Type: "samples", Unit: "count"},
+			{Type: "cpu", Unit: "nanoseconds"},
+		},
+		PeriodType: &profile.ValueType{Type: "samples", Unit: "count"},
+		Period:     1,
+		Sample: []*profile.Sample{
+			{Location: []*profile.Location{locAB, locMain}, Value: []int64{1, 10}},
+			{Location: []*profile.Location{locA, locMain}, Value: []int64{2, 20}},
+			{Location: []*profile.Location{locC, locMain}, Value: []int64{3, 30}},
+		},
+		Location: []*profile.Location{locMain, locAB, locA, locC},
+		Function: []*profile.Function{fnMain, fnA, fnB, fnC},
+	}
+}
+
+func TestWriteFlameGraph(t *testing.T) {
+	b := new(bytes.Buffer)
+	if err := WriteFlameGraph(b, newTestProfile()); err != nil {
+		t.Fatal(err)
+	}
+
+	const tree = `{"name":"all","value":60,"children":[{"name":"main","value":60,"children":[` +
+		`{"name":"a","value":30,"children":[{"name":"b","value":10}]},{"name":"c","value":30}]}]}`
+
+	if !strings.Contains(b.String(), tree) {
+		t.Errorf("flame graph does not contain the expected tree:\n%s", b.String())
+	}
+}
@@ -0,0 +1,53 @@
+package wzprof
+
+import (
+	"bufio"
+	"fmt"
+	"io"
+	"sort"
+	"strings"
+
+	"github.com/google/pprof/profile"
+)
+
+// WriteFolded writes prof to w in the folded stacks format popularized by
+// Brendan Gregg's FlameGraph tools, which is also understood by speedscope.
+//
+// Each line of the output represents a call stack, with function names
+// separated by semicolons starting from the root, followed by a space and the
+// value of the default sample type of the profile:
+//
+//	main;a;b 123
+//
+// Samples with identical stacks are aggregated, and lines are sorted so the
+// output is stable for a given profile.
+//
+// The profiles of the profilers are built with their sample rate first, for
+// example:
+//
+//	err := wzprof.WriteFolded(w, mem.NewProfile(sampleRate))
+func WriteFolded(w io.Writer, prof *profile.Profile) error {
+	index := defaultSampleIndex(prof)
+	if index < 0 {
+		return fmt.Errorf("profile has no sample types")
+	}
+
+	values := make(map[string]int64, len(prof.Sample))
+	for _, sample := range prof.Sample {
+		if value := sample.Value[index]; value != 0 {
+			values[strings.Join(sampleFrames(sample), ";")] += value
+		}
+	}
+
+	stacks := make([]string, 0, len(values))
+	for stack := range values {
+		stacks = append(stacks, stack)
+	}
+	sort.Strings(stacks)
+
+	b := bufio.NewWriter(w)
+	for _, stack := range stacks {
+		fmt.Fprintf(b, "%s %d\n", stack, values[stack])
+	}
+	return b.Flush()
+}
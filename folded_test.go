@@ -0,0 +1,21 @@
+package wzprof
+
+import (
+	"bytes"
+	"testing"
+)
+
+func TestWriteFolded(t *testing.T) {
+	b := new(bytes.Buffer)
+	if err := WriteFolded(b, newTestProfile()); err != nil {
+		t.Fatal(err)
+	}
+
+	const want = `main;a 20
+main;a;b 10
+main;c 30
+`
+	if b.String() != want {
+		t.Errorf("folded stacks mismatch:\nwant:\n%s\ngot:\n%s", want, b.String())
+	}
+}
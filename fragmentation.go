@@ -0,0 +1,257 @@
+package wzprof
+
+import (
+	"bufio"
+	"fmt"
+	"io"
+	"sort"
+
+	"github.com/tetratelabs/wazero/api"
+)
+
+// FragmentationReport describes how the objects in use by a program are laid
+// out in its linear memory, see MemoryProfiler.Fragmentation.
+//
+// WebAssembly memories never shrink, and allocators usually only return memory
+// to the top of the heap, so a few objects at high addresses keep the memory
+// below them reserved even when most of it is free. The report shows where the
+// free memory is, and the allocation sites of the objects pinning the highest
+// addresses, which helps reducing the memory growth of programs.
+//
+// The report only knows about the objects observed by the memory profiler, the
+// metadata of the allocators (e.g. the headers of the blocks of malloc) and
+// the memory that they reserve for future allocations are accounted as free.
+type FragmentationReport struct {
+	// Size of the linear memory and of the objects in use, in bytes.
+	Memory int64
+	Inuse  int64
+	// Number of objects in use.
+	Objects int
+	// Regions of equal size splitting the memory from the page of the first
+	// object in use to the end of the linear memory.
+	Regions []MemoryRegion
+	// Largest ranges of memory without objects in use after the first one,
+	// sorted by decreasing size.
+	Gaps []MemoryRange
+	// Allocation sites of the objects at the highest addresses, sorted by
+	// decreasing address of their highest object.
+	Sites []AllocationSite
+}
+
+// MemoryRange is a range of addresses of the linear memory.
+type MemoryRange struct {
+	Start, End uint64
+}
+
+// Size returns the number of bytes of the range.
+func (r MemoryRange) Size() int64 { return int64(r.End - r.Start) }
+
+// MemoryRegion is a region of the linear memory of a FragmentationReport.
+type MemoryRegion struct {
+	MemoryRange
+	// Bytes of the region used by objects, and number of objects starting
+	// in the region.
+	Inuse   int64
+	Objects int
+}
+
+// AllocationSite is a call stack allocating objects which are still in use.
+type AllocationSite struct {
+	// Names of the functions of the call stack, starting with the innermost
+	// function.
+	Stack []string
+	// End of the object at the highest address allocated by the call stack.
+	Highest uint64
+	// Number and size of the objects in use allocated by the call stack.
+	Objects int
+	Inuse   int64
+}
+
+const (
+	fragmentationRegions = 16
+	fragmentationGaps    = 10
+	fragmentationSites   = 10
+)
+
+// Fragmentation returns a report of the fragmentation of the linear memory by
+// the objects in use. The report only holds the objects observed by the
+// profiler, it should not be wrapped by Sample.
+//
+// The method returns nil if the memory in use is not tracked (see InuseMemory).
+func (p *MemoryProfiler) Fragmentation() *FragmentationReport {
+	type object struct {
+		addr uint64
+		memoryAllocation
+	}
+	p.mutex.Lock()
+	if p.inuse == nil {
+		p.mutex.Unlock()
+		return nil
+	}
+	objects := make([]object, 0, len(p.inuse))
+	for addr, inuse := range p.inuse {
+		objects = append(objects, object{uint64(addr), inuse})
+	}
+	p.mutex.Unlock()
+	sort.Slice(objects, func(i, j int) bool { return objects[i].addr < objects[j].addr })
+
+	report := &FragmentationReport{Memory: p.p.memorySize(), Objects: len(objects)}
+	if len(objects) == 0 {
+		return report
+	}
+	start := objects[0].addr &^ (wasmPageSize - 1)
+	end := start
+	for _, obj := range objects {
+		report.Inuse += int64(obj.size)
+		if e := obj.addr + uint64(obj.size); e > end {
+			end = e
+		}
+	}
+	if memory := uint64(report.Memory); memory > end {
+		end = memory
+	} else {
+		report.Memory = int64(end)
+	}
+	if end == start {
+		return report // only empty objects
+	}
+
+	regionSize := (end - start + fragmentationRegions - 1) / fragmentationRegions
+	for addr := start; addr < end; addr += regionSize {
+		r := MemoryRegion{MemoryRange: MemoryRange{addr, addr + regionSize}}
+		if r.End > end {
+			r.End = end
+		}
+		report.Regions = append(report.Regions, r)
+	}
+
+	sites := make(map[*stackCounter]*AllocationSite)
+	free := objects[0].addr
+	for _, obj := range objects {
+		objEnd := obj.addr + uint64(obj.size)
+		if obj.addr > free {
+			report.Gaps = append(report.Gaps, MemoryRange{free, obj.addr})
+		}
+		if objEnd > free {
+			free = objEnd
+		}
+
+		// The bytes of objects spanning multiple regions are split
+		// between them.
+		i := int((obj.addr - start) / regionSize)
+		if i == len(report.Regions) {
+			i-- // empty object at the end of the memory
+		}
+		report.Regions[i].Objects++
+		for ; i < len(report.Regions) && report.Regions[i].Start < objEnd; i++ {
+			r := &report.Regions[i]
+			r.Inuse += int64(minUint64(objEnd, r.End) - maxUint64(obj.addr, r.Start))
+		}
+
+		site := sites[obj.stackCounter]
+		if site == nil {
+			site = new(AllocationSite)
+			sites[obj.stackCounter] = site
+		}
+		site.Objects++
+		site.Inuse += int64(obj.size)
+		if objEnd > site.Highest {
+			site.Highest = objEnd
+		}
+	}
+	if end > free {
+		report.Gaps = append(report.Gaps, MemoryRange{free, end})
+	}
+	sort.SliceStable(report.Gaps, func(i, j int) bool { return report.Gaps[i].Size() > report.Gaps[j].Size() })
+	if len(report.Gaps) > fragmentationGaps {
+		report.Gaps = report.Gaps[:fragmentationGaps]
+	}
+
+	counters := make([]*stackCounter, 0, len(sites))
+	for sc := range sites {
+		counters = append(counters, sc)
+	}
+	sort.Slice(counters, func(i, j int) bool { return sites[counters[i]].Highest > sites[counters[j]].Highest })
+	if len(counters) > fragmentationSites {
+		counters = counters[:fragmentationSites]
+	}
+	samples := make(map[uint64]*memorySample, len(counters))
+	for i, sc := range counters {
+		samples[uint64(i)] = &memorySample{stack: sc.stack}
+	}
+	b := newProfileBuilder(p.p, samples)
+	for i, sc := range counters {
+		site := sites[sc]
+		for _, loc := range b.sample(samples[uint64(i)], 0).Location {
+			for _, line := range loc.Line {
+				site.Stack = append(site.Stack, line.Function.Name)
+			}
+		}
+		report.Sites = append(report.Sites, *site)
+	}
+	return report
+}
+
+// memorySize returns the size of the largest linear memory of the instances of
+// the module, in bytes.
+func (p *Profiling) memorySize() int64 {
+	size := int64(0)
+	p.threads.ids.Range(func(k, _ any) bool {
+		if mem := k.(api.Module).Memory(); mem != nil && int64(mem.Size()) > size {
+			size = int64(mem.Size())
+		}
+		return true
+	})
+	return size
+}
+
+// WriteText writes the report to w in a human-readable text format.
+func (r *FragmentationReport) WriteText(w io.Writer) error {
+	b := bufio.NewWriter(w)
+	fmt.Fprintf(b, "linear memory: %d bytes, in use: %d bytes (%s) in %d objects\n", r.Memory, r.Inuse, percent(r.Inuse, r.Memory), r.Objects)
+
+	if len(r.Regions) > 0 {
+		fmt.Fprintf(b, "\nregions:\n")
+		for _, region := range r.Regions {
+			fmt.Fprintf(b, "  %#010x-%#010x  %10d / %10d bytes in use (%s)  %d objects\n",
+				region.Start, region.End, region.Inuse, region.Size(), percent(region.Inuse, region.Size()), region.Objects)
+		}
+	}
+	if len(r.Gaps) > 0 {
+		fmt.Fprintf(b, "\nlargest free gaps:\n")
+		for _, gap := range r.Gaps {
+			fmt.Fprintf(b, "  %#010x-%#010x  %10d bytes\n", gap.Start, gap.End, gap.Size())
+		}
+	}
+	if len(r.Sites) > 0 {
+		fmt.Fprintf(b, "\nallocation sites of the objects at the highest addresses:\n")
+		for _, site := range r.Sites {
+			fmt.Fprintf(b, "  up to %#010x  %d objects, %d bytes in use\n", site.Highest, site.Objects, site.Inuse)
+			for _, name := range site.Stack {
+				fmt.Fprintf(b, "      %s\n", name)
+			}
+		}
+	}
+	return b.Flush()
+}
+
+func percent(value, total int64) string {
+	if total == 0 {
+		return "0.0%"
+	}
+	return fmt.Sprintf("%.1f%%", 100*float64(value)/float64(total))
+}
+
+func minUint64(a, b uint64) uint64 {
+	if a < b {
+		return a
+	}
+	return b
+}
+
+func maxUint64(a, b uint64) uint64 {
+	if a > b {
+		return a
+	}
+	return b
+}
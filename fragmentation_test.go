@@ -0,0 +1,72 @@
+package wzprof
+
+import (
+	"context"
+	"strings"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestMemoryProfilerFragmentation(t *testing.T) {
+	if ProfilingFor(nil, AllowUnprepared(true)).MemoryProfiler().Fragmentation() != nil {
+		t.Error("fragmentation report returned without tracking the memory in use")
+	}
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).MemoryProfiler(InuseMemory(true))
+
+	malloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+	malloc.FunctionName = "malloc"
+
+	free := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, addr uint32) {})
+	free.FunctionName = "free"
+
+	module := wazerotest.NewModule(nil, malloc, free)
+	stack := []experimental.StackFrame{{Function: malloc}}
+	ctx := context.Background()
+
+	mallocDef := malloc.Definition()
+	mallocListener := p.NewFunctionListener(mallocDef)
+	for _, addr := range []uint64{0x10000, 0x10100, 0x1ff00} {
+		mallocListener.Before(ctx, module, mallocDef, []uint64{0x100}, experimental.NewStackIterator(stack...))
+		mallocListener.After(ctx, module, mallocDef, []uint64{addr})
+	}
+
+	freeDef := free.Definition()
+	freeListener := p.NewFunctionListener(freeDef)
+	freeListener.Before(ctx, module, freeDef, []uint64{0x10100}, experimental.NewStackIterator(stack...))
+	freeListener.After(ctx, module, freeDef, nil)
+
+	// The module has no memory, the report ends with the highest object.
+	report := p.Fragmentation()
+	if report.Memory != 0x20000 || report.Inuse != 0x200 || report.Objects != 2 {
+		t.Errorf("wrong totals: memory=%#x inuse=%#x objects=%d", report.Memory, report.Inuse, report.Objects)
+	}
+	if len(report.Regions) != fragmentationRegions || report.Regions[0].Start != 0x10000 {
+		t.Fatalf("wrong regions: %+v", report.Regions)
+	}
+	if r := report.Regions[0]; r.Inuse != 0x100 || r.Objects != 1 {
+		t.Errorf("wrong first region: %+v", r)
+	}
+	if gap := (MemoryRange{0x10100, 0x1ff00}); len(report.Gaps) != 1 || report.Gaps[0] != gap {
+		t.Errorf("wrong gaps: want=%v got=%v", []MemoryRange{gap}, report.Gaps)
+	}
+	if len(report.Sites) != 1 {
+		t.Fatalf("wrong number of allocation sites: %d", len(report.Sites))
+	}
+	if site := report.Sites[0]; site.Highest != 0x20000 || site.Objects != 2 || len(site.Stack) != 1 || site.Stack[0] != "malloc" {
+		t.Errorf("wrong allocation site: %+v", site)
+	}
+
+	var b strings.Builder
+	if err := report.WriteText(&b); err != nil {
+		t.Fatal(err)
+	}
+	if !strings.Contains(b.String(), "0x00010100-0x0001ff00") {
+		t.Errorf("gap missing from the report:\n%s", b.String())
+	}
+}
@@ -0,0 +1,113 @@
+package wzprof
+
+import (
+	"context"
+
+	"github.com/google/pprof/profile"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+// GCProfiler is the implementation of a profiler recording the time spent by
+// the garbage collectors of the language runtimes embedded in WebAssembly
+// modules, attributed to the call stacks which triggered the collections.
+//
+// The collectors run in the guest, so their time is mixed with the time of the
+// application in CPU profiles, where it is spread across the call stacks that
+// allocate. The profiler measures calls to the entry points of the collectors:
+// - "runtime.GC" and "runtime.runGC" in TinyGo.
+// - "gc_collect_main", "_PyGC_Collect" and "PyGC_Collect" in CPython.
+// - "sgen_perform_collection" and "mono_gc_collect" in Mono (.NET).
+//
+// Collections of the Go runtime run concurrently with the goroutines of the
+// program and are not recorded.
+//
+// The profiler generates samples of two types:
+// - "collections" counts the number of garbage collections.
+// - "gc" records the time spent collecting garbage (in nanoseconds).
+//
+// The values are cumulative since the creation of the profiler.
+type GCProfiler struct {
+	delayProfiler
+	calls threadLocal[gcCall]
+}
+
+// gcCall is the state of a collection recorded by the listeners of a thread.
+// The entry points may call each other, the collection starts on entry to the
+// outermost one.
+type gcCall struct {
+	depth int
+	start int64
+	trace stackTrace
+}
+
+func newGCProfiler(p *Profiling) *GCProfiler {
+	g := &GCProfiler{}
+	g.init(p, "gc")
+	g.types = []*profile.ValueType{
+		{Type: "collections", Unit: "count"},
+		{Type: "gc", Unit: "nanoseconds"},
+	}
+	return g
+}
+
+// Name returns "gc".
+func (p *GCProfiler) Name() string {
+	return "gc"
+}
+
+// Desc returns a description of the garbage collection profile.
+func (p *GCProfiler) Desc() string {
+	return profileDescriptions[p.Name()]
+}
+
+// NewFunctionListener returns a function listener recording the time spent in
+// calls to the function passed as argument if it is the entry point of a
+// garbage collector, and nil otherwise.
+func (p *GCProfiler) NewFunctionListener(def api.FunctionDefinition) experimental.FunctionListener {
+	// Go programs switch goroutines by unwinding the wasm stack, which
+	// would interrupt the measure of calls to runtime.GC.
+	if p.p.lang == golang || def.GoFunction() != nil || !isGarbageCollection(def.Name()) {
+		return nil
+	}
+	return profilingListener{p.p, gcListener{p}}
+}
+
+func isGarbageCollection(name string) bool {
+	switch name {
+	case "runtime.GC", "runtime.runGC":
+		return true
+	case "gc_collect_main", "_PyGC_Collect", "PyGC_Collect":
+		return true
+	case "sgen_perform_collection", "mono_gc_collect":
+		return true
+	}
+	return false
+}
+
+type gcListener struct{ *GCProfiler }
+
+func (p gcListener) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64, si experimental.StackIterator) {
+	c := p.calls.get(mod)
+	if c.depth++; c.depth == 1 {
+		c.trace = makeStackTrace(ctx, c.trace, p.p.threads.lookup(mod), si, p.p.maxFrames)
+		c.start = p.time()
+	}
+}
+
+func (p gcListener) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64) {
+	c := p.calls.get(mod)
+	if c.depth == 0 {
+		return
+	}
+	if c.depth--; c.depth == 0 {
+		delay := p.time() - c.start
+		if trace, delay, ok := p.p.hookSample(p.name, c.trace, delay); ok {
+			p.counts.observe(mod, trace, delay, p.p.maxStacks)
+		}
+	}
+}
+
+func (p gcListener) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
+	p.After(ctx, mod, def, nil)
+}
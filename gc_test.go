@@ -0,0 +1,70 @@
+package wzprof
+
+import (
+	"context"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestGCProfiler(t *testing.T) {
+	currentTime := int64(0)
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).GCProfiler()
+	p.time = func() int64 { return currentTime }
+
+	runGC := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	runGC.FunctionName = "runtime.runGC"
+	gc := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	gc.FunctionName = "runtime.GC"
+	main := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	main.FunctionName = "main"
+
+	module := wazerotest.NewModule(nil, runGC, gc, main)
+	outer := []experimental.StackFrame{{Function: gc, PC: 1}, {Function: main, PC: 2}}
+	inner := append([]experimental.StackFrame{{Function: runGC, PC: 3}}, outer...)
+	ctx := context.Background()
+
+	listener := gcListener{p}
+	for i := int64(0); i < 2; i++ {
+		// runtime.GC calls runtime.runGC, which is the same collection.
+		currentTime = 10 * i
+		listener.Before(ctx, module, gc.Definition(), nil, experimental.NewStackIterator(outer...))
+		listener.Before(ctx, module, runGC.Definition(), nil, experimental.NewStackIterator(inner...))
+		currentTime = 10*i + 4
+		listener.After(ctx, module, runGC.Definition(), nil)
+		currentTime = 10*i + 5
+		listener.After(ctx, module, gc.Definition(), nil)
+	}
+
+	prof := p.NewProfile(1)
+	if len(prof.SampleType) != 2 || prof.SampleType[0].Type != "collections" || prof.SampleType[1].Type != "gc" {
+		t.Fatalf("wrong sample types: %v", prof.SampleType)
+	}
+	if len(prof.Sample) != 1 {
+		t.Fatalf("wrong number of samples: want=1 got=%d", len(prof.Sample))
+	}
+	s := prof.Sample[0]
+	if collections, delay := s.Value[0], s.Value[1]; collections != 2 || delay != 10 {
+		t.Errorf("wrong values: want=[2 10] got=[%d %d]", collections, delay)
+	}
+	if name := s.Location[0].Line[0].Function.Name; name != "runtime.GC" {
+		t.Errorf("wrong innermost frame: want=runtime.GC got=%s", name)
+	}
+}
+
+func TestIsGarbageCollection(t *testing.T) {
+	for name, want := range map[string]bool{
+		"runtime.runGC":           true,
+		"gc_collect_main":         true,
+		"sgen_perform_collection": true,
+		"runtime.alloc":           false,
+		"malloc":                  false,
+	} {
+		if got := isGarbageCollection(name); got != want {
+			t.Errorf("isGarbageCollection(%q): want=%t got=%t", name, want, got)
+		}
+	}
+}
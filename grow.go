@@ -0,0 +1,165 @@
+package wzprof
+
+import (
+	"context"
+	"net/http"
+	"strings"
+	"time"
+
+	"github.com/google/pprof/profile"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+// wasmPageSize is the size of WebAssembly memory pages.
+const wasmPageSize = 65536
+
+// GrowProfiler is the implementation of a profiler recording the call stacks
+// which caused the linear memory of a WebAssembly module to grow.
+//
+// The size of linear memory never decreases, so the code paths growing it
+// determine how close a program gets to its memory limits, which matters far
+// more than the individual allocations made within the memory already mapped.
+//
+// Function listeners cannot observe the execution of memory.grow instructions,
+// so the profiler measures the size of memory before and after calls to the
+// functions of the language runtimes which execute them:
+// - "sbrk" in wasi-libc and Emscripten, and "emscripten_resize_heap".
+// - "runtime.growMemory" in Go, and "runtime.growHeap" in TinyGo.
+// - the system allocator of dlmalloc in Rust.
+// - "~lib/rt/tlsf/growMemory" in AssemblyScript.
+//
+// The profiler generates samples of two types:
+// - "grows" counts the number of times the memory was grown.
+// - "pages" records the number of pages that the memory was grown by.
+//
+// The values are cumulative since the creation of the profiler.
+type GrowProfiler struct {
+	p      *Profiling
+	counts stackCounterShards
+	calls  threadLocal[growCall]
+	start  time.Time
+}
+
+// growCall is the state recorded by memory growth listeners between calls to
+// Before and After, which is kept for each thread of the program.
+type growCall struct {
+	size  uint32
+	stack stackTrace
+}
+
+func newGrowProfiler(p *Profiling) *GrowProfiler {
+	return &GrowProfiler{
+		p:     p,
+		start: time.Now(),
+	}
+}
+
+// Name returns "memgrow".
+func (p *GrowProfiler) Name() string {
+	return "memgrow"
+}
+
+// Desc returns a description of the memory growth profile.
+func (p *GrowProfiler) Desc() string {
+	return profileDescriptions[p.Name()]
+}
+
+// Count returns the number of call stacks recorded in p.
+func (p *GrowProfiler) Count() int {
+	return p.counts.len()
+}
+
+// SampleType returns the set of value types present in samples recorded by the
+// profiler.
+func (p *GrowProfiler) SampleType() []*profile.ValueType {
+	return []*profile.ValueType{
+		{Type: "grows", Unit: "count"},
+		{Type: "pages", Unit: "count"},
+	}
+}
+
+// NewProfile builds a profile of the memory growth observed since the profiler
+// was created.
+func (p *GrowProfiler) NewProfile(sampleRate float64) *profile.Profile {
+	return p.p.endProfile(p.Name(), p.newProfile(sampleRate))
+}
+
+func (p *GrowProfiler) newProfile(sampleRate float64) *profile.Profile {
+	samples := p.counts.merge(false)
+	samples.compact(p.p.maxStacks, addStackCounters)
+
+	ratio := 1 / sampleRate
+	prof := buildProfile(p.p, samples, p.start, time.Since(p.start), p.SampleType(),
+		[]float64{ratio, ratio},
+	)
+	prof.DefaultSampleType = "pages"
+	return prof
+}
+
+// NewHandler returns a http handler allowing the profiler to be exposed on a
+// pprof-compatible http endpoint.
+//
+// The sample rate is a value between 0 and 1 used to scale the profile results
+// based on the sampling rate applied to the profiler so the resulting values
+// remain representative.
+//
+// When the request has a "seconds" query parameter, the handler responds with
+// a delta profile of the memory growth observed during that period.
+func (p *GrowProfiler) NewHandler(sampleRate float64) http.Handler {
+	return http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		serveCumulativeProfile(w, r, p.p, p.Name(), func() *profile.Profile {
+			return p.newProfile(sampleRate)
+		})
+	})
+}
+
+// NewFunctionListener returns a function listener recording the memory growth
+// caused by calls to the function passed as argument if it grows the memory,
+// and nil otherwise.
+func (p *GrowProfiler) NewFunctionListener(def api.FunctionDefinition) experimental.FunctionListener {
+	if !isMemoryGrow(def.Name()) {
+		return nil
+	}
+	return profilingListener{p.p, growListener{p}}
+}
+
+func isMemoryGrow(name string) bool {
+	switch name {
+	case "sbrk", "emscripten_resize_heap":
+		return true
+	case "runtime.growMemory", "runtime.growHeap":
+		return true
+	case "~lib/rt/tlsf/growMemory":
+		return true
+	}
+	// Rust symbols are mangled, for example the function of older versions
+	// of dlmalloc is _ZN8dlmalloc3sys5alloc17h2ba4b5a11f2a6ed9E, newer ones
+	// implement the method of dlmalloc::Allocator on dlmalloc::sys::System.
+	return strings.Contains(name, "dlmalloc") && strings.Contains(name, "sys") &&
+		strings.Contains(name, "5alloc17h")
+}
+
+type growListener struct{ *GrowProfiler }
+
+func (p growListener) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64, si experimental.StackIterator) {
+	c := p.calls.get(mod)
+	c.size = mod.Memory().Size()
+	c.stack = makeStackTrace(ctx, c.stack, p.p.threads.lookup(mod), si, p.p.maxFrames)
+}
+
+func (p growListener) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64) {
+	c := p.calls.get(mod)
+	// The functions are also called to query the current size of memory
+	// (e.g. sbrk(0)), or may fail to grow it, which are not recorded.
+	if size := mod.Memory().Size(); size > c.size {
+		pages := int64(size-c.size) / wasmPageSize
+		if stack, pages, ok := p.p.hookSample(p.Name(), c.stack, pages); ok {
+			p.counts.observe(mod, stack, pages, p.p.maxStacks)
+		}
+	}
+}
+
+func (p growListener) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
+	p.After(ctx, mod, def, nil)
+}
@@ -0,0 +1,71 @@
+package wzprof
+
+import (
+	"context"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestGrowProfilerFunctions(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).GrowProfiler()
+
+	hasListener := func(name string) bool {
+		fn := wazerotest.NewFunction(func(context.Context, api.Module) {})
+		fn.FunctionName = name
+		wazerotest.NewModule(nil, fn)
+		return p.NewFunctionListener(fn.Definition()) != nil
+	}
+
+	for _, name := range []string{
+		"sbrk",
+		"runtime.growMemory",
+		"runtime.growHeap",
+		"_ZN8dlmalloc3sys5alloc17h2ba4b5a11f2a6ed9E",
+		"_ZN63_$LT$dlmalloc..sys..System$u20$as$u20$dlmalloc..Allocator$GT$5alloc17h8e4d5c0a0a1b2c3dE",
+	} {
+		if !hasListener(name) {
+			t.Errorf("%s: missing function listener", name)
+		}
+	}
+
+	for _, name := range []string{
+		"malloc",
+		"_ZN8dlmalloc17Dlmalloc$LT$A$GT$6malloc17h3b8c1f2d4e5a6b7cE",
+	} {
+		if hasListener(name) {
+			t.Errorf("%s: unexpected function listener", name)
+		}
+	}
+}
+
+func TestGrowProfilerPages(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).GrowProfiler()
+
+	sbrk := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, increment int32) int32 {
+		return 0
+	})
+	sbrk.FunctionName = "sbrk"
+
+	module := wazerotest.NewModule(wazerotest.NewMemory(wazerotest.PageSize), sbrk)
+	stack := []experimental.StackFrame{{Function: sbrk}}
+	ctx := context.Background()
+
+	def := sbrk.Definition()
+	listener := p.NewFunctionListener(def)
+	for _, pages := range []uint32{0, 2, 0, 3} {
+		listener.Before(ctx, module, def, []uint64{uint64(pages * wasmPageSize)}, experimental.NewStackIterator(stack...))
+		module.Memory().Grow(pages)
+		listener.After(ctx, module, def, []uint64{0})
+	}
+
+	prof := p.NewProfile(1)
+	if len(prof.Sample) != 1 {
+		t.Fatalf("wrong number of samples: want=1 got=%d", len(prof.Sample))
+	}
+	if v := prof.Sample[0].Value; v[0] != 2 || v[1] != 5 {
+		t.Errorf("sample values mismatch: want=[2 5] got=%v", v)
+	}
+}
@@ -0,0 +1,66 @@
+package wzprof
+
+import (
+	"context"
+
+	"github.com/tetratelabs/wazero/experimental"
+	"golang.org/x/exp/slices"
+)
+
+// ObservedSample is a sample observed by a profiler, which is passed to the
+// function configured with SampleHook before being added to the profile.
+type ObservedSample struct {
+	// Name of the profiler which observed the sample (see Profiler.Name).
+	Profiler string
+	// Functions of the call stack of the sample, from the innermost to the
+	// outermost frame. The hook must not modify or retain the slice.
+	Stack []experimental.InternalFunction
+	// Value observed by the profiler: the time in nanoseconds for the CPU,
+	// block, mutex and system call profilers, the size in bytes for the
+	// memory profiler, the number of pages for the memory growth profiler,
+	// the depth for the stack depth profiler, and 1 for the trap profiler.
+	// The hook may change the value recorded in the profile.
+	Value int64
+	// Labels of the sample as key/value pairs, like the arguments of
+	// WithLabels. The hook may change the labels recorded in the profile.
+	Labels []string
+}
+
+// hookSample calls the function configured with SampleHook on the sample of
+// value val observed for the call stack st by the named profiler. It returns
+// the call stack and value to record, or false if the sample is dropped.
+func (p *Profiling) hookSample(profiler string, st stackTrace, val int64) (stackTrace, int64, bool) {
+	if p.sampleHook == nil {
+		return st, val, true
+	}
+	labels := labelPairs(st.labels)
+	s := ObservedSample{
+		Profiler: profiler,
+		Stack:    st.fns,
+		Value:    val,
+		Labels:   labelPairs(st.labels),
+	}
+	if !p.sampleHook(&s) {
+		return st, val, false
+	}
+	if !slices.Equal(s.Labels, labels) {
+		st.labels = nil
+		if len(s.Labels) > 0 {
+			st.labels = contextLabels(WithLabels(context.Background(), s.Labels...))
+		}
+		st.key = st.hash()
+	}
+	return st, s.Value, true
+}
+
+// labelPairs returns the labels of set as key/value pairs.
+func labelPairs(set *labelSet) []string {
+	if set == nil {
+		return nil
+	}
+	pairs := make([]string, 0, 2*len(set.labels))
+	for _, l := range set.labels {
+		pairs = append(pairs, l.key, l.value)
+	}
+	return pairs
+}
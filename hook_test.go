@@ -0,0 +1,108 @@
+package wzprof
+
+import (
+	"context"
+	"net/http/httptest"
+	"reflect"
+	"testing"
+
+	"github.com/google/pprof/profile"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestSampleHook(t *testing.T) {
+	var profilers []string
+	p := ProfilingFor(nil, AllowUnprepared(true), SampleHook(func(s *ObservedSample) bool {
+		profilers = append(profilers, s.Profiler)
+		switch s.Stack[0].Definition().Name() {
+		case "f0":
+			return false
+		case "f1":
+			s.Labels = append(s.Labels, "root", s.Stack[len(s.Stack)-1].Definition().Name())
+			s.Value *= 10
+		}
+		return true
+	})).StackProfiler()
+
+	f0 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f0.FunctionName = "f0"
+	f1 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f1.FunctionName = "f1"
+
+	module := wazerotest.NewModule(nil, f0, f1)
+	ctx := WithLabels(context.Background(), "tenant", "a")
+
+	for _, stack := range [][]experimental.StackFrame{
+		{{Function: f0}},
+		{{Function: f1}, {Function: f0}},
+	} {
+		def := stack[0].Function.Definition()
+		listener := p.NewFunctionListener(def)
+		listener.Before(ctx, module, def, nil, experimental.NewStackIterator(stack...))
+		listener.After(ctx, module, def, nil)
+	}
+
+	if len(profilers) != 2 || profilers[0] != "stackdepth" {
+		t.Errorf("wrong calls to the sample hook: %v", profilers)
+	}
+
+	prof := p.NewProfile(1)
+	if len(prof.Sample) != 1 {
+		t.Fatalf("wrong number of samples: want=1 got=%d", len(prof.Sample))
+	}
+	s := prof.Sample[0]
+	if s.Value[0] != 20 {
+		t.Errorf("wrong value of the sample: want=20 got=%d", s.Value[0])
+	}
+	if root := s.Label["root"]; len(root) != 1 || root[0] != "f0" {
+		t.Errorf("wrong labels of the sample: %v", s.Label)
+	}
+	if tenant := s.Label["tenant"]; len(tenant) != 1 || tenant[0] != "a" {
+		t.Errorf("labels of the context were not retained: %v", s.Label)
+	}
+}
+
+func TestProfileHooks(t *testing.T) {
+	var events []string
+	p := ProfilingFor(nil, AllowUnprepared(true),
+		OnProfileStart(func(name string) {
+			events = append(events, "start "+name)
+		}),
+		OnProfileEnd(func(name string, prof *profile.Profile) {
+			events = append(events, "end "+name)
+			prof.Comments = append(prof.Comments, "build=test")
+		}),
+	)
+	cpu, mem, traps := p.CPUProfiler(), p.MemoryProfiler(), p.TrapProfiler()
+
+	if cpu.StopProfile(1) != nil {
+		t.Error("profile returned before being started")
+	}
+	if !cpu.StartProfile() {
+		t.Fatal("CPU profile could not be started")
+	}
+	if cpu.StartProfile() {
+		t.Error("CPU profile started twice")
+	}
+	if prof := cpu.StopProfile(1); !reflect.DeepEqual(prof.Comments, []string{"build=test"}) {
+		t.Errorf("wrong comments of the CPU profile: %q", prof.Comments)
+	}
+	mem.NewProfile(1)
+
+	w := httptest.NewRecorder()
+	traps.NewHandler(1).ServeHTTP(w, httptest.NewRequest("GET", "/debug/pprof/traps", nil))
+	prof, err := profile.Parse(w.Body)
+	if err != nil {
+		t.Fatal(err)
+	}
+	if !reflect.DeepEqual(prof.Comments, []string{"build=test"}) {
+		t.Errorf("wrong comments of the served profile: %q", prof.Comments)
+	}
+
+	want := []string{"start profile", "end profile", "end allocs", "end traps"}
+	if !reflect.DeepEqual(events, want) {
+		t.Errorf("wrong calls to the hooks:\nwant=%q\ngot= %q", want, events)
+	}
+}
@@ -0,0 +1,49 @@
+package wzprof
+
+import (
+	"context"
+
+	"github.com/tetratelabs/wazero"
+	"github.com/tetratelabs/wazero/api"
+)
+
+// HostModuleName is the name of the host module built by NewHostModule.
+const HostModuleName = "wzprof"
+
+// NewHostModule returns a builder of the "wzprof" host module, which guests
+// import to delimit the regions of their execution that are profiled from the
+// wasm code, for example to profile the handling of requests but not the
+// startup of a program:
+//
+//	(import "wzprof" "start_cpu_profile" (func))
+//	(import "wzprof" "stop_cpu_profile" (func))
+//
+// Calls to the functions invoke start and stop, which usually call the
+// StartProfile and StopProfile methods of a CPUProfiler:
+//
+//	_, err := wzprof.NewHostModule(runtime,
+//		func() { cpu.StartProfile() },
+//		func() { save(cpu.StopProfile(sampleRate)) },
+//	).Instantiate(ctx)
+//
+// The module must be instantiated before the guest modules importing it.
+func NewHostModule(r wazero.Runtime, start, stop func()) wazero.HostModuleBuilder {
+	return r.NewHostModuleBuilder(HostModuleName).
+		NewFunctionBuilder().
+		WithGoModuleFunction(api.GoModuleFunc(func(context.Context, api.Module, []uint64) { start() }), nil, nil).
+		Export("start_cpu_profile").
+		NewFunctionBuilder().
+		WithGoModuleFunction(api.GoModuleFunc(func(context.Context, api.Module, []uint64) { stop() }), nil, nil).
+		Export("stop_cpu_profile")
+}
+
+// ImportsHostModule returns true if the module imports functions of the host
+// module built by NewHostModule.
+func ImportsHostModule(mod wazero.CompiledModule) bool {
+	for _, def := range mod.ImportedFunctions() {
+		if moduleName, _, _ := def.Import(); moduleName == HostModuleName {
+			return true
+		}
+	}
+	return false
+}
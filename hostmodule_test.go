@@ -0,0 +1,61 @@
+package wzprof
+
+import (
+	"context"
+	"testing"
+
+	"github.com/tetratelabs/wazero"
+)
+
+func TestHostModule(t *testing.T) {
+	name := func(s string) []byte { return append([]byte{byte(len(s))}, s...) }
+	section := func(id byte, content ...[]byte) []byte {
+		var b []byte
+		for _, c := range content {
+			b = append(b, c...)
+		}
+		return append([]byte{id, byte(len(b))}, b...)
+	}
+
+	// (func $run (call $start_cpu_profile) (call $stop_cpu_profile))
+	wasm := []byte("\x00asm\x01\x00\x00\x00")
+	wasm = append(wasm, section(1, []byte{1, 0x60, 0, 0})...)
+	wasm = append(wasm, section(2, []byte{2},
+		name("wzprof"), name("start_cpu_profile"), []byte{0, 0},
+		name("wzprof"), name("stop_cpu_profile"), []byte{0, 0},
+	)...)
+	wasm = append(wasm, section(3, []byte{1, 0})...)
+	wasm = append(wasm, section(7, []byte{1}, name("run"), []byte{0, 2})...)
+	wasm = append(wasm, section(10, []byte{1, 6, 0, 0x10, 0x00, 0x10, 0x01, 0x0b})...)
+
+	ctx := context.Background()
+	runtime := wazero.NewRuntimeWithConfig(ctx, wazero.NewRuntimeConfigInterpreter())
+	defer runtime.Close(ctx)
+
+	compiled, err := runtime.CompileModule(ctx, wasm)
+	if err != nil {
+		t.Fatal(err)
+	}
+	if !ImportsHostModule(compiled) {
+		t.Error("the module does not import the host module")
+	}
+
+	var calls []string
+	_, err = NewHostModule(runtime,
+		func() { calls = append(calls, "start") },
+		func() { calls = append(calls, "stop") },
+	).Instantiate(ctx)
+	if err != nil {
+		t.Fatal(err)
+	}
+	mod, err := runtime.InstantiateModule(ctx, compiled, wazero.NewModuleConfig())
+	if err != nil {
+		t.Fatal(err)
+	}
+	if _, err := mod.ExportedFunction("run").Call(ctx); err != nil {
+		t.Fatal(err)
+	}
+	if len(calls) != 2 || calls[0] != "start" || calls[1] != "stop" {
+		t.Errorf("wrong calls to the host module: %v", calls)
+	}
+}
@@ -0,0 +1,895 @@
+package wzprof
+
+import (
+	"strconv"
+	"strings"
+)
+
+// demangleItanium demangles symbol names mangled according to the Itanium C++
+// ABI, which is used by clang when compiling C++ programs to WebAssembly.
+//
+// Similarly to the default mode of pprof, the parameters of functions are not
+// part of the output since they rarely help to identify functions in profiles
+// and make names significantly longer. When stripTemplates is true, template
+// arguments are also removed from the output.
+//
+// Expressions in template arguments are not supported, the demangling fails
+// for symbols which contain them.
+func demangleItanium(name string, stripTemplates bool) (s string, ok bool) {
+	var sym string
+	switch {
+	case strings.HasPrefix(name, "_Z"):
+		sym = name[2:]
+	case strings.HasPrefix(name, "__Z"):
+		sym = name[3:]
+	default:
+		return "", false
+	}
+
+	defer func() {
+		if r := recover(); r != nil {
+			if _, isDemangleError := r.(demangleError); !isDemangleError {
+				panic(r)
+			}
+			s, ok = "", false
+		}
+	}()
+
+	d := &cxxDemangler{sym: sym, stripTemplates: stripTemplates}
+	// The function parameters which follow the name are ignored, as well as
+	// suffixes added by the compiler (e.g. ".cold").
+	return d.encoding(false), true
+}
+
+// cxxMaxLength bounds the length of the demangled names, substitutions allow
+// crafting symbols which would otherwise expand exponentially.
+const cxxMaxLength = 1 << 16
+
+// cxxType is the representation of a demangled type. The name of a variable
+// of this type would be printed between left and right, which is how pointers
+// to functions and arrays are formatted, for example "int (*)(char)".
+type cxxType struct {
+	left  string
+	right string
+	// Unqualified name of the type, without template arguments. It is used to
+	// print the names of constructors and destructors.
+	name string
+	// True if left ends within the parentheses grouping the declarator of a
+	// pointer to function or array.
+	decl bool
+}
+
+func (t cxxType) String() string {
+	return t.left + t.right
+}
+
+func (t cxxType) pointer(op string) cxxType {
+	if t.right != "" && !t.decl {
+		t.left += "(" + op
+		t.right = ")" + t.right
+		t.decl = true
+	} else {
+		t.left += op
+	}
+	t.name = ""
+	return t
+}
+
+func (t cxxType) qualify(quals string) cxxType {
+	if t.right != "" && !t.decl {
+		t.right += quals
+	} else {
+		t.left += quals
+	}
+	return t
+}
+
+// cxxDemangler is a parser of the Itanium C++ ABI mangling scheme, see
+// https://itanium-cxx-abi.github.io/cxx-abi/abi.html#mangling for the grammar.
+type cxxDemangler struct {
+	sym            string
+	pos            int
+	depth          int
+	stripTemplates bool
+	subs           []cxxType
+	templateParams []cxxType
+}
+
+func (d *cxxDemangler) fail() {
+	panic(demangleError{})
+}
+
+func (d *cxxDemangler) enter() {
+	if d.depth++; d.depth > demangleMaxDepth {
+		d.fail()
+	}
+}
+
+func (d *cxxDemangler) leave() {
+	d.depth--
+}
+
+func (d *cxxDemangler) peek() byte {
+	return d.peekAt(0)
+}
+
+func (d *cxxDemangler) peekAt(i int) byte {
+	if i += d.pos; i < len(d.sym) {
+		return d.sym[i]
+	}
+	return 0
+}
+
+func (d *cxxDemangler) next() byte {
+	if d.pos >= len(d.sym) {
+		d.fail()
+	}
+	c := d.sym[d.pos]
+	d.pos++
+	return c
+}
+
+func (d *cxxDemangler) eat(c byte) bool {
+	if d.peek() != c {
+		return false
+	}
+	d.pos++
+	return true
+}
+
+func (d *cxxDemangler) eatString(s string) bool {
+	if !strings.HasPrefix(d.sym[d.pos:], s) {
+		return false
+	}
+	d.pos += len(s)
+	return true
+}
+
+func (d *cxxDemangler) expect(c byte) {
+	if !d.eat(c) {
+		d.fail()
+	}
+}
+
+func (d *cxxDemangler) check(s string) string {
+	if len(s) > cxxMaxLength {
+		d.fail()
+	}
+	return s
+}
+
+func (d *cxxDemangler) addSubstitution(t cxxType) {
+	d.check(t.left)
+	d.check(t.right)
+	d.subs = append(d.subs, t)
+}
+
+// encoding parses the name of a function or data object. When params is true,
+// the parameters of functions are parsed until the 'E' terminating the local
+// name they are part of.
+func (d *cxxDemangler) encoding(params bool) string {
+	d.enter()
+	defer d.leave()
+
+	if s, ok := d.specialName(); ok {
+		return s
+	}
+	name, _ := d.name(true)
+	if params {
+		for d.peek() != 'E' {
+			d.typ()
+		}
+	}
+	return name
+}
+
+func (d *cxxDemangler) specialName() (string, bool) {
+	switch {
+	case d.eatString("TV"):
+		return "vtable for " + d.typ().String(), true
+	case d.eatString("TT"):
+		return "VTT for " + d.typ().String(), true
+	case d.eatString("TI"):
+		return "typeinfo for " + d.typ().String(), true
+	case d.eatString("TS"):
+		return "typeinfo name for " + d.typ().String(), true
+	case d.eatString("Th"):
+		d.callOffset('h')
+		return "non-virtual thunk to " + d.encoding(false), true
+	case d.eatString("Tv"):
+		d.callOffset('v')
+		return "virtual thunk to " + d.encoding(false), true
+	case d.eatString("Tc"):
+		d.callOffset(d.next())
+		d.callOffset(d.next())
+		return "covariant return thunk to " + d.encoding(false), true
+	case d.eatString("TH"):
+		name, _ := d.name(true)
+		return "TLS init function for " + name, true
+	case d.eatString("TW"):
+		name, _ := d.name(true)
+		return "TLS wrapper function for " + name, true
+	case d.eatString("GV"):
+		name, _ := d.name(true)
+		return "guard variable for " + name, true
+	}
+	return "", false
+}
+
+func (d *cxxDemangler) callOffset(kind byte) {
+	switch kind {
+	case 'h':
+		d.number()
+		d.expect('_')
+	case 'v':
+		d.number()
+		d.expect('_')
+		d.number()
+		d.expect('_')
+	default:
+		d.fail()
+	}
+}
+
+func (d *cxxDemangler) number() int {
+	d.eat('n')
+	if !isDecimalDigit(d.peek()) {
+		d.fail()
+	}
+	n := 0
+	for isDecimalDigit(d.peek()) {
+		n = 10*n + int(d.next()-'0')
+		if n > len(d.sym) {
+			d.fail()
+		}
+	}
+	return n
+}
+
+// name parses a name, returning its demangled form and the unqualified name
+// of its last component. When setParams is true, the template arguments of the
+// name become the template parameters that the rest of the symbol refers to.
+func (d *cxxDemangler) name(setParams bool) (string, string) {
+	d.enter()
+	defer d.leave()
+
+	switch d.peek() {
+	case 'N':
+		return d.nestedName(setParams)
+	case 'Z':
+		return d.localName()
+	case 'S':
+		if d.peekAt(1) != 't' {
+			// A substitution is only valid as an unscoped template name.
+			sub := d.substitution()
+			if d.peek() != 'I' {
+				d.fail()
+			}
+			return sub.left + d.templateArgs(setParams), sub.name
+		}
+	}
+
+	prefix := ""
+	if d.eatString("St") {
+		prefix = "std::"
+	}
+	name, base := d.unqualifiedName("")
+	name = prefix + name
+	if d.peek() == 'I' {
+		d.addSubstitution(cxxType{left: name, name: base})
+		name += d.templateArgs(setParams)
+	}
+	return name, base
+}
+
+func (d *cxxDemangler) nestedName(setParams bool) (string, string) {
+	d.expect('N')
+	// The qualifiers of member functions are not part of the output.
+	for d.eat('r') || d.eat('V') || d.eat('K') {
+	}
+	_ = d.eat('R') || d.eat('O')
+
+	var prefix, base string
+	pushed := false
+	for !d.eat('E') {
+		switch c := d.peek(); {
+		case c == 'S' && d.peekAt(1) == 't':
+			d.pos += 2
+			prefix, base, pushed = "std", "", false
+			continue
+		case c == 'S':
+			sub := d.substitution()
+			prefix, base, pushed = sub.left, sub.name, false
+			continue
+		case c == 'M':
+			// Prefix of closures defined in the initializer of data members.
+			d.pos++
+			continue
+		case c == 'I':
+			if prefix == "" {
+				d.fail()
+			}
+			prefix += d.templateArgs(setParams)
+		case c == 'T':
+			t := d.templateParam()
+			prefix, base = t.String(), t.name
+		default:
+			name, nameBase := d.unqualifiedName(base)
+			if prefix != "" {
+				prefix += "::"
+			}
+			prefix, base = prefix+name, nameBase
+		}
+		d.addSubstitution(cxxType{left: prefix, name: base})
+		pushed = true
+	}
+	if !pushed {
+		d.fail()
+	}
+	// The full name is not a prefix, it is added to the substitutions when
+	// the name is a type.
+	d.subs = d.subs[:len(d.subs)-1]
+	return prefix, base
+}
+
+func (d *cxxDemangler) localName() (string, string) {
+	d.expect('Z')
+	fn := d.encoding(true)
+	d.expect('E')
+	if d.eat('s') {
+		d.discriminator()
+		return fn + "::string literal", ""
+	}
+	name, base := d.name(true)
+	d.discriminator()
+	return fn + "::" + name, base
+}
+
+func (d *cxxDemangler) discriminator() {
+	if !d.eat('_') {
+		return
+	}
+	if d.eat('_') {
+		d.number()
+		d.expect('_')
+	} else if !isDecimalDigit(d.next()) {
+		d.fail()
+	}
+}
+
+// unqualifiedName parses the last component of a name. The unqualified name
+// of the component's prefix is used for constructors and destructors.
+func (d *cxxDemangler) unqualifiedName(prefixBase string) (name, base string) {
+	// Names with internal linkage.
+	d.eat('L')
+
+	switch c := d.peek(); {
+	case isDecimalDigit(c):
+		name = d.sourceName()
+		base = name
+	case c == 'C':
+		d.pos++
+		if d.eat('I') {
+			// Inheriting constructor.
+			if c := d.next(); c < '1' || c > '2' {
+				d.fail()
+			}
+			d.typ()
+		} else if c := d.next(); c < '1' || c > '5' {
+			d.fail()
+		}
+		if prefixBase == "" {
+			d.fail()
+		}
+		name, base = prefixBase, prefixBase
+	case c == 'D' && isDecimalDigit(d.peekAt(1)):
+		d.pos++
+		if c := d.next(); c > '5' {
+			d.fail()
+		}
+		if prefixBase == "" {
+			d.fail()
+		}
+		name, base = "~"+prefixBase, prefixBase
+	case c == 'U':
+		name = d.unnamedTypeName()
+	case 'a' <= c && c <= 'z':
+		name = d.operatorName()
+	default:
+		d.fail()
+	}
+
+	// ABI tags.
+	for d.eat('B') {
+		name += "[abi:" + d.sourceName() + "]"
+	}
+	return name, base
+}
+
+func (d *cxxDemangler) sourceName() string {
+	n := d.number()
+	if n > len(d.sym)-d.pos {
+		d.fail()
+	}
+	name := d.sym[d.pos : d.pos+n]
+	d.pos += n
+	if strings.HasPrefix(name, "_GLOBAL__N") {
+		return "(anonymous namespace)"
+	}
+	return name
+}
+
+func (d *cxxDemangler) unnamedTypeName() string {
+	var name string
+	switch {
+	case d.eatString("Ut"):
+		name = "{unnamed type#"
+	case d.eatString("Ul"):
+		name = "{lambda(" + d.functionParams() + ")#"
+	default:
+		d.fail()
+	}
+	n := 1
+	if d.peek() != '_' {
+		n = d.number() + 2
+	}
+	d.expect('_')
+	return name + strconv.Itoa(n) + "}"
+}
+
+// functionParams parses a list of types terminated by 'E'.
+func (d *cxxDemangler) functionParams() string {
+	var params []string
+	for !d.eat('E') {
+		params = append(params, d.typ().String())
+	}
+	if len(params) == 1 && params[0] == "void" {
+		return ""
+	}
+	return strings.Join(params, ", ")
+}
+
+var cxxOperators = map[string]string{
+	"nw": "new",
+	"na": "new[]",
+	"dl": "delete",
+	"da": "delete[]",
+	"ps": "+",
+	"ng": "-",
+	"ad": "&",
+	"de": "*",
+	"co": "~",
+	"pl": "+",
+	"mi": "-",
+	"ml": "*",
+	"dv": "/",
+	"rm": "%",
+	"an": "&",
+	"or": "|",
+	"eo": "^",
+	"aS": "=",
+	"pL": "+=",
+	"mI": "-=",
+	"mL": "*=",
+	"dV": "/=",
+	"rM": "%=",
+	"aN": "&=",
+	"oR": "|=",
+	"eO": "^=",
+	"ls": "<<",
+	"rs": ">>",
+	"lS": "<<=",
+	"rS": ">>=",
+	"eq": "==",
+	"ne": "!=",
+	"lt": "<",
+	"gt": ">",
+	"le": "<=",
+	"ge": ">=",
+	"ss": "<=>",
+	"nt": "!",
+	"aa": "&&",
+	"oo": "||",
+	"pp": "++",
+	"mm": "--",
+	"cm": ",",
+	"pm": "->*",
+	"pt": "->",
+	"cl": "()",
+	"ix": "[]",
+	"qu": "?",
+	"aw": "co_await",
+}
+
+func (d *cxxDemangler) operatorName() string {
+	switch {
+	case d.eatString("cv"):
+		return "operator " + d.typ().String()
+	case d.eatString("li"):
+		return `operator"" ` + d.sourceName()
+	case d.eat('v'):
+		if !isDecimalDigit(d.next()) {
+			d.fail()
+		}
+		return "operator " + d.sourceName()
+	}
+	if d.pos+2 > len(d.sym) {
+		d.fail()
+	}
+	op, ok := cxxOperators[d.sym[d.pos:d.pos+2]]
+	if !ok {
+		d.fail()
+	}
+	d.pos += 2
+	if c := op[0]; 'a' <= c && c <= 'z' {
+		return "operator " + op
+	}
+	return "operator" + op
+}
+
+// cxxStandardSubstitutions are the abbreviations of names of the standard
+// library, with the names of their constructors.
+var cxxStandardSubstitutions = map[byte]cxxType{
+	'a': {left: "std::allocator", name: "allocator"},
+	'b': {left: "std::basic_string", name: "basic_string"},
+	's': {left: "std::string", name: "basic_string"},
+	'i': {left: "std::istream", name: "basic_istream"},
+	'o': {left: "std::ostream", name: "basic_ostream"},
+	'd': {left: "std::iostream", name: "basic_iostream"},
+}
+
+func (d *cxxDemangler) substitution() cxxType {
+	d.expect('S')
+	if t, ok := cxxStandardSubstitutions[d.peek()]; ok {
+		d.pos++
+		return t
+	}
+	i := d.seqID()
+	if i >= len(d.subs) {
+		d.fail()
+	}
+	return d.subs[i]
+}
+
+// seqID parses the index of a substitution or template parameter, where "_"
+// encodes 0 and the base 36 digits followed by "_" encode their value plus
+// one.
+func (d *cxxDemangler) seqID() int {
+	if d.eat('_') {
+		return 0
+	}
+	n := 0
+	for !d.eat('_') {
+		c := d.next()
+		switch {
+		case isDecimalDigit(c):
+			n = 36*n + int(c-'0')
+		case 'A' <= c && c <= 'Z':
+			n = 36*n + int(c-'A') + 10
+		default:
+			d.fail()
+		}
+		if n > len(d.sym) {
+			d.fail()
+		}
+	}
+	return n + 1
+}
+
+func (d *cxxDemangler) templateParam() cxxType {
+	d.expect('T')
+	i := d.seqID()
+	if i >= len(d.templateParams) {
+		d.fail()
+	}
+	return d.templateParams[i]
+}
+
+func (d *cxxDemangler) templateArgs(setParams bool) string {
+	d.expect('I')
+	var args []cxxType
+	for !d.eat('E') {
+		args = append(args, d.templateArg())
+	}
+	if setParams {
+		d.templateParams = args
+	}
+	if d.stripTemplates {
+		return ""
+	}
+	list := make([]string, len(args))
+	for i, arg := range args {
+		list[i] = arg.String()
+	}
+	return d.check("<" + strings.Join(list, ", ") + ">")
+}
+
+func (d *cxxDemangler) templateArg() cxxType {
+	d.enter()
+	defer d.leave()
+
+	switch d.peek() {
+	case 'L':
+		return cxxType{left: d.literal()}
+	case 'J':
+		d.pos++
+		var pack []string
+		for !d.eat('E') {
+			pack = append(pack, d.templateArg().String())
+		}
+		return cxxType{left: d.check(strings.Join(pack, ", "))}
+	case 'X':
+		d.fail()
+	}
+	return d.typ()
+}
+
+var cxxLiteralSuffixes = map[string]string{
+	"int":                "",
+	"unsigned int":       "u",
+	"long":               "l",
+	"unsigned long":      "ul",
+	"long long":          "ll",
+	"unsigned long long": "ull",
+}
+
+func (d *cxxDemangler) literal() string {
+	d.expect('L')
+	if d.eatString("_Z") || d.eat('Z') {
+		name := d.encoding(true)
+		d.expect('E')
+		return name
+	}
+
+	t := d.typ().String()
+	start := d.pos
+	for d.peek() != 'E' {
+		d.next()
+	}
+	value := d.sym[start:d.pos]
+	d.pos++
+
+	negative := strings.HasPrefix(value, "n")
+	if negative {
+		value = "-" + value[1:]
+	}
+	switch {
+	case t == "bool" && value == "0":
+		return "false"
+	case t == "bool" && value == "1":
+		return "true"
+	case t == "decltype(nullptr)" && value == "":
+		return "nullptr"
+	}
+	if suffix, ok := cxxLiteralSuffixes[t]; ok {
+		return value + suffix
+	}
+	return "(" + t + ")" + value
+}
+
+func cxxBuiltinType(c byte) string {
+	switch c {
+	case 'v':
+		return "void"
+	case 'w':
+		return "wchar_t"
+	case 'b':
+		return "bool"
+	case 'c':
+		return "char"
+	case 'a':
+		return "signed char"
+	case 'h':
+		return "unsigned char"
+	case 's':
+		return "short"
+	case 't':
+		return "unsigned short"
+	case 'i':
+		return "int"
+	case 'j':
+		return "unsigned int"
+	case 'l':
+		return "long"
+	case 'm':
+		return "unsigned long"
+	case 'x':
+		return "long long"
+	case 'y':
+		return "unsigned long long"
+	case 'n':
+		return "__int128"
+	case 'o':
+		return "unsigned __int128"
+	case 'f':
+		return "float"
+	case 'd':
+		return "double"
+	case 'e':
+		return "long double"
+	case 'g':
+		return "__float128"
+	case 'z':
+		return "..."
+	}
+	return ""
+}
+
+func cxxExtendedBuiltinType(c byte) string {
+	switch c {
+	case 'a':
+		return "auto"
+	case 'c':
+		return "decltype(auto)"
+	case 'd':
+		return "decimal64"
+	case 'e':
+		return "decimal128"
+	case 'f':
+		return "decimal32"
+	case 'h':
+		return "half"
+	case 'i':
+		return "char32_t"
+	case 'n':
+		return "decltype(nullptr)"
+	case 's':
+		return "char16_t"
+	case 'u':
+		return "char8_t"
+	}
+	return ""
+}
+
+func (d *cxxDemangler) typ() cxxType {
+	d.enter()
+	defer d.leave()
+
+	c := d.peek()
+	if s := cxxBuiltinType(c); s != "" {
+		d.pos++
+		return cxxType{left: s}
+	}
+
+	var t cxxType
+	switch c {
+	case 'r', 'V', 'K':
+		var quals string
+		restrict, volatile, konst := d.eat('r'), d.eat('V'), d.eat('K')
+		if konst {
+			quals += " const"
+		}
+		if volatile {
+			quals += " volatile"
+		}
+		if restrict {
+			quals += " restrict"
+		}
+		t = d.typ().qualify(quals)
+	case 'P':
+		d.pos++
+		t = d.typ().pointer("*")
+	case 'R':
+		d.pos++
+		t = d.typ().pointer("&")
+	case 'O':
+		d.pos++
+		t = d.typ().pointer("&&")
+	case 'C':
+		d.pos++
+		t = d.typ().qualify(" _Complex")
+	case 'G':
+		d.pos++
+		t = d.typ().qualify(" _Imaginary")
+	case 'F':
+		t = d.functionType()
+	case 'A':
+		t = d.arrayType()
+	case 'M':
+		t = d.pointerToMember()
+	case 'T':
+		switch d.peekAt(1) {
+		case 's', 'u', 'e':
+			// Elaborated type specifiers (struct, union, enum).
+			d.pos += 2
+			name, base := d.name(false)
+			t = cxxType{left: name, name: base}
+		default:
+			t = d.templateParam()
+			if d.peek() == 'I' {
+				d.addSubstitution(t)
+				t = cxxType{left: t.String() + d.templateArgs(false)}
+			}
+		}
+	case 'S':
+		if d.peekAt(1) == 't' {
+			name, base := d.name(false)
+			t = cxxType{left: name, name: base}
+			break
+		}
+		t = d.substitution()
+		if d.peek() != 'I' {
+			// Substitutions are not added again to the table.
+			return t
+		}
+		t = cxxType{left: t.left + d.templateArgs(false), name: t.name}
+	case 'D':
+		if s := cxxExtendedBuiltinType(d.peekAt(1)); s != "" {
+			d.pos += 2
+			return cxxType{left: s}
+		}
+		if !d.eatString("Dp") {
+			d.fail()
+		}
+		t = d.typ()
+		t.left += "..."
+	case 'u':
+		d.pos++
+		t = cxxType{left: d.sourceName()}
+	default:
+		name, base := d.name(false)
+		t = cxxType{left: name, name: base}
+	}
+	d.addSubstitution(t)
+	return t
+}
+
+func (d *cxxDemangler) functionType() cxxType {
+	d.expect('F')
+	d.eat('Y')
+	ret := d.typ()
+	var params []string
+	var quals string
+	for {
+		if d.eatString("RE") {
+			quals = " &"
+			break
+		}
+		if d.eatString("OE") {
+			quals = " &&"
+			break
+		}
+		if d.eat('E') {
+			break
+		}
+		params = append(params, d.typ().String())
+	}
+	if len(params) == 1 && params[0] == "void" {
+		params = nil
+	}
+	return cxxType{
+		left:  ret.String() + " ",
+		right: "(" + strings.Join(params, ", ") + ")" + quals,
+	}
+}
+
+func (d *cxxDemangler) arrayType() cxxType {
+	d.expect('A')
+	var dim string
+	if isDecimalDigit(d.peek()) {
+		dim = strconv.Itoa(d.number())
+	}
+	d.expect('_')
+	t := d.typ()
+	if t.right == "" {
+		t.left += " "
+	}
+	t.right = "[" + dim + "]" + t.right
+	t.name = ""
+	return t
+}
+
+func (d *cxxDemangler) pointerToMember() cxxType {
+	d.expect('M')
+	class := d.typ().String()
+	t := d.typ()
+	if t.right != "" && !t.decl {
+		t.left += "(" + class + "::*"
+		t.right = ")" + t.right
+		t.decl = true
+	} else {
+		t.left += " " + class + "::*"
+	}
+	t.name = ""
+	return t
+}
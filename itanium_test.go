@@ -0,0 +1,127 @@
+package wzprof
+
+import "testing"
+
+func TestDemangleItanium(t *testing.T) {
+	tests := []struct {
+		mangled  string
+		full     string
+		stripped string
+	}{
+		{
+			mangled:  "_Z3fooi",
+			full:     "foo",
+			stripped: "foo",
+		},
+		{
+			mangled:  "_ZN3foo3barEv",
+			full:     "foo::bar",
+			stripped: "foo::bar",
+		},
+		{
+			mangled:  "_ZNK3Foo3getEv",
+			full:     "Foo::get",
+			stripped: "Foo::get",
+		},
+		{
+			mangled:  "_ZN3FooC2Ev",
+			full:     "Foo::Foo",
+			stripped: "Foo::Foo",
+		},
+		{
+			mangled:  "_ZN3FooD1Ev",
+			full:     "Foo::~Foo",
+			stripped: "Foo::~Foo",
+		},
+		{
+			mangled:  "_ZN3FooplERKS_",
+			full:     "Foo::operator+",
+			stripped: "Foo::operator+",
+		},
+		{
+			mangled:  "_ZN12_GLOBAL__N_13fooEv",
+			full:     "(anonymous namespace)::foo",
+			stripped: "(anonymous namespace)::foo",
+		},
+		{
+			mangled:  "_ZN5outer5innerB5cxx11Ev",
+			full:     "outer::inner[abi:cxx11]",
+			stripped: "outer::inner[abi:cxx11]",
+		},
+		{
+			mangled:  "_Z3maxIiET_S0_S0_",
+			full:     "max<int>",
+			stripped: "max",
+		},
+		{
+			mangled:  "_ZNSt6vectorIiSaIiEE9push_backERKi",
+			full:     "std::vector<int, std::allocator<int>>::push_back",
+			stripped: "std::vector::push_back",
+		},
+		{
+			mangled:  "_ZNSt3__112basic_stringIcNS_11char_traitsIcEENS_9allocatorIcEEE6appendEPKc",
+			full:     "std::__1::basic_string<char, std::__1::char_traits<char>, std::__1::allocator<char>>::append",
+			stripped: "std::__1::basic_string::append",
+		},
+		{
+			mangled:  "_ZN3Foo5applyIPFivEEEvT_",
+			full:     "Foo::apply<int (*)()>",
+			stripped: "Foo::apply",
+		},
+		{
+			mangled:  "_ZZ4mainE5count",
+			full:     "main::count",
+			stripped: "main::count",
+		},
+		{
+			mangled:  "_ZZ4mainENKUlvE_clEv",
+			full:     "main::{lambda()#1}::operator()",
+			stripped: "main::{lambda()#1}::operator()",
+		},
+		{
+			mangled:  "_ZTV3Foo",
+			full:     "vtable for Foo",
+			stripped: "vtable for Foo",
+		},
+		{
+			mangled:  "_ZThn8_N3Foo3barEv",
+			full:     "non-virtual thunk to Foo::bar",
+			stripped: "non-virtual thunk to Foo::bar",
+		},
+
+		// Names which are not mangled C++ symbols are left unchanged.
+		{
+			mangled:  "main",
+			full:     "main",
+			stripped: "main",
+		},
+		{
+			mangled:  "_Z",
+			full:     "_Z",
+			stripped: "_Z",
+		},
+		{
+			mangled:  "_ZN3foo",
+			full:     "_ZN3foo",
+			stripped: "_ZN3foo",
+		},
+	}
+
+	for _, test := range tests {
+		t.Run(test.mangled, func(t *testing.T) {
+			for _, strip := range []bool{false, true} {
+				want := test.full
+				if strip {
+					want = test.stripped
+				}
+				got, ok := demangle(test.mangled, strip)
+				if !ok {
+					got = test.mangled
+				}
+				if got != want {
+					t.Errorf("wrong demangled name (strip=%t): want=%q got=%q", strip, want, got)
+				}
+			}
+		})
+	}
+}
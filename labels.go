@@ -0,0 +1,116 @@
+package wzprof
+
+import (
+	"context"
+	"hash/maphash"
+	"sort"
+
+	"github.com/tetratelabs/wazero/api"
+	"golang.org/x/exp/slices"
+)
+
+// WithLabels returns a copy of ctx carrying the given labels, which are added
+// to the samples recorded by the profilers during calls to functions of the
+// module made with the returned context. The arguments are key/value pairs,
+// similarly to pprof.Labels.
+//
+// Labels are inherited from the parent context, a key set on ctx overrides the
+// value of the same key in the parent.
+//
+// This allows slicing profiles by dimensions known to the host application,
+// for example:
+//
+//	ctx = wzprof.WithLabels(ctx, "tenant", tenant, "endpoint", "/users")
+//	handle.Call(ctx, ...)
+//
+// The labels can then be used to filter samples with the -tagfocus and
+// -tagignore options of pprof.
+func WithLabels(ctx context.Context, args ...string) context.Context {
+	if len(args)%2 != 0 {
+		panic("uneven number of arguments to wzprof.WithLabels")
+	}
+
+	merged := make(map[string]string)
+	if parent := contextLabels(ctx); parent != nil {
+		for _, l := range parent.labels {
+			merged[l.key] = l.value
+		}
+	}
+	for i := 0; i < len(args); i += 2 {
+		merged[args[i]] = args[i+1]
+	}
+
+	labels := make([]label, 0, len(merged))
+	for k, v := range merged {
+		labels = append(labels, label{key: k, value: v})
+	}
+	return context.WithValue(ctx, labelsKey{}, newLabelSet(labels))
+}
+
+type labelsKey struct{}
+
+type label struct {
+	key   string
+	value string
+}
+
+// labelSet is an immutable set of labels sorted by key. The hash is mixed in
+// the keys of stack traces so samples with different labels are recorded
+// separately.
+type labelSet struct {
+	labels []label
+	hash   uint64
+}
+
+// newLabelSet returns a set of the given labels, which must have distinct keys.
+func newLabelSet(labels []label) *labelSet {
+	sort.Slice(labels, func(i, j int) bool {
+		return labels[i].key < labels[j].key
+	})
+
+	var h maphash.Hash
+	h.SetSeed(stackTraceHashSeed)
+	for _, l := range labels {
+		// The errors are always nil when writing to maphash.Hash.
+		_, _ = h.WriteString(l.key)
+		_ = h.WriteByte(0)
+		_, _ = h.WriteString(l.value)
+		_ = h.WriteByte(0)
+	}
+	return &labelSet{labels: labels, hash: h.Sum64()}
+}
+
+// labelCache holds the last set of labels built by Profiling.labelContext on a
+// thread, the calls made with the same context usually return the same labels.
+type labelCache struct {
+	parent *labelSet
+	args   []string
+	set    *labelSet
+}
+
+// labelContext returns ctx with the labels returned by the function configured
+// with ContextLabels, or ctx itself if there are none.
+func (p *Profiling) labelContext(ctx context.Context, mod api.Module) context.Context {
+	if p.contextLabels == nil {
+		return ctx
+	}
+	args := p.contextLabels(ctx)
+	if len(args) == 0 {
+		return ctx
+	}
+	parent := contextLabels(ctx)
+	c := p.labelCache.get(mod)
+	if c.set == nil || c.parent != parent || !slices.Equal(c.args, args) {
+		c.parent, c.args = parent, slices.Clone(args)
+		c.set = contextLabels(WithLabels(ctx, args...))
+	}
+	return context.WithValue(ctx, labelsKey{}, c.set)
+}
+
+func contextLabels(ctx context.Context) *labelSet {
+	if ctx == nil {
+		return nil
+	}
+	set, _ := ctx.Value(labelsKey{}).(*labelSet)
+	return set
+}
@@ -0,0 +1,187 @@
+package wzprof
+
+import (
+	"context"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestWithLabels(t *testing.T) {
+	ctx := WithLabels(context.Background(), "tenant", "a", "endpoint", "/users")
+	ctx = WithLabels(ctx, "tenant", "b")
+
+	set := contextLabels(ctx)
+	want := []label{{"endpoint", "/users"}, {"tenant", "b"}}
+	if len(set.labels) != len(want) {
+		t.Fatalf("wrong labels: want=%v got=%v", want, set.labels)
+	}
+	for i := range want {
+		if set.labels[i] != want[i] {
+			t.Errorf("wrong label at index %d: want=%v got=%v", i, want[i], set.labels[i])
+		}
+	}
+}
+
+func TestCPUProfilerLabels(t *testing.T) {
+	currentTime := int64(0)
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(
+		HostTime(true),
+		TimeFunc(func() int64 { return currentTime }),
+	)
+
+	module := wazerotest.NewModule(nil,
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+	)
+	def := module.Function(0).Definition()
+	listener := p.NewFunctionListener(def)
+	stack := []experimental.StackFrame{{Function: module.Function(0)}}
+
+	p.StartProfile()
+	for _, call := range []struct {
+		tenant   string
+		duration int64
+	}{
+		{"a", 10},
+		{"b", 20},
+		{"a", 30},
+	} {
+		ctx := WithLabels(context.Background(), "tenant", call.tenant)
+		currentTime++
+		listener.Before(ctx, module, def, nil, experimental.NewStackIterator(stack...))
+		currentTime += call.duration
+		listener.After(ctx, module, def, nil)
+	}
+	prof := p.StopProfile(1)
+
+	if len(prof.Sample) != 2 {
+		t.Fatalf("wrong number of samples: want=2 got=%d", len(prof.Sample))
+	}
+	tenants := map[string]int64{}
+	for _, sample := range prof.Sample {
+		labels := sample.Label["tenant"]
+		if len(labels) != 1 {
+			t.Fatalf("sample is missing the tenant label: %v", sample.Label)
+		}
+		tenants[labels[0]] = sample.Value[1]
+	}
+	if tenants["a"] != 40 || tenants["b"] != 20 {
+		t.Errorf("wrong cpu time per tenant: %v", tenants)
+	}
+}
+
+func TestLabelExports(t *testing.T) {
+	currentTime := int64(0)
+
+	p := ProfilingFor(nil, AllowUnprepared(true), LabelExports(true)).CPUProfiler(
+		HostTime(true),
+		TimeFunc(func() int64 { return currentTime }),
+	)
+
+	start := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	start.ExportNames = []string{"_start"}
+	handle := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	handle.ExportNames = []string{"handle_request"}
+	module := wazerotest.NewModule(nil, start, handle,
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+	)
+	def := module.Function(2).Definition()
+	listener := p.NewFunctionListener(def)
+	ctx := context.Background()
+
+	p.StartProfile()
+	// The same function is called by the exported functions, and by a
+	// function which is not exported.
+	for i, root := range []api.Function{module.Function(0), module.Function(1), module.Function(1)} {
+		stack := []experimental.StackFrame{{Function: module.Function(2)}, {Function: root}}
+		listener.Before(ctx, module, def, nil, experimental.NewStackIterator(stack...))
+		currentTime += int64(i+1) * 10
+		listener.After(ctx, module, def, nil)
+	}
+	stack := []experimental.StackFrame{{Function: module.Function(2)}}
+	listener.Before(ctx, module, def, nil, experimental.NewStackIterator(stack...))
+	currentTime += 100
+	listener.After(ctx, module, def, nil)
+	prof := p.StopProfile(1)
+
+	exports := map[string]int64{}
+	for _, sample := range prof.Sample {
+		name := ""
+		if labels := sample.Label["export"]; len(labels) == 1 {
+			name = labels[0]
+		}
+		exports[name] += sample.Value[1]
+	}
+	if exports["_start"] != 10 || exports["handle_request"] != 50 || exports[""] != 100 {
+		t.Errorf("wrong cpu time per export: %v", exports)
+	}
+}
+
+type spanKey struct{}
+
+func TestContextLabels(t *testing.T) {
+	currentTime := int64(0)
+
+	p := ProfilingFor(nil,
+		AllowUnprepared(true),
+		ContextLabels(func(ctx context.Context) []string {
+			span, _ := ctx.Value(spanKey{}).(string)
+			if span == "" {
+				return nil
+			}
+			return []string{"span_id", span}
+		}),
+	).CPUProfiler(
+		HostTime(true),
+		TimeFunc(func() int64 { return currentTime }),
+	)
+
+	module := wazerotest.NewModule(nil,
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+	)
+	def := module.Function(0).Definition()
+	listener := p.NewFunctionListener(def)
+	stack := []experimental.StackFrame{{Function: module.Function(0)}}
+
+	p.StartProfile()
+	for _, call := range []struct {
+		span     string
+		duration int64
+	}{
+		{"1", 10},
+		{"2", 20},
+		{"1", 30},
+		{"", 40},
+	} {
+		ctx := WithLabels(context.Background(), "tenant", "a")
+		if call.span != "" {
+			ctx = context.WithValue(ctx, spanKey{}, call.span)
+		}
+		currentTime++
+		listener.Before(ctx, module, def, nil, experimental.NewStackIterator(stack...))
+		currentTime += call.duration
+		listener.After(ctx, module, def, nil)
+	}
+	prof := p.StopProfile(1)
+
+	if len(prof.Sample) != 3 {
+		t.Fatalf("wrong number of samples: want=3 got=%d", len(prof.Sample))
+	}
+	spans := map[string]int64{}
+	for _, sample := range prof.Sample {
+		if tenant := sample.Label["tenant"]; len(tenant) != 1 || tenant[0] != "a" {
+			t.Errorf("sample is missing the tenant label: %v", sample.Label)
+		}
+		var span string
+		if labels := sample.Label["span_id"]; len(labels) == 1 {
+			span = labels[0]
+		}
+		spans[span] = sample.Value[1]
+	}
+	if spans["1"] != 40 || spans["2"] != 20 || spans[""] != 40 {
+		t.Errorf("wrong cpu time per span: %v", spans)
+	}
+}
@@ -3,7 +3,10 @@ package wzprof
 import (
 	"context"
 	"encoding/binary"
+	"io"
+	"math/bits"
 	"net/http"
+	"sort"
 	"sync"
 	"time"
 
@@ -24,12 +27,28 @@ import (
 // "alloc_objects" and "alloc_space" are all time counters since the start of
 // the program, while "inuse_objects" and "inuse_space" capture the current state
 // of the program at the time the profile is taken.
+//
+// When the profiler is wrapped by Sample, it observes one every N calls to the
+// allocation functions, the values of the profiles are multiplied by N (the
+// inverse of the sample rate passed to the methods building profiles) so they
+// approximate the allocations of the program, and N is recorded as the period
+// of the profiles. Profilers tracking the objects in use are not sampled (see
+// InuseMemory).
 type MemoryProfiler struct {
-	p     *Profiling
-	mutex sync.Mutex
-	alloc stackCounterMap
-	inuse map[uint32]memoryAllocation
-	start time.Time
+	p       *Profiling
+	mutex   sync.Mutex
+	alloc   stackCounterMap
+	inuse   map[uint32]memoryAllocation
+	bytes   int64 // total size of the objects in inuse
+	sizes   bool
+	all     bool // not sampled, see UnsampledMemory
+	start   time.Time
+	nesting threadLocal[allocNesting]
+
+	// State of the profile recorded between calls to StartProfile and
+	// StopProfile.
+	profileBase  map[uint64]*memorySample
+	profileStart time.Time
 }
 
 // MemoryProfilerOption is a type used to represent configuration options for
@@ -39,14 +58,142 @@ type MemoryProfilerOption func(*MemoryProfiler)
 // InuseMemory is a memory profiler option which enables tracking of allocated
 // and freed objects to generate snapshots of the current state of a program
 // memory.
+//
+// When enabled, the profiles contain the "inuse_objects" and "inuse_space"
+// sample types in addition to the allocation counters, similarly to the heap
+// profiles of Go programs.
+//
+// The live objects are tracked by intercepting calls to the functions releasing
+// memory (e.g. free, realloc), which means that memory managed by a garbage
+// collector is not accounted for, with the exception of TinyGo where the blocks
+// released by the garbage collector are observed during the sweep phase.
+//
+// The objects in use are only accurate if no allocation or release is missed,
+// enabling the option therefore implies UnsampledMemory.
 func InuseMemory(enable bool) MemoryProfilerOption {
 	return func(p *MemoryProfiler) {
 		if enable {
 			p.inuse = make(map[uint32]memoryAllocation)
+		} else {
+			p.inuse = nil
 		}
 	}
 }
 
+// AllocationSizes is a memory profiler option which records the allocations of
+// each call stack separately for each power-of-two size class, which allows
+// distinguishing call stacks making many small allocations from those making a
+// few large ones.
+//
+// When enabled, the samples carry a "bytes" numeric label set to the lower
+// bound of their size class (e.g. 64 for allocations of 64 to 127 bytes), which
+// can be used with the -tags and -tagfocus options of pprof, similarly to the
+// heap profiles of Go programs.
+func AllocationSizes(enable bool) MemoryProfilerOption {
+	return func(p *MemoryProfiler) { p.sizes = enable }
+}
+
+// UnsampledMemory is a memory profiler option which leaves the listeners of the
+// profiler unsampled when it is wrapped by Sample or Compose, so they observe
+// every allocation. Since every call is recorded, the values of the profiles
+// are not scaled by the sample rate passed to the methods building them.
+//
+// Default to false.
+func UnsampledMemory(enable bool) MemoryProfilerOption {
+	return func(p *MemoryProfiler) { p.all = enable }
+}
+
+func (p *MemoryProfiler) unsampled() bool {
+	return p.all || p.inuse != nil
+}
+
+// ratio returns the factor that the values of the profiles built at the given
+// sample rate are multiplied by.
+func (p *MemoryProfiler) ratio(sampleRate float64) float64 {
+	if p.unsampled() {
+		return 1
+	}
+	return 1 / sampleRate
+}
+
+// Allocator describes a memory allocation function intercepted by the memory
+// profiler, see Allocators.
+type Allocator struct {
+	name string
+	kind byte // 'm' for malloc, 'c' for calloc, 'r' for realloc, 'f' for free
+	args allocArgs
+}
+
+// allocArgs are the positions of the parameters of allocation functions. The
+// zero value holds the positions of the parameters of malloc and free.
+type allocArgs struct {
+	addr  int
+	count int
+	size  int
+}
+
+var (
+	callocArgs  = allocArgs{count: 0, size: 1}
+	reallocArgs = allocArgs{addr: 0, size: 1}
+	// __rust_realloc(ptr, old_size, align, new_size)
+	rustReallocArgs = allocArgs{addr: 0, size: 3}
+	// The allocators of CPython receive the context of their domain first,
+	// e.g. _PyObject_Malloc(ctx, size).
+	pyMallocArgs  = allocArgs{size: 1}
+	pyCallocArgs  = allocArgs{count: 1, size: 2}
+	pyReallocArgs = allocArgs{addr: 1, size: 2}
+	pyFreeArgs    = allocArgs{addr: 1}
+)
+
+// MallocFunc describes a function allocating memory like malloc, returning the
+// address of an object of the size passed as the parameter at position size.
+func MallocFunc(name string, size int) Allocator {
+	return Allocator{name: name, kind: 'm', args: allocArgs{size: size}}
+}
+
+// CallocFunc describes a function allocating memory like calloc, returning the
+// address of an array of count objects of the given size, which are passed as
+// the parameters at the positions count and size.
+func CallocFunc(name string, count, size int) Allocator {
+	return Allocator{name: name, kind: 'c', args: allocArgs{count: count, size: size}}
+}
+
+// ReallocFunc describes a function reallocating memory like realloc, returning
+// the address of an object of the size passed as the parameter at position
+// size, which replaces the object at the address passed as the parameter at
+// position addr.
+func ReallocFunc(name string, addr, size int) Allocator {
+	return Allocator{name: name, kind: 'r', args: allocArgs{addr: addr, size: size}}
+}
+
+// FreeFunc describes a function releasing memory like free, the address of the
+// object released is the parameter at position addr.
+func FreeFunc(name string, addr int) Allocator {
+	return Allocator{name: name, kind: 'f', args: allocArgs{addr: addr}}
+}
+
+// listener returns the listener of the memory profiler p intercepting calls to
+// the allocation function def, or nil if the signature of the function does
+// not match a.
+func (a Allocator) listener(p *MemoryProfiler, def api.FunctionDefinition) experimental.FunctionListener {
+	params := len(def.ParamTypes())
+	param := func(i int) bool { return i >= 0 && i < params }
+	// The allocation functions return the address of the objects.
+	result := len(def.ResultTypes()) > 0
+	switch {
+	case a.kind == 'm' && param(a.args.size) && result:
+		return profilingListener{p.p, &mallocProfiler{memory: p, args: a.args}}
+	case a.kind == 'c' && param(a.args.count) && param(a.args.size) && result:
+		return profilingListener{p.p, &callocProfiler{memory: p, args: a.args}}
+	case a.kind == 'r' && param(a.args.addr) && param(a.args.size) && result:
+		return profilingListener{p.p, &reallocProfiler{memory: p, args: a.args}}
+	case a.kind == 'f' && param(a.args.addr):
+		return profilingListener{p.p, &freeProfiler{memory: p, args: a.args}}
+	default:
+		return nil
+	}
+}
+
 type memoryAllocation struct {
 	*stackCounter
 	size uint32
@@ -69,10 +216,162 @@ func newMemoryProfiler(p *Profiling, options ...MemoryProfilerOption) *MemoryPro
 // NewProfile takes a snapshot of the current memory allocation state and builds
 // a profile representing the state of the program memory.
 func (p *MemoryProfiler) NewProfile(sampleRate float64) *profile.Profile {
-	ratio := 1 / sampleRate
-	return buildProfile(p.p, p.snapshot(), p.start, time.Since(p.start), p.SampleType(),
+	return p.p.endProfile(p.Name(), p.newProfile(sampleRate))
+}
+
+func (p *MemoryProfiler) newProfile(sampleRate float64) *profile.Profile {
+	return p.buildProfile(p.snapshot(), p.start, sampleRate)
+}
+
+func (p *MemoryProfiler) buildProfile(samples map[uint64]*memorySample, start time.Time, sampleRate float64) *profile.Profile {
+	ratio := p.ratio(sampleRate)
+	prof := buildProfile(p.p, samples, start, time.Since(start), p.SampleType(),
 		[]float64{ratio, ratio, ratio, ratio},
 	)
+	prof.DefaultSampleType = p.defaultSampleType()
+	return prof
+}
+
+// WriteProfile is like NewProfile but writes the profile to w in the protobuf
+// format of pprof (see EncodeProfile). The samples are encoded as they are
+// converted instead of building the whole profile in memory first, which keeps
+// the memory usage low when writing profiles of millions of call stacks.
+//
+// Deterministic profiles are sorted, and are therefore built in memory, as well
+// as the profiles passed to the OnProfileEnd hook.
+func (p *MemoryProfiler) WriteProfile(w io.Writer, sampleRate float64, options ...WriteOption) error {
+	if p.p.deterministic || p.p.onProfileEnd != nil {
+		return EncodeProfile(w, p.NewProfile(sampleRate), options...)
+	}
+	samples := p.snapshot()
+	sampleType := p.SampleType()
+	ratio := p.ratio(sampleRate)
+	ratios := []float64{ratio, ratio, ratio, ratio}[:len(sampleType)]
+
+	prof := newProfileHeader(p.p, p.start, time.Since(p.start), sampleType, ratios)
+	prof.DefaultSampleType = p.defaultSampleType()
+	return encodeProfile(w, options, func(w io.Writer) error {
+		return streamProfile(w, p.p, prof, samples, ratios)
+	})
+}
+
+// HeapSnapshot returns a profile of the objects in use by the program, with a
+// sample for each object rather than for each call stack. The samples carry an
+// "address" numeric label set to the address of their object in the linear
+// memory, and have the "inuse_objects" and "inuse_space" sample types.
+//
+// Comparing two snapshots (e.g. with "wzprof diff") cancels out the objects
+// present in both and leaves the objects allocated in between which were not
+// released, which helps investigating memory leaks. The snapshot only holds the
+// objects observed by the profiler, it should not be wrapped by Sample.
+//
+// The method returns nil if the memory in use is not tracked (see InuseMemory).
+func (p *MemoryProfiler) HeapSnapshot() *profile.Profile {
+	p.mutex.Lock()
+	if p.inuse == nil {
+		p.mutex.Unlock()
+		return nil
+	}
+	objects := make(map[uint64]*memorySample, len(p.inuse))
+	for addr, inuse := range p.inuse {
+		objects[uint64(addr)] = &memorySample{
+			stack: inuse.stack,
+			value: [4]int64{1, int64(inuse.size)},
+		}
+	}
+	p.mutex.Unlock()
+
+	addrs := make([]uint64, 0, len(objects))
+	for addr := range objects {
+		addrs = append(addrs, addr)
+	}
+	sort.Slice(addrs, func(i, j int) bool { return addrs[i] < addrs[j] })
+
+	sampleType := []*profile.ValueType{
+		{Type: "inuse_objects", Unit: "count"},
+		{Type: "inuse_space", Unit: "bytes"},
+	}
+	prof := newProfileHeader(p.p, time.Now(), 0, sampleType, []float64{1, 1})
+	prof.DefaultSampleType = "inuse_space"
+	prof.Sample = make([]*profile.Sample, len(addrs))
+
+	b := newProfileBuilder(p.p, objects)
+	for i, addr := range addrs {
+		s := b.sample(objects[addr], len(sampleType))
+		s.NumLabel = map[string][]int64{"address": {int64(addr)}}
+		prof.Sample[i] = s
+	}
+	b.finish(prof)
+	return p.p.endProfile("heap", prof)
+}
+
+// inuseBytes returns the total size of the objects in use, and false if the
+// memory in use is not tracked.
+func (p *MemoryProfiler) inuseBytes() (int64, bool) {
+	p.mutex.Lock()
+	defer p.mutex.Unlock()
+	return p.bytes, p.inuse != nil
+}
+
+func (p *MemoryProfiler) defaultSampleType() string {
+	if p.inuse != nil {
+		// Match the default of Go heap profiles when the memory in use is
+		// being tracked.
+		return "inuse_space"
+	}
+	return ""
+}
+
+// StartProfile begins recording a memory profile of the allocations made until
+// StopProfile is called, which allows capturing consecutive profiles during the
+// lifetime of a module instance (e.g. one profile per request handled by the
+// guest). The method returns a boolean to indicate whether starting the profile
+// succeeded (e.g. false is returned if it was already started).
+//
+// Recording a profile does not affect the profiles returned by NewProfile.
+func (p *MemoryProfiler) StartProfile() bool {
+	base := p.snapshot()
+
+	p.mutex.Lock()
+	if p.profileBase != nil {
+		p.mutex.Unlock()
+		return false // already started
+	}
+	p.profileBase = base
+	p.profileStart = time.Now()
+	p.mutex.Unlock()
+
+	p.p.startProfile(p.Name())
+	return true
+}
+
+// StopProfile stops recording and returns the memory profile. The method
+// returns nil if recording of the memory profile wasn't started.
+//
+// The allocation counters of the profile are the allocations made since the
+// call to StartProfile, and the memory in use (if enabled by InuseMemory) is
+// the state of the program memory at the time StopProfile is called.
+func (p *MemoryProfiler) StopProfile(sampleRate float64) *profile.Profile {
+	p.mutex.Lock()
+	base, start := p.profileBase, p.profileStart
+	p.profileBase = nil
+	p.mutex.Unlock()
+
+	if base == nil {
+		return nil
+	}
+
+	samples := p.snapshot()
+	for k, sample := range samples {
+		if b := base[k]; b != nil {
+			sample.value[0] -= b.value[0]
+			sample.value[1] -= b.value[1]
+		}
+		if sample.value == [4]int64{} {
+			delete(samples, k)
+		}
+	}
+	return p.p.endProfile(p.Name(), p.buildProfile(samples, start, sampleRate))
 }
 
 // Name returns "allocs" to match the name of the memory profiler in pprof.
@@ -102,9 +401,9 @@ func (p *MemoryProfiler) SampleType() []*profile.ValueType {
 	}
 
 	if p.inuse != nil {
-		// TODO: when can track freeing of garbage collected languages like Go,
-		// this should be enabled by default, and we can remove the slicing of
-		// sample values in buildProfile.
+		// TODO: when we can track freeing of garbage collected languages like
+		// Go, this should be enabled by default, and we can remove the slicing
+		// of sample values in buildProfile.
 		sampleType = append(sampleType,
 			&profile.ValueType{Type: "inuse_objects", Unit: "count"},
 			&profile.ValueType{Type: "inuse_space", Unit: "bytes"},
@@ -117,6 +416,7 @@ func (p *MemoryProfiler) SampleType() []*profile.ValueType {
 type memorySample struct {
 	stack stackTrace
 	value [4]int64 // allocCount, allocBytes, inuseCount, inuseBytes
+	bytes int64    // lower bound of the size class, if AllocationSizes is enabled
 }
 
 func (m *memorySample) sampleLocation() stackTrace {
@@ -127,6 +427,10 @@ func (m *memorySample) sampleValue() []int64 {
 	return m.value[:]
 }
 
+func (m *memorySample) sampleBytes() int64 {
+	return m.bytes
+}
+
 func (p *MemoryProfiler) snapshot() map[uint64]*memorySample {
 	// We hold an exclusive lock while getting a snapshot of the profiler state.
 	// This will block concurrent calls to malloc/free/etc... We accept the cost
@@ -147,8 +451,21 @@ func (p *MemoryProfiler) snapshot() map[uint64]*memorySample {
 		p.value[1] += alloc.total()
 	}
 
+	if p.sizes {
+		for _, sample := range samples {
+			sample.bytes = sizeClass(sample.value[0], sample.value[1])
+		}
+	}
+
 	for _, inuse := range p.inuse {
 		p := samples[inuse.stack.key]
+		if p == nil {
+			// The allocation counter was merged into its caller by
+			// compact, the memory in use remains attributed to the
+			// call stack which allocated it.
+			p = &memorySample{stack: inuse.stack}
+			samples[inuse.stack.key] = p
+		}
 		p.value[2] += 1
 		p.value[3] += int64(inuse.size)
 	}
@@ -163,11 +480,21 @@ func (p *MemoryProfiler) snapshot() map[uint64]*memorySample {
 // based on the sampling rate applied to the profiler so the resulting values
 // remain representative.
 //
-// The symbolizer passed as argument is used to resolve names of program
-// locations recorded in the profile.
+// When the request has a "seconds" query parameter, the handler responds with
+// a delta profile of the memory allocations observed during that period.
+// Otherwise, the profile contains the cumulative allocations since the profiler
+// was created, and is streamed to the client (see WriteProfile).
 func (p *MemoryProfiler) NewHandler(sampleRate float64) http.Handler {
 	return http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
-		serveProfile(w, p.NewProfile(sampleRate))
+		if r.FormValue("seconds") == "" {
+			serveProfileWith(w, r, func(w io.Writer, options ...WriteOption) error {
+				return p.WriteProfile(w, sampleRate, options...)
+			})
+			return
+		}
+		serveCumulativeProfile(w, r, p.p, p.Name(), func() *profile.Profile {
+			return p.newProfile(sampleRate)
+		})
 	})
 }
 
@@ -176,37 +503,68 @@ func (p *MemoryProfiler) NewHandler(sampleRate float64) http.Handler {
 //
 // The listener recognizes multiple memory allocation functions used by
 // compilers and libraries. It uses the function name to detect memory
-// allocators, currently supporting libc, Go, and TinyGo.
+// allocators, currently supporting libc, Go, and TinyGo. Other allocators can
+// be intercepted with the Allocators option.
 func (p *MemoryProfiler) NewFunctionListener(def api.FunctionDefinition) experimental.FunctionListener {
-	if p.p.lang == python311 {
+	if a, ok := p.p.allocators[def.Name()]; ok {
+		return a.listener(p, def)
+	}
+	if p.p.lang == python3 {
 		switch def.Name() {
 		// Raw domain
 		case "PyMem_RawMalloc":
 			return profilingListener{p.p, &mallocProfiler{memory: p}}
 		case "PyMem_RawCalloc":
-			return profilingListener{p.p, &callocProfiler{memory: p}}
+			return profilingListener{p.p, &callocProfiler{memory: p, args: callocArgs}}
 		case "PyMem_RawRealloc":
-			return profilingListener{p.p, &reallocProfiler{memory: p}}
+			return profilingListener{p.p, &reallocProfiler{memory: p, args: reallocArgs}}
 		case "PyMem_RawFree":
 			return profilingListener{p.p, &freeProfiler{memory: p}}
 		// Memory domain
 		case "PyMem_Malloc":
 			return profilingListener{p.p, &mallocProfiler{memory: p}}
 		case "PyMem_Calloc":
-			return profilingListener{p.p, &callocProfiler{memory: p}}
+			return profilingListener{p.p, &callocProfiler{memory: p, args: callocArgs}}
 		case "PyMem_Realloc":
-			return profilingListener{p.p, &reallocProfiler{memory: p}}
+			return profilingListener{p.p, &reallocProfiler{memory: p, args: reallocArgs}}
 		case "PyMem_Free":
 			return profilingListener{p.p, &freeProfiler{memory: p}}
 		// Object domain
 		case "PyObject_Malloc":
 			return profilingListener{p.p, &mallocProfiler{memory: p}}
 		case "PyObject_Calloc":
-			return profilingListener{p.p, &callocProfiler{memory: p}}
+			return profilingListener{p.p, &callocProfiler{memory: p, args: callocArgs}}
 		case "PyObject_Realloc":
-			return profilingListener{p.p, &reallocProfiler{memory: p}}
+			return profilingListener{p.p, &reallocProfiler{memory: p, args: reallocArgs}}
 		case "PyObject_Free":
 			return profilingListener{p.p, &freeProfiler{memory: p}}
+		// The object allocators of pymalloc (and mimalloc since 3.13) are
+		// called through the function pointers of the object domain, not
+		// only by the functions above. Nested calls are recorded once, and
+		// the call stacks are the ones of the Python interpreter, so the
+		// objects are attributed to the Python code allocating them.
+		case "_PyObject_Malloc", "_PyObject_MiMalloc":
+			return profilingListener{p.p, &mallocProfiler{memory: p, args: pyMallocArgs}}
+		case "_PyObject_Calloc", "_PyObject_MiCalloc":
+			return profilingListener{p.p, &callocProfiler{memory: p, args: pyCallocArgs}}
+		case "_PyObject_Realloc", "_PyObject_MiRealloc":
+			return profilingListener{p.p, &reallocProfiler{memory: p, args: pyReallocArgs}}
+		case "_PyObject_Free", "_PyObject_MiFree":
+			return profilingListener{p.p, &freeProfiler{memory: p, args: pyFreeArgs}}
+		}
+		return nil
+	}
+	if p.p.lang == tinygo {
+		switch def.Name() {
+		// The malloc functions exported by TinyGo are implemented on top of
+		// runtime.alloc, only the latter is instrumented to avoid counting
+		// the allocations twice.
+		case "runtime.alloc":
+			return profilingListener{p.p, &mallocProfiler{memory: p}}
+		case "(runtime.gcBlock).markFree":
+			if p.inuse != nil && p.p.tinygoHeapStart != 0 {
+				return profilingListener{p.p, &tinygoMarkFreeProfiler{memory: p}}
+			}
 		}
 		return nil
 	}
@@ -215,12 +573,22 @@ func (p *MemoryProfiler) NewFunctionListener(def api.FunctionDefinition) experim
 	case "malloc":
 		return profilingListener{p.p, &mallocProfiler{memory: p}}
 	case "calloc":
-		return profilingListener{p.p, &callocProfiler{memory: p}}
+		return profilingListener{p.p, &callocProfiler{memory: p, args: callocArgs}}
 	case "realloc":
-		return profilingListener{p.p, &reallocProfiler{memory: p}}
+		return profilingListener{p.p, &reallocProfiler{memory: p, args: reallocArgs}}
 	case "free":
 		return profilingListener{p.p, &freeProfiler{memory: p}}
 
+	// Rust global allocators, which are usually backed by the C standard
+	// library. The calls to the functions of the C standard library made by
+	// the global allocator are recorded instead (see allocNesting).
+	case "__rust_alloc", "__rust_alloc_zeroed":
+		return profilingListener{p.p, &mallocProfiler{memory: p}}
+	case "__rust_realloc":
+		return profilingListener{p.p, &reallocProfiler{memory: p, args: rustReallocArgs}}
+	case "__rust_dealloc":
+		return profilingListener{p.p, &freeProfiler{memory: p}}
+
 	// Go
 	case "runtime.mallocgc":
 		return profilingListener{p.p, &goRuntimeMallocgcProfiler{memory: p}}
@@ -235,128 +603,287 @@ func (p *MemoryProfiler) NewFunctionListener(def api.FunctionDefinition) experim
 }
 
 func (p *MemoryProfiler) observeAlloc(addr, size uint32, stack stackTrace) {
+	stack, value, ok := p.p.hookSample(p.Name(), stack, int64(size))
+	if !ok {
+		return
+	}
+	size = uint32(value)
+	if p.sizes {
+		stack.key = sizeClassKey(stack.key, size)
+	}
 	p.mutex.Lock()
 	alloc := p.alloc.lookup(stack)
 	alloc.observe(int64(size))
-	if p.inuse != nil {
-		p.inuse[addr] = memoryAllocation{alloc, size}
+	p.alloc.compact(p.p.maxStacks, addStackCounters)
+	// A null address is returned when the allocation failed, or when the
+	// address of the allocated object could not be determined.
+	if p.inuse != nil && addr != 0 {
+		p.trackObject(addr, memoryAllocation{alloc, size})
 	}
 	p.mutex.Unlock()
 }
 
+// trackObject records the object in use at addr, replacing the object which
+// may not have been seen being released there. The mutex must be held.
+func (p *MemoryProfiler) trackObject(addr uint32, object memoryAllocation) {
+	p.bytes += int64(object.size) - int64(p.inuse[addr].size)
+	p.inuse[addr] = object
+}
+
+// sizeClass returns the lower bound of the power-of-two size class of
+// allocations, given their count and total size. All the allocations belong to
+// the same class, so does their average size.
+func sizeClass(count, total int64) int64 {
+	if count == 0 || total < count {
+		return 0
+	}
+	return 1 << (bits.Len64(uint64(total/count)) - 1)
+}
+
+// sizeClassKey mixes the size class of allocations of the given size in the key
+// of their call stack, so the allocations of each class are counted separately.
+func sizeClassKey(key uint64, size uint32) uint64 {
+	return key ^ uint64(bits.Len32(size))*0xbf58476d1ce4e5b9
+}
+
 func (p *MemoryProfiler) observeFree(addr uint32) {
-	if p.inuse != nil {
-		p.mutex.Lock()
+	p.releaseObject(addr)
+}
+
+// releaseObject stops tracking the object in use at addr, and returns it so it
+// can be restored if the memory was not actually released.
+//
+// With shared memories, the threads of a program allocate from the same heap,
+// and the memory released by a thread may be reused by another thread before
+// the listener of the function releasing it returns. The objects are released
+// before calling the functions (when the memory is still owned by the thread)
+// so the objects allocated concurrently at the same address are not lost.
+func (p *MemoryProfiler) releaseObject(addr uint32) memoryAllocation {
+	if p.inuse == nil {
+		return memoryAllocation{}
+	}
+	p.mutex.Lock()
+	object, ok := p.inuse[addr]
+	if ok {
+		p.bytes -= int64(object.size)
 		delete(p.inuse, addr)
-		p.mutex.Unlock()
 	}
+	p.mutex.Unlock()
+	return object
 }
 
-type mallocProfiler struct {
-	memory *MemoryProfiler
+// restoreObject tracks again the object at addr returned by releaseObject, for
+// example when a reallocation failed and left the memory unchanged.
+func (p *MemoryProfiler) restoreObject(addr uint32, object memoryAllocation) {
+	if p.inuse == nil || object.stackCounter == nil {
+		return
+	}
+	p.mutex.Lock()
+	p.trackObject(addr, object)
+	p.mutex.Unlock()
+}
+
+// allocNesting tracks the calls to allocation functions in progress on a thread
+// of the program. Allocators are often layered (e.g. the Rust global allocator
+// calling malloc, or calloc calling malloc), only the innermost calls are
+// recorded so the allocations are not counted multiple times.
+type allocNesting struct {
+	// For each call in progress, whether it made calls to allocation
+	// functions.
+	inner []bool
+}
+
+// enter is called by the listeners of allocation functions when they are
+// called.
+func (p *MemoryProfiler) enter(mod api.Module) {
+	n := p.nesting.get(mod)
+	n.inner = append(n.inner, false)
+}
+
+// leave is called by the listeners of allocation functions when they return,
+// it returns false if the call must not be recorded because the allocation
+// function called other allocation functions which were.
+func (p *MemoryProfiler) leave(mod api.Module) bool {
+	n := p.nesting.get(mod)
+	i := len(n.inner) - 1
+	if i < 0 {
+		return true
+	}
+	inner := n.inner[i]
+	n.inner = n.inner[:i]
+	if i > 0 {
+		n.inner[i-1] = true
+	}
+	return !inner
+}
+
+// allocCall is the state recorded by memory allocation listeners between calls
+// to Before and After, which is kept for each thread of the program.
+type allocCall struct {
+	addr   uint32
+	count  uint32
 	size   uint32
 	stack  stackTrace
+	object memoryAllocation // object released at addr, see releaseObject
+}
+
+type mallocProfiler struct {
+	memory *MemoryProfiler
+	args   allocArgs
+	calls  threadLocal[allocCall]
 }
 
 func (p *mallocProfiler) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, params []uint64, si experimental.StackIterator) {
-	p.size = api.DecodeU32(params[0])
-	p.stack = makeStackTrace(p.stack, si)
+	p.memory.enter(mod)
+	c := p.calls.get(mod)
+	c.size = api.DecodeU32(params[p.args.size])
+	c.stack = makeStackTrace(ctx, c.stack, p.memory.p.threads.lookup(mod), si, p.memory.p.maxFrames)
 }
 
 func (p *mallocProfiler) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, results []uint64) {
-	p.memory.observeAlloc(api.DecodeU32(results[0]), p.size, p.stack)
+	if p.memory.leave(mod) {
+		c := p.calls.get(mod)
+		p.memory.observeAlloc(api.DecodeU32(results[0]), c.size, c.stack)
+	}
 }
 
 func (p *mallocProfiler) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
+	p.memory.leave(mod)
 }
 
 type callocProfiler struct {
 	memory *MemoryProfiler
-	count  uint32
-	size   uint32
-	stack  stackTrace
+	args   allocArgs
+	calls  threadLocal[allocCall]
 }
 
 func (p *callocProfiler) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, params []uint64, si experimental.StackIterator) {
-	p.count = api.DecodeU32(params[0])
-	p.size = api.DecodeU32(params[1])
-	p.stack = makeStackTrace(p.stack, si)
+	p.memory.enter(mod)
+	c := p.calls.get(mod)
+	c.count = api.DecodeU32(params[p.args.count])
+	c.size = api.DecodeU32(params[p.args.size])
+	c.stack = makeStackTrace(ctx, c.stack, p.memory.p.threads.lookup(mod), si, p.memory.p.maxFrames)
 }
 
 func (p *callocProfiler) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, results []uint64) {
-	p.memory.observeAlloc(api.DecodeU32(results[0]), p.count*p.size, p.stack)
+	if p.memory.leave(mod) {
+		c := p.calls.get(mod)
+		p.memory.observeAlloc(api.DecodeU32(results[0]), c.count*c.size, c.stack)
+	}
 }
 
 func (p *callocProfiler) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
+	p.memory.leave(mod)
 }
 
 type reallocProfiler struct {
 	memory *MemoryProfiler
-	addr   uint32
-	size   uint32
-	stack  stackTrace
+	args   allocArgs
+	calls  threadLocal[allocCall]
 }
 
 func (p *reallocProfiler) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, params []uint64, si experimental.StackIterator) {
-	p.addr = api.DecodeU32(params[0])
-	p.size = api.DecodeU32(params[1])
-	p.stack = makeStackTrace(p.stack, si)
+	p.memory.enter(mod)
+	c := p.calls.get(mod)
+	c.addr = api.DecodeU32(params[p.args.addr])
+	c.size = api.DecodeU32(params[p.args.size])
+	c.stack = makeStackTrace(ctx, c.stack, p.memory.p.threads.lookup(mod), si, p.memory.p.maxFrames)
+	c.object = p.memory.releaseObject(c.addr)
 }
 
 func (p *reallocProfiler) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, results []uint64) {
-	p.memory.observeFree(p.addr)
-	p.memory.observeAlloc(api.DecodeU32(results[0]), p.size, p.stack)
+	recorded := p.memory.leave(mod)
+	c := p.calls.get(mod)
+	addr := api.DecodeU32(results[0])
+	if addr == 0 && c.size != 0 {
+		// The reallocation failed, the original memory block is left
+		// untouched.
+		p.memory.restoreObject(c.addr, c.object)
+		return
+	}
+	if recorded {
+		p.memory.observeAlloc(addr, c.size, c.stack)
+	}
 }
 
 func (p *reallocProfiler) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
+	p.memory.leave(mod)
+	c := p.calls.get(mod)
+	p.memory.restoreObject(c.addr, c.object)
 }
 
 type freeProfiler struct {
 	memory *MemoryProfiler
-	addr   uint32
+	args   allocArgs
 }
 
 func (p *freeProfiler) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, params []uint64, si experimental.StackIterator) {
-	p.addr = api.DecodeU32(params[0])
+	p.memory.enter(mod)
+	// Releasing the objects of nested calls again is harmless, the objects
+	// are only tracked at the address returned by the innermost allocation.
+	p.memory.observeFree(api.DecodeU32(params[p.args.addr]))
 }
 
 func (p *freeProfiler) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64) {
-	p.memory.observeFree(p.addr)
+	p.memory.leave(mod)
 }
 
 func (p *freeProfiler) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
-	p.After(ctx, mod, def, nil)
+	p.memory.leave(mod)
 }
 
 type goRuntimeMallocgcProfiler struct {
 	memory *MemoryProfiler
-	size   uint32
-	stack  stackTrace
+	calls  threadLocal[allocCall]
 }
 
 func (p *goRuntimeMallocgcProfiler) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, params []uint64, wasmsi experimental.StackIterator) {
 	imod := mod.(experimental.InternalModule)
 	mem := imod.Memory()
+	c := p.calls.get(mod)
 
 	sp := uint32(imod.Global(0).Get())
 	offset := sp + 8*(uint32(0)+1) // +1 for the return address
 	b, ok := mem.Read(offset, 8)
 	if ok {
-		p.size = binary.LittleEndian.Uint32(b)
-		p.stack = makeStackTrace(p.stack, wasmsi)
+		c.size = binary.LittleEndian.Uint32(b)
+		c.stack = makeStackTrace(ctx, c.stack, p.memory.p.threads.lookup(mod), wasmsi, p.memory.p.maxFrames)
 	} else {
-		p.size = 0
+		c.size = 0
 	}
 }
 
 func (p *goRuntimeMallocgcProfiler) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64) {
-	if p.size != 0 {
+	c := p.calls.get(mod)
+	if c.size != 0 {
 		// TODO: get the returned pointer
 		addr := uint32(0)
-		p.memory.observeAlloc(addr, p.size, p.stack)
+		p.memory.observeAlloc(addr, c.size, c.stack)
 	}
 }
 
 func (p *goRuntimeMallocgcProfiler) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
 	p.After(ctx, mod, def, nil)
 }
+
+// tinygoMarkFreeProfiler observes the blocks of the TinyGo heap released by the
+// garbage collector. Objects span a head block followed by tail blocks, which
+// are freed individually during the sweep phase; only the address of the head
+// block matches an allocation tracked by the profiler.
+type tinygoMarkFreeProfiler struct {
+	memory *MemoryProfiler
+}
+
+func (p *tinygoMarkFreeProfiler) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, params []uint64, si experimental.StackIterator) {
+	heapStart, ok := mod.Memory().ReadUint32Le(p.memory.p.tinygoHeapStart)
+	if ok {
+		block := api.DecodeU32(params[0])
+		p.memory.observeFree(heapStart + block*tinygoBytesPerBlock)
+	}
+}
+
+func (p *tinygoMarkFreeProfiler) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64) {
+}
+
+func (p *tinygoMarkFreeProfiler) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
+}
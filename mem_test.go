@@ -1,10 +1,571 @@
 package wzprof
 
 import (
+	"context"
 	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
 )
 
 func BenchmarkMemoryProfiler(b *testing.B) {
-	p := ProfilingFor(nil).MemoryProfiler()
+	p := ProfilingFor(nil, AllowUnprepared(true)).MemoryProfiler()
 	benchmarkFunctionListener(b, p)
 }
+
+func TestMemoryProfilerInuse(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).MemoryProfiler(InuseMemory(true))
+
+	malloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+	malloc.FunctionName = "malloc"
+
+	free := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, addr uint32) {})
+	free.FunctionName = "free"
+
+	module := wazerotest.NewModule(nil, malloc, free)
+	stack := []experimental.StackFrame{{Function: malloc}}
+	ctx := context.Background()
+
+	mallocDef := malloc.Definition()
+	mallocListener := p.NewFunctionListener(mallocDef)
+	for _, addr := range []uint64{16, 32, 48} {
+		mallocListener.Before(ctx, module, mallocDef, []uint64{10}, experimental.NewStackIterator(stack...))
+		mallocListener.After(ctx, module, mallocDef, []uint64{addr})
+	}
+
+	freeDef := free.Definition()
+	freeListener := p.NewFunctionListener(freeDef)
+	freeListener.Before(ctx, module, freeDef, []uint64{32}, experimental.NewStackIterator(stack...))
+	freeListener.After(ctx, module, freeDef, nil)
+
+	samples := p.snapshot()
+	if len(samples) != 1 {
+		t.Fatalf("wrong number of samples: want=1 got=%d", len(samples))
+	}
+	for _, sample := range samples {
+		want := [4]int64{3, 30, 2, 20}
+		if sample.value != want {
+			t.Errorf("sample values mismatch: want=%v got=%v", want, sample.value)
+		}
+	}
+}
+
+func TestMemoryProfilerHeapSnapshot(t *testing.T) {
+	if ProfilingFor(nil, AllowUnprepared(true)).MemoryProfiler().HeapSnapshot() != nil {
+		t.Error("heap snapshot returned without tracking the memory in use")
+	}
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).MemoryProfiler(InuseMemory(true))
+
+	malloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+	malloc.FunctionName = "malloc"
+
+	free := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, addr uint32) {})
+	free.FunctionName = "free"
+
+	module := wazerotest.NewModule(nil, malloc, free)
+	stack := []experimental.StackFrame{{Function: malloc}}
+	ctx := context.Background()
+
+	mallocDef := malloc.Definition()
+	mallocListener := p.NewFunctionListener(mallocDef)
+	for i, addr := range []uint64{48, 16, 32} {
+		mallocListener.Before(ctx, module, mallocDef, []uint64{uint64(10 * (i + 1))}, experimental.NewStackIterator(stack...))
+		mallocListener.After(ctx, module, mallocDef, []uint64{addr})
+	}
+
+	freeDef := free.Definition()
+	freeListener := p.NewFunctionListener(freeDef)
+	freeListener.Before(ctx, module, freeDef, []uint64{32}, experimental.NewStackIterator(stack...))
+	freeListener.After(ctx, module, freeDef, nil)
+
+	prof := p.HeapSnapshot()
+	if err := prof.CheckValid(); err != nil {
+		t.Fatal(err)
+	}
+	// The objects are sorted by address, the object at 32 was released.
+	want := []struct{ addr, size int64 }{{16, 20}, {48, 10}}
+	if len(prof.Sample) != len(want) {
+		t.Fatalf("wrong number of samples: want=%d got=%d", len(want), len(prof.Sample))
+	}
+	for i, s := range prof.Sample {
+		if addr := s.NumLabel["address"]; len(addr) != 1 || addr[0] != want[i].addr {
+			t.Errorf("wrong address of object %d: want=%d got=%v", i, want[i].addr, addr)
+		}
+		if s.Value[0] != 1 || s.Value[1] != want[i].size {
+			t.Errorf("wrong values of object %d: want=[1 %d] got=%v", i, want[i].size, s.Value)
+		}
+	}
+}
+
+func TestMemoryProfilerThreads(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).MemoryProfiler(InuseMemory(true))
+
+	malloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+	malloc.FunctionName = "malloc"
+
+	free := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, addr uint32) {})
+	free.FunctionName = "free"
+
+	realloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, addr, size uint32) uint32 {
+		return 0
+	})
+	realloc.FunctionName = "realloc"
+
+	// With wasi-threads, each thread is an instance of the module sharing
+	// the memory of the other threads.
+	thread1 := wazerotest.NewModule(nil, malloc, free, realloc)
+	thread2 := wazerotest.NewModule(nil)
+	stack := []experimental.StackFrame{{Function: malloc}}
+	ctx := context.Background()
+
+	mallocDef := malloc.Definition()
+	mallocListener := p.NewFunctionListener(mallocDef)
+	freeDef := free.Definition()
+	freeListener := p.NewFunctionListener(freeDef)
+	reallocDef := realloc.Definition()
+	reallocListener := p.NewFunctionListener(reallocDef)
+
+	mallocListener.Before(ctx, thread1, mallocDef, []uint64{10}, experimental.NewStackIterator(stack...))
+	mallocListener.After(ctx, thread1, mallocDef, []uint64{16})
+	mallocListener.Before(ctx, thread1, mallocDef, []uint64{30}, experimental.NewStackIterator(stack...))
+	mallocListener.After(ctx, thread1, mallocDef, []uint64{48})
+
+	// The memory released by the first thread is allocated by the second
+	// thread before the call to free returns on the first thread.
+	freeListener.Before(ctx, thread1, freeDef, []uint64{16}, experimental.NewStackIterator(stack...))
+	mallocListener.Before(ctx, thread2, mallocDef, []uint64{20}, experimental.NewStackIterator(stack...))
+	mallocListener.After(ctx, thread2, mallocDef, []uint64{16})
+	freeListener.After(ctx, thread1, freeDef, nil)
+
+	// The failed reallocation leaves the object of the first thread in use.
+	reallocListener.Before(ctx, thread1, reallocDef, []uint64{48, 100}, experimental.NewStackIterator(stack...))
+	reallocListener.After(ctx, thread1, reallocDef, []uint64{0})
+
+	prof := p.HeapSnapshot()
+	if err := prof.CheckValid(); err != nil {
+		t.Fatal(err)
+	}
+	want := []struct {
+		addr, size int64
+		thread     string
+	}{{16, 20, "2"}, {48, 30, "1"}}
+	if len(prof.Sample) != len(want) {
+		t.Fatalf("wrong number of samples: want=%d got=%d", len(want), len(prof.Sample))
+	}
+	for i, s := range prof.Sample {
+		if addr := s.NumLabel["address"]; len(addr) != 1 || addr[0] != want[i].addr {
+			t.Errorf("wrong address of object %d: want=%d got=%v", i, want[i].addr, addr)
+		}
+		if s.Value[1] != want[i].size {
+			t.Errorf("wrong size of object %d: want=%d got=%d", i, want[i].size, s.Value[1])
+		}
+		if thread := s.Label["thread"]; len(thread) != 1 || thread[0] != want[i].thread {
+			t.Errorf("wrong thread of object %d: want=%s got=%v", i, want[i].thread, thread)
+		}
+	}
+	if bytes, _ := p.inuseBytes(); bytes != 50 {
+		t.Errorf("wrong size of the memory in use: want=50 got=%d", bytes)
+	}
+}
+
+func TestMemoryProfilerAllocators(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true), Allocators(
+		MallocFunc("mi_heap_malloc", 1),
+		FreeFunc("mi_free", 0),
+		// The signature of this function does not match the allocator.
+		ReallocFunc("mi_expand", 0, 1),
+	)).MemoryProfiler(InuseMemory(true))
+
+	malloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, heap, size uint32) uint32 {
+		return 0
+	})
+	malloc.FunctionName = "mi_heap_malloc"
+
+	free := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, addr uint32) {})
+	free.FunctionName = "mi_free"
+
+	expand := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, addr uint32) uint32 {
+		return 0
+	})
+	expand.FunctionName = "mi_expand"
+
+	module := wazerotest.NewModule(nil, malloc, free, expand)
+	stack := []experimental.StackFrame{{Function: malloc}}
+	ctx := context.Background()
+
+	if p.NewFunctionListener(expand.Definition()) != nil {
+		t.Error("function with a mismatching signature was instrumented")
+	}
+
+	mallocDef := malloc.Definition()
+	mallocListener := p.NewFunctionListener(mallocDef)
+	for _, addr := range []uint64{16, 32} {
+		mallocListener.Before(ctx, module, mallocDef, []uint64{1, 10}, experimental.NewStackIterator(stack...))
+		mallocListener.After(ctx, module, mallocDef, []uint64{addr})
+	}
+
+	freeDef := free.Definition()
+	freeListener := p.NewFunctionListener(freeDef)
+	freeListener.Before(ctx, module, freeDef, []uint64{32}, experimental.NewStackIterator(stack...))
+	freeListener.After(ctx, module, freeDef, nil)
+
+	samples := p.snapshot()
+	if len(samples) != 1 {
+		t.Fatalf("wrong number of samples: want=1 got=%d", len(samples))
+	}
+	for _, sample := range samples {
+		want := [4]int64{2, 20, 1, 10}
+		if sample.value != want {
+			t.Errorf("sample values mismatch: want=%v got=%v", want, sample.value)
+		}
+	}
+}
+
+func TestMemoryProfilerRustAllocator(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).MemoryProfiler(InuseMemory(true))
+
+	newFunction := func(name string, fn any) *wazerotest.Function {
+		f := wazerotest.NewFunction(fn)
+		f.FunctionName = name
+		return f
+	}
+	rustAlloc := newFunction("__rust_alloc", func(ctx context.Context, mod api.Module, size, align uint32) uint32 {
+		return 0
+	})
+	rustRealloc := newFunction("__rust_realloc", func(ctx context.Context, mod api.Module, ptr, oldSize, align, newSize uint32) uint32 {
+		return 0
+	})
+	malloc := newFunction("malloc", func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+
+	module := wazerotest.NewModule(nil, rustAlloc, rustRealloc, malloc)
+	ctx := context.Background()
+	call := func(fn *wazerotest.Function, params []uint64, result uint64, inner func()) {
+		def := fn.Definition()
+		listener := p.NewFunctionListener(def)
+		stack := []experimental.StackFrame{{Function: fn}}
+		listener.Before(ctx, module, def, params, experimental.NewStackIterator(stack...))
+		if inner != nil {
+			inner()
+		}
+		listener.After(ctx, module, def, []uint64{result})
+	}
+
+	// A global allocator not backed by malloc.
+	call(rustAlloc, []uint64{10, 4}, 16, nil)
+	// The global allocator of the standard library calls malloc, only the
+	// call to malloc is recorded.
+	call(rustAlloc, []uint64{20, 4}, 32, func() {
+		call(malloc, []uint64{20}, 32, nil)
+	})
+	call(rustRealloc, []uint64{16, 10, 4, 40}, 64, nil)
+
+	var allocs, allocBytes, inuse, inuseBytes int64
+	for _, sample := range p.snapshot() {
+		allocs += sample.value[0]
+		allocBytes += sample.value[1]
+		inuse += sample.value[2]
+		inuseBytes += sample.value[3]
+	}
+	if allocs != 3 || allocBytes != 70 {
+		t.Errorf("wrong allocations: want=3/70 got=%d/%d", allocs, allocBytes)
+	}
+	if inuse != 2 || inuseBytes != 60 {
+		t.Errorf("wrong memory in use: want=2/60 got=%d/%d", inuse, inuseBytes)
+	}
+}
+
+func TestMemoryProfilerSampleRate(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).MemoryProfiler()
+
+	malloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+	malloc.FunctionName = "malloc"
+
+	module := wazerotest.NewModule(nil, malloc)
+	stack := []experimental.StackFrame{{Function: malloc}}
+	ctx := context.Background()
+
+	def := malloc.Definition()
+	listener := p.NewFunctionListener(def)
+	for _, size := range []uint64{10, 20} {
+		listener.Before(ctx, module, def, []uint64{size}, experimental.NewStackIterator(stack...))
+		listener.After(ctx, module, def, []uint64{0})
+	}
+
+	// The calls observed are one every 4 calls of the program.
+	prof := p.NewProfile(0.25)
+	if prof.Period != 4 {
+		t.Errorf("wrong period: want=4 got=%d", prof.Period)
+	}
+	if len(prof.Sample) != 1 {
+		t.Fatalf("wrong number of samples: want=1 got=%d", len(prof.Sample))
+	}
+	if v := prof.Sample[0].Value; v[0] != 8 || v[1] != 120 {
+		t.Errorf("wrong sample values: want=[8 120] got=%v", v)
+	}
+}
+
+func TestMemoryProfilerInuseSampleRate(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).MemoryProfiler(InuseMemory(true))
+
+	malloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+	malloc.FunctionName = "malloc"
+
+	free := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, addr uint32) {})
+	free.FunctionName = "free"
+
+	module := wazerotest.NewModule(nil, malloc, free)
+	stack := []experimental.StackFrame{{Function: malloc}}
+	ctx := context.Background()
+
+	// The objects in use are tracked even if the profiler is sampled, so the
+	// releases of the objects are not missed.
+	factory := Sample(0.25, p)
+	mallocDef, freeDef := malloc.Definition(), free.Definition()
+	mallocListener, freeListener := factory.NewFunctionListener(mallocDef), factory.NewFunctionListener(freeDef)
+	for addr := uint64(16); addr <= 128; addr += 16 {
+		mallocListener.Before(ctx, module, mallocDef, []uint64{10}, experimental.NewStackIterator(stack...))
+		mallocListener.After(ctx, module, mallocDef, []uint64{addr})
+	}
+	for addr := uint64(16); addr <= 128; addr += 16 {
+		freeListener.Before(ctx, module, freeDef, []uint64{addr}, experimental.NewStackIterator(stack...))
+		freeListener.After(ctx, module, freeDef, nil)
+	}
+
+	prof := p.NewProfile(0.25)
+	if prof.Period != 1 {
+		t.Errorf("wrong period: want=1 got=%d", prof.Period)
+	}
+	if len(prof.Sample) != 1 {
+		t.Fatalf("wrong number of samples: want=1 got=%d", len(prof.Sample))
+	}
+	if v := prof.Sample[0].Value; v[0] != 8 || v[1] != 80 || v[2] != 0 || v[3] != 0 {
+		t.Errorf("wrong sample values: want=[8 80 0 0] got=%v", v)
+	}
+}
+
+func TestMemoryProfilerStartStop(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).MemoryProfiler()
+
+	malloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+	malloc.FunctionName = "malloc"
+
+	module := wazerotest.NewModule(nil, malloc)
+	stack := []experimental.StackFrame{{Function: malloc}}
+	ctx := context.Background()
+
+	def := malloc.Definition()
+	listener := p.NewFunctionListener(def)
+	call := func(size uint64) {
+		listener.Before(ctx, module, def, []uint64{size}, experimental.NewStackIterator(stack...))
+		listener.After(ctx, module, def, []uint64{0})
+	}
+
+	if p.StopProfile(1) != nil {
+		t.Error("profile returned before being started")
+	}
+
+	call(10)
+	call(20)
+	for i, size := range []uint64{30, 40} {
+		if !p.StartProfile() {
+			t.Fatal("memory profile could not be started")
+		}
+		if p.StartProfile() {
+			t.Error("memory profile started twice")
+		}
+		call(size)
+
+		prof := p.StopProfile(1)
+		if len(prof.Sample) != 1 {
+			t.Fatalf("profile %d: wrong number of samples: want=1 got=%d", i, len(prof.Sample))
+		}
+		if v := prof.Sample[0].Value; v[0] != 1 || v[1] != int64(size) {
+			t.Errorf("profile %d: sample values mismatch: want=[1 %d] got=%v", i, size, v)
+		}
+	}
+
+	if v := p.NewProfile(1).Sample[0].Value; v[0] != 4 || v[1] != 100 {
+		t.Errorf("cumulative profile values mismatch: want=[4 100] got=%v", v)
+	}
+}
+
+func TestMemoryProfilerTinyGo(t *testing.T) {
+	const heapStartAddr = 8
+	const heapStart = 1024
+
+	p := ProfilingFor(nil, AllowUnprepared(true))
+	p.lang = tinygo
+	p.tinygoHeapStart = heapStartAddr
+	m := p.MemoryProfiler(InuseMemory(true))
+
+	alloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, size, layout, _ uint32) uint32 {
+		return 0
+	})
+	alloc.FunctionName = "runtime.alloc"
+
+	markFree := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, block, _ uint32) {})
+	markFree.FunctionName = "(runtime.gcBlock).markFree"
+
+	malloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+	malloc.FunctionName = "malloc"
+
+	if m.NewFunctionListener(malloc.Definition()) != nil {
+		t.Error("malloc is instrumented in TinyGo modules")
+	}
+
+	memory := wazerotest.NewMemory(wazerotest.PageSize)
+	memory.WriteUint32Le(heapStartAddr, heapStart)
+	module := wazerotest.NewModule(memory, alloc, markFree)
+	stack := []experimental.StackFrame{{Function: alloc}}
+	ctx := context.Background()
+
+	allocDef := alloc.Definition()
+	allocListener := m.NewFunctionListener(allocDef)
+	for _, block := range []uint64{0, 2, 4} {
+		allocListener.Before(ctx, module, allocDef, []uint64{20, 0, 0}, experimental.NewStackIterator(stack...))
+		allocListener.After(ctx, module, allocDef, []uint64{heapStart + block*tinygoBytesPerBlock})
+	}
+
+	// The garbage collector frees the object starting at block 2, including
+	// its tail block.
+	markFreeDef := markFree.Definition()
+	markFreeListener := m.NewFunctionListener(markFreeDef)
+	for _, block := range []uint64{2, 3} {
+		markFreeListener.Before(ctx, module, markFreeDef, []uint64{block, 0}, experimental.NewStackIterator(stack...))
+		markFreeListener.After(ctx, module, markFreeDef, nil)
+	}
+
+	samples := m.snapshot()
+	if len(samples) != 1 {
+		t.Fatalf("wrong number of samples: want=1 got=%d", len(samples))
+	}
+	for _, sample := range samples {
+		want := [4]int64{3, 60, 2, 40}
+		if sample.value != want {
+			t.Errorf("sample values mismatch: want=%v got=%v", want, sample.value)
+		}
+	}
+}
+
+func TestMemoryProfilerPython(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true))
+	p.lang = python3
+	m := p.MemoryProfiler(InuseMemory(true))
+
+	newFunction := func(name string, fn any) *wazerotest.Function {
+		f := wazerotest.NewFunction(fn)
+		f.FunctionName = name
+		return f
+	}
+	objectMalloc := newFunction("PyObject_Malloc", func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+	pyMalloc := newFunction("_PyObject_Malloc", func(ctx context.Context, mod api.Module, _, size uint32) uint32 {
+		return 0
+	})
+	pyFree := newFunction("_PyObject_Free", func(ctx context.Context, mod api.Module, _, ptr uint32) {})
+	malloc := newFunction("malloc", func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+
+	if m.NewFunctionListener(malloc.Definition()) != nil {
+		t.Error("malloc is instrumented in Python modules")
+	}
+
+	module := wazerotest.NewModule(nil, objectMalloc, pyMalloc, pyFree)
+	ctx := context.Background()
+	call := func(fn *wazerotest.Function, params, results []uint64, inner func()) {
+		def := fn.Definition()
+		listener := m.NewFunctionListener(def)
+		stack := []experimental.StackFrame{{Function: fn}}
+		listener.Before(ctx, module, def, params, experimental.NewStackIterator(stack...))
+		if inner != nil {
+			inner()
+		}
+		listener.After(ctx, module, def, results)
+	}
+
+	// The object allocated by pymalloc is only recorded once.
+	for _, addr := range []uint64{16, 48} {
+		call(objectMalloc, []uint64{20}, []uint64{addr}, func() {
+			call(pyMalloc, []uint64{1, 20}, []uint64{addr}, nil)
+		})
+	}
+	call(pyFree, []uint64{1, 16}, nil, nil)
+
+	var allocs, allocBytes, inuse, inuseBytes int64
+	for _, sample := range m.snapshot() {
+		allocs += sample.value[0]
+		allocBytes += sample.value[1]
+		inuse += sample.value[2]
+		inuseBytes += sample.value[3]
+	}
+	if allocs != 2 || allocBytes != 40 {
+		t.Errorf("wrong allocations: want=2/40 got=%d/%d", allocs, allocBytes)
+	}
+	if inuse != 1 || inuseBytes != 20 {
+		t.Errorf("wrong memory in use: want=1/20 got=%d/%d", inuse, inuseBytes)
+	}
+}
+
+func TestMemoryProfilerAllocationSizes(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).MemoryProfiler(AllocationSizes(true))
+
+	malloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+	malloc.FunctionName = "malloc"
+
+	module := wazerotest.NewModule(nil, malloc)
+	stack := []experimental.StackFrame{{Function: malloc}}
+	ctx := context.Background()
+
+	def := malloc.Definition()
+	listener := p.NewFunctionListener(def)
+	for _, size := range []uint64{10, 12, 100, 3000} {
+		listener.Before(ctx, module, def, []uint64{size}, experimental.NewStackIterator(stack...))
+		listener.After(ctx, module, def, []uint64{0})
+	}
+
+	want := map[int64][2]int64{
+		8:    {2, 22},
+		64:   {1, 100},
+		2048: {1, 3000},
+	}
+	prof := p.NewProfile(1)
+	if len(prof.Sample) != len(want) {
+		t.Fatalf("wrong number of samples: want=%d got=%d", len(want), len(prof.Sample))
+	}
+	for _, sample := range prof.Sample {
+		bytes := sample.NumLabel["bytes"]
+		if len(bytes) != 1 {
+			t.Fatalf("wrong bytes label: want=1 value got=%v", bytes)
+		}
+		w, ok := want[bytes[0]]
+		if !ok {
+			t.Errorf("unexpected size class: %d", bytes[0])
+			continue
+		}
+		if v := sample.Value; v[0] != w[0] || v[1] != w[1] {
+			t.Errorf("size class %d: sample values mismatch: want=%v got=%v", bytes[0], w, v)
+		}
+	}
+}
@@ -62,6 +62,17 @@ func deref[T any](r vmem, p ptr) T {
 	return *(*T)(unsafe.Pointer((unsafe.SliceData(b))))
 }
 
+// tryDeref is like deref, but reports whether the bytes at address p are in
+// range instead of panicking. The zero value of T is returned if they are not.
+func tryDeref[T any](r vmem, p ptr) (T, bool) {
+	var t T
+	b, ok := r.Read(p.addr(), uint32(unsafe.Sizeof(t)))
+	if !ok {
+		return t, false
+	}
+	return *(*T)(unsafe.Pointer(unsafe.SliceData(b))), true
+}
+
 // derefArrayInto copies into the given host slice contiguous elements
 // of type T starting at the virtual address p to fill it.
 func derefArray[T any](r vmem, p ptr, n uint32) []T {
@@ -0,0 +1,95 @@
+package wzprof
+
+import (
+	"bytes"
+	"fmt"
+	"net/http"
+	"sync/atomic"
+)
+
+// Number of lookups of the symbol caches of all the modules served from the
+// cache, or resolved by the symbolizers.
+var (
+	symbolCacheHits   atomic.Int64
+	symbolCacheMisses atomic.Int64
+)
+
+// shardedProfiler is implemented by the profilers recording their samples in
+// stackCounterShards, which count the samples that they observe.
+type shardedProfiler interface {
+	shards() *stackCounterShards
+}
+
+func (p *CPUProfiler) shards() *stackCounterShards   { return &p.counts }
+func (p *delayProfiler) shards() *stackCounterShards { return &p.counts }
+func (p *GrowProfiler) shards() *stackCounterShards  { return &p.counts }
+
+// MetricsHandler returns a http handler exposing metrics of the profilers in
+// the Prometheus text format, to monitor the cost of profiling in production:
+//   - wzprof_profiler_stacks: number of call stacks recorded by each profiler.
+//   - wzprof_profiler_samples_total: number of samples recorded by profilers.
+//   - wzprof_profiler_merged_stacks_total: number of call stacks merged into
+//     their callers to stay under the limit of MaxStacks.
+//   - wzprof_profiler_memory_bytes: estimate of the memory used by samples.
+//   - wzprof_symbol_cache_hits_total and wzprof_symbol_cache_misses_total:
+//     lookups of the cache of symbolized locations, for all the modules.
+//   - wzprof_sample_rate and wzprof_sampler_overhead_seconds_total: current
+//     sample rate of the sampler and time spent in the sampled listeners.
+//
+// The samples and memory metrics are only exposed for the profilers building
+// their profiles from the samples of call stacks (CPU, block, mutex, syscall,
+// garbage collection and memory growth). The sampler may be nil, it measures the time spent in
+// the listeners from the creation of the handler.
+func MetricsHandler(sampler *Sampler, profilers ...Profiler) http.Handler {
+	if sampler != nil {
+		sampler.metrics.Store(true)
+	}
+	return http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		b := new(bytes.Buffer)
+
+		writeMetricHeader(b, "wzprof_profiler_stacks", "gauge", "Number of call stacks recorded by the profiler.")
+		for _, p := range profilers {
+			fmt.Fprintf(b, "wzprof_profiler_stacks{profiler=%q} %d\n", p.Name(), p.Count())
+		}
+
+		var sharded []shardedProfiler
+		var names []string
+		for _, p := range profilers {
+			if s, ok := p.(shardedProfiler); ok {
+				sharded = append(sharded, s)
+				names = append(names, p.Name())
+			}
+		}
+		writeMetricHeader(b, "wzprof_profiler_samples_total", "counter", "Number of samples recorded by the profiler.")
+		for i, s := range sharded {
+			fmt.Fprintf(b, "wzprof_profiler_samples_total{profiler=%q} %d\n", names[i], s.shards().observed.Load())
+		}
+		writeMetricHeader(b, "wzprof_profiler_merged_stacks_total", "counter", "Number of call stacks merged into their callers to bound the memory of the profiler.")
+		for i, s := range sharded {
+			fmt.Fprintf(b, "wzprof_profiler_merged_stacks_total{profiler=%q} %d\n", names[i], s.shards().merged.Load())
+		}
+		writeMetricHeader(b, "wzprof_profiler_memory_bytes", "gauge", "Estimate of the memory used by the samples of the profiler.")
+		for i, s := range sharded {
+			fmt.Fprintf(b, "wzprof_profiler_memory_bytes{profiler=%q} %d\n", names[i], s.shards().size())
+		}
+
+		writeMetricHeader(b, "wzprof_symbol_cache_hits_total", "counter", "Number of symbolized locations served from the cache.")
+		fmt.Fprintf(b, "wzprof_symbol_cache_hits_total %d\n", symbolCacheHits.Load())
+		writeMetricHeader(b, "wzprof_symbol_cache_misses_total", "counter", "Number of locations resolved by the symbolizers.")
+		fmt.Fprintf(b, "wzprof_symbol_cache_misses_total %d\n", symbolCacheMisses.Load())
+
+		if sampler != nil {
+			writeMetricHeader(b, "wzprof_sample_rate", "gauge", "Current sample rate of the function listeners.")
+			fmt.Fprintf(b, "wzprof_sample_rate %g\n", sampler.SampleRate())
+			writeMetricHeader(b, "wzprof_sampler_overhead_seconds_total", "counter", "Time spent in the sampled function listeners.")
+			fmt.Fprintf(b, "wzprof_sampler_overhead_seconds_total %g\n", float64(sampler.total.Load())/1e9)
+		}
+
+		w.Header().Set("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+		_, _ = w.Write(b.Bytes())
+	})
+}
+
+func writeMetricHeader(b *bytes.Buffer, name, typ, help string) {
+	fmt.Fprintf(b, "# HELP %s %s\n# TYPE %s %s\n", name, help, name, typ)
+}
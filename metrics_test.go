@@ -0,0 +1,56 @@
+package wzprof
+
+import (
+	"context"
+	"net/http/httptest"
+	"strings"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestMetricsHandler(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true))
+	block := p.BlockProfiler()
+	calls := p.CallProfiler()
+
+	pollOneoff := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	pollOneoff.FunctionName = "poll_oneoff"
+	module := wazerotest.NewModule(nil, pollOneoff)
+	stack := []experimental.StackFrame{{Function: pollOneoff, PC: 1}}
+	def := pollOneoff.Definition()
+
+	listener := delayListener{&block.delayProfiler}
+	for i := 0; i < 3; i++ {
+		listener.Before(context.Background(), module, def, nil, experimental.NewStackIterator(stack...))
+		listener.After(context.Background(), module, def, nil)
+	}
+
+	sampler := NewSampler(0.5)
+	handler := MetricsHandler(sampler, block, calls)
+	if !sampler.measured() {
+		t.Error("the sampler does not measure the overhead of the listeners")
+	}
+
+	w := httptest.NewRecorder()
+	handler.ServeHTTP(w, httptest.NewRequest("GET", "/metrics", nil))
+	metrics := w.Body.String()
+
+	for _, line := range []string{
+		"# TYPE wzprof_profiler_samples_total counter\n",
+		`wzprof_profiler_stacks{profiler="block"} 1` + "\n",
+		`wzprof_profiler_stacks{profiler="calls"} 0` + "\n",
+		`wzprof_profiler_samples_total{profiler="block"} 3` + "\n",
+		`wzprof_profiler_merged_stacks_total{profiler="block"} 0` + "\n",
+		"wzprof_sample_rate 0.5\n",
+	} {
+		if !strings.Contains(metrics, line) {
+			t.Errorf("metric not found: %q\n%s", line, metrics)
+		}
+	}
+	if strings.Contains(metrics, `wzprof_profiler_samples_total{profiler="calls"}`) {
+		t.Errorf("samples of the call count profiler are not counted:\n%s", metrics)
+	}
+}
@@ -0,0 +1,90 @@
+package wzprof
+
+import (
+	"fmt"
+	"log"
+	"os"
+
+	"github.com/tetratelabs/wazero"
+	"github.com/tetratelabs/wazero/api"
+)
+
+// MapFile returns the content of the wasm file at path mapped in memory, for
+// large modules (e.g. of 100MB or more) which are profiled with their DWARF
+// sections. The pages of the file are only loaded when they are accessed and
+// can be reclaimed by the operating system, unlike a copy of the file read on
+// the heap. On platforms without support for memory-mapped files, or if the
+// file cannot be mapped, the content of the file is read instead.
+//
+// Prepare reads the custom sections from the binary of the module when the
+// compiled module has none, so the runtime compiling the module can be created
+// without wazero.RuntimeConfig.WithCustomSections, which otherwise keeps
+// another copy of the sections:
+//
+//	wasm, err := wzprof.MapFile("app.wasm")
+//	...
+//	p := wzprof.ProfilingFor(wasm)
+//	runtime := wazero.NewRuntimeWithConfig(ctx, wazero.NewRuntimeConfig())
+//	mod, err := runtime.CompileModule(ctx, wasm)
+//	...
+//	err = p.Prepare(mod)
+//
+// The slices of the DWARF sections are retained by the symbolizers prepared for
+// the module, which are cached for the lifetime of the program, so the file is
+// never unmapped. It must not be modified while the program runs.
+func MapFile(path string) ([]byte, error) {
+	f, err := os.Open(path)
+	if err != nil {
+		return nil, err
+	}
+	defer f.Close()
+
+	info, err := f.Stat()
+	if err != nil {
+		return nil, err
+	}
+	size := info.Size()
+	if size == 0 {
+		return []byte{}, nil
+	}
+	if int64(int(size)) != size {
+		return nil, fmt.Errorf("%s: file too large to be mapped in memory", path)
+	}
+	b, err := mapFile(f, int(size))
+	if err != nil {
+		log.Printf("reading %s instead of mapping it in memory: %s", path, err)
+		return os.ReadFile(path)
+	}
+	return b, nil
+}
+
+// withBinarySections returns mod, or a wrapper exposing the custom sections of
+// the binary wasm if mod was compiled by a runtime which does not keep them.
+func withBinarySections(mod wazero.CompiledModule, wasm []byte) wazero.CompiledModule {
+	if len(mod.CustomSections()) > 0 {
+		return mod
+	}
+	sections := wasmCustomSections(wasm)
+	if len(sections) == 0 {
+		return mod
+	}
+	return binarySections{mod, sections}
+}
+
+type binarySections struct {
+	wazero.CompiledModule
+	sections []api.CustomSection
+}
+
+func (m binarySections) CustomSections() []api.CustomSection { return m.sections }
+
+// customSection is a custom section of a wasm binary, the data is a slice of
+// the binary.
+type customSection struct {
+	name string
+	data []byte
+}
+
+func (s customSection) Name() string { return s.name }
+
+func (s customSection) Data() []byte { return s.data }
@@ -0,0 +1,48 @@
+package wzprof
+
+import (
+	"bytes"
+	"context"
+	"os"
+	"path/filepath"
+	"testing"
+
+	"github.com/tetratelabs/wazero"
+)
+
+func TestMapFile(t *testing.T) {
+	wasm := []byte("\x00asm\x01\x00\x00\x00\x00\x05\x04test\x00\x05\x02ab12")
+	path := filepath.Join(t.TempDir(), "test.wasm")
+	if err := os.WriteFile(path, wasm, 0o644); err != nil {
+		t.Fatal(err)
+	}
+	b, err := MapFile(path)
+	if err != nil {
+		t.Fatal(err)
+	}
+	if !bytes.Equal(b, wasm) {
+		t.Errorf("wrong content of the mapped file: %q", b)
+	}
+
+	ctx := context.Background()
+	runtime := wazero.NewRuntimeWithConfig(ctx, wazero.NewRuntimeConfigInterpreter())
+	defer runtime.Close(ctx)
+	mod, err := runtime.CompileModule(ctx, b)
+	if err != nil {
+		t.Fatal(err)
+	}
+	if n := len(mod.CustomSections()); n != 0 {
+		t.Fatalf("runtime kept %d custom sections", n)
+	}
+
+	// The custom sections are read from the binary of the module.
+	sections := withBinarySections(mod, b).CustomSections()
+	if len(sections) != 2 {
+		t.Fatalf("wrong number of custom sections: want=2 got=%d", len(sections))
+	}
+	for i, want := range []struct{ name, data string }{{"test", ""}, {"ab", "12"}} {
+		if s := sections[i]; s.Name() != want.name || string(s.Data()) != want.data {
+			t.Errorf("wrong custom section %d: want=%s:%q got=%s:%q", i, want.name, want.data, s.Name(), s.Data())
+		}
+	}
+}
@@ -0,0 +1,293 @@
+package wzprof
+
+import (
+	"bytes"
+	"fmt"
+
+	"github.com/tetratelabs/wazero"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+const (
+	monoExecMethodName = "mono_interp_exec_method"
+	monoLMFAddrName    = "mono_tls_lmf_addr"
+)
+
+const (
+	// Kinds of MonoLMFExt pushed by the interpreter when calling native code.
+	enumLMFExtInterpExit        = 2
+	enumLMFExtInterpExitWithCtx = 3
+	// Bit set in MonoLMF.previous_lmf when the LMF is a MonoLMFExt.
+	flagLMFExt = 2
+	// Bound on the length of the chains of frames and LMFs, which guards
+	// against looping on corrupted memory.
+	monoMaxFrames = 1024
+	// Bound on the length of the C strings read from memory.
+	monoMaxNameLength = 1024
+)
+
+// monoLayout holds the padding of the fields of the Mono runtime structs read
+// to walk the interpreter frames. Unlike CPython, the layout depends on the
+// configuration the runtime was built with, so the offsets are read from the
+// DWARF type information of the module.
+type monoLayout struct {
+	// InterpFrame.
+	padParentInFrame  ptr32
+	padIMethodInFrame ptr32
+	// InterpMethod.
+	padMethodInIMethod ptr32
+	// MonoMethod.
+	padNameInMethod  ptr32
+	padKlassInMethod ptr32
+	// MonoClass.
+	padNameInClass      ptr32
+	padNamespaceInClass ptr32
+	// MonoLMF.
+	padPreviousInLMF ptr32
+	// MonoLMFExt.
+	padKindInLMFExt     ptr32
+	padExitDataInLMFExt ptr32
+}
+
+// supportedMono returns the layout of the Mono runtime structs of the .NET
+// runtime embedded in the wasm module binary, or nil if the module does not
+// embed the Mono interpreter or was compiled without debug symbols.
+func supportedMono(wasmbin []byte) *monoLayout {
+	if !bytes.Contains(wasmCustomSection(wasmbin, "name"), []byte(monoExecMethodName)) {
+		return nil
+	}
+	p, err := newDwarfParserFromBin(wasmbin)
+	if err != nil {
+		return nil
+	}
+	layout, err := monoLayoutFromDwarf(p)
+	if err != nil {
+		return nil
+	}
+	return layout
+}
+
+func monoLayoutFromDwarf(p dwarfparser) (*monoLayout, error) {
+	// MonoLMFExt is the typedef of an anonymous struct.
+	s := p.Structs("InterpFrame", "InterpMethod", "_MonoMethod", "_MonoClass", "MonoLMF", "MonoLMFExt")
+	layout := &monoLayout{
+		padParentInFrame:    s.offset("InterpFrame", "parent"),
+		padIMethodInFrame:   s.offset("InterpFrame", "imethod"),
+		padMethodInIMethod:  s.offset("InterpMethod", "method"),
+		padNameInMethod:     s.offset("_MonoMethod", "name"),
+		padKlassInMethod:    s.offset("_MonoMethod", "klass"),
+		padNameInClass:      s.offset("_MonoClass", "name"),
+		padNamespaceInClass: s.offset("_MonoClass", "name_space"),
+		padPreviousInLMF:    s.offset("MonoLMF", "previous_lmf"),
+		padKindInLMFExt:     s.offset("MonoLMFExt", "kind"),
+		padExitDataInLMFExt: s.offset("MonoLMFExt", "interp_exit_data"),
+	}
+	return layout, s.err
+}
+
+func prepareMono(mod wazero.CompiledModule, layout *monoLayout, symbols symbolizer) (*mono, error) {
+	p, err := newDwarfparser(mod)
+	if err != nil {
+		return nil, fmt.Errorf("could not build dwarf parser: %w", err)
+	}
+	lmfAddr := dwarfVariableAddress(p, monoLMFAddrName)
+	if lmfAddr == 0 {
+		return nil, fmt.Errorf("could not find mono lmf address")
+	}
+	return &mono{
+		layout:  layout,
+		lmfaddr: ptr32(lmfAddr),
+		symbols: symbols,
+	}, nil
+}
+
+// mono walks the frames of the Mono interpreter used by the .NET runtime to
+// execute managed code.
+//
+// The interpreter does not recurse when managed methods call each other, all
+// the managed frames of a call to mono_interp_exec_method are linked from the
+// innermost frame. The innermost frame is only recorded in memory when the
+// interpreter calls native code, in the LMF (Last Managed Frame) pushed by the
+// interpreter to transition out of managed code. Each call to
+// mono_interp_exec_method on the wasm stack is replaced by the managed frames
+// of the matching LMF, other wasm frames are symbolized from the DWARF
+// information of the native code of the runtime.
+type mono struct {
+	layout  *monoLayout
+	lmfaddr ptr32 // address of the pointer to the current MonoLMF*
+	symbols symbolizer
+}
+
+func (m *mono) Locations(fn experimental.InternalFunction, pc experimental.ProgramCounter) (uint64, []location) {
+	call, ok := fn.(monocall)
+	if !ok {
+		return m.symbols.Locations(fn, pc)
+	}
+	loc := location{
+		HumanName:  call.name,
+		StableName: call.name,
+	}
+	return uint64(call.addr), []location{loc}
+}
+
+func (m *mono) Stackiter(mod api.Module, def api.FunctionDefinition, wasmsi experimental.StackIterator) experimental.StackIterator {
+	mem := mod.Memory()
+	exits := m.interpExits(mem)
+
+	var wasmFrames []stackFrame
+	activations := 0
+	for wasmsi.Next() {
+		fn := wasmsi.Function()
+		if fn.Definition().Name() == monoExecMethodName {
+			activations++
+		}
+		wasmFrames = append(wasmFrames, stackFrame{fn: fn, pc: wasmsi.ProgramCounter()})
+	}
+
+	// The innermost call to mono_interp_exec_method may not have pushed an
+	// LMF if it is not calling native code through the interpreter
+	// transitions (e.g. internal helpers of the runtime), so the LMFs are
+	// matched with the outermost calls.
+	unmatched := activations - len(exits)
+	frames := make([]stackFrame, 0, len(wasmFrames))
+	i := 0
+	for _, f := range wasmFrames {
+		if f.fn.Definition().Name() != monoExecMethodName {
+			frames = append(frames, f)
+			continue
+		}
+		j := i - unmatched
+		i++
+		if j < 0 || j >= len(exits) {
+			frames = append(frames, f)
+			continue
+		}
+		frames = m.appendFrames(frames, mem, exits[j])
+	}
+
+	return &stackFrameIterator{frames: frames}
+}
+
+// interpExits returns the innermost interpreter frames recorded in the LMFs
+// of the current thread, from the most recent.
+func (m *mono) interpExits(mem vmem) []ptr32 {
+	l := m.layout
+	lmfaddr := deref[ptr32](mem, m.lmfaddr)
+	if lmfaddr == 0 {
+		return nil
+	}
+
+	var exits []ptr32
+	lmf := deref[ptr32](mem, lmfaddr)
+	for n := 0; lmf != 0 && n < monoMaxFrames; n++ {
+		previous := deref[ptr32](mem, lmf+l.padPreviousInLMF)
+		if previous&flagLMFExt != 0 {
+			switch deref[int32](mem, lmf+l.padKindInLMFExt) {
+			case enumLMFExtInterpExit, enumLMFExtInterpExitWithCtx:
+				if framep := deref[ptr32](mem, lmf+l.padExitDataInLMFExt); framep != 0 {
+					exits = append(exits, framep)
+				}
+			}
+		}
+		lmf = previous &^ 3
+	}
+	return exits
+}
+
+// appendFrames appends the managed frames linked from the interpreter frame
+// at framep to frames.
+func (m *mono) appendFrames(frames []stackFrame, mem vmem, framep ptr32) []stackFrame {
+	l := m.layout
+	for n := 0; framep != 0 && n < monoMaxFrames; n++ {
+		if imethod := deref[ptr32](mem, framep+l.padIMethodInFrame); imethod != 0 {
+			frames = append(frames, stackFrame{
+				fn: monocall{name: m.methodName(mem, imethod), addr: uint32(imethod)},
+				pc: experimental.ProgramCounter(imethod),
+			})
+		}
+		framep = deref[ptr32](mem, framep+l.padParentInFrame)
+	}
+	return frames
+}
+
+// methodName returns the fully qualified name of the managed method of an
+// InterpMethod (e.g. System.Console.WriteLine).
+func (m *mono) methodName(mem vmem, imethod ptr32) string {
+	l := m.layout
+	method := deref[ptr32](mem, imethod+l.padMethodInIMethod)
+	if method == 0 {
+		return "<unknown>"
+	}
+	name := derefCString(mem, deref[ptr32](mem, method+l.padNameInMethod))
+	klass := deref[ptr32](mem, method+l.padKlassInMethod)
+	if klass == 0 {
+		return name
+	}
+	class := derefCString(mem, deref[ptr32](mem, klass+l.padNameInClass))
+	if namespace := derefCString(mem, deref[ptr32](mem, klass+l.padNamespaceInClass)); namespace != "" {
+		class = namespace + "." + class
+	}
+	return class + "." + name
+}
+
+// derefCString returns a copy of the null-terminated string at address p.
+func derefCString(m vmem, p ptr32) string {
+	if p == 0 {
+		return ""
+	}
+	var b []byte
+	for len(b) < monoMaxNameLength {
+		c, ok := m.Read(p.addr()+uint32(len(b)), 1)
+		if !ok || c[0] == 0 {
+			break
+		}
+		b = append(b, c[0])
+	}
+	return string(b)
+}
+
+// monocall represents a call to a managed method executed by the Mono
+// interpreter.
+type monocall struct {
+	name string
+	addr uint32 // address of the InterpMethod
+
+	api.FunctionDefinition // required for WazeroOnly
+}
+
+func (f monocall) Definition() api.FunctionDefinition {
+	return f
+}
+
+func (f monocall) SourceOffsetForPC(pc experimental.ProgramCounter) uint64 {
+	return 0
+}
+
+func (f monocall) ModuleName() string {
+	return "<mono>"
+}
+
+func (f monocall) Index() uint32 {
+	return 0
+}
+
+func (f monocall) Import() (string, string, bool) {
+	return "", "", false
+}
+
+func (f monocall) ExportNames() []string {
+	return nil
+}
+
+func (f monocall) Name() string {
+	return f.name
+}
+
+func (f monocall) DebugName() string {
+	return f.name
+}
+
+func (f monocall) GoFunction() interface{} {
+	return nil
+}
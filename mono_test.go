@@ -0,0 +1,90 @@
+package wzprof
+
+import (
+	"context"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestMonoStackIterator(t *testing.T) {
+	m := &mono{
+		layout: &monoLayout{
+			padParentInFrame:    0,
+			padIMethodInFrame:   4,
+			padMethodInIMethod:  0,
+			padNameInMethod:     16,
+			padKlassInMethod:    8,
+			padNameInClass:      40,
+			padNamespaceInClass: 44,
+			padPreviousInLMF:    0,
+			padKindInLMFExt:     12,
+			padExitDataInLMFExt: 20,
+		},
+		lmfaddr: 8,
+	}
+
+	memory := wazerotest.NewMemory(wazerotest.PageSize)
+	for _, w := range []struct{ addr, value uint32 }{
+		// mono_tls_lmf_addr => &lmf => MonoLMFExt at 32
+		{8, 16},
+		{16, 32},
+		{32, flagLMFExt},
+		{44, enumLMFExtInterpExit},
+		{52, 100},
+		// InterpFrame at 100 (innermost) and 120
+		{100, 120},
+		{104, 200},
+		{124, 220},
+		// InterpMethod at 200 and 220
+		{200, 300},
+		{220, 340},
+		// MonoMethod at 300 and 340
+		{308, 400},
+		{316, 500},
+		{348, 400},
+		{356, 520},
+		// MonoClass at 400
+		{440, 540},
+		{444, 560},
+	} {
+		memory.WriteUint32Le(w.addr, w.value)
+	}
+	memory.WriteString(500, "Inner")
+	memory.WriteString(520, "Outer")
+	memory.WriteString(540, "Program")
+	memory.WriteString(560, "App")
+
+	newFunction := func(name string) *wazerotest.Function {
+		f := wazerotest.NewFunction(func(ctx context.Context, mod api.Module) {})
+		f.FunctionName = name
+		return f
+	}
+	malloc := newFunction("malloc")
+	exec := newFunction(monoExecMethodName)
+	main := newFunction("main")
+	module := wazerotest.NewModule(memory, malloc, exec, main)
+
+	si := m.Stackiter(module, malloc.Definition(), experimental.NewStackIterator(
+		experimental.StackFrame{Function: malloc},
+		experimental.StackFrame{Function: exec},
+		experimental.StackFrame{Function: main},
+	))
+
+	var got []string
+	for si.Next() {
+		got = append(got, si.Function().Definition().Name())
+	}
+
+	want := []string{"malloc", "App.Program.Inner", "App.Program.Outer", "main"}
+	if len(got) != len(want) {
+		t.Fatalf("wrong number of frames: want=%q got=%q", want, got)
+	}
+	for i := range want {
+		if got[i] != want[i] {
+			t.Errorf("wrong frame at index %d: want=%q got=%q", i, want[i], got[i])
+		}
+	}
+}
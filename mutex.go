@@ -0,0 +1,63 @@
+package wzprof
+
+import (
+	"strings"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+// MutexProfiler is the implementation of a profiler recording the time spent
+// by threads of a WebAssembly module waiting on futex-based locks.
+//
+// Function listeners cannot observe the execution of memory.atomic.wait
+// instructions, so the profiler measures calls to the functions of the
+// standard libraries which wrap them:
+// - "__wait", "__timedwait" and "__timedwait_cp" in wasi-libc.
+// - functions named "futex_wait" in the Rust standard library.
+//
+// The profiler generates samples of two types:
+// - "contentions" counts the number of times threads waited.
+// - "delay" records the time spent waiting (in nanoseconds).
+//
+// Similarly to the mutex profiles of Go programs, the values are cumulative
+// since the creation of the profiler.
+type MutexProfiler struct {
+	delayProfiler
+}
+
+func newMutexProfiler(p *Profiling) *MutexProfiler {
+	m := &MutexProfiler{}
+	m.init(p, "mutex")
+	return m
+}
+
+// Name returns "mutex" to match the name of the mutex profiler in pprof.
+func (p *MutexProfiler) Name() string {
+	return "mutex"
+}
+
+// Desc returns a description copied from net/http/pprof.
+func (p *MutexProfiler) Desc() string {
+	return profileDescriptions[p.Name()]
+}
+
+// NewFunctionListener returns a function listener recording the time spent in
+// calls to the function passed as argument if it waits on a futex, and nil
+// otherwise.
+func (p *MutexProfiler) NewFunctionListener(def api.FunctionDefinition) experimental.FunctionListener {
+	if !isFutexWait(def.Name()) {
+		return nil
+	}
+	return profilingListener{p.p, delayListener{&p.delayProfiler}}
+}
+
+func isFutexWait(name string) bool {
+	switch name {
+	case "__wait", "__timedwait", "__timedwait_cp":
+		return true
+	}
+	// Symbols of the Rust standard library are mangled, for example
+	// _ZN3std3sys4wasi5futex10futex_wait17h5e7a0cd3b8fbc0a4E.
+	return strings.Contains(name, "futex_wait")
+}
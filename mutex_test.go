@@ -0,0 +1,39 @@
+package wzprof
+
+import (
+	"context"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestMutexProfilerFunctions(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).MutexProfiler()
+
+	hasListener := func(name string) bool {
+		fn := wazerotest.NewFunction(func(context.Context, api.Module) {})
+		fn.FunctionName = name
+		wazerotest.NewModule(nil, fn)
+		return p.NewFunctionListener(fn.Definition()) != nil
+	}
+
+	for _, name := range []string{
+		"__wait",
+		"__timedwait",
+		"_ZN3std3sys4wasi5futex10futex_wait17h5e7a0cd3b8fbc0a4E",
+	} {
+		if !hasListener(name) {
+			t.Errorf("%s: missing function listener", name)
+		}
+	}
+
+	for _, name := range []string{
+		"malloc",
+		"pthread_mutex_lock",
+	} {
+		if hasListener(name) {
+			t.Errorf("%s: unexpected function listener", name)
+		}
+	}
+}
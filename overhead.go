@@ -0,0 +1,72 @@
+package wzprof
+
+import (
+	"fmt"
+	"sync/atomic"
+	"time"
+
+	"github.com/google/pprof/profile"
+)
+
+// overheadFrameName is the name of the frame of the samples holding the time
+// spent in the function listeners of the profilers (see SelfProfile).
+const overheadFrameName = "wzprof"
+
+// profilingOverhead is the time spent by the profilers of a module, in
+// nanoseconds, which is measured when SelfProfile is enabled.
+type profilingOverhead struct {
+	listeners atomic.Int64 // time spent in the function listeners
+	symbols   atomic.Int64 // time spent symbolizing the call stacks
+}
+
+func (o *profilingOverhead) String() string {
+	return fmt.Sprintf("wzprof overhead: %s in listeners, %s symbolizing",
+		time.Duration(o.listeners.Load()), time.Duration(o.symbols.Load()))
+}
+
+// overheadStart returns the time at which an operation of the profilers
+// starts, or zero if the overhead is not measured.
+func (p *Profiling) overheadStart() int64 {
+	if !p.selfProfile {
+		return 0
+	}
+	return nanotime()
+}
+
+// overheadEnd adds to counter the time spent since start, which is the value
+// returned by overheadStart.
+func (p *Profiling) overheadEnd(counter *atomic.Int64, start int64) {
+	if p.selfProfile {
+		counter.Add(nanotime() - start)
+	}
+}
+
+// addOverheadSample adds to the CPU profile prof a sample of a wzprof frame
+// holding the time spent in the function listeners since it was base, in the
+// last sample type of the profile (the time spent in calls).
+func (p *Profiling) addOverheadSample(prof *profile.Profile, base int64) {
+	overhead := p.overhead.listeners.Load() - base
+	if !p.selfProfile || p.deterministic || overhead <= 0 {
+		return
+	}
+	fn := &profile.Function{
+		ID:         uint64(len(prof.Function)) + 1,
+		Name:       overheadFrameName,
+		SystemName: overheadFrameName,
+	}
+	loc := &profile.Location{
+		ID:   uint64(len(prof.Location)) + 1,
+		Line: []profile.Line{{Function: fn}},
+	}
+	if len(prof.Mapping) > 0 {
+		loc.Mapping = prof.Mapping[0]
+	}
+	value := make([]int64, len(prof.SampleType))
+	value[len(value)-1] = overhead
+	prof.Function = append(prof.Function, fn)
+	prof.Location = append(prof.Location, loc)
+	prof.Sample = append(prof.Sample, &profile.Sample{
+		Location: []*profile.Location{loc},
+		Value:    value,
+	})
+}
@@ -0,0 +1,67 @@
+package wzprof
+
+import (
+	"context"
+	"strings"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestSelfProfile(t *testing.T) {
+	currentTime := int64(0)
+	p := ProfilingFor(nil, AllowUnprepared(true), SelfProfile(true))
+	// The functions created by wazerotest are host functions.
+	cpu := p.CPUProfiler(HostTime(true), TimeFunc(func() int64 { return currentTime }))
+
+	f := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f.FunctionName = "f"
+	module := wazerotest.NewModule(nil, f)
+	def := f.Definition()
+	ctx := context.Background()
+
+	cpu.StartProfile()
+	lstn := cpu.NewFunctionListener(def)
+	for i := 0; i < 10; i++ {
+		lstn.Before(ctx, module, def, nil, experimental.NewStackIterator(experimental.StackFrame{Function: f}))
+		currentTime += 100
+		lstn.After(ctx, module, def, nil)
+	}
+	prof := cpu.StopProfile(1)
+	if err := prof.CheckValid(); err != nil {
+		t.Fatal(err)
+	}
+
+	values := make(map[string]int64)
+	for _, s := range prof.Sample {
+		values[s.Location[0].Line[0].Function.Name] += s.Value[1]
+	}
+	if values["f"] != 1000 {
+		t.Errorf("wrong time of the guest function: want=1000 got=%d", values["f"])
+	}
+	if values[overheadFrameName] <= 0 {
+		t.Errorf("no time spent in the listeners: %v", values)
+	}
+
+	found := false
+	for _, comment := range prof.Comments {
+		found = found || strings.HasPrefix(comment, "wzprof overhead: ")
+	}
+	if !found {
+		t.Errorf("overhead missing from the comments: %q", prof.Comments)
+	}
+
+	// The overhead is only measured when requested.
+	cpu = ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler()
+	cpu.StartProfile()
+	lstn = cpu.NewFunctionListener(def)
+	lstn.Before(ctx, module, def, nil, experimental.NewStackIterator(experimental.StackFrame{Function: f}))
+	lstn.After(ctx, module, def, nil)
+	for _, s := range cpu.StopProfile(1).Sample {
+		if name := s.Location[0].Line[0].Function.Name; name == overheadFrameName {
+			t.Error("overhead recorded without SelfProfile")
+		}
+	}
+}
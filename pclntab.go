@@ -638,11 +638,12 @@ func (f goFunction) Index() uint32 {
 }
 
 func (f goFunction) Import() (string, string, bool) {
-	panic("implement me")
+	// Go functions are always defined by the module.
+	return "", "", false
 }
 
 func (f goFunction) ExportNames() []string {
-	panic("implement me")
+	return nil
 }
 
 func (f goFunction) Name() string {
@@ -650,7 +651,7 @@ func (f goFunction) Name() string {
 }
 
 func (f goFunction) DebugName() string {
-	panic("implement me")
+	return f.ModuleName() + "." + f.Name()
 }
 
 func (f goFunction) GoFunction() interface{} {
@@ -2,6 +2,8 @@ package wzprof
 
 import (
 	"bytes"
+	"compress/gzip"
+	"encoding/json"
 	"fmt"
 	"html"
 	"io"
@@ -10,7 +12,9 @@ import (
 	"net/url"
 	"runtime/pprof"
 	"sort"
+	"strconv"
 	"strings"
+	"time"
 
 	"github.com/google/pprof/profile"
 )
@@ -43,12 +47,134 @@ import (
 // (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
 // OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-func serveProfile(w http.ResponseWriter, prof *profile.Profile) {
+// serveProfile writes prof to w. The "compression" query parameter of the
+// request selects the gzip compression level of the profile, between 1 (the
+// fastest) and 9 (the smallest), or 0 to serve the profile uncompressed.
+func serveProfile(w http.ResponseWriter, r *http.Request, prof *profile.Profile) {
+	serveProfileWith(w, r, func(w io.Writer, options ...WriteOption) error {
+		return EncodeProfile(w, prof, options...)
+	})
+}
+
+// serveProfileWith is like serveProfile but the profile is written by the
+// write function, for example to stream it.
+func serveProfileWith(w http.ResponseWriter, r *http.Request, write func(io.Writer, ...WriteOption) error) {
+	var options []WriteOption
+	if compression := r.FormValue("compression"); compression != "" {
+		level, err := strconv.Atoi(compression)
+		if err != nil || level < 0 || level > gzip.BestCompression {
+			serveError(w, http.StatusBadRequest, `invalid value for "compression" - must be an integer between 0 and 9`)
+			return
+		}
+		if level == 0 {
+			options = append(options, Uncompressed(true))
+		} else {
+			options = append(options, CompressionLevel(level))
+		}
+	}
 	h := w.Header()
 	h.Set("X-Content-Type-Options", "nosniff")
 	h.Set("Content-Type", "application/octet-stream")
 	h.Set("Content-Disposition", `attachment; filename="profile"`)
-	if err := prof.Write(w); err != nil {
+	if err := write(w, options...); err != nil {
+		serveError(w, http.StatusInternalServerError, err.Error())
+	}
+}
+
+// deltaProfile returns a profile representing the difference between two
+// snapshots of the same profiler, p0 being taken before p1. The time range of
+// the returned profile is the period between the two snapshots.
+//
+// The values of p0 are modified by the function.
+func deltaProfile(p0, p1 *profile.Profile) (*profile.Profile, error) {
+	start := p0.TimeNanos + p0.DurationNanos
+	end := p1.TimeNanos + p1.DurationNanos
+	p0.Scale(-1)
+	delta, err := profile.Merge([]*profile.Profile{p0, p1})
+	if err != nil {
+		return nil, err
+	}
+	delta.TimeNanos = start
+	delta.DurationNanos = end - start
+	return delta, nil
+}
+
+// serveCumulativeProfile serves profiles of profilers recording values since
+// they were created. When the request has a "seconds" query parameter, the
+// function responds with a delta profile of the values recorded during that
+// period instead. The snapshots returned by newProfile are not passed to the
+// hooks of p, only the profile served under the given name is.
+func serveCumulativeProfile(w http.ResponseWriter, r *http.Request, p *Profiling, name string, newProfile func() *profile.Profile) {
+	seconds := r.FormValue("seconds")
+	if seconds == "" {
+		serveProfile(w, r, p.endProfile(name, newProfile()))
+		return
+	}
+
+	n, err := strconv.ParseInt(seconds, 10, 64)
+	if err != nil || n <= 0 {
+		serveError(w, http.StatusBadRequest, `invalid value for "seconds" - must be a positive integer`)
+		return
+	}
+	duration := time.Duration(n) * time.Second
+
+	ctx := r.Context()
+	deadline, ok := ctx.Deadline()
+	if ok {
+		if timeout := time.Until(deadline); duration > timeout {
+			serveError(w, http.StatusBadRequest, "profile duration exceeds server's WriteTimeout")
+			return
+		}
+	}
+
+	p.startProfile(name)
+	p0 := newProfile()
+
+	timer := time.NewTimer(duration)
+	defer timer.Stop()
+	select {
+	case <-timer.C:
+	case <-ctx.Done():
+		serveError(w, http.StatusInternalServerError, "profile collection interrupted: "+ctx.Err().Error())
+		return
+	}
+
+	p1 := newProfile()
+
+	delta, err := deltaProfile(p0, p1)
+	if err != nil {
+		serveError(w, http.StatusInternalServerError, "failed to compute delta: "+err.Error())
+		return
+	}
+	serveProfile(w, r, p.endProfile(name, delta))
+}
+
+// samplerConfig is the JSON representation of the configuration of samplers
+// served by their handler.
+type samplerConfig struct {
+	SampleRate float64 `json:"sample_rate"`
+}
+
+func serveConfig(w http.ResponseWriter, r *http.Request, sampler *Sampler) {
+	switch r.Method {
+	case http.MethodGet, http.MethodHead:
+	case http.MethodPost:
+		rate, err := strconv.ParseFloat(r.FormValue("sample_rate"), 64)
+		if err != nil {
+			serveError(w, http.StatusBadRequest, "invalid sample_rate parameter: "+err.Error())
+			return
+		}
+		if err := sampler.SetSampleRate(rate); err != nil {
+			serveError(w, http.StatusBadRequest, err.Error())
+			return
+		}
+	default:
+		w.Header().Set("Allow", "GET, HEAD, POST")
+		serveError(w, http.StatusMethodNotAllowed, "method not allowed")
+		return
+	}
+	w.Header().Set("Content-Type", "application/json")
+	if err := json.NewEncoder(w).Encode(samplerConfig{SampleRate: sampler.SampleRate()}); err != nil {
 		serveError(w, http.StatusInternalServerError, err.Error())
 	}
 }
@@ -69,9 +195,28 @@ type profileEntry struct {
 	Desc    string
 	Debug   int
 	Count   int
+	Module  string
+	Seconds bool // whether the profile accepts the "seconds" query parameter
 	Handler http.Handler
 }
 
+// secondsPresets are the durations of the delta profiles linked from the index
+// page for each guest profile.
+var secondsPresets = []int{5, 15, 30, 60}
+
+// guestProfileLink returns the link to the guest profile at href, selecting the
+// module and adding the query, if not empty.
+func guestProfileLink(href, module, query string) *url.URL {
+	var params []string
+	if module != "" {
+		params = append(params, "module="+url.QueryEscape(module))
+	}
+	if query != "" {
+		params = append(params, query)
+	}
+	return &url.URL{Path: href, RawQuery: strings.Join(params, "&")}
+}
+
 func sortProfiles(entries []profileEntry) {
 	sort.Slice(entries, func(i, j int) bool {
 		return entries[i].Name < entries[j].Name
@@ -84,18 +229,91 @@ func sortProfiles(entries []profileEntry) {
 //
 // Handler responds to a request for "/debug/pprof/" with an HTML page listing
 // the available profiles.
+//
+// The handler does not depend on the path it is mounted at, which means that it
+// may be registered under other prefixes than "/debug/pprof/", for example
+// "/modules/foo/pprof/", or wrapped with http.StripPrefix.
+//
+// Like net/http/pprof, the handler serves the command line of the guest (see
+// CommandLine) at "/debug/pprof/cmdline", and resolves the names of functions
+// at the addresses of the profiles at "/debug/pprof/symbol", so go tool pprof
+// can fetch and symbolize the profiles.
 func Handler(sampleRate float64, profilers ...Profiler) http.Handler {
+	return newHandler(constSampleRate(sampleRate), nil, []moduleProfilers{{profilers: profilers}})
+}
+
+// Handler is like the Handler function of the package, but the profiles are
+// scaled by the current sample rate of s, and the handler also serves a
+// "config" endpoint allowing operators to change the sample rate without
+// restarting the program. A GET request responds with the current rate, a POST
+// request sets the rate to the value of its "sample_rate" form parameter, for
+// example:
+//
+//	curl -d sample_rate=1 http://localhost:8080/debug/pprof/config
+//
+// The response is a JSON object holding the sample rate: {"sample_rate":1}.
+func (s *Sampler) Handler(profilers ...Profiler) http.Handler {
+	return newHandler(s.SampleRate, s, []moduleProfilers{{profilers: profilers}})
+}
+
+func constSampleRate(sampleRate float64) func() float64 {
+	return func() float64 { return sampleRate }
+}
+
+// MultiHandler is like Handler but serves the profiles of multiple wasm modules
+// behind a single endpoint. The map associates module names with the profilers
+// recording the execution of each module.
+//
+// The module that a profile is fetched from is selected with the "module"
+// query parameter. For example, "/debug/pprof/profile?module=foo&seconds=5"
+// captures a CPU profile of the module named "foo". The parameter may be
+// omitted when only one module is registered.
+//
+// The HTML index page lists the profiles available for each module.
+func MultiHandler(sampleRate float64, modules map[string][]Profiler) http.Handler {
+	mods := make([]moduleProfilers, 0, len(modules))
+	for name, profilers := range modules {
+		mods = append(mods, moduleProfilers{name: name, profilers: profilers})
+	}
+	sort.Slice(mods, func(i, j int) bool {
+		return mods[i].name < mods[j].name
+	})
+	return newHandler(constSampleRate(sampleRate), nil, mods)
+}
+
+type moduleProfilers struct {
+	name      string
+	profilers []Profiler
+}
+
+func newHandler(sampleRate func() float64, sampler *Sampler, modules []moduleProfilers) http.Handler {
 	return http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
-		var guest, host []profileEntry
+		if sampler != nil && r.URL.Path[strings.LastIndexByte(r.URL.Path, '/')+1:] == "config" {
+			serveConfig(w, r, sampler)
+			return
+		}
 
-		for _, p := range profilers {
-			guest = append(guest, profileEntry{
-				Name:    p.Name(),
-				Href:    p.Name(),
-				Desc:    p.Desc(),
-				Count:   p.Count(),
-				Handler: p.NewHandler(sampleRate),
-			})
+		var host []profileEntry
+		guest := make([][]profileEntry, len(modules))
+		rate := sampleRate()
+
+		for i, m := range modules {
+			for _, p := range m.profilers {
+				// Maximums of the stack profiler cannot be subtracted,
+				// and the profiles of a ProfileStore are not recorded
+				// anymore, the other profilers serve delta profiles.
+				_, maximums := p.(*StackProfiler)
+				_, stored := p.(storedProfiler)
+				guest[i] = append(guest[i], profileEntry{
+					Name:    p.Name(),
+					Href:    p.Name(),
+					Desc:    p.Desc(),
+					Count:   p.Count(),
+					Module:  m.name,
+					Seconds: !maximums && !stored,
+					Handler: p.NewHandler(rate),
+				})
+			}
 		}
 
 		// Add host profiling debug entries.
@@ -124,6 +342,9 @@ func Handler(sampleRate float64, profilers ...Profiler) http.Handler {
 		})
 
 		for _, p := range pprof.Profiles() {
+			if _, ok := registeredProfiles.Load(p.Name()); ok {
+				continue
+			}
 			host = append(host, profileEntry{
 				Name:    p.Name(),
 				Href:    p.Name(),
@@ -143,13 +364,29 @@ func Handler(sampleRate float64, profilers ...Profiler) http.Handler {
 			Debug:   2,
 		})
 
-		if href, found := strings.CutPrefix(r.URL.Path, "/debug/pprof/"); found {
+		// Only the last element of the path is used to select the profile so
+		// the handler can be mounted under any prefix, or behind a call to
+		// http.StripPrefix.
+		if href := r.URL.Path[strings.LastIndexByte(r.URL.Path, '/')+1:]; href != "" {
 			var entries []profileEntry
-			_, queryHost := r.URL.Query()["host"]
-			if queryHost {
+			var module *moduleProfilers
+			query := r.URL.Query()
+			_, queryHost := query["host"]
+			switch {
+			case queryHost:
 				entries = host
-			} else {
-				entries = guest
+			case len(modules) == 1:
+				entries, module = guest[0], &modules[0]
+			default:
+				name := query.Get("module")
+				i := sort.Search(len(modules), func(i int) bool {
+					return modules[i].name >= name
+				})
+				if i == len(modules) || modules[i].name != name {
+					serveError(w, http.StatusNotFound, fmt.Sprintf("Unknown module: %q", name))
+					return
+				}
+				entries, module = guest[i], &modules[i]
 			}
 			for _, entry := range entries {
 				if entry.Href == href {
@@ -157,22 +394,39 @@ func Handler(sampleRate float64, profilers ...Profiler) http.Handler {
 					return
 				}
 			}
+			// The endpoints used by "go tool pprof" to fetch and symbolize
+			// profiles are served for the guest, like net/http/pprof does
+			// for Go programs.
+			if module != nil {
+				if p := module.profiling(); p != nil {
+					switch href {
+					case "cmdline":
+						serveCmdline(w, p)
+						return
+					case "symbol":
+						serveSymbol(w, r, p)
+						return
+					}
+				}
+			}
 		}
 
-		sortProfiles(guest)
+		for _, entries := range guest {
+			sortProfiles(entries)
+		}
 		sortProfiles(host)
 
 		h := w.Header()
 		h.Set("X-Content-Type-Options", "nosniff")
 		h.Set("Content-Type", "text/html; charset=utf-8")
 
-		if err := indexTmplExecute(w, guest, host); err != nil {
+		if err := indexTmplExecute(w, modules, guest, host); err != nil {
 			serveError(w, http.StatusInternalServerError, err.Error())
 		}
 	})
 }
 
-func indexTmplExecute(w io.Writer, guest, host []profileEntry) error {
+func indexTmplExecute(w io.Writer, modules []moduleProfilers, guest [][]profileEntry, host []profileEntry) error {
 	var b bytes.Buffer
 	b.WriteString(`<html>
 <head>
@@ -188,20 +442,51 @@ func indexTmplExecute(w io.Writer, guest, host []profileEntry) error {
 /debug/pprof
 <br>
 <p>Set debug=1 as a query parameter to export in legacy text format (host only)</p>
-<br>
-Types of profiles available:
-<table>
-<thead><td>Count</td><td>Profile (guest)</td></thead>
+<p>Set seconds=N as a query parameter to capture a delta profile of the next N seconds (guest only)</p>
 `)
 
-	for _, profile := range guest {
-		link := &url.URL{Path: profile.Href}
-		name := profile.Name
-		fmt.Fprintf(&b, "<tr><td>%d</td><td><a href='%s'>%s</a></td></tr>\n", profile.Count, link, html.EscapeString(name))
+	if len(modules) > 1 {
+		b.WriteString("Modules:")
+		for _, m := range modules {
+			fmt.Fprintf(&b, " <a href='#%s'>%s</a>",
+				html.EscapeString(url.PathEscape("module-"+m.name)), html.EscapeString(m.name))
+		}
+		b.WriteString("\n<br>\n")
 	}
+	b.WriteString("<br>\nTypes of profiles available:\n")
 
-	b.WriteString(`</table>
-<table>
+	for i, profiles := range guest {
+		m := &modules[i]
+		title := "guest"
+		if m.name != "" {
+			title = "guest: " + m.name
+			fmt.Fprintf(&b, "<table id='%s'>\n", html.EscapeString("module-"+m.name))
+		} else {
+			b.WriteString("<table>\n")
+		}
+		fmt.Fprintf(&b, "<thead><td>Count</td><td>Profile (%s)</td><td>Delta profiles</td></thead>\n", html.EscapeString(title))
+
+		for _, profile := range profiles {
+			link := guestProfileLink(profile.Href, profile.Module, "")
+			name := profile.Name
+			fmt.Fprintf(&b, "<tr><td>%d</td><td><a href='%s'>%s</a></td><td>", profile.Count, html.EscapeString(link.String()), html.EscapeString(name))
+			if profile.Seconds {
+				for _, seconds := range secondsPresets {
+					link := guestProfileLink(profile.Href, profile.Module, "seconds="+strconv.Itoa(seconds))
+					fmt.Fprintf(&b, "<a href='%s'>%ds</a> ", html.EscapeString(link.String()), seconds)
+				}
+			}
+			b.WriteString("</td></tr>\n")
+		}
+		if p := m.profiling(); p != nil && len(p.args) > 0 {
+			link := guestProfileLink("cmdline", m.name, "")
+			fmt.Fprintf(&b, "<tr><td></td><td><a href='%s'>cmdline</a></td><td></td></tr>\n", html.EscapeString(link.String()))
+		}
+
+		b.WriteString("</table>\n")
+	}
+
+	b.WriteString(`<table>
 <thead><td>Count</td><td>Profile (host)</td></thead>
 `)
 
@@ -218,8 +503,8 @@ Types of profiles available:
 	<ul>
 	`)
 
-	descriptionsByName := make(map[string]string, len(guest)+len(host))
-	for _, profiles := range [][]profileEntry{guest, host} {
+	descriptionsByName := make(map[string]string)
+	for _, profiles := range append(guest, host) {
 		for _, profile := range profiles {
 			if profile.Desc != "" {
 				descriptionsByName[profile.Name] = profile.Desc
@@ -248,11 +533,17 @@ Types of profiles available:
 var profileDescriptions = map[string]string{
 	"allocs":       "A sampling of all past memory allocations",
 	"block":        "Stack traces that led to blocking on synchronization primitives",
+	"calls":        "Number of calls to each function of the WebAssembly module",
 	"cmdline":      "The command line invocation of the current program",
+	"gc":           "Stack traces that led to garbage collections of the WebAssembly module",
 	"goroutine":    "Stack traces of all current goroutines. Use debug=2 as a query parameter to export in the same format as an unrecovered panic.",
 	"heap":         "A sampling of memory allocations of live objects. You can specify the gc GET parameter to run GC before taking the heap sample.",
+	"memgrow":      "Stack traces that led to growing the linear memory of the WebAssembly module",
 	"mutex":        "Stack traces of holders of contended mutexes",
 	"profile":      "CPU profile. You can specify the duration in the seconds GET parameter. After you get the profile file, use the go tool pprof command to investigate the profile.",
+	"stackdepth":   "Deepest call stacks and shadow stack sizes reached by each call path of the WebAssembly module",
+	"syscalls":     "Stack traces that led to calls to WASI host functions",
 	"threadcreate": "Stack traces that led to the creation of new OS threads",
+	"traps":        "Stack traces of the traps of the WebAssembly module, labeled with their cause",
 	"trace":        "A trace of execution of the current program. You can specify the duration in the seconds GET parameter. After you get the trace file, use the go tool trace command to investigate the trace.",
 }
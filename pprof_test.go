@@ -0,0 +1,292 @@
+package wzprof
+
+import (
+	"io"
+	"net/http"
+	"net/http/httptest"
+	"strings"
+	"testing"
+
+	"github.com/google/pprof/profile"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+type testProfiler struct {
+	name string
+	body string
+}
+
+func (p testProfiler) NewFunctionListener(api.FunctionDefinition) experimental.FunctionListener {
+	return nil
+}
+
+func (p testProfiler) Name() string { return p.name }
+
+func (p testProfiler) Desc() string { return "" }
+
+func (p testProfiler) Count() int { return 0 }
+
+func (p testProfiler) SampleType() []*profile.ValueType { return nil }
+
+func (p testProfiler) NewHandler(sampleRate float64) http.Handler {
+	return http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		io.WriteString(w, p.body)
+	})
+}
+
+func serveTestRequest(t *testing.T, handler http.Handler, target string) (int, string) {
+	t.Helper()
+	w := httptest.NewRecorder()
+	handler.ServeHTTP(w, httptest.NewRequest("GET", target, nil))
+	return w.Code, w.Body.String()
+}
+
+func TestMultiHandler(t *testing.T) {
+	handler := MultiHandler(1, map[string][]Profiler{
+		"foo": {testProfiler{name: "profile", body: "foo-profile"}},
+		"bar": {testProfiler{name: "profile", body: "bar-profile"}},
+	})
+
+	for _, test := range []struct {
+		target string
+		status int
+		body   string
+	}{
+		{"/debug/pprof/profile?module=foo", http.StatusOK, "foo-profile"},
+		{"/debug/pprof/profile?module=bar", http.StatusOK, "bar-profile"},
+		{"/debug/pprof/profile?module=baz", http.StatusNotFound, "Unknown module"},
+		{"/debug/pprof/profile", http.StatusNotFound, "Unknown module"},
+	} {
+		status, body := serveTestRequest(t, handler, test.target)
+		if status != test.status {
+			t.Errorf("%s: status mismatch: want=%d got=%d", test.target, test.status, status)
+		}
+		if !strings.Contains(body, test.body) {
+			t.Errorf("%s: body mismatch: want=%q got=%q", test.target, test.body, body)
+		}
+	}
+
+	_, index := serveTestRequest(t, handler, "/debug/pprof/")
+	for _, link := range []string{"profile?module=foo", "profile?module=bar"} {
+		if !strings.Contains(index, link) {
+			t.Errorf("index page is missing a link to %s", link)
+		}
+	}
+}
+
+func TestHandlerIndex(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true), CommandLine("app.wasm"))
+	handler := MultiHandler(1, map[string][]Profiler{
+		"foo": {p.CPUProfiler(), p.StackProfiler()},
+		"bar": {testProfiler{name: "calls"}},
+	})
+
+	_, index := serveTestRequest(t, handler, "/debug/pprof/")
+	for _, want := range []string{
+		"<a href='#module-bar'>bar</a>",
+		"<table id='module-foo'>",
+		"profile?module=foo&amp;seconds=5",
+		"profile?module=foo&amp;seconds=60",
+		"calls?module=bar&amp;seconds=30",
+		"cmdline?module=foo",
+	} {
+		if !strings.Contains(index, want) {
+			t.Errorf("index page is missing %q", want)
+		}
+	}
+	for _, unwanted := range []string{
+		"stackdepth?module=foo&amp;seconds=",
+		"cmdline?module=bar",
+	} {
+		if strings.Contains(index, unwanted) {
+			t.Errorf("index page has %q", unwanted)
+		}
+	}
+}
+
+func TestHandlerPathPrefix(t *testing.T) {
+	handler := Handler(1, testProfiler{name: "profile", body: "guest-profile"})
+
+	mux := http.NewServeMux()
+	mux.Handle("/debug/pprof/", handler)
+	mux.Handle("/modules/foo/pprof/", handler)
+	mux.Handle("/stripped/", http.StripPrefix("/stripped/", handler))
+
+	for _, target := range []string{
+		"/debug/pprof/profile",
+		"/modules/foo/pprof/profile",
+		"/stripped/profile",
+	} {
+		status, body := serveTestRequest(t, mux, target)
+		if status != http.StatusOK {
+			t.Errorf("%s: status mismatch: want=%d got=%d", target, http.StatusOK, status)
+		}
+		if body != "guest-profile" {
+			t.Errorf("%s: body mismatch: want=%q got=%q", target, "guest-profile", body)
+		}
+	}
+}
+
+func TestDeltaProfile(t *testing.T) {
+	newProfile := func(duration, count, size int64) *profile.Profile {
+		fn := &profile.Function{ID: 1, Name: "malloc"}
+		loc := &profile.Location{ID: 1, Line: []profile.Line{{Function: fn}}}
+		return &profile.Profile{
+			SampleType: []*profile.ValueType{
+				{Type: "alloc_objects", Unit: "count"},
+				{Type: "alloc_space", Unit: "bytes"},
+			},
+			PeriodType:    &profile.ValueType{Type: "alloc_objects", Unit: "count"},
+			Period:        1,
+			TimeNanos:     1000,
+			DurationNanos: duration,
+			Sample: []*profile.Sample{
+				{Location: []*profile.Location{loc}, Value: []int64{count, size}},
+			},
+			Location: []*profile.Location{loc},
+			Function: []*profile.Function{fn},
+		}
+	}
+
+	delta, err := deltaProfile(newProfile(10, 2, 20), newProfile(30, 5, 80))
+	if err != nil {
+		t.Fatal(err)
+	}
+	if delta.TimeNanos != 1010 || delta.DurationNanos != 20 {
+		t.Errorf("time range mismatch: want=(1010,20) got=(%d,%d)", delta.TimeNanos, delta.DurationNanos)
+	}
+	if len(delta.Sample) != 1 {
+		t.Fatalf("wrong number of samples: want=1 got=%d", len(delta.Sample))
+	}
+	if v := delta.Sample[0].Value; v[0] != 3 || v[1] != 60 {
+		t.Errorf("sample values mismatch: want=[3 60] got=%v", v)
+	}
+}
+
+func TestSamplerHandlerConfig(t *testing.T) {
+	sampler := NewSampler(0.5)
+	handler := sampler.Handler(testProfiler{name: "profile", body: "guest-profile"})
+
+	post := func(rate string) (int, string) {
+		w := httptest.NewRecorder()
+		r := httptest.NewRequest("POST", "/debug/pprof/config", strings.NewReader("sample_rate="+rate))
+		r.Header.Set("Content-Type", "application/x-www-form-urlencoded")
+		handler.ServeHTTP(w, r)
+		return w.Code, w.Body.String()
+	}
+
+	if _, body := serveTestRequest(t, handler, "/debug/pprof/config"); body != "{\"sample_rate\":0.5}\n" {
+		t.Errorf("config mismatch: want=%q got=%q", "{\"sample_rate\":0.5}\n", body)
+	}
+	if status, body := post("1"); status != http.StatusOK || body != "{\"sample_rate\":1}\n" {
+		t.Errorf("config mismatch: want=%q got=%d %q", "{\"sample_rate\":1}\n", status, body)
+	}
+	for _, rate := range []string{"", "0", "-1", "fast"} {
+		if status, _ := post(rate); status != http.StatusBadRequest {
+			t.Errorf("%q: status mismatch: want=%d got=%d", rate, http.StatusBadRequest, status)
+		}
+	}
+	if rate := sampler.SampleRate(); rate != 1 {
+		t.Errorf("sample rate mismatch: want=1 got=%g", rate)
+	}
+
+	if status, body := serveTestRequest(t, handler, "/debug/pprof/profile"); status != http.StatusOK || body != "guest-profile" {
+		t.Errorf("profile mismatch: want=%q got=%d %q", "guest-profile", status, body)
+	}
+}
+
+func TestServeProfileCompression(t *testing.T) {
+	prof := &profile.Profile{
+		SampleType: []*profile.ValueType{{Type: "samples", Unit: "count"}},
+	}
+	handler := http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		serveProfile(w, r, prof)
+	})
+
+	for _, test := range []struct {
+		target string
+		status int
+		gzip   bool
+	}{
+		{"/profile", http.StatusOK, true},
+		{"/profile?compression=1", http.StatusOK, true},
+		{"/profile?compression=0", http.StatusOK, false},
+		{"/profile?compression=10", http.StatusBadRequest, false},
+	} {
+		status, body := serveTestRequest(t, handler, test.target)
+		if status != test.status {
+			t.Errorf("%s: status mismatch: want=%d got=%d", test.target, test.status, status)
+			continue
+		}
+		if status != http.StatusOK {
+			continue
+		}
+		// Compressed profiles start with the magic number of gzip.
+		if gzip := strings.HasPrefix(body, "\x1f\x8b"); gzip != test.gzip {
+			t.Errorf("%s: compression mismatch: want=%t got=%t", test.target, test.gzip, gzip)
+		}
+		if _, err := profile.ParseData([]byte(body)); err != nil {
+			t.Errorf("%s: %v", test.target, err)
+		}
+	}
+}
+
+func TestHandlerSymbolCmdline(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true), CommandLine("app.wasm", "-v"))
+	symbols := newSymbolCache(noopsymbolizer{})
+	symbols.entries[locationKey{pc: 1}] = symbolCacheEntry{
+		address:   0x10,
+		locations: []location{{HumanName: "main"}},
+	}
+	p.symbols = symbols
+	handler := Handler(1, p.CPUProfiler())
+
+	code, body := serveTestRequest(t, handler, "/debug/pprof/cmdline")
+	if code != http.StatusOK || body != "app.wasm\x00-v" {
+		t.Errorf("wrong response to cmdline: %d %q", code, body)
+	}
+
+	w := httptest.NewRecorder()
+	handler.ServeHTTP(w, httptest.NewRequest("POST", "/debug/pprof/symbol", strings.NewReader("0x10+0x20")))
+	if want := "num_symbols: 1\n0x10 main\n"; w.Body.String() != want {
+		t.Errorf("wrong response to symbol: want=%q got=%q", want, w.Body.String())
+	}
+
+	code, body = serveTestRequest(t, handler, "/debug/pprof/symbol")
+	if code != http.StatusOK || body != "num_symbols: 1\n" {
+		t.Errorf("wrong response to symbol probe: %d %q", code, body)
+	}
+}
+
+func TestMultiHandlerSymbolCmdline(t *testing.T) {
+	profilers := map[string][]Profiler{}
+	for _, name := range []string{"foo", "bar"} {
+		p := ProfilingFor(nil, AllowUnprepared(true), CommandLine(name+".wasm"))
+		symbols := newSymbolCache(noopsymbolizer{})
+		symbols.entries[locationKey{pc: 1}] = symbolCacheEntry{
+			address:   0x10,
+			locations: []location{{HumanName: name + "_main"}},
+		}
+		p.symbols = symbols
+		profilers[name] = []Profiler{p.CPUProfiler()}
+	}
+	handler := MultiHandler(1, profilers)
+
+	for _, name := range []string{"foo", "bar"} {
+		code, body := serveTestRequest(t, handler, "/debug/pprof/cmdline?module="+name)
+		if code != http.StatusOK || body != name+".wasm" {
+			t.Errorf("wrong response to cmdline of %s: %d %q", name, code, body)
+		}
+
+		w := httptest.NewRecorder()
+		handler.ServeHTTP(w, httptest.NewRequest("POST", "/debug/pprof/symbol?module="+name, strings.NewReader("0x10")))
+		if want := "num_symbols: 1\n0x10 " + name + "_main\n"; w.Body.String() != want {
+			t.Errorf("wrong response to symbol of %s: want=%q got=%q", name, want, w.Body.String())
+		}
+	}
+
+	if code, _ := serveTestRequest(t, handler, "/debug/pprof/cmdline?module=baz"); code != http.StatusNotFound {
+		t.Errorf("wrong status code of an unknown module: %d", code)
+	}
+}
@@ -0,0 +1,149 @@
+package wzprof
+
+import (
+	"fmt"
+	"net/http"
+	"net/http/httptest"
+	"testing"
+	"time"
+)
+
+func TestProtectAuthentication(t *testing.T) {
+	handler := Protect(testProfiler{name: "profile", body: "ok"}.NewHandler(1),
+		BasicAuth("user", "secret"),
+		BearerToken("token"),
+	)
+
+	for _, test := range []struct {
+		name   string
+		auth   func(*http.Request)
+		status int
+	}{
+		{"none", func(*http.Request) {}, http.StatusUnauthorized},
+		{"basic", func(r *http.Request) { r.SetBasicAuth("user", "secret") }, http.StatusOK},
+		{"basic wrong user", func(r *http.Request) { r.SetBasicAuth("root", "secret") }, http.StatusUnauthorized},
+		{"basic wrong password", func(r *http.Request) { r.SetBasicAuth("user", "token") }, http.StatusUnauthorized},
+		{"bearer", func(r *http.Request) { r.Header.Set("Authorization", "Bearer token") }, http.StatusOK},
+		{"bearer wrong token", func(r *http.Request) { r.Header.Set("Authorization", "Bearer secret") }, http.StatusUnauthorized},
+	} {
+		t.Run(test.name, func(t *testing.T) {
+			r := httptest.NewRequest("GET", "/debug/pprof/profile", nil)
+			test.auth(r)
+			w := httptest.NewRecorder()
+			handler.ServeHTTP(w, r)
+			if w.Code != test.status {
+				t.Errorf("wrong status code: want=%d got=%d", test.status, w.Code)
+			}
+			if w.Code == http.StatusUnauthorized && w.Header().Get("WWW-Authenticate") == "" {
+				t.Error("missing WWW-Authenticate header")
+			}
+		})
+	}
+}
+
+func TestProtectRateLimit(t *testing.T) {
+	now := time.Now()
+	handler := Protect(testProfiler{name: "profile", body: "ok"}.NewHandler(1), RateLimit(1, 2))
+	handler.(*protectedHandler).time = func() time.Time { return now }
+
+	serve := func(remoteAddr string) int {
+		r := httptest.NewRequest("GET", "/debug/pprof/profile", nil)
+		r.RemoteAddr = remoteAddr
+		w := httptest.NewRecorder()
+		handler.ServeHTTP(w, r)
+		return w.Code
+	}
+
+	for i, want := range []int{http.StatusOK, http.StatusOK, http.StatusTooManyRequests} {
+		if status := serve("192.0.2.1:1234"); status != want {
+			t.Errorf("wrong status code of request %d: want=%d got=%d", i, want, status)
+		}
+	}
+	// Clients are limited by IP address, regardless of the port.
+	if status := serve("192.0.2.1:4321"); status != http.StatusTooManyRequests {
+		t.Errorf("request from another port was not limited: %d", status)
+	}
+	if status := serve("192.0.2.2:1234"); status != http.StatusOK {
+		t.Errorf("request from another client was limited: %d", status)
+	}
+
+	now = now.Add(time.Second)
+	if status := serve("192.0.2.1:1234"); status != http.StatusOK {
+		t.Errorf("request was limited after the bucket was refilled: %d", status)
+	}
+}
+
+func TestProtectRateLimitClients(t *testing.T) {
+	now := time.Now()
+	handler := Protect(testProfiler{name: "profile", body: "ok"}.NewHandler(1), RateLimit(1, 1))
+	h := handler.(*protectedHandler)
+	h.time = func() time.Time { return now }
+
+	serve := func(remoteAddr string) int {
+		r := httptest.NewRequest("GET", "/debug/pprof/profile", nil)
+		r.RemoteAddr = remoteAddr
+		w := httptest.NewRecorder()
+		handler.ServeHTTP(w, r)
+		return w.Code
+	}
+
+	// The clients exhaust their buckets, which are retained up to the limit
+	// of clients.
+	for i := 0; i < 2*maxRateClients; i++ {
+		serve(fmt.Sprintf("10.0.%d.%d:1234", i/256, i%256))
+		if len(h.clients) > maxRateClients || h.lru.Len() != len(h.clients) {
+			t.Fatalf("buckets were not bounded: %d clients, %d buckets", len(h.clients), h.lru.Len())
+		}
+	}
+	last := fmt.Sprintf("10.0.%d.%d:1234", (2*maxRateClients-1)/256, (2*maxRateClients-1)%256)
+	if status := serve(last); status != http.StatusTooManyRequests {
+		t.Errorf("recently seen client was not limited: %d", status)
+	}
+	if status := serve("10.0.0.0:1234"); status != http.StatusOK {
+		t.Errorf("least recently seen client was not forgotten: %d", status)
+	}
+}
+
+func TestProtectZeroLimits(t *testing.T) {
+	handler := Protect(http.HandlerFunc(func(http.ResponseWriter, *http.Request) {}),
+		RateLimit(0, 1),
+		MaxConcurrentCaptures(0),
+	)
+	for i := 0; i < 3; i++ {
+		if status, _ := serveTestRequest(t, handler, "/debug/pprof/profile?seconds=30"); status != http.StatusOK {
+			t.Errorf("request %d was rejected without limits: %d", i, status)
+		}
+	}
+}
+
+func TestProtectMaxConcurrentCaptures(t *testing.T) {
+	started, release := make(chan struct{}, 1), make(chan struct{})
+	handler := Protect(http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		if r.FormValue("seconds") != "" {
+			started <- struct{}{}
+			<-release
+		}
+	}), MaxConcurrentCaptures(1))
+
+	done := make(chan int)
+	go func() {
+		status, _ := serveTestRequest(t, handler, "/debug/pprof/profile?seconds=30")
+		done <- status
+	}()
+	<-started
+
+	if status, _ := serveTestRequest(t, handler, "/debug/pprof/profile?seconds=30"); status != http.StatusTooManyRequests {
+		t.Errorf("concurrent capture was not rejected: %d", status)
+	}
+	if status, _ := serveTestRequest(t, handler, "/debug/pprof/heap"); status != http.StatusOK {
+		t.Errorf("snapshot was rejected during a capture: %d", status)
+	}
+
+	close(release)
+	if status := <-done; status != http.StatusOK {
+		t.Errorf("wrong status code of the capture: %d", status)
+	}
+	if status, _ := serveTestRequest(t, handler, "/debug/pprof/profile?seconds=30"); status != http.StatusOK {
+		t.Errorf("capture was rejected after the previous one completed: %d", status)
+	}
+}
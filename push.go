@@ -0,0 +1,209 @@
+package wzprof
+
+import (
+	"bytes"
+	"context"
+	"errors"
+	"fmt"
+	"io"
+	"log"
+	"mime/multipart"
+	"net/http"
+	"net/url"
+	"sort"
+	"strconv"
+	"strings"
+	"time"
+
+	"github.com/google/pprof/profile"
+)
+
+// PushOption is a type used to represent configuration options for Push.
+type PushOption func(*pusher)
+
+// PushInterval configures the period of time covered by each profile pushed to
+// the server.
+//
+// Default to 15 seconds.
+func PushInterval(interval time.Duration) PushOption {
+	return func(p *pusher) { p.interval = interval }
+}
+
+// PushLabels configures labels attached to the profiles pushed to the server,
+// for example to describe the service or the wasm module being profiled.
+func PushLabels(labels map[string]string) PushOption {
+	return func(p *pusher) {
+		for k, v := range labels {
+			p.labels[k] = v
+		}
+	}
+}
+
+// PushHeader configures a http header sent with each request made to the
+// server, which may be used to pass authentication credentials.
+func PushHeader(key, value string) PushOption {
+	return func(p *pusher) { p.header.Add(key, value) }
+}
+
+// PushClient configures the http client used to push profiles.
+//
+// Default to http.DefaultClient.
+func PushClient(client *http.Client) PushOption {
+	return func(p *pusher) { p.client = client }
+}
+
+// Push enables continuous profiling by periodically capturing profiles from
+// the CPU and memory profilers and uploading them to the Pyroscope server
+// listening at serverURL (e.g. "http://localhost:4040"), under the application
+// name passed as argument. Either of the profilers may be nil.
+//
+// The memory profiles pushed are delta profiles of the allocations made during
+// each period. The CPU profiler must not be started by the application since
+// Push drives when it is active.
+//
+// The function blocks until ctx is canceled. Errors occurring while uploading
+// profiles are logged and do not interrupt profiling.
+//
+// Parca servers are usually configured to scrape pprof endpoints instead of
+// receiving pushed profiles, which is supported by exposing the profilers with
+// Handler.
+func Push(ctx context.Context, serverURL, name string, sampleRate float64, cpu *CPUProfiler, mem *MemoryProfiler, options ...PushOption) error {
+	p := &pusher{
+		url:      strings.TrimSuffix(serverURL, "/") + "/ingest",
+		name:     name,
+		interval: 15 * time.Second,
+		labels:   make(map[string]string),
+		header:   make(http.Header),
+		client:   http.DefaultClient,
+	}
+	for _, opt := range options {
+		opt(p)
+	}
+	if p.interval <= 0 {
+		return errors.New("push interval must be positive")
+	}
+
+	var prev *profile.Profile
+	if mem != nil {
+		prev = mem.newProfile(sampleRate)
+	}
+
+	for {
+		if cpu != nil && !cpu.StartProfile() {
+			return errors.New("CPU profiler already running")
+		}
+		start := time.Now()
+
+		timer := time.NewTimer(p.interval)
+		select {
+		case <-timer.C:
+		case <-ctx.Done():
+			timer.Stop()
+			if cpu != nil {
+				cpu.StopProfile(sampleRate)
+			}
+			return nil
+		}
+		end := time.Now()
+
+		if cpu != nil {
+			p.push(ctx, cpu.StopProfile(sampleRate), start, end)
+		}
+
+		if mem != nil {
+			curr := mem.newProfile(sampleRate)
+			delta, err := deltaProfile(prev, curr)
+			if err != nil {
+				log.Printf("push: computing memory profile delta: %s", err)
+			} else {
+				p.push(ctx, mem.p.endProfile(mem.Name(), delta), start, end)
+			}
+			prev = curr
+		}
+	}
+}
+
+type pusher struct {
+	url      string
+	name     string
+	interval time.Duration
+	labels   map[string]string
+	header   http.Header
+	client   *http.Client
+}
+
+func (p *pusher) push(ctx context.Context, prof *profile.Profile, start, end time.Time) {
+	if err := p.upload(ctx, prof, start, end); err != nil {
+		log.Printf("push: %s", err)
+	}
+}
+
+func (p *pusher) upload(ctx context.Context, prof *profile.Profile, start, end time.Time) error {
+	body := new(bytes.Buffer)
+	form := multipart.NewWriter(body)
+	part, err := form.CreateFormFile("profile", "profile.pprof")
+	if err != nil {
+		return err
+	}
+	if err := prof.Write(part); err != nil {
+		return err
+	}
+	if err := form.Close(); err != nil {
+		return err
+	}
+
+	query := url.Values{}
+	query.Set("name", p.appName())
+	query.Set("from", strconv.FormatInt(start.Unix(), 10))
+	query.Set("until", strconv.FormatInt(end.Unix(), 10))
+	query.Set("format", "pprof")
+	query.Set("spyName", "wzprof")
+
+	req, err := http.NewRequestWithContext(ctx, http.MethodPost, p.url+"?"+query.Encode(), body)
+	if err != nil {
+		return err
+	}
+	for k, v := range p.header {
+		req.Header[k] = v
+	}
+	req.Header.Set("Content-Type", form.FormDataContentType())
+
+	res, err := p.client.Do(req)
+	if err != nil {
+		return err
+	}
+	defer res.Body.Close()
+
+	if res.StatusCode < 200 || res.StatusCode >= 300 {
+		msg, _ := io.ReadAll(io.LimitReader(res.Body, 1024))
+		return fmt.Errorf("%s: %s: %s", p.url, res.Status, bytes.TrimSpace(msg))
+	}
+	return nil
+}
+
+// appName returns the application name with labels in the form expected by
+// Pyroscope, for example "name{module=app.wasm}".
+func (p *pusher) appName() string {
+	if len(p.labels) == 0 {
+		return p.name
+	}
+	keys := make([]string, 0, len(p.labels))
+	for k := range p.labels {
+		keys = append(keys, k)
+	}
+	sort.Strings(keys)
+
+	b := new(strings.Builder)
+	b.WriteString(p.name)
+	b.WriteByte('{')
+	for i, k := range keys {
+		if i > 0 {
+			b.WriteByte(',')
+		}
+		b.WriteString(k)
+		b.WriteByte('=')
+		b.WriteString(p.labels[k])
+	}
+	b.WriteByte('}')
+	return b.String()
+}
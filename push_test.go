@@ -0,0 +1,89 @@
+package wzprof
+
+import (
+	"context"
+	"net/http"
+	"net/http/httptest"
+	"testing"
+	"time"
+
+	"github.com/google/pprof/profile"
+)
+
+func TestPushUpload(t *testing.T) {
+	var query map[string]string
+	var received *profile.Profile
+
+	server := httptest.NewServer(http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		if r.URL.Path != "/ingest" {
+			t.Errorf("wrong path: want=/ingest got=%s", r.URL.Path)
+		}
+		if got := r.Header.Get("Authorization"); got != "Bearer token" {
+			t.Errorf("wrong authorization header: %q", got)
+		}
+		query = make(map[string]string)
+		for k := range r.URL.Query() {
+			query[k] = r.URL.Query().Get(k)
+		}
+		f, _, err := r.FormFile("profile")
+		if err != nil {
+			t.Error(err)
+			return
+		}
+		defer f.Close()
+		if received, err = profile.Parse(f); err != nil {
+			t.Error(err)
+		}
+	}))
+	defer server.Close()
+
+	p := &pusher{
+		url:    server.URL + "/ingest",
+		name:   "app",
+		labels: map[string]string{"module": "app.wasm", "env": "test"},
+		header: http.Header{},
+		client: server.Client(),
+	}
+	PushHeader("Authorization", "Bearer token")(p)
+
+	prof := newTestProfile()
+	start := time.Unix(1000, 0)
+	end := time.Unix(1015, 0)
+	if err := p.upload(context.Background(), prof, start, end); err != nil {
+		t.Fatal(err)
+	}
+
+	for k, v := range map[string]string{
+		"name":   "app{env=test,module=app.wasm}",
+		"from":   "1000",
+		"until":  "1015",
+		"format": "pprof",
+	} {
+		if query[k] != v {
+			t.Errorf("query parameter %s mismatch: want=%q got=%q", k, v, query[k])
+		}
+	}
+	if received == nil {
+		t.Fatal("no profile received")
+	}
+	if len(received.Sample) != len(prof.Sample) {
+		t.Errorf("wrong number of samples: want=%d got=%d", len(prof.Sample), len(received.Sample))
+	}
+}
+
+func TestPushUploadError(t *testing.T) {
+	server := httptest.NewServer(http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		http.Error(w, "bad profile", http.StatusBadRequest)
+	}))
+	defer server.Close()
+
+	p := &pusher{
+		url:    server.URL + "/ingest",
+		name:   "app",
+		header: http.Header{},
+		client: server.Client(),
+	}
+	if err := p.upload(context.Background(), newTestProfile(), time.Now(), time.Now()); err == nil {
+		t.Error("expected an error from the server")
+	}
+}
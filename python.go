@@ -15,23 +15,32 @@ import (
 
 const (
 	runtimeAddrName = "_PyRuntime"
+	tstateAddrName  = "_Py_tss_tstate"
 	versionAddrName = "Py_Version"
+	evalFrameName   = "_PyEval_EvalFrameDefault"
+	// Bound on the length of the chains of frames, which guards against
+	// looping on corrupted memory.
+	pythonMaxFrames = 1024
 )
 
-func supportedPython(wasmbin []byte) bool {
+// supportedPython returns the layout of the CPython structures of the
+// interpreter embedded in the wasm module binary, or nil if the module is not
+// a supported version of CPython. The version is read from Py_Version, which
+// requires the module to be compiled with debug symbols.
+func supportedPython(wasmbin []byte) *pythonLayout {
 	p, err := newDwarfParserFromBin(wasmbin)
 	if err != nil {
-		return false
+		return nil
 	}
 
-	versionAddr := pythonAddress(p, versionAddrName)
+	versionAddr := dwarfVariableAddress(p, versionAddrName)
 	if versionAddr == 0 {
-		return false
+		return nil
 	}
 
 	data := wasmdataSection(wasmbin)
 	if data == nil {
-		return false
+		return nil
 	}
 
 	var versionhex uint32
@@ -55,24 +64,46 @@ func supportedPython(wasmbin []byte) bool {
 	// see cpython patchlevel.h
 	major := (versionhex >> 24) & 0xFF
 	minor := (versionhex >> 16) & 0xFF
-	return major == 3 && minor == 11
+	if major != 3 {
+		return nil
+	}
+	return pythonLayouts[minor]
 }
 
-func preparePython(mod wazero.CompiledModule) (*python, error) {
+// preparePython returns the state used to walk the Python call stacks of mod.
+// The wasm frames are merged with the Python frames when symbols is not nil,
+// which is the symbolizer of the native code of the interpreter (see
+// NativeFrames).
+func preparePython(mod wazero.CompiledModule, layout *pythonLayout, symbols symbolizer) (*python, error) {
 	p, err := newDwarfparser(mod)
 	if err != nil {
 		return nil, fmt.Errorf("could not build dwarf parser: %w", err)
 	}
-	runtimeAddr := pythonAddress(p, runtimeAddrName)
-	if runtimeAddr == 0 {
-		return nil, fmt.Errorf("could not find python runtime address")
+	if layout.padTstateCurrentInRT != 0 {
+		runtimeAddr := dwarfVariableAddress(p, runtimeAddrName)
+		if runtimeAddr == 0 {
+			return nil, fmt.Errorf("could not find python runtime address")
+		}
+		return &python{
+			layout:     layout,
+			tstateaddr: ptr32(runtimeAddr) + layout.padTstateCurrentInRT,
+			symbols:    symbols,
+		}, nil
+	}
+	// Since 3.12, the current thread state is a thread-local variable, which
+	// is a regular global in single-threaded wasm modules.
+	tstateAddr := dwarfVariableAddress(p, tstateAddrName)
+	if tstateAddr == 0 {
+		return nil, fmt.Errorf("could not find python thread state address")
 	}
 	return &python{
-		pyrtaddr: ptr32(runtimeAddr),
+		layout:     layout,
+		tstateaddr: ptr32(tstateAddr),
+		symbols:    symbols,
 	}, nil
 }
 
-func pythonAddress(p dwarfparser, name string) uint32 {
+func dwarfVariableAddress(p dwarfparser, name string) uint32 {
 	for {
 		ent, err := p.r.Next()
 		if err != nil || ent == nil {
@@ -90,8 +121,19 @@ func pythonAddress(p dwarfparser, name string) uint32 {
 	return 0
 }
 
+// python walks the frames of the CPython interpreter.
+//
+// Since 3.11, calls between Python functions do not recurse in the interpreter
+// loop, a call to _PyEval_EvalFrameDefault executes all the frames linked from
+// the innermost frame down to the entry frame of the call. When the native
+// frames are merged with the Python frames, each call to
+// _PyEval_EvalFrameDefault on the wasm stack is replaced by the Python frames
+// that it executes, so the C functions called by Python code (e.g. extension
+// modules) show up under their Python callers.
 type python struct {
-	pyrtaddr ptr32
+	layout     *pythonLayout
+	tstateaddr ptr32      // address of the pointer to the current PyThreadState
+	symbols    symbolizer // nil unless native frames are merged
 }
 
 func getDwarfLocationAddress(ent *dwarf.Entry) uint32 {
@@ -110,37 +152,93 @@ func getDwarfLocationAddress(ent *dwarf.Entry) uint32 {
 	return binary.LittleEndian.Uint32(loc[1:])
 }
 
-// Padding of fields in various CPython structs. They are calculated
-// by writing a function in any CPython module, and executing it with
-// wazero.
+// pythonLayout holds the padding of fields in various CPython structs, which
+// change between minor versions of the interpreter. They are calculated by
+// writing a function in any CPython module, and executing it with wazero.
 //
 // TODO: look into using CGO and #import<Python.h> to generate them
 // instead.
-const (
-	// _PyRuntimeState.
-	padTstateCurrentInRT = 360
-	// PyThreadState.
-	padCframeInThreadState = 40
+type pythonLayout struct {
+	// _PyRuntimeState, zero if the current thread state is the
+	// _Py_tss_tstate variable instead.
+	padTstateCurrentInRT ptr32
+	// PyThreadState, the current frame is in a _PyCFrame until 3.12.
+	padCframeInThreadState       ptr32 // zero if there is no _PyCFrame
+	padCurrentFrameInThreadState ptr32
 	// _PyCFrame.
-	padCurrentFrameInCFrame = 4
+	padCurrentFrameInCFrame ptr32
 	// _PyInterpreterFrame.
-	padPreviousInFrame  = 24
-	padCodeInFrame      = 16
-	padPrevInstrInFrame = 28
-	padOwnerInFrame     = 37
+	padPreviousInFrame  ptr32
+	padCodeInFrame      ptr32
+	padPrevInstrInFrame ptr32 // instr_ptr since 3.13
+	padOwnerInFrame     ptr32
+	padIsEntryInFrame   ptr32 // zero if the entry frames are owned by the C stack
 	// PyCodeObject.
-	padFilenameInCodeObject       = 80
-	padNameInCodeObject           = 84
-	padCodeAdaptiveInCodeObject   = 116
-	padFirstlinenoInCodeObject    = 48
-	padLinearrayInCodeObject      = 104
-	padLinetableInCodeObject      = 92
-	padFirstTraceableInCodeObject = 108
-	sizeCodeUnit                  = 2
+	padFilenameInCodeObject     ptr32
+	padNameInCodeObject         ptr32
+	padCodeAdaptiveInCodeObject ptr32
+	padFirstlinenoInCodeObject  ptr32
+	padLinearrayInCodeObject    ptr32 // zero if there are no line arrays
+	padLinetableInCodeObject    ptr32
+	// PyASCIIObject.
+	sizeAsciiObject ptr32
+}
+
+// pythonLayouts maps minor versions of CPython 3 to the layout of their
+// structs on wasm32.
+var pythonLayouts = map[uint32]*pythonLayout{
+	11: {
+		padTstateCurrentInRT:        360,
+		padCframeInThreadState:      40,
+		padCurrentFrameInCFrame:     4,
+		padPreviousInFrame:          24,
+		padCodeInFrame:              16,
+		padPrevInstrInFrame:         28,
+		padOwnerInFrame:             37,
+		padIsEntryInFrame:           36,
+		padFilenameInCodeObject:     80,
+		padNameInCodeObject:         84,
+		padCodeAdaptiveInCodeObject: 116,
+		padFirstlinenoInCodeObject:  48,
+		padLinearrayInCodeObject:    104,
+		padLinetableInCodeObject:    92,
+		sizeAsciiObject:             24,
+	},
+	12: {
+		padCframeInThreadState:      40,
+		padCurrentFrameInCFrame:     0,
+		padPreviousInFrame:          4,
+		padCodeInFrame:              0,
+		padPrevInstrInFrame:         28,
+		padOwnerInFrame:             38,
+		padFilenameInCodeObject:     80,
+		padNameInCodeObject:         84,
+		padCodeAdaptiveInCodeObject: 124,
+		padFirstlinenoInCodeObject:  44,
+		padLinetableInCodeObject:    92,
+		sizeAsciiObject:             20,
+	},
+	13: {
+		padCurrentFrameInThreadState: 52,
+		padPreviousInFrame:           4,
+		padCodeInFrame:               0,
+		padPrevInstrInFrame:          28,
+		padOwnerInFrame:              38,
+		padFilenameInCodeObject:      80,
+		padNameInCodeObject:          84,
+		padCodeAdaptiveInCodeObject:  124,
+		padFirstlinenoInCodeObject:   44,
+		padLinetableInCodeObject:     92,
+		sizeAsciiObject:              20,
+	},
+}
+
+// Fields and constants which are identical in all supported versions.
+const (
+	sizeCodeUnit = 2
 	// PyASCIIObject.
 	padStateInAsciiObject  = 16
 	padLengthInAsciiObject = 8
-	sizeAsciiObject        = 24
 	// PyBytesObject.
 	padSvalInBytesObject = 16
 	padSizeInBytesObject = 8
@@ -150,10 +248,17 @@ const (
 	enumCodeLocationNoCol     = 13
 	enumCodeLocationLong      = 14
 	enumFrameOwnedByGenerator = 1
+	enumFrameOwnedByCStack    = 3 // since 3.12
 )
 
 func (p *python) Locations(fn experimental.InternalFunction, pc experimental.ProgramCounter) (uint64, []location) {
-	call := fn.(pyfuncall)
+	call, ok := fn.(pyfuncall)
+	if !ok {
+		if p.symbols == nil {
+			return 0, nil
+		}
+		return p.symbols.Locations(fn, pc)
+	}
 
 	loc := location{
 		File:       call.file,
@@ -169,52 +274,158 @@ func (p *python) Locations(fn experimental.InternalFunction, pc experimental.Pro
 
 func (p *python) Stackiter(mod api.Module, def api.FunctionDefinition, wasmsi experimental.StackIterator) experimental.StackIterator {
 	m := mod.Memory()
-	tsp := deref[ptr32](m, p.pyrtaddr+padTstateCurrentInRT)
-	cframep := deref[ptr32](m, tsp+padCframeInThreadState)
-	framep := deref[ptr32](m, cframep+padCurrentFrameInCFrame)
-
+	framep := p.currentFrame(m)
+	if p.symbols != nil {
+		return p.mixedStack(m, framep, wasmsi)
+	}
 	return &pystackiter{
 		namedbg: def.DebugName(),
 		mem:     m,
+		layout:  p.layout,
 		framep:  framep,
 	}
 }
 
+// currentFrame returns the innermost _PyInterpreterFrame of the current
+// thread state.
+func (p *python) currentFrame(m vmem) ptr32 {
+	l := p.layout
+	tsp := deref[ptr32](m, p.tstateaddr)
+	if l.padCframeInThreadState != 0 {
+		cframep := deref[ptr32](m, tsp+l.padCframeInThreadState)
+		return deref[ptr32](m, cframep+l.padCurrentFrameInCFrame)
+	}
+	return deref[ptr32](m, tsp+l.padCurrentFrameInThreadState)
+}
+
+// mixedStack returns a stack iterator over the wasm frames of wasmsi, where the
+// calls to _PyEval_EvalFrameDefault are replaced by the Python frames that
+// they execute.
+func (p *python) mixedStack(m vmem, framep ptr32, wasmsi experimental.StackIterator) experimental.StackIterator {
+	evals := p.evalFrames(m, framep)
+
+	var wasmFrames []stackFrame
+	activations := 0
+	for wasmsi.Next() {
+		fn := wasmsi.Function()
+		if fn.Definition().Name() == evalFrameName {
+			activations++
+		}
+		wasmFrames = append(wasmFrames, stackFrame{fn: fn, pc: wasmsi.ProgramCounter()})
+	}
+
+	// The innermost call to _PyEval_EvalFrameDefault may not have pushed
+	// its entry frame yet, or the interpreter may have pushed the frame of
+	// a call before entering the loop, so the Python frames are matched
+	// with the outermost calls.
+	unmatched := activations - len(evals)
+	frames := make([]stackFrame, 0, len(wasmFrames))
+	i := 0
+	for _, f := range wasmFrames {
+		if f.fn.Definition().Name() != evalFrameName {
+			frames = append(frames, f)
+			continue
+		}
+		j := i - unmatched
+		i++
+		if j < 0 || j >= len(evals) || len(evals[j]) == 0 {
+			frames = append(frames, f)
+			continue
+		}
+		frames = append(frames, evals[j]...)
+	}
+
+	return &stackFrameIterator{frames: frames}
+}
+
+// evalFrames returns the Python frames linked from framep, grouped by the
+// calls to _PyEval_EvalFrameDefault executing them, from the innermost frame.
+func (p *python) evalFrames(m vmem, framep ptr32) [][]stackFrame {
+	l := p.layout
+	var evals [][]stackFrame
+	var frames []stackFrame
+	for n := 0; framep != 0 && n < pythonMaxFrames; n++ {
+		// Since 3.12, the interpreter loop links the frames that it executes
+		// to an entry frame owned by the C stack. In 3.11, the first frame
+		// executed by the loop is flagged as the entry frame instead.
+		if l.padIsEntryInFrame == 0 && deref[uint8](m, framep+l.padOwnerInFrame) == enumFrameOwnedByCStack {
+			evals = append(evals, frames)
+			frames = nil
+		} else {
+			frames = append(frames, pythonFrame(m, l, framep))
+			if l.padIsEntryInFrame != 0 && deref[uint8](m, framep+l.padIsEntryInFrame) != 0 {
+				evals = append(evals, frames)
+				frames = nil
+			}
+		}
+		previous := deref[ptr32](m, framep+l.padPreviousInFrame)
+		if previous == framep {
+			break
+		}
+		framep = previous
+	}
+	if len(frames) > 0 {
+		evals = append(evals, frames)
+	}
+	return evals
+}
+
+// pythonFrame returns the stack frame of the Python function executed by the
+// _PyInterpreterFrame at framep.
+func pythonFrame(m vmem, l *pythonLayout, framep ptr32) stackFrame {
+	call := pythonCall(m, l, framep)
+	return stackFrame{fn: call, pc: experimental.ProgramCounter(call.addr)}
+}
+
 type pystackiter struct {
 	namedbg string
 	mem     api.Memory
+	layout  *pythonLayout
 	started bool
 	framep  ptr32 // _PyInterpreterFrame*
 }
 
 func (p *pystackiter) Next() bool {
-	if !p.started {
-		p.started = true
-		return p.framep != 0
-	}
-
-	oldframe := p.framep
-	p.framep = deref[ptr32](p.mem, p.framep+padPreviousInFrame)
-	if oldframe == p.framep {
-		p.framep = 0
-		return false
+	for {
+		if !p.started {
+			p.started = true
+		} else {
+			oldframe := p.framep
+			p.framep = deref[ptr32](p.mem, p.framep+p.layout.padPreviousInFrame)
+			if oldframe == p.framep {
+				p.framep = 0
+			}
+		}
+		if p.framep == 0 {
+			return false
+		}
+		// The entry frames pushed by the interpreter on the C stack do
+		// not execute Python code, skip them.
+		if deref[uint8](p.mem, p.framep+p.layout.padOwnerInFrame) != enumFrameOwnedByCStack {
+			return true
+		}
 	}
-	return p.framep != 0
 }
 
 func (p *pystackiter) ProgramCounter() experimental.ProgramCounter {
-	return experimental.ProgramCounter(deref[uint32](p.mem, p.framep+padPrevInstrInFrame))
+	return experimental.ProgramCounter(deref[uint32](p.mem, p.framep+p.layout.padPrevInstrInFrame))
 }
 
 func (p *pystackiter) Function() experimental.InternalFunction {
-	codep := deref[ptr32](p.mem, p.framep+padCodeInFrame)
-	line, _ := lineForFrame(p.mem, p.framep, codep)
-	file := derefPyUnicodeUtf8(p.mem, codep+padFilenameInCodeObject)
-	name := derefPyUnicodeUtf8(p.mem, codep+padNameInCodeObject)
+	return pythonCall(p.mem, p.layout, p.framep)
+}
+
+// pythonCall returns the call to the Python function executed by the
+// _PyInterpreterFrame at framep.
+func pythonCall(m vmem, l *pythonLayout, framep ptr32) pyfuncall {
+	codep := deref[ptr32](m, framep+l.padCodeInFrame)
+	line, _ := lineForFrame(m, l, framep, codep)
+	file := derefPyUnicodeUtf8(m, l, codep+l.padFilenameInCodeObject)
+	name := derefPyUnicodeUtf8(m, l, codep+l.padNameInCodeObject)
 	return pyfuncall{
 		file: file,
 		name: functionName(file, name),
-		addr: deref[uint32](p.mem, p.framep+padPrevInstrInFrame),
+		addr: deref[uint32](m, framep+l.padPrevInstrInFrame),
 		line: line,
 	}
 }
@@ -308,7 +519,7 @@ func (f pyfuncall) ResultNames() []string {
 // Return the utf8 encoding of a PyUnicode object. It is a
 // re-implementation of PyUnicode_AsUTF8. The bytes are copied from
 // the vmem, so the returned string is safe to use.
-func pyUnicodeUTf8(m vmem, p ptr32) string {
+func pyUnicodeUTf8(m vmem, l *pythonLayout, p ptr32) string {
 	statep := p + padStateInAsciiObject
 	state := deref[uint8](m, statep)
 	compact := state&(1<<5) > 0
@@ -318,30 +529,32 @@ func pyUnicodeUTf8(m vmem, p ptr32) string {
 	}
 
 	length := deref[int32](m, p+padLengthInAsciiObject)
-	bytes := derefArray[byte](m, p+sizeAsciiObject, uint32(length))
+	bytes := derefArray[byte](m, p+l.sizeAsciiObject, uint32(length))
 	return unsafe.String(unsafe.SliceData(bytes), len(bytes))
 }
 
-func derefPyUnicodeUtf8(m vmem, p ptr32) string {
+func derefPyUnicodeUtf8(m vmem, l *pythonLayout, p ptr32) string {
 	x := deref[ptr32](m, p)
-	return pyUnicodeUTf8(m, x)
+	return pyUnicodeUTf8(m, l, x)
 }
 
-func lineForFrame(m vmem, framep, codep ptr32) (int32, bool) {
-	codestart := codep + padCodeAdaptiveInCodeObject
-	previnstr := deref[ptr32](m, framep+padPrevInstrInFrame)
-	firstlineno := deref[int32](m, codep+padFirstlinenoInCodeObject)
+func lineForFrame(m vmem, l *pythonLayout, framep, codep ptr32) (int32, bool) {
+	codestart := codep + l.padCodeAdaptiveInCodeObject
+	previnstr := deref[ptr32](m, framep+l.padPrevInstrInFrame)
+	firstlineno := deref[int32](m, codep+l.padFirstlinenoInCodeObject)
 
 	if previnstr < codestart {
 		return firstlineno, false
 	}
 
-	linearray := deref[ptr32](m, codep+padLinearrayInCodeObject)
-	if linearray != 0 {
-		panic("can't handle code sections with line arrays")
+	if l.padLinearrayInCodeObject != 0 {
+		linearray := deref[ptr32](m, codep+l.padLinearrayInCodeObject)
+		if linearray != 0 {
+			panic("can't handle code sections with line arrays")
+		}
 	}
 
-	codebytes := deref[ptr32](m, codep+padLinetableInCodeObject)
+	codebytes := deref[ptr32](m, codep+l.padLinetableInCodeObject)
 	if codebytes == 0 {
 		panic("code section must have a linetable")
 	}
@@ -0,0 +1,115 @@
+package wzprof
+
+import (
+	"context"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestPythonStackIterSkipsEntryFrames(t *testing.T) {
+	layout := pythonLayouts[12]
+	memory := wazerotest.NewMemory(wazerotest.PageSize)
+
+	frames := []struct {
+		addr  ptr32
+		owner uint8
+	}{
+		{addr: 100, owner: 0},
+		{addr: 200, owner: enumFrameOwnedByCStack},
+		{addr: 300, owner: enumFrameOwnedByGenerator},
+	}
+	for i, f := range frames {
+		previous := ptr32(0)
+		if i+1 < len(frames) {
+			previous = frames[i+1].addr
+		}
+		memory.WriteUint32Le(uint32(f.addr+layout.padPreviousInFrame), uint32(previous))
+		memory.WriteByte(uint32(f.addr+layout.padOwnerInFrame), f.owner)
+	}
+
+	it := &pystackiter{mem: memory, layout: layout, framep: frames[0].addr}
+	var got []ptr32
+	for it.Next() {
+		got = append(got, it.framep)
+	}
+
+	want := []ptr32{100, 300}
+	if len(got) != len(want) || got[0] != want[0] || got[1] != want[1] {
+		t.Errorf("wrong frames: want=%v got=%v", want, got)
+	}
+}
+
+func TestPythonMixedStack(t *testing.T) {
+	layout := pythonLayouts[12]
+	memory := wazerotest.NewMemory(wazerotest.PageSize)
+
+	writeString := func(addr uint32, s string) {
+		memory.WriteByte(addr+padStateInAsciiObject, 1<<5|1<<6)
+		memory.WriteUint32Le(addr+padLengthInAsciiObject, uint32(len(s)))
+		memory.WriteString(addr+uint32(layout.sizeAsciiObject), s)
+	}
+	writeString(3000, "app.py")
+	writeString(3100, "inner")
+	writeString(3200, "<module>")
+	for _, code := range []struct{ addr, name uint32 }{{2000, 3100}, {2100, 3200}} {
+		memory.WriteUint32Le(code.addr+uint32(layout.padFilenameInCodeObject), 3000)
+		memory.WriteUint32Le(code.addr+uint32(layout.padNameInCodeObject), code.name)
+	}
+
+	// The frames of two calls to the interpreter loop, from the innermost.
+	frames := []struct {
+		addr, code uint32
+		owner      uint8
+	}{
+		{addr: 1000, code: 2000},
+		{addr: 1100, owner: enumFrameOwnedByCStack},
+		{addr: 1200, code: 2100},
+		{addr: 1300, owner: enumFrameOwnedByCStack},
+	}
+	for i, f := range frames {
+		previous := uint32(0)
+		if i+1 < len(frames) {
+			previous = frames[i+1].addr
+		}
+		memory.WriteUint32Le(f.addr+uint32(layout.padPreviousInFrame), previous)
+		memory.WriteUint32Le(f.addr+uint32(layout.padCodeInFrame), f.code)
+		memory.WriteByte(f.addr+uint32(layout.padOwnerInFrame), f.owner)
+	}
+
+	newFunction := func(name string) *wazerotest.Function {
+		f := wazerotest.NewFunction(func(ctx context.Context, mod api.Module) {})
+		f.FunctionName = name
+		return f
+	}
+	extension := newFunction("ext_compute")
+	eval := newFunction(evalFrameName)
+	vectorcall := newFunction("PyObject_Vectorcall")
+	main := newFunction("main")
+
+	p := &python{layout: layout, symbols: noopsymbolizer{}}
+	si := p.mixedStack(memory, 1000, experimental.NewStackIterator(
+		experimental.StackFrame{Function: extension},
+		experimental.StackFrame{Function: eval},
+		experimental.StackFrame{Function: vectorcall},
+		experimental.StackFrame{Function: eval},
+		experimental.StackFrame{Function: main},
+	))
+
+	var got []string
+	for si.Next() {
+		got = append(got, si.Function().Definition().Name())
+	}
+
+	want := []string{"ext_compute", "app.inner", "PyObject_Vectorcall", "app", "main"}
+	if len(got) != len(want) {
+		t.Fatalf("wrong number of frames: want=%q got=%q", want, got)
+	}
+	for i := range want {
+		if got[i] != want[i] {
+			t.Errorf("wrong frame at index %d: want=%q got=%q", i, want[i], got[i])
+		}
+	}
+}
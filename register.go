@@ -0,0 +1,61 @@
+package wzprof
+
+import (
+	"fmt"
+	"net/http"
+	"runtime/pprof"
+	"sync"
+)
+
+// registeredProfiles holds the names of the profiles registered in the
+// registry of runtime/pprof by RegisterProfiles, which the handlers of the
+// package do not list as profiles of the host.
+var registeredProfiles sync.Map // string => struct{}
+
+// RegisterProfiles registers the profiles of the profilers in the registry of
+// runtime/pprof, named after the profilers with the "wasm_" prefix (e.g.
+// "wasm_allocs" or "wasm_profile"), so the servers already exposing the
+// net/http/pprof handlers of the embedder serve the profiles of the guest
+// as well, without mounting the handler of this package.
+//
+// The data of the custom profiles of runtime/pprof is made of the call stacks
+// of the host, the profiles registered are only listed on the index page of
+// net/http/pprof. The profiles are served by handlers registered on mux at
+// "/debug/pprof/wasm_<name>", which take precedence over the handler of
+// net/http/pprof mounted at "/debug/pprof/". The mux defaults to
+// http.DefaultServeMux, where net/http/pprof installs its handlers.
+//
+// The registrations are permanent, the function returns an error if one of the
+// profiles was already registered.
+func RegisterProfiles(mux *http.ServeMux, sampleRate float64, profilers ...Profiler) error {
+	return registerProfiles(mux, constSampleRate(sampleRate), profilers)
+}
+
+// RegisterProfiles is like the RegisterProfiles function of the package, but
+// the profiles are scaled by the current sample rate of s.
+func (s *Sampler) RegisterProfiles(mux *http.ServeMux, profilers ...Profiler) error {
+	return registerProfiles(mux, s.SampleRate, profilers)
+}
+
+func registerProfiles(mux *http.ServeMux, sampleRate func() float64, profilers []Profiler) error {
+	if mux == nil {
+		mux = http.DefaultServeMux
+	}
+	names := make(map[string]struct{}, len(profilers))
+	for _, p := range profilers {
+		name := "wasm_" + p.Name()
+		if _, dup := names[name]; dup || pprof.Lookup(name) != nil {
+			return fmt.Errorf("wzprof: the %s profile is already registered", name)
+		}
+		names[name] = struct{}{}
+	}
+	for _, p := range profilers {
+		p, name := p, "wasm_"+p.Name()
+		registeredProfiles.Store(name, struct{}{})
+		pprof.NewProfile(name)
+		mux.Handle("/debug/pprof/"+name, http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+			p.NewHandler(sampleRate()).ServeHTTP(w, r)
+		}))
+	}
+	return nil
+}
@@ -0,0 +1,36 @@
+package wzprof
+
+import (
+	"net/http"
+	"runtime/pprof"
+	"strings"
+	"testing"
+)
+
+func TestRegisterProfiles(t *testing.T) {
+	mux := http.NewServeMux()
+	if err := RegisterProfiles(mux, 1, testProfiler{name: "registered", body: "wasm-profile"}); err != nil {
+		t.Fatal(err)
+	}
+	if pprof.Lookup("wasm_registered") == nil {
+		t.Error("the profile was not added to the registry of runtime/pprof")
+	}
+
+	status, body := serveTestRequest(t, mux, "/debug/pprof/wasm_registered")
+	if status != http.StatusOK || body != "wasm-profile" {
+		t.Errorf("wrong response: status=%d body=%q", status, body)
+	}
+
+	if err := RegisterProfiles(mux, 1, testProfiler{name: "registered"}); err == nil {
+		t.Error("no error when registering a profile twice")
+	}
+	if err := RegisterProfiles(mux, 1, testProfiler{name: "twice"}, testProfiler{name: "twice"}); err == nil {
+		t.Error("no error when registering two profiles with the same name")
+	}
+
+	// The registered profiles are not listed as profiles of the host.
+	_, index := serveTestRequest(t, Handler(1), "/debug/pprof/")
+	if strings.Contains(index, "wasm_registered") {
+		t.Error("the registered profile is listed as a profile of the host")
+	}
+}
@@ -0,0 +1,382 @@
+package wzprof
+
+import (
+	"bytes"
+	"fmt"
+	"math/bits"
+
+	"github.com/tetratelabs/wazero"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+const (
+	rubyExecCoreName = "vm_exec_core"
+	rubyCurrentEC    = "ruby_current_ec"
+)
+
+const (
+	// VALUE is a pointer-sized integer.
+	sizeRubyValue = 4
+	// Flags of the RBasic header of objects.
+	maskRubyType          = 0x1f
+	enumRubyTypeString    = 0x05
+	enumRubyTypeArray     = 0x07
+	flagRubyStringNoEmbed = 1 << 13 // RSTRING_NOEMBED
+	flagRubyArrayEmbed    = 1 << 13 // RARRAY_EMBED_FLAG
+	// Flags of the environment of control frames (ep[VM_ENV_DATA_INDEX_FLAGS]).
+	flagRubyFrameCFrame = 0x0080
+	// Bound on the length of the strings read from memory.
+	rubyMaxStringLength = 4096
+)
+
+// rubyLayout holds the padding of the fields of the CRuby structs read to walk
+// the control frames of the VM. The offsets are read from the DWARF type
+// information of the module since they change between versions of CRuby.
+//
+// Only CRuby 3.2+ is supported, where the length of strings is stored in the
+// RString struct.
+type rubyLayout struct {
+	// rb_execution_context_t.
+	padVMStackInEC     ptr32
+	padVMStackSizeInEC ptr32
+	padCfpInEC         ptr32
+	// rb_control_frame_t.
+	padPcInFrame   ptr32
+	padISeqInFrame ptr32
+	padEpInFrame   ptr32
+	sizeFrame      ptr32
+	// rb_iseq_t.
+	padBodyInISeq ptr32
+	// rb_iseq_constant_body.
+	padEncodedInBody        ptr32
+	padPathobjInBody        ptr32
+	padLabelInBody          ptr32
+	padFirstLinenoInBody    ptr32
+	padInsnsInfoInBody      ptr32
+	padInsnsInfoSizeInBody  ptr32
+	padSuccIndexTableInBody ptr32
+	// iseq_insn_info_entry.
+	padLinenoInInsnInfo ptr32
+	sizeInsnInfo        ptr32
+	// RString.
+	padLenInString   ptr32
+	padPtrInString   ptr32
+	padEmbedInString ptr32
+	// RArray.
+	padPtrInArray   ptr32
+	padEmbedInArray ptr32
+}
+
+// supportedRuby returns the layout of the CRuby structs of the interpreter
+// embedded in the wasm module binary, or nil if the module is not CRuby or was
+// compiled without debug symbols.
+func supportedRuby(wasmbin []byte) *rubyLayout {
+	if !bytes.Contains(wasmCustomSection(wasmbin, "name"), []byte(rubyExecCoreName)) {
+		return nil
+	}
+	p, err := newDwarfParserFromBin(wasmbin)
+	if err != nil {
+		return nil
+	}
+	layout, err := rubyLayoutFromDwarf(p)
+	if err != nil {
+		return nil
+	}
+	return layout
+}
+
+func rubyLayoutFromDwarf(p dwarfparser) (*rubyLayout, error) {
+	s := p.Structs(
+		"rb_execution_context_t",
+		"rb_control_frame_t",
+		"rb_iseq_t",
+		"rb_iseq_constant_body",
+		"iseq_insn_info_entry",
+		"RString",
+		"RArray",
+	)
+	layout := &rubyLayout{
+		padVMStackInEC:          s.offset("rb_execution_context_t", "vm_stack"),
+		padVMStackSizeInEC:      s.offset("rb_execution_context_t", "vm_stack_size"),
+		padCfpInEC:              s.offset("rb_execution_context_t", "cfp"),
+		padPcInFrame:            s.offset("rb_control_frame_t", "pc"),
+		padISeqInFrame:          s.offset("rb_control_frame_t", "iseq"),
+		padEpInFrame:            s.offset("rb_control_frame_t", "ep"),
+		sizeFrame:               s.size("rb_control_frame_t"),
+		padBodyInISeq:           s.offset("rb_iseq_t", "body"),
+		padEncodedInBody:        s.offset("rb_iseq_constant_body", "iseq_encoded"),
+		padPathobjInBody:        s.offset("rb_iseq_constant_body", "location.pathobj"),
+		padLabelInBody:          s.offset("rb_iseq_constant_body", "location.label"),
+		padFirstLinenoInBody:    s.offset("rb_iseq_constant_body", "location.first_lineno"),
+		padInsnsInfoInBody:      s.offset("rb_iseq_constant_body", "insns_info.body"),
+		padInsnsInfoSizeInBody:  s.offset("rb_iseq_constant_body", "insns_info.size"),
+		padSuccIndexTableInBody: s.offset("rb_iseq_constant_body", "insns_info.succ_index_table"),
+		padLinenoInInsnInfo:     s.offset("iseq_insn_info_entry", "line_no"),
+		sizeInsnInfo:            s.size("iseq_insn_info_entry"),
+		padLenInString:          s.offset("RString", "len"),
+		padPtrInString:          s.offset("RString", "as.heap.ptr"),
+		padEmbedInString:        s.offset("RString", "as.embed.ary"),
+		padPtrInArray:           s.offset("RArray", "as.heap.ptr"),
+		padEmbedInArray:         s.offset("RArray", "as.ary"),
+	}
+	return layout, s.err
+}
+
+func prepareRuby(mod wazero.CompiledModule, layout *rubyLayout) (*ruby, error) {
+	p, err := newDwarfparser(mod)
+	if err != nil {
+		return nil, fmt.Errorf("could not build dwarf parser: %w", err)
+	}
+	// The current execution context is a thread-local variable, which is a
+	// regular global in single-threaded wasm modules.
+	ecAddr := dwarfVariableAddress(p, rubyCurrentEC)
+	if ecAddr == 0 {
+		return nil, fmt.Errorf("could not find ruby execution context address")
+	}
+	return &ruby{
+		layout: layout,
+		ecaddr: ptr32(ecAddr),
+	}, nil
+}
+
+// ruby walks the control frames of the CRuby VM, which are stored in an array
+// growing down from the end of the VM stack of the current execution context.
+type ruby struct {
+	layout *rubyLayout
+	ecaddr ptr32 // address of the pointer to the current rb_execution_context_t
+}
+
+func (r *ruby) Locations(fn experimental.InternalFunction, pc experimental.ProgramCounter) (uint64, []location) {
+	call, ok := fn.(rbfuncall)
+	if !ok {
+		// Frames of the wasm stack when the VM is not initialized.
+		return 0, nil
+	}
+
+	loc := location{
+		File:       call.file,
+		Line:       int64(call.line),
+		HumanName:  call.name,
+		StableName: call.file + ":" + call.name,
+	}
+
+	return uint64(call.addr), []location{loc}
+}
+
+func (r *ruby) Stackiter(mod api.Module, def api.FunctionDefinition, wasmsi experimental.StackIterator) experimental.StackIterator {
+	m := mod.Memory()
+	l := r.layout
+	ec := deref[ptr32](m, r.ecaddr)
+	if ec == 0 {
+		// The VM is not initialized yet.
+		return wasmsi
+	}
+	vmstack := deref[ptr32](m, ec+l.padVMStackInEC)
+	vmstacksize := deref[uint32](m, ec+l.padVMStackSizeInEC)
+
+	return &rbstackiter{
+		mem:    m,
+		layout: l,
+		cfp:    deref[ptr32](m, ec+l.padCfpInEC),
+		end:    vmstack + ptr32(vmstacksize*sizeRubyValue),
+	}
+}
+
+type rbstackiter struct {
+	mem     api.Memory
+	layout  *rubyLayout
+	started bool
+	cfp     ptr32 // rb_control_frame_t*
+	end     ptr32 // end of the control frames
+}
+
+func (r *rbstackiter) Next() bool {
+	for {
+		if !r.started {
+			r.started = true
+		} else {
+			r.cfp += r.layout.sizeFrame
+		}
+		if r.cfp == 0 || r.cfp >= r.end {
+			return false
+		}
+		if r.rubyFrame() {
+			return true
+		}
+	}
+}
+
+// rubyFrame reports whether the current control frame executes Ruby code,
+// which is the equivalent of VM_FRAME_RUBYFRAME_P. The frames of methods
+// implemented in C are skipped since they have no source location.
+func (r *rbstackiter) rubyFrame() bool {
+	l := r.layout
+	if deref[ptr32](r.mem, r.cfp+l.padISeqInFrame) == 0 || deref[ptr32](r.mem, r.cfp+l.padPcInFrame) == 0 {
+		return false
+	}
+	ep := deref[ptr32](r.mem, r.cfp+l.padEpInFrame)
+	return ep != 0 && deref[uint32](r.mem, ep)&flagRubyFrameCFrame == 0
+}
+
+func (r *rbstackiter) ProgramCounter() experimental.ProgramCounter {
+	return experimental.ProgramCounter(deref[uint32](r.mem, r.cfp+r.layout.padPcInFrame))
+}
+
+func (r *rbstackiter) Function() experimental.InternalFunction {
+	l := r.layout
+	iseq := deref[ptr32](r.mem, r.cfp+l.padISeqInFrame)
+	body := deref[ptr32](r.mem, iseq+l.padBodyInISeq)
+	pc := deref[ptr32](r.mem, r.cfp+l.padPcInFrame)
+	return rbfuncall{
+		file: rubyString(r.mem, l, deref[ptr32](r.mem, body+l.padPathobjInBody)),
+		name: rubyString(r.mem, l, deref[ptr32](r.mem, body+l.padLabelInBody)),
+		line: rubyLineForPC(r.mem, l, body, pc),
+		addr: uint32(pc),
+	}
+}
+
+// rubyString returns a copy of the Ruby string object v. The path of
+// instruction sequences may be an array holding the path and the real path, in
+// which case the first element is returned.
+func rubyString(m vmem, l *rubyLayout, v ptr32) string {
+	// Special constants (nil, false, immediates) are not heap objects.
+	if v&3 != 0 || v&^4 == 0 {
+		return ""
+	}
+	flags := deref[uint32](m, v)
+	switch flags & maskRubyType {
+	case enumRubyTypeString:
+	case enumRubyTypeArray:
+		elems := v + l.padEmbedInArray
+		if flags&flagRubyArrayEmbed == 0 {
+			elems = deref[ptr32](m, v+l.padPtrInArray)
+		}
+		return rubyString(m, l, deref[ptr32](m, elems))
+	default:
+		return ""
+	}
+
+	length := deref[int32](m, v+l.padLenInString)
+	if length <= 0 || length > rubyMaxStringLength {
+		return ""
+	}
+	p := v + l.padEmbedInString
+	if flags&flagRubyStringNoEmbed != 0 {
+		p = deref[ptr32](m, v+l.padPtrInString)
+	}
+	return string(derefArray[byte](m, p, uint32(length)))
+}
+
+// rubyLineForPC returns the line number of the instruction at pc, which is
+// a re-implementation of rb_vm_get_sourceline.
+func rubyLineForPC(m vmem, l *rubyLayout, body, pc ptr32) int32 {
+	firstlineno := deref[int32](m, body+l.padFirstLinenoInBody)
+	encoded := deref[ptr32](m, body+l.padEncodedInBody)
+	if pc < encoded {
+		return firstlineno
+	}
+	pos := uint32(pc-encoded) / sizeRubyValue
+	if pos > 0 {
+		// The pc points to the next instruction.
+		pos--
+	}
+
+	size := deref[uint32](m, body+l.padInsnsInfoSizeInBody)
+	entries := deref[ptr32](m, body+l.padInsnsInfoInBody)
+	index := uint32(0)
+	switch {
+	case size == 0 || entries == 0:
+		return firstlineno
+	case size > 1:
+		table := deref[ptr32](m, body+l.padSuccIndexTableInBody)
+		if table == 0 {
+			return firstlineno
+		}
+		index = rubySuccIndexLookup(m, table, pos) - 1
+		if index >= size {
+			return firstlineno
+		}
+	}
+	return deref[int32](m, entries+ptr32(index)*l.sizeInsnInfo+l.padLinenoInInsnInfo)
+}
+
+// Layout of the succinct bitvector mapping instruction positions to the index
+// of their entry in the instruction info table, see succ_index_table in
+// CRuby's iseq.c.
+const (
+	rubySuccImmediateTableSize = 54 // bits of the first positions
+	rubySuccImmediatePartSize  = 48 // uint64_t imm_part[54 / 9]
+	rubySuccBlockBits          = 512
+	rubySuccBlockSize          = 80 // struct succ_dict_block
+	rubySuccBlockRanks         = 8  // succ_dict_block.small_block_ranks
+	rubySuccBlockBitsOffset    = 16 // succ_dict_block.bits
+)
+
+// rubySuccIndexLookup returns the number of positions with an entry up to x,
+// which is a re-implementation of succ_index_lookup.
+func rubySuccIndexLookup(m vmem, table ptr32, x uint32) uint32 {
+	if x < rubySuccImmediateTableSize {
+		imm := deref[uint64](m, table+ptr32(x/9*8))
+		return uint32(imm>>(x%9*7)) & 0x7f
+	}
+	x -= rubySuccImmediateTableSize
+	block := table + rubySuccImmediatePartSize + ptr32(x/rubySuccBlockBits*rubySuccBlockSize)
+	bit := x % rubySuccBlockBits
+	small := bit / 64
+
+	rank := deref[uint32](m, block)
+	if small > 0 {
+		ranks := deref[uint64](m, block+rubySuccBlockRanks)
+		rank += uint32(ranks>>((small-1)*9)) & 0x1ff
+	}
+	word := deref[uint64](m, block+rubySuccBlockBitsOffset+ptr32(small*8))
+	rank += uint32(bits.OnesCount64(word << (63 - bit%64)))
+	return rank
+}
+
+// rbfuncall represent a call to a Ruby method, block, or script.
+type rbfuncall struct {
+	file string
+	name string
+	line int32
+	addr uint32
+
+	api.FunctionDefinition // required for WazeroOnly
+}
+
+func (f rbfuncall) Definition() api.FunctionDefinition {
+	return f
+}
+
+func (f rbfuncall) SourceOffsetForPC(pc experimental.ProgramCounter) uint64 {
+	return 0
+}
+
+func (f rbfuncall) ModuleName() string {
+	return "<ruby>"
+}
+
+func (f rbfuncall) Index() uint32 {
+	return 0
+}
+
+func (f rbfuncall) Import() (string, string, bool) {
+	return "", "", false
+}
+
+func (f rbfuncall) ExportNames() []string {
+	return nil
+}
+
+func (f rbfuncall) Name() string {
+	return f.name
+}
+
+func (f rbfuncall) DebugName() string {
+	return f.name
+}
+
+func (f rbfuncall) GoFunction() interface{} {
+	return nil
+}
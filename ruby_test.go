@@ -0,0 +1,74 @@
+package wzprof
+
+import (
+	"testing"
+
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestRubySuccIndexLookup(t *testing.T) {
+	const table = 1024
+	memory := wazerotest.NewMemory(wazerotest.PageSize)
+	memory.WriteUint64Le(table, 1|2<<7|3<<14)
+	memory.WriteUint32Le(table+rubySuccImmediatePartSize, 10)
+	memory.WriteUint64Le(table+rubySuccImmediatePartSize+rubySuccBlockRanks, 5)
+	memory.WriteUint64Le(table+rubySuccImmediatePartSize+rubySuccBlockBitsOffset+8, 0b1011)
+
+	tests := []struct {
+		pos  uint32
+		rank uint32
+	}{
+		{pos: 0, rank: 1},
+		{pos: 1, rank: 2},
+		{pos: 2, rank: 3},
+		{pos: rubySuccImmediateTableSize + 64, rank: 16},
+		{pos: rubySuccImmediateTableSize + 65, rank: 17},
+		{pos: rubySuccImmediateTableSize + 67, rank: 18},
+	}
+
+	for _, test := range tests {
+		if rank := rubySuccIndexLookup(memory, table, test.pos); rank != test.rank {
+			t.Errorf("position %d: wrong rank: want=%d got=%d", test.pos, test.rank, rank)
+		}
+	}
+}
+
+func TestRubyString(t *testing.T) {
+	layout := &rubyLayout{
+		padLenInString:   8,
+		padPtrInString:   12,
+		padEmbedInString: 12,
+		padPtrInArray:    16,
+		padEmbedInArray:  8,
+	}
+	memory := wazerotest.NewMemory(wazerotest.PageSize)
+
+	// Embedded string.
+	memory.WriteUint32Le(2000, enumRubyTypeString)
+	memory.WriteUint32Le(2008, 5)
+	memory.WriteString(2012, "hello")
+	// Heap string.
+	memory.WriteUint32Le(2100, enumRubyTypeString|flagRubyStringNoEmbed)
+	memory.WriteUint32Le(2108, 5)
+	memory.WriteUint32Le(2112, 2200)
+	memory.WriteString(2200, "world")
+	// Embedded array holding the heap string.
+	memory.WriteUint32Le(2300, enumRubyTypeArray|flagRubyArrayEmbed)
+	memory.WriteUint32Le(2308, 2100)
+
+	tests := []struct {
+		value ptr32
+		str   string
+	}{
+		{value: 2000, str: "hello"},
+		{value: 2100, str: "world"},
+		{value: 2300, str: "world"},
+		{value: 4, str: ""}, // nil
+	}
+
+	for _, test := range tests {
+		if str := rubyString(memory, layout, test.value); str != test.str {
+			t.Errorf("value %d: wrong string: want=%q got=%q", test.value, test.str, str)
+		}
+	}
+}
@@ -2,7 +2,11 @@ package wzprof
 
 import (
 	"context"
+	"errors"
+	"fmt"
 	"math"
+	"sync/atomic"
+	"time"
 
 	"github.com/tetratelabs/wazero/api"
 	"github.com/tetratelabs/wazero/experimental"
@@ -21,20 +25,17 @@ func Flag(flag *bool, factory experimental.FunctionListenerFactory) experimental
 		if lstn == nil {
 			return nil
 		}
-		flagged := &flaggedFunctionListener{
+		return &flaggedFunctionListener{
 			flag: flag,
 			lstn: lstn,
 		}
-		flagged.stack.bits = flagged.bits[:]
-		return flagged
 	})
 }
 
 type flaggedFunctionListener struct {
-	flag  *bool
-	bits  [1]uint64
-	stack bitstack
-	lstn  experimental.FunctionListener
+	flag   *bool
+	stacks threadLocal[bitstack]
+	lstn   experimental.FunctionListener
 }
 
 func (s *flaggedFunctionListener) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, params []uint64, stack experimental.StackIterator) {
@@ -45,17 +46,17 @@ func (s *flaggedFunctionListener) Before(ctx context.Context, mod api.Module, de
 		bit = 1
 	}
 
-	s.stack.push(bit)
+	s.stacks.get(mod).push(bit)
 }
 
 func (s *flaggedFunctionListener) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, results []uint64) {
-	if s.stack.pop() != 0 {
+	if s.stacks.get(mod).pop() != 0 {
 		s.lstn.After(ctx, mod, def, results)
 	}
 }
 
 func (s *flaggedFunctionListener) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, err error) {
-	if s.stack.pop() != 0 {
+	if s.stacks.get(mod).pop() != 0 {
 		s.lstn.Abort(ctx, mod, def, err)
 	}
 }
@@ -75,22 +76,219 @@ func Sample(sampleRate float64, factory experimental.FunctionListenerFactory) ex
 	if sampleRate >= 1 {
 		return factory
 	}
-	cycle := uint32(math.Ceil(1 / sampleRate))
+	return NewSampler(sampleRate).Sample(factory)
+}
+
+// Sampler is like Sample, but the sample rate of the function listeners that
+// it creates may be changed while the module is running, for example to
+// increase the sampling temporarily when investigating an issue on a live
+// service.
+//
+// The profiles scale their values by the sample rate at the time they are
+// built, so the values recorded before changing the rate are not accurate
+// anymore. Delta profiles (e.g. requested with the "seconds" query parameter
+// of the pprof handlers) remain accurate when the rate is not changed while
+// they are being recorded.
+type Sampler struct {
+	rate  atomic.Uint64 // bits of the float64 sample rate
+	limit atomic.Uint64 // bits of the float64 sample rate set by the user
+	cycle atomic.Uint32
+	// Time spent in the sampled listeners (in nanoseconds), only measured
+	// while Adapt is running or when the metrics of s are exposed. The
+	// overhead is reset by Adapt, the total is cumulative.
+	adaptive atomic.Bool
+	metrics  atomic.Bool
+	overhead atomic.Int64
+	total    atomic.Int64
+}
+
+// NewSampler returns a Sampler configured with the given sample rate. The
+// function panics if the sample rate is not valid (see SetSampleRate).
+func NewSampler(sampleRate float64) *Sampler {
+	s := new(Sampler)
+	if err := s.SetSampleRate(sampleRate); err != nil {
+		panic(err)
+	}
+	return s
+}
+
+// SampleRate returns the current sample rate of s.
+func (s *Sampler) SampleRate() float64 {
+	return math.Float64frombits(s.rate.Load())
+}
+
+// SetSampleRate changes the sample rate of the function listeners created by
+// s. The sample rate must be greater than zero, a rate of one or more disables
+// sampling.
+func (s *Sampler) SetSampleRate(sampleRate float64) error {
+	if !(sampleRate > 0) {
+		return fmt.Errorf("invalid sample rate: %g", sampleRate)
+	}
+	if sampleRate > 1 {
+		sampleRate = 1
+	}
+	s.limit.Store(math.Float64bits(sampleRate))
+	s.setRate(sampleRate)
+	return nil
+}
+
+func (s *Sampler) setRate(sampleRate float64) {
+	s.rate.Store(math.Float64bits(sampleRate))
+	s.cycle.Store(uint32(math.Ceil(1 / sampleRate)))
+}
+
+const (
+	// Interval at which Adapt measures the overhead of the listeners.
+	adaptInterval = time.Second
+	// Lowest sample rate that Adapt lowers the rate to.
+	minAdaptiveSampleRate = 1e-4
+)
+
+// measured returns true if the time spent in the sampled listeners must be
+// measured.
+func (s *Sampler) measured() bool {
+	return s.adaptive.Load() || s.metrics.Load()
+}
+
+func (s *Sampler) addOverhead(duration int64) {
+	s.overhead.Add(duration)
+	s.total.Add(duration)
+}
+
+// Adapt continuously adjusts the sample rate of s to keep the time spent in
+// the sampled function listeners under a fraction of the elapsed time (e.g.
+// 0.02 to keep the overhead of the profilers under 2% of the CPU time), which
+// caps the cost of profiling when the load of the program increases.
+//
+// The sample rate is lowered when the overhead exceeds the budget, and raised
+// again when the load drops, up to the rate configured by NewSampler or
+// SetSampleRate. Profiles recorded while the rate changes are less accurate
+// since the values are scaled by the rate at the time the profiles are built.
+//
+// The function blocks until ctx is canceled.
+func (s *Sampler) Adapt(ctx context.Context, budget float64) error {
+	if !(budget > 0) {
+		return fmt.Errorf("invalid overhead budget: %g", budget)
+	}
+	if !s.adaptive.CompareAndSwap(false, true) {
+		return errors.New("sampler already adapting")
+	}
+	defer func() {
+		s.adaptive.Store(false)
+		s.setRate(math.Float64frombits(s.limit.Load()))
+	}()
+
+	ticker := time.NewTicker(adaptInterval)
+	defer ticker.Stop()
+
+	last := nanotime()
+	s.overhead.Store(0)
+	for {
+		select {
+		case <-ticker.C:
+		case <-ctx.Done():
+			return nil
+		}
+		now := nanotime()
+		s.adapt(budget, s.overhead.Swap(0), now-last)
+		last = now
+	}
+}
+
+// adapt adjusts the sample rate of s after spending the overhead time in the
+// listeners during the elapsed time (in nanoseconds).
+func (s *Sampler) adapt(budget float64, overhead, elapsed int64) {
+	if elapsed <= 0 {
+		return
+	}
+	factor := 2.0 // raise the rate gradually when there were no calls
+	if overhead > 0 {
+		factor = math.Min(budget/(float64(overhead)/float64(elapsed)), 2)
+	}
+	// Small variations of the overhead below the budget are ignored, which
+	// avoids changing the rate (and the scale of profiles) constantly.
+	if factor >= 1 && factor < 1.25 {
+		return
+	}
+	rate := s.SampleRate() * factor
+	rate = math.Max(rate, minAdaptiveSampleRate)
+	rate = math.Min(rate, math.Float64frombits(s.limit.Load()))
+	s.setRate(rate)
+}
+
+// Sample returns a function listener factory which creates listeners where
+// calls to their Before/After methods are sampled at the rate of s.
+//
+// Memory profilers which must observe every call (see InuseMemory and
+// UnsampledMemory) are returned as is.
+func (s *Sampler) Sample(factory experimental.FunctionListenerFactory) experimental.FunctionListenerFactory {
+	if mem, ok := factory.(*MemoryProfiler); ok && mem.unsampled() {
+		return factory
+	}
 	return experimental.FunctionListenerFactoryFunc(func(def api.FunctionDefinition) experimental.FunctionListener {
 		lstn := factory.NewFunctionListener(def)
 		if lstn == nil {
 			return nil
 		}
-		sampled := &sampledFunctionListener{
-			cycle: cycle,
-			count: cycle,
-			lstn:  lstn,
+		// The return of the entry function ends the start of the module,
+		// it is observed on every call.
+		if l, ok := lstn.(startupListener); ok {
+			if l.lstn != nil {
+				l.lstn = &sampledFunctionListener{sampler: s, lstn: l.lstn}
+			}
+			return l
+		}
+		return &sampledFunctionListener{
+			sampler: s,
+			lstn:    lstn,
 		}
-		sampled.stack.bits = sampled.bits[:]
-		return sampled
 	})
 }
 
+// Compose returns a function listener factory combining the listeners created
+// by the factories with experimental.MultiFunctionListenerFactory, in the order
+// of the arguments, which allows applications to insert the profilers in their
+// own chain of function listeners.
+//
+// The listeners of the profilers of this package are sampled by sampler, except
+// those which must observe every call: the CPU profiler in wall-clock mode, the
+// memory profiler tracking the objects in use or configured with
+// UnsampledMemory, the trap and call count profilers, the tracer and the
+// memory timeline. The other factories, including those of the application,
+// are not sampled. A nil sampler disables sampling. Nil factories are ignored.
+func Compose(sampler *Sampler, factories ...experimental.FunctionListenerFactory) experimental.FunctionListenerFactory {
+	composed := make([]experimental.FunctionListenerFactory, 0, len(factories))
+	for _, factory := range factories {
+		if factory == nil {
+			continue
+		}
+		if sampler != nil && sampled(factory) {
+			factory = sampler.Sample(factory)
+		}
+		composed = append(composed, factory)
+	}
+	return experimental.MultiFunctionListenerFactory(composed...)
+}
+
+// sampled returns true if the listeners of factory may be sampled by Compose.
+func sampled(factory experimental.FunctionListenerFactory) bool {
+	switch f := factory.(type) {
+	case *CPUProfiler:
+		// The wall-clock profiler must observe all calls to know the
+		// current call stacks of the program.
+		return !f.wall
+	case *MemoryProfiler:
+		return !f.unsampled()
+	case *BlockProfiler, *MutexProfiler, *GrowProfiler, *StackProfiler, *SyscallProfiler, *GCProfiler:
+		return true
+	default:
+		// The trap and call count profilers, the tracer and the timeline
+		// record every call, and the listeners of the application are
+		// left as they are.
+		return false
+	}
+}
+
 type emptyFunctionListenerFactory struct{}
 
 func (emptyFunctionListenerFactory) NewFunctionListener(api.FunctionDefinition) experimental.FunctionListener {
@@ -98,33 +296,56 @@ func (emptyFunctionListenerFactory) NewFunctionListener(api.FunctionDefinition)
 }
 
 type sampledFunctionListener struct {
+	sampler *Sampler
+	threads threadLocal[sampledThread]
+	lstn    experimental.FunctionListener
+}
+
+// sampledThread is the state of a sampled function listener for a thread of
+// the program. The count starts at zero, which is equivalent to starting at the
+// cycle length since it is decremented before being compared.
+type sampledThread struct {
 	count uint32
-	cycle uint32
-	bits  [1]uint64
 	stack bitstack
-	lstn  experimental.FunctionListener
 }
 
 func (s *sampledFunctionListener) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, params []uint64, stack experimental.StackIterator) {
+	t := s.threads.get(mod)
 	bit := uint(0)
 
-	if s.count--; s.count == 0 {
-		s.count = s.cycle
-		s.lstn.Before(ctx, mod, def, params, stack)
+	// The count is also reset when the cycle was shortened by a change of
+	// the sample rate.
+	if cycle := s.sampler.cycle.Load(); t.count == 0 || t.count > cycle {
+		t.count = cycle
+	}
+	if t.count--; t.count == 0 {
+		if s.sampler.measured() {
+			start := nanotime()
+			s.lstn.Before(ctx, mod, def, params, stack)
+			s.sampler.addOverhead(nanotime() - start)
+		} else {
+			s.lstn.Before(ctx, mod, def, params, stack)
+		}
 		bit = 1
 	}
 
-	s.stack.push(bit)
+	t.stack.push(bit)
 }
 
 func (s *sampledFunctionListener) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, results []uint64) {
-	if s.stack.pop() != 0 {
-		s.lstn.After(ctx, mod, def, results)
+	if s.threads.get(mod).stack.pop() != 0 {
+		if s.sampler.measured() {
+			start := nanotime()
+			s.lstn.After(ctx, mod, def, results)
+			s.sampler.addOverhead(nanotime() - start)
+		} else {
+			s.lstn.After(ctx, mod, def, results)
+		}
 	}
 }
 
 func (s *sampledFunctionListener) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, err error) {
-	if s.stack.pop() != 0 {
+	if s.threads.get(mod).stack.pop() != 0 {
 		s.lstn.Abort(ctx, mod, def, err)
 	}
 }
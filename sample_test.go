@@ -2,7 +2,9 @@ package wzprof
 
 import (
 	"context"
+	"math"
 	"testing"
+	"time"
 
 	"github.com/tetratelabs/wazero/api"
 	"github.com/tetratelabs/wazero/experimental"
@@ -85,6 +87,57 @@ func TestSampledFunctionListener(t *testing.T) {
 	}
 }
 
+func TestSamplerSetSampleRate(t *testing.T) {
+	module := wazerotest.NewModule(nil,
+		wazerotest.NewFunction(func(ctx context.Context, mod api.Module) {}),
+	)
+
+	n := 0
+	f := func(context.Context, api.Module, api.FunctionDefinition, []uint64, experimental.StackIterator) { n++ }
+
+	sampler := NewSampler(0.1)
+	factory := sampler.Sample(experimental.FunctionListenerFactoryFunc(
+		func(def api.FunctionDefinition) experimental.FunctionListener {
+			return experimental.FunctionListenerFunc(f)
+		},
+	))
+
+	function := module.Function(0).Definition()
+	listener := factory.NewFunctionListener(function)
+	ctx := context.Background()
+
+	for _, test := range []struct {
+		rate float64
+		want int
+	}{
+		{0.1, 2},
+		{0.5, 10},
+		{1, 20},
+		{0.25, 5},
+	} {
+		if err := sampler.SetSampleRate(test.rate); err != nil {
+			t.Fatal(err)
+		}
+		n = 0
+		for i := 0; i < 20; i++ {
+			listener.Before(ctx, module, function, nil, nil)
+			listener.After(ctx, module, function, nil)
+		}
+		if n != test.want {
+			t.Errorf("rate %g: wrong number of called to sampled listener: want=%d got=%d", test.rate, test.want, n)
+		}
+	}
+
+	for _, rate := range []float64{0, -1, math.NaN()} {
+		if err := sampler.SetSampleRate(rate); err == nil {
+			t.Errorf("rate %g: no error returned", rate)
+		}
+	}
+	if rate := sampler.SampleRate(); rate != 0.25 {
+		t.Errorf("sample rate changed by invalid values: want=0.25 got=%g", rate)
+	}
+}
+
 func BenchmarkSampledFunctionListener(b *testing.B) {
 	benchmarkFunctionListener(b,
 		Sample(0.1, experimental.FunctionListenerFactoryFunc(
@@ -97,3 +150,78 @@ func BenchmarkSampledFunctionListener(b *testing.B) {
 		)),
 	)
 }
+
+func TestSamplerAdapt(t *testing.T) {
+	const second = int64(time.Second)
+
+	sampler := NewSampler(0.5)
+	for _, test := range []struct {
+		overhead int64
+		rate     float64
+	}{
+		// 4% overhead for a 2% budget halves the rate.
+		{second / 25, 0.25},
+		// 1.8% overhead is close enough to the budget.
+		{second * 18 / 1000, 0.25},
+		// 1% overhead doubles the rate.
+		{second / 100, 0.5},
+		// The rate is never raised above the configured one.
+		{0, 0.5},
+		// High overhead lowers the rate to the minimum.
+		{second * 1000, minAdaptiveSampleRate},
+	} {
+		sampler.adapt(0.02, test.overhead, second)
+		if rate := sampler.SampleRate(); math.Abs(rate-test.rate) > 1e-9 {
+			t.Errorf("overhead %dns: wrong sample rate: want=%g got=%g", test.overhead, test.rate, rate)
+		}
+	}
+}
+
+func TestCompose(t *testing.T) {
+	module := wazerotest.NewModule(nil,
+		wazerotest.NewFunction(func(ctx context.Context, mod api.Module) {}),
+	)
+
+	var calls []string
+	factory := func(name string) experimental.FunctionListenerFactory {
+		return experimental.FunctionListenerFactoryFunc(func(def api.FunctionDefinition) experimental.FunctionListener {
+			return experimental.FunctionListenerFunc(func(context.Context, api.Module, api.FunctionDefinition, []uint64, experimental.StackIterator) {
+				calls = append(calls, name)
+			})
+		})
+	}
+
+	// The listeners of the application are not sampled, and are called in
+	// the order of the factories.
+	composed := Compose(NewSampler(0.1), factory("a"), nil, factory("b"))
+	function := module.Function(0).Definition()
+	listener := composed.NewFunctionListener(function)
+	ctx := context.Background()
+	for i := 0; i < 10; i++ {
+		listener.Before(ctx, module, function, nil, nil)
+		listener.After(ctx, module, function, nil)
+	}
+	if len(calls) != 20 || calls[0] != "a" || calls[1] != "b" {
+		t.Errorf("wrong calls of the composed listeners: %q", calls)
+	}
+
+	p := ProfilingFor(nil, AllowUnprepared(true))
+	for _, test := range []struct {
+		factory experimental.FunctionListenerFactory
+		sampled bool
+	}{
+		{p.CPUProfiler(), true},
+		{p.CPUProfiler(WallTime(true)), false},
+		{p.MemoryProfiler(), true},
+		{p.MemoryProfiler(UnsampledMemory(true)), false},
+		{p.StackProfiler(), true},
+		{p.TrapProfiler(), false},
+		{p.CallProfiler(), false},
+		{p.Tracer(), false},
+		{factory("a"), false},
+	} {
+		if got := sampled(test.factory); got != test.sampled {
+			t.Errorf("wrong sampling of %T: want=%t got=%t", test.factory, test.sampled, got)
+		}
+	}
+}
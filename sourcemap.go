@@ -0,0 +1,191 @@
+package wzprof
+
+import (
+	"encoding/json"
+	"fmt"
+	"sort"
+	"strings"
+
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+// sourceMappingURL is the name of the custom section holding the location of
+// the source map of a module, see
+// https://github.com/WebAssembly/tool-conventions/blob/main/Debugging.md.
+const sourceMappingURL = "sourceMappingURL"
+
+// sourceMapSymbolizer resolves program counters to source locations using a
+// source map (revision 3), which is the debug information emitted by
+// AssemblyScript instead of DWARF.
+//
+// Source maps of wasm modules have a single line, the generated columns are
+// the offsets of instructions in the module binary. Source maps do not record
+// inlining nor the names of functions, which are taken from the module.
+type sourceMapSymbolizer struct {
+	// Offset of the code section in the module, the program counters are
+	// resolved to offsets relative to the code section.
+	codeOffset uint64
+	sources    []string
+	mappings   []sourceMapping
+}
+
+type sourceMapping struct {
+	offset uint64
+	source int // -1 if the mapping has no source location
+	line   int64
+	column int64
+}
+
+// sourceMapFile is the JSON representation of source maps.
+type sourceMapFile struct {
+	Version    int      `json:"version"`
+	SourceRoot string   `json:"sourceRoot"`
+	Sources    []string `json:"sources"`
+	Mappings   string   `json:"mappings"`
+}
+
+func newSourceMapSymbolizer(wasmbin []byte, location string) (*sourceMapSymbolizer, error) {
+	codeOffset := wasmCodeSectionOffset(wasmbin)
+	if codeOffset < 0 {
+		return nil, fmt.Errorf("sourcemap: code section not found in module")
+	}
+	b, err := readLocation(location)
+	if err != nil {
+		return nil, fmt.Errorf("sourcemap: loading %s: %w", location, err)
+	}
+	s, err := parseSourceMap(b)
+	if err != nil {
+		return nil, fmt.Errorf("sourcemap: parsing %s: %w", location, err)
+	}
+	s.codeOffset = uint64(codeOffset)
+	return s, nil
+}
+
+func parseSourceMap(b []byte) (*sourceMapSymbolizer, error) {
+	var f sourceMapFile
+	if err := json.Unmarshal(b, &f); err != nil {
+		return nil, err
+	}
+	if f.Version != 3 {
+		return nil, fmt.Errorf("unsupported version %d", f.Version)
+	}
+	mappings, err := parseSourceMappings(f.Mappings, len(f.Sources))
+	if err != nil {
+		return nil, err
+	}
+
+	root := f.SourceRoot
+	if root != "" && !strings.HasSuffix(root, "/") {
+		root += "/"
+	}
+	sources := make([]string, len(f.Sources))
+	for i, source := range f.Sources {
+		sources[i] = root + source
+	}
+	return &sourceMapSymbolizer{sources: sources, mappings: mappings}, nil
+}
+
+// parseSourceMappings decodes the "mappings" field of a source map, which is
+// a list of segments encoded with base64 VLQ. Each segment has 1, 4, or 5
+// fields relative to the previous segment: the generated column, the index of
+// the source, the line and column in the source, and the index of the name.
+func parseSourceMappings(mappings string, numSources int) ([]sourceMapping, error) {
+	var out []sourceMapping
+	var source, line, column int64
+	var fields []int64
+	var err error
+
+	for _, group := range strings.Split(mappings, ";") {
+		var offset int64
+		for _, segment := range strings.Split(group, ",") {
+			if segment == "" {
+				continue
+			}
+			if fields, err = decodeVLQ(fields[:0], segment); err != nil {
+				return nil, err
+			}
+			if n := len(fields); n != 1 && n != 4 && n != 5 {
+				return nil, fmt.Errorf("invalid segment %q", segment)
+			}
+
+			offset += fields[0]
+			m := sourceMapping{offset: uint64(offset), source: -1}
+			if len(fields) > 1 {
+				source += fields[1]
+				line += fields[2]
+				column += fields[3]
+				if source < 0 || source >= int64(numSources) {
+					return nil, fmt.Errorf("invalid source index %d", source)
+				}
+				m.source, m.line, m.column = int(source), line, column
+			}
+			out = append(out, m)
+		}
+	}
+
+	sort.SliceStable(out, func(i, j int) bool { return out[i].offset < out[j].offset })
+	return out, nil
+}
+
+const base64VLQ = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"
+
+// decodeVLQ appends the values of a base64 VLQ sequence to values.
+func decodeVLQ(values []int64, s string) ([]int64, error) {
+	var value int64
+	var shift uint
+	for i := 0; i < len(s); i++ {
+		digit := strings.IndexByte(base64VLQ, s[i])
+		if digit < 0 {
+			return nil, fmt.Errorf("invalid base64 VLQ character %q", s[i])
+		}
+		value |= int64(digit&31) << shift
+		if digit&32 != 0 {
+			if shift += 5; shift > 60 {
+				return nil, fmt.Errorf("base64 VLQ value overflow in %q", s)
+			}
+			continue
+		}
+		// The least significant bit holds the sign.
+		if value&1 != 0 {
+			value = -(value >> 1)
+		} else {
+			value >>= 1
+		}
+		values = append(values, value)
+		value, shift = 0, 0
+	}
+	if shift != 0 {
+		return nil, fmt.Errorf("truncated base64 VLQ value in %q", s)
+	}
+	return values, nil
+}
+
+func (s *sourceMapSymbolizer) Locations(fn experimental.InternalFunction, pc experimental.ProgramCounter) (uint64, []location) {
+	offset := fn.SourceOffsetForPC(pc)
+	if offset == 0 {
+		return offset, nil
+	}
+	loc, ok := s.lookup(s.codeOffset + offset)
+	if !ok {
+		return offset, nil
+	}
+	return offset, []location{loc}
+}
+
+// lookup returns the source location of the instruction at the given offset
+// of the module, which is described by the last mapping at or before it.
+func (s *sourceMapSymbolizer) lookup(offset uint64) (location, bool) {
+	i := sort.Search(len(s.mappings), func(i int) bool {
+		return s.mappings[i].offset > offset
+	}) - 1
+	if i < 0 || s.mappings[i].source < 0 {
+		return location{}, false
+	}
+	m := s.mappings[i]
+	// Lines and columns are zero-based in source maps.
+	return location{
+		File:   s.sources[m.source],
+		Line:   m.line + 1,
+		Column: m.column + 1,
+	}, true
+}
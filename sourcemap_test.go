@@ -0,0 +1,66 @@
+package wzprof
+
+import "testing"
+
+func TestDecodeVLQ(t *testing.T) {
+	values, err := decodeVLQ(nil, "AACDgBhB")
+	if err != nil {
+		t.Fatal(err)
+	}
+	want := []int64{0, 0, 1, -1, 16, -16}
+	if len(values) != len(want) {
+		t.Fatalf("wrong number of values: want=%v got=%v", want, values)
+	}
+	for i := range want {
+		if values[i] != want[i] {
+			t.Errorf("wrong value at index %d: want=%d got=%d", i, want[i], values[i])
+		}
+	}
+
+	for _, invalid := range []string{"g", "A!"} {
+		if _, err := decodeVLQ(nil, invalid); err == nil {
+			t.Errorf("expected error decoding %q", invalid)
+		}
+	}
+}
+
+func TestSourceMapLookup(t *testing.T) {
+	s, err := parseSourceMap([]byte(`{
+		"version": 3,
+		"sourceRoot": "assembly",
+		"sources": ["index.ts"],
+		"mappings": "gBAAA,EACA,EAAC,C"
+	}`))
+	if err != nil {
+		t.Fatal(err)
+	}
+
+	tests := []struct {
+		offset uint64
+		found  bool
+		line   int64
+		column int64
+	}{
+		{offset: 10, found: false},
+		{offset: 16, found: true, line: 1, column: 1},
+		{offset: 17, found: true, line: 1, column: 1},
+		{offset: 19, found: true, line: 2, column: 1},
+		{offset: 20, found: true, line: 2, column: 2},
+		{offset: 21, found: false},
+	}
+
+	for _, test := range tests {
+		loc, found := s.lookup(test.offset)
+		if found != test.found {
+			t.Errorf("offset %d: want found=%t got found=%t", test.offset, test.found, found)
+			continue
+		}
+		if !found {
+			continue
+		}
+		if loc.File != "assembly/index.ts" || loc.Line != test.line || loc.Column != test.column {
+			t.Errorf("offset %d: wrong location: want=assembly/index.ts:%d:%d got=%s:%d:%d",
+				test.offset, test.line, test.column, loc.File, loc.Line, loc.Column)
+		}
+	}
+}
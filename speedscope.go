@@ -0,0 +1,113 @@
+package wzprof
+
+import (
+	"encoding/json"
+	"fmt"
+	"io"
+
+	"github.com/google/pprof/profile"
+)
+
+// WriteSpeedscope writes prof to w in the JSON file format of speedscope, so
+// it can be opened directly at https://www.speedscope.app.
+//
+// Each sample type of the profile is converted to a separate speedscope
+// profile, the one displayed first being the default sample type.
+func WriteSpeedscope(w io.Writer, prof *profile.Profile) error {
+	index := defaultSampleIndex(prof)
+	if index < 0 {
+		return fmt.Errorf("profile has no sample types")
+	}
+
+	file := &speedscopeFile{
+		Schema:             "https://www.speedscope.app/file-format-schema.json",
+		Exporter:           "wzprof",
+		ActiveProfileIndex: index,
+		Profiles:           make([]speedscopeProfile, len(prof.SampleType)),
+	}
+
+	frames := make(map[*profile.Function]int, len(prof.Function))
+	stacks := make([][]int, len(prof.Sample))
+
+	for i, sample := range prof.Sample {
+		functions := sampleFunctions(sample)
+		stack := make([]int, len(functions))
+		for j, fn := range functions {
+			frame, ok := frames[fn]
+			if !ok {
+				frame = len(file.Shared.Frames)
+				frames[fn] = frame
+				file.Shared.Frames = append(file.Shared.Frames, speedscopeFrame{
+					Name: fn.Name,
+					File: fn.Filename,
+					Line: fn.StartLine,
+				})
+			}
+			stack[j] = frame
+		}
+		stacks[i] = stack
+	}
+
+	for i, sampleType := range prof.SampleType {
+		p := &file.Profiles[i]
+		p.Type = "sampled"
+		p.Name = sampleType.Type
+		p.Unit = speedscopeUnit(sampleType.Unit)
+		p.Samples = [][]int{}
+		p.Weights = []int64{}
+
+		for j, sample := range prof.Sample {
+			if value := sample.Value[i]; value != 0 {
+				p.Samples = append(p.Samples, stacks[j])
+				p.Weights = append(p.Weights, value)
+				p.EndValue += value
+			}
+		}
+	}
+
+	if file.Shared.Frames == nil {
+		file.Shared.Frames = []speedscopeFrame{}
+	}
+	return json.NewEncoder(w).Encode(file)
+}
+
+// speedscopeUnit converts pprof units to the set of units supported by
+// speedscope.
+func speedscopeUnit(unit string) string {
+	switch unit {
+	case "nanoseconds", "microseconds", "milliseconds", "seconds", "bytes":
+		return unit
+	default:
+		return "none"
+	}
+}
+
+// Types below are a subset of the speedscope file format schema, see
+// https://github.com/jlfwong/speedscope/blob/main/src/lib/file-format-spec.ts
+type speedscopeFile struct {
+	Schema             string              `json:"$schema"`
+	Shared             speedscopeShared    `json:"shared"`
+	Profiles           []speedscopeProfile `json:"profiles"`
+	ActiveProfileIndex int                 `json:"activeProfileIndex"`
+	Exporter           string              `json:"exporter"`
+}
+
+type speedscopeShared struct {
+	Frames []speedscopeFrame `json:"frames"`
+}
+
+type speedscopeFrame struct {
+	Name string `json:"name"`
+	File string `json:"file,omitempty"`
+	Line int64  `json:"line,omitempty"`
+}
+
+type speedscopeProfile struct {
+	Type       string  `json:"type"`
+	Name       string  `json:"name"`
+	Unit       string  `json:"unit"`
+	StartValue int64   `json:"startValue"`
+	EndValue   int64   `json:"endValue"`
+	Samples    [][]int `json:"samples"`
+	Weights    []int64 `json:"weights"`
+}
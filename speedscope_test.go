@@ -0,0 +1,43 @@
+package wzprof
+
+import (
+	"bytes"
+	"encoding/json"
+	"reflect"
+	"testing"
+)
+
+func TestWriteSpeedscope(t *testing.T) {
+	b := new(bytes.Buffer)
+	if err := WriteSpeedscope(b, newTestProfile()); err != nil {
+		t.Fatal(err)
+	}
+
+	var file speedscopeFile
+	if err := json.Unmarshal(b.Bytes(), &file); err != nil {
+		t.Fatal(err)
+	}
+
+	if file.ActiveProfileIndex != 1 {
+		t.Errorf("active profile mismatch: want=1 got=%d", file.ActiveProfileIndex)
+	}
+
+	var names []string
+	for _, frame := range file.Shared.Frames {
+		names = append(names, frame.Name)
+	}
+	if want := []string{"main", "a", "b", "c"}; !reflect.DeepEqual(names, want) {
+		t.Errorf("frames mismatch: want=%v got=%v", want, names)
+	}
+
+	cpu := file.Profiles[1]
+	if cpu.Unit != "nanoseconds" || cpu.EndValue != 60 {
+		t.Errorf("profile mismatch: want=(nanoseconds,60) got=(%s,%d)", cpu.Unit, cpu.EndValue)
+	}
+	if want := [][]int{{0, 1, 2}, {0, 1}, {0, 3}}; !reflect.DeepEqual(cpu.Samples, want) {
+		t.Errorf("samples mismatch: want=%v got=%v", want, cpu.Samples)
+	}
+	if want := []int64{10, 20, 30}; !reflect.DeepEqual(cpu.Weights, want) {
+		t.Errorf("weights mismatch: want=%v got=%v", want, cpu.Weights)
+	}
+}
@@ -0,0 +1,183 @@
+package wzprof
+
+import (
+	"context"
+	"net/http"
+	"sync"
+	"time"
+
+	"github.com/google/pprof/profile"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+// StackProfiler is the implementation of a profiler recording the deepest
+// stacks reached by each call path of a WebAssembly module, which helps
+// diagnose stack overflows in runtimes with constrained stack sizes before
+// they cause the module to trap.
+//
+// The profiler generates samples of two types:
+// - "depth" records the maximum number of frames on the call stack.
+// - "stack_space" records the maximum size of the shadow stack (in bytes).
+//
+// The shadow stack is the region of linear memory where compilers like LLVM
+// allocate the variables which do not fit in the wasm value stack, delimited
+// by the __stack_pointer global. Its size is measured on entry to functions,
+// from the highest address the stack pointer was observed at on each thread.
+// Goroutines of Go programs each have their own stack, so only the depth is
+// recorded for them.
+//
+// The values are the maximums observed since the creation of the profiler.
+type StackProfiler struct {
+	p      *Profiling
+	mutex  sync.Mutex
+	depths stackCounterMap
+	stacks threadLocal[stackThread]
+	start  time.Time
+}
+
+// stackThread holds the state of the stack profiler for a single thread.
+type stackThread struct {
+	base  uint32 // highest address of the shadow stack pointer
+	trace stackTrace
+}
+
+func newStackProfiler(p *Profiling) *StackProfiler {
+	return &StackProfiler{
+		p:      p,
+		depths: make(stackCounterMap),
+		start:  time.Now(),
+	}
+}
+
+// Name returns "stackdepth".
+func (p *StackProfiler) Name() string {
+	return "stackdepth"
+}
+
+// Desc returns a description of the stack depth profile.
+func (p *StackProfiler) Desc() string {
+	return profileDescriptions[p.Name()]
+}
+
+// Count returns the number of call stacks recorded in p.
+func (p *StackProfiler) Count() int {
+	p.mutex.Lock()
+	n := p.depths.len()
+	p.mutex.Unlock()
+	return n
+}
+
+// SampleType returns the set of value types present in samples recorded by the
+// profiler.
+func (p *StackProfiler) SampleType() []*profile.ValueType {
+	return []*profile.ValueType{
+		{Type: "depth", Unit: "count"},
+		{Type: "stack_space", Unit: "bytes"},
+	}
+}
+
+// NewProfile builds a profile of the deepest stacks observed since the
+// profiler was created.
+//
+// The values are maximums, they are not scaled by the sample rate.
+func (p *StackProfiler) NewProfile(sampleRate float64) *profile.Profile {
+	p.mutex.Lock()
+	samples := make(stackCounterMap, len(p.depths))
+	for k, sc := range p.depths {
+		samples[k] = &stackCounter{stack: sc.stack, value: sc.value}
+	}
+	p.mutex.Unlock()
+
+	prof := buildProfile(p.p, samples, p.start, time.Since(p.start), p.SampleType(),
+		[]float64{1, 1},
+	)
+	return p.p.endProfile(p.Name(), prof)
+}
+
+// NewHandler returns a http handler allowing the profiler to be exposed on a
+// pprof-compatible http endpoint.
+//
+// Maximums cannot be subtracted, so the handler ignores the "seconds" query
+// parameter and always responds with the maximums since the profiler was
+// created.
+func (p *StackProfiler) NewHandler(sampleRate float64) http.Handler {
+	return http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		serveProfile(w, r, p.NewProfile(sampleRate))
+	})
+}
+
+// NewFunctionListener returns a function listener recording the depth of the
+// stack on entry to the function passed as argument, or nil if the function is
+// not instrumented (see Filter).
+func (p *StackProfiler) NewFunctionListener(def api.FunctionDefinition) experimental.FunctionListener {
+	if !p.p.instrumented(def) {
+		return nil
+	}
+	return profilingListener{p.p, stackListener{p}}
+}
+
+// stackPointer returns the value of the shadow stack pointer of mod. By
+// convention, the __stack_pointer global is the first global of modules
+// compiled with LLVM.
+func (p *StackProfiler) stackPointer(mod api.Module) (uint32, bool) {
+	if p.p.lang == golang {
+		return 0, false
+	}
+	imod, ok := mod.(experimental.InternalModule)
+	if !ok || imod.NumGlobal() == 0 {
+		return 0, false
+	}
+	g := imod.Global(0)
+	if g.Type() != api.ValueTypeI32 {
+		return 0, false
+	}
+	return uint32(g.Get()), true
+}
+
+type stackListener struct{ *StackProfiler }
+
+func (p stackListener) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64, si experimental.StackIterator) {
+	t := p.stacks.get(mod)
+	t.trace = makeStackTrace(ctx, t.trace, p.p.threads.lookup(mod), si, p.p.maxFrames)
+
+	depth := int64(t.trace.depth)
+	space := int64(0)
+	if sp, ok := p.stackPointer(mod); ok {
+		if sp > t.base {
+			t.base = sp
+		}
+		space = int64(t.base - sp)
+	}
+
+	trace, depth, ok := p.p.hookSample(p.Name(), t.trace, depth)
+	if !ok {
+		return
+	}
+	p.mutex.Lock()
+	sc := p.depths.lookup(trace)
+	if depth > sc.value[0] {
+		sc.value[0] = depth
+	}
+	if space > sc.value[1] {
+		sc.value[1] = space
+	}
+	p.depths.compact(p.p.maxStacks, maxStackCounters)
+	p.mutex.Unlock()
+}
+
+// maxStackCounters is the merge function of compact for counters recording
+// the maximum of their values.
+func maxStackCounters(dst, src *stackCounter) {
+	for i, v := range src.value {
+		if v > dst.value[i] {
+			dst.value[i] = v
+		}
+	}
+}
+
+func (p stackListener) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64) {
+}
+
+func (p stackListener) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
+}
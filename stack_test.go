@@ -0,0 +1,94 @@
+package wzprof
+
+import (
+	"context"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestStackProfilerDepth(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).StackProfiler()
+
+	f0 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f0.FunctionName = "f0"
+	f1 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f1.FunctionName = "f1"
+
+	module := wazerotest.NewModule(nil, f0, f1)
+	ctx := context.Background()
+
+	def := f1.Definition()
+	listener := p.NewFunctionListener(def)
+	for _, stack := range [][]experimental.StackFrame{
+		{{Function: f1}, {Function: f0}},
+		{{Function: f1}, {Function: f1}, {Function: f0}},
+		{{Function: f1}, {Function: f0}},
+	} {
+		listener.Before(ctx, module, def, nil, experimental.NewStackIterator(stack...))
+		listener.After(ctx, module, def, nil)
+	}
+
+	prof := p.NewProfile(1)
+	if len(prof.Sample) != 2 {
+		t.Fatalf("wrong number of samples: want=2 got=%d", len(prof.Sample))
+	}
+	for _, sample := range prof.Sample {
+		if want := int64(len(sample.Location)); sample.Value[0] != want {
+			t.Errorf("wrong stack depth: want=%d got=%d", want, sample.Value[0])
+		}
+		if sample.Value[1] != 0 {
+			t.Errorf("wrong stack space: want=0 got=%d", sample.Value[1])
+		}
+	}
+}
+
+func TestStackProfilerMaxFrames(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true), MaxFrames(3)).StackProfiler()
+
+	f0 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f0.FunctionName = "f0"
+	f1 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f1.FunctionName = "f1"
+
+	module := wazerotest.NewModule(nil, f0, f1)
+	ctx := context.Background()
+
+	def := f1.Definition()
+	listener := p.NewFunctionListener(def)
+	for _, stack := range [][]experimental.StackFrame{
+		{{Function: f1}, {Function: f1}, {Function: f1}, {Function: f1}, {Function: f0}},
+		{{Function: f1}, {Function: f1}, {Function: f1}, {Function: f0}},
+		{{Function: f1}, {Function: f1}, {Function: f0}},
+	} {
+		listener.Before(ctx, module, def, nil, experimental.NewStackIterator(stack...))
+		listener.After(ctx, module, def, nil)
+	}
+
+	// The two deepest stacks have the same innermost frames, they are merged
+	// in the same truncated sample.
+	prof := p.NewProfile(1)
+	if len(prof.Sample) != 2 {
+		t.Fatalf("wrong number of samples: want=2 got=%d", len(prof.Sample))
+	}
+	for _, sample := range prof.Sample {
+		root := sample.Location[len(sample.Location)-1].Line[0].Function.Name
+		switch root {
+		case truncatedFrameName:
+			if len(sample.Location) != 4 {
+				t.Errorf("wrong number of frames of the truncated stack: want=4 got=%d", len(sample.Location))
+			}
+			if sample.Value[0] != 5 {
+				t.Errorf("wrong depth of the truncated stack: want=5 got=%d", sample.Value[0])
+			}
+		case "f0":
+			if sample.Value[0] != 3 {
+				t.Errorf("wrong depth of the complete stack: want=3 got=%d", sample.Value[0])
+			}
+		default:
+			t.Errorf("unexpected root frame: %s", root)
+		}
+	}
+}
@@ -0,0 +1,285 @@
+package wzprof
+
+import (
+	"fmt"
+	"net/http"
+	"sort"
+	"strings"
+	"sync"
+
+	"github.com/google/pprof/profile"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+// ProfileStore accumulates the profiles of profilers which do not outlive the
+// module instances that they record, for example when a program restarts its
+// module frequently and creates new profilers for each instance. The profiles
+// recorded during the lifetime of each instance are merged in the store, which
+// serves the combined profiles with the same endpoints as Handler.
+//
+// The zero value is an empty store ready to use. ProfileStore values are safe
+// to use concurrently from multiple goroutines.
+type ProfileStore struct {
+	mutex    sync.Mutex
+	profiles map[string]*profile.Profile
+}
+
+// NewProfileStore returns a new empty store.
+func NewProfileStore() *ProfileStore {
+	return new(ProfileStore)
+}
+
+// Accumulate merges the profiles recorded by the profilers into the store,
+// under the names of the profilers. It is intended to be called once for each
+// set of profilers, when the module instance that they record terminates.
+//
+// The CPU profilers are stopped and the samples recorded since their profile
+// was started are accumulated, the other profilers contribute the values that
+// they recorded since they were created. The memory in use reported by the
+// accumulated memory profiles is the one of the last module instance, since the
+// memory of terminated instances is released, and the stack depths are the
+// maximums of all the instances.
+//
+// The state that the profilers keep for the instances which were closed is
+// released, so the store can accumulate the profiles of instances restarted
+// for as long as the program runs without retaining the previous instances.
+func (s *ProfileStore) Accumulate(sampleRate float64, profilers ...Profiler) error {
+	defer func() {
+		for _, p := range profilers {
+			if profiling := profilingOf(p); profiling != nil {
+				profiling.releaseClosed()
+			}
+		}
+	}()
+
+	for _, p := range profilers {
+		var prof *profile.Profile
+		switch p := p.(type) {
+		case *CPUProfiler:
+			prof = p.StopProfile(sampleRate)
+		case interface {
+			NewProfile(sampleRate float64) *profile.Profile
+		}:
+			prof = p.NewProfile(sampleRate)
+		}
+		if prof == nil {
+			continue
+		}
+		_, maximums := p.(*StackProfiler)
+		if err := s.add(p.Name(), prof, maximums); err != nil {
+			return fmt.Errorf("accumulating %s profile: %w", p.Name(), err)
+		}
+	}
+	return nil
+}
+
+// profilingOf returns the Profiling that created p, or nil if p is not one of
+// the profilers of the package.
+func profilingOf(p Profiler) *Profiling {
+	switch p := p.(type) {
+	case *CPUProfiler:
+		return p.p
+	case *MemoryProfiler:
+		return p.p
+	case *BlockProfiler:
+		return p.p
+	case *MutexProfiler:
+		return p.p
+	case *SyscallProfiler:
+		return p.p
+	case *GCProfiler:
+		return p.p
+	case *CallProfiler:
+		return p.p
+	case *GrowProfiler:
+		return p.p
+	case *StackProfiler:
+		return p.p
+	case *TrapProfiler:
+		return p.p
+	}
+	return nil
+}
+
+// Add merges prof into the profile of the given name, which is created if the
+// store has no profile of this name yet. The sample types of the profile must
+// match those of the profiles already added under the name.
+func (s *ProfileStore) Add(name string, prof *profile.Profile) error {
+	return s.add(name, prof, false)
+}
+
+func (s *ProfileStore) add(name string, prof *profile.Profile, maximums bool) error {
+	s.mutex.Lock()
+	defer s.mutex.Unlock()
+
+	prev := s.profiles[name]
+	if prev == nil {
+		if s.profiles == nil {
+			s.profiles = make(map[string]*profile.Profile)
+		}
+		s.profiles[name] = prof.Copy()
+		return nil
+	}
+
+	// The memory in use by the previous instances was released when they
+	// terminated.
+	for i, st := range prev.SampleType {
+		if strings.HasPrefix(st.Type, "inuse_") {
+			for _, sample := range prev.Sample {
+				sample.Value[i] = 0
+			}
+		}
+	}
+
+	var merged *profile.Profile
+	var err error
+	if maximums {
+		merged, err = mergeMaximums(prev, prof)
+	} else {
+		merged, err = profile.Merge([]*profile.Profile{prev, prof})
+	}
+	if err != nil {
+		return err
+	}
+	s.profiles[name] = merged
+	return nil
+}
+
+// Profile returns a copy of the profile accumulated under the given name, or
+// nil if the store has no profile of this name.
+func (s *ProfileStore) Profile(name string) *profile.Profile {
+	s.mutex.Lock()
+	defer s.mutex.Unlock()
+	if prof := s.profiles[name]; prof != nil {
+		return prof.Copy()
+	}
+	return nil
+}
+
+// Names returns the sorted names of the profiles of the store.
+func (s *ProfileStore) Names() []string {
+	s.mutex.Lock()
+	names := make([]string, 0, len(s.profiles))
+	for name := range s.profiles {
+		names = append(names, name)
+	}
+	s.mutex.Unlock()
+	sort.Strings(names)
+	return names
+}
+
+// Handler returns a http handler serving the profiles accumulated in the store,
+// like Handler does for the profilers of a module.
+//
+// The profiles are served with the values accumulated when the request is
+// received, the "seconds" query parameter is ignored.
+func (s *ProfileStore) Handler() http.Handler {
+	return http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		names := s.Names()
+		profilers := make([]Profiler, len(names))
+		for i, name := range names {
+			profilers[i] = storedProfiler{s, name}
+		}
+		newHandler(constSampleRate(1), nil, []moduleProfilers{{profilers: profilers}}).ServeHTTP(w, r)
+	})
+}
+
+// mergeMaximums merges the profiles p0 and p1, keeping the maximum value of the
+// samples of the same call stacks instead of adding them.
+func mergeMaximums(p0, p1 *profile.Profile) (*profile.Profile, error) {
+	maximums := make(map[string][]int64)
+	for _, prof := range []*profile.Profile{p0, p1} {
+		for _, sample := range prof.Sample {
+			key := sampleKey(sample)
+			values := maximums[key]
+			if values == nil {
+				maximums[key] = append([]int64(nil), sample.Value...)
+				continue
+			}
+			for i, v := range sample.Value {
+				if v > values[i] {
+					values[i] = v
+				}
+			}
+		}
+	}
+	merged, err := profile.Merge([]*profile.Profile{p0, p1})
+	if err != nil {
+		return nil, err
+	}
+	for _, sample := range merged.Sample {
+		copy(sample.Value, maximums[sampleKey(sample)])
+	}
+	return merged, nil
+}
+
+// sampleKey returns a string identifying the call stack and labels of sample
+// across profiles, which profile.Merge uses to combine samples.
+func sampleKey(sample *profile.Sample) string {
+	b := new(strings.Builder)
+	for _, loc := range sample.Location {
+		for _, line := range loc.Line {
+			if line.Function != nil {
+				fmt.Fprintf(b, "%s:%s", line.Function.Name, line.Function.Filename)
+			}
+			fmt.Fprintf(b, ":%d;", line.Line)
+		}
+		b.WriteByte('|')
+	}
+	keys := make([]string, 0, len(sample.Label))
+	for k := range sample.Label {
+		keys = append(keys, k)
+	}
+	sort.Strings(keys)
+	for _, k := range keys {
+		fmt.Fprintf(b, "%s=%q,", k, sample.Label[k])
+	}
+	return b.String()
+}
+
+// storedProfiler exposes a profile of a ProfileStore to the http handlers
+// serving the profiles of profilers.
+type storedProfiler struct {
+	s    *ProfileStore
+	name string
+}
+
+var _ Profiler = storedProfiler{}
+
+func (p storedProfiler) Name() string { return p.name }
+
+func (p storedProfiler) Desc() string { return profileDescriptions[p.name] }
+
+func (p storedProfiler) Count() int {
+	p.s.mutex.Lock()
+	defer p.s.mutex.Unlock()
+	if prof := p.s.profiles[p.name]; prof != nil {
+		return len(prof.Sample)
+	}
+	return 0
+}
+
+func (p storedProfiler) SampleType() []*profile.ValueType {
+	p.s.mutex.Lock()
+	defer p.s.mutex.Unlock()
+	if prof := p.s.profiles[p.name]; prof != nil {
+		return prof.SampleType
+	}
+	return nil
+}
+
+func (p storedProfiler) NewHandler(sampleRate float64) http.Handler {
+	return http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		prof := p.s.Profile(p.name)
+		if prof == nil {
+			serveError(w, http.StatusNotFound, fmt.Sprintf("Unknown profile: %s", p.name))
+			return
+		}
+		serveProfile(w, r, prof)
+	})
+}
+
+func (p storedProfiler) NewFunctionListener(api.FunctionDefinition) experimental.FunctionListener {
+	return nil
+}
@@ -0,0 +1,142 @@
+package wzprof
+
+import (
+	"context"
+	"net/http/httptest"
+	"reflect"
+	"strings"
+	"testing"
+
+	"github.com/google/pprof/profile"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+// storedValues returns the values of the samples of prof indexed by the name of
+// their leaf function.
+func storedValues(prof *profile.Profile) map[string][]int64 {
+	values := make(map[string][]int64)
+	for _, s := range prof.Sample {
+		values[s.Location[0].Line[0].Function.Name] = s.Value
+	}
+	return values
+}
+
+func TestProfileStore(t *testing.T) {
+	s := NewProfileStore()
+	for i := 0; i < 2; i++ {
+		if err := s.Add("profile", newTestProfile()); err != nil {
+			t.Fatal(err)
+		}
+	}
+	if s.Profile("heap") != nil {
+		t.Error("unexpected profile in the store")
+	}
+
+	want := map[string][]int64{"b": {2, 20}, "a": {4, 40}, "c": {6, 60}}
+	if got := storedValues(s.Profile("profile")); !reflect.DeepEqual(got, want) {
+		t.Errorf("wrong accumulated values: want=%v got=%v", want, got)
+	}
+
+	// The memory in use is the one of the last profile added.
+	inuse := func(alloc, inuse int64) *profile.Profile {
+		prof := newTestProfile()
+		prof.SampleType[0] = &profile.ValueType{Type: "alloc_space", Unit: "bytes"}
+		prof.SampleType[1] = &profile.ValueType{Type: "inuse_space", Unit: "bytes"}
+		for _, sample := range prof.Sample {
+			sample.Value = []int64{alloc, inuse}
+		}
+		return prof
+	}
+	for _, prof := range []*profile.Profile{inuse(10, 5), inuse(20, 1)} {
+		if err := s.Add("heap", prof); err != nil {
+			t.Fatal(err)
+		}
+	}
+	want = map[string][]int64{"b": {30, 1}, "a": {30, 1}, "c": {30, 1}}
+	if got := storedValues(s.Profile("heap")); !reflect.DeepEqual(got, want) {
+		t.Errorf("wrong accumulated memory values: want=%v got=%v", want, got)
+	}
+
+	// Maximums are not added.
+	deep := newTestProfile()
+	deep.Sample[2].Value = []int64{1, 100}
+	for _, prof := range []*profile.Profile{newTestProfile(), deep} {
+		if err := s.add("stackdepth", prof, true); err != nil {
+			t.Fatal(err)
+		}
+	}
+	want = map[string][]int64{"b": {1, 10}, "a": {2, 20}, "c": {3, 100}}
+	if got := storedValues(s.Profile("stackdepth")); !reflect.DeepEqual(got, want) {
+		t.Errorf("wrong accumulated maximums: want=%v got=%v", want, got)
+	}
+
+	if names := s.Names(); !reflect.DeepEqual(names, []string{"heap", "profile", "stackdepth"}) {
+		t.Errorf("wrong names: %q", names)
+	}
+}
+
+func TestProfileStoreHandler(t *testing.T) {
+	s := NewProfileStore()
+	if err := s.Add("profile", newTestProfile()); err != nil {
+		t.Fatal(err)
+	}
+	handler := s.Handler()
+
+	w := httptest.NewRecorder()
+	handler.ServeHTTP(w, httptest.NewRequest("GET", "/debug/pprof/", nil))
+	if body := w.Body.String(); !strings.Contains(body, "href='profile'") || strings.Contains(body, "seconds=5") {
+		t.Errorf("wrong index page:\n%s", body)
+	}
+
+	w = httptest.NewRecorder()
+	handler.ServeHTTP(w, httptest.NewRequest("GET", "/debug/pprof/profile", nil))
+	prof, err := profile.Parse(w.Body)
+	if err != nil {
+		t.Fatal(err)
+	}
+	want := map[string][]int64{"b": {1, 10}, "a": {2, 20}, "c": {3, 30}}
+	if got := storedValues(prof); !reflect.DeepEqual(got, want) {
+		t.Errorf("wrong served values: want=%v got=%v", want, got)
+	}
+}
+
+func TestProfileStoreRestarts(t *testing.T) {
+	s := NewProfileStore()
+	p := ProfilingFor(nil, AllowUnprepared(true))
+	ctx := context.Background()
+
+	const restarts = 50
+	for i := 0; i < restarts; i++ {
+		cpu := p.CPUProfiler(HostTime(true))
+		fn := wazerotest.NewFunction(func(context.Context, api.Module) {})
+		def := fn.Definition()
+		module := wazerotest.NewModule(nil, fn)
+		listener := cpu.NewFunctionListener(def)
+
+		cpu.StartProfile()
+		listener.Before(ctx, module, def, nil, experimental.NewStackIterator(experimental.StackFrame{Function: fn}))
+		listener.After(ctx, module, def, nil)
+		if err := module.Close(ctx); err != nil {
+			t.Fatal(err)
+		}
+		if err := s.Accumulate(1, cpu); err != nil {
+			t.Fatal(err)
+		}
+
+		// The instance is released by Accumulate, rather than when the
+		// next one starts.
+		if n := syncMapLen(&p.threads.ids); n != 0 {
+			t.Fatalf("restart %d: closed instances are retained: %d", i, n)
+		}
+	}
+
+	calls := int64(0)
+	for _, sample := range s.Profile("profile").Sample {
+		calls += sample.Value[0]
+	}
+	if calls != restarts {
+		t.Errorf("wrong accumulated calls: want=%d got=%d", restarts, calls)
+	}
+}
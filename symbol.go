@@ -0,0 +1,123 @@
+package wzprof
+
+import (
+	"bufio"
+	"bytes"
+	"fmt"
+	"io"
+	"net/http"
+	"strconv"
+	"strings"
+)
+
+// addressSymbolizer is implemented by the symbolizers which can resolve the
+// name of the function at an address of the profiles without knowing which
+// function of the module was called, as required by the /symbol endpoint of
+// the pprof handlers.
+type addressSymbolizer interface {
+	FunctionName(address uint64) string
+}
+
+func (d *dwarfmapper) FunctionName(address uint64) string {
+	for _, sr := range d.subprograms {
+		if sr.Range[0] <= address && address <= sr.Range[1] {
+			human, _ := d.namesForSubprogram(sr.Subprogram.Entry, sr.Subprogram)
+			return human
+		}
+	}
+	return ""
+}
+
+// FunctionName resolves the address with the symbolizer cached by c when it
+// can, or looks up the address in the locations resolved so far otherwise,
+// which are the addresses of the profiles.
+func (c *symbolCache) FunctionName(address uint64) string {
+	if s, ok := c.symbols.(addressSymbolizer); ok {
+		return s.FunctionName(address)
+	}
+	c.mutex.RLock()
+	defer c.mutex.RUnlock()
+	for _, e := range c.entries {
+		if e.address == address && len(e.locations) > 0 && e.locations[0].HumanName != "" {
+			return e.locations[0].HumanName
+		}
+	}
+	return ""
+}
+
+// profilingProfiler is implemented by the profilers of the package, the pprof
+// handlers use the Profiling they were created from to serve the /symbol and
+// /cmdline endpoints of the guest.
+type profilingProfiler interface {
+	profiling() *Profiling
+}
+
+func (p *CPUProfiler) profiling() *Profiling    { return p.p }
+func (p *MemoryProfiler) profiling() *Profiling { return p.p }
+func (p *delayProfiler) profiling() *Profiling  { return p.p }
+func (p *GrowProfiler) profiling() *Profiling   { return p.p }
+func (p *StackProfiler) profiling() *Profiling  { return p.p }
+func (p *TrapProfiler) profiling() *Profiling   { return p.p }
+func (p *CallProfiler) profiling() *Profiling   { return p.p }
+
+// profiling returns the Profiling that the profilers of m were created from,
+// or nil if none of them are profilers of the package.
+func (m *moduleProfilers) profiling() *Profiling {
+	for _, p := range m.profilers {
+		if pp, ok := p.(profilingProfiler); ok {
+			return pp.profiling()
+		}
+	}
+	return nil
+}
+
+// serveCmdline responds with the command line of the guest configured with
+// CommandLine, in the format of net/http/pprof: the arguments are separated by
+// NUL bytes.
+func serveCmdline(w http.ResponseWriter, p *Profiling) {
+	w.Header().Set("X-Content-Type-Options", "nosniff")
+	w.Header().Set("Content-Type", "text/plain; charset=utf-8")
+	_, _ = io.WriteString(w, strings.Join(p.args, "\x00"))
+}
+
+// serveSymbol implements the symbol lookup protocol of pprof, like
+// net/http/pprof.Symbol does for the host. The request holds addresses of the
+// profiles separated by '+' characters, in its body for POST requests or in
+// its query string otherwise, and the response has a line with the name of the
+// function at each of the addresses that could be resolved.
+func serveSymbol(w http.ResponseWriter, r *http.Request, p *Profiling) {
+	w.Header().Set("X-Content-Type-Options", "nosniff")
+	w.Header().Set("Content-Type", "text/plain; charset=utf-8")
+
+	// A non-zero number of symbols tells pprof that the endpoint supports
+	// symbol lookups.
+	var buf bytes.Buffer
+	fmt.Fprintf(&buf, "num_symbols: 1\n")
+
+	var b *bufio.Reader
+	if r.Method == http.MethodPost {
+		b = bufio.NewReader(r.Body)
+	} else {
+		b = bufio.NewReader(strings.NewReader(r.URL.RawQuery))
+	}
+	symbols, _ := p.symbols.(addressSymbolizer)
+	for {
+		word, err := b.ReadSlice('+')
+		if err == nil {
+			word = word[:len(word)-1] // trim the '+'
+		}
+		address, _ := strconv.ParseUint(string(word), 0, 64)
+		if address != 0 && symbols != nil {
+			if name := symbols.FunctionName(address); name != "" {
+				fmt.Fprintf(&buf, "%#x %s\n", address, name)
+			}
+		}
+		if err != nil {
+			if err != io.EOF {
+				fmt.Fprintf(&buf, "reading request: %v\n", err)
+			}
+			break
+		}
+	}
+	_, _ = w.Write(buf.Bytes())
+}
@@ -0,0 +1,61 @@
+package wzprof
+
+import (
+	"github.com/google/pprof/profile"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+// SyscallProfiler is the implementation of a profiler recording the calls of a
+// WebAssembly module to the WASI host functions, for example to find the code
+// paths issuing thousands of small fd_write calls.
+//
+// The call stacks of the samples end with the WASI function that was called,
+// so the profile can be broken down by system call as well as by caller.
+//
+// The profiler generates samples of two types:
+// - "calls" counts the number of calls to WASI functions.
+// - "time" records the time spent in calls to WASI functions (in nanoseconds).
+//
+// The values are cumulative since the creation of the profiler.
+type SyscallProfiler struct {
+	delayProfiler
+}
+
+func newSyscallProfiler(p *Profiling) *SyscallProfiler {
+	s := &SyscallProfiler{}
+	s.init(p, "syscalls")
+	s.types = []*profile.ValueType{
+		{Type: "calls", Unit: "count"},
+		{Type: "time", Unit: "nanoseconds"},
+	}
+	return s
+}
+
+// Name returns "syscalls".
+func (p *SyscallProfiler) Name() string {
+	return "syscalls"
+}
+
+// Desc returns a description of the WASI system call profile.
+func (p *SyscallProfiler) Desc() string {
+	return profileDescriptions[p.Name()]
+}
+
+// NewFunctionListener returns a function listener recording the calls to the
+// function passed as argument if it is a WASI host function, and nil
+// otherwise.
+func (p *SyscallProfiler) NewFunctionListener(def api.FunctionDefinition) experimental.FunctionListener {
+	if def.GoFunction() == nil || !isWASI(def.ModuleName()) {
+		return nil
+	}
+	return profilingListener{p.p, delayListener{&p.delayProfiler}}
+}
+
+func isWASI(moduleName string) bool {
+	switch moduleName {
+	case "wasi_snapshot_preview1", "wasi_unstable":
+		return true
+	}
+	return false
+}
@@ -0,0 +1,59 @@
+package wzprof
+
+import (
+	"context"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestSyscallProfiler(t *testing.T) {
+	currentTime := int64(0)
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).SyscallProfiler()
+	p.time = func() int64 { return currentTime }
+
+	fdWrite := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	fdWrite.FunctionName = "fd_write"
+	main := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	main.FunctionName = "main"
+
+	module := wazerotest.NewModule(nil, fdWrite, main)
+	stack := []experimental.StackFrame{{Function: fdWrite, PC: 1}, {Function: main, PC: 2}}
+	ctx := context.Background()
+
+	listener := delayListener{&p.delayProfiler}
+	def := fdWrite.Definition()
+	for i := int64(0); i < 3; i++ {
+		currentTime = 10 * i
+		listener.Before(ctx, module, def, nil, experimental.NewStackIterator(stack...))
+		currentTime = 10*i + 5
+		listener.After(ctx, module, def, nil)
+	}
+
+	prof := p.NewProfile(1)
+	if len(prof.SampleType) != 2 || prof.SampleType[0].Type != "calls" || prof.SampleType[1].Type != "time" {
+		t.Fatalf("wrong sample types: %v", prof.SampleType)
+	}
+	if len(prof.Sample) != 1 {
+		t.Fatalf("wrong number of samples: want=1 got=%d", len(prof.Sample))
+	}
+	if calls, time := prof.Sample[0].Value[0], prof.Sample[0].Value[1]; calls != 3 || time != 15 {
+		t.Errorf("wrong values: want=[3 15] got=[%d %d]", calls, time)
+	}
+}
+
+func TestIsWASI(t *testing.T) {
+	for name, want := range map[string]bool{
+		"wasi_snapshot_preview1": true,
+		"wasi_unstable":          true,
+		"wasi":                   false,
+		"env":                    false,
+	} {
+		if got := isWASI(name); got != want {
+			t.Errorf("isWASI(%q): want=%t got=%t", name, want, got)
+		}
+	}
+}
@@ -0,0 +1,283 @@
+package wzprof
+
+import (
+	"sort"
+	"sync"
+	"sync/atomic"
+
+	"github.com/tetratelabs/wazero/api"
+)
+
+// Modules compiled with wasi-threads run each thread in its own instance of the
+// module, all sharing the same memory. Function listeners are shared by all the
+// instances, so the state that they maintain between calls to Before and After
+// must be stored per thread. The module instance passed to the listeners is
+// used to identify the thread that the call happens on.
+
+// The same applies to the instances of a compiled module instantiated multiple
+// times, for example by a pool of workers: each instance is a distinct thread
+// of the profiles.
+//
+// Programs may create and close instances for as long as they run, the state
+// of the instances which were closed is released when a new instance is first
+// observed, so it does not accumulate (see threadLocal).
+
+// threadIDs assigns numeric identifiers to the threads of a program, in the
+// order that they are first observed. Identifiers start at 1, zero means that
+// the thread is unknown.
+//
+// The identifiers of closed instances are reused by the next instances, the
+// lowest first, so the number of threads of the profiles is bound by the
+// number of instances running at the same time rather than the number of
+// instances ever created (e.g. a worker recycled by a pool takes the
+// identifier of the worker it replaces). When unique is set, the identifiers
+// are not reused so the samples of each instance keep its name (see
+// LabelInstances).
+type threadIDs struct {
+	ids    sync.Map // api.Module => uint32
+	names  sync.Map // uint32 => string
+	next   atomic.Uint32
+	mutex  sync.Mutex
+	free   []uint32 // identifiers of closed instances, sorted in decreasing order
+	unique bool
+}
+
+func (t *threadIDs) lookup(mod api.Module) uint32 {
+	if id, ok := t.ids.Load(mod); ok {
+		return id.(uint32)
+	}
+	t.mutex.Lock()
+	defer t.mutex.Unlock()
+	if id, ok := t.ids.Load(mod); ok {
+		return id.(uint32)
+	}
+	t.release()
+
+	var id uint32
+	if n := len(t.free); n > 0 {
+		id, t.free = t.free[n-1], t.free[:n-1]
+	} else {
+		id = t.next.Add(1)
+	}
+	t.names.Store(id, mod.Name())
+	t.ids.Store(mod, id)
+	return id
+}
+
+// releaseClosed removes the instances which were closed, their identifiers
+// are given to the next instances.
+func (t *threadIDs) releaseClosed() {
+	t.mutex.Lock()
+	t.release()
+	t.mutex.Unlock()
+}
+
+// release is releaseClosed with the mutex held.
+func (t *threadIDs) release() {
+	released := false
+	t.ids.Range(func(k, v any) bool {
+		if isClosed(k) {
+			t.ids.Delete(k)
+			if !t.unique {
+				t.free = append(t.free, v.(uint32))
+				released = true
+			}
+		}
+		return true
+	})
+	if released {
+		sort.Slice(t.free, func(i, j int) bool { return t.free[i] > t.free[j] })
+	}
+}
+
+// isClosed reports whether k, a key of the maps of module instances, is an
+// instance which was closed.
+func isClosed(k any) bool {
+	mod, _ := k.(api.Module)
+	return mod != nil && mod.IsClosed()
+}
+
+// name returns the name of the module instance of the thread, which is empty
+// for anonymous instances.
+func (t *threadIDs) name(id uint32) string {
+	name, _ := t.names.Load(id)
+	s, _ := name.(string)
+	return s
+}
+
+// releaseClosed releases the state of p for the module instances which were
+// closed, without waiting for the next instance to be observed.
+func (p *Profiling) releaseClosed() {
+	p.threads.releaseClosed()
+	p.labelCache.releaseClosed(nil)
+}
+
+// threadLocal holds values of type T for each thread of a program.
+//
+// Values are lazily created on first access. The values of the instances which
+// were closed are removed when the value of a new instance is created, so they
+// do not retain the instances (and their memory) for the lifetime of the
+// listeners.
+type threadLocal[T any] struct {
+	values sync.Map // api.Module => *T
+	mutex  sync.Mutex
+}
+
+func (t *threadLocal[T]) get(mod api.Module) *T {
+	return t.load(mod, nil)
+}
+
+// load is like get, but calls release with the values of the closed instances
+// that it removes, for example to retain the samples that they hold.
+func (t *threadLocal[T]) load(mod api.Module, release func(*T)) *T {
+	if v, ok := t.values.Load(mod); ok {
+		return v.(*T)
+	}
+	v, loaded := t.values.LoadOrStore(mod, new(T))
+	if !loaded {
+		t.releaseClosed(release)
+	}
+	return v.(*T)
+}
+
+func (t *threadLocal[T]) releaseClosed(release func(*T)) {
+	t.mutex.Lock()
+	defer t.mutex.Unlock()
+	t.values.Range(func(k, _ any) bool {
+		if isClosed(k) {
+			if v, ok := t.values.LoadAndDelete(k); ok && release != nil {
+				release(v.(*T))
+			}
+		}
+		return true
+	})
+}
+
+// stackCounterShards holds the samples recorded by a profiler in a separate
+// stackCounterMap for each thread of a program. Listeners only lock the shard
+// of the thread they are called on, so the instances of a module running
+// concurrently do not contend on a lock shared by all the threads. The shards
+// are merged when profiles are built.
+type stackCounterShards struct {
+	shards threadLocal[stackCounterShard]
+	// Number of samples observed and of call stacks merged into their
+	// callers by compact, exposed by MetricsHandler.
+	observed atomic.Int64
+	merged   atomic.Int64
+}
+
+type stackCounterShard struct {
+	mutex  sync.Mutex
+	counts stackCounterMap
+}
+
+// observe records a sample of value val for the call stack st on the shard of
+// the thread that mod runs on. The number of call stacks of the shard is bound
+// to limit (see stackCounterMap.compact).
+func (s *stackCounterShards) observe(mod api.Module, st stackTrace, val int64, limit int) {
+	shard := s.shards.load(mod, s.retire)
+	shard.mutex.Lock()
+	if shard.counts == nil {
+		shard.counts = make(stackCounterMap)
+	}
+	shard.counts.observe(st, val)
+	merged := shard.counts.compact(limit, addStackCounters)
+	shard.mutex.Unlock()
+
+	s.observed.Add(1)
+	if merged > 0 {
+		s.merged.Add(int64(merged))
+	}
+}
+
+// retire moves the samples of the shard of a closed instance to the shard of
+// the nil module, which no thread of the program runs on, so they remain in the
+// profiles after the shard is released.
+func (s *stackCounterShards) retire(shard *stackCounterShard) {
+	shard.mutex.Lock()
+	counts := shard.counts
+	shard.counts = nil
+	shard.mutex.Unlock()
+	if len(counts) == 0 {
+		return
+	}
+
+	// The shard is not created with get, which would release the closed
+	// instances while they are being released.
+	v, _ := s.shards.values.LoadOrStore(nil, new(stackCounterShard))
+	dst := v.(*stackCounterShard)
+	dst.mutex.Lock()
+	if dst.counts == nil {
+		dst.counts = counts
+	} else {
+		for k, sc := range counts {
+			if c := dst.counts[k]; c != nil {
+				addStackCounters(c, sc)
+			} else {
+				dst.counts[k] = sc
+			}
+		}
+	}
+	dst.mutex.Unlock()
+}
+
+// merge returns the samples of all the shards. When reset is true, the samples
+// are removed from the shards.
+func (s *stackCounterShards) merge(reset bool) stackCounterMap {
+	samples := make(stackCounterMap)
+	s.shards.values.Range(func(_, v any) bool {
+		shard := v.(*stackCounterShard)
+		shard.mutex.Lock()
+		for k, sc := range shard.counts {
+			// Stack keys include the thread identifier so the shards
+			// usually hold distinct keys, but counters of the same call
+			// stack are added up in case they do not.
+			if dst := samples[k]; dst != nil {
+				addStackCounters(dst, sc)
+			} else {
+				samples[k] = &stackCounter{stack: sc.stack, value: sc.value}
+			}
+		}
+		if reset {
+			shard.counts = nil
+		}
+		shard.mutex.Unlock()
+		return true
+	})
+	return samples
+}
+
+// size returns an estimate of the memory used by the samples of the shards
+// (in bytes).
+func (s *stackCounterShards) size() int64 {
+	size := int64(0)
+	s.shards.values.Range(func(_, v any) bool {
+		shard := v.(*stackCounterShard)
+		shard.mutex.Lock()
+		size += shard.counts.size()
+		shard.mutex.Unlock()
+		return true
+	})
+	return size
+}
+
+// len returns the number of distinct call stacks recorded in the shards.
+func (s *stackCounterShards) len() int {
+	return s.merge(false).len()
+}
+
+// returningFrame returns the index of the frame of the call to def which is
+// returning, on a stack of n frames of the calls in progress on a thread. The
+// calls unwound without returning (e.g. by the exceptions of the exception
+// handling proposal, or by longjmp) leave their frames above it, which callers
+// discard so the stacks stay correct.
+//
+// The innermost frame is returned if none of the frames are calls to def.
+func returningFrame(n int, def api.FunctionDefinition, frameDef func(int) api.FunctionDefinition) int {
+	for i := n - 1; i >= 0; i-- {
+		if frameDef(i) == def {
+			return i
+		}
+	}
+	return n - 1
+}
@@ -0,0 +1,139 @@
+package wzprof
+
+import (
+	"context"
+	"sync"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func syncMapLen(m *sync.Map) int {
+	n := 0
+	m.Range(func(_, _ any) bool {
+		n++
+		return true
+	})
+	return n
+}
+
+func TestThreadLocalRelease(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(HostTime(true))
+
+	fn := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	def := fn.Definition()
+	listener := p.NewFunctionListener(def)
+	ctx := context.Background()
+
+	p.StartProfile()
+	const instances = 100
+	for i := 0; i < instances; i++ {
+		module := wazerotest.NewModule(nil, fn)
+		listener.Before(ctx, module, def, nil, experimental.NewStackIterator(experimental.StackFrame{Function: fn}))
+		listener.After(ctx, module, def, nil)
+		if err := module.Close(ctx); err != nil {
+			t.Fatal(err)
+		}
+	}
+
+	// The closed instances are released when the next one is observed, only
+	// the last one remains, and the shard retaining the samples of the
+	// others.
+	if n := syncMapLen(&p.stacks.values); n != 1 {
+		t.Errorf("wrong number of threads of the stacks: want=1 got=%d", n)
+	}
+	if n := syncMapLen(&p.counts.shards.values); n != 2 {
+		t.Errorf("wrong number of shards: want=2 got=%d", n)
+	}
+	if n := syncMapLen(&p.p.threads.ids); n != 1 {
+		t.Errorf("wrong number of thread identifiers: want=1 got=%d", n)
+	}
+
+	calls := int64(0)
+	for _, sample := range p.StopProfile(1).Sample {
+		calls += sample.Value[0]
+	}
+	if calls != instances {
+		t.Errorf("samples of the closed instances were lost: want=%d calls got=%d", instances, calls)
+	}
+}
+
+func TestThreadIDsRecycled(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(HostTime(true))
+
+	fn := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	def := fn.Definition()
+	listener := p.NewFunctionListener(def)
+	ctx := context.Background()
+	call := func(module api.Module) {
+		listener.Before(ctx, module, def, nil, experimental.NewStackIterator(experimental.StackFrame{Function: fn}))
+		listener.After(ctx, module, def, nil)
+	}
+
+	// A pool of two workers, of which the first one is recycled.
+	p.StartProfile()
+	pooled := wazerotest.NewModule(nil, fn)
+	call(pooled)
+	for i := 0; i < 100; i++ {
+		worker := wazerotest.NewModule(nil, fn)
+		call(worker)
+		if id := p.p.threads.lookup(worker); id != 2 {
+			t.Fatalf("recycled worker %d has thread %d, want 2", i, id)
+		}
+		if err := worker.Close(ctx); err != nil {
+			t.Fatal(err)
+		}
+	}
+	call(pooled)
+
+	if id := p.p.threads.lookup(pooled); id != 1 {
+		t.Errorf("wrong thread of the long-lived worker: want=1 got=%d", id)
+	}
+	if n := syncMapLen(&p.counts.shards.values); n > 3 {
+		t.Errorf("shards were not released: want<=3 got=%d", n)
+	}
+
+	prof := p.StopProfile(1)
+	threads := map[string]int64{}
+	for _, sample := range prof.Sample {
+		if thread := sample.Label["thread"]; len(thread) == 1 {
+			threads[thread[0]] += sample.Value[0]
+		}
+	}
+	if len(threads) != 2 || threads["1"] != 2 || threads["2"] != 100 {
+		t.Errorf("wrong calls per thread: %v", threads)
+	}
+}
+
+func TestThreadIDsLabelInstances(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true), LabelInstances(true)).CPUProfiler(HostTime(true))
+
+	fn := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	def := fn.Definition()
+	listener := p.NewFunctionListener(def)
+	ctx := context.Background()
+
+	// The samples of recycled instances remain labeled with their own name.
+	p.StartProfile()
+	for _, name := range []string{"worker-1", "worker-2"} {
+		worker := wazerotest.NewModule(nil, fn)
+		worker.ModuleName = name
+		listener.Before(ctx, worker, def, nil, experimental.NewStackIterator(experimental.StackFrame{Function: fn}))
+		listener.After(ctx, worker, def, nil)
+		if err := worker.Close(ctx); err != nil {
+			t.Fatal(err)
+		}
+	}
+
+	instances := map[string]int64{}
+	for _, sample := range p.StopProfile(1).Sample {
+		if instance := sample.Label["instance"]; len(instance) == 1 {
+			instances[instance[0]] += sample.Value[0]
+		}
+	}
+	if len(instances) != 2 || instances["worker-1"] != 1 || instances["worker-2"] != 1 {
+		t.Errorf("wrong calls per instance: %v", instances)
+	}
+}
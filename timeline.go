@@ -0,0 +1,268 @@
+package wzprof
+
+import (
+	"bufio"
+	"context"
+	"encoding/json"
+	"io"
+	"net/http"
+	"strconv"
+	"sync"
+	"time"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+// MemoryTimeline records the size of the linear memory of a WebAssembly module
+// and the size of the objects in use at a fixed interval, to correlate spikes
+// of memory usage with the time windows of the profiles. The timeline is
+// written as CSV or JSON (see WriteCSV and WriteJSON).
+//
+// The timeline must be installed as function listener of the module, it only
+// instruments the functions exported by the module to find its instances. The
+// linear memories of all the instances are added up, an instance of a module
+// instantiated multiple times is a distinct instance, but the threads created by
+// wasi-threads share the same memory.
+//
+// The size of the objects in use is only recorded if the memory profiler passed
+// to Profiling.MemoryTimeline tracks it (see InuseMemory), and should not be
+// wrapped by Sample so all the objects are accounted for.
+//
+// The number of points is bounded, when the limit is reached the resolution of
+// the timeline is halved by dropping every other point and doubling the period.
+type MemoryTimeline struct {
+	p       *Profiling
+	mem     *MemoryProfiler
+	period  time.Duration
+	mutex   sync.Mutex
+	points  []memoryPoint
+	stride  int
+	ticks   int
+	stop    chan struct{}
+	done    chan struct{}
+	maxSize int
+}
+
+// MemoryTimelineOption is a type used to represent configuration options for
+// MemoryTimeline instances created by Profiling.MemoryTimeline.
+type MemoryTimelineOption func(*MemoryTimeline)
+
+// TimelinePeriod configures the interval between the points of the timeline.
+//
+// Default to 100ms.
+func TimelinePeriod(period time.Duration) MemoryTimelineOption {
+	return func(t *MemoryTimeline) { t.period = period }
+}
+
+const (
+	defaultTimelinePeriod = 100 * time.Millisecond
+	maxTimelinePoints     = 1 << 16
+)
+
+type memoryPoint struct {
+	time   time.Time
+	memory int64
+	inuse  int64 // -1 if the memory in use is not tracked
+}
+
+func newMemoryTimeline(p *Profiling, mem *MemoryProfiler, options ...MemoryTimelineOption) *MemoryTimeline {
+	t := &MemoryTimeline{
+		p:       p,
+		mem:     mem,
+		period:  defaultTimelinePeriod,
+		stride:  1,
+		maxSize: maxTimelinePoints,
+	}
+	for _, opt := range options {
+		opt(t)
+	}
+	return t
+}
+
+// NewFunctionListener returns a function listener recording the instances of
+// the module which the function passed as argument is exported by, or nil if
+// the function is not exported or is a host function.
+func (t *MemoryTimeline) NewFunctionListener(def api.FunctionDefinition) experimental.FunctionListener {
+	if def.GoFunction() != nil || len(def.ExportNames()) == 0 {
+		return nil
+	}
+	return timelineListener{t}
+}
+
+type timelineListener struct{ *MemoryTimeline }
+
+func (t timelineListener) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64, _ experimental.StackIterator) {
+	t.p.threads.lookup(mod)
+}
+
+func (t timelineListener) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64) {
+}
+
+func (t timelineListener) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
+}
+
+// Start begins recording the timeline, until Stop is called. The method returns
+// a boolean to indicate whether starting the timeline succeeded (e.g. false is
+// returned if it was already started).
+//
+// The points recorded by a previous call to Start are retained, the timeline
+// has a gap between the calls.
+func (t *MemoryTimeline) Start() bool {
+	t.mutex.Lock()
+	defer t.mutex.Unlock()
+	if t.stop != nil {
+		return false // already started
+	}
+	t.stop = make(chan struct{})
+	t.done = make(chan struct{})
+	go t.run(t.stop, t.done)
+	return true
+}
+
+// Stop stops recording the timeline and records a last point, so the timeline
+// covers the end of the program when stopped as it exits.
+func (t *MemoryTimeline) Stop() {
+	t.mutex.Lock()
+	stop, done := t.stop, t.done
+	t.stop, t.done = nil, nil
+	t.mutex.Unlock()
+
+	if stop != nil {
+		close(stop)
+		<-done
+		t.record(time.Now())
+	}
+}
+
+func (t *MemoryTimeline) run(stop <-chan struct{}, done chan<- struct{}) {
+	defer close(done)
+	t.record(time.Now())
+	ticker := time.NewTicker(t.period)
+	defer ticker.Stop()
+	for {
+		select {
+		case now := <-ticker.C:
+			t.tick(now)
+		case <-stop:
+			return
+		}
+	}
+}
+
+// tick records a point every stride ticks of the timer.
+func (t *MemoryTimeline) tick(now time.Time) {
+	t.mutex.Lock()
+	t.ticks++
+	skip := t.ticks%t.stride != 0
+	t.mutex.Unlock()
+	if !skip {
+		t.record(now)
+	}
+}
+
+// record adds a point of the current memory usage at the given time.
+func (t *MemoryTimeline) record(now time.Time) {
+	point := memoryPoint{time: now, inuse: -1}
+	memories := make(map[api.Memory]struct{})
+	t.p.threads.ids.Range(func(k, _ any) bool {
+		mod := k.(api.Module)
+		if mod.IsClosed() {
+			return true
+		}
+		if mem := mod.Memory(); mem != nil {
+			if _, ok := memories[mem]; !ok {
+				memories[mem] = struct{}{}
+				point.memory += int64(mem.Size())
+			}
+		}
+		return true
+	})
+	if t.mem != nil {
+		if bytes, ok := t.mem.inuseBytes(); ok {
+			point.inuse = bytes
+		}
+	}
+
+	t.mutex.Lock()
+	defer t.mutex.Unlock()
+	if len(t.points) == t.maxSize {
+		n := 0
+		for i := 0; i < len(t.points); i += 2 {
+			t.points[n] = t.points[i]
+			n++
+		}
+		t.points = t.points[:n]
+		t.stride *= 2
+	}
+	t.points = append(t.points, point)
+}
+
+func (t *MemoryTimeline) snapshot() []memoryPoint {
+	t.mutex.Lock()
+	defer t.mutex.Unlock()
+	// The points are copied since halving the resolution of the timeline
+	// moves them in place.
+	return append([]memoryPoint(nil), t.points...)
+}
+
+// WriteCSV writes the points of the timeline to w as CSV, with a header line
+// and the time (in RFC 3339 format), the size of linear memory and the size of
+// the objects in use (in bytes) of each point. The last column is empty if the
+// memory in use is not tracked.
+func (t *MemoryTimeline) WriteCSV(w io.Writer) error {
+	b := bufio.NewWriter(w)
+	_, _ = b.WriteString("time,memory_bytes,inuse_bytes\n")
+	var buf []byte
+	for _, point := range t.snapshot() {
+		buf = point.time.UTC().AppendFormat(buf[:0], time.RFC3339Nano)
+		buf = append(buf, ',')
+		buf = strconv.AppendInt(buf, point.memory, 10)
+		buf = append(buf, ',')
+		if point.inuse >= 0 {
+			buf = strconv.AppendInt(buf, point.inuse, 10)
+		}
+		buf = append(buf, '\n')
+		_, _ = b.Write(buf)
+	}
+	return b.Flush()
+}
+
+// timelinePoint is the JSON representation of the points of timelines.
+type timelinePoint struct {
+	Time   time.Time `json:"time"`
+	Memory int64     `json:"memory_bytes"`
+	Inuse  *int64    `json:"inuse_bytes,omitempty"`
+}
+
+// WriteJSON writes the points of the timeline to w as a JSON array of objects
+// with the "time", "memory_bytes" and "inuse_bytes" fields, the latter is
+// omitted if the memory in use is not tracked.
+func (t *MemoryTimeline) WriteJSON(w io.Writer) error {
+	points := t.snapshot()
+	values := make([]timelinePoint, len(points))
+	for i := range points {
+		values[i] = timelinePoint{Time: points[i].time.UTC(), Memory: points[i].memory}
+		if points[i].inuse >= 0 {
+			values[i].Inuse = &points[i].inuse
+		}
+	}
+	return json.NewEncoder(w).Encode(values)
+}
+
+// NewHandler returns a http handler serving the timeline as JSON, or as CSV
+// when the "format" query parameter is "csv".
+func (t *MemoryTimeline) NewHandler() http.Handler {
+	return http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		switch r.FormValue("format") {
+		case "", "json":
+			w.Header().Set("Content-Type", "application/json")
+			_ = t.WriteJSON(w)
+		case "csv":
+			w.Header().Set("Content-Type", "text/csv")
+			_ = t.WriteCSV(w)
+		default:
+			serveError(w, http.StatusBadRequest, "unsupported timeline format: "+r.FormValue("format"))
+		}
+	})
+}
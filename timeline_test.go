@@ -0,0 +1,97 @@
+package wzprof
+
+import (
+	"bytes"
+	"context"
+	"encoding/json"
+	"strings"
+	"testing"
+	"time"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestMemoryTimeline(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true))
+	mem := p.MemoryProfiler(InuseMemory(true))
+	timeline := p.MemoryTimeline(mem)
+
+	malloc := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, size uint32) uint32 {
+		return 0
+	})
+	malloc.FunctionName = "malloc"
+	module := wazerotest.NewModule(wazerotest.NewMemory(2*wazerotest.PageSize), malloc)
+	ctx := context.Background()
+
+	start := time.Date(2023, 6, 1, 12, 0, 0, 0, time.UTC)
+	timeline.record(start)
+
+	def := malloc.Definition()
+	timelineListener{timeline}.Before(ctx, module, def, nil, nil)
+	listener := mem.NewFunctionListener(def)
+	listener.Before(ctx, module, def, []uint64{100}, experimental.NewStackIterator(experimental.StackFrame{Function: malloc}))
+	listener.After(ctx, module, def, []uint64{16})
+	timeline.record(start.Add(100 * time.Millisecond))
+
+	b := new(bytes.Buffer)
+	if err := timeline.WriteCSV(b); err != nil {
+		t.Fatal(err)
+	}
+	want := strings.Join([]string{
+		"time,memory_bytes,inuse_bytes",
+		"2023-06-01T12:00:00Z,0,0",
+		"2023-06-01T12:00:00.1Z,131072,100",
+		"",
+	}, "\n")
+	if b.String() != want {
+		t.Errorf("wrong CSV timeline:\nwant:\n%s\ngot:\n%s", want, b.String())
+	}
+
+	b.Reset()
+	if err := timeline.WriteJSON(b); err != nil {
+		t.Fatal(err)
+	}
+	var points []timelinePoint
+	if err := json.Unmarshal(b.Bytes(), &points); err != nil {
+		t.Fatal(err)
+	}
+	if len(points) != 2 || points[1].Memory != 131072 || points[1].Inuse == nil || *points[1].Inuse != 100 {
+		t.Errorf("wrong JSON timeline: %s", b.String())
+	}
+
+	// The objects in use are not recorded without a memory profiler.
+	b.Reset()
+	without := p.MemoryTimeline(nil)
+	without.record(start)
+	if err := without.WriteJSON(b); err != nil {
+		t.Fatal(err)
+	}
+	if strings.Contains(b.String(), "inuse_bytes") {
+		t.Errorf("objects in use recorded without a memory profiler: %s", b.String())
+	}
+}
+
+func TestMemoryTimelineResolution(t *testing.T) {
+	timeline := ProfilingFor(nil).MemoryTimeline(nil)
+	timeline.maxSize = 4
+
+	start := time.Now()
+	for i := 0; i < 5; i++ {
+		timeline.record(start.Add(time.Duration(i) * time.Second))
+	}
+
+	points := timeline.snapshot()
+	if len(points) != 3 {
+		t.Fatalf("wrong number of points: want=3 got=%d", len(points))
+	}
+	for i, want := range []time.Duration{0, 2 * time.Second, 4 * time.Second} {
+		if d := points[i].time.Sub(start); d != want {
+			t.Errorf("wrong time of point %d: want=%s got=%s", i, want, d)
+		}
+	}
+	if timeline.stride != 2 {
+		t.Errorf("wrong stride of the timeline: want=2 got=%d", timeline.stride)
+	}
+}
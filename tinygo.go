@@ -0,0 +1,48 @@
+package wzprof
+
+import (
+	"debug/dwarf"
+	"strings"
+)
+
+// tinygoHeapStartName is the name of the variable holding the address of the
+// first block of the TinyGo heap.
+const tinygoHeapStartName = "runtime.heapStart"
+
+// tinygoBytesPerBlock is the size of the blocks managed by the TinyGo garbage
+// collector on wasm32 (4 pointers).
+//
+// https://github.com/tinygo-org/tinygo/blob/v0.27.0/src/runtime/gc_blocks.go
+const tinygoBytesPerBlock = 16
+
+// Try to detect if the module was compiled by TinyGo, which records itself as
+// the producer of the compile units of the Go code.
+func binCompiledByTinyGo(b []byte) bool {
+	p, err := newDwarfParserFromBin(b)
+	if err != nil {
+		return false
+	}
+	for {
+		ent, err := p.r.Next()
+		if err != nil || ent == nil {
+			return false
+		}
+		if ent.Tag == dwarf.TagCompileUnit {
+			producer, _ := ent.Val(dwarf.AttrProducer).(string)
+			if strings.HasPrefix(producer, "TinyGo") {
+				return true
+			}
+		}
+		p.r.SkipChildren()
+	}
+}
+
+// tinygoHeapStart returns the address of the runtime.heapStart variable of a
+// module compiled by TinyGo, or zero if it could not be found.
+func tinygoHeapStart(b []byte) uint32 {
+	p, err := newDwarfParserFromBin(b)
+	if err != nil {
+		return 0
+	}
+	return dwarfVariableAddress(p, tinygoHeapStartName)
+}
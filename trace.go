@@ -0,0 +1,438 @@
+package wzprof
+
+import (
+	"bufio"
+	"context"
+	"encoding/json"
+	"io"
+	"sort"
+	"strconv"
+	"strings"
+	"sync"
+	"sync/atomic"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+)
+
+// Tracer records the calls to the functions of a WebAssembly module as events
+// of a timeline, so individual slow calls can be inspected rather than only
+// the aggregated values of the profiles. The trace is written in the trace
+// event format of Chrome (see WriteTrace), which the Perfetto UI at
+// https://ui.perfetto.dev and chrome://tracing open directly.
+//
+// Each call records two events, tracing every call of a program quickly adds
+// up so the number of calls recorded is bounded (see MaxTracedCalls), and
+// restricting the traced functions with Filter helps keep the trace focused.
+// The tracer may be wrapped by Sample, the calls that the sampler skips are
+// absent from the trace.
+type Tracer struct {
+	p        *Profiling
+	threads  threadLocal[traceThread]
+	time     func() int64
+	start    int64
+	maxCalls int64
+	calls    atomic.Int64
+	output   bool
+	// Threads of the instances which were closed, retained until the trace
+	// is written.
+	mutex   sync.Mutex
+	retired []*traceThread
+}
+
+// TracerOption is a type used to represent configuration options for Tracer
+// instances created by Profiling.Tracer.
+type TracerOption func(*Tracer)
+
+// MaxTracedCalls bounds the number of calls recorded by the tracer, the calls
+// made after reaching the limit are not recorded. The writes recorded with
+// TraceOutput count toward the limit, one for each write.
+//
+// Default to 1M calls, which use about 64 MiB of memory.
+func MaxTracedCalls(limit int) TracerOption {
+	return func(t *Tracer) { t.maxCalls = int64(limit) }
+}
+
+// TraceTimeFunc configures the time function used by the tracer to collect
+// monotonic timestamps, in nanoseconds.
+//
+// By default, the system's monotonic time is used.
+func TraceTimeFunc(time func() int64) TracerOption {
+	return func(t *Tracer) { t.time = time }
+}
+
+// TraceOutput configures the tracer to record the writes of the guest to its
+// standard output and error (calls to the fd_write function of WASI with the
+// file descriptors 1 and 2) as instant events of the timeline, so the lines
+// printed by the program appear next to the calls which were in progress when
+// they were printed. The writes are recorded even if fd_write is not traced,
+// and each write counts as a call toward the limit of MaxTracedCalls (with up
+// to 1 KiB of text each), so the size of the trace remains bounded.
+//
+// Default to false.
+func TraceOutput(enable bool) TracerOption {
+	return func(t *Tracer) { t.output = enable }
+}
+
+const defaultMaxTracedCalls = 1 << 20
+
+// maxTracedWriteSize is the size that the writes of the guest are truncated to
+// in the trace.
+const maxTracedWriteSize = 1024
+
+// traceThread holds the events recorded on a thread of the program. The mutex
+// guards against concurrent calls to WriteTrace.
+type traceThread struct {
+	mutex  sync.Mutex
+	id     uint32
+	events []traceEvent
+	// Number of calls in progress which were recorded, and which were not
+	// because the limit of calls was reached.
+	depth   int
+	skipped int
+	// Writes to the standard output and error, kept apart from the calls
+	// so the events do not grow when the output is not recorded.
+	writes []traceWrite
+}
+
+type traceEvent struct {
+	def   api.FunctionDefinition
+	time  int64
+	phase byte // 'B' for calls, 'E' for returns
+}
+
+type traceWrite struct {
+	time int64
+	fd   uint32
+	text string
+}
+
+func newTracer(p *Profiling, options ...TracerOption) *Tracer {
+	t := &Tracer{
+		p:        p,
+		time:     nanotime,
+		maxCalls: defaultMaxTracedCalls,
+	}
+	for _, opt := range options {
+		opt(t)
+	}
+	t.start = t.time()
+	return t
+}
+
+// Count returns the number of calls and writes recorded by the tracer.
+func (t *Tracer) Count() int {
+	return int(min64(t.calls.Load(), t.maxCalls))
+}
+
+// NewFunctionListener returns a function listener recording the calls to the
+// function passed as argument, or nil if the function is not instrumented (see
+// Filter).
+func (t *Tracer) NewFunctionListener(def api.FunctionDefinition) experimental.FunctionListener {
+	traced := t.p.instrumented(def)
+	if t.output && isWASI(def.ModuleName()) && def.Name() == "fd_write" {
+		return traceOutputListener{t, traced}
+	}
+	if !traced {
+		return nil
+	}
+	return traceListener{t}
+}
+
+// record adds an event to the trace of the thread that mod runs on.
+//
+// The returns of the calls which were recorded are recorded past the limit of
+// calls, so the calls of the trace are not truncated.
+func (t *Tracer) record(mod api.Module, def api.FunctionDefinition, phase byte) {
+	thread := t.threads.load(mod, t.retire)
+	thread.mutex.Lock()
+	defer thread.mutex.Unlock()
+
+	if thread.id == 0 {
+		thread.id = t.p.threads.lookup(mod)
+	}
+	if phase == 'B' {
+		if t.calls.Add(1) > t.maxCalls {
+			thread.skipped++
+			return
+		}
+		thread.depth++
+	} else {
+		// Calls nest, the calls made after reaching the limit return
+		// before the calls in progress which were recorded.
+		if thread.skipped > 0 {
+			thread.skipped--
+			return
+		}
+		if thread.depth == 0 {
+			return
+		}
+		thread.depth--
+	}
+	thread.events = append(thread.events, traceEvent{def: def, time: t.time(), phase: phase})
+}
+
+// recordWrite adds the data written to fd by a call to fd_write, described by
+// the iovs_len buffers at the address iovs of the memory of mod, to the trace
+// of the thread that mod runs on.
+func (t *Tracer) recordWrite(mod api.Module, fd, iovs, iovsLen uint32) {
+	mem := mod.Memory()
+	if mem == nil {
+		return
+	}
+	var text []byte
+	for i := uint32(0); i < iovsLen && len(text) < maxTracedWriteSize; i++ {
+		buf, ok := mem.ReadUint32Le(iovs + 8*i)
+		if !ok {
+			return
+		}
+		size, ok := mem.ReadUint32Le(iovs + 8*i + 4)
+		if !ok {
+			return
+		}
+		if n := uint32(maxTracedWriteSize - len(text)); size > n {
+			size = n
+		}
+		b, ok := mem.Read(buf, size)
+		if !ok {
+			return
+		}
+		text = append(text, b...)
+	}
+	if len(text) == 0 {
+		return
+	}
+
+	thread := t.threads.load(mod, t.retire)
+	thread.mutex.Lock()
+	defer thread.mutex.Unlock()
+
+	if thread.id == 0 {
+		thread.id = t.p.threads.lookup(mod)
+	}
+	if t.calls.Add(1) > t.maxCalls {
+		return
+	}
+	thread.writes = append(thread.writes, traceWrite{time: t.time(), fd: fd, text: string(text)})
+}
+
+// retire keeps the events of the thread of a closed instance, which is
+// released by the threads of the tracer.
+func (t *Tracer) retire(thread *traceThread) {
+	t.mutex.Lock()
+	t.retired = append(t.retired, thread)
+	t.mutex.Unlock()
+}
+
+type traceListener struct{ *Tracer }
+
+func (t traceListener) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64, _ experimental.StackIterator) {
+	t.record(mod, def, 'B')
+}
+
+func (t traceListener) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64) {
+	t.record(mod, def, 'E')
+}
+
+func (t traceListener) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
+	t.record(mod, def, 'E')
+}
+
+// traceOutputListener records the writes of fd_write calls to the standard
+// output and error, and the calls themselves if the function is traced.
+type traceOutputListener struct {
+	*Tracer
+	traced bool
+}
+
+func (t traceOutputListener) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, params []uint64, _ experimental.StackIterator) {
+	if t.traced {
+		t.record(mod, def, 'B')
+	}
+	// fd_write(fd, iovs, iovs_len, nwritten)
+	if len(params) == 4 {
+		if fd := uint32(params[0]); fd == 1 || fd == 2 {
+			t.recordWrite(mod, fd, uint32(params[1]), uint32(params[2]))
+		}
+	}
+}
+
+func (t traceOutputListener) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64) {
+	if t.traced {
+		t.record(mod, def, 'E')
+	}
+}
+
+func (t traceOutputListener) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ error) {
+	if t.traced {
+		t.record(mod, def, 'E')
+	}
+}
+
+// chromeTraceEvent is the JSON representation of events in the trace event
+// format of Chrome.
+type chromeTraceEvent struct {
+	Name  string            `json:"name"`
+	Cat   string            `json:"cat,omitempty"`
+	Phase string            `json:"ph"`
+	Scope string            `json:"s,omitempty"` // of instant events
+	Time  float64           `json:"ts"`          // microseconds
+	PID   int               `json:"pid"`
+	TID   uint32            `json:"tid"`
+	Args  map[string]string `json:"args,omitempty"`
+}
+
+// WriteTrace writes the events recorded by t to w, in the JSON trace event
+// format of Chrome. Each thread of the program is represented as a thread of
+// the trace, and the calls that had not returned yet end at the last event of
+// their thread. The writes of the guest recorded with TraceOutput are instant
+// events named after the text written, in the "stdout" or "stderr" category.
+// The timestamps are relative to the creation of the tracer.
+//
+// Calls recorded while the trace is written may be missing from it.
+func (t *Tracer) WriteTrace(w io.Writer) error {
+	t.mutex.Lock()
+	threads := append([]*traceThread(nil), t.retired...)
+	t.mutex.Unlock()
+	t.threads.values.Range(func(_, v any) bool {
+		threads = append(threads, v.(*traceThread))
+		return true
+	})
+
+	names := make(map[api.FunctionDefinition]string)
+	name := func(def api.FunctionDefinition) string {
+		n, ok := names[def]
+		if !ok {
+			// The category of the events is the module name, which
+			// tells the host functions apart.
+			n = definitionName(def)
+			n = t.p.functionName(location{StableName: n, HumanName: n})
+			names[def] = n
+		}
+		return n
+	}
+
+	type threadEvents struct {
+		id     uint32
+		events []traceEvent
+		writes []traceWrite
+	}
+	snapshots := make([]threadEvents, 0, len(threads))
+	for _, thread := range threads {
+		thread.mutex.Lock()
+		snapshots = append(snapshots, threadEvents{
+			thread.id,
+			thread.events[:len(thread.events):len(thread.events)],
+			thread.writes[:len(thread.writes):len(thread.writes)],
+		})
+		thread.mutex.Unlock()
+	}
+	sort.SliceStable(snapshots, func(i, j int) bool { return snapshots[i].id < snapshots[j].id })
+
+	b := bufio.NewWriter(w)
+	e := json.NewEncoder(b)
+	_, _ = b.WriteString(`{"displayTimeUnit":"ns","traceEvents":[`)
+	first := true
+	emit := func(event *chromeTraceEvent) error {
+		if !first {
+			_ = b.WriteByte(',')
+		}
+		first = false
+		return e.Encode(event)
+	}
+	micros := func(time int64) float64 { return float64(time-t.start) / 1e3 }
+
+	for _, thread := range snapshots {
+		err := emit(&chromeTraceEvent{
+			Name:  "thread_name",
+			Phase: "M",
+			PID:   1,
+			TID:   thread.id,
+			Args:  map[string]string{"name": "thread " + strconv.FormatUint(uint64(thread.id), 10)},
+		})
+		if err != nil {
+			return err
+		}
+
+		// The writes are emitted in time order with the calls, before the
+		// first event of the calls which happened after them.
+		writes := thread.writes
+		emitWrites := func(until int64) error {
+			for len(writes) > 0 && writes[0].time < until {
+				write := writes[0]
+				writes = writes[1:]
+				category := "stdout"
+				if write.fd == 2 {
+					category = "stderr"
+				}
+				err := emit(&chromeTraceEvent{
+					Name:  strings.TrimRight(write.text, "\n"),
+					Cat:   category,
+					Phase: "i",
+					Scope: "t",
+					Time:  micros(write.time),
+					PID:   1,
+					TID:   thread.id,
+				})
+				if err != nil {
+					return err
+				}
+			}
+			return nil
+		}
+
+		var stack []api.FunctionDefinition
+		var last int64
+		for _, event := range thread.events {
+			if err := emitWrites(event.time); err != nil {
+				return err
+			}
+			last = event.time
+			if event.phase == 'B' {
+				stack = append(stack, event.def)
+			} else if len(stack) > 0 {
+				stack = stack[:len(stack)-1]
+			}
+			err := emit(&chromeTraceEvent{
+				Name:  name(event.def),
+				Cat:   event.def.ModuleName(),
+				Phase: string(event.phase),
+				Time:  micros(event.time),
+				PID:   1,
+				TID:   thread.id,
+			})
+			if err != nil {
+				return err
+			}
+		}
+		if n := len(writes); n > 0 && writes[n-1].time > last {
+			last = writes[n-1].time
+		}
+		if err := emitWrites(last + 1); err != nil {
+			return err
+		}
+		for i := len(stack) - 1; i >= 0; i-- {
+			err := emit(&chromeTraceEvent{
+				Name:  name(stack[i]),
+				Cat:   stack[i].ModuleName(),
+				Phase: "E",
+				Time:  micros(last),
+				PID:   1,
+				TID:   thread.id,
+			})
+			if err != nil {
+				return err
+			}
+		}
+	}
+
+	_, _ = b.WriteString("]}\n")
+	return b.Flush()
+}
+
+func min64(a, b int64) int64 {
+	if a < b {
+		return a
+	}
+	return b
+}
@@ -0,0 +1,168 @@
+package wzprof
+
+import (
+	"bytes"
+	"context"
+	"encoding/json"
+	"reflect"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+)
+
+func TestTracer(t *testing.T) {
+	for _, test := range []struct {
+		name     string
+		maxCalls int
+		want     []string
+	}{
+		{"all", 0, []string{"M thread_name", "B f0", "B f1", "E f1", "B f1", "E f1", "E f0"}},
+		{"limit", 2, []string{"M thread_name", "B f0", "B f1", "E f1", "E f0"}},
+	} {
+		t.Run(test.name, func(t *testing.T) {
+			currentTime := int64(0)
+			options := []TracerOption{TraceTimeFunc(func() int64 {
+				currentTime += 1000
+				return currentTime
+			})}
+			if test.maxCalls > 0 {
+				options = append(options, MaxTracedCalls(test.maxCalls))
+			}
+			tracer := ProfilingFor(nil).Tracer(options...)
+
+			f0 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+			f0.FunctionName = "f0"
+			f1 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+			f1.FunctionName = "f1"
+
+			module := wazerotest.NewModule(nil, f0, f1)
+			ctx := context.Background()
+			def0, def1 := f0.Definition(), f1.Definition()
+			l0, l1 := tracer.NewFunctionListener(def0), tracer.NewFunctionListener(def1)
+
+			l0.Before(ctx, module, def0, nil, experimental.NewStackIterator())
+			for i := 0; i < 2; i++ {
+				l1.Before(ctx, module, def1, nil, experimental.NewStackIterator())
+				l1.After(ctx, module, def1, nil)
+			}
+			l0.After(ctx, module, def0, nil)
+
+			b := new(bytes.Buffer)
+			if err := tracer.WriteTrace(b); err != nil {
+				t.Fatal(err)
+			}
+			var trace struct {
+				TraceEvents []chromeTraceEvent `json:"traceEvents"`
+			}
+			if err := json.Unmarshal(b.Bytes(), &trace); err != nil {
+				t.Fatalf("invalid trace: %v\n%s", err, b)
+			}
+
+			if len(trace.TraceEvents) != len(test.want) {
+				t.Fatalf("wrong number of events: want=%d got=%d", len(test.want), len(trace.TraceEvents))
+			}
+			last := 0.0
+			for i, e := range trace.TraceEvents {
+				if got := e.Phase + " " + e.Name; got != test.want[i] {
+					t.Errorf("event %d mismatch: want=%q got=%q", i, test.want[i], got)
+				}
+				if e.Time < last {
+					t.Errorf("event %d is out of order: %g < %g", i, e.Time, last)
+				}
+				last = e.Time
+			}
+		})
+	}
+}
+
+func TestTracerOutput(t *testing.T) {
+	for _, test := range []struct {
+		name     string
+		maxCalls int
+		want     []string
+	}{
+		{
+			name:     "unlimited",
+			maxCalls: defaultMaxTracedCalls,
+			want: []string{
+				"M thread_name",
+				"B main",
+				"B fd_write", "i stdout hello", "E fd_write",
+				"B fd_write", "i stderr oops", "E fd_write",
+				"B fd_write", "E fd_write",
+				"E main",
+			},
+		},
+		{
+			// The writes count toward the limit of calls.
+			name:     "limited",
+			maxCalls: 3,
+			want: []string{
+				"M thread_name",
+				"B main",
+				"B fd_write", "i stdout hello", "E fd_write",
+				"E main",
+			},
+		},
+	} {
+		t.Run(test.name, func(t *testing.T) {
+			currentTime := int64(0)
+			tracer := ProfilingFor(nil).Tracer(TraceOutput(true), MaxTracedCalls(test.maxCalls), TraceTimeFunc(func() int64 {
+				currentTime += 1000
+				return currentTime
+			}))
+
+			main := wazerotest.NewFunction(func(context.Context, api.Module) {})
+			main.FunctionName = "main"
+			fdWrite := wazerotest.NewFunction(func(ctx context.Context, mod api.Module, fd, iovs, iovsLen, nwritten uint32) uint32 {
+				return 0
+			})
+			fdWrite.FunctionName = "fd_write"
+
+			memory := wazerotest.NewMemory(wazerotest.PageSize)
+			module := wazerotest.NewModule(memory, main, fdWrite)
+			module.ModuleName = "wasi_snapshot_preview1"
+			ctx := context.Background()
+			mainDef, writeDef := main.Definition(), fdWrite.Definition()
+			mainListener, writeListener := tracer.NewFunctionListener(mainDef), tracer.NewFunctionListener(writeDef)
+
+			mainListener.Before(ctx, module, mainDef, nil, experimental.NewStackIterator())
+			for _, write := range []struct {
+				fd   uint64
+				text string
+			}{{1, "hello\n"}, {2, "oops"}, {3, "ignored"}} {
+				memory.Write(64, []byte(write.text))
+				memory.WriteUint32Le(16, 64)
+				memory.WriteUint32Le(20, uint32(len(write.text)))
+				writeListener.Before(ctx, module, writeDef, []uint64{write.fd, 16, 1, 0}, experimental.NewStackIterator())
+				writeListener.After(ctx, module, writeDef, []uint64{0})
+			}
+			mainListener.After(ctx, module, mainDef, nil)
+
+			b := new(bytes.Buffer)
+			if err := tracer.WriteTrace(b); err != nil {
+				t.Fatal(err)
+			}
+			var trace struct {
+				TraceEvents []chromeTraceEvent `json:"traceEvents"`
+			}
+			if err := json.Unmarshal(b.Bytes(), &trace); err != nil {
+				t.Fatalf("invalid trace: %v\n%s", err, b)
+			}
+
+			var got []string
+			for _, e := range trace.TraceEvents {
+				if e.Phase == "i" {
+					got = append(got, e.Phase+" "+e.Cat+" "+e.Name)
+				} else {
+					got = append(got, e.Phase+" "+e.Name)
+				}
+			}
+			if !reflect.DeepEqual(got, test.want) {
+				t.Errorf("wrong events:\nwant=%q\ngot= %q", test.want, got)
+			}
+		})
+	}
+}
@@ -234,7 +234,7 @@ func (u *unwinder) resolveInternal(innermost bool) {
 		// We also defensively check that this won't switch M's on us,
 		// which could happen at critical points in the scheduler.
 		// This ensures gp.m doesn't change from a stack jump.
-		if u.flags&unwindJumpStack != 0 && gp == gMG0(u.mem, gp) && gMCurg(u.mem, gp) != 0 && ptr64(gMCurg(u.mem, gp)) == gM(u.mem, gp) {
+		if u.flags&unwindJumpStack != 0 && gp == gMG0(u.mem, gp) && gMCurg(u.mem, gp) != 0 && gM(u.mem, gMCurg(u.mem, gp)) == gM(u.mem, gp) {
 			switch f.FuncID {
 			case goruntime.FuncID_morestack:
 				// morestack does not return normally -- newstack()
@@ -294,7 +294,13 @@ func (u *unwinder) resolveInternal(innermost bool) {
 		var lrPtr ptr64
 		if frame.lr == 0 {
 			lrPtr = frame.fp - goarchPtrSize
-			frame.lr = deref[ptr64](u.mem, lrPtr)
+			if u.flags&(unwindPrintErrors|unwindSilentErrors) != 0 {
+				// A corrupted stack pointer may point outside of the
+				// memory, which stops the traceback with a zero lr.
+				frame.lr, _ = tryDeref[ptr64](u.mem, lrPtr)
+			} else {
+				frame.lr = deref[ptr64](u.mem, lrPtr)
+			}
 		}
 	}
 
@@ -323,6 +329,10 @@ func (u *unwinder) next() {
 		// If the next frame is identical to the current frame, we cannot make progress.
 		// print("runtime: traceback stuck. pc=", hex(frame.pc), " sp=", hex(frame.sp), "\n")
 		// tracebackHexdump(gp.stack, frame, frame.sp)
+		if u.flags&(unwindPrintErrors|unwindSilentErrors) != 0 {
+			u.finishInternal()
+			return
+		}
 		panic("traceback stuck")
 	}
 
@@ -0,0 +1,105 @@
+package wzprof
+
+import (
+	"context"
+	"os"
+	"strings"
+	"testing"
+
+	"github.com/tetratelabs/wazero"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/imports/wasi_snapshot_preview1"
+)
+
+// goMainFunc loads the pclntab of testdata/go/twocalls.wasm from the memory of
+// an instance of the module which was not started, and returns the function
+// main.main.
+func goMainFunc(t *testing.T) (*pclntab, api.Memory, funcInfo) {
+	ctx := context.Background()
+	runtime := wazero.NewRuntimeWithConfig(ctx, wazero.NewRuntimeConfigInterpreter())
+	t.Cleanup(func() { runtime.Close(ctx) })
+	wasi_snapshot_preview1.MustInstantiate(ctx, runtime)
+
+	wasm, err := os.ReadFile("testdata/go/twocalls.wasm")
+	if err != nil {
+		t.Fatal(err)
+	}
+	compiled, err := runtime.CompileModule(ctx, wasm)
+	if err != nil {
+		t.Fatal(err)
+	}
+	symbols, err := preparePclntabSymbolizer(wasm, compiled)
+	if err != nil {
+		t.Fatal(err)
+	}
+	// The memory only holds the data segments of the module.
+	mod, err := runtime.InstantiateModule(ctx, compiled, wazero.NewModuleConfig().WithStartFunctions())
+	if err != nil {
+		t.Fatal(err)
+	}
+	mem := mod.Memory()
+	symbols.EnsureReady(mem)
+
+	for _, e := range symbols.md.ftab {
+		f := symbols.FindFunc(symbols.md.textAddr(e.entryoff))
+		if f.valid() && f.name() == "main.main" {
+			return symbols, mem, f
+		}
+	}
+	t.Fatal("main.main not found")
+	return nil, nil, funcInfo{}
+}
+
+func TestUnwindCorruptedStack(t *testing.T) {
+	symbols, mem, f := goMainFunc(t)
+
+	// A g which is not running on the system stack of its M, so the
+	// unwinder does not jump stacks.
+	size := mem.Size()
+	gp, m := size-1024, size-512
+	if !mem.WriteUint64Le(gp+8*6, uint64(m)) || !mem.WriteUint64Le(m, 0) {
+		t.Fatal("writing g and m in memory")
+	}
+
+	// The stack pointer is so close to the end of the memory that the
+	// return address of the frame cannot be read.
+	si := &goStackIterator{pclntab: symbols}
+	si.mem, si.symbols = mem, symbols
+	si.initAt(f.entry(), ptr64(size-4), 0, gptr(gp), unwindJumpStack|unwindSilentErrors)
+	si.first = true
+
+	var frames []string
+	for si.Next() {
+		fn := si.Function().Definition()
+		if _, _, ok := fn.Import(); ok {
+			t.Errorf("go function %s is imported", fn.Name())
+		}
+		if names := fn.ExportNames(); len(names) != 0 {
+			t.Errorf("go function %s is exported as %q", fn.Name(), names)
+		}
+		if !strings.HasSuffix(fn.DebugName(), ".main.main") {
+			t.Errorf("wrong debug name: %q", fn.DebugName())
+		}
+		frames = append(frames, fn.Name())
+	}
+	if len(frames) != 1 || frames[0] != "main.main" {
+		t.Errorf("stack not truncated after the innermost frame: %q", frames)
+	}
+}
+
+func TestUnwindStuckStack(t *testing.T) {
+	symbols, mem, f := goMainFunc(t)
+
+	// The caller of the frame is the frame itself.
+	pc, sp := f.entry(), ptr64(mem.Size()/2)
+	u := unwinder{
+		mem:     mem,
+		symbols: symbols,
+		frame:   stkframe{fn: f, pc: pc, lr: pc, sp: sp, fp: sp},
+		flags:   unwindJumpStack | unwindSilentErrors,
+	}
+	u.next()
+	if u.valid() {
+		t.Errorf("traceback not stopped on a stuck frame at pc=%#x", u.frame.pc)
+	}
+}
@@ -0,0 +1,184 @@
+package wzprof
+
+import (
+	"context"
+	"errors"
+	"net/http"
+	"strings"
+	"sync"
+	"time"
+
+	"github.com/google/pprof/profile"
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/sys"
+)
+
+// TrapProfiler is the implementation of a profiler recording the call stacks
+// of the guest when it traps, for example when executing an unreachable
+// instruction, accessing memory out of bounds, or exhausting the call stack.
+//
+// The error returned by wazero only describes the wasm frames of the trap, the
+// profile holds the symbolized call stacks instead, including the frames of
+// the language runtimes supported by wzprof (e.g. Go or Python). Each sample
+// counts the traps of a call stack and has an "error" label describing the
+// cause of the trap. Exits of the guest with proc_exit are not recorded.
+//
+// The listener of the function which trapped is not notified of the trap
+// location within the function, so the innermost frames of the stacks are
+// positioned on entry to the function.
+//
+// The profiler must observe all function calls to know the current call stack
+// of the program, so it must not be wrapped by Sample.
+type TrapProfiler struct {
+	p      *Profiling
+	mutex  sync.Mutex
+	counts stackCounterMap
+	stacks threadLocal[trapThread]
+	start  time.Time
+}
+
+// trapThread holds the state of the trap profiler for a single thread.
+type trapThread struct {
+	traces []stackTrace
+	// Set when the trap was recorded while the frames of the remaining
+	// traces are being aborted.
+	trapped bool
+}
+
+func newTrapProfiler(p *Profiling) *TrapProfiler {
+	return &TrapProfiler{
+		p:      p,
+		counts: make(stackCounterMap),
+		start:  time.Now(),
+	}
+}
+
+// Name returns "traps".
+func (p *TrapProfiler) Name() string {
+	return "traps"
+}
+
+// Desc returns a description of the trap profile.
+func (p *TrapProfiler) Desc() string {
+	return profileDescriptions[p.Name()]
+}
+
+// Count returns the number of call stacks which trapped.
+func (p *TrapProfiler) Count() int {
+	p.mutex.Lock()
+	n := p.counts.len()
+	p.mutex.Unlock()
+	return n
+}
+
+// SampleType returns the set of value types present in samples recorded by the
+// profiler.
+func (p *TrapProfiler) SampleType() []*profile.ValueType {
+	return []*profile.ValueType{
+		{Type: "traps", Unit: "count"},
+	}
+}
+
+// NewProfile builds a profile of the traps observed since the profiler was
+// created.
+//
+// All function calls are observed, the values are not scaled by the sample
+// rate.
+func (p *TrapProfiler) NewProfile(sampleRate float64) *profile.Profile {
+	return p.p.endProfile(p.Name(), p.newProfile(sampleRate))
+}
+
+func (p *TrapProfiler) newProfile(sampleRate float64) *profile.Profile {
+	p.mutex.Lock()
+	samples := make(stackCounterMap, len(p.counts))
+	for k, sc := range p.counts {
+		samples[k] = &stackCounter{stack: sc.stack, value: sc.value}
+	}
+	p.mutex.Unlock()
+
+	return buildProfile(p.p, samples, p.start, time.Since(p.start), p.SampleType(),
+		[]float64{1},
+	)
+}
+
+// NewHandler returns a http handler allowing the profiler to be exposed on a
+// pprof-compatible http endpoint.
+//
+// When the request has a "seconds" query parameter, the handler responds with
+// a delta profile of the traps observed during that period.
+func (p *TrapProfiler) NewHandler(sampleRate float64) http.Handler {
+	return http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		serveCumulativeProfile(w, r, p.p, p.Name(), func() *profile.Profile {
+			return p.newProfile(sampleRate)
+		})
+	})
+}
+
+// NewFunctionListener returns a function listener tracking the call stacks of
+// calls to the function passed as argument.
+func (p *TrapProfiler) NewFunctionListener(def api.FunctionDefinition) experimental.FunctionListener {
+	return profilingListener{p.p, trapListener{p}}
+}
+
+func (p *TrapProfiler) observeTrap(ctx context.Context, trace stackTrace, err error) {
+	// The error may be followed by the wasm stack trace built by wazero,
+	// only its first line describes the cause of the trap.
+	cause, _, _ := strings.Cut(err.Error(), "\n")
+
+	// The cause is added to the labels of the context so traps of the same
+	// call stack with different causes are recorded separately.
+	if trace.labels != nil {
+		trace.key ^= trace.labels.hash
+	}
+	trace.labels = contextLabels(WithLabels(ctx, "error", cause))
+	trace.key ^= trace.labels.hash
+
+	trace, n, ok := p.p.hookSample(p.Name(), trace, 1)
+	if !ok {
+		return
+	}
+	p.mutex.Lock()
+	p.counts.observe(trace, n)
+	p.mutex.Unlock()
+}
+
+type trapListener struct{ *TrapProfiler }
+
+func (p trapListener) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64, si experimental.StackIterator) {
+	t := p.stacks.get(mod)
+	// A host function may have recovered from a trap of the functions it
+	// called, the next trap must be recorded.
+	t.trapped = false
+	i := len(t.traces)
+	if i < cap(t.traces) {
+		t.traces = t.traces[:i+1]
+	} else {
+		t.traces = append(t.traces, stackTrace{})
+	}
+	t.traces[i] = makeStackTrace(ctx, t.traces[i], p.p.threads.lookup(mod), si, p.p.maxFrames)
+}
+
+func (p trapListener) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, _ []uint64) {
+	t := p.stacks.get(mod)
+	t.trapped = false
+	if i := len(t.traces) - 1; i >= 0 {
+		t.traces = t.traces[:i]
+	}
+}
+
+func (p trapListener) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, err error) {
+	t := p.stacks.get(mod)
+	i := len(t.traces) - 1
+	if i < 0 {
+		return
+	}
+	// The functions are aborted from the innermost, which is the one that
+	// trapped.
+	var exitErr *sys.ExitError
+	if !t.trapped && err != nil && !errors.As(err, &exitErr) {
+		p.observeTrap(ctx, t.traces[i], err)
+	}
+	t.trapped = true
+	t.traces = t.traces[:i]
+}
@@ -0,0 +1,69 @@
+package wzprof
+
+import (
+	"context"
+	"errors"
+	"testing"
+
+	"github.com/tetratelabs/wazero/api"
+	"github.com/tetratelabs/wazero/experimental"
+	"github.com/tetratelabs/wazero/experimental/wazerotest"
+	"github.com/tetratelabs/wazero/sys"
+)
+
+func TestTrapProfiler(t *testing.T) {
+	p := ProfilingFor(nil, AllowUnprepared(true)).TrapProfiler()
+
+	f0 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f0.FunctionName = "f0"
+	f1 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f1.FunctionName = "f1"
+
+	module := wazerotest.NewModule(nil, f0, f1)
+	ctx := context.Background()
+
+	def0, def1 := f0.Definition(), f1.Definition()
+	l0, l1 := p.NewFunctionListener(def0), p.NewFunctionListener(def1)
+	call := func(err error) {
+		l0.Before(ctx, module, def0, nil, experimental.NewStackIterator(experimental.StackFrame{Function: f0}))
+		l1.Before(ctx, module, def1, nil, experimental.NewStackIterator(
+			experimental.StackFrame{Function: f1},
+			experimental.StackFrame{Function: f0},
+		))
+		if err == nil {
+			l1.After(ctx, module, def1, nil)
+			l0.After(ctx, module, def0, nil)
+		} else {
+			l1.Abort(ctx, module, def1, err)
+			l0.Abort(ctx, module, def0, err)
+		}
+	}
+
+	call(nil)
+	call(errors.New("wasm error: unreachable\nwasm stack trace:\n\tf1()\n\tf0()"))
+	call(errors.New("wasm error: unreachable\nwasm stack trace:\n\tf1()\n\tf0()"))
+	call(errors.New("wasm error: out of bounds memory access"))
+	call(sys.NewExitError(1))
+
+	prof := p.NewProfile(1)
+	if len(prof.Sample) != 2 {
+		t.Fatalf("wrong number of samples: want=2 got=%d", len(prof.Sample))
+	}
+
+	want := map[string]int64{
+		"wasm error: unreachable":                 2,
+		"wasm error: out of bounds memory access": 1,
+	}
+	for _, sample := range prof.Sample {
+		if len(sample.Location) != 2 {
+			t.Errorf("wrong number of locations: want=2 got=%d", len(sample.Location))
+		}
+		cause := sample.Label["error"]
+		if len(cause) != 1 {
+			t.Fatalf("wrong error label: %v", cause)
+		}
+		if n := sample.Value[0]; n != want[cause[0]] {
+			t.Errorf("%s: wrong number of traps: want=%d got=%d", cause[0], want[cause[0]], n)
+		}
+	}
+}
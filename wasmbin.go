@@ -1,8 +1,13 @@
 package wzprof
 
 import (
+	"crypto/sha256"
 	"encoding/binary"
+	"encoding/hex"
 	"fmt"
+
+	"github.com/tetratelabs/wazero"
+	"github.com/tetratelabs/wazero/api"
 )
 
 // Returns true if the wasm module binary b contains a custom section with this
@@ -39,6 +44,62 @@ func wasmCustomSection(b []byte, name string) []byte {
 	return nil
 }
 
+// wasmCustomSections returns the custom sections of the wasm module binary b,
+// in the order they appear in the binary. The data of the sections are slices
+// of b.
+func wasmCustomSections(b []byte) []api.CustomSection {
+	const customSectionId = 0
+	if len(b) < 8 {
+		return nil
+	}
+	var sections []api.CustomSection
+	b = b[8:] // skip magic+version
+	for len(b) > 2 {
+		id := b[0]
+		b = b[1:]
+		length, n := binary.Uvarint(b)
+		b = b[n:]
+
+		if id == customSectionId {
+			nameLen, n := binary.Uvarint(b)
+			b = b[n:]
+			sections = append(sections, customSection{
+				name: string(b[:nameLen]),
+				data: b[nameLen : length-uint64(n)],
+			})
+			b = b[length-uint64(n):]
+		} else {
+			b = b[length:]
+		}
+	}
+	return sections
+}
+
+// wasmIsComponent returns true if b is the binary of a component of the
+// component model rather than a core module. Both start with the same magic
+// number, components have a different version and layer.
+func wasmIsComponent(b []byte) bool {
+	return len(b) >= 8 && string(b[:4]) == "\x00asm" && binary.LittleEndian.Uint16(b[6:8]) == 1
+}
+
+// wasmBuildID returns the build id of the wasm module binary b in hexadecimal,
+// read from the build_id custom section of the tool conventions if the module
+// has one, or the SHA-256 of the module otherwise. Returns an empty string if
+// b is empty.
+func wasmBuildID(b []byte) string {
+	if len(b) == 0 {
+		return ""
+	}
+	if s := wasmCustomSection(b, "build_id"); s != nil {
+		// The section holds a single vector of bytes.
+		if n, r := binary.Uvarint(s); r > 0 && n <= uint64(len(s)-r) {
+			return hex.EncodeToString(s[r : r+int(n)])
+		}
+	}
+	sum := sha256.Sum256(b)
+	return hex.EncodeToString(sum[:])
+}
+
 // The functions in this file inspect the contents of a well-formed wasm-binary.
 // They are very weak parsers: they should be called on a valid module, or may
 // panic. Eventually this code should be replaced by exposing the right APIs
@@ -64,6 +125,331 @@ func wasmdataSection(b []byte) []byte {
 	return nil
 }
 
+// wasmCodeSectionOffset parses a WASM binary and returns the offset of the
+// content of the WASM "Code" section in the binary. Returns -1 if the section
+// does not exist.
+func wasmCodeSectionOffset(b []byte) int {
+	const codeSectionId = 10
+	if len(b) < 8 {
+		return -1
+	}
+
+	r := b[8:] // skip magic+version
+	for len(r) > 2 {
+		id := r[0]
+		r = r[1:]
+		length, n := binary.Uvarint(r)
+		r = r[n:]
+
+		if id == codeSectionId {
+			return len(b) - len(r)
+		}
+		r = r[length:]
+	}
+	return -1
+}
+
+// wasmFunctionOffsets parses a WASM binary and returns the offsets of the first
+// instruction of the functions defined in the module, indexed by function index
+// (the offsets of imported functions are zero). The offsets are relative to the
+// content of the "Code" section, like the code offsets of DWARF. Returns nil if
+// the binary cannot be parsed.
+func wasmFunctionOffsets(b []byte) []uint64 {
+	bodies := wasmFunctionBodies(b)
+	if bodies == nil {
+		return nil
+	}
+	offsets := make([]uint64, len(bodies))
+	for i, body := range bodies {
+		offsets[i] = body.offset
+	}
+	return offsets
+}
+
+// wasmMatchesModule reports whether mod was compiled from the wasm module binary
+// b, by comparing the names of the functions that they import and the indexes
+// of the functions that they export. Binaries which cannot be parsed are
+// assumed to match.
+func wasmMatchesModule(b []byte, mod wazero.CompiledModule) bool {
+	const (
+		importSectionId = 2
+		exportSectionId = 7
+	)
+	if len(b) < 8 {
+		return true
+	}
+
+	var imports [][2]string
+	exports := make(map[string]uint32)
+	r := wasmReader{b: b, off: 8} // skip magic+version
+	for r.off < len(r.b) && !r.err {
+		id := r.byte()
+		section := wasmReader{b: r.bytes(r.uvarint())}
+		switch id {
+		case importSectionId:
+			imports = section.importedFunctionNames()
+		case exportSectionId:
+			for n := section.uvarint(); n > 0 && !section.err; n-- {
+				name := string(section.bytes(section.uvarint()))
+				kind := section.byte()
+				index := uint32(section.uvarint())
+				if kind == 0 {
+					exports[name] = index
+				}
+			}
+		}
+		if section.err {
+			return true
+		}
+	}
+	if r.err {
+		return true
+	}
+
+	imported := mod.ImportedFunctions()
+	if len(imports) != len(imported) {
+		return false
+	}
+	for i, def := range imported {
+		if module, name, _ := def.Import(); imports[i] != [2]string{module, name} {
+			return false
+		}
+	}
+	defs := mod.ExportedFunctions()
+	if len(defs) != len(exports) {
+		return false
+	}
+	for name, def := range defs {
+		if index, ok := exports[name]; !ok || index != def.Index() {
+			return false
+		}
+	}
+	return true
+}
+
+// wasmFunctionBody is the code of a function defined in a WASM module.
+type wasmFunctionBody struct {
+	offset uint64 // offset of code in the content of the "Code" section
+	code   []byte // instructions of the function
+}
+
+// wasmFunctionBodies parses a WASM binary and returns the bodies of the
+// functions defined in the module, indexed by function index (the bodies of
+// imported functions are empty). Returns nil if the binary cannot be parsed.
+func wasmFunctionBodies(b []byte) []wasmFunctionBody {
+	const (
+		importSectionId = 2
+		codeSectionId   = 10
+	)
+	if len(b) < 8 {
+		return nil
+	}
+
+	imports := 0
+	r := wasmReader{b: b, off: 8} // skip magic+version
+	for r.off < len(r.b) && !r.err {
+		id := r.byte()
+		section := wasmReader{b: r.bytes(r.uvarint())}
+		switch id {
+		case importSectionId:
+			if imports = section.importedFunctions(); section.err {
+				return nil
+			}
+		case codeSectionId:
+			return section.functionBodies(imports)
+		}
+	}
+	return nil
+}
+
+// wasmFunctionNames parses a WASM binary and returns the names of its functions
+// by function index, read from the "name" custom section, or from the names of
+// the exports for the functions missing from the section.
+func wasmFunctionNames(b []byte) map[uint32]string {
+	const (
+		customSectionId = 0
+		exportSectionId = 7
+		functionNames   = 1
+	)
+	names := make(map[uint32]string)
+	exports := make(map[uint32]string)
+	if len(b) < 8 {
+		return names
+	}
+
+	r := wasmReader{b: b, off: 8} // skip magic+version
+	for r.off < len(r.b) && !r.err {
+		id := r.byte()
+		section := wasmReader{b: r.bytes(r.uvarint())}
+		switch id {
+		case exportSectionId:
+			for n := section.uvarint(); n > 0 && !section.err; n-- {
+				name := string(section.bytes(section.uvarint()))
+				kind := section.byte()
+				index := uint32(section.uvarint())
+				if _, ok := exports[index]; kind == 0 && !ok {
+					exports[index] = name
+				}
+			}
+		case customSectionId:
+			if string(section.bytes(section.uvarint())) != "name" {
+				continue
+			}
+			for section.off < len(section.b) && !section.err {
+				id := section.byte()
+				sub := wasmReader{b: section.bytes(section.uvarint())}
+				if id != functionNames {
+					continue
+				}
+				for n := sub.uvarint(); n > 0 && !sub.err; n-- {
+					index := uint32(sub.uvarint())
+					names[index] = string(sub.bytes(sub.uvarint()))
+				}
+			}
+		}
+	}
+	for index, name := range exports {
+		if _, ok := names[index]; !ok {
+			names[index] = name
+		}
+	}
+	return names
+}
+
+// wasmReader reads the values of a WASM binary, err is set when reading past
+// the end of b.
+type wasmReader struct {
+	b   []byte
+	off int
+	err bool
+}
+
+func (r *wasmReader) byte() byte {
+	if r.off >= len(r.b) {
+		r.err = true
+		return 0
+	}
+	c := r.b[r.off]
+	r.off++
+	return c
+}
+
+func (r *wasmReader) uvarint() uint64 {
+	v, n := binary.Uvarint(r.b[r.off:])
+	if n <= 0 {
+		r.err = true
+		r.off = len(r.b)
+		return 0
+	}
+	r.off += n
+	return v
+}
+
+func (r *wasmReader) varint() int64 {
+	var v int64
+	var c byte
+	shift := 0
+	for {
+		c = r.byte()
+		if r.err || shift >= 64 {
+			r.err = true
+			return 0
+		}
+		v |= int64(c&0x7f) << shift
+		shift += 7
+		if c&0x80 == 0 {
+			break
+		}
+	}
+	if shift < 64 && c&0x40 != 0 {
+		v |= ^0 << shift
+	}
+	return v
+}
+
+func (r *wasmReader) bytes(n uint64) []byte {
+	if n > uint64(len(r.b)-r.off) {
+		r.err = true
+		r.off = len(r.b)
+		return nil
+	}
+	b := r.b[r.off : r.off+int(n)]
+	r.off += int(n)
+	return b
+}
+
+func (r *wasmReader) limits() {
+	flags := r.byte()
+	r.uvarint() // min
+	if flags&1 != 0 {
+		r.uvarint() // max
+	}
+}
+
+// importedFunctions returns the number of functions imported by the content of
+// an "Import" section.
+func (r *wasmReader) importedFunctions() int {
+	return len(r.importedFunctionNames())
+}
+
+// importedFunctionNames returns the module and name of the functions imported
+// by the content of an "Import" section.
+func (r *wasmReader) importedFunctionNames() [][2]string {
+	var functions [][2]string
+	for n := r.uvarint(); n > 0 && !r.err; n-- {
+		module := r.bytes(r.uvarint())
+		name := r.bytes(r.uvarint())
+		switch r.byte() {
+		case 0x00: // function
+			r.uvarint()
+			functions = append(functions, [2]string{string(module), string(name)})
+		case 0x01: // table
+			r.byte()
+			r.limits()
+		case 0x02: // memory
+			r.limits()
+		case 0x03: // global
+			r.byte()
+			r.byte()
+		case 0x04: // tag
+			r.byte()
+			r.uvarint()
+		default:
+			r.err = true
+		}
+	}
+	return functions
+}
+
+// functionBodies returns the bodies of the functions of the content of a "Code"
+// section, preceded by the given number of imported functions.
+func (r *wasmReader) functionBodies(imports int) []wasmFunctionBody {
+	n := r.uvarint()
+	if r.err || n > uint64(len(r.b)) {
+		return nil
+	}
+	bodies := make([]wasmFunctionBody, imports+int(n))
+	for i := imports; i < len(bodies) && !r.err; i++ {
+		body := wasmReader{b: r.bytes(r.uvarint())}
+		// The instructions follow the declarations of the locals.
+		for locals := body.uvarint(); locals > 0 && !body.err; locals-- {
+			body.uvarint()
+			body.byte()
+		}
+		if body.err {
+			return nil
+		}
+		bodies[i] = wasmFunctionBody{
+			offset: uint64(r.off - len(body.b) + body.off),
+			code:   body.b[body.off:],
+		}
+	}
+	if r.err {
+		return nil
+	}
+	return bodies
+}
+
 // dataIterator iterates over the segments contained in a wasm Data section.
 // Only support mode 0 (memory 0 + offset) segments.
 type dataIterator struct {
@@ -1,12 +1,22 @@
 package wzprof
 
 import (
+	"compress/gzip"
 	"context"
+	"errors"
 	"fmt"
 	"hash/maphash"
+	"io"
+	"log"
+	"math"
 	"net/http"
 	"os"
+	"regexp"
+	"runtime/debug"
+	"sort"
+	"strconv"
 	"strings"
+	"sync"
 	"time"
 	"unsafe"
 
@@ -24,27 +34,419 @@ type Profiling struct {
 
 	onlyFunctions     map[string]struct{}
 	filteredFunctions map[string]struct{}
+	include           *regexp.Regexp
+	exclude           *regexp.Regexp
+	elide             *regexp.Regexp
 	symbols           symbolizer
+	entryOffsets      []uint64
 	stackIterator     func(mod api.Module, def api.FunctionDefinition, wasmsi experimental.StackIterator) experimental.StackIterator
 
-	lang          language
-	prepareCalled bool // Flag to indicate if Prepare has been called
+	threads         threadIDs
+	lang            language
+	prepareCalled   bool // Flag to indicate if Prepare has been called
+	binaryMismatch  bool // The last prepared module was not compiled from wasm
+	allowUnprepared bool
+	stripTemplates  bool
+	hostFrames      bool
+	nativeFrames    bool
+	deterministic   bool
+	selfProfile     bool
+	overhead        profilingOverhead
+	labelInstances  bool
+	labelExports    bool
+	contextLabels   func(ctx context.Context) []string
+	labelCache      threadLocal[labelCache]
+	sampleHook      func(*ObservedSample) bool
+	onProfileStart  func(name string)
+	onProfileEnd    func(name string, prof *profile.Profile)
+	maxStacks       int
+	maxFrames       int
+	progress        func(done, total int)
+	debugInfo       string
+	sourceMap       string
+	moduleFile      string
+	buildID         string
+	args            []string
+	allocators      map[string]Allocator
+	pythonLayout    *pythonLayout
+	monoLayout      *monoLayout
+	rubyLayout      *rubyLayout
+	tinygoHeapStart uint32 // address of runtime.heapStart in TinyGo modules
 }
 
+// ProfilingOption is a type used to represent configuration options for
+// Profiling instances created by ProfilingFor.
+type ProfilingOption func(*Profiling)
+
+// AllowUnprepared configures the profilers to record samples even if
+// Profiling.Prepare was not called on the compiled module. The resulting
+// profiles are not symbolized and may be missing frames of the guest call
+// stacks.
+//
+// By default, a module calling functions instrumented by profilers of an
+// unprepared Profiling fails with an error describing the missing step.
+func AllowUnprepared(enable bool) ProfilingOption {
+	return func(p *Profiling) { p.allowUnprepared = enable }
+}
+
+// StripTemplates configures the profiles to omit the template arguments from
+// the demangled names of C++ functions, which can make the names of functions
+// using the standard library significantly shorter.
+//
+// Template arguments are included by default.
+func StripTemplates(enable bool) ProfilingOption {
+	return func(p *Profiling) { p.stripTemplates = enable }
+}
+
+// HostFrames configures the profiles to represent the calls to host functions
+// as explicit frames named after the host module and function, with a "host:"
+// prefix (e.g. "host:wasi_snapshot_preview1.fd_write"), so flame graphs clearly
+// separate the time spent in the guest from the time spent in the host.
+//
+// The CPU profiler also accounts for the time spent in host functions when the
+// option is enabled, as if it was configured with HostTime.
+//
+// Host functions are named like guest functions by default.
+func HostFrames(enable bool) ProfilingOption {
+	return func(p *Profiling) { p.hostFrames = enable }
+}
+
+// NativeFrames configures the profiles of Python programs to merge the frames
+// of the native functions of the module with the frames of the Python call
+// stacks, so the C functions called by Python code (e.g. the functions of
+// extension modules) appear under their Python callers. Each call to the loop
+// of the interpreter is replaced by the Python functions that it executes, the
+// other functions are symbolized from the debug information of the module.
+//
+// All the functions of the module are then instrumented instead of only the
+// calls made by the interpreter, which increases the overhead of profiling.
+//
+// Only the Python call stacks are reported by default.
+func NativeFrames(enable bool) ProfilingOption {
+	return func(p *Profiling) { p.nativeFrames = enable }
+}
+
+// Filter restricts the instrumentation of the CPU, stack depth and call count
+// profilers to the guest functions of which the name matches the regular
+// expression, for example "^my_app::" to only instrument the functions of a
+// Rust crate. The expression is matched against both the name of functions in
+// the wasm name section and its demangled form.
+//
+// Instrumenting fewer functions reduces the overhead of profiling large
+// modules. The call stacks of the samples still include all the frames, and
+// the CPU time of the functions that are not instrumented is accounted to their
+// closest instrumented caller.
+//
+// All functions are instrumented by default.
+func Filter(include *regexp.Regexp) ProfilingOption {
+	return func(p *Profiling) { p.include = include }
+}
+
+// Exclude is like Filter but prevents the instrumentation of the guest
+// functions of which the name matches the regular expression. Functions
+// matching both Filter and Exclude are not instrumented.
+func Exclude(exclude *regexp.Regexp) ProfilingOption {
+	return func(p *Profiling) { p.exclude = exclude }
+}
+
+// DefaultElidedFrames matches the names of the trampolines generated by the
+// toolchains, which are elided from the call stacks by default (see
+// ElideFrames): the shims of wasm-bindgen, the functions wrapped with the
+// --wrap option of the linker, the dynCall and invoke trampolines of
+// Emscripten, the vtable shims of Rust, and the stack switches of the Go
+// runtime.
+var DefaultElidedFrames = regexp.MustCompile(`^(__wbg_|__wrap_|wasm_bindgen::convert::closures::invoke|(dynCall|invoke)_[vijfd]+$|runtime\.(systemstack(_switch)?|morestack(_noctxt)?)$)|(\{\{vtable\.shim\}\}|\{shim:vtable#\d+\})$`)
+
+// ElideFrames configures the frames removed from the call stacks of the
+// profiles, which are those of the functions of which the name matches the
+// regular expression. The values of the samples of which the innermost frames
+// are elided are attributed to their callers, so the profiles show the code of
+// the application rather than the trampolines between its functions. Frames of
+// inlined functions are only elided if all the functions of the frame match.
+//
+// Unlike Exclude, the functions are still instrumented. A nil expression
+// disables the elision of frames.
+//
+// Default to DefaultElidedFrames.
+func ElideFrames(elide *regexp.Regexp) ProfilingOption {
+	return func(p *Profiling) { p.elide = elide }
+}
+
+// ExternalDebugInfo configures the location of a wasm file holding the DWARF
+// sections of modules stripped of their debug information, for example the
+// .debug.wasm file produced alongside the module. The location is either a
+// path on the file system or a http URL.
+//
+// The location is only used if the module does not embed DWARF sections, and
+// takes precedence over the external_debug_info custom section of the module.
+// Prepare returns an error if the debug information cannot be loaded.
+func ExternalDebugInfo(location string) ProfilingOption {
+	return func(p *Profiling) { p.debugInfo = location }
+}
+
+// SourceMap configures the location of the source map of modules compiled by
+// toolchains which do not emit DWARF, for example AssemblyScript. The location
+// is either a path on the file system or a http URL.
+//
+// The location is only used if the module does not embed DWARF sections, and
+// takes precedence over the sourceMappingURL custom section of the module.
+// Prepare returns an error if the source map cannot be loaded.
+func SourceMap(location string) ProfilingOption {
+	return func(p *Profiling) { p.sourceMap = location }
+}
+
+// MaxStacks bounds the number of call stacks recorded by each profiler, which
+// keeps the memory used by wzprof constant during long profiling sessions.
+// When the limit is exceeded, the samples of the call stacks with the lowest
+// values are merged into the samples of their callers rather than dropped, so
+// the profiles still account for them at a coarser granularity. Profilers
+// recording the samples of each thread separately apply the limit to each
+// thread.
+//
+// Default to zero, which means that the number of call stacks is not bounded.
+func MaxStacks(limit int) ProfilingOption {
+	return func(p *Profiling) { p.maxStacks = limit }
+}
+
+// MaxFrames bounds the number of frames of the call stacks recorded by the
+// profilers. Deep recursions (e.g. of Python programs) produce stacks of
+// thousands of frames, which make profiles large and slow to capture. The
+// innermost frames of the stacks exceeding the limit are retained, and the
+// outermost frames are replaced by a single frame named "...truncated".
+//
+// The stack depth profiler still reports the depth of the complete stacks.
+//
+// Default to 128, a limit of zero or less records the complete stacks.
+func MaxFrames(limit int) ProfilingOption {
+	return func(p *Profiling) { p.maxFrames = limit }
+}
+
+const defaultMaxFrames = 128
+
+// truncatedFrameName is the name of the frame which replaces the outermost
+// frames of the call stacks exceeding the limit set by MaxFrames.
+const truncatedFrameName = "...truncated"
+
+// LabelInstances configures the profilers to label the samples with the name
+// of the module instance that they were recorded on, as the "instance" label.
+// When a compiled module is instantiated multiple times (e.g. by a pool of
+// workers), the instances are threads of the profiles and the samples of each
+// instance can be selected with the label, for example with the -tagfocus
+// option of pprof. Anonymous instances are not labeled. The threads of closed
+// instances are not reused by the next instances while the option is enabled,
+// so the samples of each instance keep its name.
+//
+// Default to false, the samples are only labeled with the numeric identifier
+// of their thread when there are multiple threads.
+func LabelInstances(enable bool) ProfilingOption {
+	return func(p *Profiling) {
+		p.labelInstances = enable
+		p.threads.unique = enable
+	}
+}
+
+// LabelExports configures the profilers to label the samples with the name of
+// the exported function at the root of their call stack, as the "export" label,
+// which is the entry point that the host called (e.g. "_start" or a request
+// handler). The samples of each entry point can then be selected with the
+// -tagfocus option of pprof, without labeling the calls with WithLabels.
+//
+// Stacks truncated by MaxFrames and stacks of which the root is not an exported
+// function, like the stacks unwound from the memory of Go programs or of the
+// Python interpreter, are not labeled. Labels of the same key set by
+// WithLabels or ContextLabels take precedence.
+//
+// Default to false.
+func LabelExports(enable bool) ProfilingOption {
+	return func(p *Profiling) { p.labelExports = enable }
+}
+
+// ContextLabels configures a function returning labels to add to the samples
+// recorded during calls made with a context, as key/value pairs like the
+// arguments of WithLabels. The function is called by the listeners of the
+// profilers, for each call that they observe, so the labels are derived from
+// the state of the program that the context carries without the callers of the
+// guest functions having to use WithLabels.
+//
+// For example, the samples can be labeled with the identifiers of the active
+// OpenTelemetry span, which continuous profiling backends use to link traces
+// to profiles:
+//
+//	wzprof.ContextLabels(func(ctx context.Context) []string {
+//		span := trace.SpanContextFromContext(ctx)
+//		if !span.IsValid() {
+//			return nil
+//		}
+//		return []string{"trace_id", span.TraceID().String(), "span_id", span.SpanID().String()}
+//	})
+//
+// The labels override the labels of the same keys set by WithLabels. Labels
+// with many distinct values like span identifiers split the samples of a call
+// stack, MaxStacks keeps the memory used by the profilers bounded.
+func ContextLabels(labels func(ctx context.Context) []string) ProfilingOption {
+	return func(p *Profiling) { p.contextLabels = labels }
+}
+
+// SampleHook configures a function called with each sample observed by the
+// profilers before it is added to the profiles. The function returns false to
+// drop the sample, and may change its value and labels, for example to drop
+// the samples of the calls blocked polling for events:
+//
+//	wzprof.SampleHook(func(s *wzprof.ObservedSample) bool {
+//		return s.Stack[0].Definition().Name() != "poll_oneoff"
+//	})
+//
+// The function is called by the listeners of the profilers, concurrently when
+// the program has multiple threads, and should return quickly since it delays
+// the guest. Samples can be redirected to another destination by copying them
+// and returning false. The call count profiler, which does not record call
+// stacks, does not call the function.
+func SampleHook(hook func(s *ObservedSample) bool) ProfilingOption {
+	return func(p *Profiling) { p.sampleHook = hook }
+}
+
+// OnProfileStart configures a function called with the name of a profiler when
+// it starts recording a profile: when StartProfile of the CPU or memory
+// profilers succeeds, when ProfileCall is invoked, and when the http handlers
+// begin recording the profile of a "seconds" query parameter.
+func OnProfileStart(hook func(name string)) ProfilingOption {
+	return func(p *Profiling) { p.onProfileStart = hook }
+}
+
+// OnProfileEnd configures a function called with each profile returned by the
+// profilers (e.g. by StopProfile or NewProfile), served by their http handlers,
+// or uploaded by Push, along with the name of the profiler. Programs embedding
+// wzprof can use it to upload, log or annotate all the profiles that they
+// capture without wrapping the handlers or the CLI. The function may modify the
+// profile, for example to record the version of the program:
+//
+//	wzprof.OnProfileEnd(func(name string, prof *profile.Profile) {
+//		prof.Comments = append(prof.Comments, "build="+buildSHA)
+//	})
+//
+// The function is called synchronously before the profile is returned, slow
+// work like uploads should be done on a copy of the profile in a separate
+// goroutine. The heap snapshots are reported under the name "heap". When the
+// function is set, the memory profiles served by the handler are built in
+// memory instead of being streamed (see MemoryProfiler.WriteProfile).
+func OnProfileEnd(hook func(name string, prof *profile.Profile)) ProfilingOption {
+	return func(p *Profiling) { p.onProfileEnd = hook }
+}
+
+// Deterministic configures the profilers to generate profiles which are the
+// same when profiling the same module with the same inputs, for example to
+// compare them with golden files in tests. The samples, locations and
+// functions of the profiles are sorted, and the time, duration and version of
+// wzprof are not recorded.
+//
+// The decisions of the Sampler are already deterministic since it samples one
+// every N calls of each thread, but the values of the profilers measuring time
+// are not unless they are configured with a TimeFunc returning predictable
+// times.
+func Deterministic(enable bool) ProfilingOption {
+	return func(p *Profiling) { p.deterministic = enable }
+}
+
+// SelfProfile configures the profilers to measure the time that they spend in
+// their function listeners and symbolizing the call stacks of the profiles,
+// which is the overhead of profiling the module.
+//
+// The CPU profiles then have a sample of a wzprof frame holding the time spent
+// in the listeners during the profile, so the overhead is visible next to the
+// guest functions in flame graphs, and all the profiles have a comment with the
+// cumulative overhead since the profilers were created. The overhead is not
+// reported in deterministic profiles (see Deterministic).
+//
+// Default to false.
+func SelfProfile(enable bool) ProfilingOption {
+	return func(p *Profiling) { p.selfProfile = enable }
+}
+
+// ModuleFile sets the file name of the wasm module (e.g. app.wasm) recorded in
+// the mapping of the profiles, along with the build id of the module. Profiles
+// have a mapping if the file name is set or the build id can be determined.
+func ModuleFile(name string) ProfilingOption {
+	return func(p *Profiling) { p.moduleFile = name }
+}
+
+// CommandLine sets the command line of the guest (e.g. the name of the module
+// followed by its arguments), which the pprof handlers serve at the /cmdline
+// endpoint of the guest profiles.
+func CommandLine(args ...string) ProfilingOption {
+	return func(p *Profiling) { p.args = args }
+}
+
+// Allocators configures the memory profilers to intercept the given memory
+// allocation functions, which allows profiling the memory of modules using
+// allocators with non-standard exports, for example mimalloc:
+//
+//	wzprof.Allocators(
+//		wzprof.MallocFunc("mi_malloc", 0),
+//		wzprof.CallocFunc("mi_calloc", 0, 1),
+//		wzprof.ReallocFunc("mi_realloc", 0, 1),
+//		wzprof.FreeFunc("mi_free", 0),
+//	)
+//
+// The allocators take precedence over the functions of the same names that
+// the memory profiler recognizes (see MemoryProfiler.NewFunctionListener).
+func Allocators(allocators ...Allocator) ProfilingOption {
+	return func(p *Profiling) {
+		if p.allocators == nil {
+			p.allocators = make(map[string]Allocator, len(allocators))
+		}
+		for _, a := range allocators {
+			p.allocators[a.name] = a
+		}
+	}
+}
+
+// PrepareProgress configures a function called by Prepare to report progress
+// while parsing the DWARF sections of the module, which can take a while for
+// large modules. The function is called with the number of compilation units
+// parsed so far and the total number of compilation units of the module, from
+// the goroutines parsing them, but calls are never made concurrently.
+//
+// Modules prepared from the cache of a previous call to Prepare or Preload
+// are not parsed again, and report no progress.
+func PrepareProgress(progress func(done, total int)) ProfilingOption {
+	return func(p *Profiling) { p.progress = progress }
+}
+
+// errNotPrepared is the error raised when profilers observe function calls of
+// a module that Profiling.Prepare was not called for.
+var errNotPrepared = errors.New("wzprof: Profiling.Prepare must be called on the compiled module before instantiating it")
+
+// errMismatch is the error returned by Profiling.Prepare when the compiled
+// module of a language runtime was not compiled from the binary passed to
+// ProfilingFor, for example when a module loaded from a compilation cache is
+// prepared with the binary of another version of the module.
+var errMismatch = errors.New("wzprof: the compiled module does not match the binary passed to ProfilingFor")
+
+// errComponent is the error returned by Profiling.Prepare for binaries of the
+// component model, which nest core modules that the profilers cannot tell
+// apart yet.
+var errComponent = errors.New("wzprof: profiling components of the component model is not supported, profile their core modules instead")
+
 type language int8
 
 const (
 	unknown language = iota
 	golang
-	python311
+	python3
+	tinygo
+	dotnet
+	cruby
 )
 
 // ProfilingFor a given wasm binary. The resulting Profiling needs to be
 // prepared after Wazero module compilation.
-func ProfilingFor(wasm []byte) *Profiling {
+func ProfilingFor(wasm []byte, options ...ProfilingOption) *Profiling {
 	r := &Profiling{
-		wasm:    wasm,
-		symbols: noopsymbolizer{},
+		wasm:      wasm,
+		symbols:   noopsymbolizer{},
+		elide:     DefaultElidedFrames,
+		buildID:   wasmBuildID(wasm),
+		maxFrames: defaultMaxFrames,
 		stackIterator: func(mod api.Module, def api.FunctionDefinition, wasmsi experimental.StackIterator) experimental.StackIterator {
 			return wasmsi
 		},
@@ -81,8 +483,9 @@ func ProfilingFor(wasm []byte) *Profiling {
 			"memcmp":                  {},
 			"memchr":                  {},
 		}
-	} else if supportedPython(wasm) {
-		r.lang = python311
+	} else if layout := supportedPython(wasm); layout != nil {
+		r.lang = python3
+		r.pythonLayout = layout
 		r.onlyFunctions = map[string]struct{}{
 			"PyObject_Vectorcall": {},
 			// Those functions are also likely candidate for useful profiling.
@@ -91,76 +494,293 @@ func ProfilingFor(wasm []byte) *Profiling {
 			// "_PyEval_EvalFrameDefault": {},
 			// "_PyEvalFramePushAndInit": {},
 		}
+	} else if binCompiledByTinyGo(wasm) {
+		r.lang = tinygo
+		r.tinygoHeapStart = tinygoHeapStart(wasm)
+	} else if layout := supportedMono(wasm); layout != nil {
+		r.lang = dotnet
+		r.monoLayout = layout
+	} else if layout := supportedRuby(wasm); layout != nil {
+		r.lang = cruby
+		r.rubyLayout = layout
 	}
 
+	for _, opt := range options {
+		opt(r)
+	}
+	if r.lang == python3 && r.nativeFrames {
+		// The native functions called by the interpreter are sampled as
+		// well.
+		r.onlyFunctions = nil
+	}
 	return r
 }
 
 // Prepare selects the most appropriate analysis functions for the guest
 // code in the provided module.
+//
+// The profilers of p may be created before the module is compiled, but
+// Prepare must be called before the module is instantiated, the profilers walk
+// the call stacks of the guest with the analysis functions that it selects
+// (see AllowUnprepared). The tracer and the memory timeline do not require it.
+//
+// Binaries of the component model are not supported, Prepare returns an error
+// for them.
 func (p *Profiling) Prepare(mod wazero.CompiledModule) error {
+	if wasmIsComponent(p.wasm) {
+		return errComponent
+	}
+	// The binary is only used for this module if it was compiled from it,
+	// the other modules prepared by p are not affected.
+	wasm, matched := p.wasm, true
+	if len(wasm) > 0 && !wasmMatchesModule(wasm, mod) {
+		// The support of language runtimes is derived from the binary,
+		// other modules can be symbolized with their custom sections.
+		if p.lang != unknown {
+			return errMismatch
+		}
+		log.Printf("preparing module without its binary: the compiled module was not compiled from the binary passed to ProfilingFor")
+		wasm, matched = nil, false
+	}
+	// The runtime may not keep the custom sections of the module, see
+	// MapFile.
+	mod = withBinarySections(mod, wasm)
 	switch p.lang {
 	case golang:
-		s, err := preparePclntabSymbolizer(p.wasm, mod)
+		s, err := preparePclntabSymbolizer(wasm, mod)
 		if err != nil {
 			return err
 		}
 
-		p.symbols = s
-		si := &goStackIterator{
-			pclntab:  s,
-			unwinder: unwinder{symbols: s},
-		}
+		p.symbols = newSymbolCache(s)
+		// Each instance of the module walks the stacks of its own memory,
+		// the iterators are not shared by concurrent instances.
+		var iterators threadLocal[goStackIterator]
 		p.stackIterator = func(mod api.Module, def api.FunctionDefinition, wasmsi experimental.StackIterator) experimental.StackIterator {
 			imod := mod.(experimental.InternalModule)
+			si := iterators.get(mod)
+			si.pclntab, si.symbols = s, s
 			si.mem = imod.Memory()
 			si.pclntab.EnsureReady(si.mem)
 			sp0 := uint32(imod.Global(0).Get())
 			gp0 := imod.Global(2).Get()
 			pc0 := si.symbols.FIDToPC(fid(def.Index()))
-			si.initAt(ptr64(pc0), ptr64(sp0), 0, gptr(gp0), 0)
+			// Samples taken while the goroutine runs on the system stack
+			// (e.g. during stack growth or garbage collection) resume at
+			// the user stack saved in g.sched, and unexpected frames stop
+			// the traceback instead of failing the call.
+			si.initAt(ptr64(pc0), ptr64(sp0), 0, gptr(gp0), unwindJumpStack|unwindSilentErrors)
 			si.first = true
 			return si
 		}
-	case python311:
-		py, err := preparePython(mod)
+	default:
+		m, err := p.prepareCached(mod, wasm)
 		if err != nil {
 			return err
 		}
-		p.symbols = py
-		p.stackIterator = py.Stackiter
-	default:
-		dwarf, err := newDwarfparser(mod)
-		if err != nil {
-			return nil // TODO: surface error as warning?
+		p.symbols = m.symbols
+		p.entryOffsets = m.entryOffsets
+		if m.stackIterator != nil {
+			p.stackIterator = m.stackIterator
 		}
-		p.symbols = buildDwarfSymbolizer(dwarf)
 	}
 
 	// Set the flag to true if Prepare succeeds
 	p.prepareCalled = true
+	p.binaryMismatch = !matched
 
 	return nil
 }
 
+// prepareModule computes the state used to symbolize the call stacks of
+// modules which are not Go programs, see prepareCached.
+func (p *Profiling) prepareModule(mod wazero.CompiledModule, wasm []byte) (*preparedModule, error) {
+	m := new(preparedModule)
+	switch p.lang {
+	case python3:
+		var symbols symbolizer
+		if p.nativeFrames {
+			s, err := p.prepareDebugInfo(mod, wasm)
+			if err != nil {
+				return nil, err
+			}
+			symbols = newSymbolCache(s)
+		}
+		py, err := preparePython(mod, p.pythonLayout, symbols)
+		if err != nil {
+			return nil, err
+		}
+		m.symbols = py
+		m.stackIterator = py.Stackiter
+	case dotnet:
+		s, err := p.prepareDebugInfo(mod, wasm)
+		if err != nil {
+			return nil, err
+		}
+		mono, err := prepareMono(mod, p.monoLayout, newSymbolCache(s))
+		if err != nil {
+			return nil, err
+		}
+		m.symbols = mono
+		m.stackIterator = mono.Stackiter
+	case cruby:
+		rb, err := prepareRuby(mod, p.rubyLayout)
+		if err != nil {
+			return nil, err
+		}
+		m.symbols = rb
+		m.stackIterator = rb.Stackiter
+	default:
+		s, err := p.prepareDebugInfo(mod, wasm)
+		if err != nil {
+			return nil, err
+		}
+		m.symbols = newSymbolCache(s)
+		m.entryOffsets = wasmFunctionOffsets(wasm)
+	}
+	return m, nil
+}
+
+// prepareDebugInfo returns the symbolizer of modules which are not recognized
+// by the language-specific heuristics, based on the DWARF sections or the
+// source map of the module. Modules without debug information can still be
+// profiled, the locations are only left unsymbolized.
+func (p *Profiling) prepareDebugInfo(mod wazero.CompiledModule, wasm []byte) (symbolizer, error) {
+	switch {
+	case hasDwarf(mod):
+		dwarf, err := newDwarfparser(mod)
+		if err != nil {
+			log.Printf("preparing module without symbols: %s", err)
+			return noopsymbolizer{}, nil
+		}
+		return buildDwarfSymbolizer(dwarf, p.progress), nil
+	case p.debugInfo != "":
+		dwarf, err := newDwarfParserFromURL(p.debugInfo)
+		if err != nil {
+			return nil, err
+		}
+		return buildDwarfSymbolizer(dwarf, p.progress), nil
+	case p.sourceMap != "":
+		return newSourceMapSymbolizer(wasm, p.sourceMap)
+	}
+
+	if location := customSectionURL(mod, externalDebugInfo); location != "" {
+		dwarf, err := newDwarfParserFromURL(location)
+		if err == nil {
+			return buildDwarfSymbolizer(dwarf, p.progress), nil
+		}
+		log.Printf("preparing module without external debug info: %s", err)
+	}
+	if location := customSectionURL(mod, sourceMappingURL); location != "" {
+		s, err := newSourceMapSymbolizer(wasm, location)
+		if err == nil {
+			return s, nil
+		}
+		log.Printf("preparing module without source map: %s", err)
+	}
+	log.Printf("preparing module without symbols: no debug information found")
+	return noopsymbolizer{}, nil
+}
+
 // CPUProfiler constructs a new instance of CPUProfiler using the given time
 // function to record the CPU time consumed.
+//
+// See Prepare for when the module must be prepared.
 func (p *Profiling) CPUProfiler(options ...CPUProfilerOption) *CPUProfiler {
-	if !p.prepareCalled {
-		panic("Profiling.Prepare must be called before creating a CPU profiler")
-	}
 	return newCPUProfiler(p, options...)
 }
 
 // MemoryProfiler constructs a new instance of MemoryProfiler using the given
 // time function to record the profile execution time.
+//
+// See Prepare for when the module must be prepared.
 func (p *Profiling) MemoryProfiler(options ...MemoryProfilerOption) *MemoryProfiler {
-	if !p.prepareCalled {
-		panic("Profiling.Prepare must be called before creating a Memory profiler")
-	}
 	return newMemoryProfiler(p, options...)
 }
 
+// BlockProfiler constructs a new instance of BlockProfiler recording the time
+// spent by the module in calls to host functions.
+//
+// See Prepare for when the module must be prepared.
+func (p *Profiling) BlockProfiler() *BlockProfiler {
+	return newBlockProfiler(p)
+}
+
+// MutexProfiler constructs a new instance of MutexProfiler recording the time
+// spent by threads of the module waiting on locks.
+//
+// See Prepare for when the module must be prepared.
+func (p *Profiling) MutexProfiler() *MutexProfiler {
+	return newMutexProfiler(p)
+}
+
+// GrowProfiler constructs a new instance of GrowProfiler recording the call
+// stacks which grow the linear memory of the module.
+//
+// See Prepare for when the module must be prepared.
+func (p *Profiling) GrowProfiler() *GrowProfiler {
+	return newGrowProfiler(p)
+}
+
+// StackProfiler constructs a new instance of StackProfiler recording the
+// deepest stacks reached by the call paths of the module.
+//
+// See Prepare for when the module must be prepared.
+func (p *Profiling) StackProfiler() *StackProfiler {
+	return newStackProfiler(p)
+}
+
+// SyscallProfiler constructs a new instance of SyscallProfiler recording the
+// calls of the module to WASI host functions.
+//
+// See Prepare for when the module must be prepared.
+func (p *Profiling) SyscallProfiler() *SyscallProfiler {
+	return newSyscallProfiler(p)
+}
+
+// GCProfiler constructs a new instance of GCProfiler recording the time spent
+// by the garbage collectors of the module.
+//
+// See Prepare for when the module must be prepared.
+func (p *Profiling) GCProfiler() *GCProfiler {
+	return newGCProfiler(p)
+}
+
+// TrapProfiler constructs a new instance of TrapProfiler recording the call
+// stacks of the module when it traps.
+//
+// See Prepare for when the module must be prepared.
+func (p *Profiling) TrapProfiler() *TrapProfiler {
+	return newTrapProfiler(p)
+}
+
+// CallProfiler constructs a new instance of CallProfiler counting the calls to
+// the functions of the module.
+//
+// See Prepare for when the module must be prepared.
+func (p *Profiling) CallProfiler() *CallProfiler {
+	return newCallProfiler(p)
+}
+
+// Tracer constructs a new instance of Tracer recording the calls to the
+// functions of the module as events of a timeline.
+//
+// The tracer may be created before the module is compiled, it does not require
+// Prepare to be called since it does not walk the call stacks.
+func (p *Profiling) Tracer(options ...TracerOption) *Tracer {
+	return newTracer(p, options...)
+}
+
+// MemoryTimeline constructs a new instance of MemoryTimeline recording the
+// memory usage of the module over time. The memory profiler may be nil, the
+// size of the objects in use is not recorded then.
+//
+// The timeline may be created before the module is compiled, it does not
+// require Prepare to be called since it does not walk the call stacks.
+func (p *Profiling) MemoryTimeline(mem *MemoryProfiler, options ...MemoryTimelineOption) *MemoryTimeline {
+	return newMemoryTimeline(p, mem, options...)
+}
+
 // profilingListener wraps a FunctionListener to adapt its stack iterator to the
 // appropriate implementation according to the module support.
 type profilingListener struct {
@@ -169,16 +789,28 @@ type profilingListener struct {
 }
 
 func (s profilingListener) Before(ctx context.Context, mod api.Module, def api.FunctionDefinition, params []uint64, si experimental.StackIterator) {
+	if !s.s.prepareCalled && !s.s.allowUnprepared {
+		// Function listeners have no other way to report errors, the panic
+		// is recovered by wazero and returned to the caller as an error
+		// (e.g. when instantiating the module).
+		panic(errNotPrepared)
+	}
+	start := s.s.overheadStart()
 	si = s.s.stackIterator(mod, def, si)
-	s.l.Before(ctx, mod, def, params, si)
+	s.l.Before(s.s.labelContext(ctx, mod), mod, def, params, si)
+	s.s.overheadEnd(&s.s.overhead.listeners, start)
 }
 
 func (s profilingListener) After(ctx context.Context, mod api.Module, def api.FunctionDefinition, results []uint64) {
-	s.l.After(ctx, mod, def, results)
+	start := s.s.overheadStart()
+	s.l.After(s.s.labelContext(ctx, mod), mod, def, results)
+	s.s.overheadEnd(&s.s.overhead.listeners, start)
 }
 
 func (s profilingListener) Abort(ctx context.Context, mod api.Module, def api.FunctionDefinition, err error) {
-	s.l.Abort(ctx, mod, def, err)
+	start := s.s.overheadStart()
+	s.l.Abort(s.s.labelContext(ctx, mod), mod, def, err)
+	s.s.overheadEnd(&s.s.overhead.listeners, start)
 }
 
 // Profiler is an interface implemented by all profiler types available in this
@@ -207,19 +839,80 @@ type Profiler interface {
 var (
 	_ Profiler = (*CPUProfiler)(nil)
 	_ Profiler = (*MemoryProfiler)(nil)
+	_ Profiler = (*BlockProfiler)(nil)
+	_ Profiler = (*MutexProfiler)(nil)
+	_ Profiler = (*GrowProfiler)(nil)
+	_ Profiler = (*StackProfiler)(nil)
+	_ Profiler = (*TrapProfiler)(nil)
+	_ Profiler = (*CallProfiler)(nil)
+	_ Profiler = (*SyscallProfiler)(nil)
+	_ Profiler = (*GCProfiler)(nil)
 )
 
 //go:linkname nanotime runtime.nanotime
 func nanotime() int64
 
 // WriteProfile writes a profile to a file at the given path.
-func WriteProfile(path string, prof *profile.Profile) error {
+func WriteProfile(path string, prof *profile.Profile, options ...WriteOption) error {
 	w, err := os.Create(path)
 	if err != nil {
 		return err
 	}
 	defer w.Close()
-	return prof.Write(w)
+	return EncodeProfile(w, prof, options...)
+}
+
+// WriteOption is a type used to represent configuration options of the
+// encoding of profiles by WriteProfile and EncodeProfile.
+type WriteOption func(*writeConfig)
+
+type writeConfig struct {
+	level        int
+	uncompressed bool
+}
+
+// CompressionLevel configures the gzip compression level of profiles, between
+// gzip.BestSpeed and gzip.BestCompression. Large profiles (e.g. the heap
+// profiles of Python programs) are much faster to write at lower levels.
+//
+// Default to gzip.DefaultCompression.
+func CompressionLevel(level int) WriteOption {
+	return func(c *writeConfig) { c.level = level }
+}
+
+// Uncompressed configures profiles to be written without gzip compression,
+// which pprof also accepts.
+//
+// Default to false.
+func Uncompressed(enable bool) WriteOption {
+	return func(c *writeConfig) { c.uncompressed = enable }
+}
+
+// EncodeProfile writes prof to w in the protobuf format of pprof, compressed
+// with gzip unless configured with Uncompressed. The compressed profile is
+// written to w as it is produced rather than buffered.
+func EncodeProfile(w io.Writer, prof *profile.Profile, options ...WriteOption) error {
+	return encodeProfile(w, options, prof.WriteUncompressed)
+}
+
+// encodeProfile calls write with w, or a gzip writer compressing the output to
+// w, according to the options.
+func encodeProfile(w io.Writer, options []WriteOption, write func(io.Writer) error) error {
+	c := writeConfig{level: gzip.DefaultCompression}
+	for _, opt := range options {
+		opt(&c)
+	}
+	if c.uncompressed {
+		return write(w)
+	}
+	zw, err := gzip.NewWriterLevel(w, c.level)
+	if err != nil {
+		return err
+	}
+	if err := write(zw); err != nil {
+		return err
+	}
+	return zw.Close()
 }
 
 type symbolizer interface {
@@ -232,8 +925,64 @@ type symbolizer interface {
 
 type noopsymbolizer struct{}
 
+// Locations returns no source locations, but the address of the wasm code of
+// the guest functions is retained so the samples of the profiles can still be
+// attributed to instructions (see Annotate).
 func (s noopsymbolizer) Locations(fn experimental.InternalFunction, pc experimental.ProgramCounter) (uint64, []location) {
-	return 0, nil
+	if fn.Definition().GoFunction() != nil {
+		return 0, nil
+	}
+	return fn.SourceOffsetForPC(pc), nil
+}
+
+// symbolCache is a symbolizer memoizing the locations resolved by another
+// symbolizer for each code offset of the functions of a module.
+//
+// Call stacks are recorded unsymbolized, the locations are resolved when the
+// profiles are built. The pprof handlers and continuous profiling build many
+// profiles from the same call stacks, so the cache is shared by all the
+// profiles to avoid repeating lookups of the DWARF line tables.
+//
+// Only symbolizers of the wasm code are cached, the symbolizers of interpreted
+// languages resolve frames captured from the memory of the guest, where the
+// same addresses may be reused by different code over time.
+type symbolCache struct {
+	symbols symbolizer
+	mutex   sync.RWMutex
+	entries map[locationKey]symbolCacheEntry
+}
+
+type symbolCacheEntry struct {
+	address   uint64
+	locations []location
+}
+
+func newSymbolCache(symbols symbolizer) *symbolCache {
+	return &symbolCache{
+		symbols: symbols,
+		entries: make(map[locationKey]symbolCacheEntry),
+	}
+}
+
+func (c *symbolCache) Locations(fn experimental.InternalFunction, pc experimental.ProgramCounter) (uint64, []location) {
+	key := makeLocationKey(fn.Definition(), pc)
+
+	c.mutex.RLock()
+	e, ok := c.entries[key]
+	c.mutex.RUnlock()
+
+	if ok {
+		symbolCacheHits.Add(1)
+	} else {
+		symbolCacheMisses.Add(1)
+		e.address, e.locations = c.symbols.Locations(fn, pc)
+		c.mutex.Lock()
+		c.entries[key] = e
+		c.mutex.Unlock()
+	}
+	// The caller fills in missing names, the cached locations are copied so
+	// they are not modified.
+	return e.address, slices.Clone(e.locations)
 }
 
 type location struct {
@@ -256,8 +1005,11 @@ func locationForCall(p *Profiling, fn experimental.InternalFunction, pc experime
 
 	out := &profile.Location{}
 
-	if pc > 0 {
-		out.Address, locations = p.symbols.Locations(fn, pc)
+	if p.hostFrames && def.GoFunction() != nil {
+		name := hostFrameName(def)
+		locations = []location{{StableName: name, HumanName: name}}
+	} else {
+		out.Address, locations = p.symbolize(fn, pc)
 		symbolFound = len(locations) > 0
 	}
 	if len(locations) == 0 {
@@ -268,10 +1020,10 @@ func locationForCall(p *Profiling, fn experimental.InternalFunction, pc experime
 	// Provide defaults in case we couldn't resolve DWARF information for
 	// the main function call's PC.
 	if locations[0].StableName == "" {
-		locations[0].StableName = def.Name()
+		locations[0].StableName = definitionName(def)
 	}
 	if locations[0].HumanName == "" {
-		locations[0].HumanName = def.Name()
+		locations[0].HumanName = definitionName(def)
 	}
 
 	lines := make([]profile.Line, len(locations))
@@ -282,7 +1034,7 @@ func locationForCall(p *Profiling, fn experimental.InternalFunction, pc experime
 		if pprofFn == nil {
 			pprofFn = &profile.Function{
 				ID:         uint64(len(funcs)) + 1, // 0 is reserved by pprof
-				Name:       loc.HumanName,
+				Name:       p.functionName(loc),
 				SystemName: loc.StableName,
 				Filename:   loc.File,
 			}
@@ -291,7 +1043,7 @@ func locationForCall(p *Profiling, fn experimental.InternalFunction, pc experime
 			// Sometimes the function had to be created while the PC
 			// wasn't found by the symbol mapper. Attempt to correct
 			// it if we had a successful match this time.
-			pprofFn.Name = locations[i].HumanName
+			pprofFn.Name = p.functionName(locations[i])
 			pprofFn.SystemName = locations[i].StableName
 			pprofFn.Filename = locations[i].File
 		}
@@ -309,6 +1061,112 @@ func locationForCall(p *Profiling, fn experimental.InternalFunction, pc experime
 	return out
 }
 
+// symbolize returns the address and source locations of the frame of fn at pc.
+//
+// The frames of calls which were just entered may have no program counter, the
+// innermost frames of the call stacks captured when the listeners are invoked
+// for example. The frames of the functions of modules symbolized with their
+// DWARF sections or source map are located at the first instruction of the
+// function, so the samples of leaf functions have a line in the profiles
+// rather than only a function.
+func (p *Profiling) symbolize(fn experimental.InternalFunction, pc experimental.ProgramCounter) (uint64, []location) {
+	if f, ok := fn.(*restoredFunction); ok {
+		// Frames restored from a checkpoint were symbolized when the
+		// checkpoint was written (see Checkpoint).
+		return f.frame.Address, f.frame.Locations
+	}
+	def := fn.Definition()
+	entry := uint64(0)
+	if i := int(def.Index()); def.GoFunction() == nil && i < len(p.entryOffsets) {
+		entry = p.entryOffsets[i]
+	}
+	// The code compiled by wazero does not always map program counters to
+	// code offsets (e.g. the code of modules loaded from a compilation
+	// cache), the frames are then located at the entry of their function
+	// rather than left unsymbolized.
+	if pc > 0 && (entry == 0 || fn.SourceOffsetForPC(pc) != 0) {
+		return p.symbols.Locations(fn, pc)
+	}
+	if entry != 0 {
+		return p.symbols.Locations(entryFunction{fn, entry}, pc)
+	}
+	return 0, nil
+}
+
+// entryFunction is a function located at the instruction at offset in the
+// code section of the module, regardless of the program counter.
+type entryFunction struct {
+	experimental.InternalFunction
+	offset uint64
+}
+
+func (f entryFunction) SourceOffsetForPC(experimental.ProgramCounter) uint64 {
+	return f.offset
+}
+
+// definitionName returns the name of a function which could not be resolved
+// by the symbolizer. wazero exposes the names recorded in the wasm name section,
+// which modules stripped of their debug information often retain. Functions
+// missing from the section fall back to their export or import name, and to a
+// name derived from their index as last resort, so each function remains
+// distinct in profiles.
+func definitionName(def api.FunctionDefinition) string {
+	if name := def.Name(); name != "" {
+		return name
+	}
+	if names := def.ExportNames(); len(names) > 0 {
+		return names[0]
+	}
+	if moduleName, name, isImport := def.Import(); isImport {
+		return moduleName + "." + name
+	}
+	return def.DebugName()
+}
+
+// instrumented returns true if the profilers instrumenting calls to all the
+// functions of the module should instrument def, according to Filter and
+// Exclude. Host functions are always instrumented.
+func (p *Profiling) instrumented(def api.FunctionDefinition) bool {
+	if def.GoFunction() != nil || (p.include == nil && p.exclude == nil) {
+		return true
+	}
+	name := definitionName(def)
+	matches := func(re *regexp.Regexp) bool {
+		if re.MatchString(name) {
+			return true
+		}
+		demangled, ok := demangle(name, false)
+		return ok && re.MatchString(demangled)
+	}
+	if p.include != nil && !matches(p.include) {
+		return false
+	}
+	return p.exclude == nil || !matches(p.exclude)
+}
+
+// hostFrameName returns the name of the frames of calls to the host function
+// def when profiles are configured with HostFrames.
+func hostFrameName(def api.FunctionDefinition) string {
+	name := def.Name()
+	if moduleName := def.ModuleName(); moduleName != "" {
+		name = moduleName + "." + name
+	}
+	return "host:" + name
+}
+
+// functionName returns the name of the function at loc in profiles. The
+// demangled linkage name is preferred when the symbolizer found one, since it
+// is fully qualified.
+func (p *Profiling) functionName(loc location) string {
+	if name, ok := demangle(loc.StableName, p.stripTemplates); ok {
+		return name
+	}
+	if name, ok := demangle(loc.HumanName, p.stripTemplates); ok {
+		return name
+	}
+	return loc.HumanName
+}
+
 type locationKey struct {
 	module string
 	index  uint32
@@ -344,6 +1202,61 @@ func (scm stackCounterMap) len() int {
 	return len(scm)
 }
 
+// compact bounds the number of call stacks of scm to limit, which keeps the
+// memory used by long profiling sessions constant. When the limit is exceeded,
+// the call stacks with the lowest totals are merged into their caller (the
+// stack without its innermost frame) until a quarter of the limit is freed,
+// so the values remain accounted for at a coarser granularity.
+//
+// Call stacks of a single frame cannot be merged, the limit may be exceeded if
+// there are more of them. A limit of zero or less disables the compaction.
+//
+// The method returns the number of call stacks merged into their callers.
+func (scm stackCounterMap) compact(limit int, merge func(dst, src *stackCounter)) int {
+	if limit <= 0 || len(scm) <= limit {
+		return 0
+	}
+	counters := make([]*stackCounter, 0, len(scm))
+	for _, sc := range scm {
+		if sc.stack.len() > 1 {
+			counters = append(counters, sc)
+		}
+	}
+	sort.Slice(counters, func(i, j int) bool {
+		return counters[i].total() < counters[j].total()
+	})
+	merged := 0
+	for _, sc := range counters {
+		if len(scm) <= limit-limit/4 {
+			break
+		}
+		delete(scm, sc.stack.key)
+		merge(scm.lookup(sc.stack.caller()), sc)
+		merged++
+	}
+	return merged
+}
+
+// size returns an estimate of the memory used by the counters of scm (in
+// bytes), including the call stacks and the entries of the map.
+func (scm stackCounterMap) size() int64 {
+	const entrySize = int64(unsafe.Sizeof(uint64(0)) + unsafe.Sizeof((*stackCounter)(nil)))
+	size := int64(0)
+	for _, sc := range scm {
+		size += entrySize + int64(unsafe.Sizeof(*sc))
+		size += int64(cap(sc.stack.fns)) * int64(unsafe.Sizeof(experimental.InternalFunction(nil)))
+		size += int64(cap(sc.stack.pcs)) * int64(unsafe.Sizeof(experimental.ProgramCounter(0)))
+	}
+	return size
+}
+
+// addStackCounters is the merge function of compact for counters cumulating
+// their values.
+func addStackCounters(dst, src *stackCounter) {
+	dst.value[0] += src.value[0]
+	dst.value[1] += src.value[1]
+}
+
 type stackCounter struct {
 	stack stackTrace
 	value [2]int64 // count, total
@@ -370,6 +1283,10 @@ func (sc *stackCounter) sampleValue() []int64 {
 	return sc.value[:]
 }
 
+func (sc *stackCounter) sampleBytes() int64 {
+	return 0
+}
+
 func (sc *stackCounter) String() string {
 	return fmt.Sprintf("{count:%d,total:%d}", sc.count(), sc.total())
 }
@@ -386,24 +1303,132 @@ type stackFrame struct {
 	pc experimental.ProgramCounter
 }
 
+// stackFrameIterator is a stack iterator over frames collected in advance, used
+// by the symbolizers which rebuild the call stacks from the wasm frames and the
+// frames of interpreters.
+type stackFrameIterator struct {
+	frames []stackFrame
+	index  int
+}
+
+func (s *stackFrameIterator) Next() bool {
+	if s.index == len(s.frames) {
+		return false
+	}
+	s.index++
+	return true
+}
+
+func (s *stackFrameIterator) ProgramCounter() experimental.ProgramCounter {
+	return s.frames[s.index-1].pc
+}
+
+func (s *stackFrameIterator) Function() experimental.InternalFunction {
+	return s.frames[s.index-1].fn
+}
+
 type stackTrace struct {
-	fns []experimental.InternalFunction
-	pcs []experimental.ProgramCounter
-	key uint64
+	fns    []experimental.InternalFunction
+	pcs    []experimental.ProgramCounter
+	sites  []uint64 // call sites of the frames, see frameSite
+	key    uint64
+	thread uint32
+	labels *labelSet
+	depth  int // number of frames, including the truncated frames
 }
 
-func makeStackTrace(st stackTrace, si experimental.StackIterator) stackTrace {
+// makeStackTrace captures the call stack of the given thread, reusing the
+// memory of st. The thread and the labels of ctx are part of the key, so the
+// same call stack seen on different threads or with different labels is
+// recorded in separate samples.
+//
+// Only the innermost maxFrames frames are retained if maxFrames is positive
+// (see MaxFrames), the outermost frames are still counted in the depth.
+func makeStackTrace(ctx context.Context, st stackTrace, thread uint32, si experimental.StackIterator, maxFrames int) stackTrace {
 	st.fns = st.fns[:0]
 	st.pcs = st.pcs[:0]
+	st.sites = st.sites[:0]
+	st.thread = thread
+	st.labels = contextLabels(ctx)
+	st.depth = 0
 
 	for si.Next() {
-		st.fns = append(st.fns, si.Function())
-		st.pcs = append(st.pcs, si.ProgramCounter())
+		if st.depth++; maxFrames > 0 && st.depth > maxFrames {
+			continue
+		}
+		fn, pc := si.Function(), si.ProgramCounter()
+		st.fns = append(st.fns, fn)
+		st.pcs = append(st.pcs, pc)
+		st.sites = append(st.sites, frameSite(fn, pc))
 	}
-	st.key = maphash.Bytes(stackTraceHashSeed, st.bytes())
+
+	st.key = st.hash()
 	return st
 }
 
+// frameSite returns the call site of a frame at the program counter pc of fn,
+// which is where the function called the next frame of the stack (or where
+// the innermost frame is). The program counters of the wazero interpreter are
+// the positions of the instructions in the code of their function, the same
+// values are seen in all functions, so the call site is qualified by the name
+// of the module and the index of the function. The call stacks reaching a
+// function from different call sites (e.g. through the call_indirect tables)
+// are then recorded in separate samples.
+//
+// The call sites are recorded when the stacks are captured, so hashing the
+// call stacks does not call the functions of their frames.
+func frameSite(fn experimental.InternalFunction, pc experimental.ProgramCounter) uint64 {
+	def := fn.Definition()
+	h := maphash.String(stackTraceHashSeed, def.ModuleName())
+	h = (h ^ uint64(def.Index())) * 0x9e3779b97f4a7c15
+	return h ^ uint64(pc)
+}
+
+// restoreSites records the call sites of the frames of st, for the call stacks
+// which were not captured by makeStackTrace.
+func (st *stackTrace) restoreSites() {
+	st.sites = st.sites[:0]
+	for i, fn := range st.fns {
+		st.sites = append(st.sites, frameSite(fn, st.pcs[i]))
+	}
+}
+
+func (st stackTrace) hash() uint64 {
+	h := maphash.Bytes(stackTraceHashSeed, st.bytes())
+	// Mix the thread identifier in the key (the multiplier is the golden
+	// ratio, which spreads consecutive identifiers across all bits).
+	h ^= uint64(st.thread) * 0x9e3779b97f4a7c15
+	if st.labels != nil {
+		h ^= st.labels.hash
+	}
+	if st.truncated() {
+		// Distinguishes the truncated stacks from the complete stacks
+		// of the same frames.
+		h = ^h
+	}
+	return h
+}
+
+// caller returns the call stack of the caller of the innermost frame of st,
+// sharing the memory of st.
+func (st stackTrace) caller() stackTrace {
+	c := st
+	c.fns = st.fns[1:]
+	c.pcs = st.pcs[1:]
+	c.sites = st.sites[1:]
+	if c.depth > 0 {
+		c.depth--
+	}
+	c.key = c.hash()
+	return c
+}
+
+// truncated returns true if the outermost frames of st were dropped because
+// the stack was deeper than the limit set by MaxFrames.
+func (st stackTrace) truncated() bool {
+	return st.depth > len(st.pcs)
+}
+
 func (st stackTrace) host() bool {
 	return len(st.fns) > 0 && st.fns[0].Definition().GoFunction() != nil
 }
@@ -421,15 +1446,19 @@ func (st stackTrace) index(i int) stackFrame {
 
 func (st stackTrace) clone() stackTrace {
 	return stackTrace{
-		fns: slices.Clone(st.fns),
-		pcs: slices.Clone(st.pcs),
-		key: st.key,
+		fns:    slices.Clone(st.fns),
+		pcs:    slices.Clone(st.pcs),
+		sites:  slices.Clone(st.sites),
+		key:    st.key,
+		thread: st.thread,
+		labels: st.labels,
+		depth:  st.depth,
 	}
 }
 
 func (st stackTrace) bytes() []byte {
-	pcs := unsafe.SliceData(st.pcs)
-	return unsafe.Slice((*byte)(unsafe.Pointer(pcs)), 8*len(st.pcs))
+	sites := unsafe.SliceData(st.sites)
+	return unsafe.Slice((*byte)(unsafe.Pointer(sites)), 8*len(st.sites))
 }
 
 func (st stackTrace) String() string {
@@ -444,63 +1473,267 @@ func (st stackTrace) String() string {
 
 var stackTraceHashSeed = maphash.MakeSeed()
 
+// startProfile calls the OnProfileStart hook, if any, for the profiler of the
+// given name.
+func (p *Profiling) startProfile(name string) {
+	if p.onProfileStart != nil {
+		p.onProfileStart(name)
+	}
+}
+
+// endProfile calls the OnProfileEnd hook, if any, with prof unless it is nil,
+// and returns prof.
+func (p *Profiling) endProfile(name string, prof *profile.Profile) *profile.Profile {
+	if prof != nil && p.onProfileEnd != nil {
+		p.onProfileEnd(name, prof)
+	}
+	return prof
+}
+
 type sampleType interface {
 	sampleLocation() stackTrace
 	sampleValue() []int64
+	// sampleBytes returns the size of the objects of the sample, which is
+	// set as the "bytes" numeric label, or zero if it is unknown.
+	sampleBytes() int64
 }
 
 func buildProfile[T sampleType](p *Profiling, samples map[uint64]T, start time.Time, duration time.Duration, sampleType []*profile.ValueType, ratios []float64) *profile.Profile {
+	prof := newProfileHeader(p, start, duration, sampleType, ratios)
+	prof.Sample = make([]*profile.Sample, 0, len(samples))
+
+	b := newProfileBuilder(p, samples)
+	for _, sample := range samples {
+		prof.Sample = append(prof.Sample, b.sample(sample, len(sampleType)))
+	}
+	b.finish(prof)
+
+	if err := prof.ScaleN(ratios[:len(sampleType)]); err != nil {
+		panic(err)
+	}
+	if p.deterministic {
+		sortProfile(prof)
+	}
+	return prof
+}
+
+// newProfileHeader returns a profile without samples, holding the fields of
+// profiles built by buildProfile and streamProfile.
+//
+// The profile has a single mapping describing the wasm module, which the
+// continuous profiling services use to deduplicate the profiles of a module and
+// find its debug information, and a comment with the version of wzprof.
+func newProfileHeader(p *Profiling, start time.Time, duration time.Duration, sampleType []*profile.ValueType, ratios []float64) *profile.Profile {
 	prof := &profile.Profile{
 		SampleType:    sampleType,
-		Sample:        make([]*profile.Sample, 0, len(samples)),
 		TimeNanos:     start.UnixNano(),
 		DurationNanos: int64(duration),
+		// The profilers record one every N events of the first sample
+		// type, where N is the inverse of the sample rate.
+		PeriodType: sampleType[0],
+		Period:     int64(math.Round(ratios[0])),
+		Comments:   []string{"wzprof " + version},
+	}
+	if p.deterministic {
+		prof.TimeNanos, prof.DurationNanos, prof.Comments = 0, 0, nil
+	} else if p.selfProfile {
+		prof.Comments = append(prof.Comments, p.overhead.String())
+	}
+	buildID := p.buildID
+	if p.binaryMismatch {
+		// The module was not compiled from the binary of the build id.
+		buildID = ""
+	}
+	if p.moduleFile != "" || buildID != "" {
+		prof.Mapping = []*profile.Mapping{{
+			ID:      1,
+			File:    p.moduleFile,
+			BuildID: buildID,
+			// The locations of the profiles always have a function,
+			// falling back to the names of the wasm functions.
+			HasFunctions: true,
+		}}
+	}
+	return prof
+}
+
+// version is the version of the wzprof module linked in the program.
+var version = moduleVersion()
+
+func moduleVersion() string {
+	const path = "github.com/stealthrocket/wzprof"
+	if info, ok := debug.ReadBuildInfo(); ok {
+		if info.Main.Path == path {
+			return info.Main.Version
+		}
+		for _, dep := range info.Deps {
+			if dep.Path == path {
+				return dep.Version
+			}
+		}
 	}
+	return "(devel)"
+}
 
-	locationID := uint64(1)
-	locationCache := make(map[locationKey]*profile.Location)
-	functionCache := make(map[string]*profile.Function)
+// profileBuilder converts the samples recorded by profilers to the samples of
+// pprof, sharing the symbolized locations and functions of the call stacks.
+type profileBuilder struct {
+	p            *Profiling
+	labelThreads bool
+	locationID   uint64
+	locations    map[locationKey]*profile.Location
+	functions    map[string]*profile.Function
+	elided       map[*profile.Location]bool
+}
 
+func newProfileBuilder[T sampleType](p *Profiling, samples map[uint64]T) *profileBuilder {
+	// Samples are only labeled with the thread they were recorded on when
+	// the program has multiple threads.
+	threads := make(map[uint32]struct{})
 	for _, sample := range samples {
-		stack := sample.sampleLocation()
-		location := make([]*profile.Location, stack.len())
-
-		for i := range location {
-			fn := stack.fns[i]
-			pc := stack.pcs[i]
-
-			def := fn.Definition()
-			key := makeLocationKey(def, pc)
-			loc := locationCache[key]
-			if loc == nil {
-				loc = locationForCall(p, fn, pc, functionCache)
-				loc.ID = locationID
-				locationID++
-				locationCache[key] = loc
+		threads[sample.sampleLocation().thread] = struct{}{}
+	}
+	return &profileBuilder{
+		p:            p,
+		labelThreads: len(threads) > 1,
+		locationID:   1,
+		locations:    make(map[locationKey]*profile.Location),
+		functions:    make(map[string]*profile.Function),
+		elided:       make(map[*profile.Location]bool),
+	}
+}
+
+// sample returns the pprof sample of the first n values of sample.
+func (b *profileBuilder) sample(sample sampleType, n int) *profile.Sample {
+	stack := sample.sampleLocation()
+	location := make([]*profile.Location, 0, stack.len()+1)
+
+	for i := 0; i < stack.len(); i++ {
+		if loc := b.location(stack.fns[i], stack.pcs[i]); !b.elided[loc] {
+			location = append(location, loc)
+		}
+	}
+	if len(location) == 0 && stack.len() > 0 {
+		// The values remain attributed to the innermost frame when all
+		// the frames of the stack are elided.
+		location = append(location, b.location(stack.fns[0], stack.pcs[0]))
+	}
+	// Locations are numbered when they are first used, the locations of
+	// elided frames are not part of the profile.
+	for _, loc := range location {
+		if loc.ID == 0 {
+			loc.ID = b.locationID
+			b.locationID++
+		}
+	}
+	if stack.truncated() {
+		location = append(location, b.truncatedLocation())
+	}
+
+	s := &profile.Sample{
+		Location: location,
+		Value:    sample.sampleValue()[:n],
+	}
+	var instance, export string
+	if b.p.labelInstances && stack.thread != 0 {
+		instance = b.p.threads.name(stack.thread)
+	}
+	if b.p.labelExports && stack.len() > 0 && !stack.truncated() {
+		if names := stack.fns[stack.len()-1].Definition().ExportNames(); len(names) > 0 {
+			export = names[0]
+		}
+	}
+	if b.labelThreads || stack.labels != nil || instance != "" || export != "" {
+		s.Label = make(map[string][]string)
+		if b.labelThreads {
+			s.Label["thread"] = []string{strconv.FormatUint(uint64(stack.thread), 10)}
+		}
+		if instance != "" {
+			s.Label["instance"] = []string{instance}
+		}
+		if export != "" {
+			s.Label["export"] = []string{export}
+		}
+		if stack.labels != nil {
+			for _, l := range stack.labels.labels {
+				s.Label[l.key] = []string{l.value}
 			}
+		}
+	}
+	if bytes := sample.sampleBytes(); bytes != 0 {
+		s.NumLabel = map[string][]int64{"bytes": {bytes}}
+		s.NumUnit = map[string][]string{"bytes": {"bytes"}}
+	}
+	return s
+}
 
-			location[i] = loc
+// location returns the location of the call to fn at pc, symbolized the first
+// time that it is seen by b.
+func (b *profileBuilder) location(fn experimental.InternalFunction, pc experimental.ProgramCounter) *profile.Location {
+	key := makeLocationKey(fn.Definition(), pc)
+	loc := b.locations[key]
+	if loc == nil {
+		start := b.p.overheadStart()
+		loc = locationForCall(b.p, fn, pc, b.functions)
+		b.p.overheadEnd(&b.p.overhead.symbols, start)
+		b.locations[key] = loc
+		b.elided[loc] = b.p.elided(loc)
+	}
+	return loc
+}
+
+// elided reports whether the frame of loc is elided from the call stacks, which
+// is the case when the names of all its functions match ElideFrames.
+func (p *Profiling) elided(loc *profile.Location) bool {
+	if p.elide == nil || len(loc.Line) == 0 {
+		return false
+	}
+	for _, line := range loc.Line {
+		if fn := line.Function; fn == nil || !(p.elide.MatchString(fn.Name) || p.elide.MatchString(fn.SystemName)) {
+			return false
 		}
+	}
+	return true
+}
 
-		prof.Sample = append(prof.Sample, &profile.Sample{
-			Location: location,
-			Value:    sample.sampleValue()[:len(sampleType)],
-		})
+// truncatedLocation returns the location of the frame at the root of the
+// truncated call stacks.
+func (b *profileBuilder) truncatedLocation() *profile.Location {
+	key := locationKey{name: truncatedFrameName}
+	loc := b.locations[key]
+	if loc == nil {
+		fn := b.functions[truncatedFrameName]
+		if fn == nil {
+			fn = &profile.Function{
+				ID:         uint64(len(b.functions)) + 1,
+				Name:       truncatedFrameName,
+				SystemName: truncatedFrameName,
+			}
+			b.functions[truncatedFrameName] = fn
+		}
+		loc = &profile.Location{ID: b.locationID, Line: []profile.Line{{Function: fn}}}
+		b.locationID++
+		b.locations[key] = loc
 	}
+	return loc
+}
 
-	prof.Location = make([]*profile.Location, len(locationCache))
-	prof.Function = make([]*profile.Function, len(functionCache))
+// finish sets the locations and functions of the samples built by b on prof.
+func (b *profileBuilder) finish(prof *profile.Profile) {
+	prof.Location = make([]*profile.Location, b.locationID-1)
+	prof.Function = make([]*profile.Function, len(b.functions))
 
-	for _, loc := range locationCache {
+	for _, loc := range b.locations {
+		if loc.ID == 0 {
+			continue
+		}
+		if len(prof.Mapping) > 0 {
+			loc.Mapping = prof.Mapping[0]
+		}
 		prof.Location[loc.ID-1] = loc
 	}
 
-	for _, fn := range functionCache {
+	for _, fn := range b.functions {
 		prof.Function[fn.ID-1] = fn
 	}
-
-	if err := prof.ScaleN(ratios[:len(sampleType)]); err != nil {
-		panic(err)
-	}
-	return prof
 }
@@ -2,11 +2,19 @@ package wzprof
 
 import (
 	"context"
+	"errors"
+	"fmt"
+	"regexp"
+	"strings"
 	"testing"
+	"time"
 
+	"github.com/google/pprof/profile"
+	"github.com/tetratelabs/wazero"
 	"github.com/tetratelabs/wazero/api"
 	"github.com/tetratelabs/wazero/experimental"
 	"github.com/tetratelabs/wazero/experimental/wazerotest"
+	"golang.org/x/exp/slices"
 )
 
 func benchmarkFunctionListener(b *testing.B, factory experimental.FunctionListenerFactory) {
@@ -29,3 +37,516 @@ func benchmarkFunctionListener(b *testing.B, factory experimental.FunctionListen
 		factory.NewFunctionListener(malloc.Definition()),
 	)
 }
+
+func TestProfilingNotPrepared(t *testing.T) {
+	module := wazerotest.NewModule(nil,
+		wazerotest.NewFunction(func(context.Context, api.Module) {}),
+	)
+	function := module.Function(0)
+	stack := []experimental.StackFrame{{Function: function}}
+
+	p := ProfilingFor(nil).CPUProfiler()
+	p.StartProfile()
+	listener := p.NewFunctionListener(function.Definition())
+
+	defer func() {
+		if err := recover(); err != errNotPrepared {
+			t.Errorf("unexpected error: want=%v got=%v", errNotPrepared, err)
+		}
+	}()
+
+	listener.Before(context.Background(), module, function.Definition(), nil, experimental.NewStackIterator(stack...))
+}
+
+func TestProfileFunctionNames(t *testing.T) {
+	currentTime := int64(0)
+
+	p := ProfilingFor(nil, AllowUnprepared(true)).CPUProfiler(
+		HostTime(true),
+		TimeFunc(func() int64 { return currentTime }),
+	)
+
+	// Without DWARF, the functions are named after the wasm name section, or
+	// after their exports when the section does not contain their name.
+	named := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	named.FunctionName = "_ZN6simple20allocate_more_memory17h1234567890abcdefE"
+	exported := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	exported.ExportNames = []string{"_start"}
+
+	module := wazerotest.NewModule(nil, named, exported)
+	def := named.Definition()
+	listener := p.NewFunctionListener(def)
+	stack := []experimental.StackFrame{{Function: named}, {Function: exported}}
+
+	p.StartProfile()
+	listener.Before(context.Background(), module, def, nil, experimental.NewStackIterator(stack...))
+	currentTime++
+	listener.After(context.Background(), module, def, nil)
+	prof := p.StopProfile(1)
+
+	names := make(map[string]bool)
+	for _, fn := range prof.Function {
+		names[fn.Name] = true
+	}
+	for _, name := range []string{"simple::allocate_more_memory", "_start"} {
+		if !names[name] {
+			t.Errorf("function %q not found in profile: %v", name, names)
+		}
+	}
+}
+
+func TestProfileHostFrames(t *testing.T) {
+	currentTime := int64(0)
+
+	p := ProfilingFor(nil, AllowUnprepared(true), HostFrames(true)).CPUProfiler(
+		TimeFunc(func() int64 { return currentTime }),
+	)
+
+	fdWrite := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	fdWrite.FunctionName = "fd_write"
+
+	module := wazerotest.NewModule(nil, fdWrite)
+	def := fdWrite.Definition()
+	listener := p.NewFunctionListener(def)
+	stack := []experimental.StackFrame{{Function: fdWrite}}
+
+	p.StartProfile()
+	listener.Before(context.Background(), module, def, nil, experimental.NewStackIterator(stack...))
+	currentTime++
+	listener.After(context.Background(), module, def, nil)
+	prof := p.StopProfile(1)
+
+	// The samples of host functions are kept without HostTime.
+	if len(prof.Sample) != 1 {
+		t.Fatalf("wrong number of samples: want=1 got=%d", len(prof.Sample))
+	}
+	name := prof.Sample[0].Location[0].Line[0].Function.Name
+	if !strings.HasPrefix(name, "host:") || !strings.HasSuffix(name, "fd_write") {
+		t.Errorf("wrong name of host frame: %q", name)
+	}
+}
+
+func TestProfileElideFrames(t *testing.T) {
+	currentTime := int64(0)
+
+	trampoline := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	trampoline.FunctionName = "dynCall_vii"
+	main := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	main.FunctionName = "main"
+
+	module := wazerotest.NewModule(nil, trampoline, main)
+	def := trampoline.Definition()
+	stack := []experimental.StackFrame{{Function: trampoline}, {Function: main}}
+
+	for _, test := range []struct {
+		option ProfilingOption
+		frames []string
+	}{
+		{ElideFrames(DefaultElidedFrames), []string{"main"}},
+		{ElideFrames(nil), []string{"dynCall_vii", "main"}},
+	} {
+		p := ProfilingFor(nil, AllowUnprepared(true), test.option).CPUProfiler(
+			HostTime(true),
+			TimeFunc(func() int64 { return currentTime }),
+		)
+		listener := p.NewFunctionListener(def)
+
+		p.StartProfile()
+		listener.Before(context.Background(), module, def, nil, experimental.NewStackIterator(stack...))
+		currentTime += 10
+		listener.After(context.Background(), module, def, nil)
+		prof := p.StopProfile(1)
+		if err := prof.CheckValid(); err != nil {
+			t.Fatal(err)
+		}
+
+		// The time of the elided frames is attributed to their callers.
+		if len(prof.Sample) != 1 {
+			t.Fatalf("wrong number of samples: want=1 got=%d", len(prof.Sample))
+		}
+		sample := prof.Sample[0]
+		var frames []string
+		for _, loc := range sample.Location {
+			frames = append(frames, loc.Line[0].Function.Name)
+		}
+		if !slices.Equal(frames, test.frames) {
+			t.Errorf("wrong call stack: want=%q got=%q", test.frames, frames)
+		}
+		if sample.Value[1] != 10 {
+			t.Errorf("wrong time of the sample: want=10 got=%d", sample.Value[1])
+		}
+		if len(prof.Location) != len(test.frames) {
+			t.Errorf("wrong number of locations: want=%d got=%d", len(test.frames), len(prof.Location))
+		}
+	}
+}
+
+func TestProfileMapping(t *testing.T) {
+	currentTime := int64(0)
+
+	p := ProfilingFor(nil, AllowUnprepared(true), HostFrames(true), ModuleFile("app.wasm")).CPUProfiler(
+		TimeFunc(func() int64 { return currentTime }),
+	)
+
+	fdWrite := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	fdWrite.FunctionName = "fd_write"
+
+	module := wazerotest.NewModule(nil, fdWrite)
+	def := fdWrite.Definition()
+	listener := p.NewFunctionListener(def)
+	stack := []experimental.StackFrame{{Function: fdWrite}}
+
+	p.StartProfile()
+	listener.Before(context.Background(), module, def, nil, experimental.NewStackIterator(stack...))
+	currentTime++
+	listener.After(context.Background(), module, def, nil)
+	prof := p.StopProfile(1)
+
+	if err := prof.CheckValid(); err != nil {
+		t.Fatal(err)
+	}
+	if len(prof.Mapping) != 1 || prof.Mapping[0].File != "app.wasm" {
+		t.Fatalf("wrong mapping of the profile: %v", prof.Mapping)
+	}
+	for _, loc := range prof.Location {
+		if loc.Mapping != prof.Mapping[0] {
+			t.Errorf("location %d is not in the mapping of the module", loc.ID)
+		}
+	}
+	if len(prof.Comments) != 1 || !strings.HasPrefix(prof.Comments[0], "wzprof ") {
+		t.Errorf("wrong comments of the profile: %q", prof.Comments)
+	}
+}
+
+func TestWasmBuildID(t *testing.T) {
+	header := []byte("\x00asm\x01\x00\x00\x00")
+	section := append([]byte{8}, "build_id"...)
+	section = append(section, 3, 0xca, 0xfe, 0x01)
+	module := append(append(header, 0, byte(len(section))), section...)
+
+	if id := wasmBuildID(module); id != "cafe01" {
+		t.Errorf("wrong build id of module with build_id section: %q", id)
+	}
+	if id := wasmBuildID(header); len(id) != 64 {
+		t.Errorf("wrong build id of module without build_id section: %q", id)
+	}
+	if id := wasmBuildID(nil); id != "" {
+		t.Errorf("wrong build id of empty module: %q", id)
+	}
+}
+
+func TestWasmFunctionOffsets(t *testing.T) {
+	module := []byte("\x00asm\x01\x00\x00\x00")
+	module = append(module, 1, 4, 1, 0x60, 0, 0)                       // type: () -> ()
+	module = append(module, 2, 7, 1, 1, 'm', 1, 'f', 0, 0)             // import: m.f
+	module = append(module, 3, 3, 2, 0, 0)                             // function: 2 functions
+	module = append(module, 10, 9, 2, 2, 0, 0x0b, 4, 1, 1, 0x7f, 0x0b) // code: the second function has a local
+
+	offsets := wasmFunctionOffsets(module)
+	if want := []uint64{0, 3, 8}; !slices.Equal(offsets, want) {
+		t.Errorf("wrong function offsets: want=%v got=%v", want, offsets)
+	}
+	if offsets := wasmFunctionOffsets(module[:len(module)-3]); offsets != nil {
+		t.Errorf("function offsets of truncated module: %v", offsets)
+	}
+}
+
+// offsetSymbolizer locates the frames at the line of their code offset.
+type offsetSymbolizer struct{}
+
+func (offsetSymbolizer) Locations(fn experimental.InternalFunction, pc experimental.ProgramCounter) (uint64, []location) {
+	offset := fn.SourceOffsetForPC(pc)
+	return offset, []location{{Line: int64(offset)}}
+}
+
+func TestSymbolizeEntry(t *testing.T) {
+	f := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f.FunctionName = "f"
+	wazerotest.NewModule(nil, f)
+
+	p := ProfilingFor(nil)
+	p.symbols = offsetSymbolizer{}
+	p.entryOffsets = []uint64{42}
+
+	// Host functions have no code offset.
+	if _, locations := p.symbolize(f, 0); locations != nil {
+		t.Errorf("host function located at its entry: %v", locations)
+	}
+
+	guest := guestInternalFunction{f, guestFunction{f.Definition(), "f"}}
+	if address, locations := p.symbolize(guest, 0); address != 42 || len(locations) != 1 || locations[0].Line != 42 {
+		t.Errorf("wrong location of function entry: address=%d locations=%v", address, locations)
+	}
+
+	// Program counters which do not map to code offsets are located at the
+	// entry of their function.
+	if address, locations := p.symbolize(noOffsetFunction{guest}, 1); address != 42 || len(locations) != 1 || locations[0].Line != 42 {
+		t.Errorf("wrong location of program counter without code offset: address=%d locations=%v", address, locations)
+	}
+}
+
+// noOffsetFunction is a function of which the program counters do not map to
+// code offsets.
+type noOffsetFunction struct{ guestInternalFunction }
+
+func (noOffsetFunction) SourceOffsetForPC(experimental.ProgramCounter) uint64 { return 0 }
+
+// guestInternalFunction is an internal function of a guest function.
+type guestInternalFunction struct {
+	experimental.InternalFunction
+	def api.FunctionDefinition
+}
+
+func (f guestInternalFunction) Definition() api.FunctionDefinition { return f.def }
+
+func TestPrepareComponent(t *testing.T) {
+	component := []byte("\x00asm\x0d\x00\x01\x00")
+	if err := ProfilingFor(component).Prepare(nil); !errors.Is(err, errComponent) {
+		t.Errorf("wrong error preparing a component: %v", err)
+	}
+	if wasmIsComponent([]byte("\x00asm\x01\x00\x00\x00")) {
+		t.Error("core module detected as a component")
+	}
+}
+
+func TestPrepareMismatch(t *testing.T) {
+	ctx := context.Background()
+	runtime := wazero.NewRuntimeWithConfig(ctx, wazero.NewRuntimeConfigInterpreter())
+	defer runtime.Close(ctx)
+
+	module := []byte("\x00asm\x01\x00\x00\x00")
+	module = append(module, 1, 4, 1, 0x60, 0, 0)           // type: () -> ()
+	module = append(module, 2, 7, 1, 1, 'm', 1, 'f', 0, 0) // import: m.f
+	module = append(module, 3, 2, 1, 0)                    // function: 1 function
+	module = append(module, 7, 5, 1, 1, 'g', 0, 1)         // export: g
+	module = append(module, 10, 4, 1, 2, 0, 0x0b)          // code
+
+	compiled, err := runtime.CompileModule(ctx, module)
+	if err != nil {
+		t.Fatal(err)
+	}
+	if !wasmMatchesModule(module, compiled) {
+		t.Error("module does not match its own binary")
+	}
+
+	// The module is prepared without the binary of another module.
+	other, err := runtime.CompileModule(ctx, []byte("\x00asm\x01\x00\x00\x00"))
+	if err != nil {
+		t.Fatal(err)
+	}
+	p := ProfilingFor(module)
+	if err := p.Prepare(other); err != nil {
+		t.Fatal(err)
+	}
+	if p.entryOffsets != nil {
+		t.Errorf("module prepared with the binary of another module")
+	}
+
+	// Modules with the same number of imports and exports do not match if
+	// they import other functions.
+	renamed := append([]byte(nil), module...)
+	renamed[len("\x00asm\x01\x00\x00\x00")+6+6] = 'h' // import: m.h
+	compiledRenamed, err := runtime.CompileModule(ctx, renamed)
+	if err != nil {
+		t.Fatal(err)
+	}
+	if wasmMatchesModule(module, compiledRenamed) {
+		t.Error("module matches the binary of a module with other imports")
+	}
+
+	// The binary is still used for the modules compiled from it.
+	if err := p.Prepare(compiled); err != nil {
+		t.Fatal(err)
+	}
+	if p.entryOffsets == nil {
+		t.Errorf("binary was dropped after preparing another module")
+	}
+}
+
+// guestFunction is a definition of a guest function, the functions created by
+// wazerotest are host functions.
+type guestFunction struct {
+	api.FunctionDefinition
+	name string
+}
+
+func (f guestFunction) Name() string { return f.name }
+
+func (f guestFunction) GoFunction() interface{} { return nil }
+
+func TestProfilingInstrumented(t *testing.T) {
+	p := ProfilingFor(nil,
+		Filter(regexp.MustCompile("^my_app::")),
+		Exclude(regexp.MustCompile("::internal::")),
+	)
+	for name, want := range map[string]bool{
+		"_ZN6my_app4main17h1234567890abcdefE":           true,
+		"_ZN6my_app8internal5parse17h1234567890abcdefE": false,
+		"_ZN4core3fmt5write17h1234567890abcdefE":        false,
+		"my_app::run":                                   true,
+		"malloc":                                        false,
+	} {
+		if got := p.instrumented(guestFunction{name: name}); got != want {
+			t.Errorf("%s: want=%t got=%t", name, want, got)
+		}
+	}
+
+	// Host functions are always instrumented.
+	host := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	host.FunctionName = "fd_write"
+	wazerotest.NewModule(nil, host)
+	if !p.instrumented(host.Definition()) {
+		t.Error("host function not instrumented")
+	}
+}
+
+func TestStackCounterMapCompact(t *testing.T) {
+	f0 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f0.FunctionName = "f0"
+	f1 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f1.FunctionName = "f1"
+	wazerotest.NewModule(nil, f0, f1)
+
+	trace := func(frames ...experimental.StackFrame) stackTrace {
+		return makeStackTrace(context.Background(), stackTrace{}, 1, experimental.NewStackIterator(frames...), defaultMaxFrames)
+	}
+	root := trace(experimental.StackFrame{Function: f0, PC: 10})
+
+	scm := make(stackCounterMap)
+	scm.observe(root, 5)
+	for pc, total := range map[uint64]int64{1: 1, 2: 2, 3: 100} {
+		scm.observe(trace(
+			experimental.StackFrame{Function: f1, PC: pc},
+			experimental.StackFrame{Function: f0, PC: 10},
+		), total)
+	}
+
+	scm.compact(2, addStackCounters)
+	if n := scm.len(); n != 2 {
+		t.Fatalf("wrong number of call stacks: want=2 got=%d", n)
+	}
+	if sc := scm[root.key]; sc == nil || sc.count() != 3 || sc.total() != 8 {
+		t.Errorf("wrong values of merged call stack: want={count:3,total:8} got=%v", sc)
+	}
+	var total int64
+	for _, sc := range scm {
+		total += sc.total()
+	}
+	if total != 108 {
+		t.Errorf("wrong total after compaction: want=108 got=%d", total)
+	}
+}
+
+func TestStackTraceCallers(t *testing.T) {
+	callee := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	callee.FunctionName = "callee"
+	f0 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f0.FunctionName = "f0"
+	f1 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f1.FunctionName = "f1"
+	wazerotest.NewModule(nil, callee, f0, f1)
+
+	// The interpreter sees the same program counters in different functions,
+	// e.g. when a function of a table is called from the same position in the
+	// code of two callers.
+	trace := func(caller *wazerotest.Function) stackTrace {
+		return makeStackTrace(context.Background(), stackTrace{}, 1, experimental.NewStackIterator(
+			experimental.StackFrame{Function: callee, PC: 1},
+			experimental.StackFrame{Function: caller, PC: 2},
+		), defaultMaxFrames)
+	}
+
+	scm := make(stackCounterMap)
+	scm.observe(trace(f0), 1)
+	scm.observe(trace(f1), 2)
+	if n := scm.len(); n != 2 {
+		t.Fatalf("wrong number of call stacks: want=2 got=%d", n)
+	}
+	for _, sc := range scm {
+		caller := definitionName(sc.stack.fns[1].Definition())
+		if want := map[string]int64{"f0": 1, "f1": 2}[caller]; sc.total() != want {
+			t.Errorf("wrong total of calls from %s: want=%d got=%d", caller, want, sc.total())
+		}
+	}
+}
+
+func TestStackTraceCallSites(t *testing.T) {
+	newModule := func(name string) (callee, caller *wazerotest.Function) {
+		callee = wazerotest.NewFunction(func(context.Context, api.Module) {})
+		callee.FunctionName = "callee"
+		caller = wazerotest.NewFunction(func(context.Context, api.Module) {})
+		caller.FunctionName = "caller"
+		wazerotest.NewModule(nil, callee, caller).ModuleName = name
+		return callee, caller
+	}
+	trace := func(callee, caller *wazerotest.Function, site experimental.ProgramCounter) stackTrace {
+		return makeStackTrace(context.Background(), stackTrace{}, 1, experimental.NewStackIterator(
+			experimental.StackFrame{Function: callee, PC: 1},
+			experimental.StackFrame{Function: caller, PC: site},
+		), defaultMaxFrames)
+	}
+
+	// The same callee called from two call sites of its caller, and from the
+	// same call site of the functions with the same index in another module.
+	callee0, caller0 := newModule("a")
+	callee1, caller1 := newModule("b")
+	scm := make(stackCounterMap)
+	scm.observe(trace(callee0, caller0, 2), 1)
+	scm.observe(trace(callee0, caller0, 3), 2)
+	scm.observe(trace(callee0, caller0, 3), 2)
+	scm.observe(trace(callee1, caller1, 2), 4)
+	if n := scm.len(); n != 3 {
+		t.Fatalf("wrong number of call stacks: want=3 got=%d", n)
+	}
+	for _, sc := range scm {
+		site := fmt.Sprintf("%s:%d", sc.stack.fns[1].Definition().ModuleName(), sc.stack.pcs[1])
+		if want := map[string]int64{"a:2": 1, "a:3": 4, "b:2": 4}[site]; sc.total() != want {
+			t.Errorf("wrong total of calls from %s: want=%d got=%d", site, want, sc.total())
+		}
+	}
+
+	// The call sites of the callers remain when the callee is merged.
+	if caller := trace(callee0, caller0, 3).caller(); caller.key != trace(callee0, caller0, 3).caller().key || caller.key == trace(callee0, caller0, 2).caller().key {
+		t.Error("call sites of the callers are not part of their key")
+	}
+}
+
+type countingSymbolizer struct{ calls int }
+
+func (s *countingSymbolizer) Locations(fn experimental.InternalFunction, pc experimental.ProgramCounter) (uint64, []location) {
+	s.calls++
+	return uint64(pc), []location{{File: "main.c", Line: int64(pc)}}
+}
+
+func TestSymbolCache(t *testing.T) {
+	f0 := wazerotest.NewFunction(func(context.Context, api.Module) {})
+	f0.FunctionName = "f0"
+	wazerotest.NewModule(nil, f0)
+
+	symbols := new(countingSymbolizer)
+	p := ProfilingFor(nil, AllowUnprepared(true))
+	p.symbols = newSymbolCache(symbols)
+
+	scm := make(stackCounterMap)
+	for pc := uint64(1); pc <= 3; pc++ {
+		scm.observe(makeStackTraceFromFrames(1, []experimental.StackFrame{{Function: f0, PC: pc}}), 1)
+	}
+
+	// The locations resolved when building the first profile are reused by
+	// the following ones.
+	sampleType := []*profile.ValueType{{Type: "samples", Unit: "count"}}
+	for i := 0; i < 2; i++ {
+		prof := buildProfile(p, scm, time.Now(), 0, sampleType, []float64{1})
+		if len(prof.Location) != 3 {
+			t.Fatalf("wrong number of locations: want=3 got=%d", len(prof.Location))
+		}
+		for _, loc := range prof.Location {
+			if line := loc.Line[0]; line.Function.Name != "f0" || line.Line != int64(loc.Address) {
+				t.Errorf("wrong location: %v", line)
+			}
+		}
+	}
+	if symbols.calls != 3 {
+		t.Errorf("wrong number of symbolizer calls: want=3 got=%d", symbols.calls)
+	}
+}